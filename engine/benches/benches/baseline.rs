@@ -1,6 +1,7 @@
-﻿use criterion::{criterion_group, criterion_main, Criterion, BatchSize};
-use wa_core::{Block, Document, Inline, DiffEngine, Editor, EditorCommand, export_json_into, export_json_to_file};
+﻿use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use wa_core::{Block, BlockSignatures, CodeText, Document, Inline, DiffEngine, Editor, EditorCommand, FindOptions, PlainTextIndex, SearchIndex, export_json_into, export_json_into_string, export_json_to_file, find_in_doc};
 use std::sync::Arc;
+use wa_benches::{layout_summary_json, print_layout_stats, prose_doc, table_doc, EditSession};
 use wa_engine::{FontMetrics, LayoutCache, LayoutConfig, LayoutEngine, RealMeasurer, TextMeasurer};
 
 fn build_large_doc(blocks: usize, lines: usize) -> Document {
@@ -14,19 +15,7 @@ fn build_large_doc(blocks: usize, lines: usize) -> Document {
             id: uuid::Uuid::new_v4(),
             content: vec![Inline::Text { value: Arc::from(format!("{} {}", i, text)) }],
             dirty: false,
-        });
-    }
-    doc
-}
-
-fn build_multilang_doc(blocks: usize) -> Document {
-    let mut doc = Document::new();
-    let sample = "Hello 世界 مرحبا Привет 12345 — テスト입니다.\n";
-    for i in 0..blocks {
-        doc.blocks.push(Block::Paragraph {
-            id: uuid::Uuid::new_v4(),
-            content: vec![Inline::Text { value: Arc::from(format!("{} {}", i, sample.repeat(3))) }],
-            dirty: false,
+            locked: false,
         });
     }
     doc
@@ -34,17 +23,21 @@ fn build_multilang_doc(blocks: usize) -> Document {
 
 fn layout_blocks(c: &mut Criterion) {
     let mut engine = LayoutEngine::new();
+    engine.set_collect_timings(true);
     let doc = build_large_doc(200, 5);
     let config = LayoutConfig::default();
     c.bench_function("layout_blocks", |b| b.iter(|| engine.layout(&doc, &config)));
+    print_layout_stats("layout_blocks", &engine.take_stats());
 }
 
 fn layout_blocks_cached(c: &mut Criterion) {
     let mut engine = LayoutEngine::new();
+    engine.set_collect_timings(true);
     let doc = build_large_doc(200, 5);
     let config = LayoutConfig::default();
     let mut cache = LayoutCache::new();
     c.bench_function("layout_blocks_cached", |b| b.iter(|| engine.layout_cached(&doc, &config, &mut cache)));
+    print_layout_stats("layout_blocks_cached", &engine.take_stats());
 }
 
 fn render_frame(c: &mut Criterion) {
@@ -65,7 +58,7 @@ fn render_frame(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, layout_blocks, layout_blocks_cached, render_frame, render_visible_sim, serialize_json, serialize_json_file, layout_1000_chars, diff_10k_blocks_1_changed, typing_latency, scroll_10k_lines, shape_1000_chars, undo_100_ops, layout_10k_lines_block, measure_10k_words);
+criterion_group!(benches, layout_blocks, layout_blocks_cached, render_frame, render_visible_sim, serialize_json, serialize_json_file, layout_1000_chars, diff_10k_blocks_1_changed, typing_latency, scroll_10k_lines, shape_1000_chars, undo_100_ops, layout_10k_lines_block, measure_10k_words, scroll_highlighted_code, find_20k_blocks, find_as_you_type_20k_blocks, relayout_unchanged_10k_blocks, type_100_chars_1mb_code, edit_middle_5mb_code, type_in_10k_block_doc, undo_redo_large_table, replace_all_5k_blocks, bridge_roundtrip_insert_layout);
 criterion_main!(benches);
 
 fn serialize_json(c: &mut Criterion) {
@@ -76,6 +69,13 @@ fn serialize_json(c: &mut Criterion) {
             export_json_into(&doc, &mut buf).unwrap();
         })
     });
+    // Same output checked as UTF-8 into a reused String.
+    let mut out = String::with_capacity(64 * 1024);
+    c.bench_function("serialize_json_string", |b| {
+        b.iter(|| {
+            export_json_into_string(&doc, &mut out).unwrap();
+        })
+    });
 }
 
 fn serialize_json_file(c: &mut Criterion) {
@@ -96,6 +96,7 @@ fn layout_1000_chars(c: &mut Criterion) {
         id: uuid::Uuid::new_v4(),
         content: vec![Inline::Text { value: Arc::from(text) }],
         dirty: false,
+        locked: false,
     });
     let mut engine = LayoutEngine::new();
     let config = LayoutConfig::default();
@@ -112,6 +113,7 @@ fn layout_10k_lines_block(c: &mut Criterion) {
         id: uuid::Uuid::new_v4(),
         content: vec![Inline::Text { value: Arc::from(text) }],
         dirty: false,
+        locked: false,
     });
     let mut engine = LayoutEngine::new();
     let config = LayoutConfig::default();
@@ -128,14 +130,21 @@ fn measure_10k_words(c: &mut Criterion) {
 fn diff_10k_blocks_1_changed(c: &mut Criterion) {
     let mut doc = build_large_doc(10000, 1);
     let mut diff = DiffEngine::new();
+    let mut signatures = BlockSignatures::new();
     let _ = diff.incremental_diff(&doc);
-    if let Some(block) = doc.blocks.get_mut(5000) {
-        if let Block::Paragraph { content, dirty, .. } = block {
-            content.push(Inline::Text { value: Arc::from("x") });
-            *dirty = true;
-        }
+    if let Some(Block::Paragraph { content, dirty, .. }) = doc.blocks.get_mut(5000) {
+        content.push(Inline::Text { value: Arc::from("x") });
+        *dirty = true;
     }
-    c.bench_function("diff_10k_blocks_1_changed", |b| b.iter(|| diff.incremental_diff(&doc)));
+    // Each iteration is a new version, as after a keystroke; the editor
+    // refreshes signatures of the dirty block at mutation time.
+    c.bench_function("diff_10k_blocks_1_changed", |b| {
+        b.iter(|| {
+            doc.touch();
+            signatures.refresh(&doc);
+            diff.incremental_diff_with_signatures(&doc, &signatures)
+        })
+    });
 }
 
 fn typing_latency(c: &mut Criterion) {
@@ -169,8 +178,7 @@ fn render_visible_sim(c: &mut Criterion) {
 fn scroll_10k_lines(c: &mut Criterion) {
     let mut engine = LayoutEngine::new();
     let doc = build_large_doc(10000, 1);
-    let mut config = LayoutConfig::default();
-    config.paged = false;
+    let config = LayoutConfig { paged: false, ..LayoutConfig::default() };
     let layout = engine.layout(&doc, &config);
     let total = layout.pages.first().map(|p| p.blocks.len()).unwrap_or(0);
     c.bench_function("scroll_10k_lines", |b| {
@@ -221,3 +229,196 @@ fn undo_100_ops(c: &mut Criterion) {
         )
     });
 }
+
+fn scroll_highlighted_code(c: &mut Criterion) {
+    let mut doc = Document::new();
+    let code = "fn frame(n: usize) -> usize {\n    let mut total = 0;\n    for i in 0..n { total += i * 2; }\n    total\n}\n".repeat(400);
+    doc.blocks.push(Block::Code {
+        id: uuid::Uuid::new_v4(),
+        lang: Arc::from("rs"),
+        code: CodeText::from(code),
+        dirty: false,
+        locked: false,
+    });
+    let mut engine = LayoutEngine::new();
+    let config = LayoutConfig { paged: false, ..LayoutConfig::default() };
+    let mut cache = LayoutCache::new();
+    engine.layout_cached(&doc, &config, &mut cache);
+    c.bench_function("scroll_highlighted_code", |b| {
+        let mut top = 0usize;
+        b.iter(|| {
+            let layout = engine.layout_cached(&doc, &config, &mut cache);
+            let lines = &layout.pages[0].blocks[0].lines;
+            top = (top + 40) % lines.len();
+            lines[top..(top + 60).min(lines.len())].iter().map(|l| l.runs.len()).sum::<usize>()
+        })
+    });
+}
+
+fn find_20k_blocks(c: &mut Criterion) {
+    let doc = build_large_doc(20_000, 2);
+    let options = FindOptions { case_insensitive: true, ..FindOptions::default() };
+    c.bench_function("find_20k_blocks_uncached", |b| b.iter(|| find_in_doc(&doc, "布局", options.clone()).len()));
+    let mut index = PlainTextIndex::new();
+    index.find_in_doc(&doc, "布局", options.clone());
+    c.bench_function("find_20k_blocks", |b| b.iter(|| index.find_in_doc(&doc, "布局", options.clone()).len()));
+}
+
+/// Typing "t", "th", "the" into the find box, each step held by fewer
+/// blocks: a scan of every block per keystroke against the trigram index
+/// narrowing the blocks it checks.
+fn find_as_you_type_20k_blocks(c: &mut Criterion) {
+    let mut doc = Document::new();
+    let words = ["这是一些测试文本 text", "布局性能评估 tooth", "这是一些测试文本", "段落 theme", "布局性能评估"];
+    for i in 0..20_000 {
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(format!("{} {}", i, words[i % words.len()])) }],
+            dirty: false,
+            locked: false,
+        });
+    }
+    let options = FindOptions { case_insensitive: true, ..FindOptions::default() };
+    let queries = ["t", "th", "the"];
+    let mut index = PlainTextIndex::new();
+    c.bench_function("find_as_you_type_20k_blocks_linear", |b| {
+        b.iter(|| queries.iter().map(|q| index.find_in_doc(&doc, q, options.clone()).len()).sum::<usize>())
+    });
+    let mut search = SearchIndex::new();
+    search.update(&doc);
+    c.bench_function("find_as_you_type_20k_blocks_indexed", |b| {
+        b.iter(|| queries.iter().map(|q| search.find_in_doc(&doc, q, options.clone()).len()).sum::<usize>())
+    });
+}
+
+/// Relayout of a clean document should cost the same whatever the amount of
+/// text per block, since clean cache hits are not hashed.
+fn relayout_unchanged_10k_blocks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("relayout_unchanged_10k_blocks");
+    for lines in [1usize, 20] {
+        let mut doc = build_large_doc(10_000, lines);
+        let mut engine = LayoutEngine::new();
+        let config = LayoutConfig::default();
+        let mut cache = LayoutCache::new();
+        engine.layout_cached(&doc, &config, &mut cache);
+        doc.clear_dirty();
+        group.bench_with_input(BenchmarkId::from_parameter(lines), &lines, |b, _| {
+            b.iter(|| engine.layout_cached(&doc, &config, &mut cache))
+        });
+    }
+    group.finish();
+}
+
+fn type_100_chars_1mb_code(c: &mut Criterion) {
+    let mut doc = Document::new();
+    let code: String = "let value = compute(input);\n".repeat(1024 * 1024 / 28);
+    let block_id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Code { id: block_id, lang: Arc::from("rust"), code: CodeText::from(code), dirty: false, locked: false });
+    c.bench_function("type_100_chars_1mb_code", |b| {
+        b.iter_batched(
+            || {
+                let mut editor = Editor::new(doc.clone());
+                editor.selection = wa_core::Selection::collapsed(wa_core::Position { block_id, offset: 0 });
+                editor
+            },
+            |mut editor| {
+                for _ in 0..100 {
+                    editor.execute(EditorCommand::InsertText("x".to_string()));
+                }
+                editor
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+/// Ten replacements in the middle of a 5 MB code block, as find and replace
+/// would issue them.
+fn edit_middle_5mb_code(c: &mut Criterion) {
+    let mut doc = Document::new();
+    let code: String = "let value = compute(input);\n".repeat(5 * 1024 * 1024 / 28);
+    let middle = code.len() / 2;
+    let block_id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Code { id: block_id, lang: Arc::from("rust"), code: CodeText::from(code), dirty: false, locked: false });
+    c.bench_function("edit_middle_5mb_code", |b| {
+        b.iter_batched(
+            || Editor::new(doc.clone()),
+            |mut editor| {
+                for i in 0..10 {
+                    editor.execute(EditorCommand::ReplaceMatch {
+                        block_id,
+                        start: middle + i,
+                        end: middle + i + 1,
+                        replacement: "x".to_string(),
+                    });
+                }
+                editor
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+/// A keystroke in the middle of a 10k-block document: the edit, merged
+/// into the previous undo step, then relayout of the one dirty block.
+fn type_in_10k_block_doc(c: &mut Criterion) {
+    let mut session = EditSession::new(prose_doc(10_000));
+    session.focus(5_000);
+    c.bench_function("type_in_10k_block_doc", |b| b.iter(|| session.keystroke("字")));
+    print_layout_stats("type_in_10k_block_doc", &session.engine.take_stats());
+}
+
+/// Undo and redo of a row inserted into a 400 by 8 table, each followed by
+/// a relayout.
+fn undo_redo_large_table(c: &mut Criterion) {
+    let mut session = EditSession::new(table_doc(400, 8));
+    session.focus(1);
+    session.run(EditorCommand::TableInsertRow);
+    session.engine.take_stats();
+    c.bench_function("undo_redo_large_table", |b| {
+        b.iter(|| {
+            session.run(EditorCommand::Undo);
+            session.run(EditorCommand::Redo)
+        })
+    });
+    print_layout_stats("undo_redo_large_table", &session.engine.take_stats());
+}
+
+/// Replacing a word found in every one of 5k blocks, as one undo step, and
+/// laying the result out.
+fn replace_all_5k_blocks(c: &mut Criterion) {
+    let doc = prose_doc(5_000);
+    // The stats of the last run; every run starts from a fresh session.
+    let mut stats = wa_engine::LayoutStats::default();
+    c.bench_function("replace_all_5k_blocks", |b| {
+        b.iter_batched(
+            || EditSession::new(doc.clone()),
+            |mut session| {
+                session.run(EditorCommand::ReplaceAll {
+                    query: "布局".to_string(),
+                    replacement: "排版".to_string(),
+                    options: FindOptions::default(),
+                });
+                stats = session.engine.take_stats();
+                session
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    print_layout_stats("replace_all_5k_blocks", &stats);
+}
+
+/// What the web editor does per keystroke, run natively: `insertText`, then
+/// `layout`, whose result is serialized for JavaScript.
+fn bridge_roundtrip_insert_layout(c: &mut Criterion) {
+    let mut session = EditSession::new(prose_doc(10_000));
+    session.focus(5_000);
+    c.bench_function("bridge_roundtrip_insert_layout", |b| {
+        b.iter(|| {
+            session.editor.execute(EditorCommand::InsertText("字".to_string()));
+            session.config = LayoutConfig { page_width: 794.0, ..LayoutConfig::default() };
+            layout_summary_json(&session.relayout()).len()
+        })
+    });
+    print_layout_stats("bridge_roundtrip_insert_layout", &session.engine.take_stats());
+}
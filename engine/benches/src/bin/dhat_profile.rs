@@ -1,4 +1,4 @@
-use wa_core::{Block, Document, Inline};
+use wa_core::{Block, Document, Editor, EditorCommand, Inline};
 use std::sync::Arc;
 
 #[global_allocator]
@@ -13,7 +13,15 @@ fn main() {
             id: uuid::Uuid::new_v4(),
             content: vec![Inline::Text { value: Arc::from(text) }],
             dirty: false,
+            locked: false,
         });
     }
     let _ = serde_json::to_string(&doc).unwrap();
+
+    // Structural edits snapshot the whole document; unchanged blocks are
+    // shared between snapshots, so this should stay far below 100 copies.
+    let mut editor = Editor::new(doc);
+    for _ in 0..100 {
+        editor.execute(EditorCommand::InsertTable(2, 2));
+    }
 }
@@ -1 +1,114 @@
-﻿// Bench crate placeholder
+//! Documents and editing sequences shared by the benches and the latency
+//! test, so both time the same work.
+use std::sync::Arc;
+use wa_core::{Block, Cell, Document, Editor, EditorCommand, Inline, Position, Selection};
+use wa_engine::{EngineOptions, LayoutCache, LayoutConfig, LayoutEngine, LayoutStats, LayoutTree};
+
+/// `blocks` paragraphs of about three wrapped lines of prose each.
+pub fn prose_doc(blocks: usize) -> Document {
+    let mut doc = Document::new();
+    for i in 0..blocks {
+        let text = format!("{} 这是一些测试文本，用于布局性能评估。Typing latency is what users feel. {}", i, "段落内容".repeat(8));
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(text) }],
+            dirty: false,
+            locked: false,
+        });
+    }
+    doc
+}
+
+/// A paragraph, then one `rows` by `cols` table of short cells.
+pub fn table_doc(rows: usize, cols: usize) -> Document {
+    let mut doc = prose_doc(1);
+    doc.blocks.push(Block::Table {
+        id: uuid::Uuid::new_v4(),
+        rows: (0..rows)
+            .map(|r| (0..cols).map(|c| Cell { content: vec![Inline::Text { value: Arc::from(format!("单元格 {r}-{c}")) }] }).collect())
+            .collect(),
+        header: true,
+        col_widths: Vec::new(),
+        dirty: false,
+        locked: false,
+    });
+    doc
+}
+
+/// An editor and a layout engine kept the way the bridge keeps them: after
+/// every edit the document is laid out again through the layout cache,
+/// keyed by the editor's block signatures.
+pub struct EditSession {
+    pub editor: Editor,
+    pub engine: LayoutEngine,
+    pub cache: LayoutCache,
+    pub config: LayoutConfig,
+}
+
+impl EditSession {
+    /// Lays `doc` out once, so the session starts with a warm cache.
+    pub fn new(doc: Document) -> Self {
+        let mut engine = LayoutEngine::with_options(EngineOptions::default());
+        engine.set_collect_timings(true);
+        let mut session = Self { editor: Editor::new(doc), engine, cache: LayoutCache::new(), config: LayoutConfig::default() };
+        session.relayout();
+        session.engine.take_stats();
+        session
+    }
+
+    /// Puts the caret at the end of top-level block `index`.
+    pub fn focus(&mut self, index: usize) {
+        let block = &self.editor.doc.blocks[index];
+        let offset = wa_core::block_text_len(block);
+        self.editor.selection = Selection::collapsed(Position { block_id: block.id(), offset });
+    }
+
+    pub fn relayout(&mut self) -> LayoutTree {
+        self.engine.layout_cached_with_signatures(&self.editor.doc, &self.config, &mut self.cache, &self.editor.signatures)
+    }
+
+    /// One keystroke as the user feels it: the edit, then the relayout.
+    pub fn keystroke(&mut self, text: &str) -> LayoutTree {
+        self.editor.execute(EditorCommand::InsertText(text.to_string()));
+        self.relayout()
+    }
+
+    pub fn run(&mut self, cmd: EditorCommand) -> LayoutTree {
+        self.editor.execute(cmd);
+        self.relayout()
+    }
+}
+
+/// What the bridge's `layout` call returns to JavaScript, as the JSON text
+/// of the value it builds.
+pub fn layout_summary_json(tree: &LayoutTree) -> String {
+    let blocks: Vec<_> = tree
+        .pages
+        .iter()
+        .flat_map(|page| &page.blocks)
+        .map(|block| serde_json::json!({ "id": block.block_id.to_string(), "height": block.height, "lines": block.lines.len() }))
+        .collect();
+    serde_json::Value::from(blocks).to_string()
+}
+
+/// Per-call averages of what the engine did during a bench; nothing for a
+/// bench the filter skipped.
+pub fn print_layout_stats(name: &str, stats: &LayoutStats) {
+    if stats.calls == 0 {
+        return;
+    }
+    let calls = stats.calls;
+    println!(
+        "{name}: {} calls, {} laid out + {} reused blocks/call, {} pages, break hits {:.2}, glyph hits {:.2}, {} workers, wrap {:?} measure {:?} paginate {:?} per call",
+        stats.calls,
+        stats.blocks_laid_out / calls,
+        stats.blocks_reused / calls,
+        stats.pages,
+        stats.break_hit_rate().unwrap_or(0.0),
+        stats.glyph_hit_rate().unwrap_or(0.0),
+        stats.parallel_workers,
+        stats.wrap / calls as u32,
+        stats.measure / calls as u32,
+        stats.paginate / calls as u32,
+    );
+}
@@ -0,0 +1,30 @@
+use std::time::{Duration, Instant};
+use wa_benches::{prose_doc, EditSession};
+
+/// One keystroke plus relayout on the 10k-block document, in whatever
+/// profile the tests run in. A release build takes a small fraction of
+/// this (see the `type_in_10k_block_doc` bench); the budget only catches
+/// order-of-magnitude regressions.
+const KEYSTROKE_BUDGET: Duration = Duration::from_millis(250);
+
+#[test]
+fn keystroke_on_10k_blocks_stays_within_budget() {
+    let mut session = EditSession::new(prose_doc(10_000));
+    session.focus(5_000);
+    session.keystroke("字");
+    session.engine.take_stats();
+
+    let keystrokes = 10;
+    let start = Instant::now();
+    for _ in 0..keystrokes {
+        session.keystroke("字");
+    }
+    let per_keystroke = start.elapsed() / keystrokes;
+    assert!(per_keystroke < KEYSTROKE_BUDGET, "keystroke took {per_keystroke:?}, budget {KEYSTROKE_BUDGET:?}");
+
+    // Time is noisy; the cache counters are not.
+    let stats = session.engine.take_stats();
+    assert_eq!(stats.calls, u64::from(keystrokes));
+    assert_eq!(stats.blocks_laid_out, u64::from(keystrokes));
+    assert_eq!(stats.blocks_reused, 9_999 * u64::from(keystrokes));
+}
@@ -1,14 +1,29 @@
-use wasm_bindgen::prelude::*;
-use wa_core::{Document, Editor, EditorCommand, Block, Inline, Style};
-use wa_engine::{LayoutEngine, LayoutCache, LayoutConfig};
+﻿use wasm_bindgen::prelude::*;
+use wa_core::{char_slice, code_offset_to_line_col, export_json_into_string, link_urls, Direction, Document, Editor, EditorCommand, Block, FindOptions, Fragment, RepairOptions, Retarget, SearchIndex, Style, TableEditor, TargetKind, Template, TemplateRegistry};
+use wa_engine::{EngineOptions, LayoutEngine, LayoutCache, LayoutConfig, LayoutTree, ViewState};
 use serde::Serialize;
-use std::sync::Arc;
 
 #[wasm_bindgen]
 pub struct WasmEditor {
     editor: Editor,
     layout_engine: LayoutEngine,
     layout_cache: LayoutCache,
+    /// Config of the last `layout` call, which scroll anchors are measured
+    /// against.
+    layout_config: LayoutConfig,
+    /// Reused by `exportJson` so repeated exports don't reallocate.
+    json_buf: String,
+    templates: TemplateRegistry,
+    /// Table cell keyboard input goes to, as block, row and column.
+    table_focus: Option<(uuid::Uuid, usize, usize)>,
+    /// Trigram index `find` narrows candidates with, once enabled.
+    search_index: Option<SearchIndex>,
+}
+
+impl Default for WasmEditor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -18,23 +33,31 @@ impl WasmEditor {
         console_error_panic_hook::set_once();
         Self {
             editor: Editor::new(Document::new()),
-            layout_engine: LayoutEngine::new(),
+            layout_engine: LayoutEngine::with_options(EngineOptions::default()),
             layout_cache: LayoutCache::new(),
+            layout_config: LayoutConfig::default(),
+            json_buf: String::new(),
+            templates: TemplateRegistry::with_builtins(),
+            table_focus: None,
+            search_index: None,
         }
     }
 
+    /// Loads a document, repairing values layout cannot use. Returns the
+    /// repairs made, one message per fixup.
     #[wasm_bindgen(js_name = loadJson)]
-    pub fn load_json(&mut self, json: &str) -> Result<(), JsValue> {
-        let doc: Document = serde_json::from_str(json)
-            .map_err(|e| JsValue::from_str(&format!("JSON解析失败: {}", e)))?;
-        self.editor = Editor::new(doc);
-        Ok(())
+    pub fn load_json(&mut self, json: &str) -> Result<JsValue, JsValue> {
+        let (doc, fixups) = wa_core::import_json_with_fixups(json).map_err(|e| core_error("JSON解析失败", e))?;
+        self.replace_document(doc);
+        let messages: Vec<String> = fixups.iter().map(|f| f.to_string()).collect();
+        serde_wasm_bindgen::to_value(&messages).map_err(|e| js_error("serialize", e))
     }
 
     #[wasm_bindgen(js_name = exportJson)]
-    pub fn export_json(&self) -> Result<String, JsValue> {
-        serde_json::to_string(&self.editor.doc)
-            .map_err(|e| JsValue::from_str(&format!("JSON序列化失败: {}", e)))
+    pub fn export_json(&mut self) -> Result<JsValue, JsValue> {
+        export_json_into_string(&self.editor.doc, &mut self.json_buf)
+            .map_err(|e| core_error("JSON序列化失败", e))?;
+        Ok(JsValue::from_str(&self.json_buf))
     }
 
     #[wasm_bindgen(js_name = insertText)]
@@ -42,6 +65,23 @@ impl WasmEditor {
         self.editor.execute(EditorCommand::InsertText(text.to_string()));
     }
 
+    #[wasm_bindgen(js_name = insertParagraphBreak)]
+    pub fn insert_paragraph_break(&mut self) {
+        self.editor.execute(EditorCommand::InsertParagraphBreak);
+    }
+
+    /// Enter in a list: a new item after the caret, or out of the list
+    /// from an empty item.
+    #[wasm_bindgen(js_name = splitListItem)]
+    pub fn split_list_item(&mut self) {
+        self.editor.execute(EditorCommand::SplitListItem);
+    }
+
+    #[wasm_bindgen(js_name = setMarkdownShortcuts)]
+    pub fn set_markdown_shortcuts(&mut self, enabled: bool) {
+        self.editor.config.markdown_shortcuts = enabled;
+    }
+
     #[wasm_bindgen(js_name = deleteBackward)]
     pub fn delete_backward(&mut self) {
         self.editor.execute(EditorCommand::DeleteSelection);
@@ -49,35 +89,139 @@ impl WasmEditor {
 
     #[wasm_bindgen(js_name = toggleBold)]
     pub fn toggle_bold(&mut self) {
-        let mut style = Style::default();
-        style.bold = true;
+        let style = Style { bold: true, ..Style::default() };
         self.editor.execute(EditorCommand::ApplyStyle(style));
     }
 
     #[wasm_bindgen(js_name = toggleItalic)]
     pub fn toggle_italic(&mut self) {
-        let mut style = Style::default();
-        style.italic = true;
+        let style = Style { italic: true, ..Style::default() };
         self.editor.execute(EditorCommand::ApplyStyle(style));
     }
 
+    /// Moves the caret a word "left" or "right", extending the selection
+    /// when `extend` is set. Returns whether it moved.
+    #[wasm_bindgen(js_name = moveWord)]
+    pub fn move_word(&mut self, direction: &str, extend: bool) -> Result<bool, JsValue> {
+        let before = self.editor.selection;
+        let forward = match direction {
+            "left" => false,
+            "right" => true,
+            _ => return Err(js_error("command", format!("未知的方向: {}", direction))),
+        };
+        self.editor.execute(EditorCommand::MoveCursorWord { forward, extend });
+        Ok(self.editor.selection != before)
+    }
+
+    #[wasm_bindgen(js_name = deleteWordBackward)]
+    pub fn delete_word_backward(&mut self) {
+        self.editor.execute(EditorCommand::DeleteWordBackward);
+    }
+
+    #[wasm_bindgen(js_name = deleteWordForward)]
+    pub fn delete_word_forward(&mut self) {
+        self.editor.execute(EditorCommand::DeleteWordForward);
+    }
+
+    #[wasm_bindgen(js_name = moveBlockUp)]
+    pub fn move_block_up(&mut self) {
+        self.editor.execute(EditorCommand::MoveBlockUp);
+    }
+
+    #[wasm_bindgen(js_name = moveBlockDown)]
+    pub fn move_block_down(&mut self) {
+        self.editor.execute(EditorCommand::MoveBlockDown);
+    }
+
+    #[wasm_bindgen(js_name = duplicateBlock)]
+    pub fn duplicate_block(&mut self) {
+        self.editor.execute(EditorCommand::DuplicateBlock);
+    }
+
+    /// Removes the block with id `block_id`, or the one holding the caret
+    /// when it is not given.
+    #[wasm_bindgen(js_name = deleteBlock)]
+    pub fn delete_block(&mut self, block_id: Option<String>) -> Result<(), JsValue> {
+        let block_id = block_id.map(|id| uuid::Uuid::parse_str(&id)).transpose().map_err(|e| js_error("command", format!("无效的块 ID: {}", e)))?;
+        self.editor.execute(EditorCommand::DeleteBlock { block_id });
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = selectAll)]
+    pub fn select_all(&mut self) {
+        self.editor.execute(EditorCommand::SelectAll);
+    }
+
+    #[wasm_bindgen(js_name = selectWord)]
+    pub fn select_word(&mut self) {
+        self.editor.execute(EditorCommand::SelectWord);
+    }
+
+    #[wasm_bindgen(js_name = selectBlock)]
+    pub fn select_block(&mut self) {
+        self.editor.execute(EditorCommand::SelectBlock);
+    }
+
+    #[wasm_bindgen(js_name = clearFormatting)]
+    pub fn clear_formatting(&mut self) {
+        self.editor.execute(EditorCommand::ClearFormatting);
+    }
+
     #[wasm_bindgen(js_name = toggleUnderline)]
     pub fn toggle_underline(&mut self) {
-        let mut style = Style::default();
-        style.underline = true;
+        let style = Style { underline: true, ..Style::default() };
         self.editor.execute(EditorCommand::ApplyStyle(style));
     }
 
     #[wasm_bindgen(js_name = toggleStrikethrough)]
     pub fn toggle_strikethrough(&mut self) {
-        let mut style = Style::default();
-        style.strikethrough = true;
+        let style = Style { strikethrough: true, ..Style::default() };
         self.editor.execute(EditorCommand::ApplyStyle(style));
     }
 
     #[wasm_bindgen(js_name = setHeading)]
-    pub fn set_heading(&mut self, level: u8) {
-        self.editor.execute(EditorCommand::SetHeading(level));
+    pub fn set_heading(&mut self, level: u8) -> Result<(), JsValue> {
+        self.editor
+            .try_execute(EditorCommand::SetHeading(level))
+            .map(|_| ())
+            .map_err(|e| js_error("command", format!("无法设为标题: {}", e)))
+    }
+
+    /// Turns the block holding the caret into a "paragraph", a "heading" of
+    /// `level`, a "quote" or a "code" block in `lang`.
+    #[wasm_bindgen(js_name = convertBlock)]
+    pub fn convert_block(&mut self, kind: &str, level: Option<u8>, lang: Option<String>) -> Result<(), JsValue> {
+        let target = match kind {
+            "paragraph" => TargetKind::Paragraph,
+            "heading" => TargetKind::Heading(level.unwrap_or(1)),
+            "quote" => TargetKind::Quote,
+            "code" => TargetKind::Code { lang: lang.unwrap_or_default() },
+            _ => return Err(js_error("command", format!("未知的块类型: {}", kind))),
+        };
+        self.editor
+            .try_execute(EditorCommand::ConvertBlock(target))
+            .map(|_| ())
+            .map_err(|e| js_error("command", format!("无法转换块: {}", e)))
+    }
+
+    /// Turns the selected paragraphs into a list, or the list holding the
+    /// caret back into paragraphs; see `EditorCommand::ToggleList`.
+    #[wasm_bindgen(js_name = toggleList)]
+    pub fn toggle_list(&mut self, ordered: bool) {
+        self.editor.execute(EditorCommand::ToggleList(ordered));
+    }
+
+    /// Ticks or unticks the task-list item holding the caret.
+    #[wasm_bindgen(js_name = toggleTask)]
+    pub fn toggle_task(&mut self) {
+        self.editor.execute(EditorCommand::ToggleTaskItem);
+    }
+
+    /// Sets the task-list item holding the caret to unticked, ticked or,
+    /// with `undefined`, a plain item.
+    #[wasm_bindgen(js_name = setTaskState)]
+    pub fn set_task_state(&mut self, checked: Option<bool>) {
+        self.editor.execute(EditorCommand::SetTaskState(checked));
     }
 
     #[wasm_bindgen(js_name = insertList)]
@@ -103,6 +247,17 @@ impl WasmEditor {
         self.editor.execute(EditorCommand::InsertImage(url.to_string()));
     }
 
+    /// Adds a figure holding the encoded image `bytes` (e.g. from a paste
+    /// event), undoable like any other insert.
+    #[wasm_bindgen(js_name = insertImageData)]
+    pub fn insert_image_data(&mut self, bytes: &[u8], mime: &str, caption: Option<String>) {
+        self.editor.execute(EditorCommand::InsertImageData {
+            bytes: bytes.to_vec(),
+            mime: mime.to_string(),
+            caption,
+        });
+    }
+
     #[wasm_bindgen(js_name = insertFigure)]
     pub fn insert_figure(&mut self, url: &str, caption: Option<String>) {
         self.editor.execute(EditorCommand::InsertFigure {
@@ -111,6 +266,14 @@ impl WasmEditor {
         });
     }
 
+    /// Adds a block the host app draws itself; `data_json` is kept as is.
+    #[wasm_bindgen(js_name = insertCustom)]
+    pub fn insert_custom(&mut self, kind: &str, data_json: &str) -> Result<(), JsValue> {
+        let data = serde_json::from_str(data_json).map_err(|e| core_error("JSON解析失败", e.into()))?;
+        self.editor.execute(EditorCommand::InsertCustom { kind: kind.to_string(), data });
+        Ok(())
+    }
+
     #[wasm_bindgen(js_name = insertQuote)]
     pub fn insert_quote(&mut self, text: &str) {
         self.editor.execute(EditorCommand::InsertQuote(text.to_string()));
@@ -124,6 +287,22 @@ impl WasmEditor {
         });
     }
 
+    #[wasm_bindgen(js_name = insertMath)]
+    pub fn insert_math(&mut self, tex: &str, display: bool) {
+        self.editor.execute(EditorCommand::InsertMath { tex: tex.to_string(), display });
+    }
+
+    #[wasm_bindgen(js_name = insertToc)]
+    pub fn insert_toc(&mut self) {
+        self.editor.execute(EditorCommand::InsertToc);
+    }
+
+    /// Rebuilds tables of contents from the headings; true when one changed.
+    #[wasm_bindgen(js_name = refreshToc)]
+    pub fn refresh_toc(&mut self) -> bool {
+        self.editor.refresh_toc()
+    }
+
     #[wasm_bindgen(js_name = tableInsertRow)]
     pub fn table_insert_row(&mut self) {
         self.editor.execute(EditorCommand::TableInsertRow);
@@ -144,14 +323,115 @@ impl WasmEditor {
         self.editor.execute(EditorCommand::TableDeleteColumn);
     }
 
+    #[wasm_bindgen(js_name = focusTableCell)]
+    pub fn focus_table_cell(&mut self, block_id: &str, row: usize, col: usize) -> Result<(), JsValue> {
+        let id = uuid::Uuid::parse_str(block_id).map_err(|e| js_error("command", format!("无效的块 ID: {}", e)))?;
+        if self.table_rows(id).and_then(|rows| rows.get(row)).and_then(|r| r.get(col)).is_none() {
+            return Err(js_error("command", format!("表格中没有单元格 ({}, {})", row, col)));
+        }
+        self.table_focus = Some((id, row, col));
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = clearTableFocus)]
+    pub fn clear_table_focus(&mut self) {
+        self.table_focus = None;
+    }
+
+    /// `{blockId, row, col}` of the focused cell, or null.
+    #[wasm_bindgen(js_name = tableFocus)]
+    pub fn table_focus(&mut self) -> JsValue {
+        match self.focused_cell() {
+            Some((id, row, col)) => serde_wasm_bindgen::to_value(&serde_json::json!({
+                "blockId": id.to_string(),
+                "row": row,
+                "col": col
+            }))
+            .unwrap_or(JsValue::NULL),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Tab: moves focus to the next cell, appending a row after the last
+    /// one. Returns false when no cell is focused.
+    #[wasm_bindgen(js_name = tableFocusNext)]
+    pub fn table_focus_next(&mut self) -> bool {
+        let Some((id, row, col)) = self.focused_cell() else {
+            return false;
+        };
+        let next = self.editor.next_table_cell(id, row, col);
+        self.move_table_focus(id, next)
+    }
+
+    /// Shift+Tab: moves focus to the previous cell. Returns false on the
+    /// first cell.
+    #[wasm_bindgen(js_name = tableFocusPrev)]
+    pub fn table_focus_prev(&mut self) -> bool {
+        let Some((id, row, col)) = self.focused_cell() else {
+            return false;
+        };
+        let prev = self.table_rows(id).and_then(|rows| TableEditor::prev_cell(rows, row, col));
+        self.move_table_focus(id, prev)
+    }
+
+    /// Enter: moves focus one row down. Returns false on the last row.
+    #[wasm_bindgen(js_name = tableFocusDown)]
+    pub fn table_focus_down(&mut self) -> bool {
+        let Some((id, row, col)) = self.focused_cell() else {
+            return false;
+        };
+        let below = self.table_rows(id).and_then(|rows| TableEditor::cell_below(rows, row, col));
+        self.move_table_focus(id, below)
+    }
+
     #[wasm_bindgen(js_name = listIndent)]
     pub fn list_indent(&mut self) {
-        self.editor.execute(EditorCommand::ListIndent);
+        self.editor.execute(EditorCommand::ListIndent { block_id: None, item: None });
     }
 
     #[wasm_bindgen(js_name = listOutdent)]
     pub fn list_outdent(&mut self) {
-        self.editor.execute(EditorCommand::ListOutdent);
+        self.editor.execute(EditorCommand::ListOutdent { block_id: None, item: None });
+    }
+
+    /// Index of the list item holding the caret, if the caret is in a list.
+    #[wasm_bindgen(js_name = focusedListItem)]
+    pub fn focused_list_item(&self) -> Option<usize> {
+        self.editor.focused_list_item()
+    }
+
+    /// Indents one item of a list; returns whether its level changed.
+    #[wasm_bindgen(js_name = listIndentItem)]
+    pub fn list_indent_item(&mut self, block_id: &str, item_index: usize) -> Result<bool, JsValue> {
+        let id = uuid::Uuid::parse_str(block_id).map_err(|e| js_error("command", format!("无效的块 ID: {}", e)))?;
+        Ok(self.run_changed(EditorCommand::ListIndent { block_id: Some(id), item: Some(item_index) }))
+    }
+
+    /// Outdents one item of a list; returns whether its level changed.
+    #[wasm_bindgen(js_name = listOutdentItem)]
+    pub fn list_outdent_item(&mut self, block_id: &str, item_index: usize) -> Result<bool, JsValue> {
+        let id = uuid::Uuid::parse_str(block_id).map_err(|e| js_error("command", format!("无效的块 ID: {}", e)))?;
+        Ok(self.run_changed(EditorCommand::ListOutdent { block_id: Some(id), item: Some(item_index) }))
+    }
+
+    /// Locks a block against edits; returns whether its lock changed.
+    #[wasm_bindgen(js_name = lockBlock)]
+    pub fn lock_block(&mut self, block_id: &str) -> Result<bool, JsValue> {
+        let id = uuid::Uuid::parse_str(block_id).map_err(|e| js_error("command", format!("无效的块 ID: {}", e)))?;
+        Ok(self.run_changed(EditorCommand::SetBlockLocked { block_id: id, locked: true }))
+    }
+
+    #[wasm_bindgen(js_name = unlockBlock)]
+    pub fn unlock_block(&mut self, block_id: &str) -> Result<bool, JsValue> {
+        let id = uuid::Uuid::parse_str(block_id).map_err(|e| js_error("command", format!("无效的块 ID: {}", e)))?;
+        Ok(self.run_changed(EditorCommand::SetBlockLocked { block_id: id, locked: false }))
+    }
+
+    /// `{kind: "applied"}`, or `{kind: "blocked_by_lock", block_id}` when
+    /// the last command left a locked block alone.
+    #[wasm_bindgen(js_name = lastOutcome)]
+    pub fn last_outcome(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.editor.last_outcome()).unwrap_or(JsValue::NULL)
     }
 
     #[wasm_bindgen(js_name = undo)]
@@ -174,46 +454,30 @@ impl WasmEditor {
     }
 
     #[wasm_bindgen(js_name = getStats)]
-    pub fn get_stats(&self) -> JsValue {
-        let char_count: usize = self.editor.doc.blocks.iter().map(|b| {
-            match b {
-                Block::Paragraph { content, .. } | Block::Heading { content, .. } => {
-                    content.iter().map(|i| match i {
-                        Inline::Text { value } => value.chars().count(),
-                        Inline::CodeSpan { value } => value.chars().count(),
-                        Inline::Link { text, .. } => text.iter().map(|t| match t {
-                            Inline::Text { value } => value.chars().count(),
-                            _ => 0,
-                        }).sum(),
-                        Inline::Styled { content, .. } => content.iter().map(|t| match t {
-                            Inline::Text { value } => value.chars().count(),
-                            _ => 0,
-                        }).sum(),
-                    }).sum()
-                }
-                _ => 0,
-            }
-        }).sum();
-
+    pub fn get_stats(&mut self) -> JsValue {
+        let stats = self.editor.text_index.document_stats(&self.editor.doc);
         serde_wasm_bindgen::to_value(&serde_json::json!({
-            "charCount": char_count,
-            "blockCount": self.editor.doc.blocks.len(),
-            "readingTime": (char_count as f64 / 400.0).ceil() as usize
+            "charCount": stats.text.chars,
+            "wordCount": stats.text.words,
+            "blockCount": stats.blocks,
+            "readingTime": stats.reading_minutes
         })).unwrap_or(JsValue::NULL)
     }
 
+    /// Layout counters since the last call (see `LayoutStats`); timings
+    /// are not collected in wasm.
+    #[wasm_bindgen(js_name = getEngineStats)]
+    pub fn get_engine_stats(&mut self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.layout_engine.take_stats()).unwrap_or(JsValue::NULL)
+    }
+
     #[wasm_bindgen(js_name = layout)]
     pub fn layout(&mut self, width: f32) -> Result<JsValue, JsValue> {
-        let config = LayoutConfig {
+        self.layout_config = LayoutConfig {
             page_width: width,
             ..Default::default()
         };
-        
-        let layout_tree = self.layout_engine.layout_cached(
-            &self.editor.doc,
-            &config,
-            &mut self.layout_cache,
-        );
+        let layout_tree = self.current_layout();
 
         let mut blocks_info = Vec::new();
         for page in &layout_tree.pages {
@@ -227,7 +491,29 @@ impl WasmEditor {
         }
 
         serde_wasm_bindgen::to_value(&blocks_info)
-            .map_err(|e| JsValue::from_str(&format!("布局序列化失败: {}", e)))
+            .map_err(|e| js_error("serialize", format!("布局序列化失败: {}", e)))
+    }
+
+    /// The view at `scroll_offset`, with pages drawn `page_gap` apart, and
+    /// the caret, as JSON for `restoreScrollAnchor`.
+    #[wasm_bindgen(js_name = scrollAnchor)]
+    pub fn scroll_anchor(&mut self, scroll_offset: f32, page_gap: f32) -> Result<String, JsValue> {
+        let layout = self.current_layout();
+        let mut state = ViewState::capture(&self.editor.doc, &layout, &self.layout_config, page_gap, scroll_offset);
+        state.caret = Some(self.editor.selection.focus);
+        serde_json::to_string(&state).map_err(|e| js_error("serialize", e))
+    }
+
+    /// Puts the caret back and returns the scroll offset to restore. When the
+    /// anchored block is gone, the block now at its index stands in.
+    #[wasm_bindgen(js_name = restoreScrollAnchor)]
+    pub fn restore_scroll_anchor(&mut self, json: &str, page_gap: f32) -> Result<f32, JsValue> {
+        let state: ViewState = serde_json::from_str(json).map_err(|e| core_error("视图状态解析失败", e.into()))?;
+        let layout = self.current_layout();
+        if let Some(caret) = state.caret_in(&self.editor.doc) {
+            self.editor.selection = wa_core::Selection::collapsed(caret);
+        }
+        Ok(state.restore_offset(&self.editor.doc, &layout, &self.layout_config, page_gap))
     }
 
     #[wasm_bindgen(js_name = exportMarkdown)]
@@ -235,71 +521,207 @@ impl WasmEditor {
         wa_core::export_markdown(&self.editor.doc)
     }
 
+    /// The selection as Markdown, styles and links kept; empty when
+    /// nothing is selected.
+    #[wasm_bindgen(js_name = copySelectionMarkdown)]
+    pub fn copy_selection_markdown(&self) -> String {
+        wa_core::export_markdown(&self.selection_doc())
+    }
+
+    /// The selection as an HTML fragment for the `text/html` clipboard
+    /// flavor.
+    #[wasm_bindgen(js_name = copySelectionHtml)]
+    pub fn copy_selection_html(&self) -> String {
+        wa_core::export_html(&self.selection_doc())
+    }
+
+    /// What changed from the document in `json`, an earlier save, to the
+    /// one being edited: `compare_documents` results with word ranges to
+    /// paint.
+    #[wasm_bindgen(js_name = compareWithJson)]
+    pub fn compare_with_json(&self, json: &str) -> Result<JsValue, JsValue> {
+        let old = wa_core::import_json(json).map_err(|e| core_error("JSON解析失败", e))?;
+        let diffs = wa_core::compare_documents(&old, &self.editor.doc);
+        serde_wasm_bindgen::to_value(&diffs).map_err(|e| js_error("serialize", e))
+    }
+
     #[wasm_bindgen(js_name = importMarkdown)]
     pub fn import_markdown(&mut self, md: &str) -> Result<(), JsValue> {
         let doc = wa_core::import_markdown(md);
-        self.editor = Editor::new(doc);
+        self.replace_document(doc);
         Ok(())
     }
 
     #[wasm_bindgen(js_name = find)]
-    pub fn find(&self, query: &str) -> JsValue {
-        let q = query;
-        if q.is_empty() {
-            let empty: Vec<FindHit> = Vec::new();
-            return serde_wasm_bindgen::to_value(&empty).unwrap_or(JsValue::NULL);
-        }
-        let mut hits: Vec<FindHit> = Vec::new();
-        let q_len = q.chars().count();
-        for (block_index, block) in self.editor.doc.blocks.iter().enumerate() {
-            let text = block_plain_text(block);
-            if text.is_empty() {
-                continue;
-            }
-            for (byte_idx, _) in text.match_indices(q) {
-                let start = text[..byte_idx].chars().count();
-                let end = start + q_len;
-                let snippet = build_snippet(&text, start, end);
-                hits.push(FindHit {
-                    block_id: block.id().to_string(),
-                    block_index,
-                    start,
-                    end,
-                    block_type: block_type_name(block).to_string(),
-                    snippet,
-                });
-            }
-        }
-        serde_wasm_bindgen::to_value(&hits).unwrap_or(JsValue::NULL)
+    pub fn find(&mut self, query: &str) -> JsValue {
+        self.find_hits(query, FindOptions::default())
+    }
+
+    /// `find` with options given as JSON, e.g. `{"include_code": true}`.
+    /// `"block_types": ["heading"]` and `"scope": {"blockIds": [...]}`
+    /// narrow the blocks searched.
+    #[wasm_bindgen(js_name = findWithOptions)]
+    pub fn find_with_options(&mut self, query: &str, options_json: &str) -> Result<JsValue, JsValue> {
+        let options = parse_find_options(options_json)?;
+        Ok(self.find_hits(query, options))
+    }
+
+    /// Makes `find` and `findWithOptions` check only blocks that can hold
+    /// the query, at the cost of keeping a trigram index of the document.
+    #[wasm_bindgen(js_name = enableSearchIndex)]
+    pub fn enable_search_index(&mut self) {
+        self.search_index.get_or_insert_with(SearchIndex::new);
     }
 
     #[wasm_bindgen(js_name = replace)]
     pub fn replace(&mut self, query: &str, replacement: &str) -> Result<usize, JsValue> {
-        if query.is_empty() {
-            return Ok(0);
-        }
-        let mut total = 0usize;
-        for block in &self.editor.doc.blocks {
-            total += count_in_block(block, query);
-        }
-        if total == 0 {
-            return Ok(0);
-        }
-        self.editor.checkpoint();
-        let mut replaced = 0usize;
-        for block in &mut self.editor.doc.blocks {
-            replaced += replace_in_block(block, query, replacement);
-        }
-        if replaced > 0 {
-            self.editor.doc.touch();
-        }
-        Ok(replaced)
+        self.replace_all(query, replacement, FindOptions::default())
+    }
+
+    /// `replace` with the same options as `findWithOptions`; by default
+    /// code blocks, inline code and link URLs are left alone.
+    #[wasm_bindgen(js_name = replaceWithOptions)]
+    pub fn replace_with_options(&mut self, query: &str, replacement: &str, options_json: &str) -> Result<usize, JsValue> {
+        let options = parse_find_options(options_json)?;
+        self.replace_all(query, replacement, options)
+    }
+
+    /// Content hash that ignores ids, dirty flags, the version and
+    /// timestamps, as 16 hex digits. Replicas that converged report the
+    /// same value.
+    #[wasm_bindgen(js_name = semanticHash)]
+    pub fn semantic_hash(&self) -> String {
+        format!("{:016x}", wa_core::semantic_hash(&self.editor.doc))
+    }
+
+    /// Whether the document in `json` holds the same content as this one.
+    #[wasm_bindgen(js_name = semanticEquals)]
+    pub fn semantic_equals(&self, json: &str) -> Result<bool, JsValue> {
+        let other = wa_core::import_json(json).map_err(|e| core_error("JSON解析失败", e))?;
+        Ok(wa_core::doc_semantic_eq(&self.editor.doc, &other))
     }
 
     #[wasm_bindgen(js_name = checkpoint)]
     pub fn checkpoint(&mut self) {
         self.editor.checkpoint();
     }
+
+    /// Moves the caret "left", "right", "up", "down", "lineStart",
+    /// "lineEnd", "blockStart", "blockEnd", "nextBlock" or "prevBlock",
+    /// extending the selection when `extend` is set. Up and down go by the
+    /// current layout and keep to the column they started from. Returns
+    /// whether it moved.
+    #[wasm_bindgen(js_name = moveCaret)]
+    pub fn move_caret(&mut self, direction: &str, extend: bool) -> Result<bool, JsValue> {
+        let before = self.editor.selection;
+        let step = match direction {
+            "left" => Direction::Left,
+            "right" => Direction::Right,
+            "lineStart" => Direction::LineStart,
+            "lineEnd" => Direction::LineEnd,
+            "blockStart" => Direction::BlockStart,
+            "blockEnd" => Direction::BlockEnd,
+            "nextBlock" => Direction::NextBlock,
+            "prevBlock" => Direction::PrevBlock,
+            "up" | "down" => {
+                let layout = self.current_layout();
+                let measurer = self.layout_engine.measurer();
+                wa_engine::move_caret_vertically(&mut self.editor, &layout, &self.layout_config, measurer, direction == "down", extend);
+                return Ok(self.editor.selection != before);
+            }
+            _ => return Err(js_error("command", format!("未知的方向: {}", direction))),
+        };
+        self.editor.execute(EditorCommand::MoveCursor { direction: step, extend });
+        Ok(self.editor.selection != before)
+    }
+
+    /// Puts the caret at 1-based `line` and `column` of a code block.
+    #[wasm_bindgen(js_name = gotoLine)]
+    pub fn goto_line(&mut self, block_id: &str, line: usize, column: usize) -> Result<(), JsValue> {
+        let id = uuid::Uuid::parse_str(block_id).map_err(|e| js_error("command", format!("无效的块 ID: {}", e)))?;
+        if !self.editor.goto_line(id, line, column) {
+            return Err(js_error("command", format!("代码块中没有第 {} 行", line)));
+        }
+        Ok(())
+    }
+
+    /// Names of the templates `insertTemplate` accepts.
+    #[wasm_bindgen(js_name = listTemplates)]
+    pub fn list_templates(&self) -> Vec<String> {
+        self.templates.names().map(str::to_string).collect()
+    }
+
+    /// Adds a template given as `{"name": ..., "blocks": [...]}`, replacing
+    /// one of the same name.
+    #[wasm_bindgen(js_name = registerTemplate)]
+    pub fn register_template(&mut self, json: &str) -> Result<(), JsValue> {
+        let template: Template = serde_json::from_str(json).map_err(|e| core_error("模板解析失败", e.into()))?;
+        self.templates.register(template);
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = insertTemplate)]
+    pub fn insert_template(&mut self, name: &str) -> Result<(), JsValue> {
+        let template = self.templates.get(name).ok_or_else(|| js_error("command", format!("未知模板: {}", name)))?;
+        self.editor.insert_template(template);
+        Ok(())
+    }
+
+    /// The selected blocks as snippet JSON, see `wa_core::Fragment`. Empty
+    /// selections are an error.
+    #[wasm_bindgen(js_name = exportSelectionFragment)]
+    pub fn export_selection_fragment(&self) -> Result<String, JsValue> {
+        let blocks = self.editor.selection_fragment();
+        if blocks.is_empty() {
+            return Err(js_error("command", "没有选中内容"));
+        }
+        Ok(Fragment::new(blocks, Some(self.editor.doc.id)).to_json())
+    }
+
+    /// Inserts the blocks of a snippet after the focused block, with fresh ids.
+    #[wasm_bindgen(js_name = insertFragment)]
+    pub fn insert_fragment(&mut self, json: &str) -> Result<(), JsValue> {
+        let blocks = wa_core::import_fragment(json).map_err(|e| core_error("片段解析失败", e))?;
+        self.editor.insert_blocks(blocks);
+        Ok(())
+    }
+
+    /// Makes the repairs in `options_json` (see `wa_core::RepairOptions`;
+    /// `{}` for the defaults) as one undo step. Returns how many blocks
+    /// changed.
+    #[wasm_bindgen(js_name = repairDocument)]
+    pub fn repair_document(&mut self, options_json: &str) -> Result<usize, JsValue> {
+        let options: RepairOptions =
+            serde_json::from_str(options_json).map_err(|e| core_error("修复选项解析失败", e.into()))?;
+        Ok(self.editor.repair(&options))
+    }
+
+    #[wasm_bindgen(js_name = startRecording)]
+    pub fn start_recording(&mut self) {
+        self.editor.start_recording();
+    }
+
+    /// The commands run since `startRecording`, as a JSON array.
+    #[wasm_bindgen(js_name = stopRecording)]
+    pub fn stop_recording(&mut self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.editor.stop_recording()).map_err(|e| js_error("serialize", e))
+    }
+
+    /// Runs a JSON array from `stopRecording` as one undo step. `retarget`
+    /// is "keep" (the default) or "focused_block".
+    #[wasm_bindgen(js_name = replay)]
+    pub fn replay(&mut self, commands_json: &str, retarget: Option<String>) -> Result<(), JsValue> {
+        let commands: Vec<EditorCommand> =
+            serde_json::from_str(commands_json).map_err(|e| core_error("宏解析失败", e.into()))?;
+        let retarget = match retarget.as_deref() {
+            None => Retarget::default(),
+            Some(name) => serde_json::from_value(serde_json::Value::from(name))
+                .map_err(|e| core_error("重定向策略无效", e.into()))?,
+        };
+        self.editor
+            .replay(&commands, retarget)
+            .map_err(|e| js_error("command", format!("宏回放失败: {}", e)))
+    }
 }
 
 #[derive(Serialize)]
@@ -308,235 +730,153 @@ struct FindHit {
     block_index: usize,
     start: usize,
     end: usize,
+    /// Set for hits inside a link URL; `start`/`end` then index that URL.
+    link: Option<usize>,
     block_type: String,
     snippet: String,
+    /// 1-based line and column of `start` for hits in code blocks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
 }
 
-fn block_type_name(block: &Block) -> &'static str {
-    match block {
-        Block::Heading { .. } => "heading",
-        Block::Paragraph { .. } => "paragraph",
-        Block::List { .. } => "list",
-        Block::Quote { .. } => "quote",
-        Block::Code { .. } => "code",
-        Block::Table { .. } => "table",
-        Block::Figure { .. } => "figure",
+impl WasmEditor {
+    /// Runs `cmd` and reports whether it changed the document.
+    fn run_changed(&mut self, cmd: EditorCommand) -> bool {
+        let version = self.editor.doc.version;
+        self.editor.execute(cmd);
+        self.editor.doc.version != version
     }
-}
 
-fn inline_plain_text(inlines: &[Inline], out: &mut String) {
-    for inline in inlines {
-        match inline {
-            Inline::Text { value } => out.push_str(value.as_ref()),
-            Inline::CodeSpan { value } => out.push_str(value.as_ref()),
-            Inline::Link { text, .. } => inline_plain_text(text, out),
-            Inline::Styled { content, .. } => inline_plain_text(content, out),
-        }
+    fn current_layout(&mut self) -> LayoutTree {
+        self.layout_engine.layout_cached_with_signatures(
+            &self.editor.doc,
+            &self.layout_config,
+            &mut self.layout_cache,
+            &self.editor.signatures,
+        )
     }
-}
 
-fn block_plain_text(block: &Block) -> String {
-    let mut out = String::new();
-    match block {
-        Block::Heading { content, .. } | Block::Paragraph { content, .. } => {
-            inline_plain_text(content, &mut out);
-        }
-        Block::List { items, .. } => {
-            for (idx, item) in items.iter().enumerate() {
-                if idx > 0 {
-                    out.push('\n');
-                }
-                inline_plain_text(&item.content, &mut out);
-            }
+    fn find_hits(&mut self, query: &str, options: FindOptions) -> JsValue {
+        let mut hits: Vec<FindHit> = Vec::new();
+        let matches = match &mut self.search_index {
+            Some(search) => search.find_in_doc(&self.editor.doc, query, options),
+            None => self.editor.text_index.find_in_doc(&self.editor.doc, query, options),
+        };
+        let index = &mut self.editor.text_index;
+        for m in matches {
+            let block = &self.editor.doc.blocks[m.block_index];
+            let snippet = match m.link {
+                Some(link) => build_snippet(link_urls(block).get(link).copied().unwrap_or(""), m.start, m.end),
+                None => build_snippet(index.text(block), m.start, m.end),
+            };
+            let line_col = m.link.is_none().then(|| code_offset_to_line_col(block, m.start)).flatten();
+            hits.push(FindHit {
+                block_id: m.block_id.to_string(),
+                block_index: m.block_index,
+                start: m.start,
+                end: m.end,
+                link: m.link,
+                block_type: block.type_name().to_string(),
+                snippet,
+                line: line_col.map(|(line, _)| line),
+                column: line_col.map(|(_, column)| column),
+            });
         }
-        Block::Quote { content, .. } => {
-            for (idx, inner) in content.iter().enumerate() {
-                if idx > 0 {
-                    out.push('\n');
-                }
-                out.push_str(&block_plain_text(inner));
-            }
+        serde_wasm_bindgen::to_value(&hits).unwrap_or(JsValue::NULL)
+    }
+
+    fn replace_all(&mut self, query: &str, replacement: &str, options: FindOptions) -> Result<usize, JsValue> {
+        if query.is_empty() {
+            return Ok(0);
         }
-        Block::Code { code, .. } => out.push_str(code.as_ref()),
-        Block::Table { rows, .. } => {
-            for (ri, row) in rows.iter().enumerate() {
-                if ri > 0 {
-                    out.push('\n');
-                }
-                for (ci, cell) in row.iter().enumerate() {
-                    if ci > 0 {
-                        out.push('\t');
-                    }
-                    inline_plain_text(&cell.content, &mut out);
-                }
-            }
+        let total = self.editor.text_index.find_in_doc(&self.editor.doc, query, options.clone()).len();
+        if total > 0 {
+            self.editor.execute(EditorCommand::ReplaceAll {
+                query: query.to_string(),
+                replacement: replacement.to_string(),
+                options,
+            });
         }
-        Block::Figure { caption, .. } => {
-            if let Some(c) = caption {
-                out.push_str(c.as_ref());
-            }
+        Ok(total)
+    }
+
+    /// Swaps in a new document, keeping the editor settings.
+    fn table_rows(&self, block_id: uuid::Uuid) -> Option<&[Vec<wa_core::Cell>]> {
+        match self.editor.doc.blocks.iter().find(|b| b.id() == block_id)? {
+            Block::Table { rows, .. } => Some(rows),
+            _ => None,
         }
     }
-    out
-}
 
-fn char_to_byte_idx(s: &str, char_idx: usize) -> usize {
-    if char_idx == 0 {
-        return 0;
+    /// The focused cell, clamped into its table after edits shrank it, or
+    /// None once the table is gone.
+    fn focused_cell(&mut self) -> Option<(uuid::Uuid, usize, usize)> {
+        let (id, row, col) = self.table_focus?;
+        let rows = self.table_rows(id).filter(|rows| !rows.is_empty());
+        self.table_focus = rows.and_then(|rows| {
+            let row = row.min(rows.len() - 1);
+            let cols = rows[row].len();
+            (cols > 0).then(|| (id, row, col.min(cols - 1)))
+        });
+        self.table_focus
     }
-    s.char_indices()
-        .nth(char_idx)
-        .map(|(i, _)| i)
-        .unwrap_or_else(|| s.len())
-}
 
-fn build_snippet(text: &str, start: usize, end: usize) -> String {
-    let total = text.chars().count();
-    let ctx = 20usize;
-    let s = start.saturating_sub(ctx);
-    let e = (end + ctx).min(total);
-    let s_b = char_to_byte_idx(text, s);
-    let e_b = char_to_byte_idx(text, e);
-    text[s_b..e_b].to_string()
-}
+    fn move_table_focus(&mut self, block_id: uuid::Uuid, to: Option<(usize, usize)>) -> bool {
+        let Some((row, col)) = to else {
+            return false;
+        };
+        self.table_focus = Some((block_id, row, col));
+        true
+    }
 
-fn count_in_text(text: &str, query: &str) -> usize {
-    if query.is_empty() {
-        return 0;
+    fn selection_doc(&self) -> Document {
+        Document { blocks: self.editor.selection_fragment(), ..Document::new() }
     }
-    text.matches(query).count()
-}
 
-fn count_in_inlines(inlines: &[Inline], query: &str) -> usize {
-    let mut count = 0;
-    for inline in inlines {
-        match inline {
-            Inline::Text { value } => count += count_in_text(value.as_ref(), query),
-            Inline::CodeSpan { value } => count += count_in_text(value.as_ref(), query),
-            Inline::Link { text, .. } => count += count_in_inlines(text, query),
-            Inline::Styled { content, .. } => count += count_in_inlines(content, query),
+    fn replace_document(&mut self, doc: Document) {
+        self.table_focus = None;
+        if self.search_index.is_some() {
+            self.search_index = Some(SearchIndex::new());
         }
+        let config = self.editor.config;
+        self.editor = Editor::new(doc);
+        self.editor.config = config;
     }
-    count
 }
 
-fn count_in_block(block: &Block, query: &str) -> usize {
-    match block {
-        Block::Heading { content, .. } | Block::Paragraph { content, .. } => count_in_inlines(content, query),
-        Block::List { items, .. } => items.iter().map(|i| count_in_inlines(&i.content, query)).sum(),
-        Block::Quote { content, .. } => content.iter().map(|b| count_in_block(b, query)).sum(),
-        Block::Code { code, .. } => count_in_text(code.as_ref(), query),
-        Block::Table { rows, .. } => rows
-            .iter()
-            .map(|r| r.iter().map(|c| count_in_inlines(&c.content, query)).sum::<usize>())
-            .sum(),
-        Block::Figure { caption, .. } => caption
-            .as_ref()
-            .map(|c| count_in_text(c.as_ref(), query))
-            .unwrap_or(0),
+
+fn parse_find_options(json: &str) -> Result<FindOptions, JsValue> {
+    if json.trim().is_empty() {
+        return Ok(FindOptions::default());
     }
+    serde_json::from_str(json).map_err(|e| core_error("查找选项无效", e.into()))
 }
 
-fn replace_text(text: &str, query: &str, replacement: &str) -> (String, usize) {
-    let count = count_in_text(text, query);
-    if count == 0 {
-        return (text.to_string(), 0);
-    }
-    (text.replace(query, replacement), count)
+/// Errors reach JS as `{ code, message }` so callers can branch on the kind;
+/// `code` is `wa_core::Error::code` or "command"/"serialize".
+#[derive(Serialize)]
+struct JsError {
+    code: &'static str,
+    message: String,
 }
 
-fn replace_in_inlines(inlines: &mut Vec<Inline>, query: &str, replacement: &str) -> usize {
-    let mut count = 0;
-    for inline in inlines.iter_mut() {
-        match inline {
-            Inline::Text { value } => {
-                let (new_text, c) = replace_text(value.as_ref(), query, replacement);
-                if c > 0 {
-                    *value = Arc::from(new_text);
-                    count += c;
-                }
-            }
-            Inline::CodeSpan { value } => {
-                let (new_text, c) = replace_text(value.as_ref(), query, replacement);
-                if c > 0 {
-                    *value = Arc::from(new_text);
-                    count += c;
-                }
-            }
-            Inline::Link { text, .. } => {
-                count += replace_in_inlines(text, query, replacement);
-            }
-            Inline::Styled { content, .. } => {
-                count += replace_in_inlines(content, query, replacement);
-            }
-        }
-    }
-    count
+fn js_error(code: &'static str, message: impl std::fmt::Display) -> JsValue {
+    let error = JsError { code, message: message.to_string() };
+    serde_wasm_bindgen::to_value(&error).unwrap_or_else(|_| JsValue::from_str(&error.message))
 }
 
-fn replace_in_block(block: &mut Block, query: &str, replacement: &str) -> usize {
-    match block {
-        Block::Heading { content, dirty, .. } | Block::Paragraph { content, dirty, .. } => {
-            let count = replace_in_inlines(content, query, replacement);
-            if count > 0 {
-                *dirty = true;
-            }
-            count
-        }
-        Block::List { items, dirty, .. } => {
-            let mut count = 0;
-            for item in items.iter_mut() {
-                count += replace_in_inlines(&mut item.content, query, replacement);
-            }
-            if count > 0 {
-                *dirty = true;
-            }
-            count
-        }
-        Block::Quote { content, dirty, .. } => {
-            let mut count = 0;
-            for inner in content.iter_mut() {
-                count += replace_in_block(inner, query, replacement);
-            }
-            if count > 0 {
-                *dirty = true;
-            }
-            count
-        }
-        Block::Code { code, dirty, .. } => {
-            let (new_text, count) = replace_text(code.as_ref(), query, replacement);
-            if count > 0 {
-                *code = Arc::from(new_text);
-                *dirty = true;
-            }
-            count
-        }
-        Block::Table { rows, dirty, .. } => {
-            let mut count = 0;
-            for row in rows.iter_mut() {
-                for cell in row.iter_mut() {
-                    count += replace_in_inlines(&mut cell.content, query, replacement);
-                }
-            }
-            if count > 0 {
-                *dirty = true;
-            }
-            count
-        }
-        Block::Figure { caption, dirty, .. } => {
-            let existing = caption.as_ref().map(|c| c.as_ref().to_string());
-            if let Some(text) = existing {
-                let (new_text, count) = replace_text(&text, query, replacement);
-                if count > 0 {
-                    *caption = Some(Arc::from(new_text));
-                    *dirty = true;
-                }
-                count
-            } else {
-                0
-            }
-        }
-    }
+fn core_error(context: &str, err: wa_core::Error) -> JsValue {
+    js_error(err.code(), format!("{}: {}", context, err))
+}
+
+fn build_snippet(text: &str, start: usize, end: usize) -> String {
+    let total = text.chars().count();
+    let ctx = 20usize;
+    let s = start.saturating_sub(ctx);
+    let e = (end + ctx).min(total);
+    char_slice(text, s, e).to_string()
 }
 
 #[wasm_bindgen(start)]
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let mut doc = wa_core::import_html_rich(input);
+    assert!(wa_core::validate_doc(&mut doc).is_empty());
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    if let Ok(mut doc) = wa_core::import_json(input) {
+        assert!(wa_core::validate_doc(&mut doc).is_empty());
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let doc = wa_core::import_markdown(input);
+    assert!(doc.blocks.len() <= input.lines().count() + 1);
+    let _ = wa_core::import_markdown(&wa_core::export_markdown(&doc));
+});
@@ -1,4 +1,5 @@
-﻿use serde::{Deserialize, Serialize};
+﻿use crate::CodeText;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -39,33 +40,54 @@ pub enum Block {
         level: u8,
         content: Vec<Inline>,
         dirty: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        locked: bool,
     },
     Paragraph {
         id: Uuid,
         content: Vec<Inline>,
         dirty: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        locked: bool,
     },
     List {
         id: Uuid,
         ordered: bool,
         items: Vec<ListItem>,
         dirty: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        locked: bool,
+        /// A table of contents: items are links to the headings, rebuilt by
+        /// `Editor::refresh_toc`.
+        #[serde(default, skip_serializing_if = "is_false")]
+        toc: bool,
     },
     Quote {
         id: Uuid,
         content: Vec<Block>,
         dirty: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        locked: bool,
     },
     Code {
         id: Uuid,
         lang: SharedStr,
-        code: SharedStr,
+        code: CodeText,
         dirty: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        locked: bool,
     },
     Table {
         id: Uuid,
         rows: Vec<Vec<Cell>>,
+        #[serde(default)]
+        header: bool,
+        /// Column widths as fractions of the table width; empty means equal.
+        #[serde(default)]
+        col_widths: Vec<f32>,
         dirty: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        locked: bool,
     },
     Figure {
         id: Uuid,
@@ -73,27 +95,56 @@ pub enum Block {
         caption: Option<SharedStr>,
         size: Option<FigureSize>,
         dirty: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        locked: bool,
+    },
+    /// Ends the page it is on; the next block starts a new one.
+    PageBreak {
+        id: Uuid,
+        dirty: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        locked: bool,
+    },
+    /// A block owned by the host app. The editor keeps it as it is; layout
+    /// asks the handler registered for `kind` how tall it is.
+    Custom {
+        id: Uuid,
+        kind: SharedStr,
+        data: serde_json::Value,
+        dirty: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        locked: bool,
     },
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
 pub struct FigureSize {
     pub width: f32,
     pub height: f32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The deepest `ListItem::level` the editor and the importers make.
+pub const MAX_LIST_LEVEL: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ListItem {
     pub id: Uuid,
     pub content: Vec<Inline>,
+    /// Nesting depth, 0 for top-level items. An item is at most one level
+    /// deeper than the one before it, and at most `MAX_LIST_LEVEL`.
+    #[serde(default)]
+    pub level: u8,
+    /// Set for a task-list item, to whether it is ticked off.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checked: Option<bool>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Cell {
     pub content: Vec<Inline>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Inline {
     Text { value: SharedStr },
@@ -101,9 +152,15 @@ pub enum Inline {
     Link { url: SharedStr, text: Vec<Inline> },
     #[serde(rename = "codespan")]
     CodeSpan { value: SharedStr },
+    /// TeX source, kept as typed; `display` sets it on a line of its own.
+    Math {
+        tex: SharedStr,
+        #[serde(default, skip_serializing_if = "is_false")]
+        display: bool,
+    },
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct Style {
     pub bold: bool,
@@ -112,6 +169,58 @@ pub struct Style {
     pub strikethrough: bool,
 }
 
+impl Style {
+    pub fn is_plain(&self) -> bool {
+        !(self.bold || self.italic || self.underline || self.strikethrough)
+    }
+
+    /// Effective style of text nested inside `self` and then `inner`.
+    pub fn union(self, inner: Style) -> Style {
+        Style {
+            bold: self.bold || inner.bold,
+            italic: self.italic || inner.italic,
+            underline: self.underline || inner.underline,
+            strikethrough: self.strikethrough || inner.strikethrough,
+        }
+    }
+
+    /// The flags set in both `self` and `other`.
+    pub fn intersect(self, other: Style) -> Style {
+        Style {
+            bold: self.bold && other.bold,
+            italic: self.italic && other.italic,
+            underline: self.underline && other.underline,
+            strikethrough: self.strikethrough && other.strikethrough,
+        }
+    }
+
+    /// `self` with every flag set in `flags` cleared.
+    pub fn without(self, flags: Style) -> Style {
+        Style {
+            bold: self.bold && !flags.bold,
+            italic: self.italic && !flags.italic,
+            underline: self.underline && !flags.underline,
+            strikethrough: self.strikethrough && !flags.strikethrough,
+        }
+    }
+
+    /// `self` with every flag set in `flags` flipped.
+    pub fn toggled(self, flags: Style) -> Style {
+        Style {
+            bold: self.bold != flags.bold,
+            italic: self.italic != flags.italic,
+            underline: self.underline != flags.underline,
+            strikethrough: self.strikethrough != flags.strikethrough,
+        }
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Document {
     pub fn new() -> Self {
         Self {
@@ -133,18 +242,33 @@ impl Document {
     }
 
     pub fn clear_dirty(&mut self) {
+        self.for_each_block_mut(&mut |block| block.set_dirty(false));
+    }
+
+    /// `Block::for_each_block_mut` over every top-level block.
+    pub fn for_each_block_mut(&mut self, f: &mut dyn FnMut(&mut Block)) {
         for block in &mut self.blocks {
-            block.set_dirty(false);
-            if let Block::Quote { content, .. } = block {
-                for inner in content {
-                    inner.set_dirty(false);
-                }
-            }
+            block.for_each_block_mut(f);
         }
     }
 }
 
 impl Block {
+    /// The `type` tag the block serializes with, e.g. "heading".
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Block::Heading { .. } => "heading",
+            Block::Paragraph { .. } => "paragraph",
+            Block::List { .. } => "list",
+            Block::Quote { .. } => "quote",
+            Block::Code { .. } => "code",
+            Block::Table { .. } => "table",
+            Block::Figure { .. } => "figure",
+            Block::PageBreak { .. } => "page_break",
+            Block::Custom { .. } => "custom",
+        }
+    }
+
     pub fn id(&self) -> Uuid {
         match self {
             Block::Heading { id, .. }
@@ -153,7 +277,23 @@ impl Block {
             | Block::Quote { id, .. }
             | Block::Code { id, .. }
             | Block::Table { id, .. }
-            | Block::Figure { id, .. } => *id,
+            | Block::Figure { id, .. }
+            | Block::PageBreak { id, .. }
+            | Block::Custom { id, .. } => *id,
+        }
+    }
+
+    pub fn id_mut(&mut self) -> &mut Uuid {
+        match self {
+            Block::Heading { id, .. }
+            | Block::Paragraph { id, .. }
+            | Block::List { id, .. }
+            | Block::Quote { id, .. }
+            | Block::Code { id, .. }
+            | Block::Table { id, .. }
+            | Block::Figure { id, .. }
+            | Block::PageBreak { id, .. }
+            | Block::Custom { id, .. } => id,
         }
     }
 
@@ -165,7 +305,44 @@ impl Block {
             | Block::Quote { dirty, .. }
             | Block::Code { dirty, .. }
             | Block::Table { dirty, .. }
-            | Block::Figure { dirty, .. } => *dirty,
+            | Block::Figure { dirty, .. }
+            | Block::PageBreak { dirty, .. }
+            | Block::Custom { dirty, .. } => *dirty,
+        }
+    }
+
+    /// Locked blocks are left alone by editing commands, see
+    /// `CommandOutcome::BlockedByLock`.
+    pub fn is_locked(&self) -> bool {
+        match self {
+            Block::Heading { locked, .. }
+            | Block::Paragraph { locked, .. }
+            | Block::List { locked, .. }
+            | Block::Quote { locked, .. }
+            | Block::Code { locked, .. }
+            | Block::Table { locked, .. }
+            | Block::Figure { locked, .. }
+            | Block::PageBreak { locked, .. }
+            | Block::Custom { locked, .. } => *locked,
+        }
+    }
+
+    /// Whether the block is a table of contents, see `toc_items`.
+    pub fn is_toc(&self) -> bool {
+        matches!(self, Block::List { toc: true, .. })
+    }
+
+    pub fn set_locked(&mut self, value: bool) {
+        match self {
+            Block::Heading { locked, .. }
+            | Block::Paragraph { locked, .. }
+            | Block::List { locked, .. }
+            | Block::Quote { locked, .. }
+            | Block::Code { locked, .. }
+            | Block::Table { locked, .. }
+            | Block::Figure { locked, .. }
+            | Block::PageBreak { locked, .. }
+            | Block::Custom { locked, .. } => *locked = value,
         }
     }
 
@@ -177,7 +354,48 @@ impl Block {
             | Block::Quote { dirty, .. }
             | Block::Code { dirty, .. }
             | Block::Table { dirty, .. }
-            | Block::Figure { dirty, .. } => *dirty = value,
+            | Block::Figure { dirty, .. }
+            | Block::PageBreak { dirty, .. }
+            | Block::Custom { dirty, .. } => *dirty = value,
         }
     }
+
+    /// Blocks nested directly inside this one.
+    pub fn children(&self) -> &[Block] {
+        match self {
+            Block::Quote { content, .. } => content,
+            _ => &[],
+        }
+    }
+
+    pub fn children_mut(&mut self) -> &mut [Block] {
+        match self {
+            Block::Quote { content, .. } => content,
+            _ => &mut [],
+        }
+    }
+
+    /// Calls `f` on this block, then on every nested block, depth first.
+    pub fn for_each_block(&self, f: &mut dyn FnMut(&Block)) {
+        f(self);
+        for child in self.children() {
+            child.for_each_block(f);
+        }
+    }
+
+    pub fn for_each_block_mut(&mut self, f: &mut dyn FnMut(&mut Block)) {
+        f(self);
+        for child in self.children_mut() {
+            child.for_each_block_mut(f);
+        }
+    }
+
+    /// Whether this block or any block nested in it is dirty.
+    pub fn is_effectively_dirty(&self) -> bool {
+        self.is_dirty() || self.children().iter().any(Block::is_effectively_dirty)
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
 }
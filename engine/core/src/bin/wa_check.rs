@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use wa_core::{document_stats, export_str, fix_document, import_any, lint_document, text_format, Document, Error};
+
+const USAGE: &str = "\
+Usage: wa_check [options] <input>
+
+Prints word, character and block counts for a document, then any
+structural problems: heading level jumps, empty headings, tables with
+uneven rows, figures without captions and links with empty urls.
+
+  --fix              pad uneven table rows and drop empty paragraphs at
+                     the end, then write the document back
+  --output <path>    with --fix, write here instead; needed when the
+                     input is not md, txt, html or json
+
+Exit codes: 0 no problems, 1 problems found (after fixing, with --fix),
+2 usage, 3 io, 4 parse, 5 unsupported, 6 export, 7 invalid document.";
+
+struct Args {
+    input: PathBuf,
+    fix: bool,
+    output: Option<PathBuf>,
+}
+
+fn main() {
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("wa_check: {message}\n\n{USAGE}");
+        std::process::exit(2);
+    });
+    let mut doc = import_any(&args.input).unwrap_or_else(|err| fail(&args.input, err));
+    print_stats(&doc);
+    if args.fix {
+        let output = args.output.as_deref().unwrap_or(&args.input);
+        for fixup in fix_document(&mut doc) {
+            println!("fixed {}", fixup);
+        }
+        if let Err(err) = write(&doc, output) {
+            fail(output, err);
+        }
+    }
+    let findings = lint_document(&doc);
+    for finding in &findings {
+        println!("{}: {}", args.input.display(), finding);
+    }
+    if !findings.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn parse_args(mut raw: impl Iterator<Item = String>) -> Result<Args, String> {
+    let (mut positional, mut fix, mut output) = (Vec::new(), false, None);
+    while let Some(arg) = raw.next() {
+        match arg.as_str() {
+            "--fix" => fix = true,
+            "--output" => output = Some(PathBuf::from(raw.next().ok_or("--output needs a value")?)),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+            _ => positional.push(arg),
+        }
+    }
+    let [input]: [String; 1] = positional.try_into().map_err(|_| "expected <input>".to_string())?;
+    let input = PathBuf::from(input);
+    if output.is_some() && !fix {
+        return Err("--output needs --fix".to_string());
+    }
+    if fix && output.is_none() && text_format(&extension(&input)).is_none() {
+        return Err(format!("cannot write {} back, pass --output", input.display()));
+    }
+    Ok(Args { input, fix, output })
+}
+
+fn print_stats(doc: &Document) {
+    let stats = document_stats(doc);
+    println!("words: {}", stats.text.words);
+    println!("chars: {} ({} without spaces)", stats.text.chars, stats.text.chars_no_spaces);
+    println!("blocks: {}", stats.blocks);
+    let mut by_type = BTreeMap::new();
+    for block in &doc.blocks {
+        *by_type.entry(block.type_name()).or_insert(0) += 1;
+    }
+    for (name, count) in by_type {
+        println!("  {}: {}", name, count);
+    }
+}
+
+fn write(doc: &Document, path: &Path) -> Result<(), Error> {
+    let ext = extension(path);
+    if text_format(&ext).is_none() {
+        return Err(Error::Unsupported(format!(".{ext} output")));
+    }
+    Ok(std::fs::write(path, export_str(doc, &ext)?)?)
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase()
+}
+
+fn fail(path: &Path, err: Error) -> ! {
+    eprintln!("wa_check: {}: {}", path.display(), err);
+    std::process::exit(err.exit_code());
+}
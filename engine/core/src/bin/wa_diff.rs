@@ -0,0 +1,83 @@
+use std::path::Path;
+use wa_core::{block_plain_text, compare_documents, BlockDiff, Document, TextChange};
+
+const USAGE: &str = "\
+Usage: wa_diff <old> <new>
+
+Prints the blocks that changed between two documents, unified diff style,
+with deleted words in [-...-] and inserted ones in {+...+}.
+
+Exit codes: 0 same, 1 different, 2 usage, 3 io, 4 parse, 5 unsupported,
+7 invalid document.";
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+    let (old, new) = (open(&args[1]), open(&args[2]));
+    let diffs = compare_documents(&old, &new);
+    if diffs.is_empty() {
+        return;
+    }
+    println!("--- {}\n+++ {}", args[1], args[2]);
+    for diff in &diffs {
+        match diff {
+            BlockDiff::Added { index, .. } => {
+                println!("@@ +{} @@", index + 1);
+                print_lines('+', &block_plain_text(&new.blocks[*index]));
+            }
+            BlockDiff::Removed { index, .. } => {
+                println!("@@ -{} @@", index + 1);
+                print_lines('-', &block_plain_text(&old.blocks[*index]));
+            }
+            BlockDiff::Modified { old_index, new_index, changes, .. } => {
+                println!("@@ -{} +{} @@", old_index + 1, new_index + 1);
+                let deleted = changes.iter().filter_map(|c| match c {
+                    TextChange::Deleted { start, end } => Some((*start, *end)),
+                    TextChange::Inserted { .. } => None,
+                });
+                let inserted = changes.iter().filter_map(|c| match c {
+                    TextChange::Inserted { start, end } => Some((*start, *end)),
+                    TextChange::Deleted { .. } => None,
+                });
+                print_lines('-', &mark(&block_plain_text(&old.blocks[*old_index]), deleted, "[-", "-]"));
+                print_lines('+', &mark(&block_plain_text(&new.blocks[*new_index]), inserted, "{+", "+}"));
+            }
+        }
+    }
+    std::process::exit(1);
+}
+
+fn open(path: &str) -> Document {
+    match wa_core::import_any(Path::new(path)) {
+        Ok(doc) => doc,
+        Err(err) => {
+            eprintln!("import failed for {}: {}", path, err);
+            std::process::exit(err.exit_code());
+        }
+    }
+}
+
+fn print_lines(prefix: char, text: &str) {
+    for line in text.split('\n') {
+        println!("{}{}", prefix, line);
+    }
+}
+
+/// `text` with each char range wrapped in `open` and `close`.
+fn mark(text: &str, ranges: impl Iterator<Item = (usize, usize)>, open: &str, close: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut at = 0;
+    for (start, end) in ranges {
+        out.extend(&chars[at..start]);
+        out.push_str(open);
+        out.extend(&chars[start..end]);
+        out.push_str(close);
+        at = end;
+    }
+    out.extend(&chars[at..]);
+    out
+}
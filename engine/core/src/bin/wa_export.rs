@@ -1,30 +1,153 @@
-#[cfg(feature = "export_docx")]
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use wa_core::{export_str, import_any, import_str, text_format, Document, Error};
+
+const USAGE: &str = "\
+Usage: wa_export [options] <input> <output>
+
+The exporter follows the output extension: .docx, .pdf, .md, .html, .json
+or .txt.
+
+  --from <fmt>       read the input from stdin as md, txt, html or json;
+                     pass - as <input>
+  --paper <size>     pdf paper: a4 (default) or letter
+  --margin <mm>      pdf page margin in millimetres (default: 20)
+  --font <path>      font file for pdf output
+  --pages <range>    pdf pages to keep: 3, 2-5 or 4- (default: all)
+
+Exit codes: 0 ok, 2 usage, 3 io, 4 parse, 5 unsupported or not compiled in,
+6 export, 7 invalid document.";
+
+/// Page setup forwarded to the PDF exporter.
+#[cfg_attr(not(feature = "export_docx"), allow(dead_code))]
+struct PageArgs {
+    letter: bool,
+    margin_mm: f32,
+    font: Option<PathBuf>,
+    pages: Option<wa_core::PageRange>,
+}
+
+struct Args {
+    input: String,
+    output: PathBuf,
+    from: Option<&'static str>,
+    page: PageArgs,
+}
 
-#[cfg(feature = "export_docx")]
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: wa_export <input_path> <output_docx>");
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("wa_export: {message}\n\n{USAGE}");
         std::process::exit(2);
-    }
-    let input = PathBuf::from(&args[1]);
-    let output = PathBuf::from(&args[2]);
-    let doc = match wa_core::import_any(&input) {
+    });
+    let doc = match read_input(&args.input, args.from) {
         Ok(doc) => doc,
         Err(err) => {
-            eprintln!("import failed: {:?}", err);
-            std::process::exit(1);
+            eprintln!("wa_export: {}: {}", args.input, err);
+            std::process::exit(err.exit_code());
         }
     };
-    if let Err(err) = wa_core::export_docx(&doc, &output) {
-        eprintln!("export failed: {:?}", err);
-        std::process::exit(1);
+    if let Err(err) = export(&doc, &args.output, &args.page) {
+        eprintln!("wa_export: {}: {}", args.output.display(), err);
+        std::process::exit(err.exit_code());
     }
 }
 
+fn parse_args(mut raw: impl Iterator<Item = String>) -> Result<Args, String> {
+    let (mut positional, mut from) = (Vec::new(), None);
+    let mut page = PageArgs { letter: false, margin_mm: 20.0, font: None, pages: None };
+    while let Some(arg) = raw.next() {
+        let mut value = |name: &str| raw.next().ok_or_else(|| format!("{name} needs a value"));
+        match arg.as_str() {
+            "--from" => {
+                let format = value("--from")?;
+                from = Some(text_format(&format).ok_or_else(|| format!("unknown format {format}"))?);
+            }
+            "--paper" => {
+                page.letter = match value("--paper")?.to_ascii_lowercase().as_str() {
+                    "a4" => false,
+                    "letter" => true,
+                    other => return Err(format!("unknown paper {other}")),
+                }
+            }
+            "--margin" => {
+                let margin = value("--margin")?;
+                page.margin_mm = margin
+                    .parse()
+                    .ok()
+                    .filter(|mm: &f32| mm.is_finite() && *mm >= 0.0)
+                    .ok_or_else(|| format!("invalid margin {margin}"))?;
+            }
+            "--font" => page.font = Some(PathBuf::from(value("--font")?)),
+            "--pages" => {
+                let pages = value("--pages")?;
+                page.pages = Some(wa_core::PageRange::parse(&pages).ok_or_else(|| format!("invalid page range {pages}"))?);
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+            _ => positional.push(arg),
+        }
+    }
+    let [input, output]: [String; 2] = positional.try_into().map_err(|_| "expected <input> <output>".to_string())?;
+    if input == "-" && from.is_none() {
+        return Err("reading stdin needs --from".to_string());
+    }
+    Ok(Args { input, output: PathBuf::from(output), from, page })
+}
+
+fn read_input(input: &str, from: Option<&str>) -> Result<Document, Error> {
+    match from {
+        Some(format) if input == "-" => {
+            let mut raw = String::new();
+            std::io::stdin().read_to_string(&mut raw)?;
+            import_str(&raw, format)
+        }
+        Some(format) => import_str(&std::fs::read_to_string(input)?, format),
+        None => import_any(Path::new(input)),
+    }
+}
+
+fn export(doc: &Document, output: &Path, page: &PageArgs) -> Result<(), Error> {
+    let ext = output.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match ext.as_str() {
+        "docx" => export_docx(doc, output),
+        "pdf" => export_pdf(doc, output, page),
+        _ if text_format(&ext).is_some() => Ok(std::fs::write(output, export_str(doc, &ext)?)?),
+        _ => Err(Error::Unsupported(format!(".{ext} output"))),
+    }
+}
+
+#[cfg(feature = "export_docx")]
+fn export_docx(doc: &Document, output: &Path) -> Result<(), Error> {
+    wa_core::export_docx(doc, output)
+}
+
+#[cfg(feature = "export_docx")]
+fn export_pdf(doc: &Document, output: &Path, page: &PageArgs) -> Result<(), Error> {
+    let options = wa_core::PdfOptions {
+        paper: if page.letter { wa_core::Paper::Letter } else { wa_core::Paper::A4 },
+        margin_mm: page.margin_mm,
+        font: page.font.clone(),
+        page_range: page.pages,
+    };
+    wa_core::export_pdf_with(doc, output, &options)
+}
+
 #[cfg(not(feature = "export_docx"))]
-fn main() {
-    eprintln!("wa_export requires the `export_docx` feature");
-    std::process::exit(1);
+fn export_docx(_doc: &Document, _output: &Path) -> Result<(), Error> {
+    Err(not_compiled_in("docx"))
+}
+
+#[cfg(not(feature = "export_docx"))]
+fn export_pdf(_doc: &Document, _output: &Path, _page: &PageArgs) -> Result<(), Error> {
+    Err(not_compiled_in("pdf"))
+}
+
+#[cfg(not(feature = "export_docx"))]
+fn not_compiled_in(format: &str) -> Error {
+    Error::Unsupported(format!(
+        "{format} output is not compiled in; rebuild with `cargo build -p wa_core --features export_docx`"
+    ))
 }
@@ -1,23 +1,232 @@
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use wa_core::{document_stats, export_str, import_any, import_str, text_format, Document, Error};
+
+const USAGE: &str = "\
+Usage: wa_import [options] <input> [<output>]
+       wa_import [options] --out-dir <dir> <input>...
+
+  -                  read the input from stdin or write the output to stdout
+  --format <fmt>     output format: md, json, txt or html (default: from the
+                     output extension, else md)
+  --from <fmt>       format of stdin input (default: md)
+  --out-dir <dir>    convert every input into <dir>, keeping relative names;
+                     inputs may use * and ? in the file name
+  --stats            print block and character counts per file to stderr
+
+Exit codes: 0 ok, 2 usage, 3 io, 4 parse, 5 unsupported, 6 export,
+7 invalid document, 9 some inputs of a batch failed.";
+
+/// Some inputs of a batch converted, others did not.
+const PARTIAL_FAILURE: i32 = 9;
+
+#[derive(Default)]
+struct Args {
+    inputs: Vec<String>,
+    format: Option<&'static str>,
+    from: Option<&'static str>,
+    out_dir: Option<PathBuf>,
+    stats: bool,
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: wa_import <input_path> <output_md>");
+    let args = parse_args(std::env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("wa_import: {message}\n\n{USAGE}");
         std::process::exit(2);
+    });
+    let code = match &args.out_dir {
+        Some(dir) => run_batch(&args, dir),
+        None => run_single(&args),
+    };
+    std::process::exit(code);
+}
+
+fn parse_args(mut raw: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut args = Args::default();
+    while let Some(arg) = raw.next() {
+        let mut value = |name: &str| raw.next().ok_or_else(|| format!("{name} needs a value"));
+        match arg.as_str() {
+            "--format" => args.format = Some(format_arg(&value("--format")?)?),
+            "--from" => args.from = Some(format_arg(&value("--from")?)?),
+            "--out-dir" => args.out_dir = Some(PathBuf::from(value("--out-dir")?)),
+            "--stats" => args.stats = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+            _ => args.inputs.push(arg),
+        }
+    }
+    match (&args.out_dir, args.inputs.len()) {
+        (_, 0) => Err("no input given".to_string()),
+        (None, 1..=2) => Ok(args),
+        (None, _) => Err("several inputs need --out-dir".to_string()),
+        (Some(_), _) if args.inputs.iter().any(|i| i == "-") => Err("stdin cannot be used with --out-dir".to_string()),
+        (Some(_), _) => Ok(args),
+    }
+}
+
+fn format_arg(value: &str) -> Result<&'static str, String> {
+    text_format(value).ok_or_else(|| format!("unknown format {value}"))
+}
+
+fn run_single(args: &Args) -> i32 {
+    let input = &args.inputs[0];
+    let output = args.inputs.get(1).map_or("-", String::as_str);
+    let format = args.format.or_else(|| extension_format(Path::new(output))).unwrap_or("md");
+    let result = read_input(input, args.from).and_then(|doc| {
+        let text = export_str(&doc, format)?;
+        write_output(output, &text)?;
+        Ok(doc)
+    });
+    match result {
+        Ok(doc) => {
+            if args.stats {
+                print_stats(input, &doc);
+            }
+            0
+        }
+        Err(err) => report(input, &err),
     }
-    let input = PathBuf::from(&args[1]);
-    let output = PathBuf::from(&args[2]);
-    let doc = match wa_core::import_any(&input) {
-        Ok(doc) => doc,
-        Err(err) => {
-            eprintln!("import failed: {:?}", err);
-            std::process::exit(1);
+}
+
+fn run_batch(args: &Args, out_dir: &Path) -> i32 {
+    let format = args.format.unwrap_or("md");
+    let (mut converted, mut first_error) = (0, None);
+    for pattern in &args.inputs {
+        let inputs = match expand(pattern) {
+            Ok(inputs) => inputs,
+            Err(err) => {
+                first_error.get_or_insert(report(pattern, &err));
+                continue;
+            }
+        };
+        for input in inputs {
+            let name = input.display().to_string();
+            let target = out_dir.join(relative_name(&input)).with_extension(format);
+            let result = import_any(&input).and_then(|doc| {
+                let text = export_str(&doc, format)?;
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&target, text)?;
+                Ok(doc)
+            });
+            match result {
+                Ok(doc) => {
+                    converted += 1;
+                    if args.stats {
+                        print_stats(&name, &doc);
+                    }
+                }
+                Err(err) => {
+                    first_error.get_or_insert(report(&name, &err));
+                }
+            }
         }
-    };
-    let md = wa_core::export_markdown(&doc);
-    if let Err(err) = std::fs::write(&output, md) {
-        eprintln!("write failed: {:?}", err);
-        std::process::exit(1);
     }
+    match first_error {
+        None => 0,
+        Some(_) if converted > 0 => PARTIAL_FAILURE,
+        Some(code) => code,
+    }
+}
+
+fn read_input(input: &str, from: Option<&str>) -> Result<Document, Error> {
+    if input != "-" {
+        return import_any(Path::new(input));
+    }
+    let mut raw = String::new();
+    std::io::stdin().read_to_string(&mut raw)?;
+    import_str(&raw, from.unwrap_or("md"))
+}
+
+fn write_output(output: &str, text: &str) -> Result<(), Error> {
+    if output == "-" {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(text.as_bytes())?;
+        if !text.ends_with('\n') {
+            stdout.write_all(b"\n")?;
+        }
+        return Ok(stdout.flush()?);
+    }
+    Ok(std::fs::write(output, text)?)
+}
+
+fn extension_format(path: &Path) -> Option<&'static str> {
+    path.extension().and_then(|e| e.to_str()).and_then(text_format)
+}
+
+/// The files a batch input names: the path itself, or the files of its
+/// directory whose names match when the last component has * or ?.
+fn expand(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    let path = Path::new(pattern);
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    if !name.contains(['*', '?']) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut matches = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if entry.file_type()?.is_file() && file_name.to_str().is_some_and(|n| wildcard_match(name, n)) {
+            matches.push(dir.join(file_name));
+        }
+    }
+    if matches.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no files match").into());
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Where a batch input lands under the output directory: its relative path
+/// without `.` and `..` parts, or just the file name when it is absolute.
+fn relative_name(input: &Path) -> PathBuf {
+    if input.is_absolute() {
+        return input.file_name().map(PathBuf::from).unwrap_or_default();
+    }
+    input.components().filter(|c| matches!(c, Component::Normal(_))).collect()
+}
+
+fn print_stats(name: &str, doc: &Document) {
+    let stats = document_stats(doc);
+    eprintln!(
+        "{name}: {} blocks, {} chars, {} chars without spaces, {} words",
+        stats.blocks, stats.text.chars, stats.text.chars_no_spaces, stats.text.words
+    );
+}
+
+fn report(name: &str, err: &Error) -> i32 {
+    eprintln!("wa_import: {name}: {err}");
+    err.exit_code()
 }
@@ -0,0 +1,307 @@
+use crate::{Block, SharedStr};
+use ropey::Rope;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+/// Code longer than this many bytes is kept in a rope, so edits in the
+/// middle of a large block do not copy the whole text.
+pub const CODE_ROPE_THRESHOLD: usize = 64 * 1024;
+
+/// Text of a code block. Small blocks share one `SharedStr`; large ones
+/// live in a rope. Serializes as a plain string either way.
+#[derive(Clone)]
+pub struct CodeText(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Flat(SharedStr),
+    Rope(Rope),
+}
+
+impl CodeText {
+    pub fn new(text: &str) -> Self {
+        if text.len() > CODE_ROPE_THRESHOLD {
+            CodeText(Repr::Rope(Rope::from_str(text)))
+        } else {
+            CodeText(Repr::Flat(SharedStr::from(text)))
+        }
+    }
+
+    /// Length in bytes.
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Repr::Flat(s) => s.len(),
+            Repr::Rope(r) => r.len_bytes(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn char_len(&self) -> usize {
+        match &self.0 {
+            Repr::Flat(s) => s.chars().count(),
+            Repr::Rope(r) => r.len_chars(),
+        }
+    }
+
+    pub fn is_rope(&self) -> bool {
+        matches!(self.0, Repr::Rope(_))
+    }
+
+    /// The whole text, when it is stored in one piece.
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.0 {
+            Repr::Flat(s) => Some(s),
+            Repr::Rope(r) => r.get_slice(..).and_then(|s| s.as_str()),
+        }
+    }
+
+    /// Number of lines as layout counts them: newlines plus one.
+    pub fn line_count(&self) -> usize {
+        match &self.0 {
+            Repr::Flat(s) => s.bytes().filter(|b| *b == b'\n').count() + 1,
+            Repr::Rope(r) => r.len_lines(),
+        }
+    }
+
+    /// Line (from 0) holding char `char_idx` and the char where that line
+    /// starts. Offsets past the end fall on the last line.
+    pub fn char_to_line(&self, char_idx: usize) -> (usize, usize) {
+        match &self.0 {
+            Repr::Flat(s) => {
+                let (mut line, mut start) = (0, 0);
+                for (idx, ch) in s.chars().take(char_idx).enumerate() {
+                    if ch == '\n' {
+                        line += 1;
+                        start = idx + 1;
+                    }
+                }
+                (line, start)
+            }
+            Repr::Rope(r) => {
+                let line = r.char_to_line(char_idx.min(r.len_chars()));
+                (line, r.line_to_char(line))
+            }
+        }
+    }
+
+    /// Char where line `line` (from 0) starts, or None past the last line.
+    pub fn line_to_char(&self, line: usize) -> Option<usize> {
+        match &self.0 {
+            Repr::Flat(_) if line == 0 => Some(0),
+            Repr::Flat(s) => {
+                let (byte, _) = s.char_indices().filter(|(_, ch)| *ch == '\n').nth(line - 1)?;
+                Some(s[..byte].chars().count() + 1)
+            }
+            Repr::Rope(r) => (line < r.len_lines()).then(|| r.line_to_char(line)),
+        }
+    }
+
+    /// The stored pieces in order; concatenated they give the full text.
+    pub fn chunks(&self) -> impl Iterator<Item = &str> {
+        let (flat, rope) = match &self.0 {
+            Repr::Flat(s) => (Some(s.as_ref()), None),
+            Repr::Rope(r) => (None, Some(r.chunks())),
+        };
+        flat.into_iter().chain(rope.into_iter().flatten())
+    }
+
+    /// Lines including their '\n', like `str::split_inclusive('\n')`.
+    pub fn lines_with_endings(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        let (flat, rope) = match &self.0 {
+            Repr::Flat(s) => (Some(s.split_inclusive('\n').map(Cow::Borrowed)), None),
+            Repr::Rope(r) => (None, Some(r.lines().map(Cow::from).filter(|l| !l.is_empty()))),
+        };
+        flat.into_iter().flatten().chain(rope.into_iter().flatten())
+    }
+
+    /// Same lines as `str::lines` over the full text.
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.lines_with_endings().map(|line| match line {
+            Cow::Borrowed(l) => Cow::Borrowed(trim_line_ending(l)),
+            Cow::Owned(mut l) => {
+                let len = trim_line_ending(&l).len();
+                l.truncate(len);
+                Cow::Owned(l)
+            }
+        })
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chunks().flat_map(str::chars)
+    }
+
+    /// Byte offset of char `char_idx`, clamped to the end.
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        match &self.0 {
+            Repr::Flat(s) => crate::char_to_byte_idx(s, char_idx),
+            Repr::Rope(r) => r.char_to_byte(char_idx.min(r.len_chars())),
+        }
+    }
+
+    /// Copy of the bytes `range`, or None when it is out of bounds or not
+    /// on char boundaries.
+    pub fn slice(&self, range: Range<usize>) -> Option<String> {
+        match &self.0 {
+            Repr::Flat(s) => s.get(range).map(str::to_string),
+            Repr::Rope(r) => {
+                let (start, end) = (rope_char_at(r, range.start)?, rope_char_at(r, range.end)?);
+                (start <= end).then(|| r.slice(start..end).to_string())
+            }
+        }
+    }
+
+    /// Replaces the bytes `range` with `inserted` and returns what was
+    /// removed. Flat text that grows past the threshold moves into a rope.
+    pub fn splice(&mut self, range: Range<usize>, inserted: &str) -> Option<String> {
+        match &mut self.0 {
+            Repr::Flat(s) => {
+                let removed = s.get(range.clone())?.to_string();
+                let mut out = String::with_capacity(s.len() - range.len() + inserted.len());
+                out.push_str(&s[..range.start]);
+                out.push_str(inserted);
+                out.push_str(&s[range.end..]);
+                *self = CodeText::from(out);
+                Some(removed)
+            }
+            Repr::Rope(r) => {
+                let (start, end) = (rope_char_at(r, range.start)?, rope_char_at(r, range.end)?);
+                if start > end {
+                    return None;
+                }
+                let removed = r.slice(start..end).to_string();
+                r.remove(start..end);
+                r.insert(start, inserted);
+                Some(removed)
+            }
+        }
+    }
+}
+
+/// 1-based line and column of char `offset` in a code block, as editors
+/// show them. None for other blocks and for offsets past the end.
+pub fn code_offset_to_line_col(block: &Block, offset: usize) -> Option<(usize, usize)> {
+    let Block::Code { code, .. } = block else {
+        return None;
+    };
+    if offset > code.char_len() {
+        return None;
+    }
+    let (line, start) = code.char_to_line(offset);
+    Some((line + 1, offset - start + 1))
+}
+
+/// Char offset of 1-based `line` and `col` in a code block, with the column
+/// clamped to the end of the line. None for other blocks and missing lines.
+pub fn code_line_col_to_offset(block: &Block, line: usize, col: usize) -> Option<usize> {
+    let Block::Code { code, .. } = block else {
+        return None;
+    };
+    let start = code.line_to_char(line.checked_sub(1)?)?;
+    let mut end = code.line_to_char(line).map_or(code.char_len(), |next| next - 1);
+    let break_at = code.char_to_byte(end);
+    if end > start && code.slice(break_at - 1..break_at).as_deref() == Some("\r") {
+        end -= 1;
+    }
+    Some(start + col.saturating_sub(1).min(end - start))
+}
+
+/// Char index of byte `byte` in `rope`, if it falls on a char boundary.
+fn rope_char_at(rope: &Rope, byte: usize) -> Option<usize> {
+    let idx = rope.try_byte_to_char(byte).ok()?;
+    (rope.char_to_byte(idx) == byte).then_some(idx)
+}
+
+fn trim_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+impl Default for CodeText {
+    fn default() -> Self {
+        CodeText(Repr::Flat(SharedStr::from("")))
+    }
+}
+
+impl From<&str> for CodeText {
+    fn from(text: &str) -> Self {
+        CodeText::new(text)
+    }
+}
+
+impl From<String> for CodeText {
+    fn from(text: String) -> Self {
+        if text.len() > CODE_ROPE_THRESHOLD {
+            CodeText(Repr::Rope(Rope::from(text)))
+        } else {
+            CodeText(Repr::Flat(SharedStr::from(text)))
+        }
+    }
+}
+
+impl From<SharedStr> for CodeText {
+    fn from(text: SharedStr) -> Self {
+        if text.len() > CODE_ROPE_THRESHOLD {
+            CodeText(Repr::Rope(Rope::from_str(&text)))
+        } else {
+            CodeText(Repr::Flat(text))
+        }
+    }
+}
+
+impl fmt::Display for CodeText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chunks().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+impl fmt::Debug for CodeText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Repr::Flat(s) => fmt::Debug::fmt(s, f),
+            Repr::Rope(r) => write!(f, "Rope({} bytes)", r.len_bytes()),
+        }
+    }
+}
+
+impl PartialEq for CodeText {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Repr::Flat(a), Repr::Flat(b)) => a == b,
+            (Repr::Rope(a), Repr::Rope(b)) => a == b,
+            _ => self.len() == other.len() && self.chunks().flat_map(str::bytes).eq(other.chunks().flat_map(str::bytes)),
+        }
+    }
+}
+
+impl Eq for CodeText {}
+
+/// Hashes the same bytes whichever way the text is stored.
+impl Hash for CodeText {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for chunk in self.chunks() {
+            state.write(chunk.as_bytes());
+        }
+        state.write_u8(0xff);
+    }
+}
+
+impl Serialize for CodeText {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.as_str() {
+            Some(s) => serializer.serialize_str(s),
+            None => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CodeText {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(CodeText::from)
+    }
+}
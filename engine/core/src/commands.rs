@@ -1,25 +1,264 @@
-﻿use crate::Style;
+﻿use crate::{FindOptions, Style};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+/// An edit as the editor runs it. Commands serialize as
+/// `{"type": "insert_text", "args": "hi"}`, so recorded macros can be
+/// stored and sent over the bridge.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "args", rename_all = "snake_case")]
 pub enum EditorCommand {
     InsertText(String),
+    /// Enter: splits the focused paragraph or heading at the caret into two
+    /// blocks and puts the caret at the start of the second. In a list it
+    /// is `SplitListItem`; in a code block it inserts a newline.
+    InsertParagraphBreak,
+    /// Enter in a list, which `InsertParagraphBreak` there turns into:
+    /// splits the item holding the caret at the caret, the new item keeping
+    /// its level and checked state, with the caret at its start. An empty item leaves the list instead and becomes a paragraph
+    /// after it; the items below it go on in a list of their own.
+    SplitListItem,
     DeleteSelection,
+    /// Ctrl+Backspace: deletes back to the start of the word before the
+    /// caret, see `word_start_before`, or the selection when there is one.
+    /// At the start of a block it joins the block to the one before it.
+    /// Repeated deletes merge into one undo step.
+    DeleteWordBackward,
+    /// Ctrl+Delete: deletes on to the end of the word after the caret, or
+    /// the selection when there is one.
+    DeleteWordForward,
     ApplyStyle(Style),
+    /// Drops every style flag from the selected text, or from the whole
+    /// focused block when the selection is collapsed. Links and code spans
+    /// stay; link text loses its styles too.
+    ClearFormatting,
     SetHeading(u8),
+    /// Turns the block holding the caret into another kind, keeping its
+    /// text: heading inlines carry over to a paragraph, each code line
+    /// becomes a block of its own, a quote unwraps into its blocks, and
+    /// code keeps only plain text. One-to-one conversions keep the block
+    /// id. Headings go the way of `SetHeading`, except from code.
+    ConvertBlock(TargetKind),
+    /// Turns the run of unlocked paragraphs around the caret, within the
+    /// selection, into one list whose items keep the paragraph ids. In a
+    /// list of the other kind it only flips `ordered`; in one of the same
+    /// kind every item becomes a paragraph again.
+    ToggleList(bool),
+    /// Ticks or unticks the task-list item holding the caret. A plain item
+    /// becomes an unticked task; `SetTaskState(None)` turns a task back
+    /// into a plain item.
+    ToggleTaskItem,
+    /// Sets the task-list item holding the caret to unticked, ticked or,
+    /// with `None`, a plain item.
+    SetTaskState(Option<bool>),
     InsertList(bool),
     InsertQuote(String),
     InsertCode { lang: String, code: String },
     InsertTable(usize, usize),
     InsertImage(String),
     InsertFigure { url: String, caption: Option<String> },
+    /// Adds a figure for encoded image bytes (e.g. a pasted PNG). The bytes
+    /// are kept in the figure as a `data:` URI, so the image travels with
+    /// the document.
+    InsertImageData { bytes: Vec<u8>, mime: String, caption: Option<String> },
+    /// Adds a `Block::PageBreak` after the block holding the caret, or at
+    /// the end when there is none.
+    InsertPageBreak,
+    /// Adds a locked table of contents after the block holding the caret,
+    /// see `toc_items`. Edits keep it in step with the headings.
+    InsertToc,
+    /// Adds a host-rendered block, see `Block::Custom`.
+    InsertCustom { kind: String, data: serde_json::Value },
+    /// Sets a figure's display size. Repeated resizes of the same figure
+    /// (a drag) merge into one undo step.
+    ResizeFigure { block_id: uuid::Uuid, width: f32, height: f32 },
     InsertLink { url: String, text: String },
+    /// Inserts TeX at the caret as one `Inline::Math`.
+    InsertMath { tex: String, display: bool },
+    EditLink { block_id: uuid::Uuid, url: String, new_url: String },
+    RemoveLink { block_id: uuid::Uuid, url: String },
+    ReplaceMatch { block_id: uuid::Uuid, start: usize, end: usize, replacement: String },
+    ReplaceAll { query: String, replacement: String, options: FindOptions },
     TableEditCell { block_id: uuid::Uuid, row: usize, col: usize, text: String },
     TableInsertRow,
     TableInsertColumn,
     TableDeleteRow,
     TableDeleteColumn,
-    ListIndent,
-    ListOutdent,
+    TableInsertRowAt { block_id: uuid::Uuid, index: usize },
+    TableInsertColumnAt { block_id: uuid::Uuid, index: usize },
+    TableDeleteRowAt { block_id: uuid::Uuid, index: usize },
+    TableDeleteColumnAt { block_id: uuid::Uuid, index: usize },
+    TableToggleHeader { block_id: uuid::Uuid },
+    TableSetColWidth { block_id: uuid::Uuid, col: usize, width: f32 },
+    /// Moves a block into the gap before `to_index`, counted in the current
+    /// order (`blocks.len()` appends). Dropping it next to itself is a no-op.
+    MoveBlock { block_id: uuid::Uuid, to_index: usize },
+    /// Swaps the block holding the caret with the one before it. The caret
+    /// moves with the block; the first block stays put.
+    MoveBlockUp,
+    /// Swaps the block holding the caret with the one after it.
+    MoveBlockDown,
+    /// Inserts a copy of the block holding the caret right after it, with
+    /// new ids throughout, and moves the selection into the copy.
+    DuplicateBlock,
+    /// Removes a block, the one holding the caret when `None`. A selection
+    /// in it moves to the end of the block before, or to the start of the
+    /// one after when it was first. Removing the last block leaves an empty
+    /// paragraph.
+    DeleteBlock { block_id: Option<uuid::Uuid> },
+    /// Moves one list item a level deeper. `None` picks the focused list
+    /// and the item holding the caret. The first item, an item already a
+    /// level below the one before it and one at `MAX_LIST_LEVEL` stay where
+    /// they are. Only the item's level changes, never its text.
+    ListIndent { block_id: Option<uuid::Uuid>, item: Option<usize> },
+    /// Moves one list item a level up; top-level items stay where they are.
+    ListOutdent { block_id: Option<uuid::Uuid>, item: Option<usize> },
+    /// Locks or unlocks a block against edits, see `Block::is_locked`.
+    SetBlockLocked { block_id: uuid::Uuid, locked: bool },
+    /// Moves the caret, see `Direction`. With `extend` the anchor stays,
+    /// so the selection grows or shrinks. Not recorded or journaled.
+    MoveCursor { direction: Direction, extend: bool },
+    /// Moves the caret a word back or on, see `Editor::move_caret_by_word`.
+    /// Not recorded or journaled either.
+    MoveCursorWord { forward: bool, extend: bool },
+    /// Selects from the start of the first block to the end of the last.
+    SelectAll,
+    /// Selects the word around the caret, see `word_at`.
+    SelectWord,
+    /// Selects the whole block holding the caret.
+    SelectBlock,
     Undo,
     Redo,
 }
+
+/// Where `EditorCommand::MoveCursor` sends the caret. Lines are the
+/// newline-separated lines of a block's text, such as code lines and list
+/// items, not the wrapped lines of a layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// One char back, to the end of the previous block from the start.
+    Left,
+    /// One char on, to the start of the next block from the end.
+    Right,
+    LineStart,
+    LineEnd,
+    BlockStart,
+    BlockEnd,
+    /// The start of the next block; the last block's end from the last.
+    NextBlock,
+    /// The start of the previous block, or of this one in the first.
+    PrevBlock,
+}
+
+impl EditorCommand {
+    /// Commands that only move the caret or selection. They never change
+    /// the document and are not recorded or journaled.
+    pub fn is_selection_only(&self) -> bool {
+        matches!(
+            self,
+            EditorCommand::MoveCursor { .. } | EditorCommand::MoveCursorWord { .. } | EditorCommand::SelectAll | EditorCommand::SelectWord | EditorCommand::SelectBlock
+        )
+    }
+
+    /// The block the command names by id, for commands that do not act on
+    /// the selection.
+    pub fn block_id_mut(&mut self) -> Option<&mut uuid::Uuid> {
+        match self {
+            EditorCommand::ResizeFigure { block_id, .. }
+            | EditorCommand::EditLink { block_id, .. }
+            | EditorCommand::RemoveLink { block_id, .. }
+            | EditorCommand::ReplaceMatch { block_id, .. }
+            | EditorCommand::TableEditCell { block_id, .. }
+            | EditorCommand::TableInsertRowAt { block_id, .. }
+            | EditorCommand::TableInsertColumnAt { block_id, .. }
+            | EditorCommand::TableDeleteRowAt { block_id, .. }
+            | EditorCommand::TableDeleteColumnAt { block_id, .. }
+            | EditorCommand::TableToggleHeader { block_id }
+            | EditorCommand::TableSetColWidth { block_id, .. }
+            | EditorCommand::MoveBlock { block_id, .. }
+            | EditorCommand::DeleteBlock { block_id: Some(block_id) }
+            | EditorCommand::ListIndent { block_id: Some(block_id), .. }
+            | EditorCommand::ListOutdent { block_id: Some(block_id), .. }
+            | EditorCommand::SetBlockLocked { block_id, .. } => Some(block_id),
+            _ => None,
+        }
+    }
+
+    /// Read-only `block_id_mut`.
+    pub fn block_id(&self) -> Option<uuid::Uuid> {
+        match self {
+            EditorCommand::ResizeFigure { block_id, .. }
+            | EditorCommand::EditLink { block_id, .. }
+            | EditorCommand::RemoveLink { block_id, .. }
+            | EditorCommand::ReplaceMatch { block_id, .. }
+            | EditorCommand::TableEditCell { block_id, .. }
+            | EditorCommand::TableInsertRowAt { block_id, .. }
+            | EditorCommand::TableInsertColumnAt { block_id, .. }
+            | EditorCommand::TableDeleteRowAt { block_id, .. }
+            | EditorCommand::TableDeleteColumnAt { block_id, .. }
+            | EditorCommand::TableToggleHeader { block_id }
+            | EditorCommand::TableSetColWidth { block_id, .. }
+            | EditorCommand::MoveBlock { block_id, .. }
+            | EditorCommand::DeleteBlock { block_id: Some(block_id) }
+            | EditorCommand::ListIndent { block_id: Some(block_id), .. }
+            | EditorCommand::ListOutdent { block_id: Some(block_id), .. }
+            | EditorCommand::SetBlockLocked { block_id, .. } => Some(*block_id),
+            _ => None,
+        }
+    }
+}
+
+/// What `EditorCommand::ConvertBlock` turns a block into.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetKind {
+    Paragraph,
+    Heading(u8),
+    Quote,
+    Code { lang: String },
+}
+
+impl TargetKind {
+    /// The `type` tag of the blocks it makes, as `Block::type_name`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            TargetKind::Paragraph => "paragraph",
+            TargetKind::Heading(_) => "heading",
+            TargetKind::Quote => "quote",
+            TargetKind::Code { .. } => "code",
+        }
+    }
+}
+
+/// What `Editor::replay` does with the block ids recorded in commands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Retarget {
+    /// Run them against the blocks they were recorded on.
+    #[default]
+    Keep,
+    /// Point them at the block holding the caret when each command runs.
+    FocusedBlock,
+}
+
+/// Why `Editor::try_execute` refused a command. A refused command leaves the
+/// document and history untouched.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("cannot turn a {0} block into a heading")]
+    NotConvertible(&'static str),
+    #[error("cannot turn a {from} block into a {to}")]
+    CannotConvert { from: &'static str, to: &'static str },
+}
+
+/// What `Editor::try_execute` did with a command it accepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CommandOutcome {
+    #[default]
+    Applied,
+    /// The command would have changed a locked block and left it alone.
+    /// Replace-all still replaces in the other blocks; `block_id` is the
+    /// first locked block it skipped.
+    BlockedByLock { block_id: uuid::Uuid },
+}
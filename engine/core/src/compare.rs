@@ -0,0 +1,234 @@
+use crate::{block_plain_text, block_semantic_eq, hash_block, is_cjk, Block, Document};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::Range;
+use uuid::Uuid;
+
+/// Share of words two blocks without a common id must have in common to
+/// count as one block edited rather than one removed and one added.
+const SIMILARITY_THRESHOLD: f32 = 0.5;
+/// Above this many token pairs the word diff stops looking for common
+/// words and marks the whole middle as changed.
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+/// How one block differs between two documents, see `compare_documents`.
+/// Indices are positions in the document the block is in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BlockDiff {
+    Added { block_id: Uuid, index: usize },
+    Removed { block_id: Uuid, index: usize },
+    /// `changes` are the words deleted from the old block's plain text and
+    /// inserted into the new one's.
+    Modified { old_id: Uuid, new_id: Uuid, old_index: usize, new_index: usize, changes: Vec<TextChange> },
+}
+
+/// A run of changed words, in chars of `block_plain_text`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TextChange {
+    /// Chars of the old text that are gone.
+    Deleted { start: usize, end: usize },
+    /// Chars of the new text that were not there.
+    Inserted { start: usize, end: usize },
+}
+
+/// What changed from `old` to `new`, block by block, in reading order of
+/// `new` with removed blocks where they used to be. Blocks pair up by id;
+/// the rest by identical content, then by shared words, so a document
+/// imported again with fresh ids still compares block to block. Blocks
+/// that only moved are not reported.
+pub fn compare_documents(old: &Document, new: &Document) -> Vec<BlockDiff> {
+    let pairs = match_blocks(&old.blocks, &new.blocks);
+    let mut old_of_new: Vec<Option<usize>> = vec![None; new.blocks.len()];
+    let mut matched_old = vec![false; old.blocks.len()];
+    for (o, n) in pairs {
+        old_of_new[n] = Some(o);
+        matched_old[o] = true;
+    }
+
+    let mut out = Vec::new();
+    let mut next_removed = 0;
+    let mut flush_removed = |until: usize, out: &mut Vec<BlockDiff>| {
+        while next_removed < until.min(old.blocks.len()) {
+            if !matched_old[next_removed] {
+                out.push(BlockDiff::Removed { block_id: old.blocks[next_removed].id(), index: next_removed });
+            }
+            next_removed += 1;
+        }
+    };
+    for (n, block) in new.blocks.iter().enumerate() {
+        let Some(o) = old_of_new[n] else {
+            out.push(BlockDiff::Added { block_id: block.id(), index: n });
+            continue;
+        };
+        flush_removed(o, &mut out);
+        let before = &old.blocks[o];
+        if !block_semantic_eq(before, block) {
+            out.push(BlockDiff::Modified {
+                old_id: before.id(),
+                new_id: block.id(),
+                old_index: o,
+                new_index: n,
+                changes: diff_words(&block_plain_text(before), &block_plain_text(block)),
+            });
+        }
+    }
+    flush_removed(usize::MAX, &mut out);
+    out
+}
+
+/// Pairs of (old index, new index) taken to be the same block.
+fn match_blocks(old: &[Block], new: &[Block]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut used_old = vec![false; old.len()];
+    let mut used_new = vec![false; new.len()];
+    let by_id: HashMap<Uuid, usize> = old.iter().enumerate().map(|(i, b)| (b.id(), i)).collect();
+    for (n, block) in new.iter().enumerate() {
+        if let Some(&o) = by_id.get(&block.id()).filter(|&&o| !used_old[o]) {
+            pairs.push((o, n));
+            used_old[o] = true;
+            used_new[n] = true;
+        }
+    }
+
+    let mut by_content: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (o, block) in old.iter().enumerate().rev().filter(|(o, _)| !used_old[*o]) {
+        by_content.entry(hash_block(block)).or_default().push(o);
+    }
+    let unmatched: Vec<usize> = (0..new.len()).filter(|&n| !used_new[n]).collect();
+    for n in unmatched {
+        let block = &new[n];
+        let found = by_content.get_mut(&hash_block(block)).and_then(|olds| olds.pop());
+        if let Some(o) = found.filter(|&o| block_semantic_eq(&old[o], block)) {
+            pairs.push((o, n));
+            used_old[o] = true;
+            used_new[n] = true;
+        }
+    }
+
+    let words = |block: &Block| -> HashMap<String, usize> {
+        let text = block_plain_text(block);
+        let mut counts = HashMap::new();
+        for range in tokens(&text).into_iter().filter(|r| !text[r.clone()].trim().is_empty()) {
+            *counts.entry(text[range].to_string()).or_insert(0) += 1;
+        }
+        counts
+    };
+    let old_words: Vec<Option<HashMap<String, usize>>> =
+        old.iter().enumerate().map(|(o, b)| (!used_old[o]).then(|| words(b))).collect();
+    for (n, block) in new.iter().enumerate().filter(|(n, _)| !used_new[*n]) {
+        let new_words = words(block);
+        let best = old_words
+            .iter()
+            .enumerate()
+            .filter(|(o, _)| !used_old[*o] && std::mem::discriminant(&old[*o]) == std::mem::discriminant(block))
+            .filter_map(|(o, counts)| Some((o, similarity(counts.as_ref()?, &new_words))))
+            .filter(|(_, score)| *score >= SIMILARITY_THRESHOLD)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((o, _)) = best {
+            pairs.push((o, n));
+            used_old[o] = true;
+        }
+    }
+    pairs
+}
+
+/// Dice coefficient of two word multisets.
+fn similarity(a: &HashMap<String, usize>, b: &HashMap<String, usize>) -> f32 {
+    let total: usize = a.values().sum::<usize>() + b.values().sum::<usize>();
+    if total == 0 {
+        return 1.0;
+    }
+    let common: usize = a.iter().map(|(word, count)| (*count).min(b.get(word).copied().unwrap_or(0))).sum();
+    2.0 * common as f32 / total as f32
+}
+
+/// Words deleted from `old` and inserted into `new`, keeping the longest
+/// common subsequence of words. Adjacent changed words merge into one
+/// range.
+pub fn diff_words(old: &str, new: &str) -> Vec<TextChange> {
+    let (old_tokens, new_tokens) = (tokens(old), tokens(new));
+    let old_words: Vec<&str> = old_tokens.iter().map(|r| &old[r.clone()]).collect();
+    let new_words: Vec<&str> = new_tokens.iter().map(|r| &new[r.clone()]).collect();
+    let prefix = old_words.iter().zip(&new_words).take_while(|(a, b)| a == b).count();
+    let suffix = old_words[prefix..].iter().rev().zip(new_words[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old_words[prefix..old_words.len() - suffix], &new_words[prefix..new_words.len() - suffix]);
+
+    let mut kept_old = vec![false; a.len()];
+    let mut kept_new = vec![false; b.len()];
+    if a.len().saturating_mul(b.len()) <= MAX_LCS_CELLS {
+        // lcs[i * w + j]: longest common subsequence of a[i..] and b[j..].
+        let w = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * w];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * w + j] = if a[i] == b[j] { lcs[(i + 1) * w + j + 1] + 1 } else { lcs[(i + 1) * w + j].max(lcs[i * w + j + 1]) };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if a[i] == b[j] {
+                kept_old[i] = true;
+                kept_new[j] = true;
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * w + j] >= lcs[i * w + j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+
+    let mut changes = Vec::new();
+    let to_chars = |text: &str, range: &Range<usize>| {
+        let start = text[..range.start].chars().count();
+        (start, start + text[range.clone()].chars().count())
+    };
+    let mut push_runs = |text: &str, ranges: &[Range<usize>], kept: &[bool], inserted: bool| {
+        let mut run: Option<Range<usize>> = None;
+        for (range, kept) in ranges.iter().zip(kept) {
+            match (&mut run, kept) {
+                (Some(open), false) => open.end = range.end,
+                (None, false) => run = Some(range.clone()),
+                (Some(_), true) => {
+                    changes.push(change(to_chars(text, &run.take().expect("open run")), inserted));
+                }
+                (None, true) => {}
+            }
+        }
+        if let Some(open) = run {
+            changes.push(change(to_chars(text, &open), inserted));
+        }
+    };
+    push_runs(old, &old_tokens[prefix..old_tokens.len() - suffix], &kept_old, false);
+    push_runs(new, &new_tokens[prefix..new_tokens.len() - suffix], &kept_new, true);
+    changes
+}
+
+fn change((start, end): (usize, usize), inserted: bool) -> TextChange {
+    if inserted {
+        TextChange::Inserted { start, end }
+    } else {
+        TextChange::Deleted { start, end }
+    }
+}
+
+/// Byte ranges of the words of `text`: Latin-script runs, single CJK
+/// characters, whitespace runs and single punctuation marks, end to end.
+pub(crate) fn tokens(text: &str) -> Vec<Range<usize>> {
+    let mut out: Vec<Range<usize>> = Vec::new();
+    let (mut in_word, mut in_space) = (false, false);
+    for (at, ch) in text.char_indices() {
+        let word = ch.is_alphanumeric() && !is_cjk(ch);
+        let joins = (in_word && (word || matches!(ch, '\'' | '-'))) || (in_space && ch.is_whitespace());
+        match out.last_mut() {
+            Some(range) if joins => range.end = at + ch.len_utf8(),
+            _ => out.push(at..at + ch.len_utf8()),
+        }
+        in_word = word || (in_word && matches!(ch, '\'' | '-'));
+        in_space = ch.is_whitespace();
+    }
+    out
+}
@@ -1,15 +1,14 @@
-use crate::{Block, Document, Inline, ListItem};
+use crate::{hash_block, BlockSignatures, Document};
 use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Patch {
     pub block_id: Uuid,
     pub kind: PatchKind,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PatchKind {
     InsertBlock,
     ReplaceBlock,
@@ -21,6 +20,11 @@ pub struct DiffEngine {
     cache: HashMap<Uuid, CacheEntry>,
     generation: u64,
     removed_scratch: Vec<Uuid>,
+    /// Document id and version of the last diff.
+    last_seen: Option<(Uuid, u64)>,
+    /// Block order of the last diff; while it holds, only dirty blocks need
+    /// a look.
+    order: Vec<Uuid>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -34,46 +38,77 @@ impl DiffEngine {
         Self::default()
     }
 
+    /// Patches since the last call. A document whose version did not move
+    /// since then is taken as unchanged without looking at its blocks, so
+    /// code that edits blocks directly must `touch()` the document.
     pub fn incremental_diff(&mut self, doc: &Document) -> Vec<Patch> {
+        self.diff_inner(doc, None)
+    }
+
+    /// Like `incremental_diff`, but takes dirty blocks' signatures from
+    /// `signatures` when they match the document version instead of hashing.
+    pub fn incremental_diff_with_signatures(&mut self, doc: &Document, signatures: &BlockSignatures) -> Vec<Patch> {
+        self.diff_inner(doc, Some(signatures))
+    }
+
+    fn diff_inner(&mut self, doc: &Document, signatures: Option<&BlockSignatures>) -> Vec<Patch> {
+        if self.last_seen == Some((doc.id, doc.version)) {
+            return Vec::new();
+        }
+        self.last_seen = Some((doc.id, doc.version));
+        let signature = |block: &crate::Block| {
+            signatures.and_then(|s| s.get(doc, block.id())).unwrap_or_else(|| hash_block(block))
+        };
+        let mut out = Vec::new();
+        if self.order.len() == doc.blocks.len() && self.order.iter().zip(&doc.blocks).all(|(id, b)| *id == b.id()) {
+            for block in doc.blocks.iter().filter(|b| b.is_effectively_dirty()) {
+                if let Some(entry) = self.cache.get_mut(&block.id()) {
+                    let hash = signature(block);
+                    if entry.hash != hash {
+                        out.push(Patch { block_id: block.id(), kind: PatchKind::ReplaceBlock });
+                        entry.hash = hash;
+                    }
+                }
+            }
+            return out;
+        }
+        self.order.clear();
+        self.order.extend(doc.blocks.iter().map(|b| b.id()));
         self.generation = self.generation.wrapping_add(1);
         let generation = self.generation;
-        let mut out = Vec::new();
         for block in &doc.blocks {
             let id = block.id();
-            let prev = self.cache.get(&id).map(|entry| entry.hash);
-            let hash = if !block.is_dirty() {
-                match prev {
-                    Some(value) => value,
-                    None => hash_block(block),
+            match self.cache.get_mut(&id) {
+                Some(entry) => {
+                    if block.is_effectively_dirty() {
+                        let hash = signature(block);
+                        if entry.hash != hash {
+                            out.push(Patch { block_id: id, kind: PatchKind::ReplaceBlock });
+                            entry.hash = hash;
+                        }
+                    }
+                    entry.generation = generation;
+                }
+                None => {
+                    out.push(Patch { block_id: id, kind: PatchKind::InsertBlock });
+                    self.cache.insert(id, CacheEntry { hash: signature(block), generation });
                 }
-            } else {
-                hash_block(block)
-            };
-            match prev {
-                None => out.push(Patch { block_id: id, kind: PatchKind::InsertBlock }),
-                Some(prev) if prev != hash => out.push(Patch { block_id: id, kind: PatchKind::ReplaceBlock }),
-                _ => {}
             }
-            self.cache.insert(
-                id,
-                CacheEntry {
-                    hash,
-                    generation,
-                },
-            );
         }
-        self.removed_scratch.clear();
-        for (id, entry) in &self.cache {
-            if entry.generation != generation {
-                self.removed_scratch.push(*id);
+        if self.cache.len() > doc.blocks.len() {
+            self.removed_scratch.clear();
+            for (id, entry) in &self.cache {
+                if entry.generation != generation {
+                    self.removed_scratch.push(*id);
+                }
+            }
+            for id in self.removed_scratch.drain(..) {
+                self.cache.remove(&id);
+                out.push(Patch {
+                    block_id: id,
+                    kind: PatchKind::RemoveBlock,
+                });
             }
-        }
-        for id in self.removed_scratch.drain(..) {
-            self.cache.remove(&id);
-            out.push(Patch {
-                block_id: id,
-                kind: PatchKind::RemoveBlock,
-            });
         }
         out
     }
@@ -84,78 +119,3 @@ impl DiffEngine {
         patches
     }
 }
-
-fn hash_block(block: &Block) -> u64 {
-    let mut hasher = std::collections::hash_map::DefaultHasher::new();
-    hash_block_inner(block, &mut hasher);
-    hasher.finish()
-}
-
-fn hash_block_inner(block: &Block, hasher: &mut impl Hasher) {
-    std::mem::discriminant(block).hash(hasher);
-    match block {
-        Block::Heading { level, content, .. } => {
-            level.hash(hasher);
-            hash_inlines(content, hasher);
-        }
-        Block::Paragraph { content, .. } => {
-            hash_inlines(content, hasher);
-        }
-        Block::List { ordered, items, .. } => {
-            ordered.hash(hasher);
-            for item in items {
-                hash_list_item(item, hasher);
-            }
-        }
-        Block::Quote { content, .. } => {
-            for inner in content {
-                hash_block_inner(inner, hasher);
-            }
-        }
-        Block::Code { lang, code, .. } => {
-            lang.as_ref().hash(hasher);
-            code.as_ref().hash(hasher);
-        }
-        Block::Table { rows, .. } => {
-            for row in rows {
-                row.len().hash(hasher);
-                for cell in row {
-                    hash_inlines(&cell.content, hasher);
-                }
-            }
-        }
-        Block::Figure { url, caption, size, .. } => {
-            url.as_ref().hash(hasher);
-            caption.as_ref().map(|c| c.as_ref()).hash(hasher);
-            if let Some(sz) = size {
-                sz.width.to_bits().hash(hasher);
-                sz.height.to_bits().hash(hasher);
-            }
-        }
-    }
-}
-
-fn hash_list_item(item: &ListItem, hasher: &mut impl Hasher) {
-    hash_inlines(&item.content, hasher);
-}
-
-fn hash_inlines(inlines: &[Inline], hasher: &mut impl Hasher) {
-    for inline in inlines {
-        std::mem::discriminant(inline).hash(hasher);
-        match inline {
-            Inline::Text { value } => value.as_ref().hash(hasher),
-            Inline::Styled { style, content } => {
-                style.bold.hash(hasher);
-                style.italic.hash(hasher);
-                style.underline.hash(hasher);
-                style.strikethrough.hash(hasher);
-                hash_inlines(content, hasher);
-            }
-            Inline::Link { url, text } => {
-                url.as_ref().hash(hasher);
-                hash_inlines(text, hasher);
-            }
-            Inline::CodeSpan { value } => value.as_ref().hash(hasher),
-        }
-    }
-}
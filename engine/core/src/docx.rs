@@ -1,13 +1,10 @@
-use crate::{Block, Document, Inline};
-use docx_rs::{Docx, Paragraph, Run};
+﻿use crate::{Block, Document, Error, Inline};
+use docx_rs::{BreakType, Docx, Paragraph, Run};
 
-#[derive(thiserror::Error, Debug)]
-pub enum DocxError {
-    #[error("docx build failed: {0}")]
-    Build(String),
-}
+#[deprecated(note = "use wa_core::Error")]
+pub type DocxError = Error;
 
-pub fn export_docx_bytes(doc: &Document) -> Result<Vec<u8>, DocxError> {
+pub fn export_docx_bytes(doc: &Document) -> Result<Vec<u8>, Error> {
     let mut docx = Docx::new();
     for block in &doc.blocks {
         match block {
@@ -30,6 +27,7 @@ pub fn export_docx_bytes(doc: &Document) -> Result<Vec<u8>, DocxError> {
                 for (idx, item) in items.iter().enumerate() {
                     let text = inline_text(&item.content);
                     let prefix = if *ordered { format!("{}. ", idx + 1) } else { "- ".to_string() };
+                    let prefix = "  ".repeat(item.level as usize) + &prefix;
                     docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(prefix + &text)));
                 }
             }
@@ -45,7 +43,7 @@ pub fn export_docx_bytes(doc: &Document) -> Result<Vec<u8>, DocxError> {
                 docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(text)));
             }
             Block::Code { code, .. } => {
-                let text = code.as_ref().to_string();
+                let text = code.to_string();
                 docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(text)));
             }
             Block::Table { rows, .. } => {
@@ -62,12 +60,17 @@ pub fn export_docx_bytes(doc: &Document) -> Result<Vec<u8>, DocxError> {
                 let cap = caption.as_ref().map(|c| c.as_ref()).unwrap_or("图片");
                 docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_text(cap)));
             }
+            Block::PageBreak { .. } => {
+                docx = docx.add_paragraph(Paragraph::new().add_run(Run::new().add_break(BreakType::Page)));
+            }
+            // Drawn by the host app; there is nothing to write.
+            Block::Custom { .. } => {}
         }
     }
     let mut cursor = std::io::Cursor::new(Vec::new());
     docx.build()
         .pack(&mut cursor)
-        .map_err(|e| DocxError::Build(e.to_string()))?;
+        .map_err(|e| Error::export("docx", e))?;
     Ok(cursor.into_inner())
 }
 
@@ -76,7 +79,7 @@ fn inline_text(inlines: &[Inline]) -> String {
     for inline in inlines {
         match inline {
             Inline::Text { value } => out.push_str(value.as_ref()),
-            Inline::CodeSpan { value } => out.push_str(value.as_ref()),
+            Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => out.push_str(value.as_ref()),
             Inline::Link { text, .. } => out.push_str(&inline_text(text)),
             Inline::Styled { content, .. } => out.push_str(&inline_text(content)),
         }
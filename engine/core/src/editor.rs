@@ -1,111 +1,616 @@
 ﻿use crate::{
-    Block, CommandHistory, Document, EditorCommand, Inline, ListItem, Position, Selection, Style, TableEditor, Snapshot, HistoryEntry,
+    Affinity, repair_document, replace_all_in_block, replace_range_in_block, Block, BlockSignatures, code_line_col_to_offset, CodeText, CommandError, CommandOutcome, CommandHistory, Direction, Error, Journal, FigureSize, hash_block, DocSnapshot, insert_inline, inline_list_mut, InputFilter, refresh_ids, Retarget, leaf_range, math_range_before, block_text_len, range_style, word_at, word_end_after, word_start_before, resolve_position, set_range_style, slice_block, slice_inlines, split_at_path, style_at_path, text_run_at, Document, EditorCommand, Inline, InternStats, inline_text_len, ListItem, list_item_at, MAX_LIST_LEVEL, normalize_inlines, PlainTextIndex, Position, RepairOptions, Selection, Style, TableEditor, TargetKind, Template, Snapshot, HistoryEntry, StringInterner, splice_text_leaf, toc_items,
 };
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::sync::Arc;
 use uuid::Uuid;
 
 pub struct Editor {
     pub doc: Document,
     pub selection: Selection,
+    pub config: EditorConfig,
+    /// Cached block text for find, replace and stats.
+    pub text_index: PlainTextIndex,
+    /// Signatures of the blocks as of the last command, shared with diff
+    /// and layout.
+    pub signatures: BlockSignatures,
+    /// Style for the next typed text, set by `ApplyStyle` on a collapsed
+    /// selection. Typing on from where it left the caret keeps it; moving
+    /// the caret or running any other command drops it.
+    pub pending_style: Option<Style>,
+    /// The caret `pending_style` was set or last used at.
+    pending_caret: Position,
+    /// Where `place_caret` last left the caret. Its affinity and goal x
+    /// only hold while the caret stays there.
+    placed_caret: Position,
+    caret_affinity: Affinity,
+    goal_x: Option<f32>,
     history: CommandHistory,
+    /// Commands run since `start_recording`, if recording.
+    recording: Option<Vec<EditorCommand>>,
+    /// Where every command that runs is logged, if attached.
+    journal: Option<Journal>,
+    /// Why the journal was detached, until `take_journal_error`.
+    journal_error: Option<Error>,
+    /// What the last command did, see `last_outcome`.
+    outcome: CommandOutcome,
+    interner: StringInterner,
+    commands_since_prune: u32,
+}
+
+/// Commands between sweeps of interned strings no block uses any more.
+const INTERN_PRUNE_EVERY: u32 = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub struct EditorConfig {
+    /// Turn `# `, `- `, `1. `, `> ` and ``` typed into an empty paragraph
+    /// into the matching block.
+    pub markdown_shortcuts: bool,
+    /// Cleanup applied to inserted, pasted and replacement text.
+    pub input: InputFilter,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self { markdown_shortcuts: true, input: InputFilter::default() }
+    }
 }
 
 impl Editor {
-    pub fn new(doc: Document) -> Self {
+    pub fn new(mut doc: Document) -> Self {
+        let mut interner = StringInterner::new();
+        interner.intern_document(&mut doc);
         let first_id = doc
             .blocks
-            .get(0)
+            .first()
             .map(|b| b.id())
             .unwrap_or_else(Uuid::new_v4);
         let selection = Selection::collapsed(Position { block_id: first_id, offset: 0 });
+        let mut signatures = BlockSignatures::new();
+        signatures.refresh(&doc);
         Self {
             doc,
             selection,
+            config: EditorConfig::default(),
+            text_index: PlainTextIndex::new(),
+            signatures,
+            pending_style: None,
+            pending_caret: selection.focus,
+            placed_caret: selection.focus,
+            caret_affinity: Affinity::Downstream,
+            goal_x: None,
             history: CommandHistory::new(100),
+            recording: None,
+            journal: None,
+            journal_error: None,
+            outcome: CommandOutcome::Applied,
+            interner,
+            commands_since_prune: 0,
         }
     }
 
     pub fn execute(&mut self, cmd: EditorCommand) {
+        let _ = self.try_execute(cmd);
+    }
+
+    /// Same as `execute`, but reports commands that cannot apply to the
+    /// focused block, or that a block lock held back, instead of ignoring
+    /// them.
+    pub fn try_execute(&mut self, cmd: EditorCommand) -> Result<CommandOutcome, CommandError> {
+        let recorded = (self.recording.is_some() || self.journal.is_some()).then(|| cmd.clone()).filter(|cmd| !cmd.is_selection_only());
+        let (version, selection) = (self.doc.version, self.selection);
+        let before: Option<HashSet<Uuid>> = self.journal.is_some().then(|| self.doc.blocks.iter().map(|b| b.id()).collect());
+        self.outcome = CommandOutcome::Applied;
+        self.goal_x = None;
+        self.caret_affinity = Affinity::Downstream;
+        self.run_command(cmd)?;
+        if let (Some(journal), Some(cmd), Some(before)) = (self.journal.as_mut(), recorded.as_ref(), before) {
+            let created: Vec<Uuid> = self.doc.blocks.iter().map(|b| b.id()).filter(|id| !before.contains(id)).collect();
+            if let Err(err) = journal.append(cmd, version, selection, &created) {
+                self.journal = None;
+                self.journal_error = Some(err);
+            }
+        }
+        if let (Some(commands), Some(cmd)) = (self.recording.as_mut(), recorded) {
+            commands.push(cmd);
+        }
+        Ok(self.outcome)
+    }
+
+    /// What the last command run did, also when it ran through `execute`.
+    pub fn last_outcome(&self) -> CommandOutcome {
+        self.outcome
+    }
+
+    /// Logs every command that runs from now on to `journal`.
+    pub fn attach_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    pub fn detach_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    pub fn journal_mut(&mut self) -> Option<&mut Journal> {
+        self.journal.as_mut()
+    }
+
+    /// The write error that detached the journal, if one did since the
+    /// last call.
+    pub fn take_journal_error(&mut self) -> Option<Error> {
+        self.journal_error.take()
+    }
+
+    /// Which line the caret draws on when it sits at a wrap point.
+    pub fn caret_affinity(&self) -> Affinity {
+        if self.selection.focus == self.placed_caret {
+            self.caret_affinity
+        } else {
+            Affinity::Downstream
+        }
+    }
+
+    /// The x, relative to the line start, that moving the caret up and down
+    /// keeps returning to, so passing a short line does not pull the caret
+    /// left for good. Commands, horizontal moves and moving the caret any
+    /// other way drop it.
+    pub fn goal_x(&self) -> Option<f32> {
+        self.goal_x.filter(|_| self.selection.focus == self.placed_caret)
+    }
+
+    /// Moves the caret to `pos`, extending the selection from its anchor
+    /// when `extend` is set. Vertical moves pass the x they aim for.
+    pub fn place_caret(&mut self, pos: Position, affinity: Affinity, goal_x: Option<f32>, extend: bool) {
+        self.selection = if extend { Selection { anchor: self.selection.anchor, focus: pos } } else { Selection::collapsed(pos) };
+        self.placed_caret = pos;
+        self.caret_affinity = affinity;
+        self.goal_x = goal_x;
+    }
+
+    /// Moves the caret one character, on into the next or previous block
+    /// at either end. Without `extend`, a selection collapses to the end it
+    /// moves towards.
+    pub fn move_caret_by_char(&mut self, forward: bool, extend: bool) {
+        let Selection { anchor, focus } = self.selection;
+        if !extend && anchor != focus {
+            let edge = match (self.position_order(anchor, focus), forward) {
+                (Some(std::cmp::Ordering::Greater), true) | (Some(std::cmp::Ordering::Less), false) => anchor,
+                _ => focus,
+            };
+            self.place_caret(edge, Affinity::Downstream, None, false);
+            return;
+        }
+        let Some(index) = self.doc.blocks.iter().position(|b| b.id() == focus.block_id) else {
+            return;
+        };
+        let pos = if forward && focus.offset < block_text_len(&self.doc.blocks[index]) {
+            Position { offset: focus.offset + 1, ..focus }
+        } else if forward {
+            self.doc.blocks.get(index + 1).map_or(focus, |next| Position { block_id: next.id(), offset: 0 })
+        } else if focus.offset > 0 {
+            Position { offset: focus.offset - 1, ..focus }
+        } else {
+            let prev = index.checked_sub(1).map(|i| &self.doc.blocks[i]);
+            prev.map_or(focus, |prev| Position { block_id: prev.id(), offset: block_text_len(prev) })
+        };
+        self.place_caret(pos, Affinity::Downstream, None, extend);
+    }
+
+    /// Moves the caret to the start of the word before it or the end of the
+    /// word after it. At either end of a block it steps into the next or
+    /// previous block like `move_caret_by_char`.
+    pub fn move_caret_by_word(&mut self, forward: bool, extend: bool) {
+        let focus = self.selection.focus;
+        let Some(block) = self.doc.blocks.iter().find(|b| b.id() == focus.block_id) else {
+            return;
+        };
+        let text = crate::block_plain_text(block);
+        let offset = focus.offset.min(text.chars().count());
+        if (forward && offset == text.chars().count()) || (!forward && offset == 0) {
+            return self.move_caret_by_char(forward, extend);
+        }
+        let offset = if forward { word_end_after(&text, offset) } else { word_start_before(&text, offset) };
+        self.place_caret(Position { offset, ..focus }, Affinity::Downstream, None, extend);
+    }
+
+    /// Moves the caret in `direction`. Left and right step by char and
+    /// collapse a selection like `move_caret_by_char`; the other moves go
+    /// from the focus.
+    pub fn move_cursor(&mut self, direction: Direction, extend: bool) {
+        let focus = self.selection.focus;
+        let Some(index) = self.doc.blocks.iter().position(|b| b.id() == focus.block_id) else {
+            return;
+        };
+        let text = crate::block_plain_text(&self.doc.blocks[index]);
+        let len = text.chars().count();
+        let offset = focus.offset.min(len);
+        let line_start = text.chars().take(offset).enumerate().filter(|(_, c)| *c == '\n').last().map_or(0, |(i, _)| i + 1);
+        let line_end = text.chars().skip(offset).position(|c| c == '\n').map_or(len, |i| offset + i);
+        let at = |offset| Position { block_id: focus.block_id, offset };
+        let pos = match direction {
+            Direction::Left | Direction::Right => return self.move_caret_by_char(direction == Direction::Right, extend),
+            Direction::LineStart => at(line_start),
+            Direction::LineEnd => at(line_end),
+            Direction::BlockStart => at(0),
+            Direction::BlockEnd => at(len),
+            Direction::NextBlock => self.doc.blocks.get(index + 1).map_or(at(len), |next| Position { block_id: next.id(), offset: 0 }),
+            Direction::PrevBlock => {
+                let prev = index.checked_sub(1).map(|i| &self.doc.blocks[i]);
+                prev.map_or(at(0), |prev| Position { block_id: prev.id(), offset: 0 })
+            }
+        };
+        self.place_caret(pos, Affinity::Downstream, None, extend);
+    }
+
+    /// The selection `SelectAll`, `SelectWord` or `SelectBlock` makes; None
+    /// when there is nothing to select.
+    fn selection_for(&self, cmd: &EditorCommand) -> Option<Selection> {
+        let focus = self.selection.focus;
+        let block = self.doc.blocks.iter().find(|b| b.id() == focus.block_id);
+        let (first, last) = match cmd {
+            EditorCommand::SelectAll => (self.doc.blocks.first()?, self.doc.blocks.last()?),
+            EditorCommand::SelectBlock => (block?, block?),
+            EditorCommand::SelectWord => {
+                let word = word_at(&crate::block_plain_text(block?), focus.offset)?;
+                return Some(Selection { anchor: Position { offset: word.start, ..focus }, focus: Position { offset: word.end, ..focus } });
+            }
+            _ => return None,
+        };
+        Some(Selection { anchor: Position { block_id: first.id(), offset: 0 }, focus: Position { block_id: last.id(), offset: block_text_len(last) } })
+    }
+
+    /// Document order of two positions, `None` if either block is gone.
+    fn position_order(&self, a: Position, b: Position) -> Option<std::cmp::Ordering> {
+        let index = |p: Position| self.doc.blocks.iter().position(|block| block.id() == p.block_id);
+        Some((index(a)?, a.offset).cmp(&(index(b)?, b.offset)))
+    }
+
+    /// Starts collecting every command that runs, until `stop_recording`.
+    /// Selection changes are not recorded, see `is_selection_only`.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Vec::new());
+    }
+
+    /// The commands run since `start_recording`; empty when not recording.
+    pub fn stop_recording(&mut self) -> Vec<EditorCommand> {
+        self.recording.take().unwrap_or_default()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Runs `commands` in order as a single undo step. The first refused
+    /// command stops the replay and rolls back the ones before it.
+    pub fn replay(&mut self, commands: &[EditorCommand], retarget: Retarget) -> Result<(), CommandError> {
+        let before = self.snapshot();
+        let version = self.doc.version;
+        let outer = std::mem::replace(&mut self.history, CommandHistory::new(usize::MAX));
+        let mut result = Ok(());
+        for cmd in commands {
+            let mut cmd = cmd.clone();
+            if let (Retarget::FocusedBlock, Some(block_id)) = (retarget, cmd.block_id_mut()) {
+                *block_id = self.selection.focus.block_id;
+            }
+            result = self.try_execute(cmd).map(|_| ());
+            if result.is_err() {
+                break;
+            }
+        }
+        self.history = outer;
+        if result.is_err() && self.doc.version != version {
+            self.restore_snapshot(before);
+            self.doc.touch();
+            self.after_change();
+        } else if self.doc.version != version {
+            self.history.push_entry(HistoryEntry::Snapshot(before));
+        }
+        result
+    }
+
+    fn run_command(&mut self, cmd: EditorCommand) -> Result<(), CommandError> {
+        let caret_kept = self.selection.is_collapsed() && self.selection.focus == self.pending_caret;
+        let pending_style = self.pending_style.take().filter(|_| caret_kept);
+        if let Some(block_id) = self.edit_target(&cmd).filter(|id| self.doc.blocks.iter().any(|b| b.id() == *id && b.is_locked())) {
+            self.outcome = CommandOutcome::BlockedByLock { block_id };
+            return Ok(());
+        }
         match cmd.clone() {
             EditorCommand::InsertText(text) => {
+                let text = self.config.input.apply(&text).into_owned();
                 if text.is_empty() {
-                    return;
+                    self.pending_style = pending_style;
+                    return Ok(());
                 }
                 let block_id = self.selection.focus.block_id;
-                self.with_block_change_merge(block_id, |b| {
-                    Self::insert_text_into_block(b, text.clone());
-                });
+                if let Some(style) = pending_style.filter(|style| self.insert_styled_text(&text, *style)) {
+                    self.pending_style = Some(style);
+                    self.pending_caret = self.selection.focus;
+                    self.doc.touch();
+                    self.after_change();
+                    return Ok(());
+                }
+                let block = self.doc.blocks.iter().find(|b| b.id() == block_id);
+                if let Some(len) = block.map(block_text_len) {
+                    self.selection.focus.offset = self.selection.focus.offset.min(len);
+                }
+                let offset = self.selection.focus.offset;
+                let insert_at = block.and_then(|b| Self::insert_point(b, offset));
+                let after = Selection::collapsed(Position { block_id, offset: offset + text.chars().count() });
+                if let Some((path, at)) = insert_at {
+                    self.with_inline_edit(block_id, path, at..at, &text, |_| after);
+                } else if !self.insert_styled_text(&text, Style::default()) {
+                    self.with_block_change_merge(block_id, |b, selection| {
+                        Self::insert_text_into_block(b, text.clone());
+                        *selection = Selection::collapsed(Position { block_id, offset: block_text_len(b) });
+                    });
+                }
+                if self.config.markdown_shortcuts {
+                    self.apply_markdown_shortcut(block_id);
+                }
+            }
+            EditorCommand::DeleteSelection if self.selection.anchor.block_id != self.selection.focus.block_id => {
+                if !self.delete_across_blocks() {
+                    return Ok(());
+                }
             }
             EditorCommand::DeleteSelection => {
                 let block_id = self.selection.focus.block_id;
-                let should_delete = self
-                    .doc
-                    .blocks
-                    .iter()
-                    .find(|b| b.id() == block_id)
-                    .and_then(|b| match b {
-                        Block::Paragraph { content, .. } | Block::Heading { content, .. } => Some(!content.is_empty()),
-                        _ => None,
-                    })
-                    .unwrap_or(false);
-                if !should_delete {
-                    return;
+                let Some(index) = self.doc.blocks.iter().position(|b| b.id() == block_id) else {
+                    return Ok(());
+                };
+                let block = &self.doc.blocks[index];
+                let len = match block {
+                    Block::Paragraph { .. } | Block::Heading { .. } | Block::Code { .. } | Block::List { .. } => block_text_len(block),
+                    _ => return Ok(()),
+                };
+                // A selection inside the block goes; otherwise the char
+                // before the caret does, like backspace, or the whole
+                // formula it belongs to. At the start of a list item the
+                // item joins the one before it, and at the start of a
+                // paragraph or heading the block joins the one before it.
+                let focus = self.selection.focus.offset.min(len);
+                let ranged = len > 0 && self.selection.anchor.block_id == block_id && self.selection.anchor.offset != focus;
+                if !ranged && focus == 0 {
+                    if !matches!(block, Block::Paragraph { .. } | Block::Heading { .. }) || !self.join_previous(index) {
+                        return Ok(());
+                    }
+                } else {
+                    let (start, end) = if ranged {
+                        (self.selection.anchor.offset.min(focus), self.selection.anchor.offset.max(focus).min(len))
+                    } else {
+                        math_range_before(block, focus).map_or((focus - 1, focus), |math| (math.start, math.end))
+                    };
+                    self.delete_chars(index, start, end, false);
                 }
-                self.with_block_change_merge(block_id, |b| {
-                    Self::delete_selection_in_block(b);
-                });
+            }
+            EditorCommand::DeleteWordBackward | EditorCommand::DeleteWordForward if !self.selection.is_collapsed() => {
+                return self.run_command(EditorCommand::DeleteSelection);
+            }
+            EditorCommand::DeleteWordBackward | EditorCommand::DeleteWordForward => {
+                let forward = matches!(cmd, EditorCommand::DeleteWordForward);
+                let Some(index) = self.doc.blocks.iter().position(|b| b.id() == self.selection.focus.block_id) else {
+                    return Ok(());
+                };
+                let text = match &self.doc.blocks[index] {
+                    block @ (Block::Paragraph { .. } | Block::Heading { .. }) => crate::block_plain_text(block),
+                    _ => return Ok(()),
+                };
+                let focus = self.selection.focus.offset.min(text.chars().count());
+                if !forward && focus == 0 {
+                    if !self.join_previous(index) {
+                        return Ok(());
+                    }
+                } else {
+                    let (start, end) = if forward { (focus, word_end_after(&text, focus)) } else { (word_start_before(&text, focus), focus) };
+                    if start == end {
+                        return Ok(());
+                    }
+                    self.delete_chars(index, start, end, true);
+                }
+            }
+            EditorCommand::ApplyStyle(style) if self.selection.is_collapsed() => {
+                let base = pending_style.unwrap_or_else(|| self.style_at_caret());
+                self.pending_style = Some(base.toggled(style));
+                self.pending_caret = self.selection.focus;
+                return Ok(());
             }
             EditorCommand::ApplyStyle(style) => {
-                let block_id = self.selection.focus.block_id;
-                self.with_block_change(block_id, |b| {
-                    Self::apply_style_in_block(b, style);
-                });
+                if !self.toggle_range_style(style) {
+                    return Ok(());
+                }
             }
-            EditorCommand::SetHeading(level) => {
-                let block_id = self.selection.focus.block_id;
-                self.with_block_change(block_id, |b| {
-                    Self::set_heading_in_block(b, level);
-                });
+            EditorCommand::ClearFormatting => {
+                let all = Style { bold: true, italic: true, underline: true, strikethrough: true };
+                let spans = if self.selection.is_collapsed() {
+                    self.doc.blocks.iter().position(|b| b.id() == self.selection.focus.block_id).map(|i| vec![(i, 0..usize::MAX)]).unwrap_or_default()
+                } else {
+                    self.selected_text_spans()
+                };
+                if !self.restyle_spans(&spans, all, false) {
+                    return Ok(());
+                }
+            }
+            EditorCommand::MoveCursor { direction, extend } => {
+                self.move_cursor(direction, extend);
+                return Ok(());
+            }
+            EditorCommand::MoveCursorWord { forward, extend } => {
+                self.move_caret_by_word(forward, extend);
+                return Ok(());
+            }
+            EditorCommand::SelectAll | EditorCommand::SelectWord | EditorCommand::SelectBlock => {
+                if let Some(selection) = self.selection_for(&cmd) {
+                    self.selection = selection;
+                }
+                return Ok(());
+            }
+            EditorCommand::InsertParagraphBreak if matches!(self.doc.blocks.iter().find(|b| b.id() == self.selection.focus.block_id), Some(Block::Code { .. })) => {
+                return self.run_command(EditorCommand::InsertText("\n".to_string()));
+            }
+            EditorCommand::InsertParagraphBreak if matches!(self.doc.blocks.iter().find(|b| b.id() == self.selection.focus.block_id), Some(Block::List { .. })) => {
+                return self.run_command(EditorCommand::SplitListItem);
+            }
+            EditorCommand::InsertParagraphBreak => self.insert_paragraph_break(),
+            EditorCommand::SplitListItem => {
+                let focus = self.selection.focus;
+                let Some(index) = self.doc.blocks.iter().position(|b| b.id() == focus.block_id) else {
+                    return Ok(());
+                };
+                match &self.doc.blocks[index] {
+                    Block::List { items, .. } if !items.is_empty() => {
+                        let (item, _) = list_item_at(items, focus.offset);
+                        if inline_text_len(&items[item].content) > 0 {
+                            self.insert_paragraph_break();
+                        } else {
+                            self.checkpoint();
+                            let id = self.lift_list_item(index, item, |id, content| Block::Paragraph { id, content, dirty: true, locked: false });
+                            self.selection = Selection::collapsed(Position { block_id: id, offset: 0 });
+                        }
+                    }
+                    _ => return Ok(()),
+                }
+            }
+            EditorCommand::SetHeading(level) => self.set_heading(level)?,
+            EditorCommand::ConvertBlock(target) => {
+                if !self.convert_block(target)? {
+                    return Ok(());
+                }
+            }
+            EditorCommand::ToggleList(ordered) => {
+                if !self.toggle_list(ordered) {
+                    return Ok(());
+                }
+            }
+            EditorCommand::ToggleTaskItem => {
+                if !self.set_task_state(|checked| Some(checked == Some(false))) {
+                    return Ok(());
+                }
+            }
+            EditorCommand::SetTaskState(state) => {
+                if !self.set_task_state(|_| state) {
+                    return Ok(());
+                }
             }
             EditorCommand::InsertList(ordered) => {
-                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
                 self.insert_list(ordered);
-
             }
             EditorCommand::InsertQuote(text) => {
-                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
                 self.insert_quote(text);
-
             }
             EditorCommand::InsertCode { lang, code } => {
-                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
                 self.insert_code(lang, code);
-
             }
             EditorCommand::InsertTable(r, c) => {
-                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
                 self.insert_table(r, c);
-
             }
             EditorCommand::InsertImage(url) => {
-                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
                 self.insert_image(url);
-
             }
             EditorCommand::InsertFigure { url, caption } => {
-                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
                 self.insert_figure(url, caption);
-
+            }
+            EditorCommand::InsertImageData { bytes, mime, caption } => {
+                self.insert_figure(image_data_uri(&mime, &bytes), Some(caption.unwrap_or_else(|| "图片".to_string())));
+            }
+            EditorCommand::InsertPageBreak => {
+                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
+                let focus = self.selection.focus.block_id;
+                let at = self.doc.blocks.iter().position(|b| b.id() == focus).map_or(self.doc.blocks.len(), |i| i + 1);
+                self.doc.blocks.insert(at, Block::PageBreak { id: Uuid::new_v4(), dirty: true, locked: false });
+            }
+            EditorCommand::InsertToc => {
+                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
+                let focus = self.selection.focus.block_id;
+                let at = self.doc.blocks.iter().position(|b| b.id() == focus).map_or(self.doc.blocks.len(), |i| i + 1);
+                let items = toc_items(&self.doc.blocks);
+                self.doc.blocks.insert(at, Block::List { id: Uuid::new_v4(), ordered: false, items, dirty: true, locked: true, toc: true });
+            }
+            EditorCommand::InsertCustom { kind, data } => {
+                self.insert_new_block(Block::Custom { id: Uuid::new_v4(), kind: Arc::from(kind), data, dirty: true, locked: false });
+            }
+            EditorCommand::ResizeFigure { block_id, width, height } => {
+                self.with_block_change_merge(block_id, |b, _| {
+                    if let Block::Figure { size, dirty, .. } = b {
+                        *size = Some(FigureSize { width: width.max(1.0), height: height.max(1.0) });
+                        *dirty = true;
+                    }
+                });
             }
             EditorCommand::InsertLink { url, text } => {
                 self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
                 self.insert_link(url, text);
 
             }
-            EditorCommand::TableEditCell { block_id, row, col, text } => {
+            EditorCommand::InsertMath { tex, display } => {
+                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
+                let len = tex.chars().count();
+                self.insert_at_caret(Inline::Math { tex: Arc::from(tex), display }, len);
+            }
+            EditorCommand::EditLink { block_id, url, new_url } => {
+                self.with_block_change(block_id, |b| {
+                    Self::relink_in_block(b, &url, Some(&new_url));
+                });
+            }
+            EditorCommand::RemoveLink { block_id, url } => {
                 self.with_block_change(block_id, |b| {
-                    TableEditor::set_cell_text(b, row, col, text.clone());
+                    Self::relink_in_block(b, &url, None);
+                });
+            }
+            EditorCommand::ReplaceMatch { block_id, start, end, replacement } => {
+                let replacement = self.config.input.apply(&replacement).into_owned();
+                let code_range = self.doc.blocks.iter().find(|b| b.id() == block_id).and_then(|b| match b {
+                    Block::Code { code, .. } if start <= end => Some(code.char_to_byte(start)..code.char_to_byte(end)),
+                    _ => None,
+                });
+                if let Some(range) = code_range {
+                    let selection = self.selection;
+                    self.with_inline_edit(block_id, Vec::new(), range, &replacement, |_| selection);
+                } else {
+                    self.with_block_change(block_id, |b| {
+                        replace_range_in_block(b, start, end, &replacement);
+                    });
+                }
+            }
+            EditorCommand::ReplaceAll { query, replacement, options } => {
+                let replacement = self.config.input.apply(&replacement).into_owned();
+                let mut matches = self.text_index.find_in_doc(&self.doc, &query, options.clone());
+                if let Some(m) = matches.iter().find(|m| self.doc.blocks[m.block_index].is_locked()) {
+                    self.outcome = CommandOutcome::BlockedByLock { block_id: m.block_id };
+                }
+                matches.retain(|m| !self.doc.blocks[m.block_index].is_locked());
+                if matches.is_empty() {
+                    return Ok(());
+                }
+                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
+                let mut last = None;
+                for m in matches {
+                    if last != Some(m.block_index) {
+                        last = Some(m.block_index);
+                        replace_all_in_block(&mut self.doc.blocks[m.block_index], &query, &replacement, &options);
+                    }
+                }
+            }
+            EditorCommand::TableEditCell { block_id, row, col, text } => {
+                let text = self.config.input.apply(&text).into_owned();
+                let current = self.doc.blocks.iter().find(|b| b.id() == block_id).and_then(|b| match b {
+                    Block::Table { rows, .. } => match rows.get(row)?.get(col)?.content.as_slice() {
+                        [Inline::Text { value }] => Some(value.clone()),
+                        _ => None,
+                    },
+                    _ => None,
                 });
+                if let Some(current) = current {
+                    let range = changed_range(&current, &text);
+                    let inserted = &text[range.start..text.len() - (current.len() - range.end)];
+                    let selection = self.selection;
+                    self.with_inline_edit(block_id, vec![row, col, 0], range, inserted, |_| selection);
+                } else {
+                    self.with_block_change(block_id, |b| {
+                        TableEditor::set_cell_text(b, row, col, text.clone());
+                    });
+                }
             }
             EditorCommand::TableInsertRow => {
                 self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
@@ -127,26 +632,298 @@ impl Editor {
                 self.table_delete_column();
 
             }
-            EditorCommand::ListIndent => {
+            EditorCommand::TableInsertRowAt { block_id, index } => {
+                self.with_block_change(block_id, |b| {
+                    TableEditor::insert_row(b, index);
+                });
+            }
+            EditorCommand::TableInsertColumnAt { block_id, index } => {
+                self.with_block_change(block_id, |b| {
+                    TableEditor::insert_column(b, index);
+                });
+            }
+            EditorCommand::TableDeleteRowAt { block_id, index } => {
+                self.with_block_change(block_id, |b| {
+                    TableEditor::delete_row(b, index);
+                });
+            }
+            EditorCommand::TableDeleteColumnAt { block_id, index } => {
+                self.with_block_change(block_id, |b| {
+                    TableEditor::delete_column(b, index);
+                });
+            }
+            EditorCommand::TableToggleHeader { block_id } => {
+                self.with_block_change(block_id, |b| {
+                    TableEditor::toggle_header(b);
+                });
+            }
+            EditorCommand::TableSetColWidth { block_id, col, width } => {
+                self.with_block_change_merge(block_id, |b, _| {
+                    TableEditor::set_col_width(b, col, width);
+                });
+            }
+            EditorCommand::MoveBlock { block_id, to_index } => {
+                let Some(from) = self.doc.blocks.iter().position(|b| b.id() == block_id) else {
+                    return Ok(());
+                };
+                let to = to_index.min(self.doc.blocks.len());
+                if to == from || to == from + 1 {
+                    return Ok(());
+                }
                 self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
-                self.list_indent(true);
-
+                let block = self.doc.blocks.remove(from);
+                let dest = if to > from { to - 1 } else { to };
+                self.doc.blocks.insert(dest, block);
+                self.doc.blocks[dest].set_dirty(true);
             }
-            EditorCommand::ListOutdent => {
+            EditorCommand::MoveBlockUp | EditorCommand::MoveBlockDown => {
+                let Some(from) = self.doc.blocks.iter().position(|b| b.id() == self.selection.focus.block_id) else {
+                    return Ok(());
+                };
+                let to = if matches!(cmd, EditorCommand::MoveBlockUp) { from.checked_sub(1) } else { Some(from + 1).filter(|to| *to < self.doc.blocks.len()) };
+                let Some(to) = to else {
+                    return Ok(());
+                };
                 self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
-                self.list_indent(false);
-
+                self.doc.blocks.swap(from, to);
+                self.doc.blocks[from].set_dirty(true);
+                self.doc.blocks[to].set_dirty(true);
+            }
+            EditorCommand::DuplicateBlock => {
+                let Some(index) = self.doc.blocks.iter().position(|b| b.id() == self.selection.focus.block_id) else {
+                    return Ok(());
+                };
+                let mut copy = self.doc.blocks[index].clone();
+                refresh_ids(&mut copy);
+                copy.set_dirty(true);
+                let (original, block_id) = (self.doc.blocks[index].id(), copy.id());
+                self.checkpoint();
+                self.doc.blocks.insert(index + 1, copy);
+                let Selection { anchor, focus } = self.selection;
+                let anchor = if anchor.block_id == original { anchor.offset } else { focus.offset };
+                self.selection = Selection { anchor: Position { block_id, offset: anchor }, focus: Position { block_id, offset: focus.offset } };
+            }
+            EditorCommand::DeleteBlock { block_id } => {
+                let block_id = block_id.unwrap_or(self.selection.focus.block_id);
+                let Some(index) = self.doc.blocks.iter().position(|b| b.id() == block_id) else {
+                    return Ok(());
+                };
+                self.checkpoint();
+                self.doc.blocks.remove(index);
+                if self.doc.blocks.is_empty() {
+                    self.doc.blocks.push(Block::Paragraph { id: Uuid::new_v4(), content: Vec::new(), dirty: true, locked: false });
+                }
+                if self.selection.anchor.block_id == block_id || self.selection.focus.block_id == block_id {
+                    let caret = match index.checked_sub(1).map(|i| &self.doc.blocks[i]) {
+                        Some(prev) => Position { block_id: prev.id(), offset: block_text_len(prev) },
+                        None => Position { block_id: self.doc.blocks[0].id(), offset: 0 },
+                    };
+                    self.selection = Selection::collapsed(caret);
+                }
+            }
+            EditorCommand::ListIndent { block_id, item } => {
+                let Some((at, item, level)) = self.list_level_change(block_id, item, true) else {
+                    return Ok(());
+                };
+                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
+                self.set_list_level(at, item, level);
+            }
+            EditorCommand::ListOutdent { block_id, item } => {
+                let Some((at, item, level)) = self.list_level_change(block_id, item, false) else {
+                    return Ok(());
+                };
+                self.history.push_entry(HistoryEntry::Snapshot(self.snapshot()));
+                self.set_list_level(at, item, level);
+            }
+            EditorCommand::SetBlockLocked { block_id, locked } => {
+                if !self.doc.blocks.iter().any(|b| b.id() == block_id && b.is_locked() != locked) {
+                    return Ok(());
+                }
+                self.with_block_change(block_id, |b| {
+                    b.set_locked(locked);
+                    b.set_dirty(true);
+                });
             }
             EditorCommand::Undo => {
-                self.undo();
-                return;
+                if !self.undo() {
+                    return Ok(());
+                }
             }
             EditorCommand::Redo => {
-                self.redo();
-                return;
+                if !self.redo() {
+                    return Ok(());
+                }
+            }
+        }
+        self.doc.touch();
+        self.after_change();
+        Ok(())
+    }
+
+    /// The cell Tab moves to from (`row`, `col`) of table `block_id`. Tab
+    /// on the last cell appends a row, as its own undo step, and moves to
+    /// its first cell.
+    pub fn next_table_cell(&mut self, block_id: Uuid, row: usize, col: usize) -> Option<(usize, usize)> {
+        let Block::Table { rows, .. } = self.doc.blocks.iter().find(|b| b.id() == block_id)? else {
+            return None;
+        };
+        if let Some(next) = TableEditor::next_cell(rows, row, col) {
+            return Some(next);
+        }
+        let index = rows.len();
+        self.execute(EditorCommand::TableInsertRowAt { block_id, index });
+        Some((index, 0))
+    }
+
+    /// Puts the caret at 1-based `line` and `col` of a code block, with the
+    /// column clamped to the line. Returns false when there is no such line.
+    pub fn goto_line(&mut self, block_id: Uuid, line: usize, col: usize) -> bool {
+        let offset = self.doc.blocks.iter().find(|b| b.id() == block_id).and_then(|b| code_line_col_to_offset(b, line, col));
+        if let Some(offset) = offset {
+            self.selection = Selection::collapsed(Position { block_id, offset });
+        }
+        offset.is_some()
+    }
+
+    /// Copies of the selected blocks, the first and last cut at the
+    /// selection ends. Empty when the selection is collapsed.
+    pub fn selection_fragment(&self) -> Vec<Block> {
+        let (anchor, focus) = (self.selection.anchor, self.selection.focus);
+        let index = |id: Uuid| self.doc.blocks.iter().position(|b| b.id() == id);
+        let (Some(a), Some(f)) = (index(anchor.block_id), index(focus.block_id)) else {
+            return Vec::new();
+        };
+        let (start, end) = if (a, anchor.offset) <= (f, focus.offset) { ((a, anchor.offset), (f, focus.offset)) } else { ((f, focus.offset), (a, anchor.offset)) };
+        if start == end {
+            return Vec::new();
+        }
+        (start.0..=end.0)
+            .map(|idx| {
+                let from = if idx == start.0 { start.1 } else { 0 };
+                let to = if idx == end.0 { end.1 } else { usize::MAX };
+                slice_block(&self.doc.blocks[idx], from, to)
+            })
+            .collect()
+    }
+
+    /// Inserts fresh copies of `template`'s blocks after the focused block
+    /// as one undo step and puts the caret at the start of the first one.
+    pub fn insert_template(&mut self, template: &Template) {
+        self.insert_blocks(template.instantiate(&self.doc.metadata));
+    }
+
+    /// Inserts `blocks`, such as those of a pasted `Fragment`, after the
+    /// focused block as one undo step and puts the caret at the start of
+    /// the first one. Their ids must not be in the document already.
+    pub fn insert_blocks(&mut self, blocks: Vec<Block>) {
+        let Some(first) = blocks.first().map(Block::id) else {
+            return;
+        };
+        self.checkpoint();
+        let focus = self.selection.focus.block_id;
+        let at = self.doc.blocks.iter().position(|b| b.id() == focus).map_or(self.doc.blocks.len(), |idx| idx + 1);
+        self.doc.blocks.splice(at..at, blocks);
+        self.selection = Selection::collapsed(Position { block_id: first, offset: 0 });
+        self.doc.touch();
+        self.after_change();
+    }
+
+    /// Appends already built blocks, such as pasted HTML, as one undo step.
+    pub fn paste_blocks(&mut self, blocks: Vec<Block>) {
+        if blocks.is_empty() {
+            return;
+        }
+        self.checkpoint();
+        let filter = self.config.input;
+        self.doc.blocks.extend(blocks.into_iter().map(|mut b| {
+            filter.apply_to_block(&mut b);
+            b.set_dirty(true);
+            b
+        }));
+        self.doc.touch();
+        self.after_change();
+    }
+
+    /// Makes the repairs `options` asks for, see `repair_document`, as one
+    /// undo step. A caret in a block that was merged away moves to the
+    /// block it joined. Returns how many blocks changed.
+    pub fn repair(&mut self, options: &RepairOptions) -> usize {
+        let before = self.snapshot();
+        let order: Vec<Uuid> = self.doc.blocks.iter().map(Block::id).collect();
+        let repaired = repair_document(&mut self.doc, options);
+        if repaired == 0 {
+            return 0;
+        }
+        self.history.push_entry(HistoryEntry::Snapshot(before));
+        let kept: HashSet<Uuid> = self.doc.blocks.iter().map(Block::id).collect();
+        let survivor = |pos: Position| {
+            if kept.contains(&pos.block_id) {
+                return pos;
             }
+            let at = order.iter().position(|id| *id == pos.block_id).unwrap_or(0);
+            let block_id = order[..at].iter().rev().find(|id| kept.contains(id)).copied().unwrap_or(order[0]);
+            Position { block_id, offset: 0 }
+        };
+        self.selection = Selection { anchor: survivor(self.selection.anchor), focus: survivor(self.selection.focus) };
+        self.doc.touch();
+        self.after_change();
+        repaired
+    }
+
+    pub fn intern_stats(&self) -> InternStats {
+        self.interner.stats()
+    }
+
+    /// Rebuilds every table of contents from the current headings as one
+    /// undo step. Commands keep them current already; this catches up
+    /// after `doc` was changed directly. Returns whether any changed.
+    pub fn refresh_toc(&mut self) -> bool {
+        let items = toc_items(&self.doc.blocks);
+        if !self.doc.blocks.iter().any(|b| matches!(b, Block::List { toc: true, items: old, .. } if *old != items)) {
+            return false;
         }
+        self.checkpoint();
         self.doc.touch();
+        self.after_change();
+        true
+    }
+
+    /// Brings tables of contents in line with the headings. Not an undo
+    /// step of its own: undoing the heading change brings them back too.
+    fn sync_toc(&mut self) {
+        if !self.doc.blocks.iter().any(Block::is_toc) {
+            return;
+        }
+        let items = toc_items(&self.doc.blocks);
+        for block in &mut self.doc.blocks {
+            if let Block::List { toc: true, items: old, dirty, .. } = block {
+                if *old != items {
+                    *old = items.clone();
+                    *dirty = true;
+                }
+            }
+        }
+    }
+
+    fn after_change(&mut self) {
+        self.sync_toc();
+        for block in self.doc.blocks.iter_mut().filter(|b| b.is_effectively_dirty()) {
+            self.interner.intern_block(block);
+        }
+        self.commands_since_prune += 1;
+        if self.commands_since_prune >= INTERN_PRUNE_EVERY {
+            self.interner.prune();
+            self.commands_since_prune = 0;
+        }
+        self.signatures.refresh(&self.doc);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
     }
 
     pub fn checkpoint(&mut self) {
@@ -154,7 +931,10 @@ impl Editor {
     }
 
     fn snapshot(&self) -> Snapshot {
-        Snapshot { doc: self.doc.clone(), selection: self.selection }
+        Snapshot {
+            doc: DocSnapshot::capture(&self.doc, self.history.latest_snapshot()),
+            selection: self.selection,
+        }
     }
 
     fn with_block_change<F>(&mut self, block_id: uuid::Uuid, mut f: F)
@@ -179,12 +959,12 @@ impl Editor {
 
     fn with_block_change_merge<F>(&mut self, block_id: uuid::Uuid, mut f: F)
     where
-        F: FnMut(&mut Block),
+        F: FnMut(&mut Block, &mut Selection),
     {
         let selection_before = self.selection;
         if let Some(pos) = self.doc.blocks.iter().position(|b| b.id() == block_id) {
             let before = self.doc.blocks[pos].clone();
-            f(&mut self.doc.blocks[pos]);
+            f(&mut self.doc.blocks[pos], &mut self.selection);
             let after = self.doc.blocks[pos].clone();
             let selection_after = self.selection;
             self.history.push_or_merge_block_change(HistoryEntry::BlockChange {
@@ -197,6 +977,121 @@ impl Editor {
         }
     }
 
+    /// Splices `inserted` over the bytes `range` of one text leaf and records
+    /// only that delta, so large blocks are never cloned into history.
+    fn with_inline_edit<F>(&mut self, block_id: Uuid, path: Vec<usize>, range: Range<usize>, inserted: &str, selection_after: F)
+    where
+        F: FnOnce(&Block) -> Selection,
+    {
+        if let Some(entry) = self.splice_inline(block_id, path, range, inserted, selection_after) {
+            self.history.push_or_merge_block_change(entry);
+        }
+    }
+
+    /// The splice of `with_inline_edit`, returning the entry to record.
+    fn splice_inline<F>(&mut self, block_id: Uuid, path: Vec<usize>, range: Range<usize>, inserted: &str, selection_after: F) -> Option<HistoryEntry>
+    where
+        F: FnOnce(&Block) -> Selection,
+    {
+        let block = self.doc.blocks.iter_mut().find(|b| b.id() == block_id)?;
+        let removed = splice_text_leaf(block, &path, range.clone(), inserted)?;
+        if removed == inserted {
+            return None;
+        }
+        let selection_before = self.selection;
+        self.selection = selection_after(block);
+        Some(HistoryEntry::InlineEdit { block_id, path, range, removed, inserted: inserted.to_string(), selections: (selection_before, self.selection) })
+    }
+
+    /// Deletes chars `start..end` of the paragraph or heading at `index`,
+    /// leaving the caret at `start`. With `merge`, a deletion that follows
+    /// on from the last one joins its undo step.
+    fn delete_chars(&mut self, index: usize, start: usize, end: usize, merge: bool) {
+        let block = &self.doc.blocks[index];
+        let block_id = block.id();
+        let caret = Selection::collapsed(Position { block_id, offset: start });
+        let leaf = match block {
+            Block::Code { code, .. } => Some((Vec::new(), code.char_to_byte(start)..code.char_to_byte(end))),
+            Block::List { items, .. } if list_item_at(items, start).0 != list_item_at(items, end).0 => {
+                // The range crosses item breaks: the items it touches join.
+                self.with_block_change_merge(block_id, |b, selection| {
+                    if let Block::List { items, dirty, .. } = b {
+                        let (first, first_start) = list_item_at(items, start);
+                        let (last, last_start) = list_item_at(items, end);
+                        let tail = slice_inlines(&items[last].content, end - last_start, usize::MAX);
+                        let head = &mut items[first].content;
+                        *head = slice_inlines(head, 0, start - first_start);
+                        head.extend(tail);
+                        normalize_inlines(head);
+                        items.drain(first + 1..=last);
+                        *dirty = true;
+                    }
+                    *selection = caret;
+                });
+                return;
+            }
+            _ => leaf_range(block, start, end),
+        };
+        if let Some((path, range)) = leaf {
+            match self.splice_inline(block_id, path, range, "", |_| caret) {
+                Some(entry) if merge => self.history.push_or_merge_deletion(entry),
+                Some(entry) => self.history.push_or_merge_block_change(entry),
+                None => {}
+            }
+        } else {
+            self.with_block_change_merge(block_id, |b, selection| {
+                replace_range_in_block(b, start, end, "");
+                *selection = Selection::collapsed(Position { block_id, offset: start.min(block_text_len(b)) });
+            });
+        }
+    }
+
+    /// Where text typed at char `offset` lands when it can be recorded as an
+    /// inline edit: inside the text run at the caret, or anywhere in a code
+    /// block.
+    fn insert_point(block: &Block, offset: usize) -> Option<(Vec<usize>, usize)> {
+        match block {
+            Block::Paragraph { .. } | Block::Heading { .. } => text_run_at(block, offset),
+            Block::Code { code, .. } => Some((Vec::new(), code.char_to_byte(offset))),
+            _ => None,
+        }
+    }
+
+    /// Style of text typed at the caret without a pending style.
+    fn style_at_caret(&self) -> Style {
+        let focus = self.selection.focus;
+        self.doc
+            .blocks
+            .iter()
+            .find(|b| b.id() == focus.block_id)
+            .and_then(|block| Some(style_at_path(block, &resolve_position(block, focus.offset)?)))
+            .unwrap_or_default()
+    }
+
+    /// Inserts `text` at the caret as a run of exactly `style`, cutting any
+    /// styled span around the caret in two. False when the caret is not on
+    /// inline text.
+    fn insert_styled_text(&mut self, text: &str, style: Style) -> bool {
+        let focus = self.selection.focus;
+        let resolves = self
+            .doc
+            .blocks
+            .iter()
+            .find(|b| b.id() == focus.block_id)
+            .is_some_and(|block| resolve_position(block, focus.offset).is_some());
+        if !resolves {
+            return false;
+        }
+        let run = Inline::Text { value: self.interner.intern(text) };
+        let inline = if style.is_plain() { run } else { Inline::Styled { style, content: vec![run] } };
+        let offset = focus.offset + text.chars().count();
+        self.with_block_change_merge(focus.block_id, |b, selection| {
+            insert_inline(b, focus.offset, inline.clone());
+            *selection = Selection::collapsed(Position { block_id: focus.block_id, offset });
+        });
+        true
+    }
+
     fn insert_text_into_block(block: &mut Block, text: String) {
         if let Block::Paragraph { content, dirty, .. } | Block::Heading { content, dirty, .. } = block {
             if let Some(Inline::Text { value }) = content.last_mut() {
@@ -211,65 +1106,533 @@ impl Editor {
         }
     }
 
-    fn delete_selection_in_block(block: &mut Block) {
-        if let Block::Paragraph { content, dirty, .. } | Block::Heading { content, dirty, .. } = block {
-            content.pop();
-            *dirty = true;
+    /// Replaces a paragraph that consists of nothing but a markdown trigger
+    /// with the block it stands for. This is its own history entry, so undo
+    /// brings back the literal trigger text.
+    fn apply_markdown_shortcut(&mut self, block_id: Uuid) {
+        let Some(Block::Paragraph { content, .. }) = self.doc.blocks.iter().find(|b| b.id() == block_id) else {
+            return;
+        };
+        let mut text = String::new();
+        crate::inline_plain_text(content, &mut text);
+        let Some(shortcut) = MarkdownShortcut::parse(&text) else {
+            return;
+        };
+        self.with_block_change(block_id, |b| {
+            *b = shortcut.block(block_id);
+        });
+        self.selection = Selection::collapsed(Position { block_id, offset: 0 });
+    }
+
+    /// Toggles `flags` over the selection in the paragraphs and headings it
+    /// covers: they are cleared when every selected char already has all of
+    /// them, and set otherwise. False when the document did not change.
+    fn toggle_range_style(&mut self, flags: Style) -> bool {
+        let spans = self.selected_text_spans();
+        let common = spans
+            .iter()
+            .filter_map(|(i, range)| match &self.doc.blocks[*i] {
+                Block::Paragraph { content, .. } | Block::Heading { content, .. } => range_style(content, range.start, range.end),
+                _ => None,
+            })
+            .reduce(Style::intersect);
+        let Some(common) = common else {
+            return false;
+        };
+        self.restyle_spans(&spans, flags, common.intersect(flags) != flags)
+    }
+
+    /// The paragraphs and headings the selection covers, by index, each with
+    /// the chars of it that are selected.
+    fn selected_text_spans(&self) -> Vec<(usize, Range<usize>)> {
+        let (anchor, focus) = (self.selection.anchor, self.selection.focus);
+        let index = |id: Uuid| self.doc.blocks.iter().position(|b| b.id() == id);
+        let (Some(a), Some(f)) = (index(anchor.block_id), index(focus.block_id)) else {
+            return Vec::new();
+        };
+        let ((first, from), (last, to)) = if (a, anchor.offset) <= (f, focus.offset) { ((a, anchor.offset), (f, focus.offset)) } else { ((f, focus.offset), (a, anchor.offset)) };
+        (first..=last)
+            .filter(|i| matches!(self.doc.blocks[*i], Block::Paragraph { .. } | Block::Heading { .. }))
+            .map(|i| (i, if i == first { from } else { 0 }..if i == last { to } else { usize::MAX }))
+            .collect()
+    }
+
+    /// Sets `flags` over `spans`, or clears them when `on` is false, as one
+    /// undo step. False when that changes nothing or would change a locked
+    /// block.
+    fn restyle_spans(&mut self, spans: &[(usize, Range<usize>)], flags: Style, on: bool) -> bool {
+        let restyled: Vec<(usize, Vec<Inline>)> = spans
+            .iter()
+            .filter_map(|(i, range)| match &self.doc.blocks[*i] {
+                Block::Paragraph { content, .. } | Block::Heading { content, .. } => {
+                    let mut next = content.clone();
+                    set_range_style(&mut next, range.start, range.end, flags, on);
+                    (next != *content).then_some((*i, next))
+                }
+                _ => None,
+            })
+            .collect();
+        if let Some(locked) = restyled.iter().map(|(i, _)| &self.doc.blocks[*i]).find(|b| b.is_locked()) {
+            self.outcome = CommandOutcome::BlockedByLock { block_id: locked.id() };
+            return false;
+        }
+        let replace = |block: &mut Block, next: &Vec<Inline>| {
+            if let Block::Paragraph { content, dirty, .. } | Block::Heading { content, dirty, .. } = block {
+                content.clone_from(next);
+                *dirty = true;
+            }
+        };
+        match restyled.as_slice() {
+            [] => return false,
+            [(i, next)] => {
+                let block_id = self.doc.blocks[*i].id();
+                self.with_block_change(block_id, |b| replace(b, next));
+            }
+            _ => {
+                self.checkpoint();
+                for (i, next) in &restyled {
+                    replace(&mut self.doc.blocks[*i], next);
+                }
+            }
         }
+        true
     }
 
-    fn apply_style_in_block(block: &mut Block, style: Style) {
-        if let Block::Paragraph { content, dirty, .. } | Block::Heading { content, dirty, .. } = block {
-            let inner = std::mem::take(content);
-            *content = vec![Inline::Styled { style, content: inner }];
+    /// Deletes a selection whose ends are in different blocks as one undo
+    /// step: the blocks between go, the paragraph or heading it starts in
+    /// keeps the text before it and takes in what the one it ends in keeps
+    /// after it. Other blocks at the ends, like tables and code, are kept
+    /// whole rather than cut. False when the document did not change, which
+    /// includes a selection over a locked block.
+    fn delete_across_blocks(&mut self) -> bool {
+        let (anchor, focus) = (self.selection.anchor, self.selection.focus);
+        let index = |id: Uuid| self.doc.blocks.iter().position(|b| b.id() == id);
+        let (Some(a), Some(f)) = (index(anchor.block_id), index(focus.block_id)) else {
+            return false;
+        };
+        let ((first, from), (last, to)) = if (a, anchor.offset) <= (f, focus.offset) { ((a, anchor.offset), (f, focus.offset)) } else { ((f, focus.offset), (a, anchor.offset)) };
+        let cuts = |block: &Block| matches!(block, Block::Paragraph { .. } | Block::Heading { .. });
+        let (cut_first, cut_last) = (cuts(&self.doc.blocks[first]), cuts(&self.doc.blocks[last]));
+        let edited = if cut_first { first } else { first + 1 }..if cut_last { last + 1 } else { last };
+        if let Some(locked) = self.doc.blocks[edited.clone()].iter().find(|b| b.is_locked()) {
+            self.outcome = CommandOutcome::BlockedByLock { block_id: locked.id() };
+            return false;
+        }
+        if edited.is_empty() {
+            return false;
+        }
+        self.checkpoint();
+        let tail = match &self.doc.blocks[last] {
+            Block::Paragraph { content, .. } | Block::Heading { content, .. } => slice_inlines(content, to, usize::MAX),
+            _ => Vec::new(),
+        };
+        let (kept, offset) = if cut_first { (first, from) } else { (last, 0) };
+        // Matches only when `kept` is one of the cut ends.
+        if let Block::Paragraph { content, dirty, .. } | Block::Heading { content, dirty, .. } = &mut self.doc.blocks[kept] {
+            let mut joined = if cut_first { slice_inlines(content, 0, from) } else { Vec::new() };
+            joined.extend(tail);
+            normalize_inlines(&mut joined);
+            *content = joined;
             *dirty = true;
         }
+        let offset = offset.min(block_text_len(&self.doc.blocks[kept]));
+        let caret = Position { block_id: self.doc.blocks[kept].id(), offset };
+        let removed = first + 1..if cut_first && cut_last { last + 1 } else { last };
+        self.doc.blocks.drain(removed);
+        self.selection = Selection::collapsed(caret);
+        true
     }
 
-    fn set_heading_in_block(block: &mut Block, level: u8) {
-        let content = match block {
-            Block::Paragraph { content, .. } | Block::Heading { content, .. } => content.clone(),
-            _ => vec![Inline::Text { value: Arc::from("") }],
+    /// Backspace at the start of paragraph or heading `index`: appends its
+    /// inlines to the paragraph, heading or last list item before it and
+    /// puts the caret where they start. Before any other block, or a locked
+    /// one, the caret only moves to its end. False when the document did
+    /// not change.
+    fn join_previous(&mut self, index: usize) -> bool {
+        let Some(prev) = index.checked_sub(1).map(|i| &self.doc.blocks[i]) else {
+            return false;
         };
-        *block = Block::Heading {
-            id: block.id(),
-            level,
-            content,
-            dirty: true,
+        let offset = block_text_len(prev);
+        let caret = Selection::collapsed(Position { block_id: prev.id(), offset });
+        let joins = !prev.is_locked()
+            && match prev {
+                Block::Paragraph { .. } | Block::Heading { .. } => true,
+                Block::List { items, .. } => !items.is_empty(),
+                _ => false,
+            };
+        if !joins {
+            self.selection = caret;
+            return false;
+        }
+        self.checkpoint();
+        let (Block::Paragraph { content, .. } | Block::Heading { content, .. }) = self.doc.blocks.remove(index) else {
+            unreachable!("only paragraphs and headings join");
+        };
+        match &mut self.doc.blocks[index - 1] {
+            Block::Paragraph { content: into, dirty, .. } | Block::Heading { content: into, dirty, .. } => {
+                into.extend(content);
+                normalize_inlines(into);
+                *dirty = true;
+            }
+            Block::List { items, dirty, .. } => {
+                let last = items.last_mut().expect("checked above");
+                last.content.extend(content);
+                normalize_inlines(&mut last.content);
+                *dirty = true;
+            }
+            _ => unreachable!("checked above"),
+        }
+        self.selection = caret;
+        true
+    }
+
+    /// See `EditorCommand::InsertParagraphBreak`; code blocks are handled
+    /// by the caller. A heading half left without text becomes a
+    /// paragraph. The caret inside a formula splits nothing, since half a
+    /// formula is not TeX.
+    fn insert_paragraph_break(&mut self) {
+        let focus = self.selection.focus;
+        let Some(index) = self.doc.blocks.iter().position(|b| b.id() == focus.block_id) else {
+            return;
+        };
+        let mut block = self.doc.blocks[index].clone();
+        let item = match &block {
+            Block::Paragraph { .. } | Block::Heading { .. } => None,
+            Block::List { items, .. } if !items.is_empty() => Some(list_item_at(items, focus.offset)),
+            _ => return,
+        };
+        let mut tail = match resolve_position(&block, focus.offset) {
+            Some(path) if item.is_none_or(|(item, _)| path.inline_indices.first() == Some(&item)) => {
+                let Some(at) = split_at_path(&mut block, &path) else {
+                    return;
+                };
+                let (list, _) = inline_list_mut(&mut block, &path.inline_indices).expect("the path was just split");
+                list.split_off(at)
+            }
+            _ => Vec::new(),
+        };
+        normalize_inlines(&mut tail);
+        self.checkpoint();
+        let new_id = Uuid::new_v4();
+        match block {
+            Block::List { id, ordered, mut items, toc, .. } => {
+                let (item, item_start) = item.expect("lists split at an item");
+                normalize_inlines(&mut items[item].content);
+                let mut head = String::new();
+                crate::inline_plain_text(&items[item].content, &mut head);
+                let (level, checked) = (items[item].level, items[item].checked);
+                items.insert(item + 1, ListItem { id: new_id, content: tail, level, checked });
+                self.doc.blocks[index] = Block::List { id, ordered, items, dirty: true, locked: false, toc };
+                let offset = item_start + head.chars().count() + 1;
+                self.selection = Selection::collapsed(Position { block_id: id, offset });
+            }
+            Block::Paragraph { id, mut content, .. } | Block::Heading { id, mut content, .. } => {
+                normalize_inlines(&mut content);
+                let level = match &self.doc.blocks[index] {
+                    Block::Heading { level, .. } => Some(*level),
+                    _ => None,
+                };
+                let half = |id, content: Vec<Inline>| {
+                    let mut text = String::new();
+                    crate::inline_plain_text(&content, &mut text);
+                    match level {
+                        Some(level) if !text.is_empty() => Block::Heading { id, level, content, dirty: true, locked: false },
+                        _ => Block::Paragraph { id, content, dirty: true, locked: false },
+                    }
+                };
+                self.doc.blocks[index] = half(id, content);
+                self.doc.blocks.insert(index + 1, half(new_id, tail));
+                self.selection = Selection::collapsed(Position { block_id: new_id, offset: 0 });
+            }
+            _ => unreachable!("checked above"),
+        }
+    }
+
+    /// See `EditorCommand::ConvertBlock`. False when the block already is
+    /// what it would become.
+    fn convert_block(&mut self, target: TargetKind) -> Result<bool, CommandError> {
+        let focus = self.selection.focus;
+        let Some(index) = self.doc.blocks.iter().position(|b| b.id() == focus.block_id) else {
+            return Ok(false);
+        };
+        let block = &self.doc.blocks[index];
+        let lines = |first: Option<Uuid>, make: &dyn Fn(Uuid, Vec<Inline>) -> Block| -> Vec<Block> {
+            let text = crate::block_plain_text(block);
+            text.split('\n')
+                .enumerate()
+                .map(|(i, line)| {
+                    let id = first.filter(|_| i == 0).unwrap_or_else(Uuid::new_v4);
+                    make(id, if line.is_empty() { Vec::new() } else { vec![Inline::Text { value: Arc::from(line) }] })
+                })
+                .collect()
+        };
+        let paragraph = |id, content| Block::Paragraph { id, content, dirty: true, locked: false };
+        let mut replacement = match (&target, block) {
+            (TargetKind::Heading(level), Block::Code { id, .. }) => lines(Some(*id), &|id, content| Block::Heading { id, level: *level, content, dirty: true, locked: false }),
+            (TargetKind::Heading(level), _) => {
+                self.set_heading(*level)?;
+                return Ok(true);
+            }
+            (TargetKind::Paragraph, Block::Paragraph { .. }) | (TargetKind::Quote, Block::Quote { .. }) => return Ok(false),
+            (TargetKind::Code { lang }, Block::Code { lang: current, .. }) if **current == **lang => return Ok(false),
+            (TargetKind::Paragraph, Block::Heading { id, content, .. }) => vec![paragraph(*id, content.clone())],
+            (TargetKind::Paragraph, Block::Code { id, .. }) => lines(Some(*id), &paragraph),
+            (TargetKind::Paragraph, Block::Quote { id, content, .. }) if content.is_empty() => vec![paragraph(*id, Vec::new())],
+            (TargetKind::Paragraph, Block::Quote { content, .. }) => content.clone(),
+            (TargetKind::Quote, Block::Paragraph { .. } | Block::Heading { .. }) => {
+                let mut inner = block.clone();
+                *inner.id_mut() = Uuid::new_v4();
+                vec![Block::Quote { id: block.id(), content: vec![inner], dirty: true, locked: false }]
+            }
+            (TargetKind::Quote, Block::Code { id, .. }) => vec![Block::Quote { id: *id, content: lines(None, &paragraph), dirty: true, locked: false }],
+            (TargetKind::Code { lang }, Block::Paragraph { .. } | Block::Heading { .. } | Block::Quote { .. } | Block::Code { .. }) => {
+                vec![Block::Code { id: block.id(), lang: Arc::from(lang.as_str()), code: CodeText::from(crate::block_plain_text(block)), dirty: true, locked: false }]
+            }
+            (_, other) => return Err(CommandError::CannotConvert { from: other.type_name(), to: target.type_name() }),
+        };
+        replacement.iter_mut().for_each(|b| b.set_dirty(true));
+        let original = block.id();
+        let caret = Position { block_id: replacement[0].id(), offset: focus.offset.min(block_text_len(&replacement[0])) };
+        if replacement.len() == 1 && caret.block_id == original {
+            let converted = replacement.remove(0);
+            self.with_block_change(original, |b| *b = converted.clone());
+        } else {
+            self.checkpoint();
+            self.doc.blocks.splice(index..=index, replacement);
+            self.selection = Selection::collapsed(caret);
+        }
+        Ok(true)
+    }
+
+    /// Sets the checked state of the list item holding the caret to what
+    /// `state` makes of its current one. False when the caret is not in a
+    /// list or the state stays the same.
+    fn set_task_state(&mut self, state: impl FnOnce(Option<bool>) -> Option<bool>) -> bool {
+        let Some(item) = self.focused_list_item() else {
+            return false;
+        };
+        let block_id = self.selection.focus.block_id;
+        let Some(Block::List { items, .. }) = self.doc.blocks.iter().find(|b| b.id() == block_id) else {
+            return false;
+        };
+        let checked = state(items[item].checked);
+        if checked == items[item].checked {
+            return false;
+        }
+        self.with_block_change(block_id, |b| {
+            if let Block::List { items, dirty, .. } = b {
+                items[item].checked = checked;
+                *dirty = true;
+            }
+        });
+        true
+    }
+
+    /// See `EditorCommand::ToggleList`. False when the caret is in neither a
+    /// paragraph nor a list.
+    fn toggle_list(&mut self, ordered: bool) -> bool {
+        let (anchor, focus) = (self.selection.anchor, self.selection.focus);
+        let index = |id: Uuid| self.doc.blocks.iter().position(|b| b.id() == id);
+        let Some(at) = index(focus.block_id) else {
+            return false;
+        };
+        match &self.doc.blocks[at] {
+            Block::List { ordered: current, .. } if *current != ordered => {
+                self.with_block_change(focus.block_id, |b| {
+                    if let Block::List { ordered: current, dirty, .. } = b {
+                        *current = ordered;
+                        *dirty = true;
+                    }
+                });
+            }
+            Block::List { items, .. } if !items.is_empty() => {
+                let (item, start) = list_item_at(items, focus.offset);
+                let caret = Position { block_id: items[item].id, offset: focus.offset.saturating_sub(start) };
+                let paragraphs: Vec<Block> = items.iter().map(|item| Block::Paragraph { id: item.id, content: item.content.clone(), dirty: true, locked: false }).collect();
+                self.checkpoint();
+                self.doc.blocks.splice(at..=at, paragraphs);
+                self.selection = Selection::collapsed(caret);
+            }
+            Block::Paragraph { .. } => {
+                let other = index(anchor.block_id).unwrap_or(at);
+                let (low, high) = (at.min(other), at.max(other));
+                let joins = |i: usize| matches!(self.doc.blocks[i], Block::Paragraph { locked: false, .. });
+                let (mut first, mut last) = (at, at);
+                while first > low && joins(first - 1) {
+                    first -= 1;
+                }
+                while last < high && joins(last + 1) {
+                    last += 1;
+                }
+                self.checkpoint();
+                let list_id = Uuid::new_v4();
+                let mut starts = Vec::with_capacity(last - first + 1);
+                let mut items = Vec::with_capacity(last - first + 1);
+                let mut start = 0;
+                for block in self.doc.blocks.drain(first..=last) {
+                    let len = block_text_len(&block);
+                    let Block::Paragraph { id, content, .. } = block else {
+                        unreachable!("checked above");
+                    };
+                    starts.push((id, start));
+                    start += len + 1;
+                    items.push(ListItem { id, content, level: 0, checked: None });
+                }
+                self.doc.blocks.insert(first, Block::List { id: list_id, ordered, items, dirty: true, locked: false, toc: false });
+                let map = |p: Position| starts.iter().find(|(id, _)| *id == p.block_id).map(|(_, start)| Position { block_id: list_id, offset: start + p.offset });
+                let caret = map(focus).expect("focus is in the run");
+                self.selection = Selection { anchor: map(anchor).unwrap_or(caret), focus: caret };
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Turns the focused paragraph, list item or leading quote paragraph into
+    /// a heading. List items and quote paragraphs move out of their block,
+    /// which keeps the rest of its content.
+    fn set_heading(&mut self, level: u8) -> Result<(), CommandError> {
+        let focus = self.selection.focus;
+        let Some(index) = self.doc.blocks.iter().position(|b| b.id() == focus.block_id) else {
+            return Ok(());
+        };
+        match &self.doc.blocks[index] {
+            Block::Paragraph { .. } | Block::Heading { .. } => {
+                self.with_block_change(focus.block_id, |b| {
+                    if let Block::Paragraph { id, content, .. } | Block::Heading { id, content, .. } = b {
+                        *b = Block::Heading { id: *id, level, content: std::mem::take(content), dirty: true, locked: false };
+                    }
+                });
+            }
+            Block::List { items, .. } => {
+                if items.is_empty() {
+                    return Ok(());
+                }
+                let (item, item_start) = list_item_at(items, focus.offset);
+                self.checkpoint();
+                let id = self.lift_list_item(index, item, |id, content| Block::Heading { id, level, content, dirty: true, locked: false });
+                let offset = focus.offset.saturating_sub(item_start);
+                self.selection = Selection::collapsed(Position { block_id: id, offset });
+            }
+            Block::Quote { content, .. } => {
+                match content.first() {
+                    None => return Ok(()),
+                    Some(Block::Paragraph { .. } | Block::Heading { .. }) => {}
+                    Some(_) => return Err(CommandError::NotConvertible("quote")),
+                }
+                self.checkpoint();
+                let Block::Quote { id, mut content, .. } = self.doc.blocks.remove(index) else {
+                    unreachable!("checked above");
+                };
+                let first = content.remove(0);
+                let len = block_text_len(&first);
+                let (Block::Paragraph { id: heading_id, content: inlines, .. }
+                | Block::Heading { id: heading_id, content: inlines, .. }) = first
+                else {
+                    unreachable!("checked above");
+                };
+                let mut replacement = vec![Block::Heading { id: heading_id, level, content: inlines, dirty: true, locked: false }];
+                if !content.is_empty() {
+                    replacement.push(Block::Quote { id, content, dirty: true, locked: false });
+                }
+                self.doc.blocks.splice(index..index, replacement);
+                self.selection = Selection::collapsed(Position { block_id: heading_id, offset: focus.offset.min(len) });
+            }
+            Block::Table { .. } => return Err(CommandError::NotConvertible("table")),
+            Block::Code { .. } => return Err(CommandError::NotConvertible("code")),
+            Block::Figure { .. } => return Err(CommandError::NotConvertible("figure")),
+            Block::PageBreak { .. } => return Err(CommandError::NotConvertible("page break")),
+            Block::Custom { .. } => return Err(CommandError::NotConvertible("custom")),
+        }
+        Ok(())
+    }
+
+    /// Takes item `item` out of the list at `index` as the block `lifted`
+    /// makes from its id and content, and returns that id. Items before it
+    /// stay in the list; those after it go on in a list of their own.
+    fn lift_list_item(&mut self, index: usize, item: usize, lifted: impl FnOnce(Uuid, Vec<Inline>) -> Block) -> Uuid {
+        let Block::List { id, ordered, mut items, .. } = self.doc.blocks.remove(index) else {
+            unreachable!("callers check for a list");
         };
+        let after = items.split_off(item + 1);
+        let taken = items.pop().expect("the item exists");
+        let mut replacement = Vec::with_capacity(3);
+        let before_empty = items.is_empty();
+        if !before_empty {
+            replacement.push(Block::List { id, ordered, items, dirty: true, locked: false, toc: false });
+        }
+        replacement.push(lifted(taken.id, taken.content));
+        if !after.is_empty() {
+            let id = if before_empty { id } else { Uuid::new_v4() };
+            replacement.push(Block::List { id, ordered, items: after, dirty: true, locked: false, toc: false });
+        }
+        self.doc.blocks.splice(index..index, replacement);
+        taken.id
+    }
+
+    /// Adds `block` at the caret as one undo step and puts the caret at its
+    /// start. It goes after the focused block, or between the halves when
+    /// the caret is inside the text of a paragraph or heading. Without a
+    /// focused block it is appended.
+    fn insert_new_block(&mut self, block: Block) {
+        let focus = self.selection.focus;
+        let target = self.doc.blocks.iter().position(|b| b.id() == focus.block_id).map(|index| {
+            let len = match &self.doc.blocks[index] {
+                text @ (Block::Paragraph { .. } | Block::Heading { .. }) if !text.is_locked() => block_text_len(text),
+                _ => 0,
+            };
+            (index, len)
+        });
+        let at = target.map_or(self.doc.blocks.len(), |(index, _)| index + 1);
+        let count = self.doc.blocks.len();
+        if target.is_some_and(|(_, len)| focus.offset > 0 && focus.offset < len) {
+            self.insert_paragraph_break();
+        }
+        if self.doc.blocks.len() == count {
+            self.checkpoint();
+        }
+        self.selection = Selection::collapsed(Position { block_id: block.id(), offset: 0 });
+        self.doc.blocks.insert(at, block);
     }
 
     fn insert_list(&mut self, ordered: bool) {
         let item = ListItem {
             id: Uuid::new_v4(),
             content: vec![Inline::Text { value: Arc::from("列表项") }],
+            level: 0,
+            checked: None,
         };
-        self.doc.blocks.push(Block::List {
+        self.insert_new_block(Block::List {
             id: Uuid::new_v4(),
             ordered,
             items: vec![item],
             dirty: true,
+            locked: false,
+            toc: false,
         });
     }
 
     fn insert_quote(&mut self, text: String) {
-        self.doc.blocks.push(Block::Quote {
+        self.insert_new_block(Block::Quote {
             id: Uuid::new_v4(),
             content: vec![Block::Paragraph {
                 id: Uuid::new_v4(),
                 content: vec![Inline::Text { value: Arc::from(text) }],
                 dirty: false,
+                locked: false,
             }],
             dirty: true,
+            locked: false,
         });
     }
 
     fn insert_code(&mut self, lang: String, code: String) {
-        self.doc.blocks.push(Block::Code {
+        self.insert_new_block(Block::Code {
             id: Uuid::new_v4(),
             lang: Arc::from(lang),
-            code: Arc::from(code),
+            code: CodeText::from(code),
             dirty: true,
+            locked: false,
         });
     }
 
@@ -284,41 +1647,59 @@ impl Editor {
             }
             table.push(row);
         }
-        self.doc.blocks.push(Block::Table {
+        self.insert_new_block(Block::Table {
             id: Uuid::new_v4(),
             rows: table,
+            header: false,
+            col_widths: Vec::new(),
             dirty: true,
+            locked: false,
         });
     }
 
     fn insert_image(&mut self, url: String) {
-        self.doc.blocks.push(Block::Figure {
+        self.insert_new_block(Block::Figure {
             id: Uuid::new_v4(),
             url: Arc::from(url),
             caption: Some(Arc::from("图片")),
             size: None,
             dirty: true,
+            locked: false,
         });
     }
 
     fn insert_figure(&mut self, url: String, caption: Option<String>) {
-        self.doc.blocks.push(Block::Figure {
+        self.insert_new_block(Block::Figure {
             id: Uuid::new_v4(),
             url: Arc::from(url),
             caption: caption.map(Arc::from),
             size: None,
             dirty: true,
+            locked: false,
         });
     }
 
     fn insert_link(&mut self, url: String, text: String) {
+        let text_len = text.chars().count();
         let link = Inline::Link {
             url: Arc::from(url),
             text: vec![Inline::Text { value: Arc::from(text) }],
         };
-        let block_id = self.selection.focus.block_id;
+        self.insert_at_caret(link, text_len);
+    }
+
+    /// Inserts `link`, `text_len` chars long, at the caret, or appends it to
+    /// the focused block when the caret is not on text.
+    fn insert_at_caret(&mut self, link: Inline, text_len: usize) {
+        let focus = self.selection.focus;
+        let block_id = focus.block_id;
         let mut inserted = false;
         if let Some(block) = self.doc.blocks.iter_mut().find(|b| b.id() == block_id) {
+            if insert_inline(block, focus.offset, link.clone()) {
+                let offset = focus.offset + text_len;
+                self.selection = Selection::collapsed(Position { block_id, offset });
+                return;
+            }
             match block {
                 Block::Paragraph { content, dirty, .. } | Block::Heading { content, dirty, .. } => {
                     content.push(link.clone());
@@ -333,13 +1714,11 @@ impl Editor {
                     }
                 }
                 Block::Quote { content, dirty, .. } => {
-                    if let Some(last) = content.last_mut() {
-                        if let Block::Paragraph { content: para, dirty: p_dirty, .. } = last {
-                            para.push(link.clone());
-                            *p_dirty = true;
-                            *dirty = true;
-                            inserted = true;
-                        }
+                    if let Some(Block::Paragraph { content: para, dirty: p_dirty, .. }) = content.last_mut() {
+                        para.push(link.clone());
+                        *p_dirty = true;
+                        *dirty = true;
+                        inserted = true;
                     }
                 }
                 Block::Table { rows, dirty, .. } => {
@@ -351,7 +1730,7 @@ impl Editor {
                         }
                     }
                 }
-                Block::Code { .. } | Block::Figure { .. } => {}
+                Block::Code { .. } | Block::Figure { .. } | Block::PageBreak { .. } | Block::Custom { .. } => {}
             }
         }
         if !inserted {
@@ -359,6 +1738,7 @@ impl Editor {
                 id: Uuid::new_v4(),
                 content: vec![link],
                 dirty: true,
+                locked: false,
             });
         }
     }
@@ -387,93 +1767,293 @@ impl Editor {
         }
     }
 
-    fn list_indent(&mut self, indent: bool) {
-        let block_id = self.selection.focus.block_id;
-        if let Some(block) = self.doc.blocks.iter_mut().find(|b| b.id() == block_id) {
-            if let Block::List { items, dirty, .. } = block {
-                for item in items.iter_mut() {
-                    if let Some(first) = item.content.get_mut(0) {
-                        match first {
-                            Inline::Text { value } => {
-                                let mut s = value.as_ref().to_string();
-                                if indent {
-                                    s = format!("  {}", s);
-                                } else if s.starts_with("  ") {
-                                    s = s.trim_start_matches("  ").to_string();
-                                }
-                                *value = Arc::from(s);
-                            }
-                            _ => {
-                                if indent {
-                                    item.content.insert(0, Inline::Text { value: Arc::from("  ") });
-                                }
-                            }
-                        }
-                    } else if indent {
-                        item.content.push(Inline::Text { value: Arc::from("  ") });
-                    }
-                }
-                *dirty = true;
-            }
+    /// Index of the list item holding the caret, when the caret is in a
+    /// list.
+    pub fn focused_list_item(&self) -> Option<usize> {
+        let focus = self.selection.focus;
+        match self.doc.blocks.iter().find(|b| b.id() == focus.block_id)? {
+            Block::List { items, .. } => Some(list_item_at(items, focus.offset).0),
+            _ => None,
+        }
+    }
+
+    /// Block index, item index and new level for indenting or outdenting an
+    /// item, or None when the item cannot move that way.
+    fn list_level_change(&self, block_id: Option<Uuid>, item: Option<usize>, indent: bool) -> Option<(usize, usize, u8)> {
+        let block_id = block_id.unwrap_or(self.selection.focus.block_id);
+        let at = self.doc.blocks.iter().position(|b| b.id() == block_id)?;
+        let Block::List { items, .. } = &self.doc.blocks[at] else {
+            return None;
+        };
+        let idx = match item {
+            Some(idx) => idx,
+            None if self.selection.focus.block_id == block_id => list_item_at(items, self.selection.focus.offset).0,
+            None => return None,
+        };
+        let level = items.get(idx)?.level;
+        if indent {
+            let prev = items.get(idx.checked_sub(1)?)?;
+            (level <= prev.level && level < MAX_LIST_LEVEL).then(|| (at, idx, level + 1))
+        } else {
+            Some((at, idx, level.checked_sub(1)?))
+        }
+    }
+
+    fn set_list_level(&mut self, at: usize, item: usize, level: u8) {
+        if let Block::List { items, dirty, .. } = &mut self.doc.blocks[at] {
+            items[item].level = level;
+            *dirty = true;
         }
     }
 
+    /// Points every link to `url` in the block at `new_url`, or unwraps the
+    /// links into plain inlines when `new_url` is `None`.
+    fn relink_in_block(block: &mut Block, url: &str, new_url: Option<&str>) -> bool {
+        let changed = match block {
+            Block::Paragraph { content, .. } | Block::Heading { content, .. } => relink_inlines(content, url, new_url),
+            Block::List { items, .. } => items
+                .iter_mut()
+                .fold(false, |acc, item| relink_inlines(&mut item.content, url, new_url) | acc),
+            Block::Quote { content, .. } => content
+                .iter_mut()
+                .fold(false, |acc, inner| Self::relink_in_block(inner, url, new_url) | acc),
+            Block::Table { rows, .. } => rows
+                .iter_mut()
+                .flatten()
+                .fold(false, |acc, cell| relink_inlines(&mut cell.content, url, new_url) | acc),
+            Block::Code { .. } | Block::Figure { .. } | Block::PageBreak { .. } | Block::Custom { .. } => false,
+        };
+        if changed {
+            block.set_dirty(true);
+        }
+        changed
+    }
+
+    /// The table holding the selection focus, else the last table.
     fn last_table_mut(&mut self) -> Option<&mut Block> {
-        self.doc.blocks.iter_mut().rev().find(|b| matches!(b, Block::Table { .. }))
-    }
-
-    fn undo(&mut self) {
-        if let Some(entry) = self.history.pop_undo() {
-            match entry {
-                HistoryEntry::Snapshot(snapshot) => {
-                    let current = HistoryEntry::Snapshot(self.snapshot());
-                    self.history.push_redo(current);
-                    self.doc = snapshot.doc;
-                    self.selection = snapshot.selection;
-                }
-                HistoryEntry::BlockChange { block_id, before, after, selection_before, selection_after } => {
-                    let current = HistoryEntry::BlockChange {
-                        block_id,
-                        before: after.clone(),
-                        after: before.clone(),
-                        selection_before: selection_after,
-                        selection_after: selection_before,
-                    };
-                    self.history.push_redo(current);
-                    if let Some(pos) = self.doc.blocks.iter().position(|b| b.id() == block_id) {
-                        self.doc.blocks[pos] = before;
-                    }
-                    self.selection = selection_before;
+        let idx = self.last_table_index()?;
+        self.doc.blocks.get_mut(idx)
+    }
+
+    fn last_table_index(&self) -> Option<usize> {
+        let focus = self.selection.focus.block_id;
+        self.doc
+            .blocks
+            .iter()
+            .position(|b| b.id() == focus && matches!(b, Block::Table { .. }))
+            .or_else(|| self.doc.blocks.iter().rposition(|b| matches!(b, Block::Table { .. })))
+    }
+
+    /// The existing block `cmd` edits, which must not be locked. Commands
+    /// that only add blocks have none; replace-all checks block by block.
+    fn edit_target(&self, cmd: &EditorCommand) -> Option<Uuid> {
+        let focus = self.selection.focus.block_id;
+        match cmd {
+            EditorCommand::InsertText(_) | EditorCommand::InsertParagraphBreak | EditorCommand::SplitListItem | EditorCommand::DeleteSelection | EditorCommand::DeleteWordBackward | EditorCommand::DeleteWordForward | EditorCommand::SetHeading(_) | EditorCommand::ConvertBlock(_) | EditorCommand::ToggleList(_) | EditorCommand::ToggleTaskItem | EditorCommand::SetTaskState(_) | EditorCommand::InsertLink { .. } | EditorCommand::InsertMath { .. } => Some(focus),
+            EditorCommand::ApplyStyle(_) => (!self.selection.is_collapsed()).then_some(focus),
+            EditorCommand::ClearFormatting => Some(focus),
+            EditorCommand::ListIndent { block_id: None, .. } | EditorCommand::ListOutdent { block_id: None, .. } => Some(focus),
+            EditorCommand::MoveBlockUp | EditorCommand::MoveBlockDown | EditorCommand::DeleteBlock { block_id: None } => Some(focus),
+            EditorCommand::TableInsertRow | EditorCommand::TableInsertColumn | EditorCommand::TableDeleteRow | EditorCommand::TableDeleteColumn => {
+                self.last_table_index().map(|idx| self.doc.blocks[idx].id())
+            }
+            EditorCommand::SetBlockLocked { .. } => None,
+            _ => cmd.block_id(),
+        }
+    }
+
+    /// Swaps in a snapshot's blocks. The version never goes back, so
+    /// version-gated relayout still fires, and only blocks that differ from
+    /// what was on screen are marked dirty.
+    fn restore_snapshot(&mut self, snapshot: Snapshot) {
+        let shown: HashMap<Uuid, (u64, bool)> =
+            self.doc.blocks.iter().map(|b| (b.id(), (hash_block(b), b.is_effectively_dirty()))).collect();
+        let version = self.doc.version;
+        self.doc = snapshot.doc.to_document();
+        self.doc.version = version;
+        for block in &mut self.doc.blocks {
+            let dirty = match shown.get(&block.id()) {
+                Some(&(hash, dirty)) if hash == hash_block(block) => dirty,
+                _ => true,
+            };
+            block.set_dirty(dirty);
+        }
+        self.selection = snapshot.selection;
+    }
+
+    fn undo(&mut self) -> bool {
+        let Some(entry) = self.history.pop_undo() else {
+            return false;
+        };
+        match entry {
+            HistoryEntry::Snapshot(snapshot) => {
+                let current = HistoryEntry::Snapshot(self.snapshot());
+                self.history.push_redo(current);
+                self.restore_snapshot(snapshot);
+            }
+            HistoryEntry::BlockChange { block_id, before, after, selection_before, selection_after } => {
+                self.history.push_redo(HistoryEntry::BlockChange {
+                    block_id,
+                    before: before.clone(),
+                    after: after.clone(),
+                    selection_before,
+                    selection_after,
+                });
+                if let Some(pos) = self.doc.blocks.iter().position(|b| b.id() == block_id) {
+                    self.doc.blocks[pos] = before;
+                    self.doc.blocks[pos].set_dirty(true);
+                }
+                self.selection = selection_before;
+            }
+            HistoryEntry::InlineEdit { block_id, path, range, removed, inserted, selections } => {
+                if let Some(block) = self.doc.blocks.iter_mut().find(|b| b.id() == block_id) {
+                    splice_text_leaf(block, &path, range.start..range.start + inserted.len(), &removed);
                 }
+                self.selection = selections.0;
+                self.history.push_redo(HistoryEntry::InlineEdit { block_id, path, range, removed, inserted, selections });
             }
         }
+        true
     }
 
-    fn redo(&mut self) {
-        if let Some(entry) = self.history.pop_redo() {
-            match entry {
-                HistoryEntry::Snapshot(snapshot) => {
-                    let current = HistoryEntry::Snapshot(self.snapshot());
-                    self.history.push_undo(current);
-                    self.doc = snapshot.doc;
-                    self.selection = snapshot.selection;
+    fn redo(&mut self) -> bool {
+        let Some(entry) = self.history.pop_redo() else {
+            return false;
+        };
+        match entry {
+            HistoryEntry::Snapshot(snapshot) => {
+                let current = HistoryEntry::Snapshot(self.snapshot());
+                self.history.push_undo(current);
+                self.restore_snapshot(snapshot);
+            }
+            HistoryEntry::BlockChange { block_id, before, after, selection_before, selection_after } => {
+                self.history.push_undo(HistoryEntry::BlockChange {
+                    block_id,
+                    before: before.clone(),
+                    after: after.clone(),
+                    selection_before,
+                    selection_after,
+                });
+                if let Some(pos) = self.doc.blocks.iter().position(|b| b.id() == block_id) {
+                    self.doc.blocks[pos] = after;
+                    self.doc.blocks[pos].set_dirty(true);
                 }
-                HistoryEntry::BlockChange { block_id, before, after, selection_before, selection_after } => {
-                    let current = HistoryEntry::BlockChange {
-                        block_id,
-                        before: after.clone(),
-                        after: before.clone(),
-                        selection_before: selection_after,
-                        selection_after: selection_before,
-                    };
-                    self.history.push_undo(current);
-                    if let Some(pos) = self.doc.blocks.iter().position(|b| b.id() == block_id) {
-                        self.doc.blocks[pos] = after;
-                    }
-                    self.selection = selection_after;
+                self.selection = selection_after;
+            }
+            HistoryEntry::InlineEdit { block_id, path, range, removed, inserted, selections } => {
+                if let Some(block) = self.doc.blocks.iter_mut().find(|b| b.id() == block_id) {
+                    splice_text_leaf(block, &path, range.clone(), &inserted);
                 }
+                self.selection = selections.1;
+                self.history.push_undo(HistoryEntry::InlineEdit { block_id, path, range, removed, inserted, selections });
             }
         }
+        true
+    }
+}
+
+/// `data:` URI carrying `bytes`, which the layout and texture loaders read
+/// like any other figure url.
+pub fn image_data_uri(mime: &str, bytes: &[u8]) -> String {
+    use base64::Engine as _;
+    let mime = if mime.trim().is_empty() { "application/octet-stream" } else { mime.trim() };
+    format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// Byte range of `old` that differs from `new` once the common prefix and
+/// suffix are trimmed, on char boundaries of both.
+fn changed_range(old: &str, new: &str) -> Range<usize> {
+    let prefix: usize = old
+        .chars()
+        .zip(new.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+    let suffix: usize = old[prefix..]
+        .chars()
+        .rev()
+        .zip(new[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+    prefix..old.len() - suffix
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MarkdownShortcut {
+    Heading(u8),
+    List { ordered: bool },
+    Quote,
+    Code,
+}
+
+impl MarkdownShortcut {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "- " | "* " => return Some(MarkdownShortcut::List { ordered: false }),
+            "1. " => return Some(MarkdownShortcut::List { ordered: true }),
+            "> " => return Some(MarkdownShortcut::Quote),
+            "```" => return Some(MarkdownShortcut::Code),
+            _ => {}
+        }
+        let hashes = text.strip_suffix(' ')?;
+        if (1..=6).contains(&hashes.len()) && hashes.bytes().all(|b| b == b'#') {
+            return Some(MarkdownShortcut::Heading(hashes.len() as u8));
+        }
+        None
+    }
+
+    fn block(self, id: Uuid) -> Block {
+        match self {
+            MarkdownShortcut::Heading(level) => Block::Heading { id, level, content: Vec::new(), dirty: true, locked: false },
+            MarkdownShortcut::List { ordered } => Block::List {
+                id,
+                ordered,
+                items: vec![ListItem { id: Uuid::new_v4(), content: Vec::new(), level: 0, checked: None }],
+                dirty: true,
+                locked: false,
+                toc: false,
+            },
+            MarkdownShortcut::Quote => Block::Quote {
+                id,
+                content: vec![Block::Paragraph { id: Uuid::new_v4(), content: Vec::new(), dirty: true, locked: false }],
+                dirty: true,
+                locked: false,
+            },
+            MarkdownShortcut::Code => Block::Code { id, lang: Arc::from(""), code: CodeText::default(), dirty: true, locked: false },
+        }
     }
 }
 
+fn relink_inlines(inlines: &mut Vec<Inline>, url: &str, new_url: Option<&str>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < inlines.len() {
+        let unwrap = match &mut inlines[i] {
+            Inline::Link { url: link_url, text } if link_url.as_ref() == url => {
+                changed = true;
+                match new_url {
+                    Some(new_url) => {
+                        *link_url = Arc::from(new_url);
+                        None
+                    }
+                    None => Some(std::mem::take(text)),
+                }
+            }
+            Inline::Link { text: content, .. } | Inline::Styled { content, .. } => {
+                changed |= relink_inlines(content, url, new_url);
+                None
+            }
+            Inline::Text { .. } | Inline::CodeSpan { .. } | Inline::Math { .. } => None,
+        };
+        match unwrap {
+            Some(text) => {
+                let len = text.len();
+                inlines.splice(i..i + 1, text);
+                i += len;
+            }
+            None => i += 1,
+        }
+    }
+    changed
+}
@@ -0,0 +1,86 @@
+use serde_json::error::Category;
+
+/// Error returned by the import and export functions of this crate.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The input is not valid `format`.
+    #[error("cannot parse {format}: {detail}")]
+    Parse { format: String, detail: String },
+    /// No importer handles this file extension.
+    #[error("unsupported format: {0}")]
+    Unsupported(String),
+    #[error("cannot export {format}: {detail}")]
+    Export { format: String, detail: String },
+    /// The input parsed but does not describe a document.
+    #[error("invalid document: {0}")]
+    Validation(String),
+    /// Saving to `path` failed; the file there was left as it was.
+    #[error("cannot write {path}: {detail}")]
+    Write { path: String, detail: String },
+    /// The saved file did not read back as a document, so it did not
+    /// replace the one at `path`.
+    #[error("{path} did not read back after saving: {detail}")]
+    Verify { path: String, detail: String },
+}
+
+impl Error {
+    pub(crate) fn parse(format: &str, detail: impl ToString) -> Self {
+        Error::Parse { format: format.to_string(), detail: detail.to_string() }
+    }
+
+    pub(crate) fn export(format: &str, detail: impl ToString) -> Self {
+        Error::Export { format: format.to_string(), detail: detail.to_string() }
+    }
+
+    pub(crate) fn write(path: &std::path::Path, detail: impl ToString) -> Self {
+        Error::Write { path: path.display().to_string(), detail: detail.to_string() }
+    }
+
+    pub(crate) fn verify(path: &std::path::Path, detail: impl ToString) -> Self {
+        Error::Verify { path: path.display().to_string(), detail: detail.to_string() }
+    }
+
+    /// Stable name of the variant, for callers that report errors as data.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io",
+            Error::Parse { .. } => "parse",
+            Error::Unsupported(_) => "unsupported",
+            Error::Export { .. } => "export",
+            Error::Validation(_) => "validation",
+            Error::Write { .. } => "write",
+            Error::Verify { .. } => "verify",
+        }
+    }
+
+    /// Process exit code for command line tools; 2 stays free for usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io(_) => 3,
+            Error::Parse { .. } => 4,
+            Error::Unsupported(_) => 5,
+            Error::Export { .. } => 6,
+            Error::Validation(_) => 7,
+            // Failed saves share the codes of the failures they come from.
+            Error::Write { .. } => 3,
+            Error::Verify { .. } => 6,
+        }
+    }
+}
+
+/// Syntax errors are `Parse`, well-formed JSON of the wrong shape is
+/// `Validation`.
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        match err.classify() {
+            Category::Io => Error::Io(err.into()),
+            Category::Syntax | Category::Eof => Error::parse("json", err),
+            Category::Data => Error::Validation(err.to_string()),
+        }
+    }
+}
+
+#[deprecated(note = "use wa_core::Error")]
+pub type ImportError = Error;
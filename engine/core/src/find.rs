@@ -0,0 +1,396 @@
+use crate::{block_plain_text, to_nfc, Block, CodeText, Document, Inline, SharedStr};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// What find and replace look at. The default covers prose only: code
+/// blocks, inline code, math and link URLs are opt-in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FindOptions {
+    pub case_insensitive: bool,
+    pub include_code: bool,
+    pub include_code_spans: bool,
+    /// The TeX of inline math.
+    pub include_math: bool,
+    pub include_urls: bool,
+    /// Block types to search, as `Block::type_name` gives them; empty
+    /// searches all.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub block_types: Vec<String>,
+    /// Top-level blocks to search; `None` searches the whole document.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<FindScope>,
+}
+
+/// A set of blocks to search, e.g. the blocks of one chapter. Serializes
+/// as `{"blockIds": [...]}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FindScope {
+    pub block_ids: Vec<Uuid>,
+}
+
+impl FindOptions {
+    /// Whether the block filters let `block` be searched at all.
+    pub fn allows(&self, block: &Block) -> bool {
+        (self.block_types.is_empty() || self.block_types.iter().any(|t| t == block.type_name()))
+            && self.scope.as_ref().is_none_or(|scope| scope.block_ids.contains(&block.id()))
+    }
+}
+
+/// A hit in `block_plain_text`, as char offsets. Hits in a link URL carry
+/// the link's index among the block's links (in document order) and are
+/// offsets into that URL instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FindMatch {
+    pub block_id: Uuid,
+    pub block_index: usize,
+    pub start: usize,
+    pub end: usize,
+    pub link: Option<usize>,
+}
+
+pub fn find_in_doc(doc: &Document, query: &str, options: FindOptions) -> Vec<FindMatch> {
+    let mut out = Vec::new();
+    if query.is_empty() {
+        return out;
+    }
+    for (block_index, block) in doc.blocks.iter().enumerate().filter(|(_, b)| options.allows(b)) {
+        for (start, end) in find_in_block(block, query, &options) {
+            out.push(FindMatch { block_id: block.id(), block_index, start, end, link: None });
+        }
+        push_url_matches(block, block_index, query, &options, &mut out);
+    }
+    out
+}
+
+/// Matches in the block text, leaving out whatever `options` excludes.
+/// The block filters are the caller's to apply.
+pub fn find_in_block(block: &Block, query: &str, options: &FindOptions) -> Vec<(usize, usize)> {
+    match block {
+        Block::Code { .. } if !options.include_code => Vec::new(),
+        Block::Code { code, .. } if !query.contains('\n') => find_in_code(code, query, options),
+        _ => without_excluded_inlines(block, match_ranges(&block_plain_text(block), query, options), options),
+    }
+}
+
+/// Drops ranges touching inline code or math unless `options` includes it.
+pub(crate) fn without_excluded_inlines(block: &Block, ranges: Vec<(usize, usize)>, options: &FindOptions) -> Vec<(usize, usize)> {
+    if ranges.is_empty() {
+        return ranges;
+    }
+    let spans = excluded_inline_ranges(block, options);
+    if spans.is_empty() {
+        return ranges;
+    }
+    ranges.into_iter().filter(|&(s, e)| !spans.iter().any(|&(a, b)| s < b && e > a)).collect()
+}
+
+/// Appends hits inside link URLs when `options` asks for them.
+pub(crate) fn push_url_matches(block: &Block, block_index: usize, query: &str, options: &FindOptions, out: &mut Vec<FindMatch>) {
+    if !options.include_urls {
+        return;
+    }
+    for (link, url) in link_urls(block).into_iter().enumerate() {
+        for (start, end) in match_ranges(url, query, options) {
+            out.push(FindMatch { block_id: block.id(), block_index, start, end, link: Some(link) });
+        }
+    }
+}
+
+/// Line by line, so large code blocks are searched without flattening.
+/// Only valid for queries that cannot span a line break.
+fn find_in_code(code: &CodeText, query: &str, options: &FindOptions) -> Vec<(usize, usize)> {
+    let mut out = Vec::new();
+    let mut offset = 0;
+    for line in code.lines_with_endings() {
+        out.extend(match_ranges(&line, query, options).into_iter().map(|(s, e)| (offset + s, offset + e)));
+        offset += line.chars().count();
+    }
+    out
+}
+
+/// Non-overlapping matches of `query` in `text`, as char ranges. The query
+/// is composed to NFC first, the form the editor stores typed text in.
+pub fn match_ranges(text: &str, query: &str, options: &FindOptions) -> Vec<(usize, usize)> {
+    let query = to_nfc(query);
+    let query = query.as_ref();
+    if options.case_insensitive {
+        char_ranges_of(&fold_case(text), &fold_case(query))
+    } else {
+        char_ranges_of(text, query)
+    }
+}
+
+/// Lowercases one char per char, so char offsets into the folded text are
+/// offsets into the original.
+pub fn fold_case(text: &str) -> String {
+    text.chars().map(fold_char).collect()
+}
+
+pub(crate) fn fold_char(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Non-overlapping occurrences of `needle` in `hay`, left to right, as char
+/// ranges. Both must already be folded the same way.
+pub(crate) fn char_ranges_of(hay: &str, needle: &str) -> Vec<(usize, usize)> {
+    let mut out = Vec::new();
+    if needle.is_empty() {
+        return out;
+    }
+    let needle_chars = needle.chars().count();
+    let mut chars = 0;
+    let mut last = 0;
+    for (byte, _) in hay.match_indices(needle) {
+        chars += hay[last..byte].chars().count();
+        out.push((chars, chars + needle_chars));
+        chars += needle_chars;
+        last = byte + needle.len();
+    }
+    out
+}
+
+/// Replaces the char range `start..end` of the block's plain text. The
+/// replacement takes the formatting of the text where the range starts.
+pub fn replace_range_in_block(block: &mut Block, start: usize, end: usize, replacement: &str) -> bool {
+    if let Block::Code { code, dirty, .. } = block {
+        let end = end.min(code.char_len());
+        if start > end {
+            return false;
+        }
+        let range = code.char_to_byte(start)..code.char_to_byte(end);
+        code.splice(range, replacement);
+        *dirty = true;
+        return true;
+    }
+    let mut inserted = false;
+    let mut changed = false;
+    visit_leaves_mut(block, &mut 0, &mut |leaf_start, value| {
+        let len = value.chars().count();
+        let leaf_end = leaf_start + len;
+        let insert_here = !inserted
+            && start >= leaf_start
+            && (start < leaf_end || (start == leaf_end && start == end));
+        let overlaps = start < leaf_end && end > leaf_start;
+        if !insert_here && !overlaps {
+            return;
+        }
+        let s = start.max(leaf_start) - leaf_start;
+        let e = end.min(leaf_end).max(start.max(leaf_start)) - leaf_start;
+        let s_b = crate::char_to_byte_idx(value, s);
+        let e_b = crate::char_to_byte_idx(value, e);
+        let mut next = String::with_capacity(value.len() + replacement.len());
+        next.push_str(&value[..s_b]);
+        if insert_here {
+            next.push_str(replacement);
+            inserted = true;
+        }
+        next.push_str(&value[e_b..]);
+        *value = Arc::from(next);
+        changed = true;
+    });
+    if changed {
+        crate::normalize_block(block);
+        block.set_dirty(true);
+    }
+    changed
+}
+
+/// Replaces every match in `block`, counting them. A block the filters
+/// leave out is left alone.
+pub fn replace_all_in_block(block: &mut Block, query: &str, replacement: &str, options: &FindOptions) -> usize {
+    if !options.allows(block) {
+        return 0;
+    }
+    let ranges = find_in_block(block, query, options);
+    for (start, end) in ranges.iter().rev() {
+        replace_range_in_block(block, *start, *end, replacement);
+    }
+    let mut count = ranges.len();
+    if options.include_urls {
+        let in_text = count;
+        visit_urls_mut(block, &mut |url| {
+            let hits = match_ranges(url, query, options);
+            if hits.is_empty() {
+                return;
+            }
+            let mut next = url.to_string();
+            for (start, end) in hits.iter().rev() {
+                let range = crate::char_to_byte_idx(&next, *start)..crate::char_to_byte_idx(&next, *end);
+                next.replace_range(range, replacement);
+            }
+            *url = Arc::from(next);
+            count += hits.len();
+        });
+        if count > in_text {
+            block.set_dirty(true);
+        }
+    }
+    count
+}
+
+/// Char ranges in `block_plain_text(block)` of the inline code and math
+/// that `options` leaves out of the search.
+pub(crate) fn excluded_inline_ranges(block: &Block, options: &FindOptions) -> Vec<(usize, usize)> {
+    fn inlines(list: &[Inline], options: &FindOptions, pos: &mut usize, out: &mut Vec<(usize, usize)>) {
+        for inline in list {
+            match inline {
+                Inline::Text { value } => *pos += value.chars().count(),
+                Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => {
+                    let len = value.chars().count();
+                    let included = match inline {
+                        Inline::CodeSpan { .. } => options.include_code_spans,
+                        _ => options.include_math,
+                    };
+                    if !included {
+                        out.push((*pos, *pos + len));
+                    }
+                    *pos += len;
+                }
+                Inline::Styled { content, .. } | Inline::Link { text: content, .. } => inlines(content, options, pos, out),
+            }
+        }
+    }
+    fn walk(block: &Block, options: &FindOptions, pos: &mut usize, out: &mut Vec<(usize, usize)>) {
+        match block {
+            Block::Heading { content, .. } | Block::Paragraph { content, .. } => inlines(content, options, pos, out),
+            Block::List { items, .. } => {
+                for (idx, item) in items.iter().enumerate() {
+                    *pos += usize::from(idx > 0);
+                    inlines(&item.content, options, pos, out);
+                }
+            }
+            Block::Quote { content, .. } => {
+                for (idx, inner) in content.iter().enumerate() {
+                    *pos += usize::from(idx > 0);
+                    walk(inner, options, pos, out);
+                }
+            }
+            Block::Table { rows, .. } => {
+                for (ri, row) in rows.iter().enumerate() {
+                    *pos += usize::from(ri > 0);
+                    for (ci, cell) in row.iter().enumerate() {
+                        *pos += usize::from(ci > 0);
+                        inlines(&cell.content, options, pos, out);
+                    }
+                }
+            }
+            Block::Code { .. } | Block::Figure { .. } | Block::PageBreak { .. } | Block::Custom { .. } => {}
+        }
+    }
+    let mut out = Vec::new();
+    if !(options.include_code_spans && options.include_math) {
+        walk(block, options, &mut 0, &mut out);
+    }
+    out
+}
+
+/// Link URLs of a block in document order, as `FindMatch::link` counts them.
+pub fn link_urls(block: &Block) -> Vec<&str> {
+    fn inlines<'a>(list: &'a [Inline], out: &mut Vec<&'a str>) {
+        for inline in list {
+            match inline {
+                Inline::Link { url, text } => {
+                    out.push(url);
+                    inlines(text, out);
+                }
+                Inline::Styled { content, .. } => inlines(content, out),
+                Inline::Text { .. } | Inline::CodeSpan { .. } | Inline::Math { .. } => {}
+            }
+        }
+    }
+    let mut out = Vec::new();
+    for_each_inline_list(block, &mut |list| inlines(list, &mut out));
+    out
+}
+
+fn visit_urls_mut(block: &mut Block, f: &mut dyn FnMut(&mut SharedStr)) {
+    fn inlines(list: &mut [Inline], f: &mut dyn FnMut(&mut SharedStr)) {
+        for inline in list {
+            match inline {
+                Inline::Link { url, text } => {
+                    f(url);
+                    inlines(text, f);
+                }
+                Inline::Styled { content, .. } => inlines(content, f),
+                Inline::Text { .. } | Inline::CodeSpan { .. } | Inline::Math { .. } => {}
+            }
+        }
+    }
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => inlines(content, f),
+        Block::List { items, .. } => items.iter_mut().for_each(|item| inlines(&mut item.content, f)),
+        Block::Quote { content, .. } => content.iter_mut().for_each(|inner| visit_urls_mut(inner, f)),
+        Block::Table { rows, .. } => rows.iter_mut().flatten().for_each(|cell| inlines(&mut cell.content, f)),
+        Block::Code { .. } | Block::Figure { .. } | Block::PageBreak { .. } | Block::Custom { .. } => {}
+    }
+}
+
+pub(crate) fn for_each_inline_list<'a>(block: &'a Block, f: &mut dyn FnMut(&'a [Inline])) {
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => f(content),
+        Block::List { items, .. } => items.iter().for_each(|item| f(&item.content)),
+        Block::Quote { content, .. } => content.iter().for_each(|inner| for_each_inline_list(inner, f)),
+        Block::Table { rows, .. } => rows.iter().flatten().for_each(|cell| f(&cell.content)),
+        Block::Code { .. } | Block::Figure { .. } | Block::PageBreak { .. } | Block::Custom { .. } => {}
+    }
+}
+
+/// Visits every text leaf in plain-text order with its char offset, counting
+/// the separators `block_plain_text` inserts. Code blocks are edited through
+/// their own `CodeText`.
+pub(crate) fn visit_leaves_mut(block: &mut Block, pos: &mut usize, f: &mut dyn FnMut(usize, &mut SharedStr)) {
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => visit_inlines_mut(content, pos, f),
+        Block::List { items, .. } => {
+            for (idx, item) in items.iter_mut().enumerate() {
+                if idx > 0 {
+                    *pos += 1;
+                }
+                visit_inlines_mut(&mut item.content, pos, f);
+            }
+        }
+        Block::Quote { content, .. } => {
+            for (idx, inner) in content.iter_mut().enumerate() {
+                if idx > 0 {
+                    *pos += 1;
+                }
+                visit_leaves_mut(inner, pos, f);
+            }
+        }
+        Block::Code { code, .. } => *pos += code.char_len(),
+        Block::Table { rows, .. } => {
+            for (ri, row) in rows.iter_mut().enumerate() {
+                if ri > 0 {
+                    *pos += 1;
+                }
+                for (ci, cell) in row.iter_mut().enumerate() {
+                    if ci > 0 {
+                        *pos += 1;
+                    }
+                    visit_inlines_mut(&mut cell.content, pos, f);
+                }
+            }
+        }
+        Block::Figure { caption: Some(caption), .. } => visit_leaf(caption, pos, f),
+        Block::Figure { caption: None, .. } | Block::PageBreak { .. } | Block::Custom { .. } => {}
+    }
+}
+
+fn visit_inlines_mut(inlines: &mut [Inline], pos: &mut usize, f: &mut dyn FnMut(usize, &mut SharedStr)) {
+    for inline in inlines {
+        match inline {
+            Inline::Text { value } | Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => visit_leaf(value, pos, f),
+            Inline::Link { text, .. } => visit_inlines_mut(text, pos, f),
+            Inline::Styled { content, .. } => visit_inlines_mut(content, pos, f),
+        }
+    }
+}
+
+fn visit_leaf(value: &mut SharedStr, pos: &mut usize, f: &mut dyn FnMut(usize, &mut SharedStr)) {
+    let len = value.chars().count();
+    f(*pos, value);
+    *pos += len;
+}
@@ -0,0 +1,160 @@
+use crate::{block_text_len, char_slice, inline_plain_text, refresh_ids, validate_doc, Block, CodeText, Document, Error, Inline, SharedStr};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Version of the snippet format `export_fragment` writes.
+pub const FRAGMENT_SCHEMA: u32 = 1;
+
+/// Blocks shared on their own between documents, such as a styled warning
+/// box or a standard table. Serializes as `{"fragment": 1, "blocks": [...]}`
+/// with the blocks in the document JSON schema.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fragment {
+    /// Schema version; newer ones than `FRAGMENT_SCHEMA` are refused.
+    #[serde(rename = "fragment")]
+    pub schema: u32,
+    /// The document the blocks were copied from, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Uuid>,
+    pub blocks: Vec<Block>,
+}
+
+impl Fragment {
+    pub fn new(blocks: Vec<Block>, source: Option<Uuid>) -> Self {
+        Self { schema: FRAGMENT_SCHEMA, source, blocks }
+    }
+
+    pub fn to_json(&self) -> String {
+        // Blocks hold nothing serde_json cannot write.
+        serde_json::to_string_pretty(self).expect("fragment serializes")
+    }
+
+    /// Parses a fragment and makes its blocks ready to insert: every block
+    /// and list item gets a fresh id, values layout cannot use are
+    /// repaired as on import (see `validate_doc`), and all are marked dirty.
+    pub fn from_json(raw: &str) -> Result<Fragment, Error> {
+        let mut fragment: Fragment = serde_json::from_str(raw)?;
+        if fragment.schema > FRAGMENT_SCHEMA {
+            return Err(Error::Validation(format!("fragment schema {} is newer than {}", fragment.schema, FRAGMENT_SCHEMA)));
+        }
+        let mut doc = Document { blocks: std::mem::take(&mut fragment.blocks), ..Document::new() };
+        validate_doc(&mut doc);
+        doc.for_each_block_mut(&mut |block| {
+            refresh_ids(block);
+            block.set_dirty(true);
+        });
+        fragment.blocks = doc.blocks;
+        Ok(fragment)
+    }
+}
+
+/// `blocks` as snippet JSON, see `Fragment`.
+pub fn export_fragment(blocks: &[Block]) -> String {
+    Fragment::new(blocks.to_vec(), None).to_json()
+}
+
+/// The blocks of a snippet made by `export_fragment`, with fresh ids.
+pub fn import_fragment(raw: &str) -> Result<Vec<Block>, Error> {
+    Fragment::from_json(raw).map(|fragment| fragment.blocks)
+}
+
+/// The inlines covering chars `start..end` of their flattened text. Styles
+/// and links wrapping a cut piece are kept around it.
+pub fn slice_inlines(inlines: &[Inline], start: usize, end: usize) -> Vec<Inline> {
+    let mut out = Vec::new();
+    slice_into(inlines, start, end, &mut 0, &mut out);
+    out
+}
+
+fn slice_into(inlines: &[Inline], start: usize, end: usize, pos: &mut usize, out: &mut Vec<Inline>) {
+    for inline in inlines {
+        if *pos >= end {
+            return;
+        }
+        match inline {
+            Inline::Text { value } | Inline::CodeSpan { value } => {
+                let len = value.chars().count();
+                let (from, to) = (start.saturating_sub(*pos).min(len), end.saturating_sub(*pos).min(len));
+                if from < to {
+                    let value = if to - from == len { value.clone() } else { SharedStr::from(char_slice(value, from, to)) };
+                    out.push(match inline {
+                        Inline::CodeSpan { .. } => Inline::CodeSpan { value },
+                        _ => Inline::Text { value },
+                    });
+                }
+                *pos += len;
+            }
+            // Half a formula is not TeX, so math is copied whole.
+            Inline::Math { tex, .. } => {
+                let len = tex.chars().count();
+                if start < *pos + len && end > *pos {
+                    out.push(inline.clone());
+                }
+                *pos += len;
+            }
+            Inline::Link { url, text } => {
+                let mut inner = Vec::new();
+                slice_into(text, start, end, pos, &mut inner);
+                if !inner.is_empty() {
+                    out.push(Inline::Link { url: url.clone(), text: inner });
+                }
+            }
+            Inline::Styled { style, content } => {
+                let mut inner = Vec::new();
+                slice_into(content, start, end, pos, &mut inner);
+                if !inner.is_empty() {
+                    out.push(Inline::Styled { style: *style, content: inner });
+                }
+            }
+        }
+    }
+}
+
+/// `block` cut down to chars `start..end` of its `block_plain_text`. List
+/// items and quoted blocks outside the range are dropped and the ones at
+/// its ends cut; tables, figures and custom blocks are kept whole.
+pub fn slice_block(block: &Block, start: usize, end: usize) -> Block {
+    let mut out = block.clone();
+    match &mut out {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => *content = slice_inlines(content, start, end),
+        Block::List { items, .. } => {
+            let mut pos = 0;
+            let mut text = String::new();
+            items.retain_mut(|item| {
+                text.clear();
+                inline_plain_text(&item.content, &mut text);
+                let len = text.chars().count();
+                let keep = overlaps(pos, len, start, end);
+                if keep {
+                    item.content = slice_inlines(&item.content, start.saturating_sub(pos), end - pos);
+                }
+                pos += len + 1;
+                keep
+            });
+        }
+        Block::Quote { content, .. } => {
+            let mut pos = 0;
+            let mut kept = Vec::new();
+            for inner in content.iter() {
+                let len = block_text_len(inner);
+                if overlaps(pos, len, start, end) {
+                    kept.push(slice_block(inner, start.saturating_sub(pos), end - pos));
+                }
+                pos += len + 1;
+            }
+            *content = kept;
+        }
+        Block::Code { code, .. } => {
+            let (from, to) = (code.char_to_byte(start.min(code.char_len())), code.char_to_byte(end.min(code.char_len())));
+            *code = CodeText::from(code.slice(from..to).unwrap_or_default().as_str());
+        }
+        Block::Table { .. } | Block::Figure { .. } | Block::PageBreak { .. } | Block::Custom { .. } => {}
+    }
+    out
+}
+
+/// Whether a part `len` chars long at `pos` shares text with `start..end`.
+/// Empty parts count when they sit inside the range.
+fn overlaps(pos: usize, len: usize, start: usize, end: usize) -> bool {
+    pos < end && (pos + len > start || (len == 0 && pos >= start))
+}
@@ -1,14 +1,97 @@
-use crate::{Block, Document, Selection};
-use std::collections::VecDeque;
+use crate::{Block, Document, Metadata, Selection};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+/// How soon an edit must follow the last one to merge into its entry.
+const MERGE_WINDOW: Duration = Duration::from_millis(400);
+
 #[derive(Debug, Clone)]
 pub struct Snapshot {
-    pub doc: Document,
+    pub doc: DocSnapshot,
     pub selection: Selection,
 }
 
+/// A document as history keeps it. Blocks are shared with the previous
+/// snapshot whenever their content did not change, so a run of structural
+/// edits only pays for the blocks it actually touched.
+#[derive(Debug, Clone)]
+pub struct DocSnapshot {
+    pub id: Uuid,
+    pub version: u64,
+    pub metadata: Metadata,
+    pub blocks: Vec<Arc<Block>>,
+}
+
+impl DocSnapshot {
+    pub fn capture(doc: &Document, previous: Option<&DocSnapshot>) -> Self {
+        let shared: HashMap<Uuid, &Arc<Block>> = previous
+            .map(|prev| prev.blocks.iter().map(|b| (b.id(), b)).collect())
+            .unwrap_or_default();
+        let blocks = doc
+            .blocks
+            .iter()
+            .map(|block| match shared.get(&block.id()) {
+                Some(prev) if same_content(prev, block) => Arc::clone(prev),
+                _ => Arc::new(block.clone()),
+            })
+            .collect();
+        Self { id: doc.id, version: doc.version, metadata: doc.metadata.clone(), blocks }
+    }
+
+    pub fn to_document(&self) -> Document {
+        Document {
+            id: self.id,
+            version: self.version,
+            metadata: self.metadata.clone(),
+            blocks: self.blocks.iter().map(|b| Block::clone(b)).collect(),
+        }
+    }
+}
+
+/// Block equality ignoring the dirty flag, which layout flips on its own.
+/// The lock counts, so undo never brings back an old lock state.
+fn same_content(a: &Block, b: &Block) -> bool {
+    if a.is_locked() != b.is_locked() {
+        return false;
+    }
+    match (a, b) {
+        (
+            Block::Heading { id: ai, level: al, content: ac, .. },
+            Block::Heading { id: bi, level: bl, content: bc, .. },
+        ) => ai == bi && al == bl && ac == bc,
+        (Block::Paragraph { id: ai, content: ac, .. }, Block::Paragraph { id: bi, content: bc, .. }) => {
+            ai == bi && ac == bc
+        }
+        (
+            Block::List { id: ai, ordered: ao, items: aitems, toc: at, .. },
+            Block::List { id: bi, ordered: bo, items: bitems, toc: bt, .. },
+        ) => ai == bi && ao == bo && at == bt && aitems == bitems,
+        (Block::Quote { id: ai, content: ac, .. }, Block::Quote { id: bi, content: bc, .. }) => {
+            ai == bi && ac.len() == bc.len() && ac.iter().zip(bc).all(|(x, y)| same_content(x, y))
+        }
+        (
+            Block::Code { id: ai, lang: al, code: ac, .. },
+            Block::Code { id: bi, lang: bl, code: bc, .. },
+        ) => ai == bi && al == bl && ac == bc,
+        (
+            Block::Table { id: ai, rows: ar, header: ah, col_widths: aw, .. },
+            Block::Table { id: bi, rows: br, header: bh, col_widths: bw, .. },
+        ) => ai == bi && ah == bh && aw == bw && ar == br,
+        (
+            Block::Figure { id: ai, url: au, caption: ac, size: asz, .. },
+            Block::Figure { id: bi, url: bu, caption: bc, size: bsz, .. },
+        ) => ai == bi && au == bu && ac == bc && asz == bsz,
+        (
+            Block::Custom { id: ai, kind: ak, data: ad, .. },
+            Block::Custom { id: bi, kind: bk, data: bd, .. },
+        ) => ai == bi && ak == bk && ad == bd,
+        _ => false,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum HistoryEntry {
     Snapshot(Snapshot),
@@ -19,6 +102,18 @@ pub enum HistoryEntry {
         selection_before: Selection,
         selection_after: Selection,
     },
+    /// A pure text edit inside one string of a block, see `splice_text_leaf`
+    /// for how `path` is read. `range` is the byte range `removed` occupied
+    /// before the edit; `inserted` starts at `range.start` afterwards.
+    InlineEdit {
+        block_id: Uuid,
+        path: Vec<usize>,
+        range: Range<usize>,
+        removed: String,
+        inserted: String,
+        /// Selection before and after the edit.
+        selections: (Selection, Selection),
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -49,10 +144,9 @@ impl CommandHistory {
     }
 
     pub fn push_or_merge_block_change(&mut self, entry: HistoryEntry) {
+        let now = Instant::now();
         match entry {
             HistoryEntry::BlockChange { block_id, before, after, selection_before, selection_after } => {
-                const MERGE_WINDOW: Duration = Duration::from_millis(400);
-                let now = Instant::now();
                 if let Some(HistoryEntry::BlockChange {
                     block_id: last_id,
                     before: _,
@@ -63,7 +157,7 @@ impl CommandHistory {
                 {
                     if *last_id == block_id
                         && *last_sel_after == selection_before
-                        && self.last_merge_at.map_or(false, |t| now.duration_since(t) <= MERGE_WINDOW)
+                        && self.last_merge_at.is_some_and(|t| now.duration_since(t) <= MERGE_WINDOW)
                     {
                         *last_after = after;
                         *last_sel_after = selection_after;
@@ -81,10 +175,92 @@ impl CommandHistory {
                 });
                 self.last_merge_at = Some(now);
             }
+            HistoryEntry::InlineEdit { block_id, path, range, removed, inserted, selections } => {
+                // Only typing that continues right where the last insertion
+                // ended is merged; deletions stay separate entries.
+                if let Some(HistoryEntry::InlineEdit {
+                    block_id: last_id,
+                    path: last_path,
+                    range: last_range,
+                    inserted: last_inserted,
+                    selections: last_selections,
+                    ..
+                }) = self.undo_stack.back_mut()
+                {
+                    if *last_id == block_id
+                        && *last_path == path
+                        && removed.is_empty()
+                        && range.start == last_range.start + last_inserted.len()
+                        && last_selections.1 == selections.0
+                        && self.last_merge_at.is_some_and(|t| now.duration_since(t) <= MERGE_WINDOW)
+                    {
+                        last_inserted.push_str(&inserted);
+                        last_selections.1 = selections.1;
+                        self.redo_stack.clear();
+                        self.last_merge_at = Some(now);
+                        return;
+                    }
+                }
+                self.push_entry(HistoryEntry::InlineEdit { block_id, path, range, removed, inserted, selections });
+                self.last_merge_at = Some(now);
+            }
             other => self.push_entry(other),
         }
     }
 
+    /// `push_or_merge_block_change` that also merges a text deletion into
+    /// the deletion before it when it continues from where that one left
+    /// the caret, on either side, so deleting word by word undoes in one step.
+    pub fn push_or_merge_deletion(&mut self, entry: HistoryEntry) {
+        let now = Instant::now();
+        if let (
+            HistoryEntry::InlineEdit { block_id, path, range, removed, inserted, selections },
+            Some(HistoryEntry::InlineEdit {
+                block_id: last_id,
+                path: last_path,
+                range: last_range,
+                removed: last_removed,
+                inserted: last_inserted,
+                selections: last_selections,
+            }),
+        ) = (&entry, self.undo_stack.back_mut())
+        {
+            let continues = last_id == block_id
+                && last_path == path
+                && inserted.is_empty()
+                && last_inserted.is_empty()
+                && last_selections.1 == selections.0
+                && self.last_merge_at.is_some_and(|t| now.duration_since(t) <= MERGE_WINDOW);
+            if continues && (range.end == last_range.start || range.start == last_range.start) {
+                if range.end == last_range.start {
+                    last_removed.insert_str(0, removed);
+                    last_range.start = range.start;
+                } else {
+                    last_removed.push_str(removed);
+                    last_range.end += removed.len();
+                }
+                last_selections.1 = selections.1;
+                self.redo_stack.clear();
+                self.last_merge_at = Some(now);
+                return;
+            }
+        }
+        self.push_or_merge_block_change(entry);
+    }
+
+    /// Most recent snapshot on either stack, for new snapshots to share
+    /// unchanged blocks with.
+    pub fn latest_snapshot(&self) -> Option<&DocSnapshot> {
+        self.undo_stack
+            .iter()
+            .rev()
+            .chain(self.redo_stack.iter().rev())
+            .find_map(|entry| match entry {
+                HistoryEntry::Snapshot(snapshot) => Some(&snapshot.doc),
+                _ => None,
+            })
+    }
+
     pub fn pop_undo(&mut self) -> Option<HistoryEntry> {
         self.undo_stack.pop_back()
     }
@@ -104,6 +280,14 @@ impl CommandHistory {
         self.redo_stack.pop_back()
     }
 
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
     pub fn clear(&mut self) {
         self.undo_stack.clear();
         self.redo_stack.clear();
@@ -0,0 +1,331 @@
+use crate::{char_to_byte_idx, Block, Inline, SharedStr, Style};
+use std::ops::Range;
+
+/// Where a flat `Position` offset falls inside a block's inline tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct InlinePath {
+    /// The path `text_leaf_mut` takes: list item, quote child or table row
+    /// and column first, then one index per inline down to the text leaf.
+    /// Code blocks and figure captions are a single leaf with no indices.
+    pub inline_indices: Vec<usize>,
+    /// Char offset inside that leaf.
+    pub char_in_leaf: usize,
+}
+
+/// Which leaf an offset on the boundary between two leaves resolves to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LeafBias {
+    /// The end of the leaf before it, so typed text continues that style.
+    #[default]
+    Before,
+    /// The start of the leaf after it.
+    After,
+}
+
+/// The leaf holding char `offset` of `block_plain_text(block)`, preferring
+/// the leaf that ends there. None when the offset is past the end or falls
+/// where there is no leaf, like an empty list item.
+pub fn resolve_position(block: &Block, offset: usize) -> Option<InlinePath> {
+    resolve_position_with(block, offset, LeafBias::Before)
+}
+
+pub fn resolve_position_with(block: &Block, offset: usize, bias: LeafBias) -> Option<InlinePath> {
+    let mut found = None;
+    visit_leaves(block, &mut Vec::new(), &mut 0, &mut |path, start, len| {
+        let end = start + len;
+        let hit = match bias {
+            LeafBias::Before => offset <= end,
+            LeafBias::After => offset < end,
+        };
+        if start <= offset && hit {
+            found = Some(InlinePath { inline_indices: path.to_vec(), char_in_leaf: offset - start });
+        }
+        found.is_some()
+    });
+    match (found, bias) {
+        (None, LeafBias::After) => resolve_position_with(block, offset, LeafBias::Before),
+        (found, _) => found,
+    }
+}
+
+/// The flat offset `path` points at; the inverse of `resolve_position`.
+pub fn flatten_path(block: &Block, path: &InlinePath) -> Option<usize> {
+    let mut found = None;
+    visit_leaves(block, &mut Vec::new(), &mut 0, &mut |indices, start, len| {
+        if indices == path.inline_indices.as_slice() {
+            found = (path.char_in_leaf <= len).then_some(start + path.char_in_leaf);
+            return true;
+        }
+        false
+    });
+    found
+}
+
+/// Splits the inline list holding `path` so the point becomes a boundary
+/// between two of its inlines, cutting the leaf and every styled span or
+/// link around it in two. Returns the index in that list where the part
+/// after the point starts; see `inline_list_mut` for the list itself.
+pub fn split_at_path(block: &mut Block, path: &InlinePath) -> Option<usize> {
+    let (list, rest) = inline_list_mut(block, &path.inline_indices)?;
+    split_inlines(list, rest, path.char_in_leaf)
+}
+
+/// Effective style of the leaf at `path`: every styled span around it
+/// combined.
+pub fn style_at_path(block: &Block, path: &InlinePath) -> Style {
+    fn walk(inlines: &[Inline], path: &[usize], style: Style) -> Style {
+        let Some((&first, rest)) = path.split_first() else {
+            return style;
+        };
+        match inlines.get(first) {
+            Some(Inline::Styled { style: inner, content }) => walk(content, rest, style.union(*inner)),
+            Some(Inline::Link { text, .. }) => walk(text, rest, style),
+            _ => style,
+        }
+    }
+    match inline_list(block, &path.inline_indices) {
+        Some((list, rest)) => walk(list, rest, Style::default()),
+        None => Style::default(),
+    }
+}
+
+/// Puts `inline` at char `offset` of the block's text, splitting the leaf
+/// there. Returns false when the offset does not resolve to a leaf.
+pub fn insert_inline(block: &mut Block, offset: usize, inline: Inline) -> bool {
+    let Some(path) = resolve_position(block, offset) else {
+        return false;
+    };
+    let Some(at) = split_at_path(block, &path) else {
+        return false;
+    };
+    let Some((list, _)) = inline_list_mut(block, &path.inline_indices) else {
+        return false;
+    };
+    list.insert(at, inline);
+    crate::normalize_inlines(list);
+    if let (Block::Quote { content, .. }, Some(&child)) = (&mut *block, path.inline_indices.first()) {
+        content[child].set_dirty(true);
+    }
+    block.set_dirty(true);
+    true
+}
+
+/// The inline list a path starts in, with the indices inside that list.
+pub fn inline_list_mut<'a, 'p>(block: &'a mut Block, path: &'p [usize]) -> Option<(&'a mut Vec<Inline>, &'p [usize])> {
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => Some((content, path)),
+        Block::List { items, .. } => {
+            let (&item, rest) = path.split_first()?;
+            Some((&mut items.get_mut(item)?.content, rest))
+        }
+        Block::Quote { content, .. } => {
+            let (&child, rest) = path.split_first()?;
+            inline_list_mut(content.get_mut(child)?, rest)
+        }
+        Block::Table { rows, .. } => {
+            let [row, col, rest @ ..] = path else {
+                return None;
+            };
+            Some((&mut rows.get_mut(*row)?.get_mut(*col)?.content, rest))
+        }
+        Block::Code { .. } | Block::Figure { .. } | Block::PageBreak { .. } | Block::Custom { .. } => None,
+    }
+}
+
+fn inline_list<'a, 'p>(block: &'a Block, path: &'p [usize]) -> Option<(&'a [Inline], &'p [usize])> {
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => Some((content, path)),
+        Block::List { items, .. } => {
+            let (&item, rest) = path.split_first()?;
+            Some((&items.get(item)?.content, rest))
+        }
+        Block::Quote { content, .. } => {
+            let (&child, rest) = path.split_first()?;
+            inline_list(content.get(child)?, rest)
+        }
+        Block::Table { rows, .. } => {
+            let [row, col, rest @ ..] = path else {
+                return None;
+            };
+            Some((&rows.get(*row)?.get(*col)?.content, rest))
+        }
+        Block::Code { .. } | Block::Figure { .. } | Block::PageBreak { .. } | Block::Custom { .. } => None,
+    }
+}
+
+/// Path and byte range of the single text leaf holding chars `start..end`,
+/// or None when the range spans several leaves or covers a whole one,
+/// since editing it would change the shape of the tree.
+pub(crate) fn leaf_range(block: &Block, start: usize, end: usize) -> Option<(Vec<usize>, Range<usize>)> {
+    if start >= end {
+        return None;
+    }
+    let from = resolve_position_with(block, start, LeafBias::After)?;
+    let to = resolve_position_with(block, end, LeafBias::Before)?;
+    if from.inline_indices != to.inline_indices {
+        return None;
+    }
+    let value = leaf_text(block, &from.inline_indices)?;
+    if from.char_in_leaf == 0 && to.char_in_leaf == value.chars().count() {
+        return None;
+    }
+    Some((from.inline_indices, char_to_byte_idx(value, from.char_in_leaf)..char_to_byte_idx(value, to.char_in_leaf)))
+}
+
+/// Chars `start..end` of the formula holding the char before `offset`,
+/// which backspace takes as a whole since part of a formula is not TeX.
+/// None when that char is not in an `Inline::Math`.
+pub(crate) fn math_range_before(block: &Block, offset: usize) -> Option<Range<usize>> {
+    fn walk<'a>(inlines: &'a [Inline], path: &[usize]) -> Option<&'a Inline> {
+        let (&first, rest) = path.split_first()?;
+        match inlines.get(first)? {
+            Inline::Styled { content: children, .. } | Inline::Link { text: children, .. } => walk(children, rest),
+            leaf => rest.is_empty().then_some(leaf),
+        }
+    }
+    let mut found = None;
+    visit_leaves(block, &mut Vec::new(), &mut 0, &mut |path, start, len| {
+        let hit = start < offset && offset <= start + len;
+        if hit {
+            found = Some((path.to_vec(), start..start + len));
+        }
+        hit
+    });
+    let (path, range) = found?;
+    let (list, rest) = inline_list(block, &path)?;
+    matches!(walk(list, rest)?, Inline::Math { .. }).then_some(range)
+}
+
+/// Path and byte index of char `offset` in the plain text run holding it,
+/// preferring the run that ends there. None when neither leaf at the offset
+/// is an `Inline::Text`, such as the end of a code span.
+pub(crate) fn text_run_at(block: &Block, offset: usize) -> Option<(Vec<usize>, usize)> {
+    fn walk<'a>(inlines: &'a [Inline], path: &[usize]) -> Option<&'a SharedStr> {
+        let (&first, rest) = path.split_first()?;
+        match inlines.get(first)? {
+            Inline::Text { value } if rest.is_empty() => Some(value),
+            Inline::Styled { content: children, .. } | Inline::Link { text: children, .. } => walk(children, rest),
+            _ => None,
+        }
+    }
+    [LeafBias::Before, LeafBias::After].into_iter().find_map(|bias| {
+        let path = resolve_position_with(block, offset, bias)?;
+        let (list, rest) = inline_list(block, &path.inline_indices)?;
+        let value = walk(list, rest)?;
+        Some((path.inline_indices, char_to_byte_idx(value, path.char_in_leaf)))
+    })
+}
+
+/// The text of an inline leaf; code blocks and captions are not included.
+fn leaf_text<'a>(block: &'a Block, path: &[usize]) -> Option<&'a SharedStr> {
+    fn walk<'a>(inlines: &'a [Inline], path: &[usize]) -> Option<&'a SharedStr> {
+        let (&first, rest) = path.split_first()?;
+        match inlines.get(first)? {
+            Inline::Text { value } | Inline::CodeSpan { value } | Inline::Math { tex: value, .. } if rest.is_empty() => Some(value),
+            Inline::Styled { content: children, .. } | Inline::Link { text: children, .. } => walk(children, rest),
+            _ => None,
+        }
+    }
+    let (list, rest) = inline_list(block, path)?;
+    walk(list, rest)
+}
+
+fn split_inlines(list: &mut Vec<Inline>, path: &[usize], at: usize) -> Option<usize> {
+    let (&idx, rest) = path.split_first()?;
+    let tail = match list.get_mut(idx)? {
+        Inline::Text { value } | Inline::CodeSpan { value } if rest.is_empty() => {
+            let len = value.chars().count();
+            if at > len {
+                return None;
+            }
+            if at == 0 || at == len {
+                return Some(if at == 0 { idx } else { idx + 1 });
+            }
+            let cut = char_to_byte_idx(value, at);
+            let tail = SharedStr::from(&value[cut..]);
+            *value = SharedStr::from(&value[..cut]);
+            match &list[idx] {
+                Inline::CodeSpan { .. } => Inline::CodeSpan { value: tail },
+                _ => Inline::Text { value: tail },
+            }
+        }
+        Inline::Styled { style, content } => {
+            let cut = split_inlines(content, rest, at)?;
+            if cut == 0 || cut == content.len() {
+                return Some(if cut == 0 { idx } else { idx + 1 });
+            }
+            Inline::Styled { style: *style, content: content.split_off(cut) }
+        }
+        Inline::Link { url, text } => {
+            let cut = split_inlines(text, rest, at)?;
+            if cut == 0 || cut == text.len() {
+                return Some(if cut == 0 { idx } else { idx + 1 });
+            }
+            Inline::Link { url: url.clone(), text: text.split_off(cut) }
+        }
+        // Math splits only at its ends.
+        Inline::Math { tex, .. } if rest.is_empty() => {
+            return match at {
+                0 => Some(idx),
+                at if at == tex.chars().count() => Some(idx + 1),
+                _ => None,
+            };
+        }
+        _ => return None,
+    };
+    list.insert(idx + 1, tail);
+    Some(idx + 1)
+}
+
+/// Visits every text leaf in plain-text order with its path, start offset
+/// and char length, counting the separators `block_plain_text` inserts,
+/// until `f` returns true. Returns whether it stopped early.
+fn visit_leaves(block: &Block, path: &mut Vec<usize>, pos: &mut usize, f: &mut dyn FnMut(&[usize], usize, usize) -> bool) -> bool {
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => visit_inlines(content, path, pos, f),
+        Block::List { items, .. } => items.iter().enumerate().any(|(idx, item)| {
+            *pos += usize::from(idx > 0);
+            nested(path, idx, |path| visit_inlines(&item.content, path, pos, f))
+        }),
+        Block::Quote { content, .. } => content.iter().enumerate().any(|(idx, inner)| {
+            *pos += usize::from(idx > 0);
+            nested(path, idx, |path| visit_leaves(inner, path, pos, f))
+        }),
+        Block::Table { rows, .. } => rows.iter().enumerate().any(|(ri, row)| {
+            *pos += usize::from(ri > 0);
+            nested(path, ri, |path| {
+                row.iter().enumerate().any(|(ci, cell)| {
+                    *pos += usize::from(ci > 0);
+                    nested(path, ci, |path| visit_inlines(&cell.content, path, pos, f))
+                })
+            })
+        }),
+        Block::Code { code, .. } => leaf(path, pos, code.char_len(), f),
+        Block::Figure { caption: Some(caption), .. } => leaf(path, pos, caption.chars().count(), f),
+        Block::Figure { caption: None, .. } | Block::PageBreak { .. } | Block::Custom { .. } => false,
+    }
+}
+
+fn visit_inlines(inlines: &[Inline], path: &mut Vec<usize>, pos: &mut usize, f: &mut dyn FnMut(&[usize], usize, usize) -> bool) -> bool {
+    inlines.iter().enumerate().any(|(idx, inline)| {
+        nested(path, idx, |path| match inline {
+            Inline::Text { value } | Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => leaf(path, pos, value.chars().count(), f),
+            Inline::Styled { content: children, .. } | Inline::Link { text: children, .. } => {
+                visit_inlines(children, path, pos, f)
+            }
+        })
+    })
+}
+
+fn leaf(path: &[usize], pos: &mut usize, len: usize, f: &mut dyn FnMut(&[usize], usize, usize) -> bool) -> bool {
+    let start = *pos;
+    *pos += len;
+    f(path, start, len)
+}
+
+fn nested(path: &mut Vec<usize>, idx: usize, f: impl FnOnce(&mut Vec<usize>) -> bool) -> bool {
+    path.push(idx);
+    let stop = f(path);
+    path.pop();
+    stop
+}
@@ -0,0 +1,171 @@
+use crate::unicode_tables::{CANONICAL_COMBINING_CLASS, CANONICAL_COMPOSITION, CANONICAL_DECOMPOSITION};
+use crate::{visit_leaves_mut, Block, SharedStr};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+/// How typed and pasted text is cleaned up before it reaches the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputFilter {
+    /// Compose to NFC, so "e" followed by U+0301 is stored, measured and
+    /// found as one "é".
+    pub nfc: bool,
+    /// Drop C0 controls other than '\n' and '\t', DEL and byte order marks.
+    /// Zero-width joiners stay, emoji sequences need them.
+    pub strip_controls: bool,
+    /// Replace every tab with this many spaces.
+    pub tab_spaces: Option<usize>,
+}
+
+impl Default for InputFilter {
+    fn default() -> Self {
+        Self { nfc: true, strip_controls: true, tab_spaces: None }
+    }
+}
+
+impl InputFilter {
+    /// Leaves text exactly as given.
+    pub const RAW: InputFilter = InputFilter { nfc: false, strip_controls: false, tab_spaces: None };
+
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let dropped = |c: char| self.strip_controls && is_stray_control(c);
+        let filtered = if text.chars().any(|c| dropped(c) || (c == '\t' && self.tab_spaces.is_some())) {
+            let mut out = String::with_capacity(text.len());
+            for c in text.chars().filter(|c| !dropped(*c)) {
+                match (c, self.tab_spaces) {
+                    ('\t', Some(spaces)) => out.extend(std::iter::repeat_n(' ', spaces)),
+                    _ => out.push(c),
+                }
+            }
+            Cow::Owned(out)
+        } else {
+            Cow::Borrowed(text)
+        };
+        if !self.nfc {
+            return filtered;
+        }
+        match to_nfc(&filtered) {
+            Cow::Borrowed(_) => filtered,
+            Cow::Owned(composed) => Cow::Owned(composed),
+        }
+    }
+
+    /// Runs every inline text leaf and caption of `block` through `apply`.
+    /// Code blocks keep their text as given.
+    pub fn apply_to_block(&self, block: &mut Block) {
+        visit_leaves_mut(block, &mut 0, &mut |_, value| {
+            if let Cow::Owned(filtered) = self.apply(value) {
+                *value = SharedStr::from(filtered);
+            }
+        });
+    }
+}
+
+fn is_stray_control(c: char) -> bool {
+    matches!(c, '\u{0}'..='\u{8}' | '\u{b}'..='\u{1f}' | '\u{7f}' | '\u{feff}')
+}
+
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const S_COUNT: u32 = L_COUNT * V_COUNT * T_COUNT;
+
+/// `text` in Unicode normalization form C, borrowed when it already is.
+pub fn to_nfc(text: &str) -> Cow<'_, str> {
+    // Nothing below the combining diacritics block changes under NFC.
+    if text.chars().all(|c| (c as u32) < 0x300) {
+        return Cow::Borrowed(text);
+    }
+    let mut chars = Vec::with_capacity(text.len());
+    text.chars().for_each(|c| decompose(c, &mut chars));
+    reorder(&mut chars);
+    let composed: String = compose(chars).into_iter().collect();
+    if composed == text {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(composed)
+    }
+}
+
+fn combining_class(c: char) -> u8 {
+    let cp = c as u32;
+    CANONICAL_COMBINING_CLASS
+        .binary_search_by(|&(lo, hi, _)| match () {
+            _ if hi < cp => Ordering::Less,
+            _ if lo > cp => Ordering::Greater,
+            _ => Ordering::Equal,
+        })
+        .map_or(0, |idx| CANONICAL_COMBINING_CLASS[idx].2)
+}
+
+fn decompose(c: char, out: &mut Vec<char>) {
+    let cp = c as u32;
+    if (S_BASE..S_BASE + S_COUNT).contains(&cp) {
+        let index = cp - S_BASE;
+        let jamo = [L_BASE + index / (V_COUNT * T_COUNT), V_BASE + index % (V_COUNT * T_COUNT) / T_COUNT];
+        out.extend(jamo.into_iter().filter_map(char::from_u32));
+        if !index.is_multiple_of(T_COUNT) {
+            out.extend(char::from_u32(T_BASE + index % T_COUNT));
+        }
+        return;
+    }
+    match CANONICAL_DECOMPOSITION.binary_search_by_key(&cp, |&(from, _, _)| from) {
+        Ok(idx) => {
+            let (_, first, second) = CANONICAL_DECOMPOSITION[idx];
+            for part in [first, second].into_iter().filter(|p| *p != 0).filter_map(char::from_u32) {
+                decompose(part, out);
+            }
+        }
+        Err(_) => out.push(c),
+    }
+}
+
+/// Canonical ordering: marks between two starters sorted by class, stably.
+fn reorder(chars: &mut [char]) {
+    for i in 1..chars.len() {
+        let class = combining_class(chars[i]);
+        let mut j = i;
+        while class != 0 && j > 0 && combining_class(chars[j - 1]) > class {
+            chars.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+}
+
+fn compose(chars: Vec<char>) -> Vec<char> {
+    let mut out: Vec<char> = Vec::with_capacity(chars.len());
+    let (mut starter, mut last_class) = (None, 0);
+    for c in chars {
+        let class = combining_class(c);
+        if let Some(at) = starter {
+            let reachable = out.len() == at + 1 || (last_class != 0 && last_class < class);
+            if let Some(composite) = reachable.then(|| compose_pair(out[at], c)).flatten() {
+                out[at] = composite;
+                continue;
+            }
+        }
+        if class == 0 {
+            starter = Some(out.len());
+        }
+        last_class = class;
+        out.push(c);
+    }
+    out
+}
+
+fn compose_pair(first: char, second: char) -> Option<char> {
+    let (a, b) = (first as u32, second as u32);
+    if (L_BASE..L_BASE + L_COUNT).contains(&a) && (V_BASE..V_BASE + V_COUNT).contains(&b) {
+        return char::from_u32(S_BASE + ((a - L_BASE) * V_COUNT + (b - V_BASE)) * T_COUNT);
+    }
+    if (S_BASE..S_BASE + S_COUNT).contains(&a) && (a - S_BASE).is_multiple_of(T_COUNT) && (T_BASE + 1..T_BASE + T_COUNT).contains(&b) {
+        return char::from_u32(a + (b - T_BASE));
+    }
+    CANONICAL_COMPOSITION
+        .binary_search_by_key(&(a, b), |&(x, y, _)| (x, y))
+        .ok()
+        .and_then(|idx| char::from_u32(CANONICAL_COMPOSITION[idx].2))
+}
@@ -1,23 +1,120 @@
-use crate::SharedStr;
-use std::collections::HashMap;
+use crate::{Block, Document, Inline, SharedStr};
+use std::collections::HashSet;
 use std::sync::Arc;
 
+/// Strings longer than this rarely repeat; they bypass the table.
+pub const MAX_INTERNED_LEN: usize = 64;
+
 #[derive(Debug, Default)]
 pub struct StringInterner {
-    map: HashMap<String, SharedStr>,
+    set: HashSet<SharedStr>,
+    hits: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InternStats {
+    /// Distinct strings held by the interner.
+    pub unique: usize,
+    pub bytes: usize,
+    /// Allocations avoided by handing out an existing string.
+    pub hits: u64,
 }
 
 impl StringInterner {
     pub fn new() -> Self {
-        Self { map: HashMap::new() }
+        Self::default()
     }
 
     pub fn intern(&mut self, s: &str) -> SharedStr {
-        if let Some(hit) = self.map.get(s) {
+        if s.len() > MAX_INTERNED_LEN {
+            return Arc::from(s);
+        }
+        if let Some(hit) = self.set.get(s) {
+            self.hits += 1;
             return hit.clone();
         }
         let shared: SharedStr = Arc::from(s);
-        self.map.insert(s.to_string(), shared.clone());
+        self.set.insert(shared.clone());
         shared
     }
+
+    /// Swaps `value` for the interned copy, adopting it when it is new.
+    pub fn intern_in_place(&mut self, value: &mut SharedStr) {
+        if value.len() > MAX_INTERNED_LEN {
+            return;
+        }
+        match self.set.get(value.as_ref()) {
+            Some(hit) if Arc::ptr_eq(hit, value) => {}
+            Some(hit) => {
+                self.hits += 1;
+                *value = hit.clone();
+            }
+            None => {
+                self.set.insert(value.clone());
+            }
+        }
+    }
+
+    pub fn intern_block(&mut self, block: &mut Block) {
+        match block {
+            Block::Heading { content, .. } | Block::Paragraph { content, .. } => self.intern_inlines(content),
+            Block::List { items, .. } => {
+                for item in items {
+                    self.intern_inlines(&mut item.content);
+                }
+            }
+            Block::Quote { content, .. } => {
+                for inner in content {
+                    self.intern_block(inner);
+                }
+            }
+            Block::Code { lang, .. } => self.intern_in_place(lang),
+            Block::Table { rows, .. } => {
+                for cell in rows.iter_mut().flatten() {
+                    self.intern_inlines(&mut cell.content);
+                }
+            }
+            Block::Figure { caption, .. } => {
+                if let Some(caption) = caption {
+                    self.intern_in_place(caption);
+                }
+            }
+            Block::PageBreak { .. } => {}
+            Block::Custom { kind, .. } => self.intern_in_place(kind),
+        }
+    }
+
+    pub fn intern_document(&mut self, doc: &mut Document) {
+        for block in &mut doc.blocks {
+            self.intern_block(block);
+        }
+    }
+
+    fn intern_inlines(&mut self, inlines: &mut [Inline]) {
+        for inline in inlines {
+            match inline {
+                Inline::Text { value } | Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => self.intern_in_place(value),
+                Inline::Styled { content, .. } => self.intern_inlines(content),
+                Inline::Link { url, text } => {
+                    self.intern_in_place(url);
+                    self.intern_inlines(text);
+                }
+            }
+        }
+    }
+
+    /// Drops strings nothing but the interner refers to any more.
+    pub fn prune(&mut self) -> usize {
+        let before = self.set.len();
+        self.set.retain(|s| Arc::strong_count(s) > 1);
+        before - self.set.len()
+    }
+
+    pub fn stats(&self) -> InternStats {
+        InternStats {
+            unique: self.set.len(),
+            bytes: self.set.iter().map(|s| s.len()).sum(),
+            hits: self.hits,
+        }
+    }
 }
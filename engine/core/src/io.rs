@@ -1,4 +1,5 @@
-﻿use crate::{Block, Document, Inline, ListItem};
+﻿use crate::{Block, Cell, CodeText, Document, Inline, ListItem, StringInterner, MAX_LIST_LEVEL};
+use std::borrow::Cow;
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -7,47 +8,64 @@ pub fn export_markdown(doc: &Document) -> String {
     for block in &doc.blocks {
         match block {
             Block::Heading { level, content, .. } => {
-                out.push(format!("{} {}", "#".repeat(*level as usize), inline_text(content)));
+                let text = inline_markdown(content);
+                out.push(format!("{} {}", "#".repeat(*level as usize), escape_first(&text, &['#', '\\'])));
             }
             Block::Paragraph { content, .. } => {
-                out.push(inline_text(content));
+                out.push(escape_line_start(&inline_markdown(content)).into_owned());
             }
             Block::List { ordered, items, .. } => {
                 for (idx, item) in items.iter().enumerate() {
                     let prefix = if *ordered { format!("{}. ", idx + 1) } else { "- ".to_string() };
-                    out.push(format!("{}{}", prefix, inline_text(&item.content)));
+                    let indent = "  ".repeat(item.level as usize);
+                    let text = inline_markdown(&item.content);
+                    let text = match item.checked {
+                        Some(checked) => format!("[{}] {}", if checked { 'x' } else { ' ' }, text),
+                        None if task_box(text.strip_prefix('\\').unwrap_or(&text)).is_some() => format!("\\{}", text),
+                        None => text,
+                    };
+                    out.push(format!("{}{}{}", indent, prefix, text));
                 }
             }
             Block::Quote { content, .. } => {
                 let text = content
                     .iter()
                     .map(|b| match b {
-                        Block::Paragraph { content, .. } => inline_text(content),
+                        Block::Paragraph { content, .. } => inline_markdown(content),
                         _ => String::new(),
                     })
                     .collect::<Vec<_>>()
                     .join(" ");
-                out.push(format!("> {}", text));
+                out.push(format!("> {}", escape_first(&text, &['>', '\\'])));
             }
             Block::Code { lang, code, .. } => {
-                out.push(format!("```{}", lang.as_ref()));
-                out.push(code.as_ref().to_string());
-                out.push("```".to_string());
+                // The fence must outlast any run of backticks opening a line
+                // of the code, or that line would close the block.
+                let longest = code.lines().map(|l| l.trim_start().chars().take_while(|c| *c == '`').count()).max();
+                let fence = "`".repeat(longest.unwrap_or(0).max(2) + 1);
+                out.push(format!("{}{}", fence, lang.as_ref()));
+                out.push(code.to_string());
+                out.push(fence);
             }
-            Block::Table { rows, .. } => {
-                for row in rows {
+            Block::Table { rows, header, .. } => {
+                for (idx, row) in rows.iter().enumerate() {
                     let row_text = row
                         .iter()
-                        .map(|c| inline_text(&c.content))
+                        .map(|c| escape_cell(&inline_markdown(&c.content)))
                         .collect::<Vec<_>>()
                         .join(" | ");
                     out.push(format!("| {} |", row_text));
+                    if idx == 0 && *header {
+                        out.push(format!("|{}", " --- |".repeat(row.len())));
+                    }
                 }
             }
             Block::Figure { url, caption, .. } => {
                 let cap = caption.as_ref().map(|c| c.as_ref()).unwrap_or("图");
                 out.push(format!("![{}]({})", cap, url.as_ref()));
             }
+            Block::PageBreak { .. } => out.push(PAGE_BREAK.to_string()),
+            Block::Custom { kind, data, .. } => out.push(custom_comment(kind, data)),
         }
         out.push(String::new());
     }
@@ -57,169 +75,456 @@ pub fn export_markdown(doc: &Document) -> String {
 pub fn import_markdown(md: &str) -> Document {
     let mut doc = Document::new();
     let mut blocks = Vec::new();
-    let mut list_items: Vec<ListItem> = Vec::new();
-    let mut list_ordered = false;
-    let mut in_code = false;
+    let mut run = BlockRun::default();
+    let mut fence: Option<(char, usize)> = None;
     let mut code_lang = String::new();
     let mut code_buf = Vec::new();
+    let mut interner = StringInterner::new();
 
     for raw in md.lines() {
         let line = raw.trim_end();
-        if line.starts_with("```") {
-            if in_code {
+        if let Some((ch, len)) = fence {
+            let closing = line.trim_start();
+            if closing.len() >= len && closing.chars().all(|c| c == ch) {
                 blocks.push(Block::Code {
                     id: Uuid::new_v4(),
-                    lang: Arc::from(code_lang.clone()),
-                    code: Arc::from(code_buf.join("\n")),
+                    lang: interner.intern(&code_lang),
+                    code: CodeText::from(code_buf.join("\n")),
                     dirty: false,
+                    locked: false,
                 });
                 code_buf.clear();
                 code_lang.clear();
-                in_code = false;
+                fence = None;
             } else {
-                in_code = true;
-                code_lang = line.trim_start_matches("```").trim().to_string();
+                code_buf.push(raw.to_string());
             }
             continue;
         }
-        if in_code {
-            code_buf.push(line.to_string());
+        if let Some((ch, len)) = opening_fence(line) {
+            run.flush(&mut blocks);
+            fence = Some((ch, len));
+            code_lang = line[len..].trim().to_string();
             continue;
         }
         if let Some(h) = parse_heading(line) {
-            flush_list(&mut blocks, &mut list_items, list_ordered);
+            run.flush(&mut blocks);
             blocks.push(Block::Heading {
                 id: Uuid::new_v4(),
                 level: h.0,
-                content: vec![Inline::Text { value: Arc::from(h.1) }],
+                content: parse_inlines(&h.1, &mut interner),
                 dirty: false,
+                locked: false,
             });
             continue;
         }
-        if let Some(item) = parse_list(line) {
-            list_ordered = item.0;
-            list_items.push(ListItem {
+        if let Some((ordered, level, checked, text)) = parse_list(line) {
+            let item = ListItem {
                 id: Uuid::new_v4(),
-                content: vec![Inline::Text { value: Arc::from(item.1) }],
-            });
+                content: parse_inlines(&text, &mut interner),
+                level,
+                checked,
+            };
+            run.push(&mut blocks, ordered, item);
             continue;
         }
         if line.starts_with('>') {
-            flush_list(&mut blocks, &mut list_items, list_ordered);
-            let text = line.trim_start_matches('>').trim();
+            run.flush(&mut blocks);
+            let text = unescape_first(line.trim_start_matches('>').trim());
             blocks.push(Block::Quote {
                 id: Uuid::new_v4(),
                 content: vec![Block::Paragraph {
                     id: Uuid::new_v4(),
-                    content: vec![Inline::Text { value: Arc::from(text) }],
+                    content: parse_inlines(text, &mut interner),
                     dirty: false,
+                    locked: false,
                 }],
                 dirty: false,
+                locked: false,
             });
             continue;
         }
+        if line.trim() == PAGE_BREAK || line.trim() == "\\pagebreak" {
+            run.flush(&mut blocks);
+            blocks.push(Block::PageBreak { id: Uuid::new_v4(), dirty: false, locked: false });
+            continue;
+        }
+        if let Some((kind, data)) = parse_custom_comment(line) {
+            run.flush(&mut blocks);
+            blocks.push(Block::Custom { id: Uuid::new_v4(), kind: interner.intern(kind), data, dirty: false, locked: false });
+            continue;
+        }
         if line.starts_with("![") && line.contains("](") && line.ends_with(')') {
-            flush_list(&mut blocks, &mut list_items, list_ordered);
+            run.flush(&mut blocks);
             if let Some((cap, url)) = parse_image(line) {
                 blocks.push(Block::Figure {
                     id: Uuid::new_v4(),
                     url: Arc::from(url),
-                    caption: Some(Arc::from(cap)),
+                    caption: Some(interner.intern(&cap)),
                     size: None,
                     dirty: false,
+                    locked: false,
                 });
             }
             continue;
         }
-        if line.starts_with('|') && line.ends_with('|') {
-            flush_list(&mut blocks, &mut list_items, list_ordered);
-            let cells = line
-                .trim_matches('|')
-                .split('|')
-                .map(|c| crate::Cell {
-                    content: vec![Inline::Text { value: Arc::from(c.trim()) }],
-                })
-                .collect::<Vec<_>>();
-            blocks.push(Block::Table {
-                id: Uuid::new_v4(),
-                rows: vec![cells],
-                dirty: false,
-            });
+        if line.len() > 1 && line.starts_with('|') && line.ends_with('|') {
+            let inner = &line[1..line.len() - 1];
+            let cells = split_cells(inner).iter().map(|c| Cell { content: parse_inlines(c, &mut interner) }).collect();
+            run.push_row(&mut blocks, cells, is_separator(inner));
             continue;
         }
         if line.trim().is_empty() {
-            flush_list(&mut blocks, &mut list_items, list_ordered);
+            run.flush(&mut blocks);
             continue;
         }
-        flush_list(&mut blocks, &mut list_items, list_ordered);
+        run.flush(&mut blocks);
         blocks.push(Block::Paragraph {
             id: Uuid::new_v4(),
-            content: vec![Inline::Text { value: Arc::from(line) }],
+            content: parse_inlines(&unescape_line_start(line), &mut interner),
             dirty: false,
+            locked: false,
         });
     }
-    flush_list(&mut blocks, &mut list_items, list_ordered);
+    run.flush(&mut blocks);
     doc.blocks = blocks;
     doc
 }
 
-fn flush_list(blocks: &mut Vec<Block>, items: &mut Vec<ListItem>, ordered: bool) {
-    if items.is_empty() {
-        return;
+/// Consecutive lines that import as one block: list lines with the same
+/// marker kind, or table rows with the same number of cells. Any other
+/// block, a blank line or a change of kind ends the run.
+#[derive(Default)]
+struct BlockRun {
+    ordered: bool,
+    items: Vec<ListItem>,
+    rows: Vec<Vec<Cell>>,
+    header: bool,
+}
+
+impl BlockRun {
+    fn push(&mut self, blocks: &mut Vec<Block>, ordered: bool, item: ListItem) {
+        if self.ordered != ordered || !self.rows.is_empty() {
+            self.flush(blocks);
+        }
+        self.ordered = ordered;
+        let deepest = self.items.last().map_or(0, |prev| prev.level.saturating_add(1)).min(MAX_LIST_LEVEL);
+        self.items.push(ListItem { level: item.level.min(deepest), ..item });
+    }
+
+    /// Adds a table row. A `| --- |` row right after the first one marks
+    /// that row as the header.
+    fn push_row(&mut self, blocks: &mut Vec<Block>, row: Vec<Cell>, separator: bool) {
+        let width = self.rows.first().map(Vec::len);
+        if self.rows.len() == 1 && !self.header && width == Some(row.len()) && separator {
+            self.header = true;
+            return;
+        }
+        if !self.items.is_empty() || width.is_some_and(|w| w != row.len()) {
+            self.flush(blocks);
+        }
+        self.rows.push(row);
+    }
+
+    fn flush(&mut self, blocks: &mut Vec<Block>) {
+        if !self.items.is_empty() {
+            blocks.push(Block::List {
+                id: Uuid::new_v4(),
+                ordered: self.ordered,
+                items: std::mem::take(&mut self.items),
+                dirty: false,
+                locked: false,
+                toc: false,
+            });
+        }
+        if !self.rows.is_empty() {
+            blocks.push(Block::Table {
+                id: Uuid::new_v4(),
+                rows: std::mem::take(&mut self.rows),
+                header: std::mem::take(&mut self.header),
+                col_widths: Vec::new(),
+                dirty: false,
+                locked: false,
+            });
+        }
+    }
+}
+
+/// Whether a row, as written between its outer pipes, is a `--- | :--:`
+/// header separator.
+fn is_separator(inner: &str) -> bool {
+    inner.split('|').all(|cell| {
+        let cell = cell.trim();
+        cell.contains('-') && cell.chars().all(|c| matches!(c, '-' | ':'))
+    })
+}
+
+/// Fence char and length of a line opening a code block: three or more
+/// backticks or tildes.
+fn opening_fence(line: &str) -> Option<(char, usize)> {
+    let ch = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = line.chars().take_while(|c| *c == ch).count();
+    (len >= 3).then_some((ch, len))
+}
+
+/// Characters that make the start of a paragraph line read as markup.
+const LINE_TRIGGERS: &[char] = &['#', '-', '>', '|', '!', '`', '~', '<', '.', '\\'];
+
+/// Paragraph text with a backslash where the importer would otherwise see
+/// a heading, list, quote, table, fence, image or custom block: before a
+/// leading trigger character, or after a leading number ahead of ". ".
+fn escape_line_start(text: &str) -> Cow<'_, str> {
+    let body = text.trim_start();
+    let lead = &text[..text.len() - body.len()];
+    let digits = body.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 && body.starts_with(LINE_TRIGGERS) {
+        return Cow::Owned(format!("{lead}\\{body}"));
+    }
+    let rest = &body[digits..];
+    if digits > 0 && (rest.starts_with(". ") || rest.starts_with('\\')) {
+        return Cow::Owned(format!("{lead}{}\\{rest}", &body[..digits]));
+    }
+    Cow::Borrowed(text)
+}
+
+/// Reverses `escape_line_start`.
+fn unescape_line_start(line: &str) -> Cow<'_, str> {
+    let body = line.trim_start();
+    let at = line.len() - body.len() + body.bytes().take_while(u8::is_ascii_digit).count();
+    match unescape_first(&line[at..]) {
+        rest if rest.len() < line.len() - at => Cow::Owned(format!("{}{}", &line[..at], rest)),
+        _ => Cow::Borrowed(line),
+    }
+}
+
+/// `text` with a backslash in front when it starts with one of `triggers`.
+fn escape_first<'a>(text: &'a str, triggers: &[char]) -> Cow<'a, str> {
+    match text.starts_with(triggers) {
+        true => Cow::Owned(format!("\\{text}")),
+        false => Cow::Borrowed(text),
     }
-    blocks.push(Block::List {
-        id: Uuid::new_v4(),
-        ordered,
-        items: std::mem::take(items),
-        dirty: false,
-    });
+}
+
+/// Drops a leading backslash that escapes ASCII punctuation.
+fn unescape_first(text: &str) -> &str {
+    match text.strip_prefix('\\') {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_punctuation()) => rest,
+        _ => text,
+    }
+}
+
+/// Cell text with `\` and `|` escaped, and a backslash in front of text
+/// that would read as a `| --- |` header separator.
+fn escape_cell(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('|', "\\|");
+    match !escaped.is_empty() && escaped.chars().all(|c| matches!(c, '-' | ':')) {
+        true => format!("\\{escaped}"),
+        false => escaped,
+    }
+}
+
+/// Cells of a table row without its outer pipes, splitting on pipes that
+/// are not escaped. A backslash before ASCII punctuation is dropped.
+fn split_cells(inner: &str) -> Vec<String> {
+    let (mut cells, mut cell) = (Vec::new(), String::new());
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(char::is_ascii_punctuation) => cell.extend(chars.next()),
+            '|' => cells.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells.iter().map(|c| c.trim().to_string()).collect()
+}
+
+/// The inlines of imported text: math between `$$` or `$`, and plain text
+/// with `\$` read as a dollar sign. As in Pandoc, inline math cannot start
+/// or end with a space or be followed by a digit, so `$5 and $10` stays
+/// text.
+fn parse_inlines(text: &str, interner: &mut StringInterner) -> Vec<Inline> {
+    if !text.contains('$') {
+        return vec![Inline::Text { value: interner.intern(text) }];
+    }
+    let mut out = Vec::new();
+    let mut buf = String::new();
+    let mut rest = text;
+    while let Some(at) = rest.find('$') {
+        let (before, from) = rest.split_at(at);
+        if let Some(before) = before.strip_suffix('\\') {
+            buf.push_str(before);
+            buf.push('$');
+            rest = &from[1..];
+            continue;
+        }
+        buf.push_str(before);
+        match closing_math(from) {
+            Some((tex, display, len)) => {
+                if !buf.is_empty() {
+                    out.push(Inline::Text { value: interner.intern(&std::mem::take(&mut buf)) });
+                }
+                out.push(Inline::Math { tex: interner.intern(tex), display });
+                rest = &from[len..];
+            }
+            None => {
+                buf.push('$');
+                rest = &from[1..];
+            }
+        }
+    }
+    buf.push_str(rest);
+    if !buf.is_empty() || out.is_empty() {
+        out.push(Inline::Text { value: interner.intern(&buf) });
+    }
+    out
+}
+
+/// The TeX, display flag and byte length of the math `text` opens with.
+fn closing_math(text: &str) -> Option<(&str, bool, usize)> {
+    if let Some(body) = text.strip_prefix("$$") {
+        let end = body.find("$$").filter(|end| *end > 0)?;
+        return Some((&body[..end], true, end + 4));
+    }
+    let body = &text[1..];
+    let end = body.find('$')?;
+    let tex = &body[..end];
+    let spaced = tex.starts_with(char::is_whitespace) || tex.ends_with(char::is_whitespace);
+    if tex.is_empty() || spaced || body[end + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((tex, false, end + 2))
 }
 
 fn parse_heading(line: &str) -> Option<(u8, String)> {
     let trimmed = line.trim();
     let level = trimmed.chars().take_while(|c| *c == '#').count();
-    if level == 0 || level > 3 {
+    if level == 0 || level > 6 {
         return None;
     }
     let text = trimmed.trim_start_matches('#').trim();
     if text.is_empty() {
         return None;
     }
-    Some((level as u8, text.to_string()))
+    Some((level as u8, unescape_first(text).to_string()))
 }
 
-fn parse_list(line: &str) -> Option<(bool, String)> {
+/// Ordered or not, nesting level, task state and text of a list line.
+/// Every two spaces or one tab of indentation is a level.
+fn parse_list(line: &str) -> Option<(bool, u8, Option<bool>, String)> {
     let trimmed = line.trim();
+    let indent: usize = line.chars().take_while(|c| c.is_whitespace()).map(|c| if c == '\t' { 2 } else { 1 }).sum();
+    let level = (indent / 2).min(u8::MAX as usize) as u8;
+    let item = |ordered, rest: &str| {
+        if let Some((checked, text)) = task_box(rest) {
+            return Some((ordered, level, Some(checked), text.to_string()));
+        }
+        // The exporter escapes item text that would read as a task box.
+        let text = match rest.strip_prefix('\\') {
+            Some(inner) if task_box(inner.strip_prefix('\\').unwrap_or(inner)).is_some() => inner,
+            _ => rest,
+        };
+        Some((ordered, level, None, text.to_string()))
+    };
     if let Some(rest) = trimmed.strip_prefix("- ") {
-        return Some((false, rest.to_string()));
+        return item(false, rest);
     }
     if let Some(pos) = trimmed.find(". ") {
         let (num, rest) = trimmed.split_at(pos);
         if num.chars().all(|c| c.is_ascii_digit()) {
-            return Some((true, rest.trim_start_matches(". ").to_string()));
+            return item(true, &rest[2..]);
         }
     }
     None
 }
 
+/// Whether the `[ ]` or `[x]` box `text` starts with is ticked, and the
+/// text after it and its space.
+fn task_box(text: &str) -> Option<(bool, &str)> {
+    let checked = match text.get(..3)? {
+        "[ ]" => false,
+        "[x]" | "[X]" => true,
+        _ => return None,
+    };
+    match &text[3..] {
+        "" => Some((checked, "")),
+        rest => rest.strip_prefix(' ').map(|rest| (checked, rest)),
+    }
+}
+
+const CUSTOM_COMMENT: &str = "<!-- wa-custom ";
+/// LaTeX's page break, in a comment so other renderers leave it out. A bare
+/// `\pagebreak` line imports as one too.
+const PAGE_BREAK: &str = "<!-- \\pagebreak -->";
+
+/// Custom blocks have no markdown form; they travel as an HTML comment
+/// holding the kind and the JSON payload.
+pub(crate) fn custom_comment(kind: &str, data: &serde_json::Value) -> String {
+    // "->" only occurs inside JSON strings, where '>' may be escaped.
+    let json = data.to_string().replace("->", "-\\u003e");
+    format!("{CUSTOM_COMMENT}{kind} {json} -->")
+}
+
+fn parse_custom_comment(line: &str) -> Option<(&str, serde_json::Value)> {
+    let body = line.strip_prefix(CUSTOM_COMMENT)?.strip_suffix("-->")?;
+    let (kind, json) = body.split_once(' ')?;
+    let data = serde_json::from_str(json.trim()).ok()?;
+    (!kind.is_empty()).then_some((kind, data))
+}
+
 fn parse_image(line: &str) -> Option<(String, String)> {
     let cap_start = line.find("![")? + 2;
-    let cap_end = line.find("](")?;
+    let cap_end = cap_start + line.get(cap_start..)?.find("](")?;
     let url_start = cap_end + 2;
-    let url_end = line.rfind(')')?;
-    let cap = line[cap_start..cap_end].to_string();
-    let url = line[url_start..url_end].to_string();
+    let url_end = line.rfind(')').filter(|end| *end >= url_start)?;
+    let cap = line.get(cap_start..cap_end)?.to_string();
+    let url = line.get(url_start..url_end)?.to_string();
     Some((cap, url))
 }
 
-fn inline_text(inlines: &[Inline]) -> String {
+/// Inline markdown: `**`, `*` and `~~` around styled runs, `<u>` for
+/// underline, backticks around code, `[text](url)` for links and `$` or
+/// `$$` around math. A `$` in text is written `\$`.
+fn inline_markdown(inlines: &[Inline]) -> String {
     let mut out = String::new();
     for inline in inlines {
         match inline {
-            Inline::Text { value } => out.push_str(value.as_ref()),
-            Inline::CodeSpan { value } => out.push_str(value.as_ref()),
-            Inline::Link { text, .. } => out.push_str(&inline_text(text)),
-            Inline::Styled { content, .. } => out.push_str(&inline_text(content)),
+            Inline::Text { value } => out.push_str(&value.replace('$', "\\$")),
+            Inline::Math { tex, display } => {
+                let fence = if *display { "$$" } else { "$" };
+                out.push_str(&format!("{fence}{tex}{fence}"));
+            }
+            Inline::CodeSpan { value } => {
+                let longest = value.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+                let fence = "`".repeat(longest + 1);
+                let pad = if value.starts_with('`') || value.ends_with('`') { " " } else { "" };
+                out.push_str(&format!("{fence}{pad}{value}{pad}{fence}"));
+            }
+            Inline::Link { text, url } => out.push_str(&format!("[{}]({})", inline_markdown(text), url)),
+            Inline::Styled { style, content } => {
+                let text = inline_markdown(content);
+                let trimmed = text.trim();
+                if trimmed.is_empty() {
+                    out.push_str(&text);
+                    continue;
+                }
+                // Emphasis cannot open or close next to whitespace, so the
+                // markers go inside it.
+                let lead = &text[..text.len() - text.trim_start().len()];
+                let trail = &text[text.trim_end().len()..];
+                let mut open = String::new();
+                for (on, marker) in [(style.bold, "**"), (style.italic, "*"), (style.strikethrough, "~~"), (style.underline, "<u>")] {
+                    if on {
+                        open.push_str(marker);
+                    }
+                }
+                let close: String = [(style.underline, "</u>"), (style.strikethrough, "~~"), (style.italic, "*"), (style.bold, "**")]
+                    .into_iter()
+                    .filter(|(on, _)| *on)
+                    .map(|(_, marker)| marker)
+                    .collect();
+                out.push_str(&format!("{lead}{open}{trimmed}{close}{trail}"));
+            }
         }
     }
     out
@@ -1,43 +1,25 @@
-﻿use crate::{import_markdown, Block, Document, Inline, StringInterner};
+﻿use crate::{
+    block_plain_text, export_html, export_json, export_markdown, import_markdown, Block, Document, Error, Inline,
+    StringInterner,
+};
 #[cfg(feature = "export_docx")]
-use crate::{export_docx_bytes as export_docx_native, export_pdf_bytes as export_pdf_native};
+use crate::{export_docx_bytes as export_docx_native, export_pdf_bytes_with, PdfOptions};
 use std::sync::Arc;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-#[derive(Debug)]
-pub enum ImportError {
-    Io(String),
-    Unsupported(String),
-}
-
-pub fn import_any(path: &Path) -> Result<Document, ImportError> {
+pub fn import_any(path: &Path) -> Result<Document, Error> {
     let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
     match ext.as_str() {
-        "md" | "markdown" => {
-            let raw = read_text(path)?;
-            Ok(import_markdown(&raw))
-        }
-        "txt" => {
-            let raw = read_text(path)?;
-            Ok(import_plaintext(&raw))
-        }
-        "html" | "htm" => {
-            let raw = read_text(path)?;
-            Ok(import_html(&raw))
-        }
-        "json" => {
-            let raw = read_text(path)?;
-            super::import_json(&raw).map_err(|e| ImportError::Io(e.to_string()))
-        }
         "docx" | "doc" | "odt" | "rtf" | "pdf" => {
-            let text = extract_via_python(path)?;
+            let text = extract_via_python(path, &ext)?;
             Ok(import_plaintext(&text))
         }
+        _ if text_format(&ext).is_some() => import_str(&read_text(path)?, &ext),
         _ => {
-            let text = extract_via_python(path).unwrap_or_default();
+            let text = extract_via_python(path, &ext).unwrap_or_default();
             if text.trim().is_empty() {
-                Err(ImportError::Unsupported(ext))
+                Err(Error::Unsupported(ext))
             } else {
                 Ok(import_plaintext(&text))
             }
@@ -45,22 +27,52 @@ pub fn import_any(path: &Path) -> Result<Document, ImportError> {
     }
 }
 
-fn extract_via_python(path: &Path) -> Result<String, ImportError> {
+/// Canonical name of a text format ("md", "txt", "html" or "json") given
+/// it or one of its other extensions.
+pub fn text_format(name: &str) -> Option<&'static str> {
+    match name.to_ascii_lowercase().as_str() {
+        "md" | "markdown" => Some("md"),
+        "txt" | "text" => Some("txt"),
+        "html" | "htm" => Some("html"),
+        "json" => Some("json"),
+        _ => None,
+    }
+}
+
+/// Imports `raw` as the text format `format`; see `text_format`.
+pub fn import_str(raw: &str, format: &str) -> Result<Document, Error> {
+    match text_format(format) {
+        Some("md") => Ok(import_markdown(raw)),
+        Some("txt") => Ok(import_plaintext(raw)),
+        Some("html") => Ok(import_html(raw)),
+        Some(_) => super::import_json(raw),
+        None => Err(Error::Unsupported(format.to_string())),
+    }
+}
+
+/// Exports `doc` as the text format `format`; see `text_format`.
+pub fn export_str(doc: &Document, format: &str) -> Result<String, Error> {
+    match text_format(format) {
+        Some("md") => Ok(export_markdown(doc)),
+        Some("txt") => Ok(export_plaintext(doc)),
+        Some("html") => Ok(export_html(doc)),
+        Some(_) => export_json(doc),
+        None => Err(Error::Unsupported(format.to_string())),
+    }
+}
+
+fn extract_via_python(path: &Path, format: &str) -> Result<String, Error> {
     let root = project_root();
     let script = root.join("engine").join("tools").join("extract_text.py");
-    let output = Command::new("python")
-        .arg(script)
-        .arg(path)
-        .output()
-        .map_err(|e| ImportError::Io(e.to_string()))?;
+    let output = Command::new("python").arg(script).arg(path).output()?;
     if !output.status.success() {
-        return Err(ImportError::Io(String::from_utf8_lossy(&output.stderr).to_string()));
+        return Err(Error::parse(format, String::from_utf8_lossy(&output.stderr)));
     }
     Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
-fn read_text(path: &Path) -> Result<String, ImportError> {
-    std::fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))
+fn read_text(path: &Path) -> Result<String, Error> {
+    Ok(std::fs::read_to_string(path)?)
 }
 
 pub fn import_plaintext(raw: &str) -> Document {
@@ -83,12 +95,18 @@ pub fn import_plaintext(raw: &str) -> Document {
             id: uuid::Uuid::new_v4(),
             content: vec![Inline::Text { value: interner.intern(raw.trim()) }],
             dirty: false,
+            locked: false,
         });
     }
     doc.blocks = blocks;
     doc
 }
 
+/// Plain text of every block, separated by blank lines.
+pub fn export_plaintext(doc: &Document) -> String {
+    doc.blocks.iter().map(block_plain_text).collect::<Vec<_>>().join("\n\n")
+}
+
 fn flush_para(blocks: &mut Vec<Block>, buf: &mut Vec<String>, interner: &mut StringInterner) {
     if buf.is_empty() {
         return;
@@ -99,6 +117,7 @@ fn flush_para(blocks: &mut Vec<Block>, buf: &mut Vec<String>, interner: &mut Str
             id: uuid::Uuid::new_v4(),
             content: vec![Inline::Text { value: interner.intern(&text) }],
             dirty: false,
+            locked: false,
         });
     }
     buf.clear();
@@ -110,69 +129,57 @@ pub fn import_html(raw: &str) -> Document {
     let mut blocks = Vec::new();
     let mut current: Vec<Inline> = Vec::new();
     let mut interner = StringInterner::new();
-    for inline in parse_html_inlines(raw) {
-        match inline {
-            Inline::Text { value } if value.as_ref() == "
-" => {
-                if !current.is_empty() {
-                    blocks.push(Block::Paragraph {
-                        id: uuid::Uuid::new_v4(),
-                        content: std::mem::take(&mut current),
-                        dirty: false,
-                    });
-                }
-            }
-            Inline::Text { value } if value.as_ref().contains('\n') => {
-                let parts = value.as_ref().split('\n');
-                for (idx, part) in parts.enumerate() {
-                    if !part.is_empty() {
-                        current.push(Inline::Text { value: interner.intern(part) });
-                    }
-                    if idx != 0 {
-                        if !current.is_empty() {
-                            blocks.push(Block::Paragraph {
-                                id: uuid::Uuid::new_v4(),
-                                content: std::mem::take(&mut current),
-                                dirty: false,
-                            });
-                        }
-                    }
-                }
-            }
-            other => current.push(other),
+    for event in parse_html_events(raw) {
+        match event {
+            HtmlEvent::Inline(inline) => current.push(inline),
+            HtmlEvent::Break => flush_html_paragraph(&mut blocks, &mut current),
         }
     }
-    if !current.is_empty() {
-        blocks.push(Block::Paragraph {
-            id: uuid::Uuid::new_v4(),
-            content: current,
-            dirty: false,
-        });
-    }
+    flush_html_paragraph(&mut blocks, &mut current);
     if blocks.is_empty() {
         blocks.push(Block::Paragraph {
             id: uuid::Uuid::new_v4(),
             content: vec![Inline::Text { value: interner.intern(raw.trim()) }],
             dirty: false,
+            locked: false,
         });
     }
+    blocks.iter_mut().for_each(crate::normalize_block);
     doc.blocks = blocks;
+    interner.intern_document(&mut doc);
     doc
 }
 
+/// Ends the paragraph collected so far; blank ones left over from the HTML
+/// source between block tags are dropped.
+fn flush_html_paragraph(blocks: &mut Vec<Block>, current: &mut Vec<Inline>) {
+    let content = std::mem::take(current);
+    let mut text = String::new();
+    crate::inline_plain_text(&content, &mut text);
+    if text.trim().is_empty() {
+        return;
+    }
+    blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content, dirty: false, locked: false });
+}
+
 // Basic rich HTML import (tables/lists/images). Best-effort.
 pub fn import_html_rich(raw: &str) -> Document {
     let lower = raw.to_lowercase();
-    if lower.contains("<table") {
-        return import_html_table(raw);
-    }
-    if lower.contains("<ul") || lower.contains("<ol") || lower.contains("<li") {
-        return import_html_list(raw);
-    }
-    if lower.contains("<img") {
-        return import_html_image(raw);
-    }
-    import_html(raw)
+    let mut doc = if lower.contains("<table") {
+        import_html_table(raw)
+    } else if lower.contains("<ul") || lower.contains("<ol") || lower.contains("<li") {
+        import_html_list(raw)
+    } else if lower.contains("<img") {
+        import_html_image(raw)
+    } else {
+        import_html(raw)
+    };
+    doc.blocks.iter_mut().for_each(crate::normalize_block);
+    // Pasted rows can have any number of cells.
+    let mut doc = crate::sanitize_doc(doc);
+    // Pasted tables and lists repeat short cells a lot.
+    StringInterner::new().intern_document(&mut doc);
+    doc
 }
 
 fn import_html_table(raw: &str) -> Document {
@@ -181,6 +188,7 @@ fn import_html_table(raw: &str) -> Document {
     for tr in raw.split("<tr").skip(1) {
         let mut row = Vec::new();
         for td in tr.split("<td").skip(1) {
+            let td = element_body(td);
             let inlines = parse_html_inlines(td);
             let content = if inlines.is_empty() {
                 vec![Inline::Text { value: Arc::from(strip_html(td)) }]
@@ -197,7 +205,10 @@ fn import_html_table(raw: &str) -> Document {
         doc.blocks.push(Block::Table {
             id: uuid::Uuid::new_v4(),
             rows,
+            header: false,
+            col_widths: Vec::new(),
             dirty: false,
+            locked: false,
         });
     } else {
         doc = import_html(raw);
@@ -209,6 +220,7 @@ fn import_html_list(raw: &str) -> Document {
     let mut doc = Document::new();
     let mut items = Vec::new();
     for li in raw.split("<li").skip(1) {
+        let li = element_body(li);
         let inlines = parse_html_inlines(li);
         let text = strip_html(li);
         if !text.trim().is_empty() || !inlines.is_empty() {
@@ -219,6 +231,8 @@ fn import_html_list(raw: &str) -> Document {
                 } else {
                     inlines
                 },
+                level: 0,
+                checked: None,
             });
         }
     }
@@ -228,6 +242,8 @@ fn import_html_list(raw: &str) -> Document {
             ordered: raw.to_lowercase().contains("<ol"),
             items,
             dirty: false,
+            locked: false,
+            toc: false,
         });
     } else {
         doc = import_html(raw);
@@ -237,15 +253,16 @@ fn import_html_list(raw: &str) -> Document {
 
 fn import_html_image(raw: &str) -> Document {
     let mut doc = Document::new();
-    let lower = raw.to_lowercase();
+    // ASCII lowercasing keeps byte offsets valid in `raw`.
+    let lower = raw.to_ascii_lowercase();
     let mut url = None;
     if let Some(idx) = lower.find("src=") {
         let tail = &raw[idx + 4..];
-        let quote = tail.chars().next().unwrap_or('"');
-        let rest = if quote == '"' || quote == '\'' { &tail[1..] } else { tail };
-        if let Some(end) = rest.find(quote) {
-            url = Some(rest[..end].to_string());
-        }
+        let rest = match tail.chars().next() {
+            Some(quote @ ('"' | '\'')) => tail[1..].find(quote).map(|end| &tail[1..1 + end]),
+            _ => tail.split(|c: char| c.is_whitespace() || c == '>').next().filter(|u| !u.is_empty()),
+        };
+        url = rest.map(str::to_string);
     }
     if let Some(u) = url {
         doc.blocks.push(Block::Figure {
@@ -254,6 +271,7 @@ fn import_html_image(raw: &str) -> Document {
             caption: Some(Arc::from("图片")),
             size: None,
             dirty: false,
+            locked: false,
         });
     } else {
         doc = import_html(raw);
@@ -261,80 +279,156 @@ fn import_html_image(raw: &str) -> Document {
     doc
 }
 
+/// What the inline HTML parser produces: styled runs, and the breaks that
+/// `<br>` and `<p>` boundaries stand for.
+enum HtmlEvent {
+    Inline(Inline),
+    Break,
+}
+
+/// Inline content of an HTML fragment that holds a single cell or list item.
+/// Breaks inside it become spaces.
 fn parse_html_inlines(html: &str) -> Vec<Inline> {
+    let mut out = Vec::new();
+    let mut pending_break = false;
+    for event in parse_html_events(html) {
+        match event {
+            HtmlEvent::Break => pending_break = !out.is_empty(),
+            HtmlEvent::Inline(inline) => {
+                if std::mem::take(&mut pending_break) {
+                    out.push(Inline::Text { value: Arc::from(" ") });
+                }
+                out.push(inline);
+            }
+        }
+    }
+    out
+}
+
+fn parse_html_events(html: &str) -> Vec<HtmlEvent> {
     let mut out = Vec::new();
     let mut bold = false;
     let mut italic = false;
     let mut underline = false;
     let mut strikethrough = false;
     let mut buf = String::new();
-    let mut chars = html.chars().peekable();
+    let mut text = HtmlWhitespace::default();
+    let mut chars = html.chars();
     while let Some(ch) = chars.next() {
         if ch == '<' {
             if !buf.is_empty() {
-                push_styled(&mut out, &mut buf, bold, italic, underline, strikethrough);
-            }
-            let mut tag = String::new();
-            while let Some(c) = chars.next() {
-                if c == '>' { break; }
-                tag.push(c);
+                out.push(HtmlEvent::Inline(styled_run(&mut buf, bold, italic, underline, strikethrough)));
             }
-            let t = tag.trim().to_lowercase();
-            match t.as_str() {
-                "b" | "strong" => bold = true,
-                "/b" | "/strong" => bold = false,
-                "i" | "em" => italic = true,
-                "/i" | "/em" => italic = false,
-                "u" => underline = true,
-                "/u" => underline = false,
-                "s" | "strike" | "del" => strikethrough = true,
-                "/s" | "/strike" | "/del" => strikethrough = false,
-                "br" | "br/" | "/p" | "p" => {
-                    if !buf.is_empty() {
-                        push_styled(&mut out, &mut buf, bold, italic, underline, strikethrough);
-                    }
-                    out.push(Inline::Text { value: Arc::from("\n") });
+            let tag: String = chars.by_ref().take_while(|c| *c != '>').collect();
+            let (closing, name) = text.tag(&tag);
+            match name.as_str() {
+                "b" | "strong" => bold = !closing,
+                "i" | "em" => italic = !closing,
+                "u" => underline = !closing,
+                "s" | "strike" | "del" => strikethrough = !closing,
+                "br" | "p" => {
+                    out.push(HtmlEvent::Break);
+                    text.line_start();
                 }
                 _ => {}
             }
         } else {
-            buf.push(ch);
+            text.push(ch, &mut buf);
         }
     }
     if !buf.is_empty() {
-        push_styled(&mut out, &mut buf, bold, italic, underline, strikethrough);
+        out.push(HtmlEvent::Inline(styled_run(&mut buf, bold, italic, underline, strikethrough)));
     }
     out
 }
 
-fn push_styled(out: &mut Vec<Inline>, buf: &mut String, bold: bool, italic: bool, underline: bool, strikethrough: bool) {
-    let text = std::mem::take(buf);
+fn styled_run(buf: &mut String, bold: bool, italic: bool, underline: bool, strikethrough: bool) -> Inline {
+    let text = Inline::Text { value: Arc::from(std::mem::take(buf)) };
     if bold || italic || underline || strikethrough {
-        out.push(Inline::Styled {
-            style: crate::Style { bold, italic, underline, strikethrough },
-            content: vec![Inline::Text { value: Arc::from(text) }],
-        });
+        Inline::Styled { style: crate::Style { bold, italic, underline, strikethrough }, content: vec![text] }
     } else {
-        out.push(Inline::Text { value: Arc::from(text) });
+        text
+    }
+}
+
+/// Source whitespace handling for HTML text: runs of ASCII whitespace become
+/// one space, or nothing between two CJK characters, and whitespace at the
+/// start or end of a line is dropped. Text inside `<pre>` and `<code>` is
+/// kept verbatim.
+#[derive(Default)]
+struct HtmlWhitespace {
+    pending_space: bool,
+    last: Option<char>,
+    verbatim: usize,
+}
+
+impl HtmlWhitespace {
+    /// Tracks `<pre>`/`<code>` nesting for the tag body `tag` and returns
+    /// whether it closes an element, along with its lowercase name.
+    fn tag(&mut self, tag: &str) -> (bool, String) {
+        let tag = tag.trim();
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_ascii_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if matches!(name.as_str(), "pre" | "code") && !tag.ends_with('/') {
+            if closing {
+                self.verbatim = self.verbatim.saturating_sub(1);
+            } else {
+                self.verbatim += 1;
+            }
+        }
+        (closing, name)
     }
+
+    fn line_start(&mut self) {
+        self.pending_space = false;
+        self.last = None;
+    }
+
+    fn push(&mut self, ch: char, out: &mut String) {
+        if self.verbatim == 0 && ch.is_ascii_whitespace() {
+            self.pending_space = self.last.is_some();
+            return;
+        }
+        if std::mem::take(&mut self.pending_space) && !(self.last.is_some_and(joins_tightly) && joins_tightly(ch)) {
+            out.push(' ');
+        }
+        out.push(ch);
+        self.last = Some(ch);
+    }
+}
+
+/// CJK text is written without spaces, so source line breaks between two
+/// such characters must not turn into one.
+fn joins_tightly(ch: char) -> bool {
+    crate::is_cjk(ch) || matches!(ch as u32, 0x3000..=0x303F | 0xFF00..=0xFFEF)
 }
 
-#[allow(dead_code)]
+/// Text content of an HTML fragment, whitespace handled as in
+/// `HtmlWhitespace`.
 fn strip_html(html: &str) -> String {
     let mut out = String::new();
-    let mut in_tag = false;
-    for ch in html.chars() {
-        match ch {
-            '<' => in_tag = true,
-            '>' => in_tag = false,
-            _ => {
-                if !in_tag {
-                    out.push(ch);
-                }
-            }
+    let mut text = HtmlWhitespace::default();
+    let mut chars = html.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '<' {
+            let tag: String = chars.by_ref().take_while(|c| *c != '>').collect();
+            text.tag(&tag);
+        } else {
+            text.push(ch, &mut out);
         }
     }
-    out.split_whitespace().collect::<Vec<_>>().join(" ")
+    out
+}
+
+/// What follows the opening tag of a fragment cut at `<td`, `<li` and the
+/// like.
+fn element_body(fragment: &str) -> &str {
+    fragment.split_once('>').map_or("", |(_, body)| body)
 }
 
 fn project_root() -> PathBuf {
@@ -345,13 +439,18 @@ fn project_root() -> PathBuf {
 }
 
 #[cfg(feature = "export_docx")]
-pub fn export_docx(doc: &Document, out_path: &Path) -> Result<(), ImportError> {
-    let payload = export_docx_native(doc).map_err(|e| ImportError::Io(e.to_string()))?;
-    std::fs::write(out_path, payload).map_err(|e| ImportError::Io(e.to_string()))
+pub fn export_docx(doc: &Document, out_path: &Path) -> Result<(), Error> {
+    let payload = export_docx_native(doc)?;
+    Ok(std::fs::write(out_path, payload)?)
+}
+
+#[cfg(feature = "export_docx")]
+pub fn export_pdf(doc: &Document, out_path: &Path) -> Result<(), Error> {
+    export_pdf_with(doc, out_path, &PdfOptions::default())
 }
 
 #[cfg(feature = "export_docx")]
-pub fn export_pdf(doc: &Document, out_path: &Path) -> Result<(), ImportError> {
-    let payload = export_pdf_native(doc).map_err(|e| ImportError::Io(e.to_string()))?;
-    std::fs::write(out_path, payload).map_err(|e| ImportError::Io(e.to_string()))
+pub fn export_pdf_with(doc: &Document, out_path: &Path, options: &PdfOptions) -> Result<(), Error> {
+    let payload = export_pdf_bytes_with(doc, options)?;
+    Ok(std::fs::write(out_path, payload)?)
 }
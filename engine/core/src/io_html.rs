@@ -0,0 +1,145 @@
+use crate::{custom_comment, Block, Document, Inline, Style};
+use std::fmt::Write;
+
+/// HTML fragment for `doc`, one element per block. Styles and links are
+/// kept; custom blocks travel as the same comment `export_markdown` writes.
+pub fn export_html(doc: &Document) -> String {
+    let mut out = String::new();
+    for block in &doc.blocks {
+        write_block(block, &mut out);
+        out.push('\n');
+    }
+    out
+}
+
+fn write_block(block: &Block, out: &mut String) {
+    match block {
+        Block::Heading { level, content, .. } => {
+            let level = (*level).clamp(1, 6);
+            let _ = write!(out, "<h{level}>");
+            write_inlines(content, out);
+            let _ = write!(out, "</h{level}>");
+        }
+        Block::Paragraph { content, .. } => {
+            out.push_str("<p>");
+            write_inlines(content, out);
+            out.push_str("</p>");
+        }
+        Block::List { ordered, items, .. } => {
+            let tag = if *ordered { "ol" } else { "ul" };
+            let _ = write!(out, "<{tag}>");
+            for item in items {
+                match item.level {
+                    0 => out.push_str("<li>"),
+                    level => {
+                        let _ = write!(out, "<li style=\"margin-left: {}em\">", u32::from(level) * 2);
+                    }
+                }
+                write_inlines(&item.content, out);
+                out.push_str("</li>");
+            }
+            let _ = write!(out, "</{tag}>");
+        }
+        Block::Quote { content, .. } => {
+            out.push_str("<blockquote>");
+            for inner in content {
+                write_block(inner, out);
+            }
+            out.push_str("</blockquote>");
+        }
+        Block::Code { lang, code, .. } => {
+            out.push_str("<pre><code");
+            if !lang.is_empty() {
+                out.push_str(" class=\"language-");
+                escape_into(lang, out);
+                out.push('"');
+            }
+            out.push('>');
+            code.chunks().for_each(|chunk| escape_into(chunk, out));
+            out.push_str("</code></pre>");
+        }
+        Block::Table { rows, .. } => {
+            out.push_str("<table>");
+            for row in rows {
+                out.push_str("<tr>");
+                for cell in row {
+                    out.push_str("<td>");
+                    write_inlines(&cell.content, out);
+                    out.push_str("</td>");
+                }
+                out.push_str("</tr>");
+            }
+            out.push_str("</table>");
+        }
+        Block::Figure { url, caption, .. } => {
+            out.push_str("<figure><img src=\"");
+            escape_into(url, out);
+            out.push_str("\" alt=\"");
+            escape_into(caption.as_deref().unwrap_or(""), out);
+            out.push_str("\">");
+            if let Some(caption) = caption {
+                out.push_str("<figcaption>");
+                escape_into(caption, out);
+                out.push_str("</figcaption>");
+            }
+            out.push_str("</figure>");
+        }
+        Block::PageBreak { .. } => out.push_str("<div style=\"break-after: page\"></div>"),
+        Block::Custom { kind, data, .. } => out.push_str(&custom_comment(kind, data)),
+    }
+}
+
+fn write_inlines(inlines: &[Inline], out: &mut String) {
+    for inline in inlines {
+        match inline {
+            Inline::Text { value } => escape_into(value, out),
+            Inline::CodeSpan { value } => {
+                out.push_str("<code>");
+                escape_into(value, out);
+                out.push_str("</code>");
+            }
+            // The TeX as written, for a script such as KaTeX to render.
+            Inline::Math { tex, display } => {
+                out.push_str(if *display { "<span class=\"math display\">" } else { "<span class=\"math\">" });
+                escape_into(tex, out);
+                out.push_str("</span>");
+            }
+            Inline::Styled { style, content } => {
+                let tags = style_tags(*style);
+                tags.iter().for_each(|tag| {
+                    let _ = write!(out, "<{tag}>");
+                });
+                write_inlines(content, out);
+                tags.iter().rev().for_each(|tag| {
+                    let _ = write!(out, "</{tag}>");
+                });
+            }
+            Inline::Link { url, text } => {
+                out.push_str("<a href=\"");
+                escape_into(url, out);
+                out.push_str("\">");
+                write_inlines(text, out);
+                out.push_str("</a>");
+            }
+        }
+    }
+}
+
+fn style_tags(style: Style) -> Vec<&'static str> {
+    [(style.bold, "strong"), (style.italic, "em"), (style.underline, "u"), (style.strikethrough, "s")]
+        .into_iter()
+        .filter_map(|(on, tag)| on.then_some(tag))
+        .collect()
+}
+
+fn escape_into(text: &str, out: &mut String) {
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(ch),
+        }
+    }
+}
@@ -1,55 +1,233 @@
-﻿use crate::{Block, Document};
+﻿use crate::{repair_document, Block, Cell, Document, Error, FigureSize, Inline, RepairOptions};
+use serde::Serialize;
 use serde_json::Value;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
 
-pub fn export_json(doc: &Document) -> serde_json::Result<String> {
-    serde_json::to_string_pretty(doc)
+pub fn export_json(doc: &Document) -> Result<String, Error> {
+    serde_json::to_string_pretty(doc).map_err(export_error)
 }
 
-pub fn export_json_into(doc: &Document, buf: &mut Vec<u8>) -> serde_json::Result<()> {
+pub fn export_json_into(doc: &Document, buf: &mut Vec<u8>) -> Result<(), Error> {
     buf.clear();
     let target = doc.blocks.len().saturating_mul(128) + 256;
     if buf.capacity() < target {
         buf.reserve(target - buf.capacity());
     }
-    serde_json::to_writer(buf, doc)?;
-    Ok(())
+    serde_json::to_writer(buf, doc).map_err(export_error)
+}
+
+/// Compact JSON into `out`, reusing its allocation across calls.
+pub fn export_json_into_string(doc: &Document, out: &mut String) -> Result<(), Error> {
+    let mut buf = std::mem::take(out).into_bytes();
+    let result = export_json_into(doc, &mut buf);
+    if result.is_err() {
+        buf.clear();
+    }
+    // serde_json only writes UTF-8; the check guards against a writer change
+    // and costs less than serializing (see the serialize_json_string bench).
+    *out = String::from_utf8(buf).expect("serde_json wrote invalid UTF-8");
+    result
+}
+
+pub fn export_json_fast(doc: &Document) -> Result<String, Error> {
+    let mut out = String::new();
+    export_json_into_string(doc, &mut out)?;
+    Ok(out)
 }
 
-pub fn export_json_fast(doc: &Document) -> serde_json::Result<String> {
-    let mut buf = Vec::with_capacity(doc.blocks.len().saturating_mul(128) + 256);
-    export_json_into(doc, &mut buf)?;
-    Ok(unsafe { String::from_utf8_unchecked(buf) })
+/// How `export_json_to_file_with` replaces the file it saves to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// Keep the previous version as `<path>.bak`.
+    pub backup: bool,
+    /// Read the written file back with `import_json` before it replaces
+    /// the old one.
+    pub verify: bool,
 }
 
-pub fn export_json_to_file(doc: &Document, path: &std::path::Path) -> serde_json::Result<()> {
-    let file = std::fs::File::create(path)
-        .map_err(serde_json::Error::io)?;
-    let mut writer = std::io::BufWriter::new(file);
-    serde_json::to_writer(&mut writer, doc)?;
+pub fn export_json_to_file(doc: &Document, path: &Path) -> Result<(), Error> {
+    export_json_to_file_with(doc, path, SaveOptions::default())
+}
+
+/// Writes to `<path>.tmp`, syncs it and renames it over `path`, so an error
+/// or panic part way leaves the previous file as it was. `doc` can be
+/// anything that serializes as a `Document`.
+pub fn export_json_to_file_with<T: Serialize + ?Sized>(doc: &T, path: &Path, options: SaveOptions) -> Result<(), Error> {
+    let write_error = |err: std::io::Error| Error::write(path, err);
+    let tmp_path = with_suffix(path, "tmp");
+    let mut tmp = TempFile(Some(tmp_path.clone()));
+    let mut writer = BufWriter::new(File::create(&tmp_path).map_err(write_error)?);
+    serde_json::to_writer(&mut writer, doc).map_err(|err| match err.is_io() {
+        true => Error::write(path, err),
+        false => export_error(err),
+    })?;
+    let file = writer.into_inner().map_err(|err| write_error(err.into_error()))?;
+    file.sync_all().map_err(write_error)?;
+    drop(file);
+    if options.verify {
+        let raw = std::fs::read_to_string(&tmp_path).map_err(|err| Error::verify(path, err))?;
+        import_json(&raw).map_err(|err| Error::verify(path, err))?;
+    }
+    if options.backup && path.exists() {
+        std::fs::copy(path, with_suffix(path, "bak")).map_err(write_error)?;
+    }
+    std::fs::rename(&tmp_path, path).map_err(write_error)?;
+    tmp.0 = None;
+    sync_parent(path);
     Ok(())
 }
 
-pub fn import_json(raw: &str) -> serde_json::Result<Document> {
-    serde_json::from_str(raw)
+/// `path` with `.{suffix}` added after its full file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Removes a half-written file unless it was renamed into place.
+struct TempFile(Option<PathBuf>);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Makes the rename itself durable. Best effort: not every platform can
+/// open a directory.
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir).and_then(|dir| dir.sync_all()).ok();
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Writer failures stay `Io`; anything else means the document itself
+/// could not be serialized.
+fn export_error(err: serde_json::Error) -> Error {
+    if err.is_io() {
+        Error::from(err)
+    } else {
+        Error::export("json", err)
+    }
+}
+
+/// Parses a document and repairs values layout cannot use; see `validate_doc`.
+pub fn import_json(raw: &str) -> Result<Document, Error> {
+    import_json_with_fixups(raw).map(|(doc, _)| doc)
+}
+
+/// `import_json` that also returns what had to be repaired.
+pub fn import_json_with_fixups(raw: &str) -> Result<(Document, Vec<Fixup>), Error> {
+    let mut doc: Document = serde_json::from_str(raw)?;
+    let fixups = validate_doc(&mut doc);
+    Ok((doc, fixups))
 }
 
 pub fn upgrade_unknown_fields(raw: &Value) -> Value {
     raw.clone()
 }
 
-pub fn sanitize_doc(mut doc: Document) -> Document {
-    for block in doc.blocks.iter_mut() {
-        match block {
-            Block::Heading { dirty, .. }
-            | Block::Paragraph { dirty, .. }
-            | Block::List { dirty, .. }
-            | Block::Quote { dirty, .. }
-            | Block::Code { dirty, .. }
-            | Block::Table { dirty, .. }
-            | Block::Figure { dirty, .. } => {
-                *dirty = false;
+pub fn sanitize_doc(doc: Document) -> Document {
+    sanitize_doc_with(doc, &RepairOptions { merge_tables: false, ..RepairOptions::default() })
+}
+
+/// `sanitize_doc` that also makes the repairs in `repair`, such as merging
+/// the split tables of old Markdown imports.
+pub fn sanitize_doc_with(mut doc: Document, repair: &RepairOptions) -> Document {
+    validate_doc(&mut doc);
+    repair_document(&mut doc, repair);
+    doc.clear_dirty();
+    doc
+}
+
+/// A repair `validate_doc` made to a loaded document.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fixup {
+    /// A figure size that was not finite was dropped, or a non-positive one
+    /// raised to one pixel.
+    FigureSize { block_id: Uuid },
+    /// A heading level outside 1–6 was clamped; `level` is the old value.
+    HeadingLevel { block_id: Uuid, level: u8 },
+    /// Empty rows were removed and the rest padded or cut to the width of
+    /// the first row.
+    TableRows { block_id: Uuid },
+    /// Column widths that were not finite or not positive were reset.
+    ColumnWidths { block_id: Uuid },
+    /// An empty paragraph at the end of the document was removed; only
+    /// `fix_document` does this.
+    TrailingParagraph { block_id: Uuid },
+}
+
+impl fmt::Display for Fixup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fixup::FigureSize { block_id } => write!(f, "figure {}: invalid size", block_id),
+            Fixup::HeadingLevel { block_id, level } => write!(f, "heading {}: level {} out of range", block_id, level),
+            Fixup::TableRows { block_id } => write!(f, "table {}: uneven rows", block_id),
+            Fixup::ColumnWidths { block_id } => write!(f, "table {}: invalid column widths", block_id),
+            Fixup::TrailingParagraph { block_id } => write!(f, "paragraph {}: empty at the end", block_id),
+        }
+    }
+}
+
+/// Repairs values that would break layout (non-finite sizes, heading levels
+/// outside 1–6, ragged tables) in every block, nested ones included.
+/// Repaired blocks are marked dirty.
+pub fn validate_doc(doc: &mut Document) -> Vec<Fixup> {
+    let mut fixups = Vec::new();
+    doc.for_each_block_mut(&mut |block| {
+        let before = fixups.len();
+        validate_block(block, &mut fixups);
+        if fixups.len() > before {
+            block.set_dirty(true);
+        }
+    });
+    fixups
+}
+
+fn validate_block(block: &mut Block, fixups: &mut Vec<Fixup>) {
+    let block_id = block.id();
+    match block {
+        Block::Heading { level, .. } if !(1..=6).contains(level) => {
+            fixups.push(Fixup::HeadingLevel { block_id, level: *level });
+            *level = (*level).clamp(1, 6);
+        }
+        Block::Figure { size, .. } => match *size {
+            Some(sz) if !sz.width.is_finite() || !sz.height.is_finite() => {
+                fixups.push(Fixup::FigureSize { block_id });
+                *size = None;
+            }
+            Some(sz) if sz.width <= 0.0 || sz.height <= 0.0 => {
+                fixups.push(Fixup::FigureSize { block_id });
+                *size = Some(FigureSize { width: sz.width.max(1.0), height: sz.height.max(1.0) });
+            }
+            _ => {}
+        },
+        Block::Table { rows, col_widths, .. } => {
+            let cols = rows.iter().find(|r| !r.is_empty()).map(|r| r.len()).unwrap_or(0);
+            if rows.iter().any(|r| r.len() != cols) {
+                fixups.push(Fixup::TableRows { block_id });
+                rows.retain(|r| !r.is_empty());
+                for row in rows.iter_mut() {
+                    row.resize_with(cols, || Cell { content: vec![Inline::Text { value: Arc::from("") }] });
+                }
+            }
+            if col_widths.iter().any(|w| !w.is_finite() || *w <= 0.0) {
+                fixups.push(Fixup::ColumnWidths { block_id });
+                col_widths.clear();
             }
         }
+        _ => {}
     }
-    doc
 }
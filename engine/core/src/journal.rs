@@ -0,0 +1,184 @@
+use crate::{Editor, EditorCommand, Error, Selection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Records between syncs to disk by default.
+pub const JOURNAL_SYNC_EVERY: usize = 16;
+
+/// Write-ahead log of the commands run since the last full save, so they
+/// can be replayed onto that save after a crash. Each record is one line:
+/// the byte length of its JSON, a space, then the JSON holding the
+/// document version and selection the command ran at and the ids of the
+/// blocks it added. A record a crash cut short fails its length check and
+/// ends the journal there.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    /// Records written since the last sync.
+    unsynced: usize,
+    /// Records between syncs; 1 syncs after every append.
+    pub sync_every: usize,
+}
+
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    version: u64,
+    selection: Selection,
+    command: &'a EditorCommand,
+    #[serde(skip_serializing_if = "<[Uuid]>::is_empty")]
+    created: &'a [Uuid],
+}
+
+#[derive(Deserialize)]
+struct Record {
+    version: u64,
+    selection: Selection,
+    command: EditorCommand,
+    #[serde(default)]
+    created: Vec<Uuid>,
+}
+
+/// What `Journal::replay` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Replay {
+    pub applied: usize,
+    /// Records older than the document, already part of the save.
+    pub skipped: usize,
+    /// Byte offset of a damaged or cut off record replay stopped at.
+    pub stopped_at: Option<u64>,
+}
+
+impl Journal {
+    /// Opens `path` for appending, creating it when missing. A damaged
+    /// tail is cut off so new records follow the last readable one.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let write_error = |err: std::io::Error| Error::write(path, err);
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path).map_err(write_error)?;
+        let bytes = std::fs::read(path).map_err(write_error)?;
+        let (_, valid) = parse_records(&bytes);
+        if valid < bytes.len() {
+            file.set_len(valid as u64).map_err(write_error)?;
+        }
+        Ok(Self { path: path.to_path_buf(), file, unsynced: 0, sync_every: JOURNAL_SYNC_EVERY })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `cmd`, run on the document at `version` with `selection`.
+    /// `created` are the top-level blocks it added, in document order;
+    /// replay gives their new copies the same ids, so later records still
+    /// find them.
+    pub fn append(&mut self, cmd: &EditorCommand, version: u64, selection: Selection, created: &[Uuid]) -> Result<(), Error> {
+        let json = serde_json::to_string(&RecordRef { version, selection, command: cmd, created })?;
+        let record = format!("{} {}\n", json.len(), json);
+        self.file.write_all(record.as_bytes()).map_err(|err| Error::write(&self.path, err))?;
+        self.unsynced += 1;
+        if self.unsynced >= self.sync_every.max(1) {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Forces appended records to disk.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        if self.unsynced > 0 {
+            self.file.sync_data().map_err(|err| Error::write(&self.path, err))?;
+            self.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    /// Empties the journal once a full save holds everything in it.
+    pub fn truncate(&mut self) -> Result<(), Error> {
+        self.file.set_len(0).and_then(|()| self.file.sync_all()).map_err(|err| Error::write(&self.path, err))?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Closes the journal and deletes its file, for edits thrown away
+    /// unsaved.
+    pub fn discard(self) -> Result<(), Error> {
+        let path = self.path.clone();
+        drop(self);
+        std::fs::remove_file(&path).map_err(|err| Error::write(&path, err))
+    }
+
+    /// Runs the records of the journal at `path` on `editor`, which should
+    /// hold the last full save. Records older than its document were saved
+    /// already and are skipped. Undo past the save cannot be replayed, as
+    /// the history before it is gone.
+    pub fn replay(path: &Path, editor: &mut Editor) -> Result<Replay, Error> {
+        let bytes = std::fs::read(path)?;
+        let (records, valid) = parse_records(&bytes);
+        let saved_version = editor.doc.version;
+        let journal = editor.detach_journal();
+        let mut replay = Replay { stopped_at: (valid < bytes.len()).then_some(valid as u64), ..Replay::default() };
+        for record in records {
+            if record.version < saved_version {
+                replay.skipped += 1;
+                continue;
+            }
+            editor.selection = record.selection;
+            let before: HashSet<Uuid> = editor.doc.blocks.iter().map(|b| b.id()).collect();
+            editor.execute(record.command);
+            let fresh = editor.doc.blocks.iter_mut().filter(|b| !before.contains(&b.id()));
+            for (block, id) in fresh.zip(record.created) {
+                for pos in [&mut editor.selection.anchor, &mut editor.selection.focus] {
+                    if pos.block_id == block.id() {
+                        pos.block_id = id;
+                    }
+                }
+                *block.id_mut() = id;
+            }
+            replay.applied += 1;
+        }
+        if let Some(journal) = journal {
+            editor.attach_journal(journal);
+        }
+        Ok(replay)
+    }
+
+    /// Whether the journal at `path` holds at least one record.
+    pub fn has_records(path: &Path) -> bool {
+        std::fs::read(path).is_ok_and(|bytes| !parse_records(&bytes).0.is_empty())
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        self.sync().ok();
+    }
+}
+
+/// The readable records at the start of `bytes` and the length they take.
+fn parse_records(bytes: &[u8]) -> (Vec<Record>, usize) {
+    let mut records = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        let Some(record) = parse_record(&bytes[at..]) else {
+            break;
+        };
+        records.push(record.0);
+        at += record.1;
+    }
+    (records, at)
+}
+
+fn parse_record(bytes: &[u8]) -> Option<(Record, usize)> {
+    let space = bytes.iter().take(20).position(|b| *b == b' ')?;
+    let len: usize = std::str::from_utf8(&bytes[..space]).ok()?.parse().ok()?;
+    let start = space + 1;
+    let end = start.checked_add(len)?;
+    if bytes.get(end) != Some(&b'\n') {
+        return None;
+    }
+    let record = serde_json::from_slice(&bytes[start..end]).ok()?;
+    Some((record, end + 1))
+}
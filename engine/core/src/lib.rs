@@ -1,31 +1,74 @@
 ﻿mod ast;
+mod code_text;
 mod commands;
+mod compare;
 mod diff;
 #[cfg(feature = "export_docx")]
 mod docx;
 mod editor;
+mod error;
+mod find;
+mod fragment;
 mod history;
+mod inline_path;
+mod input_filter;
 mod interner;
 mod io;
 mod io_any;
+mod io_html;
 mod io_json;
+mod journal;
+mod lint;
+mod normalize;
 #[cfg(feature = "export_docx")]
 mod pdf;
+mod pdf_pages;
+mod plain_index;
+mod repair;
+mod restyle;
+mod search_index;
 mod selection;
+mod signature;
+mod stats;
 mod table;
+mod templates;
+mod text;
+mod toc;
+mod unicode_tables;
 
 pub use ast::*;
+pub use code_text::*;
 pub use commands::*;
+pub use compare::*;
 pub use diff::*;
 #[cfg(feature = "export_docx")]
 pub use docx::*;
 pub use editor::*;
+pub use error::*;
+pub use find::*;
+pub use fragment::*;
 pub use history::*;
+pub use inline_path::*;
+pub use input_filter::*;
 pub use interner::*;
 pub use io::*;
 pub use io_any::*;
+pub use io_html::*;
 pub use io_json::*;
+pub use journal::*;
+pub use lint::*;
+pub use normalize::*;
 #[cfg(feature = "export_docx")]
 pub use pdf::*;
+pub use pdf_pages::*;
+pub use plain_index::*;
+pub use repair::*;
+pub use restyle::*;
+pub use search_index::*;
 pub use selection::*;
+pub use signature::*;
+pub use stats::*;
 pub use table::*;
+pub use templates::*;
+pub use text::*;
+pub use toc::*;
@@ -0,0 +1,116 @@
+use crate::{block_plain_text, for_each_inline_list, validate_doc, Block, Document, Fixup, Inline};
+use std::fmt;
+use uuid::Uuid;
+
+/// A structural problem `lint_document` found in a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintFinding {
+    pub block_id: Uuid,
+    /// Index of the top-level block it is in.
+    pub index: usize,
+    pub rule: LintRule,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LintRule {
+    /// A heading more than one level below the heading before it.
+    HeadingJump { from: u8, to: u8 },
+    EmptyHeading,
+    /// Row `row` has `cells` cells where the first row that has any has
+    /// `expected`.
+    RaggedTable { row: usize, cells: usize, expected: usize },
+    MissingCaption,
+    EmptyLinkUrl,
+}
+
+impl fmt::Display for LintRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintRule::HeadingJump { from, to } => write!(f, "heading jumps from level {} to {}", from, to),
+            LintRule::EmptyHeading => write!(f, "empty heading"),
+            LintRule::RaggedTable { row, cells, expected } => {
+                write!(f, "table row {} has {} cells, expected {}", row + 1, cells, expected)
+            }
+            LintRule::MissingCaption => write!(f, "figure has no caption"),
+            LintRule::EmptyLinkUrl => write!(f, "link has an empty url"),
+        }
+    }
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block {}: {}", self.index + 1, self.rule)
+    }
+}
+
+/// Every finding in `doc`, in document order. Blocks nested in quotes are
+/// checked too.
+pub fn lint_document(doc: &Document) -> Vec<LintFinding> {
+    let mut findings = Vec::new();
+    let mut prev_level = None;
+    for (index, top) in doc.blocks.iter().enumerate() {
+        top.for_each_block(&mut |block| {
+            let mut push = |rule| findings.push(LintFinding { block_id: block.id(), index, rule });
+            match block {
+                Block::Heading { level, .. } => {
+                    if let Some(from) = prev_level.filter(|from| *level > from + 1) {
+                        push(LintRule::HeadingJump { from, to: *level });
+                    }
+                    prev_level = Some(*level);
+                    if block_plain_text(block).trim().is_empty() {
+                        push(LintRule::EmptyHeading);
+                    }
+                }
+                Block::Table { rows, .. } => {
+                    let expected = rows.iter().find(|row| !row.is_empty()).map_or(0, |row| row.len());
+                    if let Some((row, cells)) = rows.iter().map(|r| r.len()).enumerate().find(|(_, len)| *len != expected) {
+                        push(LintRule::RaggedTable { row, cells, expected });
+                    }
+                }
+                Block::Figure { caption, .. } if caption.as_ref().is_none_or(|c| c.trim().is_empty()) => {
+                    push(LintRule::MissingCaption);
+                }
+                _ => {}
+            }
+            // Quote paragraphs are visited on their own.
+            let mut empty_links = 0;
+            if !matches!(block, Block::Quote { .. }) {
+                for_each_inline_list(block, &mut |inlines| empty_links += count_empty_links(inlines));
+            }
+            for _ in 0..empty_links {
+                push(LintRule::EmptyLinkUrl);
+            }
+        });
+    }
+    findings
+}
+
+fn count_empty_links(inlines: &[Inline]) -> usize {
+    inlines
+        .iter()
+        .map(|inline| match inline {
+            Inline::Link { url, text } => usize::from(url.trim().is_empty()) + count_empty_links(text),
+            Inline::Styled { content, .. } => count_empty_links(content),
+            Inline::Text { .. } | Inline::CodeSpan { .. } | Inline::Math { .. } => 0,
+        })
+        .sum()
+}
+
+/// Applies the fixes that cannot lose content: the repairs of
+/// `validate_doc`, which pad ragged tables, and dropping empty paragraphs
+/// at the end of the document. The first block always stays.
+pub fn fix_document(doc: &mut Document) -> Vec<Fixup> {
+    let mut fixups = validate_doc(doc);
+    while doc.blocks.len() > 1 {
+        let last = doc.blocks.last().expect("more than one block");
+        if !matches!(last, Block::Paragraph { .. }) || !block_plain_text(last).trim().is_empty() {
+            break;
+        }
+        fixups.push(Fixup::TrailingParagraph { block_id: last.id() });
+        doc.blocks.pop();
+    }
+    if !fixups.is_empty() {
+        doc.touch();
+    }
+    fixups
+}
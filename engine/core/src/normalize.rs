@@ -0,0 +1,75 @@
+use crate::{Block, Inline, SharedStr, Style};
+
+/// Rewrites an inline tree into its canonical form without changing its
+/// text or effective styles: adjacent text runs are merged, styled spans
+/// that add nothing over their parent are flattened, equal styled siblings
+/// are combined and empty nodes are dropped. A list that ends up empty
+/// keeps one empty text run so the caret has somewhere to go.
+pub fn normalize_inlines(inlines: &mut Vec<Inline>) {
+    if inlines.is_empty() {
+        return;
+    }
+    let mut out = Vec::with_capacity(inlines.len());
+    normalize_into(std::mem::take(inlines), Style::default(), &mut out);
+    if out.is_empty() {
+        out.push(Inline::Text { value: SharedStr::from("") });
+    }
+    *inlines = out;
+}
+
+/// `normalize_inlines` on every inline list of `block`.
+pub fn normalize_block(block: &mut Block) {
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => normalize_inlines(content),
+        Block::List { items, .. } => items.iter_mut().for_each(|item| normalize_inlines(&mut item.content)),
+        Block::Quote { content, .. } => content.iter_mut().for_each(normalize_block),
+        Block::Table { rows, .. } => rows.iter_mut().flatten().for_each(|cell| normalize_inlines(&mut cell.content)),
+        Block::Code { .. } | Block::Figure { .. } | Block::PageBreak { .. } | Block::Custom { .. } => {}
+    }
+}
+
+fn normalize_into(inlines: Vec<Inline>, parent: Style, out: &mut Vec<Inline>) {
+    for inline in inlines {
+        match inline {
+            Inline::Text { value } | Inline::CodeSpan { value } | Inline::Math { tex: value, .. } if value.is_empty() => {}
+            Inline::Text { value } => match out.last_mut() {
+                Some(Inline::Text { value: last }) => {
+                    let mut merged = String::with_capacity(last.len() + value.len());
+                    merged.push_str(last);
+                    merged.push_str(&value);
+                    *last = SharedStr::from(merged);
+                }
+                _ => out.push(Inline::Text { value }),
+            },
+            Inline::CodeSpan { .. } | Inline::Math { .. } => out.push(inline),
+            Inline::Styled { style, content } => {
+                let effective = parent.union(style);
+                if effective == parent {
+                    normalize_into(content, parent, out);
+                    continue;
+                }
+                let style = effective.without(parent);
+                let mut inner = Vec::with_capacity(content.len());
+                normalize_into(content, effective, &mut inner);
+                if inner.is_empty() {
+                    continue;
+                }
+                match out.last_mut() {
+                    Some(Inline::Styled { style: last, content }) if *last == style => {
+                        let mut combined = std::mem::take(content);
+                        combined.extend(inner);
+                        normalize_into(combined, effective, content);
+                    }
+                    _ => out.push(Inline::Styled { style, content: inner }),
+                }
+            }
+            Inline::Link { url, text } => {
+                let mut inner = Vec::with_capacity(text.len());
+                normalize_into(text, parent, &mut inner);
+                if !inner.is_empty() {
+                    out.push(Inline::Link { url, text: inner });
+                }
+            }
+        }
+    }
+}
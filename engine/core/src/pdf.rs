@@ -1,45 +1,47 @@
-use crate::{Block, Document, Inline};
+﻿use crate::{pdf_pages, Document, Error, PdfOptions};
 use printpdf::{PdfDocument, Mm, IndirectFontRef};
+use std::path::Path;
 
-#[derive(thiserror::Error, Debug)]
-pub enum PdfErrorWrapper {
-    #[error("pdf build failed: {0}")]
-    Build(String),
+#[deprecated(note = "use wa_core::Error")]
+pub type PdfErrorWrapper = Error;
+
+pub fn export_pdf_bytes(doc: &Document) -> Result<Vec<u8>, Error> {
+    export_pdf_bytes_with(doc, &PdfOptions::default())
 }
 
-pub fn export_pdf_bytes(doc: &Document) -> Result<Vec<u8>, PdfErrorWrapper> {
-    let (mut pdf, page1, layer1) = PdfDocument::new("Writing Agent", Mm(210.0), Mm(297.0), "Layer 1");
-    let layer = pdf.get_page(page1).get_layer(layer1);
-    let font = load_default_font(&mut pdf)?;
-    let mut cursor_y = 280.0f32;
-    for block in &doc.blocks {
-        let text = block_text(block);
-        if text.is_empty() {
-            cursor_y -= 6.0;
-            continue;
-        }
-        for line in text.lines() {
-            layer.use_text(line, 12.0, Mm(20.0), Mm(cursor_y), &font);
-            cursor_y -= 6.0;
-            if cursor_y < 20.0 {
-                break;
-            }
-        }
-        cursor_y -= 4.0;
-        if cursor_y < 20.0 {
-            break;
+/// Writes the pages `pdf_pages` gives for `options`. A page range that
+/// leaves no page is an error rather than an empty file.
+pub fn export_pdf_bytes_with(doc: &Document, options: &PdfOptions) -> Result<Vec<u8>, Error> {
+    let pages = pdf_pages(doc, options);
+    if pages.is_empty() {
+        let range = options.page_range.map(|r| r.to_string()).unwrap_or_default();
+        return Err(Error::export("pdf", format!("page range {} is past the last page", range)));
+    }
+    let (width, height) = options.paper.size_mm();
+    let (mut pdf, page1, layer1) = PdfDocument::new("Writing Agent", Mm(width), Mm(height), "Layer 1");
+    let font = load_default_font(&mut pdf, options.font.as_deref())?;
+    for (idx, page) in pages.iter().enumerate() {
+        let (page_idx, layer_idx) = if idx == 0 { (page1, layer1) } else { pdf.add_page(Mm(width), Mm(height), "Layer 1") };
+        let layer = pdf.get_page(page_idx).get_layer(layer_idx);
+        for line in &page.lines {
+            layer.use_text(line.text.as_str(), 12.0, Mm(line.x_mm), Mm(line.y_mm), &font);
         }
     }
     let mut buf = std::io::BufWriter::new(Vec::new());
-    pdf.save(&mut buf).map_err(|e| PdfErrorWrapper::Build(format!("{:?}", e)))?;
-    Ok(buf.into_inner().map_err(|e| PdfErrorWrapper::Build(e.to_string()))?)
+    pdf.save(&mut buf).map_err(|e| Error::export("pdf", format!("{:?}", e)))?;
+    buf.into_inner().map_err(|e| Error::Io(e.into_error()))
 }
 
-fn load_default_font(pdf: &mut printpdf::PdfDocumentReference) -> Result<IndirectFontRef, PdfErrorWrapper> {
+fn load_default_font(pdf: &mut printpdf::PdfDocumentReference, font: Option<&Path>) -> Result<IndirectFontRef, Error> {
+    if let Some(path) = font {
+        let bytes = std::fs::read(path)?;
+        return pdf.add_external_font(std::io::Cursor::new(bytes))
+            .map_err(|e| Error::export("pdf", format!("{}: {:?}", path.display(), e)));
+    }
     if let Ok(path) = std::env::var("WA_FONT_PATH") {
         if let Ok(bytes) = std::fs::read(&path) {
             return pdf.add_external_font(std::io::Cursor::new(bytes))
-                .map_err(|e| PdfErrorWrapper::Build(format!("{:?}", e)));
+                .map_err(|e| Error::export("pdf", format!("{:?}", e)));
         }
     }
     let candidates = [
@@ -57,44 +59,5 @@ fn load_default_font(pdf: &mut printpdf::PdfDocumentReference) -> Result<Indirec
             }
         }
     }
-    Err(PdfErrorWrapper::Build("font not found".to_string()))
-}
-
-fn block_text(block: &Block) -> String {
-    match block {
-        Block::Heading { content, .. } | Block::Paragraph { content, .. } => inline_text(content),
-        Block::List { items, .. } => items
-            .iter()
-            .map(|i| inline_text(&i.content))
-            .collect::<Vec<_>>()
-            .join("\n"),
-        Block::Quote { content, .. } => content
-            .iter()
-            .map(|b| match b {
-                Block::Paragraph { content, .. } => inline_text(content),
-                _ => String::new(),
-            })
-            .collect::<Vec<_>>()
-            .join("\n"),
-        Block::Code { code, .. } => code.as_ref().to_string(),
-        Block::Table { rows, .. } => rows
-            .iter()
-            .map(|r| r.iter().map(|c| inline_text(&c.content)).collect::<Vec<_>>().join(" | "))
-            .collect::<Vec<_>>()
-            .join("\n"),
-        Block::Figure { caption, .. } => caption.as_ref().map(|c| c.as_ref()).unwrap_or("").to_string(),
-    }
-}
-
-fn inline_text(inlines: &[Inline]) -> String {
-    let mut out = String::new();
-    for inline in inlines {
-        match inline {
-            Inline::Text { value } => out.push_str(value.as_ref()),
-            Inline::CodeSpan { value } => out.push_str(value.as_ref()),
-            Inline::Link { text, .. } => out.push_str(&inline_text(text)),
-            Inline::Styled { content, .. } => out.push_str(&inline_text(content)),
-        }
-    }
-    out
+    Err(Error::export("pdf", "font not found"))
 }
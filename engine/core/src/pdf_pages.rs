@@ -0,0 +1,178 @@
+use crate::{Block, Document, Inline};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+
+/// Line pitch and the extra space after each block, in millimetres.
+const LINE_MM: f32 = 6.0;
+const BLOCK_GAP_MM: f32 = 4.0;
+
+/// Paper sizes for `PdfOptions`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Paper {
+    #[default]
+    A4,
+    Letter,
+}
+
+impl Paper {
+    pub fn size_mm(self) -> (f32, f32) {
+        match self {
+            Paper::A4 => (210.0, 297.0),
+            Paper::Letter => (215.9, 279.4),
+        }
+    }
+}
+
+/// Page setup and font for `export_pdf_bytes_with`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfOptions {
+    pub paper: Paper,
+    pub margin_mm: f32,
+    /// Font file tried before WA_FONT_PATH and the system fonts.
+    pub font: Option<PathBuf>,
+    /// Pages to keep; the rest are left out. `None` keeps them all.
+    pub page_range: Option<PageRange>,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        PdfOptions { paper: Paper::A4, margin_mm: 20.0, font: None, page_range: None }
+    }
+}
+
+/// Pages numbered from 1, both ends included, as typed into a print
+/// dialog: `3`, `2-5`, or `4-` for the fourth page on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRange {
+    pub first: usize,
+    pub last: Option<usize>,
+}
+
+impl PageRange {
+    pub fn parse(text: &str) -> Option<PageRange> {
+        let number = |s: &str| s.trim().parse::<usize>().ok().filter(|n| *n > 0);
+        let range = match text.split_once('-') {
+            None => {
+                let page = number(text)?;
+                PageRange { first: page, last: Some(page) }
+            }
+            Some((first, last)) if last.trim().is_empty() => PageRange { first: number(first)?, last: None },
+            Some((first, last)) => PageRange { first: number(first)?, last: Some(number(last)?) },
+        };
+        range.last.is_none_or(|last| last >= range.first).then_some(range)
+    }
+
+    pub fn contains(&self, page: usize) -> bool {
+        page >= self.first && self.last.is_none_or(|last| page <= last)
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.last {
+            Some(last) if last == self.first => write!(f, "{}", last),
+            Some(last) => write!(f, "{}-{}", self.first, last),
+            None => write!(f, "{}-", self.first),
+        }
+    }
+}
+
+/// A page of PDF output and the text lines on it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfPage {
+    /// Number of the page in the whole document, from 1.
+    pub number: usize,
+    pub lines: Vec<PdfLine>,
+}
+
+/// A line of text placed on a PDF page, its baseline measured in
+/// millimetres from the bottom left corner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfLine {
+    pub text: String,
+    pub x_mm: f32,
+    pub y_mm: f32,
+}
+
+/// Breaks `doc` into the pages the PDF exporter draws, keeping only those in
+/// `options.page_range`. A line that would run into the bottom margin and
+/// a `Block::PageBreak` start a new page.
+pub fn pdf_pages(doc: &Document, options: &PdfOptions) -> Vec<PdfPage> {
+    let (width, height) = options.paper.size_mm();
+    let margin = options.margin_mm.clamp(0.0, width.min(height) / 2.0 - 10.0);
+    let top = height - margin;
+    let mut pages = vec![PdfPage { number: 1, lines: Vec::new() }];
+    let mut cursor_y = top;
+    let new_page = |pages: &mut Vec<PdfPage>, cursor_y: &mut f32| {
+        pages.push(PdfPage { number: pages.len() + 1, lines: Vec::new() });
+        *cursor_y = top;
+    };
+    for block in &doc.blocks {
+        if let Block::PageBreak { .. } = block {
+            if pages.last().is_some_and(|page| !page.lines.is_empty()) {
+                new_page(&mut pages, &mut cursor_y);
+            }
+            continue;
+        }
+        let text = block_text(block);
+        if text.is_empty() {
+            cursor_y -= LINE_MM;
+            continue;
+        }
+        for line in text.lines() {
+            if cursor_y < margin {
+                new_page(&mut pages, &mut cursor_y);
+            }
+            let page = pages.last_mut().expect("at least one page");
+            page.lines.push(PdfLine { text: line.to_string(), x_mm: margin, y_mm: cursor_y });
+            cursor_y -= LINE_MM;
+        }
+        cursor_y -= BLOCK_GAP_MM;
+    }
+    if let Some(range) = options.page_range {
+        pages.retain(|page| range.contains(page.number));
+    }
+    pages
+}
+
+fn block_text(block: &Block) -> String {
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => inline_text(content),
+        Block::List { items, .. } => items
+            .iter()
+            .map(|i| inline_text(&i.content))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Block::Quote { content, .. } => content
+            .iter()
+            .map(|b| match b {
+                Block::Paragraph { content, .. } => inline_text(content),
+                _ => String::new(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Block::Code { code, .. } => code.to_string(),
+        Block::Table { rows, .. } => rows
+            .iter()
+            .map(|r| r.iter().map(|c| inline_text(&c.content)).collect::<Vec<_>>().join(" | "))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Block::Figure { caption, .. } => caption.as_ref().map(|c| c.as_ref()).unwrap_or("").to_string(),
+        Block::PageBreak { .. } | Block::Custom { .. } => String::new(),
+    }
+}
+
+fn inline_text(inlines: &[Inline]) -> String {
+    let mut out = String::new();
+    for inline in inlines {
+        match inline {
+            Inline::Text { value } => out.push_str(value.as_ref()),
+            Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => out.push_str(value.as_ref()),
+            Inline::Link { text, .. } => out.push_str(&inline_text(text)),
+            Inline::Styled { content, .. } => out.push_str(&inline_text(content)),
+        }
+    }
+    out
+}
@@ -0,0 +1,136 @@
+use crate::{
+    block_plain_text, char_ranges_of, find_in_block, fold_case, hash_block, push_url_matches, to_nfc, without_excluded_inlines, Block, Document, DocumentStats, FindMatch, FindOptions,
+    TextStats,
+};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Flattened block text kept between calls, so incremental search and live
+/// stats only re-flatten blocks whose content changed. Entries are keyed by
+/// block id and checked against the block's content signature on access.
+#[derive(Debug, Default)]
+pub struct PlainTextIndex {
+    entries: HashMap<Uuid, IndexedBlock>,
+}
+
+#[derive(Debug)]
+struct IndexedBlock {
+    signature: u64,
+    text: Option<String>,
+    folded: Option<String>,
+    stats: Option<TextStats>,
+}
+
+impl PlainTextIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn entry(&mut self, block: &Block) -> &mut IndexedBlock {
+        let signature = hash_block(block);
+        let entry = self.entries.entry(block.id()).or_insert_with(|| IndexedBlock {
+            signature,
+            text: None,
+            folded: None,
+            stats: None,
+        });
+        if entry.signature != signature {
+            *entry = IndexedBlock { signature, text: None, folded: None, stats: None };
+        }
+        entry
+    }
+
+    /// Same as `block_plain_text`, without allocating when unchanged.
+    pub fn text(&mut self, block: &Block) -> &str {
+        self.entry(block).text.get_or_insert_with(|| block_plain_text(block))
+    }
+
+    /// The block text run through `fold_case`, computed on first use.
+    pub fn folded_text(&mut self, block: &Block) -> &str {
+        let entry = self.entry(block);
+        let text = entry.text.get_or_insert_with(|| block_plain_text(block));
+        entry.folded.get_or_insert_with(|| fold_case(text))
+    }
+
+    /// Rope-backed code blocks are counted line by line and never flattened.
+    pub fn stats(&mut self, block: &Block) -> TextStats {
+        let entry = self.entry(block);
+        if let Some(stats) = entry.stats {
+            return stats;
+        }
+        let stats = match block {
+            Block::Code { code, .. } if code.is_rope() => {
+                let mut stats = TextStats::default();
+                code.lines_with_endings().for_each(|line| stats.add(TextStats::of_str(&line)));
+                stats
+            }
+            _ => TextStats::of_str(entry.text.get_or_insert_with(|| block_plain_text(block))),
+        };
+        entry.stats = Some(stats);
+        stats
+    }
+
+    /// `find_in_doc` on top of the cached text.
+    pub fn find_in_doc(&mut self, doc: &Document, query: &str, options: FindOptions) -> Vec<FindMatch> {
+        let mut out = Vec::new();
+        if query.is_empty() {
+            return out;
+        }
+        self.prune(doc);
+        let query = to_nfc(query);
+        let needle = if options.case_insensitive { fold_case(&query) } else { query.to_string() };
+        for (block_index, block) in doc.blocks.iter().enumerate().filter(|(_, b)| options.allows(b)) {
+            self.find_in_block_into(block, block_index, &query, &needle, &options, &mut out);
+        }
+        out
+    }
+
+    /// Appends the hits of one block. `query` must be NFC and `needle` the
+    /// query folded as `options` asks.
+    pub(crate) fn find_in_block_into(&mut self, block: &Block, block_index: usize, query: &str, needle: &str, options: &FindOptions, out: &mut Vec<FindMatch>) {
+        let ranges = match block {
+            Block::Code { .. } if !options.include_code => Vec::new(),
+            Block::Code { code, .. } if code.is_rope() => find_in_block(block, query, options),
+            _ => {
+                let hay = if options.case_insensitive { self.folded_text(block) } else { self.text(block) };
+                without_excluded_inlines(block, char_ranges_of(hay, needle), options)
+            }
+        };
+        for (start, end) in ranges {
+            out.push(FindMatch { block_id: block.id(), block_index, start, end, link: None });
+        }
+        push_url_matches(block, block_index, query, options, out);
+    }
+
+    /// `document_stats` on top of the cached per-block counts.
+    pub fn document_stats(&mut self, doc: &Document) -> DocumentStats {
+        self.prune(doc);
+        let mut text = TextStats::default();
+        for block in &doc.blocks {
+            text.add(self.stats(block));
+        }
+        DocumentStats::new(text, doc.blocks.len())
+    }
+
+    /// Drops entries of deleted blocks once they make up a good share of
+    /// the index.
+    pub(crate) fn prune(&mut self, doc: &Document) {
+        if self.entries.len() <= doc.blocks.len() * 2 + 64 {
+            return;
+        }
+        let live: std::collections::HashSet<Uuid> = doc.blocks.iter().map(|b| b.id()).collect();
+        self.entries.retain(|id, _| live.contains(id));
+    }
+}
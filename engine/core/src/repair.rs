@@ -0,0 +1,71 @@
+use crate::{Block, Cell, Document, Inline, SharedStr};
+use serde::{Deserialize, Serialize};
+
+/// Which repairs `repair_document` makes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepairOptions {
+    /// Merge runs of adjacent tables, see `merge_adjacent_tables_with`.
+    pub merge_tables: bool,
+    /// How many columns two tables may differ by and still merge; the
+    /// narrower rows are padded with empty cells.
+    pub max_column_gap: usize,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self { merge_tables: true, max_column_gap: 1 }
+    }
+}
+
+/// Makes the repairs `options` asks for and returns how many blocks they
+/// changed or removed.
+pub fn repair_document(doc: &mut Document, options: &RepairOptions) -> usize {
+    let mut repaired = 0;
+    if options.merge_tables {
+        repaired += merge_adjacent_tables_with(doc, options.max_column_gap);
+    }
+    repaired
+}
+
+/// `merge_adjacent_tables_with` padding tables one column short.
+pub fn merge_adjacent_tables(doc: &mut Document) -> usize {
+    merge_adjacent_tables_with(doc, RepairOptions::default().max_column_gap)
+}
+
+/// Joins runs of top-level tables, such as the one-row tables the old
+/// Markdown import made, into the first table of the run, which keeps its
+/// id and header and is marked dirty. A table joins the one before it when
+/// their column counts differ by at most `max_column_gap`. Locked tables
+/// are left alone. Returns how many tables were merged away.
+pub fn merge_adjacent_tables_with(doc: &mut Document, max_column_gap: usize) -> usize {
+    let mut merged = 0;
+    let mut blocks: Vec<Block> = Vec::with_capacity(doc.blocks.len());
+    for block in std::mem::take(&mut doc.blocks) {
+        if let (Some(Block::Table { rows, col_widths, dirty, locked: false, .. }), Block::Table { rows: more, locked: false, .. }) =
+            (blocks.last_mut(), &block)
+        {
+            let (cols, more_cols) = (column_count(rows), column_count(more));
+            if cols.abs_diff(more_cols) <= max_column_gap {
+                let width = cols.max(more_cols);
+                rows.extend(more.iter().cloned());
+                for row in rows.iter_mut() {
+                    row.resize_with(width, || Cell { content: vec![Inline::Text { value: SharedStr::from("") }] });
+                }
+                if col_widths.len() != width {
+                    col_widths.clear();
+                }
+                *dirty = true;
+                merged += 1;
+                continue;
+            }
+        }
+        blocks.push(block);
+    }
+    doc.blocks = blocks;
+    merged
+}
+
+fn column_count(rows: &[Vec<Cell>]) -> usize {
+    rows.iter().map(Vec::len).max().unwrap_or(0)
+}
@@ -0,0 +1,86 @@
+use crate::{char_slice, inline_text_len, normalize_inlines, Inline, SharedStr, Style};
+use std::ops::Range;
+
+/// The flags every char of chars `start..end` of `inlines` carries, looking
+/// through styled spans and links. A formula counts when the range touches
+/// any of it. None when the range covers no text.
+pub fn range_style(inlines: &[Inline], start: usize, end: usize) -> Option<Style> {
+    if start >= end {
+        return None;
+    }
+    fn walk(inlines: &[Inline], parent: Style, pos: &mut usize, range: &Range<usize>, common: &mut Option<Style>) {
+        for inline in inlines {
+            match inline {
+                Inline::Text { value } | Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => {
+                    let len = value.chars().count();
+                    if *pos < range.end && *pos + len > range.start {
+                        *common = Some(common.map_or(parent, |style| style.intersect(parent)));
+                    }
+                    *pos += len;
+                }
+                Inline::Styled { style, content } => walk(content, parent.union(*style), pos, range, common),
+                Inline::Link { text, .. } => walk(text, parent, pos, range, common),
+            }
+        }
+    }
+    let mut common = None;
+    walk(inlines, Style::default(), &mut 0, &(start..end), &mut common);
+    common
+}
+
+/// Sets every flag of `flags` on chars `start..end` of `inlines`, or clears
+/// them when `on` is false, cutting text runs and styled spans at the range
+/// ends. A formula the range touches is restyled whole. The result is
+/// normalized, so neighbours left with the same style merge.
+pub fn set_range_style(inlines: &mut Vec<Inline>, start: usize, end: usize, flags: Style, on: bool) {
+    let change = |style: Style| if on { style.union(flags) } else { style.without(flags) };
+    let mut out = Vec::with_capacity(inlines.len());
+    restyle_into(std::mem::take(inlines), Style::default(), &mut 0, &(start..end), &change, &mut out);
+    *inlines = out;
+    normalize_inlines(inlines);
+}
+
+/// Pushes `inlines` onto `out`, each inside `parent` when it has any flags.
+/// Inlines the range does not reach go over as they are; the others are
+/// taken apart down to their leaves, which are wrapped in their effective
+/// style, changed by `change` inside `range`. Links stay links around
+/// their restyled text.
+fn restyle_into(inlines: Vec<Inline>, parent: Style, pos: &mut usize, range: &Range<usize>, change: &dyn Fn(Style) -> Style, out: &mut Vec<Inline>) {
+    let wrap = |style: Style, leaf: Inline| if style.is_plain() { leaf } else { Inline::Styled { style, content: vec![leaf] } };
+    for inline in inlines {
+        let len = inline_text_len(std::slice::from_ref(&inline));
+        if range.is_empty() || *pos >= range.end || *pos + len <= range.start {
+            *pos += len;
+            out.push(wrap(parent, inline));
+            continue;
+        }
+        match inline {
+            Inline::Text { ref value } | Inline::CodeSpan { ref value } => {
+                let cut_from = range.start.saturating_sub(*pos).min(len);
+                let cut_to = range.end.saturating_sub(*pos).clamp(cut_from, len);
+                for (from, to, style) in [(0, cut_from, parent), (cut_from, cut_to, change(parent)), (cut_to, len, parent)] {
+                    if from == to {
+                        continue;
+                    }
+                    let piece = SharedStr::from(char_slice(value, from, to));
+                    let leaf = match inline {
+                        Inline::CodeSpan { .. } => Inline::CodeSpan { value: piece },
+                        _ => Inline::Text { value: piece },
+                    };
+                    out.push(wrap(style, leaf));
+                }
+                *pos += len;
+            }
+            Inline::Math { .. } => {
+                *pos += len;
+                out.push(wrap(change(parent), inline));
+            }
+            Inline::Styled { style, content } => restyle_into(content, parent.union(style), pos, range, change, out),
+            Inline::Link { url, text } => {
+                let mut inner = Vec::with_capacity(text.len());
+                restyle_into(text, parent, pos, range, change, &mut inner);
+                out.push(Inline::Link { url, text: inner });
+            }
+        }
+    }
+}
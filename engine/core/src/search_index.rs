@@ -0,0 +1,151 @@
+use crate::{excluded_inline_ranges, fold_case, fold_char, link_urls, to_nfc, Block, DiffEngine, Document, FindMatch, FindOptions, PatchKind, PlainTextIndex};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+type Trigram = [char; 3];
+
+/// Trigram index over case-folded block text and link URLs, kept current
+/// from `DiffEngine` patches, so find only verifies blocks that can hold
+/// the query. Like the diff engine it relies on edited blocks being dirty
+/// when the document version moves.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    diff: DiffEngine,
+    text: PlainTextIndex,
+    blocks: HashMap<Uuid, HashSet<Trigram>>,
+    postings: HashMap<Trigram, HashSet<Uuid>>,
+    last: Option<LastQuery>,
+}
+
+/// Blocks that held the previous query, by index. While the document stays
+/// put and the user keeps typing, the next query can only hit a subset of
+/// them.
+#[derive(Debug)]
+struct LastQuery {
+    doc: (Uuid, u64),
+    needle: String,
+    options: FindOptions,
+    held: Vec<bool>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of indexed blocks.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Brings the index up to date with `doc`, reindexing only the blocks
+    /// the diff reports.
+    pub fn update(&mut self, doc: &Document) {
+        let patches = self.diff.incremental_diff(doc);
+        if patches.is_empty() {
+            return;
+        }
+        let by_id: HashMap<Uuid, &Block> = doc.blocks.iter().map(|b| (b.id(), b)).collect();
+        for patch in patches {
+            self.remove(patch.block_id);
+            if patch.kind != PatchKind::RemoveBlock {
+                if let Some(block) = by_id.get(&patch.block_id) {
+                    self.insert(block);
+                }
+            }
+        }
+        self.text.prune(doc);
+    }
+
+    /// Blocks that can hold `query` as of the last `update`, or `None` when
+    /// the query is too short to narrow anything down.
+    pub fn query(&self, query: &str) -> Option<HashSet<Uuid>> {
+        let mut grams = HashSet::new();
+        trigrams(fold_case(&to_nfc(query)).chars(), &mut grams);
+        let mut lists = Vec::with_capacity(grams.len());
+        for gram in &grams {
+            match self.postings.get(gram) {
+                Some(ids) => lists.push(ids),
+                None => return Some(HashSet::new()),
+            }
+        }
+        lists.sort_by_key(|ids| ids.len());
+        let (first, rest) = lists.split_first()?;
+        Some(first.iter().filter(|id| rest.iter().all(|ids| ids.contains(id))).copied().collect())
+    }
+
+    /// Same hits as `PlainTextIndex::find_in_doc`, checking only the
+    /// candidate blocks.
+    pub fn find_in_doc(&mut self, doc: &Document, query: &str, options: FindOptions) -> Vec<FindMatch> {
+        let mut out = Vec::new();
+        if query.is_empty() {
+            return out;
+        }
+        self.update(doc);
+        let query = to_nfc(query);
+        let needle = if options.case_insensitive { fold_case(&query) } else { query.to_string() };
+        let candidates = self.query(&query);
+        let last = self.last.take().filter(|last| {
+            last.doc == (doc.id, doc.version) && last.options == options && needle.contains(&last.needle)
+        });
+        let mut held = vec![false; doc.blocks.len()];
+        for (block_index, block) in doc.blocks.iter().enumerate() {
+            if !options.allows(block)
+                || last.as_ref().is_some_and(|last| !last.held[block_index])
+                || candidates.as_ref().is_some_and(|ids| !ids.contains(&block.id()))
+            {
+                continue;
+            }
+            let before = out.len();
+            self.text.find_in_block_into(block, block_index, &query, &needle, &options, &mut out);
+            // A hit dropped for overlapping inline code or math may come
+            // back once the query grows past the span.
+            held[block_index] = out.len() > before || !excluded_inline_ranges(block, &options).is_empty();
+        }
+        self.last = Some(LastQuery { doc: (doc.id, doc.version), needle, options, held });
+        out
+    }
+
+    fn insert(&mut self, block: &Block) {
+        let mut grams = HashSet::new();
+        match block {
+            Block::Code { code, .. } if code.is_rope() => trigrams(code.chars().map(fold_char), &mut grams),
+            _ => trigrams(self.text.folded_text(block).chars(), &mut grams),
+        }
+        for url in link_urls(block) {
+            trigrams(url.chars().map(fold_char), &mut grams);
+        }
+        for gram in &grams {
+            self.postings.entry(*gram).or_default().insert(block.id());
+        }
+        self.blocks.insert(block.id(), grams);
+    }
+
+    fn remove(&mut self, id: Uuid) {
+        let Some(grams) = self.blocks.remove(&id) else {
+            return;
+        };
+        for gram in grams {
+            if let Some(ids) = self.postings.get_mut(&gram) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.postings.remove(&gram);
+                }
+            }
+        }
+    }
+}
+
+fn trigrams(chars: impl Iterator<Item = char>, out: &mut HashSet<Trigram>) {
+    let mut window = ['\0'; 3];
+    for (i, c) in chars.enumerate() {
+        window = [window[1], window[2], c];
+        if i >= 2 {
+            out.insert(window);
+        }
+    }
+}
@@ -1,12 +1,22 @@
-﻿use uuid::Uuid;
+﻿use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Position {
     pub block_id: Uuid,
     pub offset: usize,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Which line a caret at a wrap point draws on: the end of the line before
+/// it (`Upstream`) or the start of the line it begins (`Downstream`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Affinity {
+    Upstream,
+    #[default]
+    Downstream,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Selection {
     pub anchor: Position,
     pub focus: Position,
@@ -0,0 +1,217 @@
+use crate::{Block, Document, Inline};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use uuid::Uuid;
+
+/// Content signature of a block: changes whenever anything that affects its
+/// text or layout changes. Only meaningful within one process.
+pub fn hash_block(block: &Block) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_block_into(block, &mut hasher);
+    hasher.finish()
+}
+
+pub fn hash_block_into(block: &Block, hasher: &mut impl Hasher) {
+    std::mem::discriminant(block).hash(hasher);
+    match block {
+        Block::Heading { level, content, .. } => {
+            level.hash(hasher);
+            hash_inlines(content, hasher);
+        }
+        Block::Paragraph { content, .. } => {
+            hash_inlines(content, hasher);
+        }
+        Block::List { ordered, items, .. } => {
+            ordered.hash(hasher);
+            items.len().hash(hasher);
+            for item in items {
+                item.level.hash(hasher);
+                item.checked.hash(hasher);
+                hash_inlines(&item.content, hasher);
+            }
+        }
+        Block::Quote { content, .. } => {
+            content.len().hash(hasher);
+            for inner in content {
+                hash_block_into(inner, hasher);
+            }
+        }
+        Block::Code { lang, code, .. } => {
+            lang.as_ref().hash(hasher);
+            code.hash(hasher);
+        }
+        Block::Table { rows, header, col_widths, .. } => {
+            header.hash(hasher);
+            col_widths.len().hash(hasher);
+            for width in col_widths {
+                width.to_bits().hash(hasher);
+            }
+            rows.len().hash(hasher);
+            for row in rows {
+                row.len().hash(hasher);
+                for cell in row {
+                    hash_inlines(&cell.content, hasher);
+                }
+            }
+        }
+        Block::Figure { url, caption, size, .. } => {
+            url.as_ref().hash(hasher);
+            caption.as_ref().map(|c| c.as_ref()).hash(hasher);
+            if let Some(sz) = size {
+                sz.width.to_bits().hash(hasher);
+                sz.height.to_bits().hash(hasher);
+            }
+        }
+        Block::PageBreak { .. } => {}
+        Block::Custom { kind, data, .. } => {
+            kind.as_ref().hash(hasher);
+            data.to_string().hash(hasher);
+        }
+    }
+}
+
+pub fn hash_inlines(inlines: &[Inline], hasher: &mut impl Hasher) {
+    inlines.len().hash(hasher);
+    for inline in inlines {
+        std::mem::discriminant(inline).hash(hasher);
+        match inline {
+            Inline::Text { value } | Inline::CodeSpan { value } => {
+                value.as_ref().hash(hasher);
+            }
+            Inline::Math { tex, display } => {
+                tex.as_ref().hash(hasher);
+                display.hash(hasher);
+            }
+            Inline::Styled { style, content } => {
+                style.bold.hash(hasher);
+                style.italic.hash(hasher);
+                style.underline.hash(hasher);
+                style.strikethrough.hash(hasher);
+                hash_inlines(content, hasher);
+            }
+            Inline::Link { url, text } => {
+                url.as_ref().hash(hasher);
+                hash_inlines(text, hasher);
+            }
+        }
+    }
+}
+
+pub fn hash_inlines_value(inlines: &[Inline]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_inlines(inlines, &mut hasher);
+    hasher.finish()
+}
+
+/// Content signature of a whole document: block structure, inline content,
+/// table shapes, figures and the title and author. Ids, dirty flags, the
+/// version and timestamps do not count, so documents that
+/// `doc_semantic_eq` agree on hash alike.
+pub fn semantic_hash(doc: &Document) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    doc.metadata.title.as_ref().hash(&mut hasher);
+    doc.metadata.author.as_ref().hash(&mut hasher);
+    doc.blocks.len().hash(&mut hasher);
+    for block in &doc.blocks {
+        hash_block_into(block, &mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Whether two documents hold the same content, ignoring ids, dirty flags,
+/// the version and timestamps.
+pub fn doc_semantic_eq(a: &Document, b: &Document) -> bool {
+    a.metadata.title == b.metadata.title
+        && a.metadata.author == b.metadata.author
+        && a.blocks.len() == b.blocks.len()
+        && a.blocks.iter().zip(&b.blocks).all(|(a, b)| block_semantic_eq(a, b))
+}
+
+/// `doc_semantic_eq` for one block.
+pub fn block_semantic_eq(a: &Block, b: &Block) -> bool {
+    match (a, b) {
+        (Block::Heading { level: la, content: ca, .. }, Block::Heading { level: lb, content: cb, .. }) => {
+            la == lb && ca == cb
+        }
+        (Block::Paragraph { content: a, .. }, Block::Paragraph { content: b, .. }) => a == b,
+        (Block::List { ordered: oa, items: ia, .. }, Block::List { ordered: ob, items: ib, .. }) => {
+            oa == ob && ia.len() == ib.len() && ia.iter().zip(ib).all(|(a, b)| a.level == b.level && a.checked == b.checked && a.content == b.content)
+        }
+        (Block::Quote { content: a, .. }, Block::Quote { content: b, .. }) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| block_semantic_eq(a, b))
+        }
+        (Block::Code { lang: la, code: ca, .. }, Block::Code { lang: lb, code: cb, .. }) => la == lb && ca == cb,
+        (
+            Block::Table { rows: ra, header: ha, col_widths: wa, .. },
+            Block::Table { rows: rb, header: hb, col_widths: wb, .. },
+        ) => ha == hb && ra == rb && wa.iter().map(|w| w.to_bits()).eq(wb.iter().map(|w| w.to_bits())),
+        (
+            Block::Figure { url: ua, caption: ca, size: sa, .. },
+            Block::Figure { url: ub, caption: cb, size: sb, .. },
+        ) => {
+            let bits = |size: &Option<crate::FigureSize>| size.map(|s| (s.width.to_bits(), s.height.to_bits()));
+            ua == ub && ca == cb && bits(sa) == bits(sb)
+        }
+        (Block::Custom { kind: ka, data: da, .. }, Block::Custom { kind: kb, data: db, .. }) => ka == kb && da == db,
+        _ => false,
+    }
+}
+
+/// Block signatures kept up to date by the editor as it mutates blocks, so
+/// diffing and layout can look them up instead of hashing again. They only
+/// describe the document version they were refreshed at.
+#[derive(Debug, Clone, Default)]
+pub struct BlockSignatures {
+    version: Option<u64>,
+    map: HashMap<Uuid, u64>,
+    /// Block order at the last refresh; while it holds, clean blocks are
+    /// known to have a signature already.
+    order: Vec<Uuid>,
+}
+
+impl BlockSignatures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rehashes dirty blocks and blocks seen for the first time, and forgets
+    /// blocks that left the document.
+    pub fn refresh(&mut self, doc: &Document) {
+        self.version = Some(doc.version);
+        if self.order.len() == doc.blocks.len() && self.order.iter().zip(&doc.blocks).all(|(id, b)| *id == b.id()) {
+            for block in doc.blocks.iter().filter(|b| b.is_effectively_dirty()) {
+                self.map.insert(block.id(), hash_block(block));
+            }
+            return;
+        }
+        self.order.clear();
+        self.order.extend(doc.blocks.iter().map(|b| b.id()));
+        for block in &doc.blocks {
+            if block.is_effectively_dirty() || !self.map.contains_key(&block.id()) {
+                self.map.insert(block.id(), hash_block(block));
+            }
+        }
+        if self.map.len() > doc.blocks.len() {
+            let live: HashSet<Uuid> = doc.blocks.iter().map(|b| b.id()).collect();
+            self.map.retain(|id, _| live.contains(id));
+        }
+    }
+
+    /// Signature of `block_id` if these signatures match `doc`'s version.
+    pub fn get(&self, doc: &Document, block_id: Uuid) -> Option<u64> {
+        if self.version != Some(doc.version) {
+            return None;
+        }
+        self.map.get(&block_id).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
@@ -0,0 +1,98 @@
+use crate::{block_plain_text, char_to_byte_idx, Document, Selection};
+
+/// Characters per minute used for the reading time estimate.
+const READING_SPEED: usize = 400;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextStats {
+    pub chars: usize,
+    pub chars_no_spaces: usize,
+    /// Latin-script runs count as one word each, CJK characters individually.
+    pub words: usize,
+}
+
+impl TextStats {
+    pub fn of_str(text: &str) -> Self {
+        let mut stats = TextStats::default();
+        let mut in_word = false;
+        for ch in text.chars() {
+            stats.chars += 1;
+            if ch.is_whitespace() {
+                in_word = false;
+                continue;
+            }
+            stats.chars_no_spaces += 1;
+            if is_cjk(ch) {
+                stats.words += 1;
+                in_word = false;
+            } else if ch.is_alphanumeric() || (in_word && matches!(ch, '\'' | '-')) {
+                if !in_word {
+                    stats.words += 1;
+                    in_word = true;
+                }
+            } else {
+                in_word = false;
+            }
+        }
+        stats
+    }
+
+    pub fn add(&mut self, other: TextStats) {
+        self.chars += other.chars;
+        self.chars_no_spaces += other.chars_no_spaces;
+        self.words += other.words;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DocumentStats {
+    pub text: TextStats,
+    pub blocks: usize,
+    pub reading_minutes: usize,
+}
+
+impl DocumentStats {
+    pub fn new(text: TextStats, blocks: usize) -> Self {
+        DocumentStats { text, blocks, reading_minutes: text.chars_no_spaces.div_ceil(READING_SPEED) }
+    }
+}
+
+pub fn document_stats(doc: &Document) -> DocumentStats {
+    let mut text = TextStats::default();
+    for block in &doc.blocks {
+        text.add(TextStats::of_str(&block_plain_text(block)));
+    }
+    DocumentStats::new(text, doc.blocks.len())
+}
+
+/// Stats for the selected text, in plain-text offsets, or `None` when the
+/// selection is collapsed or points at blocks that no longer exist.
+pub fn selection_stats(doc: &Document, selection: &Selection) -> Option<TextStats> {
+    if selection.is_collapsed() {
+        return None;
+    }
+    let index_of = |id| doc.blocks.iter().position(|b| b.id() == id);
+    let a = (index_of(selection.anchor.block_id)?, selection.anchor.offset);
+    let f = (index_of(selection.focus.block_id)?, selection.focus.offset);
+    let (start, end) = if a <= f { (a, f) } else { (f, a) };
+    let mut stats = TextStats::default();
+    for idx in start.0..=end.0 {
+        let text = block_plain_text(&doc.blocks[idx]);
+        let from = if idx == start.0 { char_to_byte_idx(&text, start.1) } else { 0 };
+        let to = if idx == end.0 { char_to_byte_idx(&text, end.1) } else { text.len() };
+        if from < to {
+            stats.add(TextStats::of_str(&text[from..to]));
+        }
+    }
+    Some(stats)
+}
+
+pub(crate) fn is_cjk(ch: char) -> bool {
+    matches!(ch as u32,
+        0x3040..=0x30FF      // kana
+        | 0x3400..=0x4DBF    // CJK extension A
+        | 0x4E00..=0x9FFF    // CJK unified ideographs
+        | 0xAC00..=0xD7AF    // hangul
+        | 0xF900..=0xFAFF    // compatibility ideographs
+        | 0x20000..=0x2FA1F) // extensions B+
+}
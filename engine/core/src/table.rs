@@ -2,12 +2,15 @@
 use std::sync::Arc;
 use uuid::Uuid;
 
+/// Narrowest a column may get, as a fraction of the table width.
+pub const MIN_TABLE_COL_WIDTH: f32 = 0.05;
+
 #[derive(Debug, Default)]
 pub struct TableEditor;
 
 impl TableEditor {
     pub fn insert_row(block: &mut Block, index: usize) -> bool {
-        if let Block::Table { rows, .. } = block {
+        if let Block::Table { rows, dirty, .. } = block {
             let cols = rows.first().map(|r| r.len()).unwrap_or(1);
             let mut row = Vec::with_capacity(cols);
             for _ in 0..cols {
@@ -15,15 +18,17 @@ impl TableEditor {
             }
             let idx = index.min(rows.len());
             rows.insert(idx, row);
+            *dirty = true;
             return true;
         }
         false
     }
 
     pub fn delete_row(block: &mut Block, index: usize) -> bool {
-        if let Block::Table { rows, .. } = block {
+        if let Block::Table { rows, dirty, .. } = block {
             if index < rows.len() {
                 rows.remove(index);
+                *dirty = true;
                 return true;
             }
         }
@@ -31,33 +36,118 @@ impl TableEditor {
     }
 
     pub fn insert_column(block: &mut Block, index: usize) -> bool {
-        if let Block::Table { rows, .. } = block {
+        if let Block::Table { rows, col_widths, dirty, .. } = block {
+            let cols = rows.first().map(|r| r.len()).unwrap_or(0);
+            if col_widths.len() == cols && cols > 0 {
+                col_widths.insert(index.min(cols), 1.0 / (cols + 1) as f32);
+                normalize_widths(col_widths);
+            }
             for row in rows.iter_mut() {
                 let idx = index.min(row.len());
                 row.insert(idx, Cell { content: vec![Inline::Text { value: Arc::from("") }] });
             }
+            *dirty = true;
             return true;
         }
         false
     }
 
     pub fn delete_column(block: &mut Block, index: usize) -> bool {
-        if let Block::Table { rows, .. } = block {
+        if let Block::Table { rows, col_widths, dirty, .. } = block {
+            if index < col_widths.len() {
+                col_widths.remove(index);
+                normalize_widths(col_widths);
+            }
             for row in rows.iter_mut() {
                 if index < row.len() {
                     row.remove(index);
                 }
             }
+            *dirty = true;
+            return true;
+        }
+        false
+    }
+
+    /// Column widths as fractions summing to 1, equal when none are stored.
+    pub fn col_fractions(block: &Block) -> Vec<f32> {
+        match block {
+            Block::Table { rows, col_widths, .. } => {
+                let cols = rows.first().map(|r| r.len()).unwrap_or(0);
+                if col_widths.len() == cols {
+                    col_widths.clone()
+                } else {
+                    vec![1.0 / cols.max(1) as f32; cols]
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Sets column `col` to `width` (a fraction of the table width). The next
+    /// column absorbs the difference so the total stays the same; both keep
+    /// at least `MIN_TABLE_COL_WIDTH`.
+    pub fn set_col_width(block: &mut Block, col: usize, width: f32) -> bool {
+        let mut widths = Self::col_fractions(block);
+        if col + 1 >= widths.len() || !width.is_finite() {
+            return false;
+        }
+        let pair = widths[col] + widths[col + 1];
+        let width = width.clamp(MIN_TABLE_COL_WIDTH, (pair - MIN_TABLE_COL_WIDTH).max(MIN_TABLE_COL_WIDTH));
+        if (widths[col] - width).abs() < f32::EPSILON {
+            return false;
+        }
+        widths[col] = width;
+        widths[col + 1] = pair - width;
+        if let Block::Table { col_widths, dirty, .. } = block {
+            *col_widths = widths;
+            *dirty = true;
+            return true;
+        }
+        false
+    }
+
+    pub fn toggle_header(block: &mut Block) -> bool {
+        if let Block::Table { header, dirty, .. } = block {
+            *header = !*header;
+            *dirty = true;
             return true;
         }
         false
     }
 
+    /// The cell Tab moves to from (`row`, `col`): the next one in the row,
+    /// else the first of the next non-empty row. None past the last cell.
+    pub fn next_cell(rows: &[Vec<Cell>], row: usize, col: usize) -> Option<(usize, usize)> {
+        if rows.get(row).is_some_and(|r| col + 1 < r.len()) {
+            return Some((row, col + 1));
+        }
+        rows.iter().enumerate().skip(row + 1).find(|(_, r)| !r.is_empty()).map(|(idx, _)| (idx, 0))
+    }
+
+    /// The cell Shift+Tab moves to from (`row`, `col`): the previous one in
+    /// the row, else the last of the previous non-empty row. None before
+    /// the first cell.
+    pub fn prev_cell(rows: &[Vec<Cell>], row: usize, col: usize) -> Option<(usize, usize)> {
+        if col > 0 && rows.get(row).is_some_and(|r| !r.is_empty()) {
+            return Some((row, (col - 1).min(rows[row].len() - 1)));
+        }
+        rows.iter().enumerate().take(row.min(rows.len())).rev().find(|(_, r)| !r.is_empty()).map(|(idx, r)| (idx, r.len() - 1))
+    }
+
+    /// The cell Enter moves to from (`row`, `col`): the same column one row
+    /// down, or the last cell of that row when it is shorter.
+    pub fn cell_below(rows: &[Vec<Cell>], row: usize, col: usize) -> Option<(usize, usize)> {
+        let below = rows.get(row + 1).filter(|r| !r.is_empty())?;
+        Some((row + 1, col.min(below.len() - 1)))
+    }
+
     pub fn set_cell_text(block: &mut Block, row: usize, col: usize, text: String) -> bool {
-        if let Block::Table { rows, .. } = block {
+        if let Block::Table { rows, dirty, .. } = block {
             if let Some(r) = rows.get_mut(row) {
                 if let Some(c) = r.get_mut(col) {
                     c.content = vec![Inline::Text { value: Arc::from(text) }];
+                    *dirty = true;
                     return true;
                 }
             }
@@ -66,6 +156,15 @@ impl TableEditor {
     }
 }
 
+fn normalize_widths(widths: &mut [f32]) {
+    let total: f32 = widths.iter().sum();
+    if total > 0.0 {
+        for w in widths.iter_mut() {
+            *w /= total;
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TableDescriptor {
     pub id: Uuid,
@@ -0,0 +1,122 @@
+use crate::{visit_leaves_mut, Block, Inline, ListItem, Metadata, SharedStr};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Named blocks inserted as a unit, see `Editor::insert_template`. Text may
+/// hold `{{title}}`, `{{author}}` and `{{date}}`, filled in on insert.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Template {
+    pub name: String,
+    pub blocks: Vec<Block>,
+}
+
+impl Template {
+    /// Copies of the blocks with fresh ids, marked dirty, and placeholders
+    /// replaced from `metadata` and today's date.
+    pub fn instantiate(&self, metadata: &Metadata) -> Vec<Block> {
+        let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+        let values = [("{{title}}", metadata.title.as_ref()), ("{{author}}", metadata.author.as_ref()), ("{{date}}", &date)];
+        let mut blocks = self.blocks.clone();
+        for block in &mut blocks {
+            block.for_each_block_mut(&mut |b| {
+                refresh_ids(b);
+                b.set_dirty(true);
+            });
+            visit_leaves_mut(block, &mut 0, &mut |_, value| {
+                if value.contains("{{") {
+                    let filled = values.iter().fold(value.to_string(), |text, (key, with)| text.replace(key, with));
+                    *value = SharedStr::from(filled);
+                }
+            });
+        }
+        blocks
+    }
+}
+
+/// Gives `block`, the blocks nested in it and its list items new ids.
+pub(crate) fn refresh_ids(block: &mut Block) {
+    block.for_each_block_mut(&mut |block| {
+        *block.id_mut() = Uuid::new_v4();
+        if let Block::List { items, .. } = block {
+            items.iter_mut().for_each(|item| item.id = Uuid::new_v4());
+        }
+    });
+}
+
+/// Templates by name, in the order they were registered.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateRegistry {
+    templates: Vec<Template>,
+}
+
+impl TemplateRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry holding `builtin_templates`.
+    pub fn with_builtins() -> Self {
+        Self { templates: builtin_templates() }
+    }
+
+    /// Adds `template`, replacing one of the same name.
+    pub fn register(&mut self, template: Template) {
+        match self.templates.iter_mut().find(|t| t.name == template.name) {
+            Some(existing) => *existing = template,
+            None => self.templates.push(template),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Template> {
+        self.templates.iter().find(|t| t.name == name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.templates.iter().map(|t| t.name.as_str())
+    }
+}
+
+/// "meeting_notes": title, date and empty attendee, agenda and action item
+/// lists. "figure_with_caption": a placeholder figure with a numbered
+/// caption.
+pub fn builtin_templates() -> Vec<Template> {
+    vec![
+        Template {
+            name: "meeting_notes".to_string(),
+            blocks: vec![
+                heading(1, "{{title}} 会议纪要"),
+                paragraph("日期：{{date}}　记录人：{{author}}"),
+                heading(2, "参会人"),
+                empty_list(false),
+                heading(2, "议程"),
+                empty_list(true),
+                heading(2, "待办事项"),
+                empty_list(false),
+            ],
+        },
+        Template {
+            name: "figure_with_caption".to_string(),
+            blocks: vec![Block::Figure {
+                id: Uuid::new_v4(),
+                url: Arc::from("local://placeholder"),
+                caption: Some(Arc::from("图 1：{{title}}")),
+                size: None,
+                dirty: false,
+                locked: false,
+            }],
+        },
+    ]
+}
+
+fn heading(level: u8, text: &str) -> Block {
+    Block::Heading { id: Uuid::new_v4(), level, content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false }
+}
+
+fn paragraph(text: &str) -> Block {
+    Block::Paragraph { id: Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false }
+}
+
+fn empty_list(ordered: bool) -> Block {
+    Block::List { id: Uuid::new_v4(), ordered, items: vec![ListItem { id: Uuid::new_v4(), content: Vec::new(), level: 0, checked: None }], dirty: false, locked: false, toc: false }
+}
@@ -0,0 +1,210 @@
+use crate::{tokens, Block, Inline, ListItem, SharedStr};
+
+pub fn inline_plain_text(inlines: &[Inline], out: &mut String) {
+    for inline in inlines {
+        match inline {
+            Inline::Text { value } => out.push_str(value.as_ref()),
+            Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => out.push_str(value.as_ref()),
+            Inline::Link { text, .. } => inline_plain_text(text, out),
+            Inline::Styled { content, .. } => inline_plain_text(content, out),
+        }
+    }
+}
+
+/// Block text as search and stats see it: list items and quote paragraphs
+/// are separated by '\n', table cells by '\t' and rows by '\n'.
+pub fn block_plain_text(block: &Block) -> String {
+    let mut out = String::new();
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => {
+            inline_plain_text(content, &mut out);
+        }
+        Block::List { items, .. } => {
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    out.push('\n');
+                }
+                inline_plain_text(&item.content, &mut out);
+            }
+        }
+        Block::Quote { content, .. } => {
+            for (idx, inner) in content.iter().enumerate() {
+                if idx > 0 {
+                    out.push('\n');
+                }
+                out.push_str(&block_plain_text(inner));
+            }
+        }
+        Block::Code { code, .. } => code.chunks().for_each(|chunk| out.push_str(chunk)),
+        Block::Table { rows, .. } => {
+            for (ri, row) in rows.iter().enumerate() {
+                if ri > 0 {
+                    out.push('\n');
+                }
+                for (ci, cell) in row.iter().enumerate() {
+                    if ci > 0 {
+                        out.push('\t');
+                    }
+                    inline_plain_text(&cell.content, &mut out);
+                }
+            }
+        }
+        Block::Figure { caption, .. } => {
+            if let Some(c) = caption {
+                out.push_str(c.as_ref());
+            }
+        }
+        Block::PageBreak { .. } | Block::Custom { .. } => {}
+    }
+    out
+}
+
+/// Length in chars of `block_plain_text`, which is what caret offsets into
+/// a block count. Paragraphs, headings and code blocks are measured without
+/// building the text.
+pub fn block_text_len(block: &Block) -> usize {
+    match block {
+        Block::Paragraph { content, .. } | Block::Heading { content, .. } => inline_text_len(content),
+        Block::Code { code, .. } => code.char_len(),
+        _ => block_plain_text(block).chars().count(),
+    }
+}
+
+/// Index of the list item holding char `offset` of the list's plain text,
+/// where items are separated by '\n', and the offset that item starts at,
+/// so the caret is `offset` minus that into the item.
+pub fn list_item_at(items: &[ListItem], offset: usize) -> (usize, usize) {
+    let mut start = 0;
+    for (idx, item) in items.iter().enumerate() {
+        let end = start + inline_text_len(&item.content);
+        if offset <= end || idx + 1 == items.len() {
+            return (idx, start);
+        }
+        start = end + 1;
+    }
+    (0, 0)
+}
+
+/// Length in chars of `inline_plain_text`.
+pub fn inline_text_len(inlines: &[Inline]) -> usize {
+    inlines
+        .iter()
+        .map(|inline| match inline {
+            Inline::Text { value } | Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => value.chars().count(),
+            Inline::Styled { content, .. } => inline_text_len(content),
+            Inline::Link { text, .. } => inline_text_len(text),
+        })
+        .sum()
+}
+
+pub fn char_to_byte_idx(s: &str, char_idx: usize) -> usize {
+    if char_idx == 0 {
+        return 0;
+    }
+    s.char_indices()
+        .nth(char_idx)
+        .map(|(i, _)| i)
+        .unwrap_or(s.len())
+}
+
+/// Chars `start..end` of `s`. Offsets past the end are clamped, and an
+/// inverted range is empty.
+pub fn char_slice(s: &str, start: usize, end: usize) -> &str {
+    let from = char_to_byte_idx(s, start);
+    let to = char_to_byte_idx(s, end.max(start));
+    &s[from..to]
+}
+
+/// Char offset of the start of the word before char `offset` of `text`,
+/// past any whitespace in between; 0 when there is none. Words are split
+/// as `diff_words` splits them, so each CJK char is a word of its own.
+pub fn word_start_before(text: &str, offset: usize) -> usize {
+    word_spans(text).into_iter().rev().find(|span| span.start < offset).map_or(0, |span| span.start)
+}
+
+/// Char range of the word or punctuation mark at char `offset` of `text`,
+/// preferring the one after the caret when it sits between two. None in
+/// whitespace.
+pub fn word_at(text: &str, offset: usize) -> Option<std::ops::Range<usize>> {
+    let spans = word_spans(text);
+    let after = spans.iter().find(|span| span.start <= offset && offset < span.end);
+    after.or_else(|| spans.iter().find(|span| span.end == offset)).cloned()
+}
+
+/// Char offset of the end of the word after char `offset` of `text`, past
+/// any whitespace in between; the end of the text when there is none.
+pub fn word_end_after(text: &str, offset: usize) -> usize {
+    let spans = word_spans(text);
+    spans.iter().find(|span| span.end > offset).map_or(text.chars().count(), |span| span.end)
+}
+
+/// Char ranges of the words and punctuation marks of `text`.
+fn word_spans(text: &str) -> Vec<std::ops::Range<usize>> {
+    let mut chars = 0;
+    tokens(text)
+        .into_iter()
+        .filter_map(|bytes| {
+            let token = &text[bytes];
+            let start = chars;
+            chars += token.chars().count();
+            (!token.starts_with(char::is_whitespace)).then_some(start..chars)
+        })
+        .collect()
+}
+
+/// The string a history path points at. Paragraphs and headings are walked
+/// by inline index, descending into styled spans and link text; lists start
+/// with the item index, quotes with the child block, tables with row and
+/// column. Figure captions use the empty path. Code blocks are not a
+/// `SharedStr` and are only reachable through `splice_text_leaf`.
+pub fn text_leaf_mut<'a>(block: &'a mut Block, path: &[usize]) -> Option<&'a mut SharedStr> {
+    fn inline_leaf<'a>(inlines: &'a mut [Inline], path: &[usize]) -> Option<&'a mut SharedStr> {
+        let (&first, rest) = path.split_first()?;
+        match inlines.get_mut(first)? {
+            Inline::Text { value } | Inline::CodeSpan { value } | Inline::Math { tex: value, .. } if rest.is_empty() => Some(value),
+            Inline::Styled { content, .. } => inline_leaf(content, rest),
+            Inline::Link { text, .. } => inline_leaf(text, rest),
+            _ => None,
+        }
+    }
+    match block {
+        Block::Heading { content, .. } | Block::Paragraph { content, .. } => inline_leaf(content, path),
+        Block::List { items, .. } => {
+            let (&item, rest) = path.split_first()?;
+            inline_leaf(&mut items.get_mut(item)?.content, rest)
+        }
+        Block::Quote { content, .. } => {
+            let (&child, rest) = path.split_first()?;
+            text_leaf_mut(content.get_mut(child)?, rest)
+        }
+        Block::Table { rows, .. } => {
+            let [row, col, rest @ ..] = path else {
+                return None;
+            };
+            inline_leaf(&mut rows.get_mut(*row)?.get_mut(*col)?.content, rest)
+        }
+        Block::Figure { caption: Some(caption), .. } if path.is_empty() => Some(caption),
+        _ => None,
+    }
+}
+
+/// Replaces the bytes `range` of the leaf at `path` with `inserted`, marks
+/// the block dirty and returns the removed text. Code blocks use the empty
+/// path. Returns None when the path or range no longer fits the block.
+pub fn splice_text_leaf(block: &mut Block, path: &[usize], range: std::ops::Range<usize>, inserted: &str) -> Option<String> {
+    let removed = match block {
+        Block::Code { code, .. } if path.is_empty() => code.splice(range, inserted)?,
+        _ => {
+            let leaf = text_leaf_mut(block, path)?;
+            let removed = leaf.get(range.clone())?.to_string();
+            let mut out = String::with_capacity(leaf.len() - range.len() + inserted.len());
+            out.push_str(&leaf[..range.start]);
+            out.push_str(inserted);
+            out.push_str(&leaf[range.end..]);
+            *leaf = SharedStr::from(out);
+            removed
+        }
+    };
+    block.set_dirty(true);
+    Some(removed)
+}
@@ -0,0 +1,42 @@
+use crate::{block_plain_text, Block, Inline, ListItem, SharedStr};
+use uuid::Uuid;
+
+/// Link target of a table of contents entry for the block `id`.
+pub fn toc_anchor(id: Uuid) -> String {
+    format!("#{}", id)
+}
+
+/// The block a link made by `toc_anchor` points at.
+pub fn toc_target(url: &str) -> Option<Uuid> {
+    Uuid::parse_str(url.strip_prefix('#')?).ok()
+}
+
+/// Table of contents entries for the top-level headings of `blocks`, each
+/// a link to its heading. The shallowest heading level is the top level,
+/// and no entry sits more than one level below the one before it. Entries
+/// take their heading's id, so rebuilding gives the same list.
+pub fn toc_items(blocks: &[Block]) -> Vec<ListItem> {
+    let headings: Vec<(Uuid, u8, String)> = blocks
+        .iter()
+        .filter_map(|b| match b {
+            Block::Heading { id, level, .. } => Some((*id, *level, block_plain_text(b))),
+            _ => None,
+        })
+        .filter(|(_, _, text)| !text.trim().is_empty())
+        .collect();
+    let top = headings.iter().map(|(_, level, _)| *level).min().unwrap_or(1);
+    let mut prev: Option<u8> = None;
+    headings
+        .into_iter()
+        .map(|(id, level, text)| {
+            let level = (level - top).min(prev.map_or(0, |p| p + 1));
+            prev = Some(level);
+            ListItem {
+                id,
+                content: vec![Inline::Link { url: SharedStr::from(toc_anchor(id)), text: vec![Inline::Text { value: SharedStr::from(text) }] }],
+                level,
+                checked: None,
+            }
+        })
+        .collect()
+}
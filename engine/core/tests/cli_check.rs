@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn wa_check(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_wa_check")).args(args).output().unwrap()
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("wa_check_{}_{name}", uuid::Uuid::new_v4()))
+}
+
+#[test]
+fn findings_fail_the_check_until_fixed() {
+    let clean = temp_path("clean.md");
+    std::fs::write(&clean, "# 标题\n\n## 小节\n\nsome words here\n").unwrap();
+    let out = wa_check(&[clean.to_str().unwrap()]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stdout));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("words: 7"), "{stdout}");
+    assert!(stdout.contains("  heading: 2\n  paragraph: 1"), "{stdout}");
+
+    let jumpy = temp_path("jumpy.md");
+    std::fs::write(&jumpy, "# 标题\n\n### 细节\n").unwrap();
+    let out = wa_check(&[jumpy.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("block 2: heading jumps from level 1 to 3"));
+
+    // Fixing cannot repair a heading jump, so the check still fails.
+    let fixed = temp_path("fixed.md");
+    let out = wa_check(&["--fix", "--output", fixed.to_str().unwrap(), jumpy.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(fixed.exists());
+
+    assert_eq!(wa_check(&["--output", "x.md", "a.md"]).status.code(), Some(2));
+    assert_eq!(wa_check(&["--fix", "a.docx"]).status.code(), Some(2));
+    assert_eq!(wa_check(&[temp_path("missing.md").to_str().unwrap()]).status.code(), Some(3));
+    for path in [clean, jumpy, fixed] {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+#[test]
+fn fix_writes_the_input_back() {
+    let path = temp_path("doc.json");
+    let mut doc = wa_core::import_markdown("正文");
+    doc.blocks.push(wa_core::Block::Paragraph { id: uuid::Uuid::new_v4(), content: Vec::new(), dirty: false, locked: false });
+    std::fs::write(&path, wa_core::export_json(&doc).unwrap()).unwrap();
+    let out = wa_check(&["--fix", path.to_str().unwrap()]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("fixed paragraph"));
+    let fixed = wa_core::import_json(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(fixed.blocks.len(), 1);
+    std::fs::remove_file(path).ok();
+}
@@ -0,0 +1,75 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn wa_export(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wa_export"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("wa_export_{}_{name}", uuid::Uuid::new_v4()))
+}
+
+#[test]
+fn output_extension_picks_the_exporter() {
+    let input = temp_path("in.md");
+    std::fs::write(&input, "# 标题\n\n正文 **加粗**").unwrap();
+    let html = temp_path("out.html");
+    let out = wa_export(&[input.to_str().unwrap(), html.to_str().unwrap()], "");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(std::fs::read_to_string(&html).unwrap().starts_with("<h1>标题</h1>\n"));
+
+    let json = temp_path("out.json");
+    let out = wa_export(&["--from", "html", "-", json.to_str().unwrap()], "<p>one</p><p>two</p>");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let doc = wa_core::import_json(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(doc.blocks.len(), 2);
+
+    let out = wa_export(&[input.to_str().unwrap(), temp_path("out.xyz").to_str().unwrap()], "");
+    assert_eq!(out.status.code(), Some(5));
+    assert_eq!(wa_export(&["-", json.to_str().unwrap()], "").status.code(), Some(2));
+    assert_eq!(wa_export(&["--margin", "-3", "a.md", "b.md"], "").status.code(), Some(2));
+    assert_eq!(wa_export(&["--pages", "5-2", "a.md", "b.md"], "").status.code(), Some(2));
+    for path in [input, html, json] {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+#[cfg(not(feature = "export_docx"))]
+#[test]
+fn missing_exporters_name_the_feature() {
+    let out = wa_export(&["--from", "md", "-", temp_path("out.pdf").to_str().unwrap()], "text");
+    assert_eq!(out.status.code(), Some(5));
+    assert!(String::from_utf8(out.stderr).unwrap().contains("--features export_docx"));
+}
+
+#[cfg(feature = "export_docx")]
+#[test]
+fn writes_pdf_and_docx_with_their_magic_numbers() {
+    let input = temp_path("in.json");
+    std::fs::write(&input, wa_core::export_json(&wa_core::import_markdown("# 标题\n\n正文")).unwrap()).unwrap();
+    let docx = temp_path("out.docx");
+    let out = wa_export(&[input.to_str().unwrap(), docx.to_str().unwrap()], "");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(std::fs::read(&docx).unwrap().starts_with(b"PK\x03\x04"));
+
+    let pdf = temp_path("out.pdf");
+    let out = wa_export(&["--from", "md", "--paper", "letter", "--margin", "15", "-", pdf.to_str().unwrap()], "# 标题\n\n正文");
+    if out.status.success() {
+        assert!(std::fs::read(&pdf).unwrap().starts_with(b"%PDF"));
+    } else {
+        // No usable font on this machine.
+        assert!(String::from_utf8_lossy(&out.stderr).contains("font not found"));
+    }
+    for path in [input, docx, pdf] {
+        std::fs::remove_file(path).ok();
+    }
+}
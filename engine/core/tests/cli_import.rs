@@ -0,0 +1,73 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn wa_import(args: &[&str], dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_wa_import")).args(args).current_dir(dir).output().unwrap()
+}
+
+/// A fresh directory with one .md, .txt and .html file, the markdown one in
+/// a subdirectory.
+fn mixed_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wa_cli_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("docs/sub")).unwrap();
+    std::fs::write(dir.join("docs/sub/a.md"), "# 标题\n\n正文 text").unwrap();
+    std::fs::write(dir.join("docs/b.txt"), "第一段\n\n第二段").unwrap();
+    std::fs::write(dir.join("docs/c.html"), "<p>hello <b>world</b></p>").unwrap();
+    dir
+}
+
+#[test]
+fn converts_one_file_to_each_format() {
+    let dir = mixed_dir();
+    let out = wa_import(&["docs/sub/a.md", "a.json"], &dir);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let doc = wa_core::import_json(&std::fs::read_to_string(dir.join("a.json")).unwrap()).unwrap();
+    assert_eq!(doc.blocks.len(), 2);
+
+    let out = wa_import(&["--format", "html", "docs/b.txt", "-"], &dir);
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "<p>第一段</p>\n<p>第二段</p>\n");
+
+    let out = wa_import(&["--format", "txt", "docs/c.html"], &dir);
+    assert_eq!(String::from_utf8(out.stdout).unwrap().trim_end(), "hello world");
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn reads_stdin_and_prints_stats() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wa_import"))
+        .args(["--from", "html", "--format", "md", "--stats", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"<p>one</p><p>two</p>").unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "one\n\ntwo\n");
+    assert_eq!(String::from_utf8(out.stderr).unwrap(), "-: 2 blocks, 6 chars, 6 chars without spaces, 2 words\n");
+}
+
+#[test]
+fn batch_keeps_relative_names_and_reports_partial_failures() {
+    let dir = mixed_dir();
+    let out = wa_import(&["--out-dir", "out", "--stats", "docs/sub/*.md", "docs/b.txt", "docs/c.htm?"], &dir);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    for name in ["out/docs/sub/a.md", "out/docs/b.md", "out/docs/c.md"] {
+        assert!(dir.join(name).is_file(), "{name} missing");
+    }
+    assert_eq!(String::from_utf8(out.stderr).unwrap().lines().count(), 3);
+
+    let out = wa_import(&["--out-dir", "out", "--format", "json", "docs/b.txt", "docs/missing.md"], &dir);
+    assert_eq!(out.status.code(), Some(9));
+    assert!(dir.join("out/docs/b.json").is_file());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.starts_with("wa_import: docs/missing.md: io error"), "{stderr}");
+
+    let out = wa_import(&["--out-dir", "out", "docs/missing.md", "docs/*.xyz"], &dir);
+    assert_eq!(out.status.code(), Some(3));
+    let out = wa_import(&["docs/b.txt", "x.md", "y.md"], &dir);
+    assert_eq!(out.status.code(), Some(2));
+    std::fs::remove_dir_all(dir).ok();
+}
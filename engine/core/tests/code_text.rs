@@ -0,0 +1,145 @@
+use std::sync::Arc;
+use wa_core::{
+    block_plain_text, export_json, find_in_doc, hash_block, import_json, Block, CodeText, Document, Editor, EditorCommand,
+    FindOptions, CODE_ROPE_THRESHOLD,
+};
+
+fn code_doc(code: &str) -> Document {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Code {
+        id: uuid::Uuid::new_v4(),
+        lang: Arc::from("rs"),
+        code: CodeText::from(code),
+        dirty: false,
+        locked: false,
+    });
+    doc
+}
+
+fn large_code() -> String {
+    "let 值 = compute(input);\r\n".repeat(CODE_ROPE_THRESHOLD / 10)
+}
+
+#[test]
+fn rope_reads_like_the_flat_string() {
+    let text = large_code() + "tail\n\n";
+    let rope = CodeText::from(text.as_str());
+    assert!(rope.is_rope());
+    assert_eq!(rope.len(), text.len());
+    assert_eq!(rope.char_len(), text.chars().count());
+    assert_eq!(rope.to_string(), text);
+    assert!(rope.lines().eq(text.lines()));
+    assert!(rope.lines_with_endings().eq(text.split_inclusive('\n')));
+    assert_eq!(rope.line_count(), text.matches('\n').count() + 1);
+
+    for small in ["", "a", "a\n", "a\r\nb", "\n\n"] {
+        let flat = CodeText::from(small);
+        assert!(!flat.is_rope());
+        assert!(flat.lines().eq(small.lines()));
+    }
+}
+
+#[test]
+fn rope_and_flat_compare_and_hash_alike() {
+    let text = large_code();
+    let mut grown = CodeText::from("");
+    grown.splice(0..0, &text);
+    assert!(grown.is_rope());
+    let flat_doc = code_doc("x");
+    let mut rope_doc = flat_doc.clone();
+    if let (Block::Code { code: a, .. }, Block::Code { code: b, .. }) = (&flat_doc.blocks[0], &mut rope_doc.blocks[0]) {
+        *b = CodeText::from(text.as_str());
+        assert_ne!(a, b);
+        b.splice(0..text.len(), "x");
+        assert!(b.is_rope());
+        assert_eq!(a, b);
+    }
+    assert_eq!(hash_block(&flat_doc.blocks[0]), hash_block(&rope_doc.blocks[0]));
+}
+
+#[test]
+fn json_stays_a_plain_string() {
+    let text = large_code();
+    let doc = code_doc(&text);
+    let json = export_json(&doc).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["blocks"][0]["code"].as_str(), Some(text.as_str()));
+    let back = import_json(&json).unwrap();
+    assert!(matches!(&back.blocks[0], Block::Code { code, .. } if code.is_rope() && code.to_string() == text));
+}
+
+#[test]
+fn large_block_edits_find_and_undo() {
+    let text = large_code();
+    let mut editor = Editor::new(code_doc(&text));
+    let block_id = editor.doc.blocks[0].id();
+    let middle = text.chars().count() / 2;
+    editor.execute(EditorCommand::ReplaceMatch {
+        block_id,
+        start: middle,
+        end: middle,
+        replacement: "/* 标记 */".to_string(),
+    });
+    let code = FindOptions { include_code: true, ..FindOptions::default() };
+    let hits = find_in_doc(&editor.doc, "标记", code.clone());
+    assert_eq!(hits.iter().map(|m| m.start).collect::<Vec<_>>(), vec![middle + 3]);
+    assert_eq!(editor.text_index.find_in_doc(&editor.doc, "标记", code.clone()), hits);
+    assert_eq!(find_in_doc(&editor.doc, "值", code.clone()).len(), CODE_ROPE_THRESHOLD / 10);
+
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), text);
+    editor.execute(EditorCommand::Redo);
+    assert!(matches!(&editor.doc.blocks[0], Block::Code { code, .. } if code.is_rope() && code.len() == text.len() + 12));
+
+    editor.execute(EditorCommand::ReplaceAll {
+        query: "COMPUTE".to_string(),
+        replacement: "run".to_string(),
+        options: FindOptions { case_insensitive: true, ..code.clone() },
+    });
+    assert!(find_in_doc(&editor.doc, "compute", code).is_empty());
+    let chars = block_plain_text(&editor.doc.blocks[0]).chars().count();
+    assert_eq!(editor.text_index.stats(&editor.doc.blocks[0]).chars, chars);
+}
+
+#[test]
+fn line_and_column_round_trip_in_flat_and_rope_code() {
+    let small = "fn 主() {\r\n    let x = \"🦀\";\n}\n".to_string();
+    for text in [small.clone(), large_code() + &small] {
+        let doc = code_doc(&text);
+        let block = &doc.blocks[0];
+        let chars: Vec<char> = text.chars().collect();
+        let (mut line, mut col) = (1, 1);
+        for offset in 0..=chars.len() {
+            assert_eq!(wa_core::code_offset_to_line_col(block, offset), Some((line, col)));
+            if offset == 0 || chars[offset - 1] != '\r' {
+                assert_eq!(wa_core::code_line_col_to_offset(block, line, col), Some(offset));
+            }
+            match chars.get(offset) {
+                Some('\n') => (line, col) = (line + 1, 1),
+                _ => col += 1,
+            }
+        }
+        assert_eq!(wa_core::code_offset_to_line_col(block, chars.len() + 1), None);
+    }
+
+    let doc = code_doc(&small);
+    let block = &doc.blocks[0];
+    // Columns clamp before the line break, CRLF included.
+    assert_eq!(wa_core::code_line_col_to_offset(block, 1, 99), Some("fn 主() {".chars().count()));
+    assert_eq!(wa_core::code_line_col_to_offset(block, 2, 99), Some(10 + "    let x = \"🦀\";".chars().count()));
+    assert_eq!(wa_core::code_line_col_to_offset(block, 4, 1), Some(small.chars().count()));
+    assert_eq!(wa_core::code_line_col_to_offset(block, 5, 1), None);
+    assert_eq!(wa_core::code_line_col_to_offset(block, 0, 1), None);
+}
+
+#[test]
+fn goto_line_moves_the_caret_into_the_code_block() {
+    let doc = code_doc("a\nbb\nccc");
+    let id = doc.blocks[0].id();
+    let mut editor = Editor::new(doc);
+    assert!(editor.goto_line(id, 3, 2));
+    assert!(editor.selection.is_collapsed());
+    assert_eq!(editor.selection.focus.offset, 6);
+    assert!(!editor.goto_line(id, 9, 1));
+    assert_eq!(editor.selection.focus.offset, 6);
+}
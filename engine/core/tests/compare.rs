@@ -0,0 +1,100 @@
+use std::process::Command;
+use std::sync::Arc;
+use wa_core::{char_slice, compare_documents, diff_words, export_json, Block, BlockDiff, Document, Inline, TextChange};
+
+fn paragraph(text: &str) -> Block {
+    Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false }
+}
+
+fn doc_of(texts: &[&str]) -> Document {
+    let mut doc = Document::new();
+    doc.blocks = texts.iter().map(|t| paragraph(t)).collect();
+    doc
+}
+
+/// The changed words, read back from the texts they point into.
+fn changed_words(old: &str, new: &str, changes: &[TextChange]) -> Vec<String> {
+    changes
+        .iter()
+        .map(|change| match change {
+            TextChange::Deleted { start, end } => format!("-{}", char_slice(old, *start, *end)),
+            TextChange::Inserted { start, end } => format!("+{}", char_slice(new, *start, *end)),
+        })
+        .collect()
+}
+
+#[test]
+fn moved_paragraphs_are_not_modified_with_or_without_their_ids() {
+    let old = doc_of(&["第一段", "第二段", "第三段", "第四段"]);
+    let mut moved = old.clone();
+    let second = moved.blocks.remove(1);
+    moved.blocks.insert(3, second);
+    assert_eq!(compare_documents(&old, &moved), []);
+
+    // Imported again: same text, fresh ids.
+    let mut reimported = moved.clone();
+    for block in &mut reimported.blocks {
+        *block.id_mut() = uuid::Uuid::new_v4();
+    }
+    assert_eq!(compare_documents(&old, &reimported), []);
+}
+
+#[test]
+fn an_edited_sentence_reports_its_words() {
+    let old_text = "The quick brown fox jumps.";
+    let new_text = "The quick red fox jumps over.";
+    let old = doc_of(&["Intro", old_text, "Gone"]);
+    let mut new = old.clone();
+    new.blocks[1] = Block::Paragraph { id: new.blocks[1].id(), content: vec![Inline::Text { value: Arc::from(new_text) }], dirty: false, locked: false };
+    new.blocks.remove(2);
+    new.blocks.insert(0, paragraph("Title"));
+
+    let diffs = compare_documents(&old, &new);
+    assert_eq!(diffs.len(), 3);
+    assert_eq!(diffs[0], BlockDiff::Added { block_id: new.blocks[0].id(), index: 0 });
+    let BlockDiff::Modified { old_index: 1, new_index: 2, changes, .. } = &diffs[1] else {
+        panic!("expected the sentence to be modified: {:?}", diffs[1]);
+    };
+    assert_eq!(changed_words(old_text, new_text, changes), ["-brown", "+red", "+ over"]);
+    assert_eq!(diffs[2], BlockDiff::Removed { block_id: old.blocks[2].id(), index: 2 });
+
+    // Without ids in common the edit still pairs up by shared words.
+    let mut reimported = new.clone();
+    for block in &mut reimported.blocks {
+        *block.id_mut() = uuid::Uuid::new_v4();
+    }
+    let kinds: Vec<_> = compare_documents(&old, &reimported)
+        .iter()
+        .map(|d| match d {
+            BlockDiff::Added { .. } => "added",
+            BlockDiff::Removed { .. } => "removed",
+            BlockDiff::Modified { .. } => "modified",
+        })
+        .collect();
+    assert_eq!(kinds, ["added", "modified", "removed"]);
+
+    // CJK text compares character by character.
+    let changes = diff_words("今天天气很好", "今天天气不好");
+    assert_eq!(changed_words("今天天气很好", "今天天气不好", &changes), ["-很", "+不"]);
+}
+
+#[test]
+fn wa_diff_prints_a_unified_report() {
+    let dir = std::env::temp_dir().join(format!("wa_diff_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let old = doc_of(&["same", "old words here"]);
+    let mut new = old.clone();
+    new.blocks[1] = Block::Paragraph { id: new.blocks[1].id(), content: vec![Inline::Text { value: Arc::from("new words here") }], dirty: false, locked: false };
+    let (old_path, new_path) = (dir.join("old.json"), dir.join("new.json"));
+    std::fs::write(&old_path, export_json(&old).unwrap()).unwrap();
+    std::fs::write(&new_path, export_json(&new).unwrap()).unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_wa_diff")).arg(&old_path).arg(&new_path).output().unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    let report = String::from_utf8(out.stdout).unwrap();
+    assert!(report.ends_with("@@ -2 +2 @@\n-[-old-] words here\n+{+new+} words here\n"), "{report}");
+
+    let out = Command::new(env!("CARGO_BIN_EXE_wa_diff")).arg(&old_path).arg(&old_path).output().unwrap();
+    assert_eq!((out.status.code(), out.stdout.len()), (Some(0), 0));
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use wa_core::{
+    doc_semantic_eq, export_json, export_json_fast, export_markdown, hash_block, import_json, import_markdown, Block, CommandError,
+    Document, Editor, EditorCommand, Position, Selection,
+};
+
+const PAYLOAD: &str = r#"{"zeta":1,"alpha":[true,null,{"y":"二","x":"a --> b"}],"mid":{"b":2.5,"a":-3}}"#;
+
+fn custom_doc(kind: &str, data: &str) -> Document {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Custom {
+        id: uuid::Uuid::new_v4(),
+        kind: Arc::from(kind),
+        data: serde_json::from_str(data).unwrap(),
+        dirty: false,
+        locked: false,
+    });
+    doc
+}
+
+fn payload(block: &Block) -> String {
+    match block {
+        Block::Custom { data, .. } => data.to_string(),
+        other => panic!("expected custom block, got {:?}", other),
+    }
+}
+
+#[test]
+fn json_keeps_the_payload_as_written() {
+    let doc = custom_doc("chart", PAYLOAD);
+    let json = export_json_fast(&doc).unwrap();
+    assert!(json.contains(PAYLOAD));
+    let back = import_json(&json).unwrap();
+    assert_eq!(payload(&back.blocks[0]), PAYLOAD);
+    assert_eq!(export_json_fast(&back).unwrap(), json);
+    let pretty = import_json(&export_json(&doc).unwrap()).unwrap();
+    assert_eq!(payload(&pretty.blocks[0]), PAYLOAD);
+    assert!(doc_semantic_eq(&doc, &back));
+}
+
+#[test]
+fn markdown_carries_custom_blocks_in_a_comment() {
+    let mut doc = custom_doc("chart", PAYLOAD);
+    doc.blocks.extend(import_markdown("段落").blocks);
+    let md = export_markdown(&doc);
+    assert!(md.starts_with("<!-- wa-custom chart "));
+    assert_eq!(md.matches("-->").count(), 1);
+    let back = import_markdown(&md);
+    assert_eq!(payload(&back.blocks[0]), PAYLOAD);
+    assert!(doc_semantic_eq(&doc, &back));
+
+    let broken = import_markdown("<!-- wa-custom chart {not json} -->");
+    assert!(matches!(broken.blocks.as_slice(), [Block::Paragraph { .. }]));
+}
+
+#[test]
+fn payload_changes_the_signature() {
+    let a = custom_doc("chart", r#"{"v":1}"#);
+    let b = custom_doc("chart", r#"{"v":2}"#);
+    let c = custom_doc("map", r#"{"v":1}"#);
+    assert_ne!(hash_block(&a.blocks[0]), hash_block(&b.blocks[0]));
+    assert_ne!(hash_block(&a.blocks[0]), hash_block(&c.blocks[0]));
+    assert!(!doc_semantic_eq(&a, &b));
+    assert!(!doc_semantic_eq(&a, &c));
+}
+
+#[test]
+fn editing_leaves_custom_blocks_alone() {
+    let doc = custom_doc("chart", PAYLOAD);
+    let block_id = doc.blocks[0].id();
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id, offset: 0 });
+    editor.execute(EditorCommand::InsertText("x".to_string()));
+    editor.execute(EditorCommand::ApplyStyle(wa_core::Style { bold: true, ..Default::default() }));
+    editor.execute(EditorCommand::DeleteSelection);
+    assert!(matches!(
+        editor.try_execute(EditorCommand::SetHeading(1)),
+        Err(CommandError::NotConvertible("custom"))
+    ));
+    let custom = editor.doc.blocks.iter().find(|b| b.id() == block_id).expect("custom block kept");
+    assert_eq!(payload(custom), PAYLOAD);
+
+    let data = serde_json::json!({ "title": "提示" });
+    editor.execute(EditorCommand::InsertCustom { kind: "callout".to_string(), data: data.clone() });
+    assert!(matches!(editor.doc.blocks.last(), Some(Block::Custom { kind, data: d, .. }) if kind.as_ref() == "callout" && *d == data));
+    editor.execute(EditorCommand::Undo);
+    assert!(matches!(editor.doc.blocks.last(), Some(Block::Custom { kind, .. }) if kind.as_ref() == "chart"));
+}
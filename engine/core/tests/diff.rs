@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use wa_core::{hash_block, Block, DiffEngine, Document, Editor, EditorCommand, Inline, Patch, PatchKind};
+
+/// The diff as it was before signatures and the version fast path: every
+/// block is hashed on every call.
+#[derive(Default)]
+struct FullRehashDiff {
+    hashes: HashMap<uuid::Uuid, u64>,
+}
+
+impl FullRehashDiff {
+    fn diff(&mut self, doc: &Document) -> Vec<Patch> {
+        let mut out = Vec::new();
+        let mut next = HashMap::new();
+        for block in &doc.blocks {
+            let hash = hash_block(block);
+            match self.hashes.get(&block.id()) {
+                None => out.push(Patch { block_id: block.id(), kind: PatchKind::InsertBlock }),
+                Some(prev) if *prev != hash => out.push(Patch { block_id: block.id(), kind: PatchKind::ReplaceBlock }),
+                _ => {}
+            }
+            next.insert(block.id(), hash);
+        }
+        for id in self.hashes.keys() {
+            if !next.contains_key(id) {
+                out.push(Patch { block_id: *id, kind: PatchKind::RemoveBlock });
+            }
+        }
+        self.hashes = next;
+        out
+    }
+}
+
+type Step = Box<dyn Fn(&mut Editor)>;
+
+fn sorted(mut patches: Vec<Patch>) -> Vec<(uuid::Uuid, u8)> {
+    let mut out: Vec<(uuid::Uuid, u8)> = patches.drain(..).map(|p| (p.block_id, p.kind as u8)).collect();
+    out.sort();
+    out
+}
+
+fn paragraph(text: &str) -> Block {
+    Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![Inline::Text { value: Arc::from(text) }],
+        dirty: false,
+        locked: false,
+    }
+}
+
+#[test]
+fn signature_diff_matches_full_rehash() {
+    let mut doc = Document::new();
+    for i in 0..50 {
+        doc.blocks.push(paragraph(&format!("block {}", i)));
+    }
+    let mut editor = Editor::new(doc);
+    let mut fast = DiffEngine::new();
+    let mut reference = FullRehashDiff::default();
+    assert_eq!(
+        sorted(fast.incremental_diff_with_signatures(&editor.doc, &editor.signatures)),
+        sorted(reference.diff(&editor.doc))
+    );
+
+    let steps: Vec<Step> = vec![
+        Box::new(|e| e.execute(EditorCommand::InsertText("typed".to_string()))),
+        Box::new(|e| e.execute(EditorCommand::InsertTable(2, 2))),
+        Box::new(|e| {
+            let id = e.doc.blocks[10].id();
+            e.execute(EditorCommand::MoveBlock { block_id: id, to_index: 30 });
+        }),
+        Box::new(|e| e.execute(EditorCommand::Undo)),
+        Box::new(|e| e.execute(EditorCommand::Undo)),
+        Box::new(|e| {
+            // Direct edits outside the editor, announced by touch().
+            e.doc.blocks.remove(3);
+            e.doc.blocks[4] = paragraph("replaced");
+            e.doc.touch();
+        }),
+        Box::new(|e| e.execute(EditorCommand::Redo)),
+    ];
+    for step in steps {
+        step(&mut editor);
+        assert_eq!(
+            sorted(fast.incremental_diff_with_signatures(&editor.doc, &editor.signatures)),
+            sorted(reference.diff(&editor.doc))
+        );
+        editor.doc.clear_dirty();
+    }
+}
+
+#[test]
+fn unchanged_version_short_circuits() {
+    let mut doc = Document::new();
+    doc.blocks.push(paragraph("a"));
+    let mut diff = DiffEngine::new();
+    assert_eq!(diff.incremental_diff(&doc).len(), 1);
+    assert!(diff.incremental_diff(&doc).is_empty());
+    doc.blocks.push(paragraph("b"));
+    doc.touch();
+    let patches = diff.incremental_diff(&doc);
+    assert_eq!(patches.len(), 1);
+    assert_eq!(patches[0].kind, PatchKind::InsertBlock);
+}
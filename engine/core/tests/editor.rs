@@ -0,0 +1,1795 @@
+﻿use wa_core::{block_plain_text, block_text_len, char_slice, word_end_after, word_start_before, document_stats, find_in_doc, hash_block, selection_stats, Block, CodeText, CommandError, CommandOutcome, Direction, Document, Editor, EditorCommand, FindOptions, Inline, InputFilter, ListItem, PlainTextIndex, Position, Retarget, SearchIndex, Selection, Style, TargetKind};
+use std::sync::Arc;
+
+fn paragraph_doc(text: &str) -> Document {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![Inline::Text { value: Arc::from(text) }],
+        dirty: false,
+        locked: false,
+    });
+    doc
+}
+
+#[test]
+fn insert_text_moves_caret() {
+    let mut editor = Editor::new(paragraph_doc("ab"));
+    editor.execute(EditorCommand::InsertText("中文".to_string()));
+    assert!(editor.selection.is_collapsed());
+    assert_eq!(editor.selection.focus.offset, 2);
+    editor.execute(EditorCommand::InsertText("c".to_string()));
+    assert_eq!(editor.selection.focus.offset, 3);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "中文cab");
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(editor.selection.focus.offset, 2);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "中文ab");
+}
+
+#[test]
+fn backspace_deletes_one_char_before_the_caret() {
+    let bold = Style { bold: true, ..Style::default() };
+    let block_id = uuid::Uuid::new_v4();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: block_id,
+        content: vec![
+            Inline::Text { value: Arc::from("hello ") },
+            Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("world") }] },
+            Inline::CodeSpan { value: Arc::from("x") },
+        ],
+        dirty: false,
+        locked: false,
+    });
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id, offset: 11 });
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "hello worlx");
+    assert_eq!(editor.selection.focus, Position { block_id, offset: 10 });
+
+    // The last char of a run takes the run with it.
+    editor.selection = Selection::collapsed(Position { block_id, offset: 11 });
+    editor.execute(EditorCommand::DeleteSelection);
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(content, &vec![
+        Inline::Text { value: Arc::from("hello ") },
+        Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("worl") }] },
+    ]);
+    assert_eq!(editor.selection.focus.offset, 10);
+
+    // At the start of the block there is nothing before the caret.
+    editor.selection = Selection::collapsed(Position { block_id, offset: 0 });
+    let version = editor.doc.version;
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!((editor.doc.version, editor.selection.focus.offset), (version, 0));
+
+    // A range selection goes as a whole.
+    editor.selection = Selection { anchor: Position { block_id, offset: 8 }, focus: Position { block_id, offset: 4 } };
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "hellrl");
+    assert_eq!(editor.selection.focus.offset, 4);
+}
+
+#[test]
+fn backspace_at_the_start_joins_the_block_before() {
+    let bold = Style { bold: true, ..Style::default() };
+    let ids: Vec<_> = (0..3).map(|_| uuid::Uuid::new_v4()).collect();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Heading { id: ids[0], level: 1, content: vec![Inline::Text { value: Arc::from("标题") }], dirty: false, locked: false });
+    doc.blocks.push(Block::Paragraph { id: ids[1], content: vec![Inline::Text { value: Arc::from("ab") }], dirty: false, locked: false });
+    doc.blocks.push(Block::Paragraph {
+        id: ids[2],
+        content: vec![Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("cd") }] }],
+        dirty: false,
+        locked: false,
+    });
+    let mut editor = Editor::new(doc);
+
+    // Paragraph into paragraph, keeping the styles of the joined part.
+    editor.selection = Selection::collapsed(Position { block_id: ids[2], offset: 0 });
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(editor.doc.blocks.len(), 2);
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[1] else { unreachable!() };
+    assert_eq!(content, &vec![
+        Inline::Text { value: Arc::from("ab") },
+        Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("cd") }] },
+    ]);
+    assert_eq!(editor.selection.focus, Position { block_id: ids[1], offset: 2 });
+
+    // Paragraph into the heading before it; the heading stays a heading.
+    editor.selection = Selection::collapsed(Position { block_id: ids[1], offset: 0 });
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(editor.doc.blocks.len(), 1);
+    assert!(matches!(&editor.doc.blocks[0], Block::Heading { id, level: 1, .. } if *id == ids[0]));
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "标题abcd");
+    assert_eq!(editor.selection.focus, Position { block_id: ids[0], offset: 2 });
+
+    // Each join is one undo step that brings both blocks back.
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), ids[..2]);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "abcd");
+    assert_eq!(editor.selection.focus, Position { block_id: ids[1], offset: 0 });
+
+    // The first block of the document has nothing to join.
+    editor.selection = Selection::collapsed(Position { block_id: ids[0], offset: 0 });
+    let version = editor.doc.version;
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!((editor.doc.blocks.len(), editor.doc.version), (2, version));
+}
+
+#[test]
+fn backspace_at_the_start_joins_lists_and_steps_into_code() {
+    let list = uuid::Uuid::new_v4();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::List {
+        id: list,
+        ordered: false,
+        items: ["一", "二"].map(|t| ListItem { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(t) }], level: 0, checked: None }).into(),
+        dirty: false,
+        locked: false,
+        toc: false,
+    });
+    doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from("三") }], dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id: editor.doc.blocks[1].id(), offset: 0 });
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(editor.doc.blocks.len(), 1);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "一\n二三");
+    assert_eq!(editor.selection.focus, Position { block_id: list, offset: 3 });
+
+    // A paragraph after a code block is left alone; the caret moves to the
+    // end of the code.
+    editor.execute(EditorCommand::InsertCode { lang: "rs".to_string(), code: "fn".to_string() });
+    editor.doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from("after") }], dirty: false, locked: false });
+    let code = editor.doc.blocks[1].id();
+    editor.selection = Selection::collapsed(Position { block_id: editor.doc.blocks[2].id(), offset: 0 });
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(editor.doc.blocks.len(), 3);
+    assert_eq!(block_plain_text(&editor.doc.blocks[2]), "after");
+    assert_eq!(editor.selection.focus, Position { block_id: code, offset: 2 });
+}
+
+#[test]
+fn backspace_edits_code_and_joins_list_items() {
+    let code = uuid::Uuid::new_v4();
+    let list = uuid::Uuid::new_v4();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Code { id: code, lang: Arc::from("rs"), code: CodeText::from("ab\ncd"), dirty: false, locked: false });
+    doc.blocks.push(Block::List {
+        id: list,
+        ordered: false,
+        items: ["一二", "三四"].map(|t| ListItem { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(t) }], level: 0, checked: None }).into(),
+        dirty: false,
+        locked: false,
+        toc: false,
+    });
+    let mut editor = Editor::new(doc);
+
+    // A newline typed into code goes with one backspace.
+    editor.selection = Selection::collapsed(Position { block_id: code, offset: 3 });
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "abcd");
+    assert_eq!(editor.selection.focus, Position { block_id: code, offset: 2 });
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "ab\ncd");
+
+    // Inside an item one char goes; at its start it joins the item before.
+    editor.selection = Selection::collapsed(Position { block_id: list, offset: 4 });
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "一二\n四");
+    editor.selection = Selection::collapsed(Position { block_id: list, offset: 3 });
+    editor.execute(EditorCommand::DeleteSelection);
+    let Block::List { items, .. } = &editor.doc.blocks[1] else { unreachable!() };
+    assert_eq!(items.len(), 1);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "一二四");
+    assert_eq!(editor.selection.focus, Position { block_id: list, offset: 2 });
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "一二\n四");
+
+    // The start of the first item and of the code block stay put.
+    let version = editor.doc.version;
+    for block_id in [list, code] {
+        editor.selection = Selection::collapsed(Position { block_id, offset: 0 });
+        editor.execute(EditorCommand::DeleteSelection);
+    }
+    assert_eq!((editor.doc.blocks.len(), editor.doc.version), (2, version));
+}
+
+#[test]
+fn backspace_takes_a_formula_whole() {
+    let block_id = uuid::Uuid::new_v4();
+    let math = Inline::Math { tex: Arc::from("x^2"), display: false };
+    let content = vec![Inline::Text { value: Arc::from("a ") }, math, Inline::Text { value: Arc::from("!") }];
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph { id: block_id, content: content.clone(), dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id, offset: 5 });
+    editor.execute(EditorCommand::DeleteSelection);
+    let Block::Paragraph { content: after, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(after, &vec![Inline::Text { value: Arc::from("a !") }]);
+    assert_eq!(editor.selection.focus, Position { block_id, offset: 2 });
+    editor.execute(EditorCommand::Undo);
+    assert!(matches!(&editor.doc.blocks[0], Block::Paragraph { content: back, .. } if *back == content));
+}
+
+#[test]
+fn deleting_across_blocks_joins_the_ends() {
+    let bold = Style { bold: true, ..Style::default() };
+    let ids: Vec<_> = (0..4).map(|_| uuid::Uuid::new_v4()).collect();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Heading { id: ids[0], level: 2, content: vec![Inline::Text { value: Arc::from("标题一") }], dirty: false, locked: false });
+    doc.blocks.push(Block::Paragraph { id: ids[1], content: vec![Inline::Text { value: Arc::from("middle") }], dirty: false, locked: false });
+    doc.blocks.push(Block::Code { id: ids[2], lang: Arc::from("rs"), code: CodeText::from("fn"), dirty: false, locked: false });
+    doc.blocks.push(Block::Paragraph {
+        id: ids[3],
+        content: vec![Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("tail end") }] }],
+        dirty: false,
+        locked: false,
+    });
+    let mut editor = Editor::new(doc);
+    let original = editor.doc.blocks.clone();
+
+    // Focus before anchor in document order.
+    editor.selection = Selection { anchor: Position { block_id: ids[3], offset: 5 }, focus: Position { block_id: ids[0], offset: 2 } };
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(editor.doc.blocks.len(), 1);
+    let Block::Heading { id, level: 2, content, .. } = &editor.doc.blocks[0] else { panic!("{:?}", editor.doc.blocks) };
+    assert_eq!(*id, ids[0]);
+    assert_eq!(content, &vec![Inline::Text { value: Arc::from("标题") }, Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("end") }] }]);
+    assert_eq!(editor.selection, Selection::collapsed(Position { block_id: ids[0], offset: 2 }));
+
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), ids);
+    assert!(editor.doc.blocks.iter().zip(&original).all(|(a, b)| block_plain_text(a) == block_plain_text(b)));
+}
+
+#[test]
+fn deleting_across_blocks_keeps_code_and_tables_at_the_ends_whole() {
+    let ids: Vec<_> = (0..3).map(|_| uuid::Uuid::new_v4()).collect();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Code { id: ids[0], lang: Arc::from("rs"), code: CodeText::from("let a = 1;"), dirty: false, locked: false });
+    doc.blocks.push(Block::Paragraph { id: ids[1], content: vec![Inline::Text { value: Arc::from("gone") }], dirty: false, locked: false });
+    doc.blocks.push(Block::Paragraph { id: ids[2], content: vec![Inline::Text { value: Arc::from("kept") }], dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection { anchor: Position { block_id: ids[0], offset: 4 }, focus: Position { block_id: ids[2], offset: 2 } };
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), [ids[0], ids[2]]);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "let a = 1;");
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "pt");
+    assert_eq!(editor.selection, Selection::collapsed(Position { block_id: ids[2], offset: 0 }));
+
+    // Ending inside a table keeps the table and takes the tail of the start.
+    editor.selection = Selection::collapsed(Position { block_id: ids[2], offset: 0 });
+    editor.execute(EditorCommand::InsertTable(2, 2));
+    let table = editor.doc.blocks[2].id();
+    editor.selection = Selection { anchor: Position { block_id: ids[2], offset: 1 }, focus: Position { block_id: table, offset: 3 } };
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "p");
+    assert_eq!(editor.doc.blocks[2].id(), table);
+    assert_eq!(editor.selection, Selection::collapsed(Position { block_id: ids[2], offset: 1 }));
+
+    // A locked block in the way stops the whole delete.
+    editor.execute(EditorCommand::SetBlockLocked { block_id: ids[2], locked: true });
+    editor.selection = Selection { anchor: Position { block_id: ids[0], offset: 0 }, focus: Position { block_id: table, offset: 0 } };
+    assert_eq!(editor.try_execute(EditorCommand::DeleteSelection), Ok(CommandOutcome::BlockedByLock { block_id: ids[2] }));
+    assert_eq!(editor.doc.blocks.len(), 3);
+}
+
+#[test]
+fn backspace_removes_whole_cjk_chars() {
+    let mut editor = Editor::new(paragraph_doc("中文𠀀字"));
+    let block_id = editor.doc.blocks[0].id();
+    editor.selection = Selection::collapsed(Position { block_id, offset: 3 });
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "中文字");
+    editor.execute(EditorCommand::DeleteSelection);
+    editor.execute(EditorCommand::DeleteSelection);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "字");
+    assert_eq!(editor.selection.focus.offset, 0);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "中字");
+    assert_eq!(editor.selection.focus.offset, 1);
+}
+
+#[test]
+fn insert_text_lands_at_the_caret() {
+    let mut editor = Editor::new(paragraph_doc("你好world"));
+    let block_id = editor.doc.blocks[0].id();
+    for (offset, text, expected) in [(0, "<", "<你好world"), (3, "|", "<你好|world"), (9, ">", "<你好|world>"), (99, "!", "<你好|world>!")] {
+        editor.selection = Selection::collapsed(Position { block_id, offset });
+        editor.execute(EditorCommand::InsertText(text.to_string()));
+        assert_eq!(block_plain_text(&editor.doc.blocks[0]), expected);
+        assert_eq!(editor.selection.focus, Position { block_id, offset: offset.min(expected.chars().count() - 1) + 1 });
+    }
+
+    // Between two styled runs the text continues the run before the caret;
+    // inside a link it joins the link text.
+    let bold = Style { bold: true, ..Style::default() };
+    let italic = Style { italic: true, ..Style::default() };
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: block_id,
+        content: vec![
+            Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("ab") }] },
+            Inline::Styled { style: italic, content: vec![Inline::Link { url: Arc::from("u"), text: vec![Inline::Text { value: Arc::from("cd") }] }] },
+        ],
+        dirty: false,
+        locked: false,
+    });
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id, offset: 2 });
+    editor.execute(EditorCommand::InsertText("x".to_string()));
+    editor.selection = Selection::collapsed(Position { block_id, offset: 4 });
+    editor.execute(EditorCommand::InsertText("y".to_string()));
+    assert_eq!(editor.selection.focus.offset, 5);
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(content, &vec![
+        Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("abx") }] },
+        Inline::Styled { style: italic, content: vec![Inline::Link { url: Arc::from("u"), text: vec![Inline::Text { value: Arc::from("cyd") }] }] },
+    ]);
+}
+
+#[test]
+fn move_cursor_steps_over_lines_and_blocks() {
+    let mut doc = paragraph_doc("你好");
+    let first = doc.blocks[0].id();
+    let code = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Code { id: code, lang: Arc::from("rs"), code: CodeText::from("ab\ncde\nf"), dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+    let version = editor.doc.version;
+    let at = |block_id, offset| Position { block_id, offset };
+    editor.selection = Selection::collapsed(at(first, 2));
+    let steps = [
+        (Direction::Right, at(code, 0)),
+        (Direction::Left, at(first, 2)),
+        (Direction::Left, at(first, 1)),
+        (Direction::NextBlock, at(code, 0)),
+        (Direction::NextBlock, at(code, 8)),
+        (Direction::Right, at(code, 8)),
+        (Direction::PrevBlock, at(first, 0)),
+        (Direction::PrevBlock, at(first, 0)),
+        (Direction::BlockEnd, at(first, 2)),
+    ];
+    for (direction, expected) in steps {
+        editor.execute(EditorCommand::MoveCursor { direction, extend: false });
+        assert_eq!(editor.selection, Selection::collapsed(expected), "{direction:?}");
+    }
+
+    // Lines are the code lines, whatever the layout wraps.
+    editor.selection = Selection::collapsed(at(code, 4));
+    editor.execute(EditorCommand::MoveCursor { direction: Direction::LineStart, extend: false });
+    assert_eq!(editor.selection.focus, at(code, 3));
+    editor.execute(EditorCommand::MoveCursor { direction: Direction::LineEnd, extend: true });
+    assert_eq!(editor.selection, Selection { anchor: at(code, 3), focus: at(code, 6) });
+    editor.execute(EditorCommand::MoveCursor { direction: Direction::BlockStart, extend: true });
+    assert_eq!(editor.selection, Selection { anchor: at(code, 3), focus: at(code, 0) });
+    editor.start_recording();
+    editor.execute(EditorCommand::MoveCursor { direction: Direction::Right, extend: false });
+    assert_eq!(editor.selection, Selection::collapsed(at(code, 3)));
+    assert!(editor.stop_recording().is_empty());
+    assert_eq!(editor.doc.version, version);
+    assert!(!editor.can_undo());
+}
+
+#[test]
+fn word_boundaries_split_latin_runs_and_single_cjk_chars() {
+    let text = "say hello, 你好 world";
+    assert_eq!(word_start_before(text, 9), 4);
+    assert_eq!(word_start_before(text, 10), 9);
+    assert_eq!(word_start_before(text, 11), 9);
+    assert_eq!(word_start_before(text, 13), 12);
+    assert_eq!(word_start_before(text, 2), 0);
+    assert_eq!(word_end_after(text, 3), 9);
+    assert_eq!(word_end_after(text, 10), 12);
+    assert_eq!(word_end_after(text, 13), 19);
+    assert_eq!(word_end_after(text, 19), 19);
+}
+
+#[test]
+fn word_moves_and_deletes() {
+    let mut doc = paragraph_doc("one");
+    let first = doc.blocks[0].id();
+    doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from("我们 write it") }], dirty: false, locked: false });
+    let second = doc.blocks[1].id();
+    let mut editor = Editor::new(doc);
+    let at = |block_id, offset| Position { block_id, offset };
+    editor.selection = Selection::collapsed(at(first, 1));
+    for expected in [at(first, 3), at(second, 0), at(second, 1), at(second, 2), at(second, 8), at(second, 11)] {
+        editor.execute(EditorCommand::MoveCursorWord { forward: true, extend: false });
+        assert_eq!(editor.selection.focus, expected);
+    }
+    editor.execute(EditorCommand::MoveCursorWord { forward: false, extend: true });
+    assert_eq!(editor.selection, Selection { anchor: at(second, 11), focus: at(second, 9) });
+
+    // Ctrl+Backspace takes a word at a time, CJK one char at a time, and
+    // the run of deletes undoes in one step.
+    editor.selection = Selection::collapsed(at(second, 11));
+    for expected in ["我们 write ", "我们 ", "我"] {
+        editor.execute(EditorCommand::DeleteWordBackward);
+        assert_eq!(block_plain_text(&editor.doc.blocks[1]), expected);
+    }
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "我们 write it");
+    assert_eq!(editor.selection.focus, at(second, 11));
+
+    editor.selection = Selection::collapsed(at(second, 2));
+    editor.execute(EditorCommand::DeleteWordForward);
+    editor.execute(EditorCommand::DeleteWordForward);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "我们");
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "我们 write it");
+
+    // At the start of a block it joins the block before.
+    editor.selection = Selection::collapsed(at(second, 0));
+    editor.execute(EditorCommand::DeleteWordBackward);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "one我们 write it");
+    assert_eq!(editor.selection.focus, at(first, 3));
+}
+
+#[test]
+fn select_commands_cover_the_word_block_and_document() {
+    let mut doc = paragraph_doc("hello, 世界");
+    let first = doc.blocks[0].id();
+    let list = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::List {
+        id: list,
+        ordered: false,
+        items: ["ab", "cde"].map(|t| ListItem { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(t) }], level: 0, checked: None }).into(),
+        dirty: false,
+        locked: false,
+        toc: false,
+    });
+    assert_eq!(block_text_len(&doc.blocks[0]), 9);
+    assert_eq!(block_text_len(&doc.blocks[1]), block_plain_text(&doc.blocks[1]).chars().count());
+    let mut editor = Editor::new(doc);
+    let at = |block_id, offset| Position { block_id, offset };
+    editor.selection = Selection::collapsed(at(first, 5));
+    editor.execute(EditorCommand::SelectWord);
+    assert_eq!(editor.selection, Selection { anchor: at(first, 5), focus: at(first, 6) });
+    editor.selection = Selection::collapsed(at(first, 3));
+    editor.execute(EditorCommand::SelectWord);
+    assert_eq!(editor.selection, Selection { anchor: at(first, 0), focus: at(first, 5) });
+    editor.selection = Selection::collapsed(at(first, 8));
+    editor.execute(EditorCommand::SelectWord);
+    assert_eq!(editor.selection, Selection { anchor: at(first, 8), focus: at(first, 9) });
+
+    editor.selection = Selection::collapsed(at(list, 4));
+    editor.execute(EditorCommand::SelectBlock);
+    assert_eq!(editor.selection, Selection { anchor: at(list, 0), focus: at(list, 6) });
+    editor.start_recording();
+    editor.execute(EditorCommand::SelectAll);
+    assert_eq!(editor.selection, Selection { anchor: at(first, 0), focus: at(list, 6) });
+    assert!(editor.stop_recording().is_empty());
+    assert!(!editor.can_undo());
+}
+
+#[test]
+fn paragraph_break_splits_at_the_caret() {
+    let bold = Style { bold: true, ..Style::default() };
+    let block_id = uuid::Uuid::new_v4();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: block_id,
+        content: vec![Inline::Text { value: Arc::from("ab ") }, Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("cd") }] }],
+        dirty: false,
+        locked: false,
+    });
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id, offset: 4 });
+    editor.execute(EditorCommand::InsertParagraphBreak);
+    let [Block::Paragraph { id: first, content: head, .. }, Block::Paragraph { id: second, content: tail, .. }] = editor.doc.blocks.as_slice() else {
+        panic!("expected two paragraphs: {:?}", editor.doc.blocks);
+    };
+    assert_eq!(*first, block_id);
+    assert_ne!(*second, block_id);
+    assert_eq!(head, &vec![Inline::Text { value: Arc::from("ab ") }, Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("c") }] }]);
+    assert_eq!(tail, &vec![Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("d") }] }]);
+    assert_eq!(editor.selection.focus, Position { block_id: *second, offset: 0 });
+
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.len(), 1);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "ab cd");
+    assert_eq!(editor.selection.focus, Position { block_id, offset: 4 });
+}
+
+#[test]
+fn paragraph_break_at_the_ends_leaves_an_empty_paragraph() {
+    let block_id = uuid::Uuid::new_v4();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Heading { id: block_id, level: 2, content: vec![Inline::Text { value: Arc::from("标题") }], dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+
+    // At the end the heading stays and a paragraph follows.
+    editor.selection = Selection::collapsed(Position { block_id, offset: 2 });
+    editor.execute(EditorCommand::InsertParagraphBreak);
+    assert!(matches!(&editor.doc.blocks[0], Block::Heading { id, level: 2, .. } if *id == block_id));
+    assert!(matches!(&editor.doc.blocks[1], Block::Paragraph { content, .. } if content.is_empty()));
+    assert_eq!(editor.selection.focus, Position { block_id: editor.doc.blocks[1].id(), offset: 0 });
+
+    // At the start an empty paragraph goes before it.
+    editor.selection = Selection::collapsed(Position { block_id, offset: 0 });
+    editor.execute(EditorCommand::InsertParagraphBreak);
+    assert_eq!(editor.doc.blocks.len(), 3);
+    assert!(matches!(&editor.doc.blocks[0], Block::Paragraph { id, content, .. } if *id == block_id && content.is_empty()));
+    assert!(matches!(&editor.doc.blocks[1], Block::Heading { level: 2, .. }));
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "标题");
+    assert_eq!(editor.selection.focus, Position { block_id: editor.doc.blocks[1].id(), offset: 0 });
+}
+
+#[test]
+fn paragraph_break_in_a_list_adds_an_item() {
+    let list = uuid::Uuid::new_v4();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::List {
+        id: list,
+        ordered: true,
+        items: [("一二", 0), ("三四", 1)].map(|(t, level)| ListItem { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(t) }], level, checked: None }).into(),
+        dirty: false,
+        locked: false,
+        toc: false,
+    });
+    let mut editor = Editor::new(doc);
+    // The caret is between "三" and "四".
+    editor.selection = Selection::collapsed(Position { block_id: list, offset: 4 });
+    editor.execute(EditorCommand::InsertParagraphBreak);
+    assert_eq!(editor.doc.blocks.len(), 1);
+    let Block::List { items, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(items.iter().map(|item| item.level).collect::<Vec<_>>(), [0, 1, 1]);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "一二\n三\n四");
+    assert_eq!(editor.selection.focus, Position { block_id: list, offset: 5 });
+    assert_eq!(editor.focused_list_item(), Some(2));
+}
+
+#[test]
+fn split_list_item_adds_items_and_leaves_the_list_from_an_empty_one() {
+    let list = uuid::Uuid::new_v4();
+    let ids = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4(), uuid::Uuid::new_v4()];
+    let mut doc = Document::new();
+    doc.blocks.push(Block::List {
+        id: list,
+        ordered: false,
+        items: [("ab", 0, None), ("", 1, None), ("cd", 1, Some(true))]
+            .into_iter()
+            .zip(ids)
+            .map(|((t, level, checked), id)| ListItem { id, content: vec![Inline::Text { value: Arc::from(t) }], level, checked })
+            .collect(),
+        dirty: false,
+        locked: false,
+        toc: false,
+    });
+    // Offset 5 is one char into "cd", which starts at 4.
+    let Block::List { items, .. } = &doc.blocks[0] else { unreachable!() };
+    assert_eq!(wa_core::list_item_at(items, 5), (2, 4));
+    let mut editor = Editor::new(doc);
+
+    // Splitting "cd" keeps its level and its tick.
+    editor.selection = Selection::collapsed(Position { block_id: list, offset: 5 });
+    editor.execute(EditorCommand::SplitListItem);
+    let Block::List { items, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(items.iter().map(|item| (item.level, item.checked)).collect::<Vec<_>>(), [(0, None), (1, None), (1, Some(true)), (1, Some(true))]);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "ab\n\nc\nd");
+    assert_eq!(editor.selection.focus, Position { block_id: list, offset: 6 });
+    editor.execute(EditorCommand::Undo);
+
+    // Enter in the empty item leaves the list: it becomes a paragraph
+    // between the items before and after it.
+    editor.selection = Selection::collapsed(Position { block_id: list, offset: 3 });
+    editor.execute(EditorCommand::InsertParagraphBreak);
+    let kinds = editor.doc.blocks.iter().map(|b| b.type_name()).collect::<Vec<_>>();
+    assert_eq!(kinds, ["list", "paragraph", "list"]);
+    assert_eq!(editor.doc.blocks[0].id(), list);
+    assert_eq!(editor.doc.blocks[1].id(), ids[1]);
+    assert_eq!(block_plain_text(&editor.doc.blocks[2]), "cd");
+    assert_eq!(editor.selection, Selection::collapsed(Position { block_id: ids[1], offset: 0 }));
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.len(), 1);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "ab\n\ncd");
+
+    // Outside a list it does nothing.
+    editor.execute(EditorCommand::Redo);
+    let version = editor.doc.version;
+    editor.selection = Selection::collapsed(Position { block_id: editor.doc.blocks[1].id(), offset: 0 });
+    editor.execute(EditorCommand::SplitListItem);
+    assert_eq!(editor.doc.version, version);
+}
+
+#[test]
+fn block_inserts_land_at_the_caret() {
+    let mut doc = paragraph_doc("one");
+    for text in ["two words", "three"] {
+        doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false });
+    }
+    let mut editor = Editor::new(doc);
+    let ids = editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>();
+    let inserts = [
+        (EditorCommand::InsertList(false), "list"),
+        (EditorCommand::InsertQuote("q".to_string()), "quote"),
+        (EditorCommand::InsertCode { lang: "rs".to_string(), code: "fn f() {}".to_string() }, "code"),
+        (EditorCommand::InsertTable(2, 2), "table"),
+        (EditorCommand::InsertImage("local://a".to_string()), "figure"),
+        (EditorCommand::InsertFigure { url: "local://b".to_string(), caption: None }, "figure"),
+        (EditorCommand::InsertImageData { bytes: vec![1, 2, 3], mime: "image/png".to_string(), caption: None }, "figure"),
+        (EditorCommand::InsertCustom { kind: "chart".to_string(), data: serde_json::Value::Null }, "custom"),
+    ];
+    for (cmd, kind) in inserts {
+        editor.selection = Selection::collapsed(Position { block_id: ids[1], offset: 9 });
+        editor.execute(cmd);
+        assert_eq!(editor.doc.blocks[2].type_name(), kind);
+        assert_eq!(editor.selection, Selection::collapsed(Position { block_id: editor.doc.blocks[2].id(), offset: 0 }));
+        editor.execute(EditorCommand::Undo);
+        assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), ids);
+    }
+
+    // Inside a paragraph the block goes between its halves, in one step.
+    editor.selection = Selection::collapsed(Position { block_id: ids[1], offset: 3 });
+    editor.execute(EditorCommand::InsertTable(1, 1));
+    let texts = editor.doc.blocks.iter().map(|b| (b.type_name(), block_plain_text(b))).collect::<Vec<_>>();
+    assert_eq!(texts[1..4], [("paragraph", "two".to_string()), ("table", String::new()), ("paragraph", " words".to_string())]);
+    assert_eq!(editor.selection.focus.block_id, editor.doc.blocks[2].id());
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), ids);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "two words");
+    assert!(!editor.can_undo());
+}
+
+#[test]
+fn convert_block_keeps_text_and_ids() {
+    let bold = Style { bold: true, ..Style::default() };
+    let inlines = vec![Inline::Text { value: Arc::from("a ") }, Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("b") }] }];
+    let id = uuid::Uuid::new_v4();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Heading { id, level: 2, content: inlines.clone(), dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id: id, offset: 2 });
+    let convert = |editor: &mut Editor, target| editor.try_execute(EditorCommand::ConvertBlock(target));
+    let kinds = |editor: &Editor| editor.doc.blocks.iter().map(|b| b.type_name()).collect::<Vec<_>>();
+
+    convert(&mut editor, TargetKind::Paragraph).unwrap();
+    assert!(matches!(&editor.doc.blocks[0], Block::Paragraph { id: same, content, .. } if *same == id && *content == inlines));
+    let version = editor.doc.version;
+    convert(&mut editor, TargetKind::Paragraph).unwrap();
+    assert_eq!(editor.doc.version, version);
+
+    convert(&mut editor, TargetKind::Quote).unwrap();
+    assert_eq!((kinds(&editor), editor.doc.blocks[0].id()), (vec!["quote"], id));
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "a b");
+    convert(&mut editor, TargetKind::Code { lang: "md".to_string() }).unwrap();
+    assert!(matches!(&editor.doc.blocks[0], Block::Code { id: same, lang, .. } if *same == id && &**lang == "md"));
+
+    // Code splits into one block per line, in one undo step.
+    editor.execute(EditorCommand::InsertText("\n\nc".to_string()));
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "a \n\ncb");
+    convert(&mut editor, TargetKind::Paragraph).unwrap();
+    assert_eq!(kinds(&editor), ["paragraph"; 3]);
+    assert_eq!(editor.doc.blocks.iter().map(block_plain_text).collect::<Vec<_>>(), ["a ", "", "cb"]);
+    assert_eq!(editor.doc.blocks[0].id(), id);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(kinds(&editor), ["code"]);
+    convert(&mut editor, TargetKind::Heading(3)).unwrap();
+    assert_eq!(kinds(&editor), ["heading"; 3]);
+    editor.execute(EditorCommand::Undo);
+    convert(&mut editor, TargetKind::Quote).unwrap();
+    assert_eq!(editor.doc.blocks[0].children().len(), 3);
+
+    // A quote unwraps into the blocks it holds, which keep their ids.
+    let inner = editor.doc.blocks[0].children().iter().map(|b| b.id()).collect::<Vec<_>>();
+    convert(&mut editor, TargetKind::Paragraph).unwrap();
+    assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), inner);
+    assert_eq!(editor.selection.focus.block_id, inner[0]);
+
+    editor.execute(EditorCommand::InsertTable(1, 1));
+    let table = editor.doc.blocks.iter().map(|b| b.type_name()).position(|kind| kind == "table").unwrap();
+    editor.selection = Selection::collapsed(Position { block_id: editor.doc.blocks[table].id(), offset: 0 });
+    let version = editor.doc.version;
+    assert_eq!(convert(&mut editor, TargetKind::Code { lang: String::new() }), Err(CommandError::CannotConvert { from: "table", to: "code" }));
+    assert_eq!(editor.doc.version, version);
+}
+
+#[test]
+fn toggle_list_wraps_paragraphs_and_unwraps_items() {
+    let mut doc = Document::new();
+    let texts = ["intro", "one", "two", "fixed", "after"];
+    for (i, text) in texts.into_iter().enumerate() {
+        let (id, content) = (uuid::Uuid::new_v4(), vec![Inline::Text { value: Arc::from(text) }]);
+        doc.blocks.push(match i {
+            0 => Block::Heading { id, level: 1, content, dirty: false, locked: false },
+            _ => Block::Paragraph { id, content, dirty: false, locked: i == 3 },
+        });
+    }
+    let ids = doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>();
+    let mut editor = Editor::new(doc);
+    let kinds = |editor: &Editor| editor.doc.blocks.iter().map(|b| b.type_name()).collect::<Vec<_>>();
+
+    // The run stops at the heading the anchor is in and at the locked paragraph.
+    editor.selection = Selection { anchor: Position { block_id: ids[0], offset: 1 }, focus: Position { block_id: ids[2], offset: 2 } };
+    editor.execute(EditorCommand::ToggleList(false));
+    assert_eq!(kinds(&editor), ["heading", "list", "paragraph", "paragraph"]);
+    let list = editor.doc.blocks[1].id();
+    let Block::List { ordered: false, items, dirty: true, .. } = &editor.doc.blocks[1] else { panic!("expected an unordered list") };
+    assert_eq!(items.iter().map(|item| item.id).collect::<Vec<_>>(), ids[1..3]);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "one\ntwo");
+    assert_eq!(editor.selection, Selection::collapsed(Position { block_id: list, offset: 6 }));
+
+    // The other kind only flips the list.
+    editor.execute(EditorCommand::ToggleList(true));
+    assert!(matches!(&editor.doc.blocks[1], Block::List { id, ordered: true, items, .. } if *id == list && items.len() == 2));
+
+    // The same kind turns the items back into their paragraphs.
+    editor.execute(EditorCommand::ToggleList(true));
+    assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), ids);
+    assert_eq!(kinds(&editor), ["heading", "paragraph", "paragraph", "paragraph", "paragraph"]);
+    assert_eq!(editor.selection.focus, Position { block_id: ids[2], offset: 2 });
+
+    editor.execute(EditorCommand::Undo);
+    assert!(matches!(&editor.doc.blocks[1], Block::List { ordered: true, .. }));
+    editor.execute(EditorCommand::Undo);
+    assert!(matches!(&editor.doc.blocks[1], Block::List { ordered: false, .. }));
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), ids);
+    assert_eq!(kinds(&editor), ["heading", "paragraph", "paragraph", "paragraph", "paragraph"]);
+
+    // Headings are left alone.
+    let version = editor.doc.version;
+    editor.selection = Selection::collapsed(Position { block_id: ids[0], offset: 0 });
+    editor.execute(EditorCommand::ToggleList(false));
+    assert_eq!(editor.doc.version, version);
+}
+
+#[test]
+fn paragraph_break_in_code_is_a_newline() {
+    let mut editor = Editor::new(paragraph_doc("intro"));
+    editor.execute(EditorCommand::InsertCode { lang: "rs".to_string(), code: "fn main() {}".to_string() });
+    let code = editor.doc.blocks[1].id();
+    editor.selection = Selection::collapsed(Position { block_id: code, offset: 11 });
+    editor.execute(EditorCommand::InsertParagraphBreak);
+    assert_eq!(editor.doc.blocks.len(), 2);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "fn main() {\n}");
+    assert_eq!(editor.selection.focus.offset, 12);
+}
+
+#[test]
+fn edit_and_remove_link() {
+    let mut doc = Document::new();
+    let id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Paragraph {
+        id,
+        content: vec![
+            Inline::Text { value: Arc::from("see ") },
+            Inline::Link {
+                url: Arc::from("https://old.example"),
+                text: vec![Inline::Text { value: Arc::from("docs") }],
+            },
+        ],
+        dirty: false,
+        locked: false,
+    });
+    let mut editor = Editor::new(doc);
+    editor.execute(EditorCommand::EditLink {
+        block_id: id,
+        url: "https://old.example".to_string(),
+        new_url: "https://new.example".to_string(),
+    });
+    match &editor.doc.blocks[0] {
+        Block::Paragraph { content, .. } => match &content[1] {
+            Inline::Link { url, .. } => assert_eq!(url.as_ref(), "https://new.example"),
+            other => panic!("expected link, got {:?}", other),
+        },
+        other => panic!("expected paragraph, got {:?}", other),
+    }
+
+    editor.execute(EditorCommand::RemoveLink { block_id: id, url: "https://new.example".to_string() });
+    match &editor.doc.blocks[0] {
+        Block::Paragraph { content, .. } => {
+            assert_eq!(content.len(), 2);
+            assert!(matches!(&content[1], Inline::Text { value } if value.as_ref() == "docs"));
+        }
+        other => panic!("expected paragraph, got {:?}", other),
+    }
+
+    editor.execute(EditorCommand::Undo);
+    match &editor.doc.blocks[0] {
+        Block::Paragraph { content, .. } => assert!(matches!(&content[1], Inline::Link { .. })),
+        other => panic!("expected paragraph, got {:?}", other),
+    }
+}
+
+#[test]
+fn find_is_case_insensitive_on_request() {
+    let doc = paragraph_doc("Rust and rust");
+    assert_eq!(find_in_doc(&doc, "rust", FindOptions::default()).len(), 1);
+    let hits = find_in_doc(&doc, "RUST", FindOptions { case_insensitive: true, ..FindOptions::default() });
+    assert_eq!(hits.iter().map(|m| (m.start, m.end)).collect::<Vec<_>>(), vec![(0, 4), (9, 13)]);
+}
+
+#[test]
+fn replace_match_spans_styled_text() {
+    let mut doc = Document::new();
+    let id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Paragraph {
+        id,
+        content: vec![
+            Inline::Text { value: Arc::from("hello wo") },
+            Inline::Styled {
+                style: Style { bold: true, ..Style::default() },
+                content: vec![Inline::Text { value: Arc::from("rld!") }],
+            },
+        ],
+        dirty: false,
+        locked: false,
+    });
+    let mut editor = Editor::new(doc);
+    let hit = find_in_doc(&editor.doc, "world", FindOptions::default())[0];
+    editor.execute(EditorCommand::ReplaceMatch {
+        block_id: hit.block_id,
+        start: hit.start,
+        end: hit.end,
+        replacement: "世界".to_string(),
+    });
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "hello 世界!");
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "hello world!");
+}
+
+#[test]
+fn replace_all_is_one_undo_step() {
+    let mut editor = Editor::new(paragraph_doc("a-a-A"));
+    editor.execute(EditorCommand::ReplaceAll {
+        query: "a".to_string(),
+        replacement: "bb".to_string(),
+        options: FindOptions { case_insensitive: true, ..FindOptions::default() },
+    });
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "bb-bb-bb");
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "a-a-A");
+}
+
+#[test]
+fn find_and_replace_skip_code_and_urls_by_default() {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![
+            Inline::Text { value: Arc::from("foo ") },
+            Inline::CodeSpan { value: Arc::from("foo()") },
+            Inline::Text { value: Arc::from(" ") },
+            Inline::Link {
+                url: Arc::from("https://foo.example/foo"),
+                text: vec![Inline::Text { value: Arc::from("foo") }],
+            },
+        ],
+        dirty: false,
+        locked: false,
+    });
+    doc.blocks.push(Block::Code {
+        id: uuid::Uuid::new_v4(),
+        lang: Arc::from("rs"),
+        code: CodeText::from("fn foo() {}"),
+        dirty: false,
+        locked: false,
+    });
+    let all = FindOptions { include_code: true, include_code_spans: true, include_urls: true, ..FindOptions::default() };
+    let cases = [
+        (FindOptions::default(), 2),
+        (FindOptions { include_code_spans: true, ..FindOptions::default() }, 3),
+        (FindOptions { include_code: true, ..FindOptions::default() }, 3),
+        (FindOptions { include_urls: true, ..FindOptions::default() }, 4),
+        (all.clone(), 6),
+    ];
+    let mut index = PlainTextIndex::new();
+    for (options, expected) in cases {
+        let hits = find_in_doc(&doc, "foo", options.clone());
+        assert_eq!(hits.len(), expected, "{:?}", options);
+        assert_eq!(index.find_in_doc(&doc, "foo", options.clone()), hits);
+
+        let mut editor = Editor::new(doc.clone());
+        editor.execute(EditorCommand::ReplaceAll { query: "foo".to_string(), replacement: "bar".to_string(), options: options.clone() });
+        assert_eq!(find_in_doc(&editor.doc, "bar", options.clone()).len(), expected, "{:?}", options);
+        assert_eq!(find_in_doc(&editor.doc, "foo", all.clone()).len(), 6 - expected);
+    }
+
+    let url_hits: Vec<_> = find_in_doc(&doc, "foo", all).into_iter().filter(|m| m.link.is_some()).collect();
+    assert_eq!(url_hits.iter().map(|m| (m.link, m.start, m.end)).collect::<Vec<_>>(), vec![(Some(0), 8, 11), (Some(0), 20, 23)]);
+    let serialized: FindOptions = serde_json::from_str(r#"{"include_urls": true}"#).unwrap();
+    assert_eq!(serialized, FindOptions { include_urls: true, ..FindOptions::default() });
+}
+
+#[test]
+fn block_filters_limit_find_and_replace() {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Heading { id: uuid::Uuid::new_v4(), level: 1, content: vec![Inline::Text { value: Arc::from("draft title") }], dirty: false, locked: false });
+    doc.blocks.push(Block::Code { id: uuid::Uuid::new_v4(), lang: Arc::from("rs"), code: CodeText::from("let draft = 1;"), dirty: false, locked: false });
+    doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from("a draft") }], dirty: false, locked: false });
+    let headings: FindOptions = serde_json::from_str(r#"{"include_code": true, "block_types": ["heading"]}"#).unwrap();
+    let everywhere = FindOptions { include_code: true, ..FindOptions::default() };
+    assert_eq!(find_in_doc(&doc, "draft", everywhere.clone()).len(), 3);
+    assert_eq!(find_in_doc(&doc, "draft", headings.clone()).iter().map(|m| m.block_index).collect::<Vec<_>>(), [0]);
+    let mut search = SearchIndex::new();
+    assert_eq!(search.find_in_doc(&doc, "draft", headings.clone()), find_in_doc(&doc, "draft", headings.clone()));
+
+    let mut editor = Editor::new(doc.clone());
+    editor.execute(EditorCommand::ReplaceAll { query: "draft".to_string(), replacement: "Final".to_string(), options: headings });
+    let texts = |editor: &Editor| editor.doc.blocks.iter().map(block_plain_text).collect::<Vec<_>>();
+    assert_eq!(texts(&editor), ["Final title", "let draft = 1;", "a draft"]);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(texts(&editor), ["draft title", "let draft = 1;", "a draft"]);
+
+    let scoped: FindOptions = serde_json::from_str(&format!(r#"{{"include_code": true, "scope": {{"blockIds": ["{}", "{}"]}}}}"#, doc.blocks[1].id(), doc.blocks[2].id())).unwrap();
+    assert_eq!(find_in_doc(&doc, "draft", scoped.clone()).iter().map(|m| m.block_index).collect::<Vec<_>>(), [1, 2]);
+    editor.execute(EditorCommand::ReplaceAll { query: "draft".to_string(), replacement: "x".to_string(), options: scoped });
+    assert_eq!(texts(&editor), ["draft title", "let x = 1;", "a x"]);
+}
+
+#[test]
+fn locked_blocks_are_found_but_not_replaced() {
+    let mut doc = paragraph_doc("foo one");
+    doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from("foo two") }], dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+    let (open, locked) = (editor.doc.blocks[0].id(), editor.doc.blocks[1].id());
+    assert_eq!(editor.try_execute(EditorCommand::SetBlockLocked { block_id: locked, locked: true }), Ok(CommandOutcome::Applied));
+    assert!(editor.doc.blocks[1].is_locked());
+    assert_eq!(find_in_doc(&editor.doc, "foo", FindOptions::default()).len(), 2);
+
+    let replace = EditorCommand::ReplaceAll { query: "foo".to_string(), replacement: "bar".to_string(), options: FindOptions::default() };
+    assert_eq!(editor.try_execute(replace.clone()), Ok(CommandOutcome::BlockedByLock { block_id: locked }));
+    let texts = |editor: &Editor| editor.doc.blocks.iter().map(block_plain_text).collect::<Vec<_>>();
+    assert_eq!(texts(&editor), ["bar one", "foo two"]);
+    assert_eq!(find_in_doc(&editor.doc, "foo", FindOptions::default()).len(), 1);
+    // Nothing left to replace outside the lock: no change, no undo step.
+    let version = editor.doc.version;
+    assert_eq!(editor.try_execute(replace), Ok(CommandOutcome::BlockedByLock { block_id: locked }));
+    assert_eq!(editor.doc.version, version);
+
+    editor.selection = Selection::collapsed(Position { block_id: locked, offset: 7 });
+    assert_eq!(editor.try_execute(EditorCommand::InsertText("!".to_string())), Ok(CommandOutcome::BlockedByLock { block_id: locked }));
+    assert_eq!(editor.try_execute(EditorCommand::MoveBlock { block_id: locked, to_index: 0 }), Ok(CommandOutcome::BlockedByLock { block_id: locked }));
+    assert_eq!(editor.doc.version, version);
+    assert_eq!(editor.last_outcome(), CommandOutcome::BlockedByLock { block_id: locked });
+    editor.execute(EditorCommand::ReplaceMatch { block_id: open, start: 0, end: 3, replacement: "baz".to_string() });
+    assert_eq!(editor.last_outcome(), CommandOutcome::Applied);
+
+    let json = serde_json::to_value(&editor.doc.blocks).unwrap();
+    assert_eq!((json[0].get("locked"), &json[1]["locked"]), (None, &serde_json::Value::Bool(true)));
+
+    // Unlocking is an edit of its own and undoes like one.
+    editor.execute(EditorCommand::SetBlockLocked { block_id: locked, locked: false });
+    editor.execute(EditorCommand::InsertText("!".to_string()));
+    assert_eq!(texts(&editor), ["baz one", "foo two!"]);
+    editor.execute(EditorCommand::Undo);
+    editor.execute(EditorCommand::Undo);
+    assert!(editor.doc.blocks[1].is_locked());
+}
+
+#[test]
+fn search_index_follows_edits_and_matches_a_full_scan() {
+    let mut doc = paragraph_doc("The cat");
+    for content in [
+        vec![Inline::Text { value: Arc::from("then other") }],
+        vec![Inline::Text { value: Arc::from("xyz") }],
+        // "aa" only occurs across the code span, "aab" clear of it.
+        vec![Inline::CodeSpan { value: Arc::from("a") }, Inline::Text { value: Arc::from("aab") }],
+    ] {
+        doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content, dirty: false, locked: false });
+    }
+    let mut editor = Editor::new(doc);
+    let mut search = SearchIndex::new();
+    let insensitive = FindOptions { case_insensitive: true, ..FindOptions::default() };
+    let check = |search: &mut SearchIndex, editor: &Editor| {
+        for options in [FindOptions::default(), insensitive.clone()] {
+            for query in ["t", "th", "the", "then", "x", "xy", "a", "aa", "aab"] {
+                assert_eq!(search.find_in_doc(&editor.doc, query, options.clone()), find_in_doc(&editor.doc, query, options.clone()), "{query:?} {options:?}");
+            }
+        }
+    };
+    check(&mut search, &editor);
+    assert_eq!(search.len(), 4);
+    assert_eq!(search.query("THE"), Some([editor.doc.blocks[0].id(), editor.doc.blocks[1].id()].into_iter().collect()));
+    assert_eq!(search.query("th"), None);
+
+    let xyz = editor.doc.blocks[2].id();
+    editor.execute(EditorCommand::ReplaceMatch { block_id: xyz, start: 0, end: 3, replacement: "other".to_string() });
+    check(&mut search, &editor);
+    assert!(search.query("the").unwrap().contains(&xyz));
+    assert!(search.query("xyz").unwrap().is_empty());
+
+    editor.execute(EditorCommand::InsertQuote("then".to_string()));
+    check(&mut search, &editor);
+    assert_eq!(search.len(), 5);
+    editor.execute(EditorCommand::Undo);
+    editor.execute(EditorCommand::Undo);
+    check(&mut search, &editor);
+    assert_eq!(search.len(), 4);
+    assert!(search.query("xyz").unwrap().contains(&xyz));
+}
+
+#[test]
+fn image_data_becomes_an_undoable_figure() {
+    let mut editor = Editor::new(Document::new());
+    let before = editor.doc.version;
+    editor.execute(EditorCommand::InsertImageData { bytes: vec![0x89, b'P', b'N', b'G'], mime: "image/png".to_string(), caption: None });
+    assert_ne!(editor.doc.version, before);
+    match &editor.doc.blocks[..] {
+        [Block::Figure { url, caption, .. }] => {
+            assert_eq!(url.as_ref(), "data:image/png;base64,iVBORw==");
+            assert_eq!(caption.as_deref(), Some("图片"));
+        }
+        other => panic!("expected one figure, got {:?}", other),
+    }
+    editor.execute(EditorCommand::Undo);
+    assert!(editor.doc.blocks.is_empty());
+}
+
+/// Runs `cmd` and checks it changed the blocks and bumped the version, so
+/// the version-gated relayout picks the change up.
+fn assert_bumps_version(editor: &mut Editor, cmd: EditorCommand) {
+    let blocks = |doc: &Document| doc.blocks.iter().map(hash_block).collect::<Vec<_>>();
+    let (before, version) = (blocks(&editor.doc), editor.doc.version);
+    editor.execute(cmd.clone());
+    assert_ne!(blocks(&editor.doc), before, "{:?} left the blocks alone", cmd);
+    assert!(editor.doc.version > version, "{:?} did not bump the version", cmd);
+}
+
+#[test]
+fn every_block_mutation_bumps_version() {
+    let mut doc = paragraph_doc("hello world");
+    let paragraph = doc.blocks[0].id();
+    if let Block::Paragraph { content, .. } = &mut doc.blocks[0] {
+        content.push(Inline::Link { url: Arc::from("https://a.example"), text: vec![Inline::Text { value: Arc::from("a") }] });
+    }
+    let list = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::List {
+        id: list,
+        ordered: false,
+        items: ["item", "next"]
+            .map(|t| ListItem { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(t) }], level: 0, checked: None })
+            .into(),
+        dirty: false,
+        locked: false,
+        toc: false,
+    });
+    let mut editor = Editor::new(doc);
+    let at = |block_id, anchor, focus| Selection {
+        anchor: Position { block_id, offset: anchor },
+        focus: Position { block_id, offset: focus },
+    };
+
+    editor.selection = at(paragraph, 0, 5);
+    assert_bumps_version(&mut editor, EditorCommand::ApplyStyle(Style { italic: true, ..Style::default() }));
+    editor.selection = at(paragraph, 11, 11);
+    assert_bumps_version(&mut editor, EditorCommand::InsertText("!".to_string()));
+    editor.selection = at(paragraph, 0, 1);
+    assert_bumps_version(&mut editor, EditorCommand::DeleteSelection);
+    assert_bumps_version(&mut editor, EditorCommand::ReplaceMatch { block_id: paragraph, start: 0, end: 4, replacement: "Hi".to_string() });
+    assert_bumps_version(&mut editor, EditorCommand::ReplaceAll { query: "wor".to_string(), replacement: "W".to_string(), options: FindOptions::default() });
+    assert_bumps_version(&mut editor, EditorCommand::EditLink { block_id: paragraph, url: "https://a.example".to_string(), new_url: "https://b.example".to_string() });
+    assert_bumps_version(&mut editor, EditorCommand::RemoveLink { block_id: paragraph, url: "https://b.example".to_string() });
+    assert_bumps_version(&mut editor, EditorCommand::SetHeading(2));
+    editor.selection = at(list, 6, 6);
+    assert_bumps_version(&mut editor, EditorCommand::ListIndent { block_id: None, item: None });
+    assert_bumps_version(&mut editor, EditorCommand::ListOutdent { block_id: None, item: None });
+
+    assert_bumps_version(&mut editor, EditorCommand::InsertList(true));
+    assert_bumps_version(&mut editor, EditorCommand::InsertQuote("q".to_string()));
+    assert_bumps_version(&mut editor, EditorCommand::InsertCode { lang: "rs".to_string(), code: "fn f() {}".to_string() });
+    assert_bumps_version(&mut editor, EditorCommand::InsertLink { url: "https://c.example".to_string(), text: "c".to_string() });
+    assert_bumps_version(&mut editor, EditorCommand::InsertImage("local://a".to_string()));
+    assert_bumps_version(&mut editor, EditorCommand::InsertFigure { url: "local://placeholder".to_string(), caption: Some("示意图".to_string()) });
+    let figure = editor.selection.focus.block_id;
+    assert_bumps_version(&mut editor, EditorCommand::ResizeFigure { block_id: figure, width: 100.0, height: 50.0 });
+    assert_bumps_version(&mut editor, EditorCommand::ResizeFigure { block_id: figure, width: 120.0, height: 60.0 });
+    // Both steps of the drag undo together.
+    assert_bumps_version(&mut editor, EditorCommand::Undo);
+    assert!(matches!(editor.doc.blocks.iter().find(|b| b.id() == figure), Some(Block::Figure { size: None, .. })));
+    assert_bumps_version(&mut editor, EditorCommand::Redo);
+    assert_bumps_version(&mut editor, EditorCommand::InsertImageData { bytes: vec![1, 2, 3], mime: "image/png".to_string(), caption: None });
+    assert_bumps_version(&mut editor, EditorCommand::MoveBlock { block_id: figure, to_index: 0 });
+
+    assert_bumps_version(&mut editor, EditorCommand::InsertTable(2, 2));
+    let table = editor.selection.focus.block_id;
+    assert_bumps_version(&mut editor, EditorCommand::TableEditCell { block_id: table, row: 0, col: 0, text: "x".to_string() });
+    assert_bumps_version(&mut editor, EditorCommand::TableInsertRow);
+    assert_bumps_version(&mut editor, EditorCommand::TableInsertColumn);
+    assert_bumps_version(&mut editor, EditorCommand::TableDeleteRow);
+    assert_bumps_version(&mut editor, EditorCommand::TableDeleteColumn);
+    assert_bumps_version(&mut editor, EditorCommand::TableInsertRowAt { block_id: table, index: 0 });
+    assert_bumps_version(&mut editor, EditorCommand::TableInsertColumnAt { block_id: table, index: 0 });
+    assert_bumps_version(&mut editor, EditorCommand::TableDeleteRowAt { block_id: table, index: 0 });
+    assert_bumps_version(&mut editor, EditorCommand::TableDeleteColumnAt { block_id: table, index: 0 });
+    assert_bumps_version(&mut editor, EditorCommand::TableToggleHeader { block_id: table });
+    assert_bumps_version(&mut editor, EditorCommand::TableSetColWidth { block_id: table, col: 0, width: 80.0 });
+
+    assert_bumps_version(&mut editor, EditorCommand::Undo);
+    assert_bumps_version(&mut editor, EditorCommand::Redo);
+}
+
+#[test]
+fn clear_dirty_reaches_nested_quotes() {
+    let paragraph = |text: &str| Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![Inline::Text { value: Arc::from(text) }],
+        dirty: true,
+        locked: false,
+    };
+    let quote = |content| Block::Quote { id: uuid::Uuid::new_v4(), content, dirty: true, locked: false };
+    let mut doc = Document::new();
+    doc.blocks.push(quote(vec![paragraph("one"), quote(vec![quote(vec![paragraph("deep")])])]));
+    doc.blocks.push(paragraph("top"));
+
+    let mut count = 0;
+    doc.for_each_block_mut(&mut |_| count += 1);
+    assert_eq!(count, 6);
+    doc.clear_dirty();
+    assert!(doc.blocks.iter().all(|b| !b.is_effectively_dirty()));
+
+    if let Block::Quote { content, .. } = &mut doc.blocks[0] {
+        content[1].for_each_block_mut(&mut |b| {
+            if matches!(b, Block::Paragraph { .. }) {
+                b.set_dirty(true);
+            }
+        });
+    }
+    assert!(doc.blocks[0].is_effectively_dirty());
+    assert!(!doc.blocks[0].is_dirty());
+    doc.clear_dirty();
+    assert!(!doc.blocks[0].is_effectively_dirty());
+}
+
+#[test]
+fn undo_bumps_version_and_marks_dirty() {
+    let mut editor = Editor::new(paragraph_doc("ab"));
+    editor.selection = Selection::collapsed(Position { block_id: editor.doc.blocks[0].id(), offset: 2 });
+    assert!(!editor.can_undo());
+    editor.execute(EditorCommand::InsertText("c".to_string()));
+    editor.doc.clear_dirty();
+    let edited = editor.doc.version;
+    assert!(editor.can_undo());
+
+    editor.execute(EditorCommand::Undo);
+    assert_ne!(editor.doc.version, edited);
+    assert!(editor.doc.blocks[0].is_dirty());
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "ab");
+    assert!(editor.can_redo());
+
+    let undone = editor.doc.version;
+    editor.execute(EditorCommand::Redo);
+    assert_ne!(editor.doc.version, undone);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "abc");
+
+    let before = editor.doc.version;
+    editor.execute(EditorCommand::Redo);
+    assert_eq!(editor.doc.version, before);
+}
+
+#[test]
+fn snapshot_undo_keeps_version_moving_forward() {
+    let mut editor = Editor::new(paragraph_doc("ab"));
+    editor.execute(EditorCommand::InsertTable(2, 2));
+    let edited = editor.doc.version;
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.len(), 1);
+    assert!(editor.doc.version > edited);
+}
+
+#[test]
+fn table_commands_target_given_cell() {
+    let mut editor = Editor::new(paragraph_doc("intro"));
+    editor.execute(EditorCommand::InsertTable(2, 2));
+    let table_id = editor.doc.blocks[1].id();
+    editor.execute(EditorCommand::TableEditCell { block_id: table_id, row: 1, col: 1, text: "x".to_string() });
+    editor.execute(EditorCommand::TableInsertRowAt { block_id: table_id, index: 1 });
+    editor.execute(EditorCommand::TableInsertColumnAt { block_id: table_id, index: 0 });
+    editor.execute(EditorCommand::TableToggleHeader { block_id: table_id });
+    match &editor.doc.blocks[1] {
+        Block::Table { rows, header, .. } => {
+            assert_eq!(rows.len(), 3);
+            assert_eq!(rows[0].len(), 3);
+            assert!(*header);
+            assert_eq!(block_plain_text(&editor.doc.blocks[1]).lines().nth(2), Some("\t\tx"));
+        }
+        other => panic!("expected table, got {:?}", other),
+    }
+    editor.execute(EditorCommand::TableDeleteRowAt { block_id: table_id, index: 2 });
+    editor.execute(EditorCommand::Undo);
+    assert!(block_plain_text(&editor.doc.blocks[1]).ends_with('x'));
+}
+
+#[test]
+fn stats_count_words_and_selection() {
+    let mut doc = paragraph_doc("Hello, world! 你好");
+    doc.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![Inline::Text { value: Arc::from("it's well-known") }],
+        dirty: false,
+        locked: false,
+    });
+    let stats = document_stats(&doc);
+    assert_eq!(stats.text.words, 6);
+    assert_eq!(stats.text.chars, 16 + 15);
+    assert_eq!(stats.blocks, 2);
+    assert_eq!(stats.reading_minutes, 1);
+
+    let first = Position { block_id: doc.blocks[0].id(), offset: 7 };
+    let second = Position { block_id: doc.blocks[1].id(), offset: 4 };
+    let selected = selection_stats(&doc, &Selection { anchor: second, focus: first }).unwrap();
+    assert_eq!(selected.words, 4);
+    assert!(selection_stats(&doc, &Selection::collapsed(first)).is_none());
+}
+
+#[test]
+fn char_slice_clamps_offsets() {
+    let text = "选中 to 末尾";
+    assert_eq!(char_slice(text, 3, 8), "to 末尾");
+    assert_eq!(char_slice(text, 8, 8), "");
+    assert_eq!(char_slice(text, 6, 42), "末尾");
+    assert_eq!(char_slice(text, 42, 50), "");
+    assert_eq!(char_slice(text, 5, 1), "");
+}
+
+#[test]
+fn move_block_reorders_and_skips_noop_drops() {
+    let mut doc = paragraph_doc("a");
+    for text in ["b", "c"] {
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(text) }],
+            dirty: false,
+            locked: false,
+        });
+    }
+    let mut editor = Editor::new(doc);
+    let order = |editor: &Editor| editor.doc.blocks.iter().map(block_plain_text).collect::<Vec<_>>().join("");
+    let a = editor.doc.blocks[0].id();
+
+    let version = editor.doc.version;
+    editor.execute(EditorCommand::MoveBlock { block_id: a, to_index: 1 });
+    editor.execute(EditorCommand::MoveBlock { block_id: a, to_index: 0 });
+    assert_eq!(editor.doc.version, version);
+    assert!(!editor.can_undo());
+
+    editor.execute(EditorCommand::MoveBlock { block_id: a, to_index: 3 });
+    assert_eq!(order(&editor), "bca");
+    let c = editor.doc.blocks[1].id();
+    editor.execute(EditorCommand::MoveBlock { block_id: c, to_index: 0 });
+    assert_eq!(order(&editor), "cba");
+    editor.execute(EditorCommand::Undo);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(order(&editor), "abc");
+}
+
+#[test]
+fn move_block_up_and_down_swap_with_the_neighbour() {
+    let mut doc = paragraph_doc("a");
+    for text in ["b", "c"] {
+        doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false });
+    }
+    let mut editor = Editor::new(doc);
+    let order = |editor: &Editor| editor.doc.blocks.iter().map(block_plain_text).collect::<Vec<_>>().join("");
+    let a = editor.doc.blocks[0].id();
+    editor.selection = Selection::collapsed(Position { block_id: a, offset: 1 });
+
+    let version = editor.doc.version;
+    editor.execute(EditorCommand::MoveBlockUp);
+    assert_eq!(editor.doc.version, version);
+    assert!(!editor.can_undo());
+
+    for block in &mut editor.doc.blocks {
+        block.set_dirty(false);
+    }
+    editor.execute(EditorCommand::MoveBlockDown);
+    assert_eq!(order(&editor), "bac");
+    assert!(editor.doc.blocks[..2].iter().all(|b| b.is_effectively_dirty()));
+    assert!(!editor.doc.blocks[2].is_effectively_dirty());
+    editor.execute(EditorCommand::MoveBlockDown);
+    assert_eq!(order(&editor), "bca");
+    assert_eq!(editor.selection.focus, Position { block_id: a, offset: 1 });
+    let version = editor.doc.version;
+    editor.execute(EditorCommand::MoveBlockDown);
+    assert_eq!(editor.doc.version, version);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(order(&editor), "bac");
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(order(&editor), "abc");
+    assert!(!editor.can_undo());
+}
+
+#[test]
+fn duplicate_block_copies_with_fresh_ids() {
+    let item = |t: &str| ListItem { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(t) }], level: 0, checked: None };
+    let list = Block::List { id: uuid::Uuid::new_v4(), ordered: true, items: vec![item("一"), item("二")], dirty: false, locked: false, toc: false };
+    let quote = Block::Quote { id: uuid::Uuid::new_v4(), content: vec![paragraph_doc("引文").blocks.remove(0), list], dirty: false, locked: false };
+    let mut doc = paragraph_doc("after");
+    doc.blocks.insert(0, quote);
+    let mut editor = Editor::new(doc);
+    let original = editor.doc.blocks[0].id();
+    editor.selection = Selection { anchor: Position { block_id: original, offset: 1 }, focus: Position { block_id: original, offset: 4 } };
+    editor.execute(EditorCommand::DuplicateBlock);
+
+    let ids = |block: &Block| {
+        let mut ids = Vec::new();
+        block.for_each_block(&mut |b| {
+            ids.push(b.id());
+            if let Block::List { items, .. } = b {
+                ids.extend(items.iter().map(|item| item.id));
+            }
+        });
+        ids
+    };
+    let (before, copy) = (ids(&editor.doc.blocks[0]), ids(&editor.doc.blocks[1]));
+    assert_eq!(before.len(), 5);
+    assert_eq!(copy.len(), 5);
+    assert!(copy.iter().all(|id| !before.contains(id)));
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), block_plain_text(&editor.doc.blocks[0]));
+    assert!(editor.doc.blocks[1].is_dirty());
+    assert_eq!(editor.selection, Selection { anchor: Position { block_id: copy[0], offset: 1 }, focus: Position { block_id: copy[0], offset: 4 } });
+    assert_eq!(block_plain_text(&editor.doc.blocks[2]), "after");
+
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.len(), 2);
+    assert_eq!(editor.selection.focus, Position { block_id: original, offset: 4 });
+}
+
+#[test]
+fn delete_block_removes_any_block_and_undoes() {
+    let mut doc = paragraph_doc("first");
+    let figure = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Figure { id: figure, url: Arc::from("a.png"), caption: None, size: None, dirty: false, locked: false });
+    doc.blocks.push(Block::Code { id: uuid::Uuid::new_v4(), lang: Arc::from("rs"), code: CodeText::from("fn"), dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+    let (first, code) = (editor.doc.blocks[0].id(), editor.doc.blocks[2].id());
+
+    // Deleting another block leaves the caret where it is.
+    editor.selection = Selection::collapsed(Position { block_id: code, offset: 1 });
+    editor.execute(EditorCommand::DeleteBlock { block_id: Some(figure) });
+    assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), vec![first, code]);
+    assert_eq!(editor.selection.focus, Position { block_id: code, offset: 1 });
+    editor.execute(EditorCommand::DeleteBlock { block_id: None });
+    assert_eq!(editor.selection, Selection::collapsed(Position { block_id: first, offset: 5 }));
+    editor.execute(EditorCommand::DeleteBlock { block_id: None });
+    assert_eq!(editor.doc.blocks.len(), 1);
+    assert!(matches!(&editor.doc.blocks[0], Block::Paragraph { content, .. } if content.is_empty()));
+    assert_eq!(editor.selection.focus, Position { block_id: editor.doc.blocks[0].id(), offset: 0 });
+
+    for _ in 0..3 {
+        editor.execute(EditorCommand::Undo);
+    }
+    assert_eq!(editor.doc.blocks.iter().map(|b| b.id()).collect::<Vec<_>>(), vec![first, figure, code]);
+    assert_eq!(editor.selection.focus, Position { block_id: code, offset: 1 });
+
+    // Deleting the first block puts the caret at the start of the next.
+    editor.selection = Selection::collapsed(Position { block_id: first, offset: 2 });
+    editor.execute(EditorCommand::DeleteBlock { block_id: None });
+    assert_eq!(editor.selection, Selection::collapsed(Position { block_id: figure, offset: 0 }));
+}
+
+fn type_into_empty_paragraph(editor: &mut Editor, keys: &str) {
+    for ch in keys.chars() {
+        editor.execute(EditorCommand::InsertText(ch.to_string()));
+    }
+}
+
+#[test]
+fn markdown_shortcuts_convert_empty_paragraph() {
+    type Check = fn(&Block) -> bool;
+    let cases: [(&str, Check); 6] = [
+        ("# ", |b| matches!(b, Block::Heading { level: 1, content, .. } if content.is_empty())),
+        ("### ", |b| matches!(b, Block::Heading { level: 3, .. })),
+        ("- ", |b| matches!(b, Block::List { ordered: false, items, .. } if items.len() == 1)),
+        ("1. ", |b| matches!(b, Block::List { ordered: true, .. })),
+        ("> ", |b| matches!(b, Block::Quote { content, .. } if content.len() == 1)),
+        ("```", |b| matches!(b, Block::Code { code, .. } if code.is_empty())),
+    ];
+    for (trigger, expected) in cases {
+        let mut editor = Editor::new(paragraph_doc(""));
+        let id = editor.doc.blocks[0].id();
+        type_into_empty_paragraph(&mut editor, trigger);
+        assert!(expected(&editor.doc.blocks[0]), "trigger {:?}", trigger);
+        assert_eq!(editor.doc.blocks[0].id(), id);
+        assert_eq!(editor.selection.focus, Position { block_id: id, offset: 0 });
+    }
+}
+
+#[test]
+fn markdown_shortcuts_need_an_otherwise_empty_paragraph() {
+    let mut editor = Editor::new(paragraph_doc("note"));
+    type_into_empty_paragraph(&mut editor, "# ");
+    assert!(matches!(editor.doc.blocks[0], Block::Paragraph { .. }));
+
+    let mut editor = Editor::new(paragraph_doc(""));
+    type_into_empty_paragraph(&mut editor, "#######");
+    type_into_empty_paragraph(&mut editor, " ");
+    assert!(matches!(editor.doc.blocks[0], Block::Paragraph { .. }));
+
+    let mut editor = Editor::new(paragraph_doc(""));
+    editor.config.markdown_shortcuts = false;
+    type_into_empty_paragraph(&mut editor, "# ");
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "# ");
+}
+
+#[test]
+fn undo_after_markdown_shortcut_restores_literal_text() {
+    let mut editor = Editor::new(paragraph_doc(""));
+    type_into_empty_paragraph(&mut editor, "> ");
+    assert!(matches!(editor.doc.blocks[0], Block::Quote { .. }));
+
+    editor.execute(EditorCommand::Undo);
+    assert!(matches!(editor.doc.blocks[0], Block::Paragraph { .. }));
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "> ");
+    assert_eq!(editor.selection.focus.offset, 2);
+
+    editor.execute(EditorCommand::Redo);
+    assert!(matches!(editor.doc.blocks[0], Block::Quote { .. }));
+}
+
+#[test]
+fn plain_text_index_tracks_block_changes() {
+    let mut doc = paragraph_doc("Ärger über ÄRGER");
+    doc.blocks.push(Block::Code {
+        id: uuid::Uuid::new_v4(),
+        lang: Arc::from("rs"),
+        code: CodeText::from("let ärger = 1;"),
+        dirty: false,
+        locked: false,
+    });
+    let options = FindOptions { case_insensitive: true, include_code: true, ..FindOptions::default() };
+    let mut index = PlainTextIndex::new();
+    assert_eq!(index.find_in_doc(&doc, "ärger", options.clone()), find_in_doc(&doc, "ärger", options.clone()));
+    assert_eq!(index.find_in_doc(&doc, "ärger", options.clone()).len(), 3);
+    assert_eq!(index.document_stats(&doc), document_stats(&doc));
+    assert_eq!(index.len(), 2);
+
+    if let Block::Paragraph { content, .. } = &mut doc.blocks[0] {
+        content.push(Inline::Text { value: Arc::from(" ärger") });
+    }
+    let matches = index.find_in_doc(&doc, "ärger", options.clone());
+    assert_eq!(matches.len(), 4);
+    assert_eq!((matches[2].start, matches[2].end), (17, 22));
+    assert_eq!(index.text(&doc.blocks[0]), block_plain_text(&doc.blocks[0]));
+    assert_eq!(index.document_stats(&doc), document_stats(&doc));
+    assert_eq!(index.len(), 2);
+}
+
+#[test]
+fn inline_edits_merge_and_undo_as_deltas() {
+    let mut editor = Editor::new(paragraph_doc("ab"));
+    editor.selection = Selection::collapsed(Position { block_id: editor.doc.blocks[0].id(), offset: 2 });
+    editor.execute(EditorCommand::InsertText("c".to_string()));
+    editor.execute(EditorCommand::InsertText("d".to_string()));
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "abcd");
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "ab");
+    assert_eq!(editor.selection.focus.offset, 2);
+    assert!(!editor.can_undo());
+    editor.execute(EditorCommand::Redo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "abcd");
+    assert_eq!(editor.selection.focus.offset, 4);
+
+    editor.execute(EditorCommand::InsertCode { lang: "rs".to_string(), code: "fn".to_string() });
+    let code_id = editor.doc.blocks[1].id();
+    editor.selection = Selection::collapsed(Position { block_id: code_id, offset: 2 });
+    editor.execute(EditorCommand::InsertText(" main".to_string()));
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "fn main");
+    assert_eq!(editor.selection.focus.offset, 7);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "fn");
+}
+
+#[test]
+fn table_cell_edits_undo_per_edit() {
+    let mut editor = Editor::new(paragraph_doc("intro"));
+    editor.execute(EditorCommand::InsertTable(2, 2));
+    let table_id = editor.doc.blocks[1].id();
+    editor.execute(EditorCommand::TableEditCell { block_id: table_id, row: 0, col: 1, text: "hello".to_string() });
+    editor.execute(EditorCommand::TableEditCell { block_id: table_id, row: 0, col: 1, text: "help 世界".to_string() });
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]).lines().next(), Some("\thelp 世界"));
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]).lines().next(), Some("\thello"));
+    editor.execute(EditorCommand::Redo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]).lines().next(), Some("\thelp 世界"));
+}
+
+#[test]
+fn delete_selection_inside_one_run() {
+    let mut doc = Document::new();
+    let id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Paragraph {
+        id,
+        content: vec![
+            Inline::Text { value: Arc::from("你好 ") },
+            Inline::Styled { style: Style { bold: true, ..Style::default() }, content: vec![Inline::Text { value: Arc::from("big world") }] },
+        ],
+        dirty: false,
+        locked: false,
+    });
+    let mut editor = Editor::new(doc);
+    let selection = Selection { anchor: Position { block_id: id, offset: 7 }, focus: Position { block_id: id, offset: 3 } };
+    editor.selection = selection;
+    let version = editor.doc.version;
+    editor.execute(EditorCommand::DeleteSelection);
+    assert!(editor.doc.version > version);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "你好 world");
+    assert_eq!(editor.selection, Selection::collapsed(Position { block_id: id, offset: 3 }));
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "你好 big world");
+    assert_eq!(editor.selection, selection);
+}
+
+#[test]
+fn set_heading_keeps_other_block_content() {
+    let text = |v: &str| vec![Inline::Text { value: Arc::from(v) }];
+    let item = |v: &str| ListItem { id: uuid::Uuid::new_v4(), content: text(v), level: 0, checked: None };
+    let paragraph = |v: &str| Block::Paragraph { id: uuid::Uuid::new_v4(), content: text(v), dirty: false, locked: false };
+    let mut doc = Document::new();
+    let list_id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::List { id: list_id, ordered: true, items: vec![item("一"), item("二二"), item("三")], dirty: false, locked: false, toc: false });
+    let quote_id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Quote { id: quote_id, content: vec![paragraph("引文"), paragraph("出处")], dirty: false, locked: false });
+    let table_id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Table {
+        id: table_id,
+        rows: vec![vec![wa_core::Cell { content: text("a") }]],
+        header: false,
+        col_widths: Vec::new(),
+        dirty: false,
+        locked: false,
+    });
+    let code_id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Code { id: code_id, lang: Arc::from("rs"), code: CodeText::from("x"), dirty: false, locked: false });
+    let figure_id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Figure { id: figure_id, url: Arc::from("a.png"), caption: None, size: None, dirty: false, locked: false });
+    let para = paragraph("正文");
+    let para_id = para.id();
+    doc.blocks.push(para);
+    let mut editor = Editor::new(doc);
+    let original = editor.doc.blocks.clone();
+
+    editor.selection = Selection::collapsed(Position { block_id: list_id, offset: 3 });
+    assert_eq!(editor.try_execute(EditorCommand::SetHeading(2)), Ok(CommandOutcome::Applied));
+    let kinds: Vec<String> = editor.doc.blocks[..3].iter().map(block_plain_text).collect();
+    assert_eq!(kinds, vec!["一", "二二", "三"]);
+    assert!(matches!(&editor.doc.blocks[0], Block::List { id, items, .. } if *id == list_id && items.len() == 1));
+    assert!(matches!(&editor.doc.blocks[1], Block::Heading { level: 2, .. }));
+    assert!(matches!(&editor.doc.blocks[2], Block::List { ordered: true, items, .. } if items.len() == 1));
+    assert_eq!(editor.selection.focus, Position { block_id: editor.doc.blocks[1].id(), offset: 1 });
+
+    editor.selection = Selection::collapsed(Position { block_id: quote_id, offset: 1 });
+    assert_eq!(editor.try_execute(EditorCommand::SetHeading(1)), Ok(CommandOutcome::Applied));
+    assert!(matches!(&editor.doc.blocks[3], Block::Heading { level: 1, .. }));
+    assert!(matches!(&editor.doc.blocks[4], Block::Quote { id, content, .. } if *id == quote_id && content.len() == 1));
+    assert_eq!(block_plain_text(&editor.doc.blocks[3]), "引文");
+
+    let version = editor.doc.version;
+    for (block_id, kind) in [(table_id, "table"), (code_id, "code"), (figure_id, "figure")] {
+        editor.selection = Selection::collapsed(Position { block_id, offset: 0 });
+        assert_eq!(editor.try_execute(EditorCommand::SetHeading(1)), Err(CommandError::NotConvertible(kind)));
+    }
+    assert_eq!(editor.doc.version, version);
+    assert_eq!(editor.doc.blocks.len(), 9);
+
+    editor.selection = Selection::collapsed(Position { block_id: para_id, offset: 0 });
+    editor.execute(EditorCommand::SetHeading(3));
+    assert!(matches!(&editor.doc.blocks[8], Block::Heading { id, level: 3, .. } if *id == para_id));
+
+    for _ in 0..3 {
+        editor.execute(EditorCommand::Undo);
+    }
+    assert_eq!(editor.doc.blocks.len(), original.len());
+    assert!(editor.doc.blocks.iter().zip(&original).all(|(a, b)| block_plain_text(a) == block_plain_text(b)));
+}
+
+#[test]
+fn collapsed_style_toggles_apply_to_typed_text() {
+    let mut editor = Editor::new(paragraph_doc(""));
+    let block_id = editor.doc.blocks[0].id();
+    let bold = Style { bold: true, ..Style::default() };
+    let version = editor.doc.version;
+    editor.execute(EditorCommand::ApplyStyle(bold));
+    assert_eq!(editor.pending_style, Some(bold));
+    assert_eq!(editor.doc.version, version);
+    editor.execute(EditorCommand::InsertText("h".to_string()));
+    editor.execute(EditorCommand::InsertText("i".to_string()));
+    editor.execute(EditorCommand::ApplyStyle(bold));
+    assert_eq!(editor.pending_style, Some(Style::default()));
+    editor.execute(EditorCommand::InsertText("there".to_string()));
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(
+        content,
+        &vec![
+            Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("hi") }] },
+            Inline::Text { value: Arc::from("there") },
+        ]
+    );
+    assert_eq!(editor.selection.focus, Position { block_id, offset: 7 });
+
+    // Moving the caret drops the pending style; typing then lands at the caret.
+    let italic = Style { italic: true, ..Style::default() };
+    editor.execute(EditorCommand::ApplyStyle(italic));
+    editor.selection = Selection::collapsed(Position { block_id, offset: 5 });
+    editor.execute(EditorCommand::InsertText("!".to_string()));
+    assert_eq!(editor.pending_style, None);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "hithe!re");
+
+    // Inside a bold run, the toggle cuts it in two around the plain text.
+    editor.selection = Selection::collapsed(Position { block_id, offset: 1 });
+    editor.execute(EditorCommand::ApplyStyle(bold));
+    editor.execute(EditorCommand::InsertText("-".to_string()));
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(content[..3], [
+        Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("h") }] },
+        Inline::Text { value: Arc::from("-") },
+        Inline::Styled { style: bold, content: vec![Inline::Text { value: Arc::from("i") }] },
+    ]);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "hithe!re");
+}
+
+#[test]
+fn inserted_text_is_composed_and_found_by_either_form() {
+    let mut editor = Editor::new(paragraph_doc(""));
+    editor.execute(EditorCommand::InsertText("cafe\u{301}\u{feff}\u{7}\r\n".to_string()));
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "caf\u{e9}\n");
+
+    let mut index = PlainTextIndex::new();
+    for query in ["caf\u{e9}", "cafe\u{301}"] {
+        let hits = find_in_doc(&editor.doc, query, FindOptions::default());
+        assert_eq!(hits.iter().map(|m| (m.start, m.end)).collect::<Vec<_>>(), vec![(0, 4)], "{query:?}");
+        assert_eq!(index.find_in_doc(&editor.doc, query, FindOptions::default()).len(), 1);
+    }
+
+    editor.config.input = InputFilter { tab_spaces: Some(2), ..InputFilter::RAW };
+    editor.execute(EditorCommand::InsertText("\te\u{301}".to_string()));
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "caf\u{e9}\n  e\u{301}");
+}
+
+#[test]
+fn recorded_macro_replays_on_another_block_as_one_undo_step() {
+    let mut doc = paragraph_doc("first");
+    doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from("second") }], dirty: false, locked: false });
+    doc.blocks.push(Block::Code { id: uuid::Uuid::new_v4(), lang: Arc::from(""), code: CodeText::from("let x;"), dirty: false, locked: false });
+    let ids: Vec<_> = doc.blocks.iter().map(|b| b.id()).collect();
+    let mut editor = Editor::new(doc);
+    let bold = Style { bold: true, ..Style::default() };
+
+    editor.start_recording();
+    assert!(editor.is_recording());
+    editor.selection = Selection { anchor: Position { block_id: ids[0], offset: 0 }, focus: Position { block_id: ids[0], offset: 5 } };
+    editor.execute(EditorCommand::ApplyStyle(bold));
+    editor.execute(EditorCommand::InsertText("!".to_string()));
+    editor.execute(EditorCommand::ReplaceMatch { block_id: ids[0], start: 0, end: 1, replacement: "F".to_string() });
+    let recorded = editor.stop_recording();
+    assert!(!editor.is_recording());
+    assert_eq!(recorded.len(), 3);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "First!");
+
+    let json = serde_json::to_string(&recorded).unwrap();
+    assert!(json.contains(r#""type":"apply_style""#), "{json}");
+    let commands: Vec<EditorCommand> = serde_json::from_str(&json).unwrap();
+
+    let before = editor.doc.blocks[1].clone();
+    editor.selection = Selection { anchor: Position { block_id: ids[1], offset: 0 }, focus: Position { block_id: ids[1], offset: 6 } };
+    editor.replay(&commands, Retarget::FocusedBlock).unwrap();
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "First!");
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "Fecond!");
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[1] else { unreachable!() };
+    assert!(matches!(&content[0], Inline::Styled { style, .. } if *style == bold), "{content:?}");
+
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(hash_block(&editor.doc.blocks[1]), hash_block(&before));
+
+    // A refused command rolls back the part of the macro already applied.
+    let code_before = editor.doc.blocks[2].clone();
+    editor.selection = Selection::collapsed(Position { block_id: ids[2], offset: 0 });
+    let failing = [EditorCommand::ReplaceMatch { block_id: ids[2], start: 0, end: 3, replacement: "var".to_string() }, EditorCommand::SetHeading(1)];
+    assert!(editor.replay(&failing, Retarget::Keep).is_err());
+    assert_eq!(block_plain_text(&editor.doc.blocks[2]), block_plain_text(&code_before));
+}
+
+#[test]
+fn list_indent_changes_only_the_targeted_item() {
+    let list = uuid::Uuid::new_v4();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::List {
+        id: list,
+        ordered: false,
+        items: ["一", "二", "三"]
+            .map(|t| ListItem { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(t) }], level: 0, checked: None })
+            .into(),
+        dirty: false,
+        locked: false,
+        toc: false,
+    });
+    let mut editor = Editor::new(doc);
+    let levels = |editor: &Editor| match &editor.doc.blocks[0] {
+        Block::List { items, .. } => items.iter().map(|item| item.level).collect::<Vec<_>>(),
+        _ => unreachable!(),
+    };
+    let indent = |item| EditorCommand::ListIndent { block_id: None, item };
+    let outdent = |item| EditorCommand::ListOutdent { block_id: None, item };
+
+    // The caret is in "二", at list offset 2.
+    editor.selection = Selection::collapsed(Position { block_id: list, offset: 2 });
+    assert_eq!(editor.focused_list_item(), Some(1));
+    editor.execute(indent(None));
+    assert_eq!(levels(&editor), [0, 1, 0]);
+    // One level below the item before it is as deep as it goes.
+    editor.execute(indent(None));
+    assert_eq!(levels(&editor), [0, 1, 0]);
+
+    let version = editor.doc.version;
+    editor.execute(indent(Some(0)));
+    editor.execute(outdent(Some(2)));
+    editor.execute(indent(Some(7)));
+    assert_eq!(editor.doc.version, version);
+    assert_eq!(levels(&editor), [0, 1, 0]);
+
+    editor.execute(EditorCommand::ListIndent { block_id: Some(list), item: Some(2) });
+    editor.execute(EditorCommand::ListIndent { block_id: Some(list), item: Some(2) });
+    assert_eq!(levels(&editor), [0, 1, 2]);
+    editor.execute(outdent(Some(1)));
+    assert_eq!(levels(&editor), [0, 0, 2]);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(levels(&editor), [0, 1, 2]);
+
+    let md = wa_core::export_markdown(&editor.doc);
+    assert_eq!(md.trim_end(), "- 一\n  - 二\n    - 三");
+    assert_eq!(levels(&Editor::new(wa_core::import_markdown(&md))), [0, 1, 2]);
+
+    // Items go no deeper than MAX_LIST_LEVEL, in the editor or on import,
+    // and their text never changes.
+    let md = (0..8).map(|depth| format!("{}- 项", "  ".repeat(depth))).collect::<Vec<_>>().join("\n");
+    let mut editor = Editor::new(wa_core::import_markdown(&md));
+    assert_eq!(levels(&editor), [0, 1, 2, 3, 4, 5, 5, 5]);
+    editor.selection = Selection::collapsed(Position { block_id: editor.doc.blocks[0].id(), offset: 0 });
+    let version = editor.doc.version;
+    editor.execute(indent(Some(7)));
+    assert_eq!(editor.doc.version, version);
+    editor.execute(outdent(Some(7)));
+    assert_eq!(levels(&editor), [0, 1, 2, 3, 4, 5, 5, 4]);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), ["项"; 8].join("\n"));
+    assert_eq!(wa_core::MAX_LIST_LEVEL, 5);
+}
+
+#[test]
+fn task_items_toggle_and_round_trip_through_markdown() {
+    let doc = wa_core::import_markdown("- [ ] buy milk\n- [x] done\n- plain\n- \\[x] not a task");
+    let checked = |doc: &Document| match &doc.blocks[0] {
+        Block::List { items, .. } => items.iter().map(|item| item.checked).collect::<Vec<_>>(),
+        other => panic!("expected a list, got {other:?}"),
+    };
+    assert_eq!(checked(&doc), [Some(false), Some(true), None, None]);
+    assert_eq!(block_plain_text(&doc.blocks[0]), "buy milk\ndone\nplain\n[x] not a task");
+    let md = wa_core::export_markdown(&doc);
+    assert_eq!(md.trim_end(), "- [ ] buy milk\n- [x] done\n- plain\n- \\[x] not a task");
+
+    // The caret is in "plain", which becomes an unticked task, then ticked.
+    let mut editor = Editor::new(doc);
+    let list = editor.doc.blocks[0].id();
+    editor.selection = Selection::collapsed(Position { block_id: list, offset: 16 });
+    editor.execute(EditorCommand::ToggleTaskItem);
+    assert_eq!(checked(&editor.doc)[2], Some(false));
+    editor.execute(EditorCommand::ToggleTaskItem);
+    assert_eq!(checked(&editor.doc)[2], Some(true));
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(checked(&editor.doc)[2], Some(false));
+
+    // SetTaskState(None) turns the task back into a plain item; setting the
+    // state it already has changes nothing.
+    editor.execute(EditorCommand::SetTaskState(None));
+    assert_eq!(checked(&editor.doc)[2], None);
+    let version = editor.doc.version;
+    editor.execute(EditorCommand::SetTaskState(None));
+    assert_eq!(editor.doc.version, version);
+    editor.execute(EditorCommand::SetTaskState(Some(true)));
+    assert_eq!(checked(&editor.doc)[2], Some(true));
+    editor.execute(EditorCommand::Undo);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(checked(&editor.doc)[2], Some(false));
+
+    // A new item split off a ticked task is ticked too.
+    editor.selection = Selection::collapsed(Position { block_id: list, offset: 13 });
+    editor.execute(EditorCommand::InsertParagraphBreak);
+    assert_eq!(checked(&editor.doc), [Some(false), Some(true), Some(true), Some(false), None]);
+
+    // Documents saved before task items load with plain items.
+    let old: ListItem = serde_json::from_str(&format!(r#"{{"id":"{list}","content":[]}}"#)).unwrap();
+    assert_eq!((old.level, old.checked), (0, None));
+    assert!(!serde_json::to_string(&old).unwrap().contains("checked"));
+}
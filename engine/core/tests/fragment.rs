@@ -0,0 +1,127 @@
+use std::sync::Arc;
+use wa_core::{
+    block_plain_text, doc_semantic_eq, export_fragment, export_html, export_markdown, import_fragment, Block, Cell, Document, Editor, FigureSize,
+    Fragment, Inline, ListItem, Position, Selection, Style,
+};
+
+fn text(value: &str) -> Inline {
+    Inline::Text { value: Arc::from(value) }
+}
+
+fn bold() -> Style {
+    Style { bold: true, ..Style::default() }
+}
+
+fn fragment_doc(editor: &Editor, anchor: (usize, usize), focus: (usize, usize)) -> Document {
+    let position = |(block, offset): (usize, usize)| Position { block_id: editor.doc.blocks[block].id(), offset };
+    let mut editor = Editor::new(editor.doc.clone());
+    editor.selection = Selection { anchor: position(anchor), focus: position(focus) };
+    Document { blocks: editor.selection_fragment(), ..Document::new() }
+}
+
+#[test]
+fn half_of_a_bold_sentence_copies_with_markers_around_that_half() {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![text("Note: "), Inline::Styled { style: bold(), content: vec![text("keep this safe")] }, text(".")],
+        dirty: false,
+        locked: false,
+    });
+    let editor = Editor::new(doc);
+    assert_eq!(export_markdown(&editor.doc), "Note: **keep this safe**.");
+
+    // "this safe." selected backwards.
+    let fragment = fragment_doc(&editor, (0, 21), (0, 11));
+    assert_eq!(export_markdown(&fragment), "**this safe**.");
+    assert_eq!(export_html(&fragment).trim(), "<p><strong>this safe</strong>.</p>");
+    let fragment = fragment_doc(&editor, (0, 2), (0, 10));
+    assert_eq!(export_markdown(&fragment), "te: **keep**");
+    assert!(fragment_doc(&editor, (0, 4), (0, 4)).blocks.is_empty());
+}
+
+#[test]
+fn fragments_span_blocks_and_keep_links() {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![text("see "), Inline::Link { url: Arc::from("https://example.com"), text: vec![text("the docs")] }],
+        dirty: false,
+        locked: false,
+    });
+    doc.blocks.push(Block::List {
+        id: uuid::Uuid::new_v4(),
+        ordered: false,
+        items: ["one", "two", "three"].into_iter().map(|t| ListItem { id: uuid::Uuid::new_v4(), content: vec![text(t)], level: 0, checked: None }).collect(),
+        dirty: false,
+        locked: false,
+        toc: false,
+    });
+    let editor = Editor::new(doc);
+
+    let fragment = fragment_doc(&editor, (0, 8), (1, 5));
+    assert_eq!(fragment.blocks.len(), 2);
+    assert_eq!(export_markdown(&fragment), "[docs](https://example.com)\n\n- one\n- t");
+    let items: Vec<String> = fragment.blocks.iter().map(block_plain_text).collect();
+    assert_eq!(items, ["docs", "one\nt"]);
+
+    let fragment = fragment_doc(&editor, (1, 4), (1, 9));
+    assert_eq!(export_markdown(&fragment), "- two\n- t");
+}
+
+#[test]
+fn snippets_round_trip_with_fresh_ids() {
+    let cell = |value: &str| Cell { content: vec![text(value)] };
+    let blocks = vec![
+        Block::Table {
+            id: uuid::Uuid::new_v4(),
+            rows: vec![vec![cell("名称"), cell("说明")], vec![cell("a"), cell("b")]],
+            header: true,
+            col_widths: vec![0.3, 0.7],
+            dirty: false,
+            locked: false,
+        },
+        Block::Figure {
+            id: uuid::Uuid::new_v4(),
+            url: Arc::from("https://example.com/logo.png"),
+            caption: Some(Arc::from("标志")),
+            size: Some(FigureSize { width: 64.0, height: 32.0 }),
+            dirty: false,
+            locked: false,
+        },
+    ];
+    let json = export_fragment(&blocks);
+    let imported = import_fragment(&json).unwrap();
+    let as_doc = |blocks: &[Block]| Document { blocks: blocks.to_vec(), ..Document::new() };
+    assert!(doc_semantic_eq(&as_doc(&blocks), &as_doc(&imported)));
+    assert!(imported.iter().zip(&blocks).all(|(a, b)| a.id() != b.id() && a.is_dirty()));
+    // Each import gets its own ids, so a snippet can be pasted twice.
+    assert_ne!(import_fragment(&json).unwrap()[0].id(), imported[0].id());
+
+    let mut editor = Editor::new(as_doc(&[Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![text("前")], dirty: false, locked: false }]));
+    editor.insert_blocks(imported);
+    assert_eq!(editor.doc.blocks.len(), 3);
+    assert_eq!(editor.selection.focus.block_id, editor.doc.blocks[1].id());
+}
+
+#[test]
+fn snippets_are_checked_on_import() {
+    // Ragged rows are padded as when loading a document.
+    let source = uuid::Uuid::new_v4();
+    let mut fragment: serde_json::Value = serde_json::from_str(&Fragment::new(Vec::new(), Some(source)).to_json()).unwrap();
+    fragment["blocks"] = serde_json::json!([{
+        "type": "table",
+        "id": uuid::Uuid::new_v4(),
+        "rows": [[{"content": []}, {"content": []}], [{"content": []}]],
+        "dirty": false,
+    }]);
+    let parsed = Fragment::from_json(&fragment.to_string()).unwrap();
+    assert_eq!(parsed.source, Some(source));
+    let Block::Table { rows, .. } = &parsed.blocks[0] else { panic!("expected a table") };
+    assert!(rows.iter().all(|row| row.len() == 2));
+
+    fragment["fragment"] = serde_json::json!(99);
+    assert!(import_fragment(&fragment.to_string()).unwrap_err().to_string().contains("99"));
+    // A whole document is not a snippet.
+    assert!(import_fragment(&wa_core::export_json(&Document::new()).unwrap()).is_err());
+}
@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use wa_core::{block_plain_text, Block, DocSnapshot, Document, Editor, EditorCommand, Inline};
+
+fn large_doc(blocks: usize) -> Document {
+    let mut doc = Document::new();
+    for i in 0..blocks {
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(format!("段落 {}", i)) }],
+            dirty: false,
+            locked: false,
+        });
+    }
+    doc
+}
+
+#[test]
+fn snapshots_share_unchanged_blocks() {
+    let mut doc = large_doc(1000);
+    let mut previous = DocSnapshot::capture(&doc, None);
+    let mut retained: HashSet<*const Block> = previous.blocks.iter().map(Arc::as_ptr).collect();
+    // Snapshots stay alive so freed blocks cannot hand their address on.
+    let mut history = Vec::new();
+    for i in 0..100 {
+        if let Block::Paragraph { content, .. } = &mut doc.blocks[i] {
+            content.push(Inline::Text { value: Arc::from("!") });
+        }
+        // Layout flipping dirty flags alone must not defeat sharing.
+        doc.blocks[999 - i].set_dirty(true);
+        let snapshot = DocSnapshot::capture(&doc, Some(&previous));
+        retained.extend(snapshot.blocks.iter().map(Arc::as_ptr));
+        history.push(std::mem::replace(&mut previous, snapshot));
+    }
+    assert_eq!(retained.len(), 1000 + 100);
+    let restored = previous.to_document();
+    assert_eq!(restored.blocks.len(), 1000);
+    assert_eq!(block_plain_text(&restored.blocks[0]), "段落 0!");
+}
+
+#[test]
+fn undo_through_shared_snapshots() {
+    let mut editor = Editor::new(large_doc(200));
+    for _ in 0..20 {
+        editor.execute(EditorCommand::InsertTable(1, 1));
+    }
+    assert_eq!(editor.doc.blocks.len(), 220);
+    for _ in 0..20 {
+        editor.execute(EditorCommand::Undo);
+    }
+    assert_eq!(editor.doc.blocks.len(), 200);
+    assert_eq!(block_plain_text(&editor.doc.blocks[199]), "段落 199");
+    editor.execute(EditorCommand::Redo);
+    assert_eq!(editor.doc.blocks.len(), 201);
+}
+
+#[test]
+fn undo_keeps_a_lock_set_between_snapshots() {
+    let mut editor = Editor::new(large_doc(3));
+    let id = editor.doc.blocks[1].id();
+    editor.execute(EditorCommand::InsertPageBreak);
+    editor.execute(EditorCommand::SetBlockLocked { block_id: id, locked: true });
+    editor.execute(EditorCommand::InsertPageBreak);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.iter().filter(|b| matches!(b, Block::PageBreak { .. })).count(), 1);
+    assert!(editor.doc.blocks.iter().find(|b| b.id() == id).unwrap().is_locked());
+    editor.execute(EditorCommand::Undo);
+    assert!(!editor.doc.blocks.iter().find(|b| b.id() == id).unwrap().is_locked());
+}
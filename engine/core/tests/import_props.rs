@@ -0,0 +1,158 @@
+use proptest::prelude::*;
+use std::sync::Arc;
+use wa_core::{
+    block_plain_text, doc_semantic_eq, export_markdown, import_html, import_html_rich, import_json, import_markdown,
+    validate_doc, Block, Cell, CodeText, Document, Inline, ListItem,
+};
+
+/// Pieces that exercise the importers' scanners: markers, brackets, tags and
+/// attribute syntax, cut up by multi-byte characters.
+fn token() -> impl Strategy<Value = String> {
+    prop_oneof![
+        prop::sample::select(vec![
+            "![", "](", ")", "[", "]", "# ", "## ", "#### ", "- ", "1. ", ". ", "> ", "```", "```rs", "|", " | ",
+            "<p>", "</p>", "<br>", "<br/>", "<b>", "</b>", "<i>", "<a href=\"", "\">", "</a>", "<code>", "</code>",
+            "<pre>", "</pre>", "<table>", "<tr", "<td", "</td>", "<ul>", "<ol>", "<li", "</li>", "<img src=", "src=",
+            "src=\"", "src='", "'", "\"", "<", ">", "&amp;", "&", ";", "\n", "\r\n", " ", "\t", "İ", "ẞ", "中", "，",
+            "é", "\u{200d}", "😀",
+        ])
+        .prop_map(str::to_string),
+        "[a-z]{1,4}",
+    ]
+}
+
+fn hostile() -> impl Strategy<Value = String> {
+    prop_oneof![
+        prop::collection::vec(token(), 0..40).prop_map(|parts| parts.concat()),
+        any::<String>(),
+    ]
+}
+
+/// Markdown made of the constructs the importer knows, so the text of every
+/// block is something the exporter can write back without escaping.
+fn markdown() -> impl Strategy<Value = String> {
+    let word = "[a-z中é]{1,6}";
+    let words = prop::collection::vec(word, 1..4).prop_map(|w| w.join(" "));
+    let line = prop_oneof![
+        (1..=3usize, words.clone()).prop_map(|(level, text)| format!("{} {text}", "#".repeat(level))),
+        words.clone().prop_map(|text| format!("- {text}")),
+        (1..20u32, words.clone()).prop_map(|(n, text)| format!("{n}. {text}")),
+        words.clone().prop_map(|text| format!("> {text}")),
+        (words.clone(), word).prop_map(|(cap, url)| format!("![{cap}](https://{url}.png)")),
+        prop::collection::vec(words.clone(), 1..4).prop_map(|cells| format!("| {} |", cells.join(" | "))),
+        prop::collection::vec(words.clone(), 0..3).prop_map(|code| format!("```\n{}\n```", code.join("\n"))),
+        words,
+        Just(String::new()),
+    ];
+    prop::collection::vec(line, 0..12).prop_map(|lines| lines.join("\n"))
+}
+
+/// One-line text full of markdown syntax. Leading and trailing whitespace
+/// is trimmed, as every line-based importer does.
+fn markup_text() -> impl Strategy<Value = String> {
+    let piece = prop_oneof![
+        prop::sample::select(vec![
+            "#", "## ", "- ", "-", "1. ", "12", ". ", ".", ">", "|", " | ", "\\", "\\|", "\\#", "!", "![", "](", ")",
+            "```", "~~~", "`", "<!-- wa-custom ", "-->", "---", ":", " ", "中", "é",
+        ])
+        .prop_map(str::to_string),
+        "[a-z]{1,3}",
+    ];
+    prop::collection::vec(piece, 1..8)
+        .prop_map(|parts| parts.concat().trim().to_string())
+        .prop_filter("blank text", |text| !text.is_empty())
+}
+
+fn text_block() -> impl Strategy<Value = Block> {
+    let inline = |text: String| vec![Inline::Text { value: Arc::from(text) }];
+    let id = uuid::Uuid::new_v4;
+    let code_line = prop_oneof![markup_text(), Just(String::new()), Just("  ````  ".to_string())];
+    prop_oneof![
+        (1..=6u8, markup_text()).prop_map(move |(level, text)| Block::Heading { id: id(), level, content: inline(text), dirty: false, locked: false }),
+        markup_text().prop_map(move |text| Block::Paragraph { id: id(), content: inline(text), dirty: false, locked: false }),
+        (any::<bool>(), prop::collection::vec(markup_text(), 1..4)).prop_map(move |(ordered, items)| Block::List {
+            id: id(),
+            ordered,
+            items: items.into_iter().map(|text| ListItem { id: id(), content: inline(text), level: 0, checked: None }).collect(),
+            dirty: false,
+            locked: false,
+            toc: false,
+        }),
+        markup_text().prop_map(move |text| Block::Quote {
+            id: id(),
+            content: vec![Block::Paragraph { id: id(), content: inline(text), dirty: false, locked: false }],
+            dirty: false,
+            locked: false,
+        }),
+        ("[a-z]{0,3}", prop::collection::vec(code_line, 0..4)).prop_map(move |(lang, lines)| Block::Code {
+            id: id(),
+            lang: Arc::from(lang),
+            code: CodeText::from(lines.join("\n")),
+            dirty: false,
+            locked: false,
+        }),
+        (1..4usize, prop::collection::vec(prop_oneof![markup_text(), Just(String::new())], 1..10), any::<bool>()).prop_map(
+            move |(cols, cells, header)| Block::Table {
+                id: id(),
+                rows: cells.chunks(cols).filter(|row| row.len() == cols).map(|row| row.iter().map(|t| Cell { content: inline(t.clone()) }).collect()).collect(),
+                header,
+                col_widths: Vec::new(),
+                dirty: false,
+                locked: false,
+            }
+        ),
+    ]
+    .prop_filter("table without rows", |block| !matches!(block, Block::Table { rows, .. } if rows.is_empty()))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2000))]
+
+    #[test]
+    fn importers_never_panic(input in hostile()) {
+        let md = import_markdown(&input);
+        prop_assert!(md.blocks.len() <= input.lines().count() + 1);
+        for doc in [import_html(&input), import_html_rich(&input)] {
+            prop_assert!(doc.blocks.len() <= input.len() + 1);
+        }
+        for mut doc in [md, import_html_rich(&input)] {
+            prop_assert_eq!(validate_doc(&mut doc), vec![]);
+        }
+        let _ = import_json(&input);
+    }
+
+    #[test]
+    fn pasted_tables_come_out_rectangular(cells in prop::collection::vec(0..4usize, 1..5)) {
+        let rows: String = cells.iter().map(|n| format!("<tr>{}</tr>", "<td>x</td>".repeat(*n))).collect();
+        let mut doc = import_html_rich(&format!("<table>{rows}</table>"));
+        prop_assert_eq!(validate_doc(&mut doc), vec![]);
+    }
+
+    #[test]
+    fn markdown_export_reimports_the_same_structure(input in markdown()) {
+        let first = import_markdown(&input);
+        let second = import_markdown(&export_markdown(&first));
+        prop_assert!(doc_semantic_eq(&first, &second), "{:?} != {:?}", first.blocks, second.blocks);
+    }
+
+    #[test]
+    fn markdown_round_trip_keeps_block_text(blocks in prop::collection::vec(text_block(), 0..6)) {
+        let mut doc = Document::new();
+        doc.blocks = blocks;
+        let md = export_markdown(&doc);
+        let back = import_markdown(&md);
+        let texts = |doc: &Document| doc.blocks.iter().map(|b| (std::mem::discriminant(b), block_plain_text(b))).collect::<Vec<_>>();
+        prop_assert_eq!(texts(&back), texts(&doc), "{}", md);
+    }
+}
+
+
+#[test]
+fn unterminated_images_do_not_panic() {
+    for input in ["![", "![](", "![a)](", "![)", "![İ](x)", "<img src=", "<img src=\"", "<IMG SRC=İ>", "İİ<img src='a'"] {
+        import_markdown(input);
+        import_html_rich(input);
+    }
+    let doc = import_html_rich("<p>İ</p><img src=a.png alt=x>");
+    assert!(matches!(&doc.blocks[0], Block::Figure { url, .. } if url.as_ref() == "a.png"));
+}
@@ -0,0 +1,157 @@
+use proptest::prelude::*;
+use std::sync::Arc;
+use wa_core::{
+    block_plain_text, flatten_path, inline_plain_text, resolve_position, resolve_position_with, split_at_path, Block, Cell,
+    Document, Editor, EditorCommand, Inline, InlinePath, LeafBias, ListItem, Position, Selection, Style,
+};
+
+fn text(value: &str) -> Inline {
+    Inline::Text { value: Arc::from(value) }
+}
+
+fn bold(content: Vec<Inline>) -> Inline {
+    Inline::Styled { style: Style { bold: true, ..Style::default() }, content }
+}
+
+fn paragraph(content: Vec<Inline>) -> Block {
+    Block::Paragraph { id: uuid::Uuid::new_v4(), content, dirty: false, locked: false }
+}
+
+fn path(inline_indices: &[usize], char_in_leaf: usize) -> InlinePath {
+    InlinePath { inline_indices: inline_indices.to_vec(), char_in_leaf }
+}
+
+#[test]
+fn boundaries_follow_the_bias() {
+    let block = paragraph(vec![text("ab"), bold(vec![text("cd")]), text("e")]);
+    assert_eq!(resolve_position(&block, 2), Some(path(&[0], 2)));
+    assert_eq!(resolve_position_with(&block, 2, LeafBias::After), Some(path(&[1, 0], 0)));
+    assert_eq!(resolve_position_with(&block, 5, LeafBias::After), Some(path(&[2], 1)));
+    assert_eq!(resolve_position(&block, 6), None);
+    assert_eq!(flatten_path(&block, &path(&[1, 0], 2)), Some(4));
+    assert_eq!(flatten_path(&block, &path(&[1, 0], 3)), None);
+    assert_eq!(flatten_path(&block, &path(&[1], 0)), None);
+}
+
+#[test]
+fn separators_count_like_plain_text() {
+    let list = Block::List {
+        id: uuid::Uuid::new_v4(),
+        ordered: false,
+        items: ["一二", "三"]
+            .map(|t| ListItem { id: uuid::Uuid::new_v4(), content: vec![text(t)], level: 0, checked: None })
+            .to_vec(),
+        dirty: false,
+        locked: false,
+        toc: false,
+    };
+    assert_eq!(resolve_position(&list, 2), Some(path(&[0, 0], 2)));
+    assert_eq!(resolve_position(&list, 3), Some(path(&[1, 0], 0)));
+
+    let cell = |t: &str| Cell { content: vec![text(t)] };
+    let table = Block::Table {
+        id: uuid::Uuid::new_v4(),
+        rows: vec![vec![cell("a"), cell("bc")], vec![cell("d"), cell("")]],
+        col_widths: Vec::new(),
+        header: false,
+        dirty: false,
+        locked: false,
+    };
+    assert_eq!(block_plain_text(&table), "a\tbc\nd\t");
+    assert_eq!(resolve_position(&table, 4), Some(path(&[0, 1, 0], 2)));
+    assert_eq!(resolve_position(&table, 5), Some(path(&[1, 0, 0], 0)));
+    assert_eq!(resolve_position(&table, 7), Some(path(&[1, 1, 0], 0)));
+
+    let quote = Block::Quote { id: uuid::Uuid::new_v4(), content: vec![paragraph(vec![text("x")]), paragraph(vec![bold(vec![text("yz")])])], dirty: false, locked: false };
+    assert_eq!(resolve_position(&quote, 3), Some(path(&[1, 0, 0], 1)));
+    assert_eq!(flatten_path(&quote, &path(&[1, 0, 0], 2)), Some(4));
+}
+
+#[test]
+fn split_cuts_styles_and_links_in_two() {
+    let link = Inline::Link { url: Arc::from("https://a"), text: vec![text("li"), bold(vec![text("nk")])] };
+    let mut block = paragraph(vec![text("a"), link]);
+    let point = resolve_position(&block, 4).unwrap();
+    let at = split_at_path(&mut block, &point).unwrap();
+    let Block::Paragraph { content, .. } = &block else { unreachable!() };
+    assert_eq!(at, 2);
+    let (mut before, mut after) = (String::new(), String::new());
+    inline_plain_text(&content[..at], &mut before);
+    inline_plain_text(&content[at..], &mut after);
+    assert_eq!((before.as_str(), after.as_str()), ("alin", "k"));
+    assert!(matches!(&content[2], Inline::Link { url, text } if url.as_ref() == "https://a" && text == &vec![bold(vec![self::text("k")])]));
+}
+
+#[test]
+fn links_are_inserted_at_the_caret() {
+    let mut doc = Document::new();
+    doc.blocks.push(paragraph(vec![text("前后")]));
+    let block_id = doc.blocks[0].id();
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id, offset: 1 });
+    editor.execute(EditorCommand::InsertLink { url: "https://a".to_string(), text: "链接".to_string() });
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(content.len(), 3);
+    assert!(matches!(&content[1], Inline::Link { .. }));
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "前链接后");
+    assert_eq!(editor.selection.focus.offset, 3);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(block_plain_text(&editor.doc.blocks[0]), "前后");
+}
+
+fn arb_style() -> impl Strategy<Value = Style> {
+    (any::<bool>(), any::<bool>()).prop_map(|(bold, italic)| Style { bold, italic, ..Style::default() })
+}
+
+fn arb_inline() -> impl Strategy<Value = Inline> {
+    let leaf = prop_oneof![
+        "[a-c世]{0,3}".prop_map(|v| Inline::Text { value: Arc::from(v) }),
+        "[x-z]{0,2}".prop_map(|v| Inline::CodeSpan { value: Arc::from(v) }),
+    ];
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        prop_oneof![
+            (arb_style(), prop::collection::vec(inner.clone(), 0..4))
+                .prop_map(|(style, content)| Inline::Styled { style, content }),
+            prop::collection::vec(inner, 0..3).prop_map(|text| Inline::Link { url: Arc::from("https://a"), text }),
+        ]
+    })
+}
+
+fn has_leaf(inlines: &[Inline]) -> bool {
+    inlines.iter().any(|inline| match inline {
+        Inline::Text { .. } | Inline::CodeSpan { .. } | Inline::Math { .. } => true,
+        Inline::Styled { content, .. } | Inline::Link { text: content, .. } => has_leaf(content),
+    })
+}
+
+proptest! {
+    #[test]
+    fn resolve_then_flatten_is_identity(inlines in prop::collection::vec(arb_inline(), 0..6)) {
+        let block = paragraph(inlines.clone());
+        let len = block_plain_text(&block).chars().count();
+        for offset in 0..=len {
+            for bias in [LeafBias::Before, LeafBias::After] {
+                let resolved = resolve_position_with(&block, offset, bias);
+                prop_assert_eq!(resolved.is_some(), has_leaf(&inlines));
+                if let Some(path) = resolved {
+                    prop_assert_eq!(flatten_path(&block, &path), Some(offset));
+                }
+            }
+        }
+        prop_assert_eq!(resolve_position(&block, len + 1), None);
+    }
+
+    #[test]
+    fn splitting_keeps_the_text_on_both_sides(inlines in prop::collection::vec(arb_inline(), 1..6), at in 0usize..20) {
+        let original = block_plain_text(&paragraph(inlines.clone()));
+        let mut block = paragraph(inlines);
+        let at = at.min(original.chars().count());
+        let Some(path) = resolve_position(&block, at) else { return Ok(()) };
+        let cut = split_at_path(&mut block, &path).unwrap();
+        prop_assert_eq!(block_plain_text(&block), original.clone());
+        let Block::Paragraph { content, .. } = &block else { unreachable!() };
+        let mut before = String::new();
+        inline_plain_text(&content[..cut], &mut before);
+        prop_assert_eq!(before, original.chars().take(at).collect::<String>());
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use wa_core::{export_json, import_json, Block, Cell, Document, Editor, EditorCommand, Inline, StringInterner};
+
+fn cell_ptrs(doc: &Document) -> HashSet<*const u8> {
+    let mut out = HashSet::new();
+    for block in &doc.blocks {
+        if let Block::Table { rows, .. } = block {
+            for cell in rows.iter().flatten() {
+                if let [Inline::Text { value }] = cell.content.as_slice() {
+                    out.insert(value.as_ptr());
+                }
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn editor_deduplicates_repeated_table_cells() {
+    let labels = ["是", "否", "待定"];
+    let rows = (0..10_000)
+        .map(|i| {
+            (0..3)
+                .map(|c| Cell { content: vec![Inline::Text { value: Arc::from(labels[(i + c) % 3]) }] })
+                .collect()
+        })
+        .collect();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Table { id: uuid::Uuid::new_v4(), rows, header: false, col_widths: Vec::new(), dirty: false, locked: false });
+    let imported = import_json(&export_json(&doc).unwrap()).unwrap();
+    assert_eq!(cell_ptrs(&imported).len(), 30_000);
+
+    let mut editor = Editor::new(imported);
+    assert_eq!(cell_ptrs(&editor.doc).len(), 3);
+    let stats = editor.intern_stats();
+    assert_eq!(stats.unique, 3);
+    assert_eq!(stats.hits, 30_000 - 3);
+
+    let table_id = editor.doc.blocks[0].id();
+    editor.execute(EditorCommand::TableEditCell { block_id: table_id, row: 5, col: 0, text: "是".to_string() });
+    assert_eq!(wa_core::block_plain_text(&editor.doc.blocks[0]).lines().nth(5), Some("是\t是\t否"));
+    assert_eq!(cell_ptrs(&editor.doc).len(), 3);
+}
+
+#[test]
+fn long_strings_bypass_and_prune_drops_unused() {
+    let mut interner = StringInterner::new();
+    let a = interner.intern("label");
+    let b = interner.intern("label");
+    assert!(Arc::ptr_eq(&a, &b));
+    let long = "x".repeat(500);
+    assert!(!Arc::ptr_eq(&interner.intern(&long), &interner.intern(&long)));
+    interner.intern("unused");
+    assert_eq!(interner.stats().unique, 2);
+    assert_eq!(interner.prune(), 1);
+    assert_eq!(interner.stats().unique, 1);
+}
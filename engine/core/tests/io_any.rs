@@ -1,4 +1,4 @@
-use wa_core::{Block, import_any, import_html_rich};
+﻿use wa_core::{block_plain_text, doc_semantic_eq, import_any, import_html, import_html_rich, import_json, Block, Error};
 #[cfg(feature = "export_docx")]
 use std::sync::Arc;
 #[cfg(feature = "export_docx")]
@@ -12,20 +12,85 @@ fn import_plaintext_smoke() {
     tmp.push("wa_import_test.txt");
     std::fs::write(&tmp, "第一段\n\n第二段").unwrap();
     let doc = import_any(&tmp).unwrap();
-    assert!(doc.blocks.len() >= 1);
+    assert!(!doc.blocks.is_empty());
+}
+
+#[test]
+fn import_errors_keep_their_kind() {
+    let dir = std::env::temp_dir();
+    let missing = dir.join(format!("wa_missing_{}.md", uuid::Uuid::new_v4()));
+    assert!(matches!(import_any(&missing), Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::NotFound));
+
+    let unknown = dir.join(format!("wa_unknown_{}.xyz", uuid::Uuid::new_v4()));
+    std::fs::write(&unknown, "").unwrap();
+    let err = import_any(&unknown).unwrap_err();
+    std::fs::remove_file(&unknown).ok();
+    assert!(matches!(&err, Error::Unsupported(ext) if ext == "xyz"));
+    assert_eq!((err.code(), err.exit_code()), ("unsupported", 5));
+
+    assert!(matches!(import_json("{\"blocks\": ["), Err(Error::Parse { format, .. }) if format == "json"));
+    assert!(matches!(import_json("{\"blocks\": 3}"), Err(Error::Validation(_))));
 }
 
 #[test]
 fn import_html_rich_list_table() {
     let list_html = "<ul><li><b>一</b>号</li><li>二号</li></ul>";
     let doc = import_html_rich(list_html);
-    assert!(doc.blocks.iter().any(|b| matches!(b, Block::List { .. })));
+    match doc.blocks.as_slice() {
+        [Block::List { ordered: false, items, .. }] => {
+            assert_eq!(items.len(), 2);
+            assert!(matches!(items[0].content.as_slice(), [wa_core::Inline::Styled { style, .. }, _] if style.bold));
+        }
+        other => panic!("expected one list, got {:?}", other),
+    }
 
     let table_html = "<table><tr><td>甲</td><td><i>乙</i></td></tr></table>";
     let doc = import_html_rich(table_html);
-    match doc.blocks.get(0) {
-        Some(Block::Table { rows, .. }) => assert_eq!(rows.get(0).map(|r| r.len()), Some(2)),
-        _ => panic!("expected table"),
+    let plain = import_html_rich("<table><tr><td>甲</td><td>乙</td></tr></table>");
+    assert!(!doc_semantic_eq(&doc, &plain));
+    assert!(doc_semantic_eq(&doc, &import_html_rich(table_html)));
+    match doc.blocks.as_slice() {
+        [Block::Table { rows, .. }] => assert_eq!(rows.iter().map(Vec::len).collect::<Vec<_>>(), vec![2]),
+        other => panic!("expected one table, got {:?}", other),
+    }
+}
+
+#[test]
+fn import_html_splits_paragraphs_on_breaks() {
+    let texts = |html: &str| import_html(html).blocks.iter().map(block_plain_text).collect::<Vec<_>>();
+    assert_eq!(texts("<p>a<br>b</p>"), vec!["a", "b"]);
+    assert_eq!(texts("<p>一</p>\n  <p>二\n三</p>\n"), vec!["一", "二三"]);
+
+    let doc = import_html("<b>x<br>y</b>");
+    assert_eq!(doc.blocks.len(), 2);
+    for (block, text) in doc.blocks.iter().zip(["x", "y"]) {
+        match block {
+            Block::Paragraph { content, .. } => match content.as_slice() {
+                [wa_core::Inline::Styled { style, content }] => {
+                    assert!(style.bold);
+                    assert!(matches!(content.as_slice(), [wa_core::Inline::Text { value }] if value.as_ref() == text));
+                }
+                other => panic!("expected one bold run, got {:?}", other),
+            },
+            other => panic!("expected paragraph, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn html_whitespace_keeps_cjk_and_pre_intact() {
+    let table_html = "<table>\n<tr>\n  <td class=\"c\">\n    中文\n    内容\n  </td>\n  <td>Hello\n   world</td>\n</tr></table>";
+    match import_html_rich(table_html).blocks.first() {
+        Some(block @ Block::Table { .. }) => assert_eq!(block_plain_text(block), "中文内容\tHello world"),
+        other => panic!("expected table, got {:?}", other),
+    }
+
+    let list_html = "<ul><li>第一，\n 第二</li><li><pre>fn main() {\n    go();\n}</pre></li></ul>";
+    match import_html_rich(list_html).blocks.first() {
+        Some(block @ Block::List { .. }) => {
+            assert_eq!(block_plain_text(block), "第一，第二\nfn main() {\n    go();\n}");
+        }
+        other => panic!("expected list, got {:?}", other),
     }
 }
 
@@ -1,4 +1,4 @@
-﻿use wa_core::{export_markdown, export_json, import_json, import_markdown, sanitize_doc, Block, Inline, TableEditor};
+﻿use wa_core::{doc_semantic_eq, semantic_hash, export_markdown, export_json, export_json_fast, export_json_into_string, import_json, import_json_with_fixups, import_markdown, sanitize_doc, validate_doc, Block, FigureSize, Fixup, Inline, TableEditor, MIN_TABLE_COL_WIDTH};
 use std::sync::Arc;
 
 #[test]
@@ -10,6 +10,26 @@ fn markdown_roundtrip_basic() {
     assert!(out.contains("- 项目一"));
     assert!(out.contains("> 引用"));
     assert!(out.contains("```rs"));
+    assert!(doc_semantic_eq(&doc, &import_markdown(&out)));
+}
+
+#[test]
+fn markdown_lists_split_on_marker_and_blocks() {
+    let lists = |md: &str| {
+        import_markdown(md)
+            .blocks
+            .iter()
+            .map(|b| match b {
+                Block::List { ordered, items, .. } => format!("{}{}", if *ordered { "ol" } else { "ul" }, items.len()),
+                Block::Quote { .. } => "quote".to_string(),
+                Block::Code { .. } => "code".to_string(),
+                _ => "other".to_string(),
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lists("- a\n- b\n1. c\n2. d\n- e"), vec!["ul2", "ol2", "ul1"]);
+    assert_eq!(lists("1. a\n> 引用\n2. b"), vec!["ol1", "quote", "ol1"]);
+    assert_eq!(lists("- a\n```\nx\n```\n- b"), vec!["ul1", "code", "ul1"]);
 }
 
 #[test]
@@ -20,6 +40,85 @@ fn json_roundtrip_basic() {
     let doc2 = import_json(&json).unwrap();
     let clean = sanitize_doc(doc2);
     assert!(!clean.blocks.is_empty());
+    assert!(doc_semantic_eq(&doc, &clean));
+    assert_eq!(semantic_hash(&doc), semantic_hash(&clean));
+}
+
+#[test]
+fn semantic_eq_ignores_ids_but_not_order() {
+    let doc = import_markdown("# 标题\n\n- 一\n- 二\n\n段落");
+    let again = import_markdown("# 标题\n\n- 一\n- 二\n\n段落");
+    assert_ne!(doc.blocks[0].id(), again.blocks[0].id());
+    assert!(doc_semantic_eq(&doc, &again));
+    assert_eq!(semantic_hash(&doc), semantic_hash(&again));
+
+    let mut touched = again.clone();
+    touched.version += 5;
+    touched.metadata.updated_at = 42;
+    touched.blocks.iter_mut().for_each(|b| b.set_dirty(true));
+    assert!(doc_semantic_eq(&doc, &touched));
+    assert_eq!(semantic_hash(&doc), semantic_hash(&touched));
+
+    let mut reordered = again.clone();
+    reordered.blocks.swap(1, 2);
+    assert!(!doc_semantic_eq(&doc, &reordered));
+    assert_ne!(semantic_hash(&doc), semantic_hash(&reordered));
+
+    let mut edited = again;
+    if let Block::List { items, .. } = &mut edited.blocks[1] {
+        items[1].content = vec![Inline::Text { value: Arc::from("三") }];
+    }
+    assert!(!doc_semantic_eq(&doc, &edited));
+}
+
+#[test]
+fn json_import_repairs_hostile_values() {
+    let id = |n: u8| format!("00000000-0000-0000-0000-0000000000{:02}", n);
+    let cell = r#"{"content": [{"type": "text", "value": "x"}]}"#;
+    let raw = format!(
+        r#"{{"id": "{doc}", "version": 3, "metadata": {{"title": "", "author": "", "created_at": 0, "updated_at": 0}}, "blocks": [
+            {{"type": "heading", "id": "{h}", "level": 0, "content": [], "dirty": false}},
+            {{"type": "figure", "id": "{f1}", "url": "local://a", "caption": null, "size": {{"width": 1e300, "height": 10}}, "dirty": false}},
+            {{"type": "quote", "id": "{q}", "dirty": false, "content": [
+                {{"type": "figure", "id": "{f2}", "url": "local://b", "caption": null, "size": {{"width": -5, "height": 0}}, "dirty": false}},
+                {{"type": "heading", "id": "{h2}", "level": 200, "content": [], "dirty": false}}
+            ]}},
+            {{"type": "table", "id": "{t}", "header": false, "col_widths": [0.5, -1], "dirty": false,
+              "rows": [[], [{cell}, {cell}], [{cell}], [{cell}, {cell}, {cell}]]}}
+        ]}}"#,
+        doc = id(0), h = id(1), f1 = id(2), q = id(3), f2 = id(4), h2 = id(5), t = id(6), cell = cell,
+    );
+    let (doc, fixups) = import_json_with_fixups(&raw).unwrap();
+    let uuid = |n: u8| uuid::Uuid::parse_str(&id(n)).unwrap();
+    assert_eq!(
+        fixups,
+        vec![
+            Fixup::HeadingLevel { block_id: uuid(1), level: 0 },
+            Fixup::FigureSize { block_id: uuid(2) },
+            Fixup::FigureSize { block_id: uuid(4) },
+            Fixup::HeadingLevel { block_id: uuid(5), level: 200 },
+            Fixup::TableRows { block_id: uuid(6) },
+            Fixup::ColumnWidths { block_id: uuid(6) },
+        ]
+    );
+    assert!(matches!(doc.blocks[0], Block::Heading { level: 1, dirty: true, .. }));
+    assert!(matches!(doc.blocks[1], Block::Figure { size: None, .. }));
+    match &doc.blocks[2] {
+        Block::Quote { content, dirty: false, .. } => {
+            assert!(matches!(content[0], Block::Figure { size: Some(FigureSize { width: 1.0, height: 1.0 }), .. }));
+            assert!(matches!(content[1], Block::Heading { level: 6, .. }));
+        }
+        other => panic!("expected quote, got {:?}", other),
+    }
+    match &doc.blocks[3] {
+        Block::Table { rows, col_widths, .. } => {
+            assert_eq!(rows.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 2, 2]);
+            assert!(col_widths.is_empty());
+        }
+        other => panic!("expected table, got {:?}", other),
+    }
+    assert!(validate_doc(&mut doc.clone()).is_empty());
+    assert!(sanitize_doc(doc).blocks.iter().all(|b| !b.is_effectively_dirty()));
 }
 
 #[test]
@@ -27,7 +126,10 @@ fn table_editor_ops() {
     let mut block = Block::Table {
         id: uuid::Uuid::new_v4(),
         rows: vec![vec![wa_core::Cell { content: vec![Inline::Text { value: Arc::from("a") }] }]],
+        header: false,
+        col_widths: Vec::new(),
         dirty: false,
+        locked: false,
     };
     assert!(TableEditor::insert_row(&mut block, 1));
     assert!(TableEditor::insert_column(&mut block, 1));
@@ -35,3 +137,95 @@ fn table_editor_ops() {
     assert!(TableEditor::delete_row(&mut block, 0));
     assert!(TableEditor::delete_column(&mut block, 0));
 }
+
+#[test]
+fn table_column_widths() {
+    let cell = || wa_core::Cell { content: vec![Inline::Text { value: Arc::from("") }] };
+    let mut block = Block::Table {
+        id: uuid::Uuid::new_v4(),
+        rows: vec![vec![cell(), cell(), cell()]],
+        header: false,
+        col_widths: Vec::new(),
+        dirty: false,
+        locked: false,
+    };
+    let third = 1.0 / 3.0;
+    assert_eq!(TableEditor::col_fractions(&block), vec![third; 3]);
+
+    assert!(TableEditor::set_col_width(&mut block, 0, 0.5));
+    let widths = TableEditor::col_fractions(&block);
+    assert!((widths[0] - 0.5).abs() < 1e-6);
+    assert!((widths[1] - (2.0 * third - 0.5)).abs() < 1e-6);
+    assert!((widths[2] - third).abs() < 1e-6);
+
+    TableEditor::set_col_width(&mut block, 1, 0.0);
+    assert!((TableEditor::col_fractions(&block)[1] - MIN_TABLE_COL_WIDTH).abs() < 1e-6);
+    assert!(!TableEditor::set_col_width(&mut block, 2, 0.2));
+
+    assert!(TableEditor::insert_column(&mut block, 1));
+    assert!(TableEditor::delete_column(&mut block, 0));
+    let widths = TableEditor::col_fractions(&block);
+    assert_eq!(widths.len(), 3);
+    assert!((widths.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+}
+
+#[test]
+fn json_string_export_reuses_buffer() {
+    let doc = import_markdown("# 标题\n\n段落");
+    let mut out = String::from("stale");
+    export_json_into_string(&doc, &mut out).unwrap();
+    assert_eq!(out, export_json_fast(&doc).unwrap());
+    let capacity = out.capacity();
+    export_json_into_string(&doc, &mut out).unwrap();
+    assert_eq!(out.capacity(), capacity);
+    assert!(doc_semantic_eq(&import_json(&out).unwrap(), &doc));
+}
+
+#[test]
+fn tab_order_wraps_at_row_ends_and_appends_after_the_last_cell() {
+    let cell = |t: &str| wa_core::Cell { content: vec![Inline::Text { value: Arc::from(t) }] };
+    let rows = vec![vec![cell("a"), cell("b")], vec![cell("c"), cell("d")]];
+    assert_eq!(TableEditor::next_cell(&rows, 0, 0), Some((0, 1)));
+    assert_eq!(TableEditor::next_cell(&rows, 0, 1), Some((1, 0)));
+    assert_eq!(TableEditor::next_cell(&rows, 1, 1), None);
+    assert_eq!(TableEditor::prev_cell(&rows, 1, 0), Some((0, 1)));
+    assert_eq!(TableEditor::prev_cell(&rows, 0, 0), None);
+    assert_eq!(TableEditor::cell_below(&rows, 0, 1), Some((1, 1)));
+    assert_eq!(TableEditor::cell_below(&rows, 1, 1), None);
+
+    let id = uuid::Uuid::new_v4();
+    let mut doc = wa_core::Document::new();
+    doc.blocks.push(Block::Table { id, rows, header: false, col_widths: Vec::new(), dirty: false, locked: false });
+    let mut editor = wa_core::Editor::new(doc);
+    assert_eq!(editor.next_table_cell(id, 0, 1), Some((1, 0)));
+    assert_eq!(editor.next_table_cell(id, 1, 1), Some((2, 0)));
+    assert!(matches!(&editor.doc.blocks[0], Block::Table { rows, .. } if rows.len() == 3 && rows[2].len() == 2));
+    editor.execute(wa_core::EditorCommand::Undo);
+    assert!(matches!(&editor.doc.blocks[0], Block::Table { rows, .. } if rows.len() == 2));
+}
+
+#[test]
+fn markdown_escapes_pipes_leading_markup_and_fences() {
+    let text = |t: &str| vec![Inline::Text { value: Arc::from(t) }];
+    let cell = |t: &str| wa_core::Cell { content: text(t) };
+    let mut doc = wa_core::Document::new();
+    doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: text("# not a heading"), dirty: false, locked: false });
+    doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: text("2. not a list"), dirty: false, locked: false });
+    doc.blocks.push(Block::Table {
+        id: uuid::Uuid::new_v4(),
+        rows: vec![vec![cell("a|b"), cell("c")], vec![cell("---"), cell("d\\")]],
+        header: true,
+        col_widths: Vec::new(),
+        dirty: false,
+        locked: false,
+    });
+    doc.blocks.push(Block::Code { id: uuid::Uuid::new_v4(), lang: Arc::from("md"), code: wa_core::CodeText::from("```\nx\n```"), dirty: false, locked: false });
+
+    let md = export_markdown(&doc);
+    assert!(md.contains("\\# not a heading\n"), "{md}");
+    assert!(md.contains("2\\. not a list\n"), "{md}");
+    assert!(md.contains("| a\\|b | c |\n| --- | --- |\n| \\--- | d\\\\ |"), "{md}");
+    assert!(md.contains("````md\n```\nx\n```\n````"), "{md}");
+    let back = import_markdown(&md);
+    assert!(doc_semantic_eq(&doc, &back), "{:?}", back.blocks);
+}
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use wa_core::{block_plain_text, Block, Document, Editor, EditorCommand, Inline, Journal, Position, Selection};
+
+fn scratch() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wa_journal_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join("doc.json.journal")
+}
+
+fn saved_doc() -> Document {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from("start") }], dirty: false, locked: false });
+    doc
+}
+
+fn type_text(editor: &mut Editor, text: &str) {
+    let block_id = editor.doc.blocks[0].id();
+    for ch in text.chars() {
+        let offset = block_plain_text(&editor.doc.blocks[0]).chars().count();
+        editor.selection = Selection::collapsed(Position { block_id, offset });
+        editor.execute(EditorCommand::InsertText(ch.to_string()));
+    }
+}
+
+fn texts(editor: &Editor) -> Vec<String> {
+    editor.doc.blocks.iter().map(block_plain_text).collect()
+}
+
+#[test]
+fn replay_stops_at_a_record_cut_short_by_a_crash() {
+    let path = scratch();
+    let saved = saved_doc();
+    let mut editor = Editor::new(saved.clone());
+    let mut journal = Journal::open(&path).unwrap();
+    journal.sync_every = 4;
+    editor.attach_journal(journal);
+    type_text(&mut editor, "abc");
+    editor.execute(EditorCommand::SetHeading(2));
+    // Blocks a command adds get fresh ids, so replay must reuse the
+    // recorded ones for later records to find them.
+    editor.execute(EditorCommand::InsertTable(2, 2));
+    let table_id = editor.doc.blocks[1].id();
+    editor.execute(EditorCommand::TableEditCell { block_id: table_id, row: 1, col: 1, text: "格".to_string() });
+    let before_last = texts(&editor);
+    type_text(&mut editor, "d");
+    drop(editor.detach_journal());
+
+    // The writer died half way through the last record.
+    let bytes = std::fs::read(&path).unwrap();
+    let last_start = bytes[..bytes.len() - 1].iter().rposition(|b| *b == b'\n').unwrap() + 1;
+    std::fs::write(&path, &bytes[..bytes.len() - 7]).unwrap();
+
+    let mut recovered = Editor::new(saved.clone());
+    let replay = Journal::replay(&path, &mut recovered).unwrap();
+    assert_eq!((replay.applied, replay.skipped, replay.stopped_at), (6, 0, Some(last_start as u64)));
+    assert_eq!(texts(&recovered), before_last);
+    assert!(matches!(recovered.doc.blocks[0], Block::Heading { level: 2, .. }));
+    assert_eq!(recovered.doc.blocks[1].id(), table_id);
+
+    // Reopening cuts the damaged tail, so new records stay reachable.
+    let mut journal = Journal::open(&path).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), last_start as u64);
+    let block_id = recovered.doc.blocks[0].id();
+    journal.append(&EditorCommand::InsertText("!".to_string()), recovered.doc.version, Selection::collapsed(Position { block_id, offset: 0 }), &[]).unwrap();
+    drop(journal);
+    let mut again = Editor::new(saved);
+    let replay = Journal::replay(&path, &mut again).unwrap();
+    assert_eq!((replay.applied, replay.stopped_at), (7, None));
+    assert_eq!(texts(&again)[0], "!startabc");
+    assert_eq!(texts(&again)[1..], before_last[1..]);
+}
+
+#[test]
+fn records_before_the_last_save_are_not_replayed_twice() {
+    let path = scratch();
+    let mut editor = Editor::new(saved_doc());
+    editor.attach_journal(Journal::open(&path).unwrap());
+    type_text(&mut editor, "12");
+    // Saved, but the app died before the journal was truncated.
+    let saved = editor.doc.clone();
+    type_text(&mut editor, "34");
+    let expected = texts(&editor);
+    drop(editor);
+
+    let mut recovered = Editor::new(saved.clone());
+    let replay = Journal::replay(&path, &mut recovered).unwrap();
+    assert_eq!((replay.applied, replay.skipped), (2, 2));
+    assert_eq!(texts(&recovered), expected);
+
+    // After a save that did truncate, nothing is left to recover.
+    recovered.attach_journal(Journal::open(&path).unwrap());
+    recovered.journal_mut().unwrap().truncate().unwrap();
+    assert!(!Journal::has_records(&path));
+    type_text(&mut recovered, "5");
+    assert!(Journal::has_records(&path));
+    recovered.detach_journal().unwrap().discard().unwrap();
+    assert!(!path.exists());
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+use wa_core::{fix_document, lint_document, Block, Cell, Document, Fixup, Inline, LintRule, Style};
+
+fn text(value: &str) -> Vec<Inline> {
+    vec![Inline::Text { value: Arc::from(value) }]
+}
+
+fn heading(level: u8, value: &str) -> Block {
+    Block::Heading { id: uuid::Uuid::new_v4(), level, content: text(value), dirty: false, locked: false }
+}
+
+fn paragraph(content: Vec<Inline>) -> Block {
+    Block::Paragraph { id: uuid::Uuid::new_v4(), content, dirty: false, locked: false }
+}
+
+fn table(widths: &[usize]) -> Block {
+    Block::Table {
+        id: uuid::Uuid::new_v4(),
+        rows: widths.iter().map(|w| (0..*w).map(|_| Cell { content: text("格") }).collect()).collect(),
+        header: false,
+        col_widths: Vec::new(),
+        dirty: false,
+        locked: false,
+    }
+}
+
+fn doc(blocks: Vec<Block>) -> Document {
+    let mut doc = Document::new();
+    doc.blocks = blocks;
+    doc
+}
+
+fn rules(doc: &Document) -> Vec<(usize, LintRule)> {
+    lint_document(doc).into_iter().map(|f| (f.index, f.rule)).collect()
+}
+
+#[test]
+fn heading_jumps_are_found_after_the_first_heading() {
+    let doc = doc(vec![heading(2, "a"), heading(4, "b"), heading(3, "c"), heading(1, "d"), heading(3, "e")]);
+    assert_eq!(
+        rules(&doc),
+        [(1, LintRule::HeadingJump { from: 2, to: 4 }), (4, LintRule::HeadingJump { from: 1, to: 3 })]
+    );
+}
+
+#[test]
+fn empty_headings_are_found() {
+    let doc = doc(vec![heading(1, "标题"), heading(2, " "), heading(2, "")]);
+    assert_eq!(rules(&doc), [(1, LintRule::EmptyHeading), (2, LintRule::EmptyHeading)]);
+}
+
+#[test]
+fn ragged_tables_report_the_first_uneven_row() {
+    let doc = doc(vec![table(&[3, 3]), table(&[0, 2, 2, 1, 3])]);
+    assert_eq!(rules(&doc), [(1, LintRule::RaggedTable { row: 0, cells: 0, expected: 2 })]);
+}
+
+#[test]
+fn figures_need_a_caption() {
+    let figure = |caption: Option<&str>| Block::Figure {
+        id: uuid::Uuid::new_v4(),
+        url: Arc::from("a.png"),
+        caption: caption.map(Arc::from),
+        size: None,
+        dirty: false,
+        locked: false,
+    };
+    let doc = doc(vec![figure(Some("图 1")), figure(None), figure(Some("  "))]);
+    assert_eq!(rules(&doc), [(1, LintRule::MissingCaption), (2, LintRule::MissingCaption)]);
+}
+
+#[test]
+fn empty_link_urls_are_found_in_nested_blocks() {
+    let link = |url: &str| Inline::Link { url: Arc::from(url), text: text("链接") };
+    let styled = Inline::Styled { style: Style { bold: true, ..Style::default() }, content: vec![link("")] };
+    let quote = Block::Quote { id: uuid::Uuid::new_v4(), content: vec![paragraph(vec![link(" ")])], dirty: false, locked: false };
+    let doc = doc(vec![paragraph(vec![link("https://example.com"), styled]), quote]);
+    let findings = lint_document(&doc);
+    assert_eq!(rules(&doc), [(0, LintRule::EmptyLinkUrl), (1, LintRule::EmptyLinkUrl)]);
+    // The quote's finding names the paragraph inside it.
+    assert_eq!(findings[1].block_id, doc.blocks[1].children()[0].id());
+}
+
+#[test]
+fn fixing_pads_tables_and_drops_trailing_empty_paragraphs() {
+    let mut doc = doc(vec![paragraph(text("")), table(&[2, 1]), paragraph(text("正文")), paragraph(text(" ")), paragraph(Vec::new())]);
+    let ids: Vec<_> = doc.blocks.iter().map(Block::id).collect();
+    let fixups = fix_document(&mut doc);
+    assert_eq!(
+        fixups,
+        [
+            Fixup::TableRows { block_id: ids[1] },
+            Fixup::TrailingParagraph { block_id: ids[4] },
+            Fixup::TrailingParagraph { block_id: ids[3] },
+        ]
+    );
+    assert_eq!(doc.blocks.len(), 3);
+    assert!(lint_document(&doc).is_empty());
+
+    // An empty document keeps its only block.
+    let mut empty = self::doc(vec![paragraph(Vec::new())]);
+    assert!(fix_document(&mut empty).is_empty());
+    assert_eq!(empty.blocks.len(), 1);
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+use wa_core::{
+    block_plain_text, export_html, export_json, export_markdown, find_in_doc, import_json, import_markdown, Block, Document, Editor,
+    EditorCommand, FindOptions, Inline, Position, Selection,
+};
+
+fn text(value: &str) -> Inline {
+    Inline::Text { value: Arc::from(value) }
+}
+
+fn math(tex: &str, display: bool) -> Inline {
+    Inline::Math { tex: Arc::from(tex), display }
+}
+
+fn paragraph_doc(content: Vec<Inline>) -> Document {
+    let mut doc = Document::new();
+    doc.blocks = vec![Block::Paragraph { id: uuid::Uuid::new_v4(), content, dirty: false, locked: false }];
+    doc
+}
+
+fn content(doc: &Document) -> &[Inline] {
+    match &doc.blocks[0] {
+        Block::Paragraph { content, .. } => content,
+        other => panic!("expected a paragraph, got {}", other.type_name()),
+    }
+}
+
+#[test]
+fn math_round_trips_through_json() {
+    let doc = paragraph_doc(vec![text("质能方程 "), math("E=mc^2", false), math("\\int_0^1 x\\,dx", true)]);
+    let json = export_json(&doc).unwrap();
+    // Inline math leaves `display` out.
+    assert_eq!(json.matches("\"display\"").count(), 1);
+    assert_eq!(content(&import_json(&json).unwrap()), content(&doc));
+}
+
+#[test]
+fn math_round_trips_through_markdown() {
+    let doc = import_markdown("质能方程 $E=mc^2$ 成立");
+    assert_eq!(content(&doc), [text("质能方程 "), math("E=mc^2", false), text(" 成立")]);
+    assert_eq!(export_markdown(&doc), "质能方程 $E=mc^2$ 成立");
+
+    let doc = paragraph_doc(vec![math("a^2+b^2", true), text(" 价格 $5")]);
+    let md = export_markdown(&doc);
+    assert_eq!(md, "$$a^2+b^2$$ 价格 \\$5");
+    assert_eq!(content(&import_markdown(&md)), content(&doc));
+}
+
+#[test]
+fn dollar_amounts_stay_text() {
+    for md in ["costs $5 and $10", "$ x$", "a $b $c", "$$", "$x$1"] {
+        assert_eq!(content(&import_markdown(md)), [text(md)], "{md}");
+    }
+    let doc = import_markdown("- $x$ 与 $y$\n\n| $a$ | b |");
+    let cells: Vec<_> = doc.blocks.iter().map(block_plain_text).collect();
+    assert_eq!(cells, ["x 与 y", "a\tb"]);
+}
+
+#[test]
+fn html_wraps_math_in_spans() {
+    let doc = paragraph_doc(vec![math("a<b", false), math("x", true)]);
+    let html = export_html(&doc);
+    assert!(html.contains(r#"<span class="math">a&lt;b</span><span class="math display">x</span>"#), "{html}");
+}
+
+#[test]
+fn find_looks_in_math_only_when_asked() {
+    let doc = paragraph_doc(vec![text("mc 与 "), math("E=mc^2", false)]);
+    assert_eq!(find_in_doc(&doc, "mc", FindOptions::default()).len(), 1);
+    let with_math = FindOptions { include_math: true, ..FindOptions::default() };
+    assert_eq!(find_in_doc(&doc, "mc", with_math).len(), 2);
+}
+
+#[test]
+fn insert_math_goes_in_at_the_caret_and_undoes() {
+    let doc = paragraph_doc(vec![text("设  成立")]);
+    let block_id = doc.blocks[0].id();
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id, offset: 2 });
+    editor.execute(EditorCommand::InsertMath { tex: "x^2".to_string(), display: false });
+    assert_eq!(content(&editor.doc), [text("设 "), math("x^2", false), text(" 成立")]);
+    assert_eq!(editor.selection.focus.offset, 5);
+
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(content(&editor.doc), [text("设  成立")]);
+}
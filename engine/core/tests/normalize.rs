@@ -0,0 +1,112 @@
+﻿use proptest::prelude::*;
+use std::sync::Arc;
+use wa_core::{
+    import_html_rich, inline_plain_text, normalize_inlines, Block, Document, Editor, EditorCommand, Inline, Position,
+    Selection, Style,
+};
+
+fn text(value: &str) -> Inline {
+    Inline::Text { value: Arc::from(value) }
+}
+
+fn styled(style: Style, content: Vec<Inline>) -> Inline {
+    Inline::Styled { style, content }
+}
+
+const BOLD: Style = Style { bold: true, italic: false, underline: false, strikethrough: false };
+const ITALIC: Style = Style { bold: false, italic: true, underline: false, strikethrough: false };
+
+#[test]
+fn collapses_pathological_trees() {
+    let mut inlines = vec![
+        styled(BOLD, vec![styled(BOLD, vec![text("a")])]),
+        styled(BOLD, vec![text("b"), styled(Style::default(), vec![])]),
+        text(""),
+        text("c"),
+        text("d"),
+        styled(ITALIC, vec![]),
+        styled(BOLD.union(ITALIC), vec![styled(ITALIC, vec![text("e")])]),
+    ];
+    normalize_inlines(&mut inlines);
+    assert_eq!(
+        inlines,
+        vec![
+            styled(BOLD, vec![text("ab")]),
+            text("cd"),
+            styled(BOLD.union(ITALIC), vec![text("e")]),
+        ]
+    );
+
+    let mut empty = vec![styled(BOLD, vec![text("")])];
+    normalize_inlines(&mut empty);
+    assert_eq!(empty, vec![text("")]);
+}
+
+#[test]
+fn editing_keeps_trees_flat() {
+    let mut doc = Document::new();
+    let block_id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Paragraph { id: block_id, content: vec![text("one "), text("two")], dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection {
+        anchor: Position { block_id, offset: 0 },
+        focus: Position { block_id, offset: 7 },
+    };
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(content, &vec![styled(BOLD, vec![text("one two")])]);
+    // Bolding it again toggles back rather than nesting.
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(content, &vec![text("one two")]);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+
+    editor.selection = Selection {
+        anchor: Position { block_id, offset: 0 },
+        focus: Position { block_id, offset: 7 },
+    };
+    editor.execute(EditorCommand::DeleteSelection);
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(content, &vec![text("")]);
+    editor.execute(EditorCommand::Undo);
+    let Block::Paragraph { content, .. } = &editor.doc.blocks[0] else { unreachable!() };
+    assert_eq!(content, &vec![styled(BOLD, vec![text("one two")])]);
+
+    let pasted = import_html_rich("<p><b><b>x</b></b><b>y</b>z</p>");
+    let Block::Paragraph { content, .. } = &pasted.blocks[0] else { unreachable!() };
+    assert_eq!(content, &vec![styled(BOLD, vec![text("xy")]), text("z")]);
+}
+
+fn arb_style() -> impl Strategy<Value = Style> {
+    (any::<bool>(), any::<bool>(), any::<bool>(), any::<bool>())
+        .prop_map(|(bold, italic, underline, strikethrough)| Style { bold, italic, underline, strikethrough })
+}
+
+fn arb_inline() -> impl Strategy<Value = Inline> {
+    let leaf = prop_oneof![
+        "[a-c世]{0,3}".prop_map(|v| Inline::Text { value: Arc::from(v) }),
+        "[x-z]{0,2}".prop_map(|v| Inline::CodeSpan { value: Arc::from(v) }),
+    ];
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        prop_oneof![
+            (arb_style(), prop::collection::vec(inner.clone(), 0..4))
+                .prop_map(|(style, content)| Inline::Styled { style, content }),
+            prop::collection::vec(inner, 0..3).prop_map(|text| Inline::Link { url: Arc::from("https://a"), text }),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn normalizing_is_idempotent_and_keeps_text(mut inlines in prop::collection::vec(arb_inline(), 0..6)) {
+        let mut before = String::new();
+        inline_plain_text(&inlines, &mut before);
+        normalize_inlines(&mut inlines);
+        let mut after = String::new();
+        inline_plain_text(&inlines, &mut after);
+        prop_assert_eq!(before, after);
+        let once = inlines.clone();
+        normalize_inlines(&mut inlines);
+        prop_assert_eq!(once, inlines);
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+use wa_core::{pdf_pages, Block, Document, Inline, PageRange, Paper, PdfOptions};
+
+fn paragraph(text: &str) -> Block {
+    Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false }
+}
+
+fn first_lines(doc: &Document, options: &PdfOptions) -> Vec<(usize, String)> {
+    pdf_pages(doc, options).into_iter().map(|page| (page.number, page.lines[0].text.clone())).collect()
+}
+
+#[test]
+fn page_ranges_parse_like_a_print_dialog() {
+    assert_eq!(PageRange::parse("3"), Some(PageRange { first: 3, last: Some(3) }));
+    assert_eq!(PageRange::parse(" 2 - 5 "), Some(PageRange { first: 2, last: Some(5) }));
+    assert_eq!(PageRange::parse("4-"), Some(PageRange { first: 4, last: None }));
+    for bad in ["", "0", "5-2", "-3", "a-b", "1-2-3"] {
+        assert_eq!(PageRange::parse(bad), None, "{bad:?}");
+    }
+    assert_eq!(PageRange { first: 2, last: Some(5) }.to_string(), "2-5");
+}
+
+#[test]
+fn page_range_keeps_only_the_pages_it_names() {
+    let mut doc = Document::new();
+    // 297mm paper, 20mm margins and 10mm per block: 26 blocks a page.
+    doc.blocks = (1..=60).map(|i| paragraph(&format!("第 {i} 段"))).collect();
+    let mut options = PdfOptions { paper: Paper::A4, margin_mm: 20.0, ..PdfOptions::default() };
+    assert_eq!(first_lines(&doc, &options), [(1, "第 1 段".to_string()), (2, "第 27 段".to_string()), (3, "第 53 段".to_string())]);
+
+    options.page_range = PageRange::parse("2");
+    assert_eq!(first_lines(&doc, &options), [(2, "第 27 段".to_string())]);
+    options.page_range = PageRange::parse("2-");
+    assert_eq!(first_lines(&doc, &options), [(2, "第 27 段".to_string()), (3, "第 53 段".to_string())]);
+    options.page_range = PageRange::parse("4-9");
+    assert!(pdf_pages(&doc, &options).is_empty());
+
+    // A page break starts the next page, and lines keep their place on it.
+    doc.blocks.insert(1, Block::PageBreak { id: uuid::Uuid::new_v4(), dirty: false, locked: false });
+    options.page_range = PageRange::parse("1-2");
+    let pages = pdf_pages(&doc, &options);
+    assert_eq!(pages[0].lines.len(), 1);
+    assert_eq!(pages[1].lines[0].text, "第 2 段");
+    assert_eq!(pages[1].lines[0].y_mm, pages[0].lines[0].y_mm);
+}
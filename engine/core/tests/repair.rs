@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use wa_core::{
+    merge_adjacent_tables, merge_adjacent_tables_with, sanitize_doc, sanitize_doc_with, Block, Cell, Document, Editor, EditorCommand, Inline,
+    Position, RepairOptions, Selection,
+};
+
+fn row_table(cells: &[&str]) -> Block {
+    Block::Table {
+        id: uuid::Uuid::new_v4(),
+        rows: vec![cells.iter().map(|c| Cell { content: vec![Inline::Text { value: Arc::from(*c) }] }).collect()],
+        header: false,
+        col_widths: Vec::new(),
+        dirty: false,
+        locked: false,
+    }
+}
+
+fn paragraph(text: &str) -> Block {
+    Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false }
+}
+
+fn shapes(doc: &Document) -> Vec<Vec<usize>> {
+    doc.blocks
+        .iter()
+        .map(|b| match b {
+            Block::Table { rows, .. } => rows.iter().map(Vec::len).collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+fn legacy_doc() -> Document {
+    let mut doc = Document::new();
+    doc.blocks = vec![paragraph("前言")];
+    doc.blocks.extend((0..5).map(|i| row_table(&["名称", &i.to_string()])));
+    doc.blocks.push(paragraph("后记"));
+    doc
+}
+
+#[test]
+fn single_row_tables_merge_into_the_first() {
+    let mut doc = legacy_doc();
+    let first = doc.blocks[1].id();
+    assert_eq!(merge_adjacent_tables(&mut doc), 4);
+    assert_eq!(shapes(&doc), [vec![], vec![2; 5], vec![]]);
+    assert_eq!(doc.blocks[1].id(), first);
+    assert!(doc.blocks[1].is_dirty());
+    assert_eq!(merge_adjacent_tables(&mut doc), 0);
+}
+
+#[test]
+fn tables_too_different_in_width_stay_apart() {
+    let mut doc = Document::new();
+    doc.blocks = vec![row_table(&["a", "b"]), row_table(&["a", "b", "c", "d"]), row_table(&["a", "b", "c"])];
+    // Four and three columns are one apart, so the last two merge and the
+    // shorter row is padded.
+    assert_eq!(merge_adjacent_tables(&mut doc), 1);
+    assert_eq!(shapes(&doc), [vec![2], vec![4, 4]]);
+
+    let mut doc = Document::new();
+    doc.blocks = vec![row_table(&["a", "b"]), row_table(&["a", "b", "c"]), paragraph("x"), row_table(&["a", "b"])];
+    assert_eq!(merge_adjacent_tables_with(&mut doc, 0), 0);
+    assert_eq!(shapes(&doc), [vec![2], vec![3], vec![], vec![2]]);
+}
+
+#[test]
+fn merging_through_the_editor_undoes_in_one_step() {
+    let doc = legacy_doc();
+    let (first, last) = (doc.blocks[1].id(), doc.blocks[5].id());
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id: last, offset: 1 });
+    assert_eq!(editor.repair(&RepairOptions::default()), 4);
+    assert_eq!(editor.doc.blocks.len(), 3);
+    assert_eq!(editor.selection.focus, Position { block_id: first, offset: 0 });
+    assert_eq!(editor.repair(&RepairOptions::default()), 0);
+
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(shapes(&editor.doc), shapes(&legacy_doc()));
+    assert_eq!(editor.selection.focus.block_id, last);
+}
+
+#[test]
+fn sanitizing_merges_tables_only_when_asked() {
+    assert_eq!(sanitize_doc(legacy_doc()).blocks.len(), 7);
+    let repaired = sanitize_doc_with(legacy_doc(), &RepairOptions::default());
+    assert_eq!(shapes(&repaired), [vec![], vec![2; 5], vec![]]);
+    assert!(!repaired.blocks[1].is_dirty());
+}
@@ -0,0 +1,210 @@
+use proptest::prelude::*;
+use std::sync::Arc;
+use wa_core::{
+    block_plain_text, inline_plain_text, range_style, set_range_style, Block, Document, Editor, EditorCommand, Inline, Position, Selection, Style,
+};
+
+fn text(value: &str) -> Inline {
+    Inline::Text { value: Arc::from(value) }
+}
+
+fn styled(style: Style, content: Vec<Inline>) -> Inline {
+    Inline::Styled { style, content }
+}
+
+const BOLD: Style = Style { bold: true, italic: false, underline: false, strikethrough: false };
+const ITALIC: Style = Style { bold: false, italic: true, underline: false, strikethrough: false };
+
+fn editor_with(content: Vec<Inline>) -> (Editor, uuid::Uuid) {
+    let block_id = uuid::Uuid::new_v4();
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph { id: block_id, content, dirty: false, locked: false });
+    (Editor::new(doc), block_id)
+}
+
+fn select(editor: &mut Editor, block_id: uuid::Uuid, start: usize, end: usize) {
+    editor.selection = Selection { anchor: Position { block_id, offset: start }, focus: Position { block_id, offset: end } };
+}
+
+fn content(editor: &Editor, index: usize) -> &Vec<Inline> {
+    match &editor.doc.blocks[index] {
+        Block::Paragraph { content, .. } | Block::Heading { content, .. } => content,
+        other => panic!("not a text block: {other:?}"),
+    }
+}
+
+#[test]
+fn bold_toggles_on_and_off() {
+    let (mut editor, block_id) = editor_with(vec![text("hello world")]);
+    select(&mut editor, block_id, 6, 11);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    assert_eq!(content(&editor, 0), &vec![text("hello "), styled(BOLD, vec![text("world")])]);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    assert_eq!(content(&editor, 0), &vec![text("hello world")]);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(content(&editor, 0), &vec![text("hello "), styled(BOLD, vec![text("world")])]);
+}
+
+#[test]
+fn partly_bold_ranges_become_bold_and_unbolding_cuts_spans() {
+    let (mut editor, block_id) = editor_with(vec![text("ab"), styled(BOLD, vec![text("cd")]), text("ef")]);
+    select(&mut editor, block_id, 1, 5);
+    assert_eq!(range_style(content(&editor, 0), 1, 5), Some(Style::default()));
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    assert_eq!(content(&editor, 0), &vec![text("a"), styled(BOLD, vec![text("bcde")]), text("f")]);
+
+    // Clearing the middle of a bold run leaves bold on both sides.
+    select(&mut editor, block_id, 3, 2);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    assert_eq!(content(&editor, 0), &vec![text("a"), styled(BOLD, vec![text("b")]), text("c"), styled(BOLD, vec![text("de")]), text("f")]);
+}
+
+#[test]
+fn styling_leaves_inlines_outside_the_range_alone() {
+    let underline = Style { underline: true, ..Style::default() };
+    let nested = styled(ITALIC, vec![text("ab"), styled(underline, vec![text("cd")])]);
+    let link = Inline::Link { url: Arc::from("u"), text: vec![styled(BOLD, vec![text("ef")])] };
+    let (mut editor, block_id) = editor_with(vec![nested.clone(), text("gh"), link.clone()]);
+    select(&mut editor, block_id, 4, 6);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    assert_eq!(content(&editor, 0), &vec![nested.clone(), styled(BOLD, vec![text("gh")]), link.clone()]);
+
+    // Toggling the same range back and forth does not fragment the runs.
+    for _ in 0..10 {
+        editor.execute(EditorCommand::ApplyStyle(BOLD));
+    }
+    assert_eq!(content(&editor, 0), &vec![nested, styled(BOLD, vec![text("gh")]), link]);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    let before = content(&editor, 0).clone();
+    select(&mut editor, block_id, 1, 5);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(content(&editor, 0), &before);
+}
+
+#[test]
+fn bold_and_italic_combine_and_come_off_separately() {
+    let (mut editor, block_id) = editor_with(vec![styled(ITALIC, vec![text("ab")]), text("cd")]);
+    select(&mut editor, block_id, 0, 4);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    assert_eq!(content(&editor, 0), &vec![styled(BOLD.union(ITALIC), vec![text("ab")]), styled(BOLD, vec![text("cd")])]);
+    assert_eq!(range_style(content(&editor, 0), 0, 4), Some(BOLD));
+
+    // Only "ab" is italic, so italic goes on everywhere, then off again.
+    editor.execute(EditorCommand::ApplyStyle(ITALIC));
+    assert_eq!(content(&editor, 0), &vec![styled(BOLD.union(ITALIC), vec![text("abcd")])]);
+    editor.execute(EditorCommand::ApplyStyle(ITALIC));
+    assert_eq!(content(&editor, 0), &vec![styled(BOLD, vec![text("abcd")])]);
+
+    // Both flags at once count as set only when both are.
+    editor.execute(EditorCommand::ApplyStyle(BOLD.union(ITALIC)));
+    assert_eq!(content(&editor, 0), &vec![styled(BOLD.union(ITALIC), vec![text("abcd")])]);
+    editor.execute(EditorCommand::ApplyStyle(BOLD.union(ITALIC)));
+    assert_eq!(content(&editor, 0), &vec![text("abcd")]);
+}
+
+#[test]
+fn links_and_formulas_keep_their_shape() {
+    let link = Inline::Link { url: Arc::from("https://example.com"), text: vec![text("link")] };
+    let math = Inline::Math { tex: Arc::from("x^2"), display: false };
+    let (mut editor, block_id) = editor_with(vec![link, text(" "), math.clone()]);
+    // The range ends inside the formula, which is bolded whole.
+    select(&mut editor, block_id, 2, 6);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    assert_eq!(
+        content(&editor, 0),
+        &vec![
+            Inline::Link { url: Arc::from("https://example.com"), text: vec![text("li"), styled(BOLD, vec![text("nk")])] },
+            styled(BOLD, vec![text(" "), math]),
+        ]
+    );
+}
+
+#[test]
+fn selections_over_several_blocks_toggle_as_one_step() {
+    let (mut editor, first) = editor_with(vec![text("one")]);
+    let second = uuid::Uuid::new_v4();
+    editor.doc.blocks.push(Block::Heading { id: second, level: 2, content: vec![styled(BOLD, vec![text("two")])], dirty: false, locked: false });
+    editor.selection = Selection { anchor: Position { block_id: second, offset: 2 }, focus: Position { block_id: first, offset: 1 } };
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    assert_eq!(content(&editor, 0), &vec![text("o"), styled(BOLD, vec![text("ne")])]);
+    assert_eq!(content(&editor, 1), &vec![styled(BOLD, vec![text("two")])]);
+    editor.execute(EditorCommand::ApplyStyle(BOLD));
+    assert_eq!(content(&editor, 0), &vec![text("one")]);
+    assert_eq!(content(&editor, 1), &vec![text("tw"), styled(BOLD, vec![text("o")])]);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(content(&editor, 0), &vec![text("o"), styled(BOLD, vec![text("ne")])]);
+    assert_eq!(content(&editor, 1), &vec![styled(BOLD, vec![text("two")])]);
+    assert_eq!(block_plain_text(&editor.doc.blocks[1]), "two");
+}
+
+#[test]
+fn clear_formatting_flattens_nested_styles() {
+    let underline = Style { underline: true, ..Style::default() };
+    let nested = styled(BOLD, vec![styled(ITALIC, vec![styled(underline, vec![text("deep")])]), text("er")]);
+    let (mut editor, block_id) = editor_with(vec![text("a "), nested.clone(), text(" b")]);
+    select(&mut editor, block_id, 0, 0);
+    editor.execute(EditorCommand::ClearFormatting);
+    assert_eq!(content(&editor, 0), &vec![text("a deeper b")]);
+    assert!(matches!(editor.doc.blocks[0], Block::Paragraph { dirty: true, .. }));
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(content(&editor, 0), &vec![text("a "), nested, text(" b")]);
+
+    // A range clears only what it covers.
+    select(&mut editor, block_id, 6, 2);
+    editor.execute(EditorCommand::ClearFormatting);
+    assert_eq!(content(&editor, 0), &vec![text("a deep"), styled(BOLD, vec![text("er")]), text(" b")]);
+}
+
+#[test]
+fn clear_formatting_keeps_links_and_code_spans() {
+    let link = Inline::Link { url: Arc::from("https://example.com"), text: vec![styled(BOLD, vec![styled(ITALIC, vec![text("link")])])] };
+    let code = styled(ITALIC, vec![Inline::CodeSpan { value: Arc::from("x") }]);
+    let (mut editor, block_id) = editor_with(vec![link, code]);
+    select(&mut editor, block_id, 5, 0);
+    editor.execute(EditorCommand::ClearFormatting);
+    assert_eq!(
+        content(&editor, 0),
+        &vec![Inline::Link { url: Arc::from("https://example.com"), text: vec![text("link")] }, Inline::CodeSpan { value: Arc::from("x") }]
+    );
+
+    // Nothing left to clear adds no undo step.
+    editor.execute(EditorCommand::ClearFormatting);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(range_style(content(&editor, 0), 0, 5), Some(ITALIC));
+}
+
+fn arb_inline() -> impl Strategy<Value = Inline> {
+    let leaf = prop_oneof![
+        "[a-c中]{0,4}".prop_map(|s| text(&s)),
+        "[xy]{1,3}".prop_map(|s| Inline::Math { tex: Arc::from(s), display: false }),
+    ];
+    leaf.prop_recursive(3, 12, 3, |inner| {
+        prop_oneof![
+            (0..16u8, prop::collection::vec(inner.clone(), 0..3)).prop_map(|(bits, content)| styled(
+                Style { bold: bits & 1 != 0, italic: bits & 2 != 0, underline: bits & 4 != 0, strikethrough: bits & 8 != 0 },
+                content
+            )),
+            prop::collection::vec(inner, 0..3).prop_map(|text| Inline::Link { url: Arc::from("u"), text }),
+        ]
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(200))]
+
+    #[test]
+    fn restyling_keeps_text_and_sets_the_flags(inlines in prop::collection::vec(arb_inline(), 0..4), a in 0..16usize, b in 0..16usize, on in any::<bool>()) {
+        let (start, end) = (a.min(b), a.max(b));
+        let mut before = String::new();
+        inline_plain_text(&inlines, &mut before);
+        let mut restyled = inlines.clone();
+        set_range_style(&mut restyled, start, end, BOLD, on);
+        let mut after = String::new();
+        inline_plain_text(&restyled, &mut after);
+        prop_assert_eq!(before, after);
+        if let Some(style) = range_style(&restyled, start, end) {
+            prop_assert_eq!(style.bold, on);
+        }
+    }
+}
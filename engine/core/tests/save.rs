@@ -0,0 +1,99 @@
+use serde::ser::{Error as _, SerializeMap};
+use serde::{Serialize, Serializer};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wa_core::{export_json_to_file, export_json_to_file_with, import_json, Block, Document, Error, Inline, SaveOptions};
+
+fn doc_with(text: &str) -> Document {
+    let mut doc = Document::new();
+    for _ in 0..200 {
+        doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false });
+    }
+    doc
+}
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wa_save_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn leftovers(path: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Writes part of the document, then fails the way a buggy or crashing
+/// serializer would.
+struct Poisoned<'a> {
+    doc: &'a Document,
+    panic: bool,
+}
+
+impl Serialize for Poisoned<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("blocks", &self.doc.blocks)?;
+        if self.panic {
+            panic!("serializer crashed");
+        }
+        Err(S::Error::custom("serializer failed"))
+    }
+}
+
+#[test]
+fn failed_serialization_leaves_the_original_untouched() {
+    let path = scratch("doc.json");
+    export_json_to_file(&doc_with("原始内容"), &path).unwrap();
+    let original = std::fs::read(&path).unwrap();
+    let next = doc_with("新的内容");
+
+    let crashed = std::panic::catch_unwind(|| export_json_to_file_with(&Poisoned { doc: &next, panic: true }, &path, SaveOptions::default()));
+    assert!(crashed.is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+    assert_eq!(leftovers(&path), ["doc.json"]);
+
+    let err = export_json_to_file_with(&Poisoned { doc: &next, panic: false }, &path, SaveOptions::default()).unwrap_err();
+    assert!(matches!(err, Error::Export { .. }), "{err}");
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+    assert_eq!(leftovers(&path), ["doc.json"]);
+    std::fs::remove_dir_all(path.parent().unwrap()).ok();
+}
+
+#[test]
+fn verify_rejects_output_that_does_not_read_back() {
+    let path = scratch("doc.json");
+    export_json_to_file(&doc_with("原始内容"), &path).unwrap();
+    let original = std::fs::read(&path).unwrap();
+
+    let options = SaveOptions { backup: true, verify: true };
+    let err = export_json_to_file_with(&serde_json::json!({ "blocks": 5 }), &path, options).unwrap_err();
+    assert!(matches!(err, Error::Verify { .. }), "{err}");
+    assert_eq!(err.code(), "verify");
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+    assert_eq!(leftovers(&path), ["doc.json"]);
+
+    let missing_dir = path.parent().unwrap().join("missing").join("doc.json");
+    let err = export_json_to_file(&doc_with("x"), &missing_dir).unwrap_err();
+    assert!(matches!(err, Error::Write { .. }), "{err}");
+    std::fs::remove_dir_all(path.parent().unwrap()).ok();
+}
+
+#[test]
+fn backup_keeps_the_previous_version() {
+    let path = scratch("doc.json");
+    let options = SaveOptions { backup: true, verify: true };
+    export_json_to_file_with(&doc_with("第一版"), &path, options).unwrap();
+    assert_eq!(leftovers(&path), ["doc.json"]);
+    export_json_to_file_with(&doc_with("第二版"), &path, options).unwrap();
+    assert_eq!(leftovers(&path), ["doc.json", "doc.json.bak"]);
+
+    let read = |p: &Path| wa_core::block_plain_text(&import_json(&std::fs::read_to_string(p).unwrap()).unwrap().blocks[0]);
+    assert_eq!(read(&path), "第二版");
+    assert_eq!(read(&path.with_file_name("doc.json.bak")), "第一版");
+    std::fs::remove_dir_all(path.parent().unwrap()).ok();
+}
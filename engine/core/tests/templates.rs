@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use wa_core::{block_plain_text, Block, Document, Editor, EditorCommand, Inline, Template, TemplateRegistry};
+
+fn ids(doc: &Document) -> Vec<uuid::Uuid> {
+    let mut out = Vec::new();
+    for block in &doc.blocks {
+        block.for_each_block(&mut |b| out.push(b.id()));
+        if let Block::List { items, .. } = block {
+            out.extend(items.iter().map(|item| item.id));
+        }
+    }
+    out
+}
+
+#[test]
+fn inserted_templates_get_fresh_ids_every_time() {
+    let registry = TemplateRegistry::with_builtins();
+    assert_eq!(registry.names().collect::<Vec<_>>(), ["meeting_notes", "figure_with_caption"]);
+    let template = registry.get("meeting_notes").unwrap();
+    let template_ids: HashSet<_> = template.blocks.iter().map(Block::id).collect();
+
+    let mut editor = Editor::new(Document::new());
+    editor.insert_template(template);
+    editor.insert_template(template);
+    let all = ids(&editor.doc);
+    assert_eq!(all.len(), 2 * (template.blocks.len() + 3));
+    assert_eq!(all.iter().collect::<HashSet<_>>().len(), all.len());
+    assert!(all.iter().all(|id| !template_ids.contains(id)));
+}
+
+#[test]
+fn template_insert_is_one_undo_step_after_the_focused_block() {
+    let mut doc = Document::new();
+    doc.metadata.title = Arc::from("周会");
+    for text in ["a", "b"] {
+        doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false });
+    }
+    let mut editor = Editor::new(doc);
+    let template = TemplateRegistry::with_builtins().get("meeting_notes").cloned().unwrap();
+    editor.insert_template(&template);
+
+    let texts: Vec<String> = editor.doc.blocks.iter().map(block_plain_text).collect();
+    assert_eq!(texts.len(), 2 + template.blocks.len());
+    assert_eq!(texts[0], "a");
+    assert_eq!(texts[1], "周会 会议纪要");
+    assert!(!texts[2].contains("{{date}}"), "{}", texts[2]);
+    assert_eq!(texts.last().unwrap(), "b");
+    assert_eq!(editor.selection.focus.block_id, editor.doc.blocks[1].id());
+
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(editor.doc.blocks.iter().map(block_plain_text).collect::<Vec<_>>(), ["a", "b"]);
+}
+
+#[test]
+fn templates_round_trip_through_json() {
+    let mut registry = TemplateRegistry::new();
+    let json = serde_json::to_string(&TemplateRegistry::with_builtins().get("figure_with_caption").unwrap()).unwrap();
+    let mut template: Template = serde_json::from_str(&json).unwrap();
+    template.name = "figure".to_string();
+    registry.register(template.clone());
+    registry.register(template);
+    assert_eq!(registry.names().collect::<Vec<_>>(), ["figure"]);
+}
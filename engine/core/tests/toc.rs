@@ -0,0 +1,55 @@
+use std::sync::Arc;
+use wa_core::{block_plain_text, toc_target, Block, Document, Editor, EditorCommand, Inline, ListItem, Position, Selection};
+
+fn heading(level: u8, text: &str) -> Block {
+    Block::Heading { id: uuid::Uuid::new_v4(), level, content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false }
+}
+
+fn paragraph(text: &str) -> Block {
+    Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false }
+}
+
+fn toc(editor: &Editor) -> Vec<ListItem> {
+    editor.doc.blocks.iter().find_map(|b| match b {
+        Block::List { toc: true, items, .. } => Some(items.clone()),
+        _ => None,
+    }).expect("a table of contents")
+}
+
+fn entry(item: &ListItem) -> (uuid::Uuid, String, u8) {
+    let Inline::Link { url, text } = &item.content[0] else { panic!("entry is not a link") };
+    let text = block_plain_text(&Block::Paragraph { id: item.id, content: text.clone(), dirty: false, locked: false });
+    (toc_target(url).expect("link to a block"), text, item.level)
+}
+
+#[test]
+fn refreshed_toc_links_to_new_and_renamed_headings() {
+    let mut doc = Document::new();
+    doc.blocks = vec![paragraph("前言"), heading(2, "概述"), heading(4, "细节"), heading(3, "背景")];
+    let ids: Vec<_> = doc.blocks.iter().map(Block::id).collect();
+    let mut editor = Editor::new(doc);
+    editor.execute(EditorCommand::InsertToc);
+    assert!(editor.doc.blocks[1].is_toc() && editor.doc.blocks[1].is_locked());
+    // Levels count from the shallowest heading, one step deeper at most.
+    let entries: Vec<_> = toc(&editor).iter().map(entry).collect();
+    assert_eq!(
+        entries,
+        [(ids[1], "概述".to_string(), 0), (ids[2], "细节".to_string(), 1), (ids[3], "背景".to_string(), 1)]
+    );
+    assert!(!editor.refresh_toc());
+
+    // A heading added behind the editor's back shows up on refresh.
+    let added = heading(2, "结论");
+    let added_id = added.id();
+    editor.doc.blocks.push(added);
+    assert!(editor.refresh_toc());
+    assert_eq!(entry(toc(&editor).last().unwrap()), (added_id, "结论".to_string(), 0));
+
+    // Renaming through commands keeps it current without a refresh, and
+    // undo takes the entry back with the heading.
+    editor.selection = Selection::collapsed(Position { block_id: added_id, offset: 2 });
+    editor.execute(EditorCommand::InsertText("与展望".to_string()));
+    assert_eq!(entry(toc(&editor).last().unwrap()).1, "结论与展望");
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(entry(toc(&editor).last().unwrap()).1, "结论");
+}
@@ -8,6 +8,7 @@ fn main() {
         id: uuid::Uuid::new_v4(),
         content: vec![Inline::Text { value: Arc::from("smoke test") }],
         dirty: true,
+        locked: false,
     });
     let mut engine = LayoutEngine::new();
     let config = LayoutConfig::default();
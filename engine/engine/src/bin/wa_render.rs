@@ -0,0 +1,45 @@
+﻿#[cfg(feature = "raster")]
+use std::path::PathBuf;
+
+#[cfg(feature = "raster")]
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: wa_render <input_path> <output_dir>");
+        std::process::exit(2);
+    }
+    let input = PathBuf::from(&args[1]);
+    let output = PathBuf::from(&args[2]);
+    let doc = match wa_core::import_any(&input) {
+        Ok(doc) => doc,
+        Err(err) => {
+            eprintln!("import failed: {}", err);
+            std::process::exit(err.exit_code());
+        }
+    };
+    if let Err(err) = std::fs::create_dir_all(&output) {
+        eprintln!("cannot create {}: {}", output.display(), err);
+        std::process::exit(wa_core::Error::from(err).exit_code());
+    }
+    let config = wa_engine::LayoutConfig::default();
+    let context = wa_engine::FontContext::global();
+    let tree = wa_engine::LayoutEngine::with_context(context).layout(&doc, &config);
+    for index in 0..tree.pages.len() {
+        let Some(page) = wa_engine::render_page(&tree, &config, index, context) else {
+            continue;
+        };
+        let path = output.join(format!("page-{:03}.png", index + 1));
+        if let Err(err) = page.save_with_format(&path, image::ImageFormat::Png) {
+            eprintln!("write failed for {}: {}", path.display(), err);
+            let err = wa_core::Error::Export { format: "png".to_string(), detail: err.to_string() };
+            std::process::exit(err.exit_code());
+        }
+    }
+    println!("{} page(s) written to {}", tree.pages.len(), output.display());
+}
+
+#[cfg(not(feature = "raster"))]
+fn main() {
+    eprintln!("wa_render requires the `raster` feature");
+    std::process::exit(1);
+}
@@ -101,7 +101,7 @@ pub fn placeholder_block(kind: LayoutKind) -> Arc<LayoutBlock> {
     Arc::new(LayoutBlock {
         block_id: Uuid::nil(),
         kind,
-        lines: vec![Line { text: String::new(), width: 0.0 }],
+        lines: vec![Line { text: String::new(), width: 0.0, start: 0, runs: Vec::new(), number: None }],
         height: 0.0,
         meta: None,
     })
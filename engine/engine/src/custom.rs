@@ -0,0 +1,68 @@
+use crate::LayoutConfig;
+use std::collections::HashMap;
+use std::sync::Arc;
+use wa_core::SharedStr;
+
+/// Height, in lines, of custom blocks whose kind has no handler.
+pub const PLACEHOLDER_LINES: usize = 3;
+
+/// Measures `Block::Custom` blocks of one kind. The host app draws them;
+/// layout only needs to know how much room they take.
+pub trait BlockHandler: Send + Sync {
+    /// Height of the block and the text lines shown in it.
+    fn measure(&self, data: &serde_json::Value, config: &LayoutConfig) -> (f32, Vec<String>);
+}
+
+/// Block handlers by custom kind. Cloning shares the handlers.
+#[derive(Clone, Default)]
+pub struct BlockHandlers(Arc<HashMap<SharedStr, Arc<dyn BlockHandler>>>);
+
+impl BlockHandlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The handlers shipped with the engine: `callout`.
+    pub fn builtin() -> Self {
+        let mut handlers = Self::new();
+        handlers.register("callout", CalloutHandler);
+        handlers
+    }
+
+    pub fn register(&mut self, kind: &str, handler: impl BlockHandler + 'static) {
+        Arc::make_mut(&mut self.0).insert(SharedStr::from(kind), Arc::new(handler));
+    }
+
+    pub fn get(&self, kind: &str) -> Option<&dyn BlockHandler> {
+        self.0.get(kind).map(|h| h.as_ref())
+    }
+
+    /// What the handler for `kind` reports, or a placeholder naming the kind.
+    pub fn measure(&self, kind: &str, data: &serde_json::Value, config: &LayoutConfig) -> (f32, Vec<String>) {
+        match self.get(kind) {
+            Some(handler) => handler.measure(data, config),
+            None => (PLACEHOLDER_LINES as f32 * line_height(config), vec![format!("[{kind}]")]),
+        }
+    }
+}
+
+/// A highlighted note: `{"title": "...", "text": "..."}`, both optional.
+/// One line for the title, one per line of text, and half a line of
+/// padding above and below.
+pub struct CalloutHandler;
+
+impl BlockHandler for CalloutHandler {
+    fn measure(&self, data: &serde_json::Value, config: &LayoutConfig) -> (f32, Vec<String>) {
+        let field = |name: &str| data.get(name).and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+        let mut lines: Vec<String> = field("title").into_iter().map(str::to_string).collect();
+        lines.extend(field("text").into_iter().flat_map(str::lines).map(str::to_string));
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        ((lines.len() + 1) as f32 * line_height(config), lines)
+    }
+}
+
+fn line_height(config: &LayoutConfig) -> f32 {
+    config.metrics.font_size * config.metrics.line_height
+}
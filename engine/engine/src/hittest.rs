@@ -1,48 +1,168 @@
-use wa_core::Position;
+use wa_core::{Affinity, Editor, Position};
 
-use crate::{LayoutConfig, LayoutTree, SharedMeasurer, RealMeasurer};
+use crate::{FontContext, Line, LayoutBlock, LayoutConfig, LayoutTree, SharedMeasurer, RealMeasurer, TextMeasurer};
 
 pub struct HitTester {
     measurer: SharedMeasurer,
 }
 
+impl Default for HitTester {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HitTester {
     pub fn new() -> Self {
-        Self { measurer: SharedMeasurer(std::sync::Arc::new(RealMeasurer::new())) }
+        Self::with_context(FontContext::global())
     }
 
+    pub fn with_context(context: &FontContext) -> Self {
+        Self { measurer: SharedMeasurer(std::sync::Arc::new(RealMeasurer::from_context(context))) }
+    }
+
+    /// The caret position under `x` and the document offset `y`, with pages
+    /// placed as `LayoutTree::page_top` puts them. Margins and the gaps
+    /// between blocks hit nothing.
     pub fn hit_test(&self, layout: &LayoutTree, config: &LayoutConfig, x: f32, y: f32, page_gap: f32) -> Option<Position> {
-        let mut page_top = 0.0;
-        for page in &layout.pages {
-            let page_bottom = page_top + config.page_height;
-            if y >= page_top && y <= page_bottom {
-                let mut cursor_y = page_top + config.margin;
-                for block in &page.blocks {
-                    for line in &block.lines {
-                        let line_height = config.metrics.font_size * config.metrics.line_height;
-                        let line_top = cursor_y;
-                        let line_bottom = cursor_y + line_height;
-                        if y >= line_top && y <= line_bottom {
-                            let mut acc = 0.0;
-                            let mut offset = 0usize;
-                            let mut buf = [0u8; 4];
-                            for ch in line.text.chars() {
-                                let w = self.measurer.0.measure(ch.encode_utf8(&mut buf), config.metrics);
-                                if (config.margin + acc + w) >= x {
-                                    break;
-                                }
-                                acc += w;
-                                offset += 1;
-                            }
-                            return Some(Position { block_id: block.block_id, offset });
-                        }
-                        cursor_y += line_height;
-                    }
-                    cursor_y += config.metrics.font_size * 0.5;
+        let line_height = config.metrics.font_size * config.metrics.line_height;
+        let mut hit = None;
+        layout.visit_block_tops(config, page_gap, &mut |block, top| {
+            if y < top {
+                return true;
+            }
+            if y > top + block.height {
+                return false;
+            }
+            let Some(line) = block.lines.get(((y - top) / line_height) as usize).or(block.lines.last()) else {
+                hit = Some(Position { block_id: block.block_id, offset: 0 });
+                return true;
+            };
+            let mut acc = 0.0;
+            let mut offset = 0usize;
+            let mut buf = [0u8; 4];
+            for ch in line.text.chars() {
+                let w = self.measurer.0.measure(ch.encode_utf8(&mut buf), config.metrics);
+                if (config.margin + acc + w) >= x {
+                    break;
                 }
+                acc += w;
+                offset += 1;
+            }
+            hit = Some(Position { block_id: block.block_id, offset: line.start + offset });
+            true
+        });
+        hit
+    }
+}
+
+/// Caret placement relative to the top-left corner of a laid-out block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaretGeometry {
+    pub line: usize,
+    pub x: f32,
+    pub y: f32,
+    pub height: f32,
+}
+
+pub fn caret_geometry(block: &LayoutBlock, offset: usize, config: &LayoutConfig, measurer: &dyn TextMeasurer) -> CaretGeometry {
+    caret_geometry_with_affinity(block, offset, Affinity::Downstream, config, measurer)
+}
+
+/// `caret_geometry` for a caret that may sit at a wrap point, where
+/// `affinity` picks the line it draws on.
+pub fn caret_geometry_with_affinity(
+    block: &LayoutBlock,
+    offset: usize,
+    affinity: Affinity,
+    config: &LayoutConfig,
+    measurer: &dyn TextMeasurer,
+) -> CaretGeometry {
+    let line_height = config.metrics.font_size * config.metrics.line_height;
+    let line_idx = caret_line(block, offset, affinity);
+    let x = block.lines.get(line_idx).map_or(0.0, |line| line_x(line, offset, config, measurer));
+    CaretGeometry {
+        line: line_idx,
+        x,
+        y: line_idx as f32 * line_height,
+        height: line_height,
+    }
+}
+
+/// The line of `block` a caret at `offset` is on.
+fn caret_line(block: &LayoutBlock, offset: usize, affinity: Affinity) -> usize {
+    // Line starts only increase within one wrapped text; a drop marks the
+    // next list item or table row, where the caret stays on the earlier line.
+    (0..block.lines.len())
+        .find(|&i| match block.lines.get(i + 1) {
+            Some(next) => {
+                let start = block.lines[i].start;
+                next.start > offset
+                    || next.start <= start
+                    || (affinity == Affinity::Upstream && next.start == offset && offset > start)
             }
-            page_top = page_bottom + page_gap;
+            None => true,
+        })
+        .unwrap_or(0)
+}
+
+fn line_x(line: &Line, offset: usize, config: &LayoutConfig, measurer: &dyn TextMeasurer) -> f32 {
+    let chars = offset - line.start.min(offset);
+    let end = line.text.char_indices().nth(chars).map(|(i, _)| i).unwrap_or(line.text.len());
+    measurer.measure(&line.text[..end], config.metrics)
+}
+
+/// Moves the caret to the line above or below it in `layout`, keeping to
+/// the editor's goal x (or the caret's own x when it has none) and landing
+/// on the nearest character boundary. On the first or last line it goes to
+/// that line's start or end. Returns false when the caret is not in the
+/// layout.
+pub fn move_caret_vertically(
+    editor: &mut Editor,
+    layout: &LayoutTree,
+    config: &LayoutConfig,
+    measurer: &dyn TextMeasurer,
+    down: bool,
+    extend: bool,
+) -> bool {
+    let focus = editor.selection.focus;
+    let lines: Vec<(&LayoutBlock, usize)> = layout
+        .pages
+        .iter()
+        .flat_map(|page| page.blocks.iter())
+        .flat_map(|block| (0..block.lines.len()).map(move |i| (&**block, i)))
+        .collect();
+    let Some(block) = lines.iter().map(|(block, _)| *block).find(|block| block.block_id == focus.block_id) else {
+        return false;
+    };
+    let line = caret_line(block, focus.offset, editor.caret_affinity());
+    let Some(at) = lines.iter().position(|(b, i)| b.block_id == block.block_id && *i == line) else {
+        return false;
+    };
+    let goal = editor.goal_x().unwrap_or_else(|| line_x(&block.lines[line], focus.offset, config, measurer));
+    let target = if down { lines.get(at + 1) } else { at.checked_sub(1).and_then(|i| lines.get(i)) };
+    let (block, line, chars) = match target {
+        Some(&(block, line)) => (block, line, nearest_boundary(&block.lines[line], goal, config, measurer)),
+        None if down => (block, line, block.lines[line].text.chars().count()),
+        None => (block, line, 0),
+    };
+    let offset = block.lines[line].start + chars;
+    // The end of a wrapped line is also the start of the next one.
+    let wraps_here = block.lines.get(line + 1).is_some_and(|next| next.start == offset && next.start > block.lines[line].start);
+    let affinity = if wraps_here { Affinity::Upstream } else { Affinity::Downstream };
+    editor.place_caret(Position { block_id: block.block_id, offset }, affinity, Some(goal), extend);
+    true
+}
+
+/// Chars of `line` before the boundary closest to `x`.
+fn nearest_boundary(line: &Line, x: f32, config: &LayoutConfig, measurer: &dyn TextMeasurer) -> usize {
+    let mut prev = 0.0;
+    for (chars, (byte, ch)) in line.text.char_indices().enumerate() {
+        let next = measurer.measure(&line.text[..byte + ch.len_utf8()], config.metrics);
+        if x < (prev + next) / 2.0 {
+            return chars;
         }
-        None
+        prev = next;
     }
+    line.text.chars().count()
 }
@@ -1,5 +1,25 @@
-﻿use std::collections::HashMap;
+use std::collections::HashMap;
+use std::io::Cursor;
 use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use base64::Engine as _;
+use image::ImageEncoder as _;
+
+const PLACEHOLDER_WIDTH: f32 = 320.0;
+const PLACEHOLDER_HEIGHT: f32 = 180.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImageError {
+    #[error("unsupported image source: {0}")]
+    UnsupportedSource(String),
+    #[error("invalid data uri: {0}")]
+    DataUri(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("decode error: {0}")]
+    Decode(#[from] image::ImageError),
+}
 
 #[derive(Debug, Clone)]
 pub struct ImageAsset {
@@ -10,41 +30,280 @@ pub struct ImageAsset {
     pub display_height: f32,
 }
 
-#[derive(Debug, Default, Clone)]
+impl ImageAsset {
+    fn sized(key: &str, width: f32, height: f32) -> Self {
+        Self { key: key.to_string(), width, height, display_width: width, display_height: height }
+    }
+}
+
+/// Decoded pixels, straight (non-premultiplied) RGBA8.
+#[derive(Debug, Clone)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Arc<Vec<u8>>,
+}
+
+/// Decoded pixels kept by a new `ImageCache`, in bytes.
+pub const DEFAULT_IMAGE_BUDGET: usize = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+struct CachedImage {
+    asset: ImageAsset,
+    /// Bytes given to `insert_bytes`, kept to decode again after eviction.
+    source: Option<Arc<[u8]>>,
+    pixels: Option<DecodedImage>,
+    last_used: u64,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    entries: HashMap<String, CachedImage>,
+    budget: usize,
+    resident: usize,
+    clock: u64,
+}
+
+impl CacheState {
+    fn keep_pixels(&mut self, key: &str, pixels: DecodedImage) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(key) {
+            let size = pixels.rgba.len();
+            if let Some(old) = entry.pixels.replace(pixels) {
+                self.resident -= old.rgba.len();
+            }
+            entry.last_used = clock;
+            self.resident += size;
+        }
+        self.shrink();
+    }
+
+    /// Drops the least recently used pixels until they fit the budget.
+    fn shrink(&mut self) {
+        while self.resident > self.budget {
+            let Some(key) = self
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.pixels.is_some())
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.evict(&key);
+        }
+    }
+
+    fn evict(&mut self, key: &str) -> bool {
+        match self.entries.get_mut(key).and_then(|entry| entry.pixels.take()) {
+            Some(pixels) => {
+                self.resident -= pixels.rgba.len();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Image sizes by key, and the decoded pixels of the most recently used ones
+/// up to a byte budget. Evicting pixels keeps the size, so layout does not
+/// change. Clones share the same cache.
+#[derive(Debug, Clone)]
 pub struct ImageCache {
-    entries: HashMap<String, ImageAsset>,
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl Default for ImageCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ImageCache {
     pub fn new() -> Self {
-        Self { entries: HashMap::new() }
+        Self::with_budget(DEFAULT_IMAGE_BUDGET)
+    }
+
+    pub fn with_budget(budget: usize) -> Self {
+        let state = CacheState { entries: HashMap::new(), budget, resident: 0, clock: 0 };
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn budget(&self) -> usize {
+        self.state().budget
     }
 
-    pub fn load(&mut self, key: &str) -> ImageAsset {
-        if let Some(asset) = self.entries.get(key) {
-            return asset.clone();
+    /// Evicts pixels right away if they no longer fit.
+    pub fn set_budget(&self, budget: usize) {
+        let mut state = self.state();
+        state.budget = budget;
+        state.shrink();
+    }
+
+    /// Bytes of decoded pixels currently held.
+    pub fn resident_bytes(&self) -> usize {
+        self.state().resident
+    }
+
+    /// Returns the asset for `key`, reading only the image header to find the
+    /// intrinsic size. Sources that cannot be read get a placeholder size.
+    pub fn load(&self, key: &str) -> ImageAsset {
+        if let Some(asset) = self.get(key) {
+            return asset;
         }
-        let asset = ImageAsset {
-            key: key.to_string(),
-            width: 320.0,
-            height: 180.0,
-            display_width: 320.0,
-            display_height: 180.0,
-        };
-        self.entries.insert(key.to_string(), asset.clone());
-        asset
+        let (width, height) = probe_image_size(key)
+            .map(|(w, h)| (w as f32, h as f32))
+            .unwrap_or((PLACEHOLDER_WIDTH, PLACEHOLDER_HEIGHT));
+        let entry = CachedImage { asset: ImageAsset::sized(key, width, height), source: None, pixels: None, last_used: 0 };
+        self.state().entries.entry(key.to_string()).or_insert(entry).asset.clone()
     }
 
-    pub fn load_from_path(&mut self, path: &Path) -> ImageAsset {
+    pub fn load_from_path(&self, path: &Path) -> ImageAsset {
         let key = path.to_string_lossy().to_string();
         self.load(&key)
     }
 
-    pub fn resize(&mut self, key: &str, width: f32, height: f32) {
-        if let Some(asset) = self.entries.get_mut(key) {
-            asset.display_width = width.max(1.0);
-            asset.display_height = height.max(1.0);
+    /// Decodes `bytes` as the image for `key`, replacing what was there, so
+    /// figures whose url is `key` lay out at its size. Figures already laid
+    /// out keep their old size until marked dirty.
+    pub fn insert_bytes(&self, key: &str, bytes: &[u8]) -> Result<ImageAsset, ImageError> {
+        let pixels = decode_bytes(bytes.to_vec())?;
+        let asset = ImageAsset::sized(key, pixels.width as f32, pixels.height as f32);
+        let mut state = self.state();
+        state.evict(key);
+        let entry = CachedImage { asset: asset.clone(), source: Some(Arc::from(bytes)), pixels: None, last_used: 0 };
+        state.entries.insert(key.to_string(), entry);
+        state.keep_pixels(key, pixels);
+        Ok(asset)
+    }
+
+    /// The asset for `key` if it has been loaded or inserted.
+    pub fn get(&self, key: &str) -> Option<ImageAsset> {
+        self.state().entries.get(key).map(|entry| entry.asset.clone())
+    }
+
+    /// Whether the decoded pixels for `key` are held.
+    pub fn is_resident(&self, key: &str) -> bool {
+        self.state().entries.get(key).is_some_and(|entry| entry.pixels.is_some())
+    }
+
+    /// Pixels for `key`: the held ones, or else decoded from the inserted
+    /// bytes or the url and then held.
+    pub fn pixels(&self, key: &str) -> Result<DecodedImage, ImageError> {
+        let source = {
+            let mut state = self.state();
+            state.clock += 1;
+            let clock = state.clock;
+            match state.entries.get_mut(key) {
+                Some(entry) => {
+                    if let Some(pixels) = &entry.pixels {
+                        entry.last_used = clock;
+                        return Ok(pixels.clone());
+                    }
+                    entry.source.clone()
+                }
+                None => None,
+            }
+        };
+        // Decode without holding the lock; layout may be waiting on it.
+        let pixels = match source {
+            Some(bytes) => decode_bytes(bytes.to_vec())?,
+            None => decode_image(key)?,
+        };
+        let mut state = self.state();
+        state.entries.entry(key.to_string()).or_insert_with(|| CachedImage {
+            asset: ImageAsset::sized(key, pixels.width as f32, pixels.height as f32),
+            source: None,
+            pixels: None,
+            last_used: 0,
+        });
+        state.keep_pixels(key, pixels.clone());
+        Ok(pixels)
+    }
+
+    /// Drops the decoded pixels for `key`, keeping its size. Returns whether
+    /// there were any.
+    pub fn evict(&self, key: &str) -> bool {
+        self.state().evict(key)
+    }
+
+    pub fn resize(&self, key: &str, width: f32, height: f32) {
+        if let Some(entry) = self.state().entries.get_mut(key) {
+            entry.asset.display_width = width.max(1.0);
+            entry.asset.display_height = height.max(1.0);
         }
     }
 }
 
+/// Reads the raw bytes behind a figure url: `data:` URIs (base64) and local
+/// paths, with or without a `file://` prefix.
+pub fn image_bytes(url: &str) -> Result<Vec<u8>, ImageError> {
+    if let Some(rest) = url.strip_prefix("data:") {
+        let (meta, payload) = rest
+            .split_once(',')
+            .ok_or_else(|| ImageError::DataUri("missing ','".to_string()))?;
+        if !meta.ends_with(";base64") {
+            return Err(ImageError::DataUri("only base64 payloads are supported".to_string()));
+        }
+        return base64::engine::general_purpose::STANDARD
+            .decode(payload.trim())
+            .map_err(|e| ImageError::DataUri(e.to_string()));
+    }
+    let path = url.strip_prefix("file://").unwrap_or(url);
+    if is_remote_or_virtual(path) {
+        return Err(ImageError::UnsupportedSource(url.to_string()));
+    }
+    Ok(std::fs::read(path)?)
+}
+
+pub fn decode_image(url: &str) -> Result<DecodedImage, ImageError> {
+    decode_bytes(image_bytes(url)?)
+}
+
+fn decode_bytes(bytes: Vec<u8>) -> Result<DecodedImage, ImageError> {
+    let img = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .decode()?
+        .into_rgba8();
+    Ok(DecodedImage {
+        width: img.width(),
+        height: img.height(),
+        rgba: Arc::new(img.into_raw()),
+    })
+}
+
+/// Encodes straight RGBA8 pixels (e.g. a clipboard image) as PNG.
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, ImageError> {
+    let mut out = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut out).write_image(rgba, width, height, image::ExtendedColorType::Rgba8)?;
+    Ok(out)
+}
+
+fn probe_image_size(url: &str) -> Result<(u32, u32), ImageError> {
+    if url.starts_with("data:") {
+        let bytes = image_bytes(url)?;
+        return Ok(image::ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()?
+            .into_dimensions()?);
+    }
+    let path = url.strip_prefix("file://").unwrap_or(url);
+    if is_remote_or_virtual(path) {
+        return Err(ImageError::UnsupportedSource(url.to_string()));
+    }
+    Ok(image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_dimensions()?)
+}
+
+fn is_remote_or_virtual(path: &str) -> bool {
+    // "C:\..." is a Windows path, not a scheme.
+    match path.split_once("://") {
+        Some((scheme, _)) => scheme.len() > 1,
+        None => false,
+    }
+}
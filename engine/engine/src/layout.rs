@@ -1,5 +1,5 @@
-﻿use crate::{FontMetrics, LineBreaker, SharedMeasurer, RealMeasurer, ImageCache, LayoutCache, FontdueMeasurer};
-use wa_core::{Block, Inline, Document};
+﻿use crate::{BlockHandlers, CodeTheme, EngineOptions, GlyphStats, LayoutStats, Timer, FontContext, FontMetrics, LineBreaker, MathMeasurer, SharedMeasurer, RealMeasurer, ImageCache, LayoutCache, SyntaxHighlighter, TextMeasurer};
+use wa_core::{hash_block, hash_inlines, hash_inlines_value, Block, BlockSignatures, Inline, Document, ListItem, SharedStr, Style};
 use uuid::Uuid;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
@@ -16,6 +16,15 @@ pub struct LayoutConfig {
     pub margin: f32,
     pub metrics: FontMetrics,
     pub paged: bool,
+    /// Display scale already folded into the sizes above; figures use it to
+    /// scale their document-space size.
+    pub zoom: f32,
+    /// Distinct characters warmed into the glyph cache per layout call.
+    pub prewarm_limit: usize,
+    /// Number every line, see `Line::number`.
+    pub line_numbers: bool,
+    /// Full-width spaces a nested list item is pushed right by, per level.
+    pub list_indent: usize,
 }
 
 impl Default for LayoutConfig {
@@ -26,15 +35,77 @@ impl Default for LayoutConfig {
             margin: 64.0,
             metrics: FontMetrics::default(),
             paged: true,
+            zoom: 1.0,
+            prewarm_limit: 512,
+            line_numbers: false,
+            list_indent: 2,
         }
     }
 }
 
+impl LayoutConfig {
+    /// The same layout drawn `zoom` times larger. Page, margin and font sizes
+    /// scale together, so line breaks stay where they were at 100%.
+    pub fn zoomed(self, zoom: f32) -> Self {
+        let factor = zoom / self.zoom;
+        Self {
+            page_width: self.page_width * factor,
+            page_height: self.page_height * factor,
+            margin: self.margin * factor,
+            metrics: FontMetrics {
+                font_size: self.metrics.font_size * factor,
+                ..self.metrics
+            },
+            zoom,
+            ..self
+        }
+    }
+
+    /// Space left between consecutive blocks when drawn.
+    pub fn block_gap(&self) -> f32 {
+        self.metrics.font_size * 0.5
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LayoutTree {
     pub pages: Vec<Page>,
 }
 
+impl LayoutTree {
+    /// Index of the page holding `block_id`.
+    pub fn page_of(&self, block_id: Uuid) -> Option<usize> {
+        self.pages
+            .iter()
+            .position(|page| page.blocks.iter().any(|b| b.block_id == block_id))
+    }
+
+    /// Scroll offset of the top of page `index`, with pages drawn
+    /// `page_gap` apart.
+    pub fn page_top(&self, index: usize, config: &LayoutConfig, page_gap: f32) -> f32 {
+        if config.paged {
+            return index as f32 * (config.page_height + page_gap);
+        }
+        self.pages.iter().take(index).map(|page| page.extent(config) + page_gap).sum()
+    }
+
+    /// The page drawn at scroll offset `y`, clamped to the pages there are.
+    pub fn page_at_offset(&self, y: f32, config: &LayoutConfig, page_gap: f32) -> usize {
+        let last = self.pages.len().saturating_sub(1);
+        if config.paged {
+            return ((y.max(0.0) / (config.page_height + page_gap)) as usize).min(last);
+        }
+        let mut top = 0.0;
+        for (idx, page) in self.pages.iter().enumerate() {
+            top += page.extent(config) + page_gap;
+            if y < top {
+                return idx;
+            }
+        }
+        last
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Page {
     pub number: usize,
@@ -42,7 +113,18 @@ pub struct Page {
     pub height: f32,
 }
 
-#[derive(Debug, Clone)]
+impl Page {
+    /// Height the page is drawn at: the page height when paged, otherwise
+    /// its blocks, the gaps between them and the margins.
+    pub fn extent(&self, config: &LayoutConfig) -> f32 {
+        if config.paged {
+            return config.page_height;
+        }
+        self.height + self.blocks.len().saturating_sub(1) as f32 * config.block_gap() + config.margin * 2.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LayoutBlock {
     pub block_id: Uuid,
     pub kind: LayoutKind,
@@ -51,7 +133,7 @@ pub struct LayoutBlock {
     pub meta: Option<BlockMeta>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LayoutKind {
     Heading(u8),
     Paragraph,
@@ -60,119 +142,235 @@ pub enum LayoutKind {
     Code,
     Table,
     Figure,
+    /// A `Block::PageBreak`: no lines and no height.
+    PageBreak,
+    /// A `Block::Custom` of this kind.
+    Custom(SharedStr),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BlockMeta {
     pub width: f32,
     pub height: f32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     pub text: String,
     pub width: f32,
+    /// Char offset of the line's first character within the text it was
+    /// wrapped from (the block text for headings and paragraphs).
+    pub start: usize,
+    /// Styled pieces covering `text` end to end; empty when the line is plain.
+    pub runs: Vec<TextRun>,
+    /// Running number of the line in the document, for a gutter. Only set
+    /// with `LayoutConfig::line_numbers`.
+    pub number: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextRun {
+    /// Byte range into `Line::text`.
+    pub range: std::ops::Range<usize>,
+    pub style: Style,
+    pub link: Option<SharedStr>,
+    pub code: bool,
+    /// Syntax highlight color (RGB) for code block runs.
+    pub color: Option<[u8; 3]>,
+    pub width: f32,
+}
+
+#[derive(Debug, Clone)]
+struct InlineSpan {
+    range: std::ops::Range<usize>,
+    style: Style,
+    link: Option<SharedStr>,
+    code: bool,
+    math: bool,
+}
+
+/// What the wrapped lines of a block are measured with.
+#[derive(Clone, Copy)]
+struct Measurers<'a> {
+    text: &'a dyn TextMeasurer,
+    math: &'a dyn MathMeasurer,
 }
 
 pub struct LayoutEngine {
     breaker: LineBreaker,
     measurer: SharedMeasurer,
+    math: std::sync::Arc<dyn MathMeasurer>,
     real: RealMeasurer,
     images: ImageCache,
+    highlighter: SyntaxHighlighter,
+    handlers: BlockHandlers,
     break_buf: Vec<usize>,
     scratch: String,
     last_prewarm_version: u64,
+    prewarm: PrewarmState,
     break_cache_long: LruCache<BreakKey, Vec<usize>>,
     break_cache_short: HashMap<BreakKey, Vec<usize>>,
-    break_cache_hits: u64,
-    break_cache_misses: u64,
+    stats: LayoutStats,
+    /// Glyph cache counters at the last `take_stats`.
+    glyph_base: GlyphStats,
+    collect_timings: bool,
+    options: EngineOptions,
+    break_cache_short_cap: usize,
+}
+
+impl Default for LayoutEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LayoutEngine {
+    /// An engine set up from the environment, see `EngineOptions::from_env`.
     pub fn new() -> Self {
-        let real = RealMeasurer::new();
-        let low_spec = std::env::var("WA_LOW_SPEC").ok().as_deref() == Some("1");
-        let short_cap = if low_spec { 1024 } else { 4096 };
-        let long_cap = if low_spec { 256 } else { 512 };
-        Self {
-            breaker: LineBreaker,
-            measurer: SharedMeasurer(std::sync::Arc::new(real.clone())),
-            real,
-            images: ImageCache::new(),
-            break_buf: Vec::new(),
-            scratch: String::new(),
-            last_prewarm_version: 0,
-            break_cache_long: LruCache::new(NonZeroUsize::new(long_cap).unwrap()),
-            break_cache_short: HashMap::with_capacity(short_cap),
-            break_cache_hits: 0,
-            break_cache_misses: 0,
-        }
+        Self::with_options(EngineOptions::from_env())
+    }
+
+    pub fn with_options(options: EngineOptions) -> Self {
+        Self::with_context_and_options(&FontContext::for_options(&options), options)
     }
 
     pub fn with_font(font: fontdue::Font) -> Self {
-        let real = RealMeasurer::Fontdue(FontdueMeasurer::new(font, 8192));
-        let low_spec = std::env::var("WA_LOW_SPEC").ok().as_deref() == Some("1");
-        let short_cap = if low_spec { 1024 } else { 4096 };
-        let long_cap = if low_spec { 256 } else { 512 };
+        let options = EngineOptions::from_env();
+        Self::with_context_and_options(&FontContext::new(font, options.glyph_cache_capacity()), options)
+    }
+
+    /// A layout engine measuring with `context`'s font and glyph cache.
+    pub fn with_context(context: &FontContext) -> Self {
+        Self::with_context_and_options(context, EngineOptions::from_env())
+    }
+
+    /// `options.font` is ignored; the font comes from `context`.
+    pub fn with_context_and_options(context: &FontContext, options: EngineOptions) -> Self {
+        let real = RealMeasurer::from_context(context);
+        let (short_cap, long_cap) = options.break_cache_capacities();
+        let glyph_base = real.glyph_stats().unwrap_or(GlyphStats { hits: 0, misses: 0 });
         Self {
             breaker: LineBreaker,
             measurer: SharedMeasurer(std::sync::Arc::new(real.clone())),
+            math: std::sync::Arc::new(crate::TexSourceMeasurer),
             real,
             images: ImageCache::new(),
+            highlighter: SyntaxHighlighter::default(),
+            handlers: BlockHandlers::builtin(),
             break_buf: Vec::new(),
             scratch: String::new(),
             last_prewarm_version: 0,
+            prewarm: PrewarmState::default(),
             break_cache_long: LruCache::new(NonZeroUsize::new(long_cap).unwrap()),
             break_cache_short: HashMap::with_capacity(short_cap),
-            break_cache_hits: 0,
-            break_cache_misses: 0,
+            glyph_base,
+            stats: LayoutStats::default(),
+            collect_timings: options.diagnostics,
+            options,
+            break_cache_short_cap: short_cap,
         }
     }
 
+    /// Returns true when the theme changed; cached code blocks are then stale.
+    pub fn set_code_theme(&mut self, theme: CodeTheme) -> bool {
+        let changed = self.highlighter.theme() != theme;
+        self.highlighter.set_theme(theme);
+        changed
+    }
+
+    /// Lays out custom blocks of `kind` with `handler`. Cached layouts of
+    /// such blocks are not refreshed; clear the cache after registering.
+    pub fn register_block_handler(&mut self, kind: &str, handler: impl crate::BlockHandler + 'static) {
+        self.handlers.register(kind, handler);
+    }
+
+    pub fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+
+    /// Takes wrap, measure and paginate timings from now on (see
+    /// `LayoutStats`). Off unless `EngineOptions::diagnostics` is set.
+    pub fn set_collect_timings(&mut self, enabled: bool) {
+        self.collect_timings = enabled;
+    }
+
+    /// What the engine measures text with, for placing carets in its
+    /// layouts.
+    pub fn measurer(&self) -> &dyn TextMeasurer {
+        &*self.measurer.0
+    }
+
+    /// Sizes inline math with `math` instead of `TexSourceMeasurer`. Cached
+    /// layouts are not refreshed; clear the cache after setting it.
+    pub fn set_math_measurer(&mut self, math: impl MathMeasurer + 'static) {
+        self.math = std::sync::Arc::new(math);
+    }
+
+    /// The cache figures get their size from. Clones share it, so pixels
+    /// decoded for drawing count against the same budget.
+    pub fn images(&self) -> &ImageCache {
+        &self.images
+    }
+
+    pub fn set_images(&mut self, images: ImageCache) {
+        self.images = images;
+    }
+
+    /// Stats gathered since the last call, resetting them.
+    pub fn take_stats(&mut self) -> LayoutStats {
+        let mut stats = std::mem::take(&mut self.stats);
+        if let Some(glyphs) = self.real.glyph_stats() {
+            stats.glyph_hits = glyphs.hits.saturating_sub(self.glyph_base.hits);
+            stats.glyph_misses = glyphs.misses.saturating_sub(self.glyph_base.misses);
+            self.glyph_base = glyphs;
+        }
+        stats
+    }
+
     pub fn layout(&mut self, doc: &Document, config: &LayoutConfig) -> LayoutTree {
-        self.prewarm_if_needed(doc, config.metrics);
+        let call = self.begin_call(doc);
+        let tree = self.layout_uncounted(doc, config);
+        self.end_call(call, &tree);
+        tree
+    }
+
+    fn layout_uncounted(&mut self, doc: &Document, config: &LayoutConfig) -> LayoutTree {
+        self.prewarm_if_needed(doc, config);
         #[cfg(feature = "parallel")]
         {
-            if std::env::var("WA_LAYOUT_PAR").ok().as_deref() == Some("1") && doc.blocks.len() > 512 {
-                return self.layout_parallel(doc, config);
+            if self.options.parallel && doc.blocks.len() > crate::PARALLEL_MIN_BLOCKS {
+                return self.layout_parallel_uncounted(doc, config);
             }
         }
-        let mut pages = Vec::new();
-        let mut current = Page {
-            number: 1,
-            blocks: Vec::new(),
-            height: 0.0,
-        };
-        let max_height = config.page_height - config.margin * 2.0;
+        let mut pages = Paginator::new(config);
         for block in &doc.blocks {
-            let lb = std::sync::Arc::new(self.layout_block(block, config));
-            let needed = lb.height;
-            if config.paged && current.height + needed > max_height && !current.blocks.is_empty() {
-                pages.push(current);
-                current = Page {
-                    number: pages.len() + 1,
-                    blocks: Vec::new(),
-                    height: 0.0,
-                };
-            }
-            current.height += needed;
-            current.blocks.push(lb);
+            pages.push(std::sync::Arc::new(self.layout_block(block, config)));
         }
-        pages.push(current);
-        self.maybe_log_stats();
-        LayoutTree { pages }
+        pages.finish()
     }
 
     #[cfg(feature = "parallel")]
-    pub fn layout_parallel(&self, doc: &Document, config: &LayoutConfig) -> LayoutTree {
+    pub fn layout_parallel(&mut self, doc: &Document, config: &LayoutConfig) -> LayoutTree {
+        let call = self.begin_call(doc);
+        let tree = self.layout_parallel_uncounted(doc, config);
+        self.end_call(call, &tree);
+        tree
+    }
+
+    #[cfg(feature = "parallel")]
+    fn layout_parallel_uncounted(&mut self, doc: &Document, config: &LayoutConfig) -> LayoutTree {
+        let timer = Timer::start(self.collect_timings);
+        self.stats.parallel_workers = rayon::current_num_threads();
+        self.stats.blocks_laid_out += doc.blocks.len() as u64;
         let blocks: Vec<std::sync::Arc<LayoutBlock>> = doc
             .blocks
             .par_iter()
             .map(|block| {
-                let mut worker = LayoutWorker::new(self.measurer.clone(), self.images.clone());
+                let mut worker = LayoutWorker::new(self.measurer.clone(), self.math.clone(), self.images.clone(), self.highlighter.clone(), self.handlers.clone());
                 std::sync::Arc::new(worker.layout_block(block, config))
             })
             .collect();
+        self.stats.measure += timer.elapsed();
         paginate_blocks(blocks, config)
     }
 
@@ -182,107 +380,123 @@ impl LayoutEngine {
         config: &LayoutConfig,
         cache: &mut LayoutCache,
     ) -> LayoutTree {
-        self.prewarm_if_needed(doc, config.metrics);
+        let call = self.begin_call(doc);
+        let tree = self.layout_cached_inner(doc, config, cache, None);
+        self.end_call(call, &tree);
+        tree
+    }
+
+    /// Like `layout_cached`, but dirty blocks take their signature from the
+    /// editor's `signatures` when those match the document version.
+    pub fn layout_cached_with_signatures(
+        &mut self,
+        doc: &Document,
+        config: &LayoutConfig,
+        cache: &mut LayoutCache,
+        signatures: &BlockSignatures,
+    ) -> LayoutTree {
+        let call = self.begin_call(doc);
+        let tree = self.layout_cached_inner(doc, config, cache, Some(signatures));
+        self.end_call(call, &tree);
+        tree
+    }
+
+    fn layout_cached_inner(
+        &mut self,
+        doc: &Document,
+        config: &LayoutConfig,
+        cache: &mut LayoutCache,
+        signatures: Option<&BlockSignatures>,
+    ) -> LayoutTree {
+        self.prewarm_if_needed(doc, config);
+        let signature = |block: &Block| signatures.and_then(|s| s.get(doc, block.id())).unwrap_or_else(|| hash_block(block));
         #[cfg(feature = "parallel")]
         {
-            if std::env::var("WA_LAYOUT_PAR").ok().as_deref() == Some("1") && doc.blocks.len() > 512 {
-                return self.layout_cached_parallel(doc, config, cache);
+            if self.options.parallel && doc.blocks.len() > crate::PARALLEL_MIN_BLOCKS {
+                return self.layout_cached_parallel(doc, config, cache, signature);
             }
         }
-        let mut pages = Vec::new();
-        let mut current = Page {
-            number: 1,
-            blocks: Vec::new(),
-            height: 0.0,
-        };
-        let max_height = config.page_height - config.margin * 2.0;
+        let mut pages = Paginator::new(config);
         for block in &doc.blocks {
-            let dirty = is_effectively_dirty(block);
-            let sig = hash_block(block);
-            let lb = if dirty {
-                if let Some(hit) = cache.get(block.id()) {
-                    if cache.signature(block.id()) == Some(sig) {
-                        hit.clone()
-                    } else {
-                        let fresh = std::sync::Arc::new(self.layout_block_with_pool(block, config, cache));
-                        cache.insert_with_sig(block.id(), fresh.clone(), sig);
-                        fresh
+            let lb = match clean_cache_hit(block, cache) {
+                Some(hit) => {
+                    self.stats.blocks_reused += 1;
+                    hit
+                }
+                None => {
+                    let sig = signature(block);
+                    match cache.get(block.id()) {
+                        Some(hit) if cache.signature(block.id()) == Some(sig) => {
+                            self.stats.blocks_reused += 1;
+                            hit.clone()
+                        }
+                        _ => {
+                            let fresh = std::sync::Arc::new(self.layout_block_with_pool(block, config, cache));
+                            cache.insert_with_sig(block.id(), fresh.clone(), sig);
+                            fresh
+                        }
                     }
-                } else {
-                    let fresh = std::sync::Arc::new(self.layout_block_with_pool(block, config, cache));
-                    cache.insert_with_sig(block.id(), fresh.clone(), sig);
-                    fresh
                 }
-            } else if let Some(hit) = cache.get(block.id()) {
-                hit.clone()
-            } else {
-                let fresh = std::sync::Arc::new(self.layout_block_with_pool(block, config, cache));
-                cache.insert_with_sig(block.id(), fresh.clone(), sig);
-                fresh
             };
-            let needed = lb.height;
-            if config.paged && current.height + needed > max_height && !current.blocks.is_empty() {
-                pages.push(current);
-                current = Page {
-                    number: pages.len() + 1,
-                    blocks: Vec::new(),
-                    height: 0.0,
-                };
-            }
-            current.height += needed;
-            current.blocks.push(lb);
+            pages.push(lb);
         }
-        pages.push(current);
-        self.maybe_log_stats();
-        LayoutTree { pages }
+        pages.finish()
     }
 
     #[cfg(feature = "parallel")]
-    fn layout_cached_parallel(&mut self, doc: &Document, config: &LayoutConfig, cache: &mut LayoutCache) -> LayoutTree {
+    fn layout_cached_parallel(
+        &mut self,
+        doc: &Document,
+        config: &LayoutConfig,
+        cache: &mut LayoutCache,
+        signature: impl Fn(&Block) -> u64,
+    ) -> LayoutTree {
         let mut reuse: Vec<Option<std::sync::Arc<LayoutBlock>>> = Vec::with_capacity(doc.blocks.len());
-        let mut sigs: Vec<u64> = Vec::with_capacity(doc.blocks.len());
+        // Signatures are only computed for blocks that may need a relayout.
+        let mut sigs: Vec<Option<u64>> = Vec::with_capacity(doc.blocks.len());
         let mut compute_idx: Vec<usize> = Vec::new();
         for (idx, block) in doc.blocks.iter().enumerate() {
-            let dirty = is_effectively_dirty(block);
-            let sig = hash_block(block);
-            sigs.push(sig);
-            let hit = cache.get(block.id()).cloned();
-            let reuse_hit = if dirty {
-                if let Some(h) = hit {
-                    if cache.signature(block.id()) == Some(sig) { Some(h) } else { None }
-                } else {
-                    None
+            if let Some(hit) = clean_cache_hit(block, cache) {
+                reuse.push(Some(hit));
+                sigs.push(None);
+                continue;
+            }
+            let sig = signature(block);
+            sigs.push(Some(sig));
+            match cache.get(block.id()) {
+                Some(hit) if cache.signature(block.id()) == Some(sig) => reuse.push(Some(hit.clone())),
+                _ => {
+                    reuse.push(None);
+                    compute_idx.push(idx);
                 }
-            } else {
-                hit
-            };
-            if reuse_hit.is_some() {
-                reuse.push(reuse_hit);
-            } else {
-                reuse.push(None);
-                compute_idx.push(idx);
             }
         }
 
+        self.stats.parallel_workers = rayon::current_num_threads();
+        self.stats.blocks_reused += (doc.blocks.len() - compute_idx.len()) as u64;
+        self.stats.blocks_laid_out += compute_idx.len() as u64;
+        let timer = Timer::start(self.collect_timings);
         let computed: HashMap<Uuid, std::sync::Arc<LayoutBlock>> = compute_idx
             .par_iter()
             .map(|idx| {
                 let block = &doc.blocks[*idx];
-                let mut worker = LayoutWorker::new(self.measurer.clone(), self.images.clone());
+                let mut worker = LayoutWorker::new(self.measurer.clone(), self.math.clone(), self.images.clone(), self.highlighter.clone(), self.handlers.clone());
                 let lb = worker.layout_block(block, config);
                 (block.id(), std::sync::Arc::new(lb))
             })
             .collect();
+        self.stats.measure += timer.elapsed();
 
         let mut blocks = Vec::with_capacity(doc.blocks.len());
         for (idx, block) in doc.blocks.iter().enumerate() {
-            let sig = sigs[idx];
             let lb = if let Some(hit) = reuse[idx].clone() {
                 hit
             } else if let Some(comp) = computed.get(&block.id()) {
+                let sig = sigs[idx].unwrap_or_else(|| signature(block));
                 cache.insert_with_sig(block.id(), comp.clone(), sig);
                 comp.clone()
             } else {
+                let sig = sigs[idx].unwrap_or_else(|| signature(block));
                 let fresh = std::sync::Arc::new(self.layout_block_with_pool(block, config, cache));
                 cache.insert_with_sig(block.id(), fresh.clone(), sig);
                 fresh
@@ -301,12 +515,21 @@ impl LayoutEngine {
     }
 
     fn layout_block_inner(&mut self, block: &Block, config: &LayoutConfig, cache: Option<&mut LayoutCache>) -> LayoutBlock {
+        let timer = Timer::start(self.collect_timings);
+        let wrap_before = self.stats.wrap;
+        let lb = self.layout_block_uncounted(block, config, cache);
+        self.stats.blocks_laid_out += 1;
+        self.stats.measure += timer.elapsed().saturating_sub(self.stats.wrap - wrap_before);
+        lb
+    }
+
+    fn layout_block_uncounted(&mut self, block: &Block, config: &LayoutConfig, cache: Option<&mut LayoutCache>) -> LayoutBlock {
         let mut cache = cache;
         let width = config.page_width - config.margin * 2.0;
         match block {
             Block::Heading { level, content, .. } => {
-                let text = join_inline(content);
-                let lines = self.wrap_text_with_pool(&text, width, config.metrics, cache.as_deref_mut());
+                let (text, spans) = join_inline_spans(content);
+                let lines = self.wrap_text_with_pool(&text, &spans, width, config.metrics, cache.as_deref_mut());
                 let height = lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
                 LayoutBlock {
                     block_id: block.id(),
@@ -317,8 +540,8 @@ impl LayoutEngine {
                 }
             }
             Block::Paragraph { content, .. } => {
-                let text = join_inline(content);
-                let lines = self.wrap_text_with_pool(&text, width, config.metrics, cache.as_deref_mut());
+                let (text, spans) = join_inline_spans(content);
+                let lines = self.wrap_text_with_pool(&text, &spans, width, config.metrics, cache.as_deref_mut());
                 let height = lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
                 LayoutBlock {
                     block_id: block.id(),
@@ -332,7 +555,7 @@ impl LayoutEngine {
                 let mut lines = self.alloc_lines(cache.as_deref_mut(), items.len().saturating_mul(2));
                 for (idx, item) in items.iter().enumerate() {
                     if let Some(cache) = cache.as_deref_mut() {
-                        let sig = hash_inlines_value(&item.content);
+                        let sig = list_item_sig(item);
                         if let Some(hit) = cache.get_list_item(block.id(), idx, sig) {
                             lines.extend(hit.iter().cloned());
                             continue;
@@ -342,12 +565,13 @@ impl LayoutEngine {
                     let item_len = inline_text_len(&item.content);
                     let digits = (idx + 1).to_string().len();
                     self.scratch.reserve(item_len + digits + 1);
-                    let _ = std::fmt::Write::write_fmt(&mut self.scratch, format_args!("{} ", idx + 1));
-                    join_inline_into(&mut self.scratch, &item.content);
+                    write_list_marker(&mut self.scratch, idx, item, config.list_indent);
+                    let mut spans = Vec::new();
+                    join_inline_spans_into(&mut self.scratch, &mut spans, &item.content, Style::default(), None);
                     let text = std::mem::take(&mut self.scratch);
-                    let wrapped = self.wrap_text_with_pool(&text, width, config.metrics, cache.as_deref_mut());
+                    let wrapped = self.wrap_text_with_pool(&text, &spans, width, config.metrics, cache.as_deref_mut());
                     if let Some(cache) = cache.as_deref_mut() {
-                        let sig = hash_inlines_value(&item.content);
+                        let sig = list_item_sig(item);
                         cache.put_list_item(block.id(), idx, sig, wrapped.clone());
                     }
                     lines.extend(wrapped);
@@ -374,9 +598,10 @@ impl LayoutEngine {
                             }
                         }
                         self.scratch.clear();
-                        join_inline_into(&mut self.scratch, content);
+                        let mut spans = Vec::new();
+                        join_inline_spans_into(&mut self.scratch, &mut spans, content, Style::default(), None);
                         let text = std::mem::take(&mut self.scratch);
-                        let wrapped = self.wrap_text_with_pool(&text, width, config.metrics, cache.as_deref_mut());
+                        let wrapped = self.wrap_text_with_pool(&text, &spans, width, config.metrics, cache.as_deref_mut());
                         if let Some(cache) = cache.as_deref_mut() {
                             let sig = hash_inlines_value(content);
                             cache.put_quote_item(block.id(), idx, sig, wrapped.clone());
@@ -394,14 +619,24 @@ impl LayoutEngine {
                     meta: None,
                 }
             }
-            Block::Code { code, .. } => {
-                let line_count = code.as_ref().bytes().filter(|b| *b == b'\n').count() + 1;
+            Block::Code { lang, code, .. } => {
+                let line_count = code.line_count();
                 let mut lines = self.alloc_lines(cache.as_deref_mut(), line_count);
-                for l in code.as_ref().lines() {
+                let mut highlighted = self
+                    .highlighter
+                    .highlight_lines(lang, code, &*self.measurer.0, config.metrics)
+                    .unwrap_or_default()
+                    .into_iter();
+                let mut start = 0usize;
+                for l in code.lines() {
                     lines.push(Line {
                         text: l.to_string(),
-                        width: self.measurer.0.measure(l, config.metrics),
+                        width: self.measurer.0.measure(&l, config.metrics),
+                        start,
+                        runs: highlighted.next().unwrap_or_default(),
+                        number: None,
                     });
+                    start += l.chars().count() + 1;
                 }
                 let height = lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
                 LayoutBlock {
@@ -430,15 +665,20 @@ impl LayoutEngine {
                         row_len += (row.len() - 1) * 3;
                     }
                     let mut row_text = String::with_capacity(row_len);
+                    let mut spans = Vec::new();
                     for (idx, cell) in row.iter().enumerate() {
                         if idx > 0 {
                             row_text.push_str(" | ");
                         }
-                        join_inline_into(&mut row_text, &cell.content);
+                        join_inline_spans_into(&mut row_text, &mut spans, &cell.content, Style::default(), None);
                     }
+                    let runs = line_runs(&row_text, 0, &spans, self.measurers(), config.metrics);
                     let row_line = Line {
                         text: row_text,
-                        width: width,
+                        width,
+                        start: 0,
+                        runs,
+                        number: None,
                     };
                     lines.push(row_line.clone());
                     if let Some(cache) = cache.as_deref_mut() {
@@ -458,13 +698,13 @@ impl LayoutEngine {
             Block::Figure { url, caption, size, .. } => {
                 let asset = self.images.load(url);
                 let (asset_w, asset_h) = if let Some(sz) = size {
-                    (sz.width.max(1.0), sz.height.max(1.0))
+                    (sz.width.max(1.0) * config.zoom, sz.height.max(1.0) * config.zoom)
                 } else {
-                    (asset.width, asset.height)
+                    fit_width(asset.width * config.zoom, asset.height * config.zoom, width)
                 };
                 let fig_height = asset_h;
                 let text = caption.as_ref().map(|c| c.as_ref()).unwrap_or("图片");
-                let lines = self.wrap_text_with_pool(&text, width, config.metrics, cache.as_deref_mut());
+                let lines = self.wrap_text_with_pool(text, &[], width, config.metrics, cache);
                 let height = fig_height + lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
                 LayoutBlock {
                     block_id: block.id(),
@@ -474,95 +714,31 @@ impl LayoutEngine {
                     meta: Some(BlockMeta { width: asset_w, height: asset_h }),
                 }
             }
+            Block::PageBreak { .. } => page_break_block(block.id()),
+            Block::Custom { kind, data, .. } => {
+                layout_custom(block.id(), kind, data, &self.handlers, &*self.measurer.0, config)
+            }
         }
     }
 
-    fn wrap_text_with_pool(&mut self, text: &str, width: f32, metrics: FontMetrics, cache: Option<&mut LayoutCache>) -> Vec<Line> {
+    fn wrap_text_with_pool(&mut self, text: &str, spans: &[InlineSpan], width: f32, metrics: FontMetrics, cache: Option<&mut LayoutCache>) -> Vec<Line> {
+        let timer = Timer::start(self.collect_timings);
+        let lines = self.wrap_text_uncounted(text, spans, width, metrics, cache);
+        self.stats.wrap += timer.elapsed();
+        lines
+    }
+
+    fn wrap_text_uncounted(&mut self, text: &str, spans: &[InlineSpan], width: f32, metrics: FontMetrics, cache: Option<&mut LayoutCache>) -> Vec<Line> {
         if text.is_empty() {
-            return vec![Line { text: String::new(), width: 0.0 }];
+            return vec![Line { text: String::new(), width: 0.0, start: 0, runs: Vec::new(), number: None }];
         }
         self.fill_break_buf(text, width, metrics.font_size);
-        let mut break_idx = 0usize;
-        let cap = self.break_buf.len().saturating_add(1);
-        let mut out = self.alloc_lines(cache, cap);
-        let break_positions = &self.break_buf;
-        let mut start = 0usize;
-        let mut last_break: Option<usize> = None;
-        let mut last_break_width = 0.0;
-        let mut current_width = 0.0;
-        let mut iter = text.char_indices().peekable();
-        let mut buf = [0u8; 4];
-        while let Some((pos, ch)) = iter.next() {
-            while break_idx < break_positions.len() && break_positions[break_idx] < pos {
-                break_idx += 1;
-            }
-            if break_idx < break_positions.len() && break_positions[break_idx] == pos {
-                last_break = Some(pos);
-                last_break_width = current_width;
-            }
-            let w = self.measurer.0.measure(ch.encode_utf8(&mut buf), metrics);
-            current_width += w;
-            let total_width = current_width;
-            let next_pos = iter.peek().map(|(p, _)| *p).unwrap_or(text.len());
-            if current_width > width && pos > start {
-                let mut break_pos = last_break.unwrap_or(pos);
-                if break_pos <= start {
-                    break_pos = pos;
-                }
-                let mut adjusted = false;
-                let adjusted_pos = adjust_break(text, start, break_pos);
-                if adjusted_pos != break_pos {
-                    adjusted = true;
-                    break_pos = adjusted_pos;
-                }
-                let slice = text[start..break_pos].trim_end();
-                if !slice.is_empty() {
-                    let slice_width = if !adjusted && Some(break_pos) == last_break {
-                        last_break_width
-                    } else if !adjusted && break_pos == pos {
-                        (current_width - w).max(0.0)
-                    } else {
-                        self.measurer.0.measure(slice, metrics)
-                    };
-                    out.push(Line { text: slice.to_string(), width: slice_width });
-                }
-                let base_width = if !adjusted && Some(break_pos) == last_break {
-                    last_break_width
-                } else if !adjusted && break_pos == pos {
-                    (current_width - w).max(0.0)
-                } else {
-                    self.measurer.0.measure(&text[start..break_pos], metrics)
-                };
-                start = break_pos;
-                current_width = 0.0;
-                if start < next_pos {
-                    if !adjusted && Some(break_pos) == last_break {
-                        current_width = (total_width - base_width).max(0.0);
-                    } else if !adjusted && break_pos == pos {
-                        current_width = w;
-                    } else {
-                        let rem = &text[start..next_pos];
-                        current_width = self.measurer.0.measure(rem, metrics);
-                    }
-                }
-                last_break = None;
-                last_break_width = 0.0;
-            }
-        }
-        let slice = text[start..text.len()].trim_end();
-        if !slice.is_empty() {
-            let raw = &text[start..text.len()];
-            let slice_width = if slice.len() == raw.len() {
-                current_width
-            } else {
-                self.measurer.0.measure(slice, metrics)
-            };
-            out.push(Line { text: slice.to_string(), width: slice_width });
-        }
-        if out.is_empty() {
-            out.push(Line { text: String::new(), width: 0.0 });
-        }
-        out
+        let out = self.alloc_lines(cache, self.break_buf.len().saturating_add(1));
+        wrap_lines(text, spans, width, metrics, self.measurers(), &self.break_buf, out)
+    }
+
+    fn measurers(&self) -> Measurers<'_> {
+        Measurers { text: &*self.measurer.0, math: &*self.math }
     }
 
     fn alloc_lines(&mut self, cache: Option<&mut LayoutCache>, cap: usize) -> Vec<Line> {
@@ -578,16 +754,53 @@ impl LayoutEngine {
         out
     }
 
-    fn prewarm_if_needed(&mut self, doc: &Document, metrics: FontMetrics) {
+    /// Warms up to `config.prewarm_limit` new characters, carrying on from
+    /// where the previous call stopped. Once the whole document was covered
+    /// only dirty blocks are looked at.
+    fn prewarm_if_needed(&mut self, doc: &Document, config: &LayoutConfig) {
         if self.last_prewarm_version == doc.version {
             return;
         }
+        self.last_prewarm_version = doc.version;
+        self.prewarm.start(doc, config.metrics);
         let mut chars = Vec::new();
-        collect_doc_chars(doc, &mut chars, 512);
+        self.prewarm.collect(doc, &mut chars, config.prewarm_limit);
         if !chars.is_empty() {
-            self.real.prewarm_chars(&chars, metrics);
+            self.real.prewarm_chars(&chars, config.metrics);
+        }
+    }
+
+    /// Walks the part of `doc` that prewarming has not reached yet on a
+    /// worker thread, filling the shared glyph cache. Returns None when
+    /// there is nothing left to cover.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn prewarm_in_background(&mut self, doc: &Document, metrics: FontMetrics) -> Option<std::thread::JoinHandle<usize>> {
+        self.prewarm.start(doc, metrics);
+        if self.prewarm.complete {
+            return None;
+        }
+        let rest = doc.blocks[self.prewarm.next_block.min(doc.blocks.len())..].to_vec();
+        let mut seen = self.prewarm.seen.clone();
+        self.prewarm.next_block = doc.blocks.len();
+        self.prewarm.complete = true;
+        let real = self.real.clone();
+        Some(std::thread::spawn(move || {
+            let mut chars = Vec::new();
+            for block in &rest {
+                collect_block_chars(block, &mut chars, &mut seen, usize::MAX);
+            }
+            real.prewarm_chars(&chars, metrics);
+            chars.len()
+        }))
+    }
+
+    pub fn prewarm_coverage(&self) -> PrewarmCoverage {
+        PrewarmCoverage {
+            blocks: self.prewarm.next_block,
+            total_blocks: self.prewarm.total_blocks,
+            chars: self.prewarm.seen.len(),
+            complete: self.prewarm.complete,
         }
-        self.last_prewarm_version = doc.version;
     }
 
     fn fill_break_buf(&mut self, text: &str, width: f32, font_size: f32) {
@@ -602,19 +815,19 @@ impl LayoutEngine {
             if let Some(cached) = self.break_cache_short.get(&key) {
                 self.break_buf.clear();
                 self.break_buf.extend_from_slice(cached);
-                self.break_cache_hits += 1;
+                self.stats.break_cache_hits += 1;
                 return;
             }
         } else if let Some(cached) = self.break_cache_long.get(&key) {
             self.break_buf.clear();
             self.break_buf.extend_from_slice(cached);
-            self.break_cache_hits += 1;
+            self.stats.break_cache_hits += 1;
             return;
         }
         self.breaker.break_positions_into(text, &mut self.break_buf);
-        self.break_cache_misses += 1;
+        self.stats.break_cache_misses += 1;
         if text.len() <= 128 {
-            if self.break_cache_short.len() > 4096 {
+            if self.break_cache_short.len() >= self.break_cache_short_cap {
                 self.break_cache_short.clear();
             }
             self.break_cache_short.insert(key, self.break_buf.clone());
@@ -623,19 +836,93 @@ impl LayoutEngine {
         }
     }
 
-    fn maybe_log_stats(&self) {
-        if std::env::var("WA_DIAG").ok().as_deref() != Some("1") {
-            return;
+    fn begin_call(&self, doc: &Document) -> CallStart {
+        CallStart {
+            _span: tracing::debug_span!("layout", blocks = doc.blocks.len()).entered(),
+            timer: Timer::start(self.collect_timings),
+            wrap: self.stats.wrap,
+            measure: self.stats.measure,
         }
-        if self.break_cache_hits + self.break_cache_misses == 0 {
+    }
+
+    fn end_call(&mut self, call: CallStart, tree: &LayoutTree) {
+        let blocks = (self.stats.wrap - call.wrap) + (self.stats.measure - call.measure);
+        self.stats.paginate += call.timer.elapsed().saturating_sub(blocks);
+        self.stats.pages = tree.pages.len();
+        self.stats.calls += 1;
+        if self.options.diagnostics {
+            let coverage = self.prewarm_coverage();
+            tracing::debug!(
+                stats = ?self.stats,
+                prewarm_blocks = coverage.blocks,
+                prewarm_chars = coverage.chars,
+                "layout stats"
+            );
+        }
+    }
+}
+
+/// Where the stats stood when a layout call began.
+struct CallStart {
+    _span: tracing::span::EnteredSpan,
+    timer: Timer,
+    wrap: std::time::Duration,
+    measure: std::time::Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrewarmCoverage {
+    /// Blocks scanned for characters so far.
+    pub blocks: usize,
+    pub total_blocks: usize,
+    /// Distinct characters sent to the glyph cache.
+    pub chars: usize,
+    pub complete: bool,
+}
+
+/// How far glyph prewarming got through the current document.
+#[derive(Debug, Default)]
+struct PrewarmState {
+    doc_id: Option<Uuid>,
+    size: u16,
+    seen: HashSet<char>,
+    next_block: usize,
+    total_blocks: usize,
+    complete: bool,
+}
+
+impl PrewarmState {
+    /// Starts over for another document or font size; glyphs are cached per
+    /// size, so characters seen at another size are not warm.
+    fn start(&mut self, doc: &Document, metrics: FontMetrics) {
+        let size = quantize_size(metrics.font_size);
+        if self.size != size {
+            self.seen.clear();
+            self.size = size;
+            self.doc_id = None;
+        }
+        if self.doc_id != Some(doc.id) {
+            self.doc_id = Some(doc.id);
+            self.next_block = 0;
+            self.complete = false;
+        }
+        self.total_blocks = doc.blocks.len();
+    }
+
+    fn collect(&mut self, doc: &Document, out: &mut Vec<char>, limit: usize) {
+        if self.complete {
+            for block in doc.blocks.iter().filter(|b| b.is_effectively_dirty()) {
+                collect_block_chars(block, out, &mut self.seen, limit);
+            }
             return;
         }
-        let total = self.break_cache_hits + self.break_cache_misses;
-        let hit_rate = self.break_cache_hits as f64 / total as f64;
-        eprintln!("[layout] break_cache hit_rate={:.2} hits={} misses={}", hit_rate, self.break_cache_hits, self.break_cache_misses);
-        if let Some(rate) = self.real.hit_rate() {
-            eprintln!("[layout] glyph_cache hit_rate={:.2}", rate);
+        while self.next_block < doc.blocks.len() && out.len() < limit {
+            collect_block_chars(&doc.blocks[self.next_block], out, &mut self.seen, limit);
+            if out.len() < limit {
+                self.next_block += 1;
+            }
         }
+        self.complete = self.next_block >= doc.blocks.len();
     }
 }
 
@@ -656,46 +943,90 @@ fn quantize_size(size: f32) -> u16 {
 
 #[cfg(feature = "parallel")]
 fn paginate_blocks(blocks: Vec<std::sync::Arc<LayoutBlock>>, config: &LayoutConfig) -> LayoutTree {
-    let mut pages = Vec::new();
-    let mut current = Page {
-        number: 1,
-        blocks: Vec::new(),
-        height: 0.0,
-    };
-    let max_height = config.page_height - config.margin * 2.0;
+    let mut pages = Paginator::new(config);
     for block in blocks {
-        let needed = block.height;
-        if config.paged && current.height + needed > max_height && !current.blocks.is_empty() {
-            pages.push(current);
-            current = Page {
-                number: pages.len() + 1,
-                blocks: Vec::new(),
-                height: 0.0,
-            };
+        pages.push(block);
+    }
+    pages.finish()
+}
+
+/// Fills pages in block order. A block that does not fit starts the next
+/// page, as does anything after a page break; in the scroll view
+/// everything lands on one page.
+struct Paginator<'a> {
+    config: &'a LayoutConfig,
+    pages: Vec<Page>,
+    current: Page,
+    /// The current page ends at a page break.
+    broken: bool,
+}
+
+impl<'a> Paginator<'a> {
+    fn new(config: &'a LayoutConfig) -> Self {
+        Self { config, pages: Vec::new(), current: Page { number: 1, blocks: Vec::new(), height: 0.0 }, broken: false }
+    }
+
+    fn push(&mut self, block: std::sync::Arc<LayoutBlock>) {
+        let config = self.config;
+        let is_break = block.kind == LayoutKind::PageBreak;
+        let max_height = config.page_height - config.margin * 2.0;
+        let overflows = || self.current.height + self.current.blocks.len() as f32 * config.block_gap() + block.height > max_height;
+        // A break always fits on the page it ends.
+        if config.paged && !self.current.blocks.is_empty() && (self.broken || (!is_break && overflows())) {
+            let number = self.pages.len() + 2;
+            self.pages.push(std::mem::replace(&mut self.current, Page { number, blocks: Vec::new(), height: 0.0 }));
+        }
+        self.broken = is_break;
+        self.current.height += block.height;
+        self.current.blocks.push(block);
+    }
+
+    fn finish(mut self) -> LayoutTree {
+        self.pages.push(self.current);
+        if self.config.line_numbers {
+            number_lines(&mut self.pages);
         }
-        current.height += needed;
-        current.blocks.push(block);
+        LayoutTree { pages: self.pages }
+    }
+}
+
+/// Numbers every line from 1, running on across blocks and pages. Blocks
+/// shared with the layout cache are copied rather than numbered in place.
+fn number_lines(pages: &mut [Page]) {
+    let mut next = 1;
+    for block in pages.iter_mut().flat_map(|page| page.blocks.iter_mut()) {
+        let numbered = block.lines.iter().zip(next..).all(|(line, number)| line.number == Some(number));
+        if !numbered {
+            for (line, number) in std::sync::Arc::make_mut(block).lines.iter_mut().zip(next..) {
+                line.number = Some(number);
+            }
+        }
+        next += block.lines.len();
     }
-    pages.push(current);
-    LayoutTree { pages }
 }
 
 #[cfg(feature = "parallel")]
 struct LayoutWorker {
     breaker: LineBreaker,
     measurer: SharedMeasurer,
+    math: std::sync::Arc<dyn MathMeasurer>,
     images: ImageCache,
+    highlighter: SyntaxHighlighter,
+    handlers: BlockHandlers,
     break_buf: Vec<usize>,
     scratch: String,
 }
 
 #[cfg(feature = "parallel")]
 impl LayoutWorker {
-    fn new(measurer: SharedMeasurer, images: ImageCache) -> Self {
+    fn new(measurer: SharedMeasurer, math: std::sync::Arc<dyn MathMeasurer>, images: ImageCache, highlighter: SyntaxHighlighter, handlers: BlockHandlers) -> Self {
         Self {
             breaker: LineBreaker,
             measurer,
+            math,
             images,
+            highlighter,
+            handlers,
             break_buf: Vec::new(),
             scratch: String::new(),
         }
@@ -705,8 +1036,8 @@ impl LayoutWorker {
         let width = config.page_width - config.margin * 2.0;
         match block {
             Block::Heading { level, content, .. } => {
-                let text = join_inline(content);
-                let lines = self.wrap_text(&text, width, config.metrics);
+                let (text, spans) = join_inline_spans(content);
+                let lines = self.wrap_text(&text, &spans, width, config.metrics);
                 let height = lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
                 LayoutBlock {
                     block_id: block.id(),
@@ -717,8 +1048,8 @@ impl LayoutWorker {
                 }
             }
             Block::Paragraph { content, .. } => {
-                let text = join_inline(content);
-                let lines = self.wrap_text(&text, width, config.metrics);
+                let (text, spans) = join_inline_spans(content);
+                let lines = self.wrap_text(&text, &spans, width, config.metrics);
                 let height = lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
                 LayoutBlock {
                     block_id: block.id(),
@@ -735,10 +1066,11 @@ impl LayoutWorker {
                     let item_len = inline_text_len(&item.content);
                     let digits = (idx + 1).to_string().len();
                     self.scratch.reserve(item_len + digits + 1);
-                    let _ = std::fmt::Write::write_fmt(&mut self.scratch, format_args!("{} ", idx + 1));
-                    join_inline_into(&mut self.scratch, &item.content);
+                    write_list_marker(&mut self.scratch, idx, item, config.list_indent);
+                    let mut spans = Vec::new();
+                    join_inline_spans_into(&mut self.scratch, &mut spans, &item.content, Style::default(), None);
                     let text = std::mem::take(&mut self.scratch);
-                    lines.extend(self.wrap_text(&text, width, config.metrics));
+                    lines.extend(self.wrap_text(&text, &spans, width, config.metrics));
                     self.scratch = text;
                 }
                 let height = lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
@@ -751,32 +1083,17 @@ impl LayoutWorker {
                 }
             }
             Block::Quote { content, .. } => {
-                self.scratch.clear();
-                let mut total_len = 0usize;
-                let mut parts = 0usize;
-                for b in content {
-                    if let Block::Paragraph { content, .. } = b {
-                        total_len += inline_text_len(content);
-                        parts += 1;
-                    }
-                }
-                if parts > 1 {
-                    total_len += parts - 1;
-                }
-                self.scratch.reserve(total_len);
-                let mut first = true;
+                let mut lines = Vec::with_capacity(content.len().saturating_mul(2));
                 for b in content {
                     if let Block::Paragraph { content, .. } = b {
-                        if !first {
-                            self.scratch.push(' ');
-                        }
-                        join_inline_into(&mut self.scratch, content);
-                        first = false;
+                        self.scratch.clear();
+                        let mut spans = Vec::new();
+                        join_inline_spans_into(&mut self.scratch, &mut spans, content, Style::default(), None);
+                        let text = std::mem::take(&mut self.scratch);
+                        lines.extend(self.wrap_text(&text, &spans, width, config.metrics));
+                        self.scratch = text;
                     }
                 }
-                let text = std::mem::take(&mut self.scratch);
-                let lines = self.wrap_text(&text, width, config.metrics);
-                self.scratch = text;
                 let height = lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
                 LayoutBlock {
                     block_id: block.id(),
@@ -786,14 +1103,24 @@ impl LayoutWorker {
                     meta: None,
                 }
             }
-            Block::Code { code, .. } => {
-                let line_count = code.as_ref().bytes().filter(|b| *b == b'\n').count() + 1;
+            Block::Code { lang, code, .. } => {
+                let line_count = code.line_count();
                 let mut lines = Vec::with_capacity(line_count);
-                for l in code.as_ref().lines() {
+                let mut highlighted = self
+                    .highlighter
+                    .highlight_lines(lang, code, &*self.measurer.0, config.metrics)
+                    .unwrap_or_default()
+                    .into_iter();
+                let mut start = 0usize;
+                for l in code.lines() {
                     lines.push(Line {
                         text: l.to_string(),
-                        width: self.measurer.0.measure(l, config.metrics),
+                        width: self.measurer.0.measure(&l, config.metrics),
+                        start,
+                        runs: highlighted.next().unwrap_or_default(),
+                        number: None,
                     });
+                    start += l.chars().count() + 1;
                 }
                 let height = lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
                 LayoutBlock {
@@ -815,15 +1142,20 @@ impl LayoutWorker {
                         row_len += (row.len() - 1) * 3;
                     }
                     let mut row_text = String::with_capacity(row_len);
+                    let mut spans = Vec::new();
                     for (idx, cell) in row.iter().enumerate() {
                         if idx > 0 {
                             row_text.push_str(" | ");
                         }
-                        join_inline_into(&mut row_text, &cell.content);
+                        join_inline_spans_into(&mut row_text, &mut spans, &cell.content, Style::default(), None);
                     }
+                    let runs = line_runs(&row_text, 0, &spans, self.measurers(), config.metrics);
                     lines.push(Line {
                         text: row_text,
-                        width: width,
+                        width,
+                        start: 0,
+                        runs,
+                        number: None,
                     });
                 }
                 let height = lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
@@ -838,13 +1170,13 @@ impl LayoutWorker {
             Block::Figure { url, caption, size, .. } => {
                 let asset = self.images.load(url);
                 let (asset_w, asset_h) = if let Some(sz) = size {
-                    (sz.width.max(1.0), sz.height.max(1.0))
+                    (sz.width.max(1.0) * config.zoom, sz.height.max(1.0) * config.zoom)
                 } else {
-                    (asset.width, asset.height)
+                    fit_width(asset.width * config.zoom, asset.height * config.zoom, width)
                 };
                 let fig_height = asset_h;
                 let text = caption.as_ref().map(|c| c.as_ref()).unwrap_or("图片");
-                let lines = self.wrap_text(&text, width, config.metrics);
+                let lines = self.wrap_text(text, &[], width, config.metrics);
                 let height = fig_height + lines.len() as f32 * config.metrics.font_size * config.metrics.line_height;
                 LayoutBlock {
                     block_id: block.id(),
@@ -854,176 +1186,126 @@ impl LayoutWorker {
                     meta: Some(BlockMeta { width: asset_w, height: asset_h }),
                 }
             }
+            Block::PageBreak { .. } => page_break_block(block.id()),
+            Block::Custom { kind, data, .. } => {
+                layout_custom(block.id(), kind, data, &self.handlers, &*self.measurer.0, config)
+            }
         }
     }
 
-    fn wrap_text(&mut self, text: &str, width: f32, metrics: FontMetrics) -> Vec<Line> {
-        if text.is_empty() {
-            return vec![Line { text: String::new(), width: 0.0 }];
-        }
+    fn wrap_text(&mut self, text: &str, spans: &[InlineSpan], width: f32, metrics: FontMetrics) -> Vec<Line> {
         self.breaker.break_positions_into(text, &mut self.break_buf);
-        let break_positions = &self.break_buf;
-        let mut break_idx = 0usize;
-        let mut out = Vec::with_capacity(break_positions.len().saturating_add(1));
-        let mut start = 0usize;
-        let mut last_break: Option<usize> = None;
-        let mut last_break_width = 0.0;
-        let mut current_width = 0.0;
-        let mut iter = text.char_indices().peekable();
-        let mut buf = [0u8; 4];
-        while let Some((pos, ch)) = iter.next() {
-            while break_idx < break_positions.len() && break_positions[break_idx] < pos {
-                break_idx += 1;
+        let out = Vec::with_capacity(self.break_buf.len().saturating_add(1));
+        wrap_lines(text, spans, width, metrics, self.measurers(), &self.break_buf, out)
+    }
+
+    fn measurers(&self) -> Measurers<'_> {
+        Measurers { text: &*self.measurer.0, math: &*self.math }
+    }
+}
+
+/// Breaks `text` into lines no wider than `width`, at the break positions
+/// `break_positions` allows, appending them to `out`. Every layout path
+/// wraps through here, so they cannot drift apart.
+fn wrap_lines(
+    text: &str,
+    spans: &[InlineSpan],
+    width: f32,
+    metrics: FontMetrics,
+    measurers: Measurers,
+    break_positions: &[usize],
+    mut out: Vec<Line>,
+) -> Vec<Line> {
+    let mut break_idx = 0usize;
+    let mut start = 0usize;
+    let mut start_char = 0usize;
+    let mut last_break: Option<usize> = None;
+    let mut last_break_width = 0.0;
+    let mut current_width = 0.0;
+    let mut iter = text.char_indices().peekable();
+    let mut buf = [0u8; 4];
+    while let Some((pos, ch)) = iter.next() {
+        while break_idx < break_positions.len() && break_positions[break_idx] < pos {
+            break_idx += 1;
+        }
+        if break_idx < break_positions.len() && break_positions[break_idx] == pos {
+            last_break = Some(pos);
+            last_break_width = current_width;
+        }
+        let w = measurers.text.measure(ch.encode_utf8(&mut buf), metrics);
+        current_width += w;
+        let total_width = current_width;
+        let next_pos = iter.peek().map(|(p, _)| *p).unwrap_or(text.len());
+        if current_width > width && pos > start {
+            let mut break_pos = last_break.unwrap_or(pos);
+            if break_pos <= start {
+                break_pos = pos;
             }
-            if break_idx < break_positions.len() && break_positions[break_idx] == pos {
-                last_break = Some(pos);
-                last_break_width = current_width;
+            let mut adjusted = false;
+            let adjusted_pos = adjust_break(text, start, break_pos);
+            if adjusted_pos != break_pos {
+                adjusted = true;
+                break_pos = adjusted_pos;
             }
-            let w = self.measurer.0.measure(ch.encode_utf8(&mut buf), metrics);
-            current_width += w;
-            let total_width = current_width;
-            let next_pos = iter.peek().map(|(p, _)| *p).unwrap_or(text.len());
-            if current_width > width && pos > start {
-                let mut break_pos = last_break.unwrap_or(pos);
-                if break_pos <= start {
-                    break_pos = pos;
-                }
-                let mut adjusted = false;
-                let adjusted_pos = adjust_break(text, start, break_pos);
-                if adjusted_pos != break_pos {
-                    adjusted = true;
-                    break_pos = adjusted_pos;
-                }
-                let slice = text[start..break_pos].trim_end();
-                if !slice.is_empty() {
-                    let slice_width = if !adjusted && Some(break_pos) == last_break {
-                        last_break_width
-                    } else if !adjusted && break_pos == pos {
-                        (current_width - w).max(0.0)
-                    } else {
-                        self.measurer.0.measure(slice, metrics)
-                    };
-                    out.push(Line { text: slice.to_string(), width: slice_width });
-                }
-                let base_width = if !adjusted && Some(break_pos) == last_break {
+            let slice = text[start..break_pos].trim_end();
+            if !slice.is_empty() {
+                let slice_width = if !adjusted && Some(break_pos) == last_break {
                     last_break_width
                 } else if !adjusted && break_pos == pos {
                     (current_width - w).max(0.0)
                 } else {
-                    self.measurer.0.measure(&text[start..break_pos], metrics)
+                    measurers.text.measure(slice, metrics)
                 };
-                start = break_pos;
-                current_width = 0.0;
-                if start < next_pos {
-                    if !adjusted && Some(break_pos) == last_break {
-                        current_width = (total_width - base_width).max(0.0);
-                    } else if !adjusted && break_pos == pos {
-                        current_width = w;
-                    } else {
-                        let rem = &text[start..next_pos];
-                        current_width = self.measurer.0.measure(rem, metrics);
-                    }
-                }
-                last_break = None;
-                last_break_width = 0.0;
+                out.push(make_line(slice, start, start_char, slice_width, spans, measurers, metrics));
             }
-        }
-        let slice = text[start..text.len()].trim_end();
-        if !slice.is_empty() {
-            let raw = &text[start..text.len()];
-            let slice_width = if slice.len() == raw.len() {
-                current_width
+            let base_width = if !adjusted && Some(break_pos) == last_break {
+                last_break_width
+            } else if !adjusted && break_pos == pos {
+                (current_width - w).max(0.0)
             } else {
-                self.measurer.0.measure(slice, metrics)
+                measurers.text.measure(&text[start..break_pos], metrics)
             };
-            out.push(Line { text: slice.to_string(), width: slice_width });
-        }
-        if out.is_empty() {
-            out.push(Line { text: String::new(), width: 0.0 });
-        }
-        out
-    }
-}
-
-fn hash_block(block: &Block) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    hash_block_into(block, &mut hasher);
-    hasher.finish()
-}
-
-fn hash_block_into(block: &Block, hasher: &mut impl Hasher) {
-    std::mem::discriminant(block).hash(hasher);
-    match block {
-        Block::Heading { level, content, .. } => {
-            level.hash(hasher);
-            hash_inlines(content, hasher);
-        }
-        Block::Paragraph { content, .. } => {
-            hash_inlines(content, hasher);
-        }
-        Block::List { ordered, items, .. } => {
-            ordered.hash(hasher);
-            items.len().hash(hasher);
-            for item in items {
-                hash_inlines(&item.content, hasher);
-            }
-        }
-        Block::Quote { content, .. } => {
-            content.len().hash(hasher);
-            for inner in content {
-                hash_block_into(inner, hasher);
-            }
-        }
-        Block::Code { lang, code, .. } => {
-            lang.as_ref().hash(hasher);
-            code.as_ref().hash(hasher);
-        }
-        Block::Table { rows, .. } => {
-            rows.len().hash(hasher);
-            for row in rows {
-                row.len().hash(hasher);
-                for cell in row {
-                    hash_inlines(&cell.content, hasher);
+            start_char += text[start..break_pos].chars().count();
+            start = break_pos;
+            current_width = 0.0;
+            if start < next_pos {
+                if !adjusted && Some(break_pos) == last_break {
+                    current_width = (total_width - base_width).max(0.0);
+                } else if !adjusted && break_pos == pos {
+                    current_width = w;
+                } else {
+                    let rem = &text[start..next_pos];
+                    current_width = measurers.text.measure(rem, metrics);
                 }
             }
-        }
-        Block::Figure { url, caption, size, .. } => {
-            url.as_ref().hash(hasher);
-            caption.as_ref().map(|c| c.as_ref()).hash(hasher);
-            if let Some(sz) = size {
-                sz.width.to_bits().hash(hasher);
-                sz.height.to_bits().hash(hasher);
-            }
+            last_break = None;
+            last_break_width = 0.0;
         }
     }
-}
-
-fn hash_inlines(inlines: &[Inline], hasher: &mut impl Hasher) {
-    inlines.len().hash(hasher);
-    for inline in inlines {
-        std::mem::discriminant(inline).hash(hasher);
-        match inline {
-            Inline::Text { value } | Inline::CodeSpan { value } => {
-                value.as_ref().hash(hasher);
-            }
-            Inline::Styled { style, content } => {
-                style.bold.hash(hasher);
-                style.italic.hash(hasher);
-                style.underline.hash(hasher);
-                hash_inlines(content, hasher);
-            }
-            Inline::Link { url, text } => {
-                url.as_ref().hash(hasher);
-                hash_inlines(text, hasher);
-            }
-        }
+    let slice = text[start..text.len()].trim_end();
+    if !slice.is_empty() {
+        let raw = &text[start..text.len()];
+        let slice_width = if slice.len() == raw.len() {
+            current_width
+        } else {
+            measurers.text.measure(slice, metrics)
+        };
+        out.push(make_line(slice, start, start_char, slice_width, spans, measurers, metrics));
     }
+    if out.is_empty() {
+        out.push(Line { text: String::new(), width: 0.0, start: 0, runs: Vec::new(), number: None });
+    }
+    out
 }
 
-fn hash_inlines_value(inlines: &[Inline]) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    hash_inlines(inlines, &mut hasher);
-    hasher.finish()
+/// Intrinsic image size, scaled down (keeping the aspect ratio) to fit the
+/// content width.
+fn fit_width(w: f32, h: f32, max_w: f32) -> (f32, f32) {
+    if w > max_w && w > 0.0 {
+        (max_w, h * max_w / w)
+    } else {
+        (w, h)
+    }
 }
 
 fn hash_row_value(row: &[wa_core::Cell]) -> u64 {
@@ -1035,24 +1317,39 @@ fn hash_row_value(row: &[wa_core::Cell]) -> u64 {
     hasher.finish()
 }
 
-fn is_effectively_dirty(block: &Block) -> bool {
-    if block.is_dirty() {
-        return true;
-    }
-    if let Block::Quote { content, .. } = block {
-        return content.iter().any(is_effectively_dirty);
+/// A cached layout for a block that is not dirty. Clean blocks trust the
+/// stored entry, so their content is never hashed.
+fn clean_cache_hit(block: &Block, cache: &LayoutCache) -> Option<std::sync::Arc<LayoutBlock>> {
+    if block.is_effectively_dirty() {
+        return None;
     }
-    false
+    cache.get(block.id()).cloned()
 }
 
-fn collect_doc_chars(doc: &Document, out: &mut Vec<char>, limit: usize) {
-    let mut seen = HashSet::new();
-    for block in &doc.blocks {
-        collect_block_chars(block, out, &mut seen, limit);
-        if out.len() >= limit {
-            break;
-        }
-    }
+/// Custom blocks are drawn by the host; their lines are not wrapped.
+fn layout_custom(
+    block_id: Uuid,
+    kind: &SharedStr,
+    data: &serde_json::Value,
+    handlers: &BlockHandlers,
+    measurer: &dyn TextMeasurer,
+    config: &LayoutConfig,
+) -> LayoutBlock {
+    let (height, texts) = handlers.measure(kind, data, config);
+    let mut start = 0;
+    let lines = texts
+        .into_iter()
+        .map(|text| {
+            let line = Line { width: measurer.measure(&text, config.metrics), start, runs: Vec::new(), text, number: None };
+            start += line.text.chars().count() + 1;
+            line
+        })
+        .collect();
+    LayoutBlock { block_id, kind: LayoutKind::Custom(kind.clone()), lines, height, meta: None }
+}
+
+fn page_break_block(block_id: Uuid) -> LayoutBlock {
+    LayoutBlock { block_id, kind: LayoutKind::PageBreak, lines: Vec::new(), height: 0.0, meta: None }
 }
 
 fn collect_block_chars(block: &Block, out: &mut Vec<char>, seen: &mut HashSet<char>, limit: usize) {
@@ -1081,7 +1378,7 @@ fn collect_block_chars(block: &Block, out: &mut Vec<char>, seen: &mut HashSet<ch
             }
         }
         Block::Code { code, .. } => {
-            for ch in code.as_ref().chars() {
+            for ch in code.chars() {
                 if out.len() >= limit {
                     break;
                 }
@@ -1112,6 +1409,7 @@ fn collect_block_chars(block: &Block, out: &mut Vec<char>, seen: &mut HashSet<ch
                 }
             }
         }
+        Block::PageBreak { .. } | Block::Custom { .. } => {}
     }
 }
 
@@ -1121,7 +1419,7 @@ fn collect_inline_chars(inlines: &[Inline], out: &mut Vec<char>, seen: &mut Hash
             break;
         }
         match inline {
-            Inline::Text { value } | Inline::CodeSpan { value } => {
+            Inline::Text { value } | Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => {
                 for ch in value.as_ref().chars() {
                     if out.len() >= limit {
                         break;
@@ -1196,41 +1494,157 @@ fn is_forbidden_line_end(ch: char) -> bool {
     )
 }
 
-fn join_inline(inlines: &[Inline]) -> String {
+fn join_inline_spans(inlines: &[Inline]) -> (String, Vec<InlineSpan>) {
     let mut out = String::with_capacity(inline_text_len(inlines));
-    join_inline_into(&mut out, inlines);
-    out
+    let mut spans = Vec::new();
+    join_inline_spans_into(&mut out, &mut spans, inlines, Style::default(), None);
+    (out, spans)
 }
 
-fn inline_text_len(inlines: &[Inline]) -> usize {
-    let mut len = 0usize;
+/// Joins the inline text into `out`, recording a span for every piece that is
+/// not plain. Adjacent pieces with the same attributes share one span.
+fn join_inline_spans_into(
+    out: &mut String,
+    spans: &mut Vec<InlineSpan>,
+    inlines: &[Inline],
+    style: Style,
+    link: Option<&SharedStr>,
+) {
     for inline in inlines {
         match inline {
-            Inline::Text { value } => len += value.len(),
-            Inline::CodeSpan { value } => len += value.len(),
-            Inline::Link { text, .. } => len += inline_text_len(text),
-            Inline::Styled { content, .. } => len += inline_text_len(content),
+            Inline::Text { value } => push_span(out, spans, value, style, link, false, false),
+            Inline::CodeSpan { value } => push_span(out, spans, value, style, link, true, false),
+            // Laid out as the TeX source, in italics.
+            Inline::Math { tex, .. } => {
+                push_span(out, spans, tex, style.union(Style { italic: true, ..Style::default() }), link, false, true)
+            }
+            Inline::Link { url, text } => join_inline_spans_into(out, spans, text, style, Some(url)),
+            Inline::Styled { style: inner, content } => {
+                join_inline_spans_into(out, spans, content, style.union(*inner), link)
+            }
         }
     }
-    len
 }
 
-fn join_inline_into(out: &mut String, inlines: &[Inline]) {
-    for inline in inlines {
-        match inline {
-            Inline::Text { value } => out.push_str(value.as_ref()),
-            Inline::CodeSpan { value } => out.push_str(value.as_ref()),
-            Inline::Link { text, .. } => join_inline_into(out, text),
-            Inline::Styled { content, .. } => join_inline_into(out, content),
+fn push_span(
+    out: &mut String,
+    spans: &mut Vec<InlineSpan>,
+    value: &str,
+    style: Style,
+    link: Option<&SharedStr>,
+    code: bool,
+    math: bool,
+) {
+    let start = out.len();
+    out.push_str(value);
+    if value.is_empty() || (style.is_plain() && link.is_none() && !code && !math) {
+        return;
+    }
+    if let Some(last) = spans.last_mut() {
+        if last.range.end == start && last.style == style && last.code == code && last.math == math && last.link.as_ref() == link {
+            last.range.end = out.len();
+            return;
         }
     }
+    spans.push(InlineSpan {
+        range: start..out.len(),
+        style,
+        link: link.cloned(),
+        code,
+        math,
+    });
 }
 
+fn make_line(
+    text: &str,
+    start: usize,
+    start_char: usize,
+    width: f32,
+    spans: &[InlineSpan],
+    measurers: Measurers,
+    metrics: FontMetrics,
+) -> Line {
+    Line {
+        text: text.to_string(),
+        width,
+        start: start_char,
+        runs: line_runs(text, start, spans, measurers, metrics),
+        number: None,
+    }
+}
 
+/// Cuts `spans` (offsets into the block text) down to the line that starts at
+/// `start`, filling the gaps with plain runs.
+fn line_runs(
+    text: &str,
+    start: usize,
+    spans: &[InlineSpan],
+    measurers: Measurers,
+    metrics: FontMetrics,
+) -> Vec<TextRun> {
+    let end = start + text.len();
+    if !spans.iter().any(|s| s.range.start < end && s.range.end > start) {
+        return Vec::new();
+    }
+    let mut runs = Vec::new();
+    let mut push = |range: std::ops::Range<usize>, style: Style, link: Option<SharedStr>, code: bool, math: bool| {
+        let width = match math {
+            true => measurers.math.measure(&text[range.clone()], measurers.text, metrics),
+            false => measurers.text.measure(&text[range.clone()], metrics),
+        };
+        runs.push(TextRun { range, style, link, code, color: None, width });
+    };
+    let mut cursor = start;
+    for span in spans {
+        let s = span.range.start.max(start);
+        let e = span.range.end.min(end);
+        if s >= e {
+            continue;
+        }
+        if s > cursor {
+            push(cursor - start..s - start, Style::default(), None, false, false);
+        }
+        push(s - start..e - start, span.style, span.link.clone(), span.code, span.math);
+        cursor = e;
+    }
+    if cursor < end {
+        push(cursor - start..end - start, Style::default(), None, false, false);
+    }
+    runs
+}
 
+/// The number in front of item `idx`, or the box of a task item, after
+/// `indent` full-width spaces per level.
+fn write_list_marker(out: &mut String, idx: usize, item: &ListItem, indent: usize) {
+    out.extend(std::iter::repeat_n('\u{3000}', indent * item.level as usize));
+    match item.checked {
+        Some(true) => out.push_str("[x] "),
+        Some(false) => out.push_str("[ ] "),
+        None => {
+            let _ = std::fmt::Write::write_fmt(out, format_args!("{} ", idx + 1));
+        }
+    }
+}
 
+/// Cache signature of a list item; the level and task box change its
+/// marker.
+fn list_item_sig(item: &ListItem) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    item.level.hash(&mut hasher);
+    item.checked.hash(&mut hasher);
+    hash_inlines(&item.content, &mut hasher);
+    hasher.finish()
+}
 
-
-
-
-
+fn inline_text_len(inlines: &[Inline]) -> usize {
+    let mut len = 0usize;
+    for inline in inlines {
+        match inline {
+            Inline::Text { value } => len += value.len(),
+            Inline::CodeSpan { value } | Inline::Math { tex: value, .. } => len += value.len(),
+            Inline::Link { text, .. } => len += inline_text_len(text),
+            Inline::Styled { content, .. } => len += inline_text_len(content),
+        }
+    }
+    len
+}
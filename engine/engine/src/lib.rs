@@ -1,15 +1,31 @@
 ﻿mod cache;
+mod custom;
 mod image;
 mod layout;
 mod linebreak;
 mod metrics;
+mod options;
 mod hittest;
+#[cfg(feature = "raster")]
+mod raster;
 mod render_cache;
+mod stats;
+mod syntax;
+mod thumbnail;
+mod view_state;
 
 pub use cache::*;
+pub use custom::*;
 pub use image::*;
 pub use layout::*;
 pub use linebreak::*;
 pub use metrics::*;
+pub use options::*;
 pub use hittest::*;
+#[cfg(feature = "raster")]
+pub use raster::*;
 pub use render_cache::*;
+pub use stats::*;
+pub use syntax::*;
+pub use thumbnail::*;
+pub use view_state::*;
@@ -1,4 +1,5 @@
-﻿use std::sync::{Arc, Mutex};
+﻿use crate::EngineOptions;
+use std::sync::{Arc, Mutex, OnceLock};
 use std::num::NonZeroUsize;
 use fontdue::{Font, FontSettings};
 use lru::LruCache;
@@ -22,6 +23,25 @@ pub trait TextMeasurer: Send + Sync {
     fn measure(&self, text: &str, metrics: FontMetrics) -> f32;
 }
 
+/// Sizes inline math, which is laid out as its TeX source. Lines still
+/// wrap by the width of that source; a typesetting renderer can take over
+/// the width of each piece of a line.
+pub trait MathMeasurer: Send + Sync {
+    /// Width of `tex`, all or part of one formula, on one line.
+    fn measure(&self, tex: &str, text: &dyn TextMeasurer, metrics: FontMetrics) -> f32;
+}
+
+/// Measures the TeX source as text, the way it is drawn (in italics) until
+/// math is typeset.
+#[derive(Debug, Default, Clone)]
+pub struct TexSourceMeasurer;
+
+impl MathMeasurer for TexSourceMeasurer {
+    fn measure(&self, tex: &str, text: &dyn TextMeasurer, metrics: FontMetrics) -> f32 {
+        text.measure(tex, metrics)
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct SimpleMeasurer;
 
@@ -107,17 +127,28 @@ pub enum RealMeasurer {
     Simple(SimpleMeasurer),
 }
 
+impl Default for RealMeasurer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RealMeasurer {
+    /// A measurer on the process-wide font context.
     pub fn new() -> Self {
-        let cap = if std::env::var("WA_LOW_SPEC").ok().as_deref() == Some("1") {
-            2048
-        } else {
-            8192
-        };
-        if let Some(font) = load_default_font() {
-            RealMeasurer::Fontdue(FontdueMeasurer::new(font, cap))
-        } else {
-            RealMeasurer::Simple(SimpleMeasurer)
+        Self::from_context(FontContext::global())
+    }
+
+    /// A measurer on the font `options` name, or the process-wide context
+    /// when they name none.
+    pub fn with_options(options: &EngineOptions) -> Self {
+        Self::from_context(&FontContext::for_options(options))
+    }
+
+    pub fn from_context(context: &FontContext) -> Self {
+        match &context.fontdue {
+            Some(m) => RealMeasurer::Fontdue(m.clone()),
+            None => RealMeasurer::Simple(SimpleMeasurer),
         }
     }
 
@@ -128,6 +159,13 @@ impl RealMeasurer {
         }
     }
 
+    pub fn glyph_stats(&self) -> Option<GlyphStats> {
+        match self {
+            RealMeasurer::Fontdue(m) => Some(m.glyph_stats()),
+            _ => None,
+        }
+    }
+
     pub fn prewarm_chars(&self, chars: &[char], metrics: FontMetrics) {
         if let RealMeasurer::Fontdue(m) = self {
             m.prewarm_chars(chars, metrics);
@@ -144,14 +182,72 @@ impl TextMeasurer for RealMeasurer {
     }
 }
 
-fn load_default_font() -> Option<Font> {
-    if let Ok(path) = std::env::var("WA_FONT_PATH") {
-        if let Ok(bytes) = std::fs::read(&path) {
-            if let Ok(font) = Font::from_bytes(bytes, FontSettings::default()) {
-                return Some(font);
-            }
+/// A loaded font and its glyph cache. Measurers, layout engines and hit
+/// testers built from the same context share one warm cache.
+#[derive(Clone)]
+pub struct FontContext {
+    fontdue: Option<FontdueMeasurer>,
+}
+
+static GLOBAL_FONT_CONTEXT: OnceLock<FontContext> = OnceLock::new();
+
+impl FontContext {
+    pub fn new(font: Font, cache_capacity: usize) -> Self {
+        Self { fontdue: Some(FontdueMeasurer::new(font, cache_capacity)) }
+    }
+
+    /// The system font, or estimated widths when none can be found.
+    /// Settings come from the environment, see `EngineOptions::from_env`.
+    pub fn load_default() -> Self {
+        Self::load(&EngineOptions::from_env())
+    }
+
+    /// `options.font`, else the system font, else estimated widths.
+    pub fn load(options: &EngineOptions) -> Self {
+        let font = options.font.as_deref().and_then(load_font).or_else(load_system_font);
+        Self { fontdue: font.map(|font| FontdueMeasurer::new(font, options.glyph_cache_capacity())) }
+    }
+
+    /// The process-wide context unless `options` name their own font.
+    pub fn for_options(options: &EngineOptions) -> Self {
+        match options.font {
+            Some(_) => Self::load(options),
+            None => Self::global().clone(),
         }
     }
+
+    /// Estimated widths only. Layout is then the same on every machine,
+    /// which golden tests rely on.
+    pub fn without_font() -> Self {
+        Self { fontdue: None }
+    }
+
+    pub fn font(&self) -> Option<&Font> {
+        self.fontdue.as_ref().map(|m| m.font.as_ref())
+    }
+
+    /// Process-wide default context, loaded on first use.
+    pub fn global() -> &'static FontContext {
+        GLOBAL_FONT_CONTEXT.get_or_init(Self::load_default)
+    }
+
+    pub fn glyph_stats(&self) -> Option<GlyphStats> {
+        self.fontdue.as_ref().map(|m| m.glyph_stats())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlyphStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+fn load_font(path: &std::path::Path) -> Option<Font> {
+    let bytes = std::fs::read(path).ok()?;
+    Font::from_bytes(bytes, FontSettings::default()).ok()
+}
+
+fn load_system_font() -> Option<Font> {
     let candidates = [
         "C:\\Windows\\Fonts\\segoeui.ttf",
         "C:\\Windows\\Fonts\\arial.ttf",
@@ -160,14 +256,7 @@ fn load_default_font() -> Option<Font> {
         "/System/Library/Fonts/Helvetica.ttc",
         "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
     ];
-    for path in candidates {
-        if let Ok(bytes) = std::fs::read(path) {
-            if let Ok(font) = Font::from_bytes(bytes, FontSettings::default()) {
-                return Some(font);
-            }
-        }
-    }
-    None
+    candidates.into_iter().find_map(|path| load_font(path.as_ref()))
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -239,6 +328,13 @@ impl FontdueMeasurer {
         self.cache.lock().map(|c| c.hit_rate()).unwrap_or(0.0)
     }
 
+    pub fn glyph_stats(&self) -> GlyphStats {
+        self.cache
+            .lock()
+            .map(|c| GlyphStats { hits: c.hits, misses: c.misses })
+            .unwrap_or(GlyphStats { hits: 0, misses: 0 })
+    }
+
     pub fn prewarm_chars(&self, chars: &[char], metrics: FontMetrics) {
         let mut cache = self.cache.lock().unwrap();
         let size = metrics.font_size.round().max(1.0) as u16;
@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+/// Engine settings that used to come from environment variables. Library
+/// users pass them explicitly; `from_env` reads the old variables.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineOptions {
+    /// Smaller line break and glyph caches for low-memory machines.
+    pub low_spec: bool,
+    /// Lay out documents of more than `PARALLEL_MIN_BLOCKS` blocks on the
+    /// rayon pool. Needs the `parallel` feature.
+    pub parallel: bool,
+    /// Font file tried before the system fonts.
+    pub font: Option<PathBuf>,
+    /// Time every layout call and emit its stats as a `tracing` event.
+    pub diagnostics: bool,
+}
+
+/// Smallest document laid out in parallel when `EngineOptions::parallel` is on.
+pub const PARALLEL_MIN_BLOCKS: usize = 512;
+
+impl EngineOptions {
+    /// WA_LOW_SPEC=1, WA_LAYOUT_PAR=1, WA_FONT_PATH and WA_DIAG=1.
+    pub fn from_env() -> Self {
+        let flag = |name: &str| std::env::var(name).ok().as_deref() == Some("1");
+        Self {
+            low_spec: flag("WA_LOW_SPEC"),
+            parallel: flag("WA_LAYOUT_PAR"),
+            font: std::env::var_os("WA_FONT_PATH").map(PathBuf::from),
+            diagnostics: flag("WA_DIAG"),
+        }
+    }
+
+    pub(crate) fn glyph_cache_capacity(&self) -> usize {
+        if self.low_spec {
+            2048
+        } else {
+            8192
+        }
+    }
+
+    /// Capacities of the short and long text line break caches.
+    pub(crate) fn break_cache_capacities(&self) -> (usize, usize) {
+        if self.low_spec {
+            (1024, 256)
+        } else {
+            (4096, 512)
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{FontContext, LayoutConfig, LayoutEngine, LayoutKind, LayoutTree, RealMeasurer, TextMeasurer};
+use image::{ImageFormat, Rgba, RgbaImage};
+use std::io::Cursor;
+use wa_core::Document;
+
+const PAGE_BACKGROUND: Rgba<u8> = Rgba([250, 248, 242, 255]);
+const PAGE_BORDER: Rgba<u8> = Rgba([200, 200, 200, 255]);
+const BLOCK_FRAME: Rgba<u8> = Rgba([210, 210, 210, 255]);
+const CODE_BACKGROUND: Rgba<u8> = Rgba([245, 242, 235, 255]);
+const FIGURE_BACKGROUND: Rgba<u8> = Rgba([238, 232, 220, 255]);
+const TEXT_COLOR: [u8; 3] = [40, 30, 20];
+
+/// Lays `doc` out with the default font and encodes page `page_index` as
+/// PNG. Returns an empty buffer when there is no such page.
+pub fn render_png(doc: &Document, config: &LayoutConfig, page_index: usize) -> Vec<u8> {
+    let context = FontContext::global();
+    let tree = LayoutEngine::with_context(context).layout(doc, config);
+    let Some(page) = render_page(&tree, config, page_index, context) else {
+        return Vec::new();
+    };
+    let mut out = Cursor::new(Vec::new());
+    match page.write_to(&mut out, ImageFormat::Png) {
+        Ok(()) => out.into_inner(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Rasterizes one page of `tree`: page and block frames plus text. Glyphs
+/// come from `context`'s font; without one every visible character is
+/// drawn as a box of its estimated width. `tree` should have been laid out
+/// with the same context so advances match the line breaks.
+pub fn render_page(tree: &LayoutTree, config: &LayoutConfig, page_index: usize, context: &FontContext) -> Option<RgbaImage> {
+    let page = tree.pages.get(page_index)?;
+    let height = page.extent(config);
+    let mut canvas = Canvas {
+        image: RgbaImage::from_pixel(config.page_width.ceil().max(1.0) as u32, height.ceil().max(1.0) as u32, PAGE_BACKGROUND),
+        context,
+        measurer: RealMeasurer::from_context(context),
+        config,
+    };
+    let (width, height) = (canvas.image.width() as f32, canvas.image.height() as f32);
+    canvas.stroke_rect(0.0, 0.0, width, height, PAGE_BORDER);
+
+    let line_height = config.metrics.font_size * config.metrics.line_height;
+    let block_gap = config.block_gap();
+    let (left, right) = (config.margin, width - config.margin);
+    let mut top = config.margin;
+    for block in &page.blocks {
+        let bottom = top + block.height;
+        let mut line_y = top;
+        match block.kind {
+            LayoutKind::Code => canvas.fill_rect(left, top, right, bottom, CODE_BACKGROUND),
+            LayoutKind::Figure => {
+                let image_height = block.meta.as_ref().map(|m| m.height).unwrap_or(0.0);
+                canvas.fill_rect(left, top, right, top + image_height, FIGURE_BACKGROUND);
+                line_y += image_height;
+            }
+            _ => {}
+        }
+        canvas.stroke_rect(left, top, right, bottom, BLOCK_FRAME);
+        for line in &block.lines {
+            canvas.draw_text(&line.text, left, line_y);
+            line_y += line_height;
+        }
+        top = bottom + block_gap;
+    }
+    Some(canvas.image)
+}
+
+struct Canvas<'a> {
+    image: RgbaImage,
+    context: &'a FontContext,
+    measurer: RealMeasurer,
+    config: &'a LayoutConfig,
+}
+
+impl Canvas<'_> {
+    fn draw_text(&mut self, text: &str, left: f32, top: f32) {
+        let size = self.config.metrics.font_size;
+        let baseline = top + size;
+        let mut x = left;
+        let mut buf = [0u8; 4];
+        for ch in text.chars() {
+            let advance = self.measurer.measure(ch.encode_utf8(&mut buf), self.config.metrics);
+            if !ch.is_whitespace() {
+                match self.context.font() {
+                    Some(font) => {
+                        let (metrics, coverage) = font.rasterize(ch, size);
+                        let gx = (x + metrics.xmin as f32).round() as i64;
+                        let gy = (baseline - metrics.height as f32 - metrics.ymin as f32).round() as i64;
+                        for (i, alpha) in coverage.iter().enumerate() {
+                            let (dx, dy) = ((i % metrics.width.max(1)) as i64, (i / metrics.width.max(1)) as i64);
+                            self.blend(gx + dx, gy + dy, *alpha);
+                        }
+                    }
+                    None => {
+                        let box_top = baseline - size * 0.7;
+                        let inset = (advance * 0.1).max(0.5);
+                        for py in box_top.round() as i64..baseline.round() as i64 {
+                            for px in (x + inset).round() as i64..(x + advance - inset).round() as i64 {
+                                self.blend(px, py, 255);
+                            }
+                        }
+                    }
+                }
+            }
+            x += advance;
+        }
+    }
+
+    fn blend(&mut self, x: i64, y: i64, alpha: u8) {
+        if x < 0 || y < 0 || x >= self.image.width() as i64 || y >= self.image.height() as i64 || alpha == 0 {
+            return;
+        }
+        let pixel = self.image.get_pixel_mut(x as u32, y as u32);
+        let a = alpha as u32;
+        for (channel, text) in pixel.0.iter_mut().zip(TEXT_COLOR) {
+            *channel = ((*channel as u32 * (255 - a) + text as u32 * a) / 255) as u8;
+        }
+    }
+
+    fn fill_rect(&mut self, left: f32, top: f32, right: f32, bottom: f32, color: Rgba<u8>) {
+        for y in px_range(top, bottom, self.image.height()) {
+            for x in px_range(left, right, self.image.width()) {
+                self.image.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    fn stroke_rect(&mut self, left: f32, top: f32, right: f32, bottom: f32, color: Rgba<u8>) {
+        let (w, h) = (self.image.width(), self.image.height());
+        let (x0, x1) = (clamp_px(left, w), clamp_px(right - 1.0, w));
+        let (y0, y1) = (clamp_px(top, h), clamp_px(bottom - 1.0, h));
+        for x in x0..=x1 {
+            self.image.put_pixel(x, y0, color);
+            self.image.put_pixel(x, y1, color);
+        }
+        for y in y0..=y1 {
+            self.image.put_pixel(x0, y, color);
+            self.image.put_pixel(x1, y, color);
+        }
+    }
+}
+
+fn px_range(from: f32, to: f32, limit: u32) -> std::ops::Range<u32> {
+    let start = from.round().clamp(0.0, limit as f32) as u32;
+    let end = to.round().clamp(0.0, limit as f32) as u32;
+    start..end.max(start)
+}
+
+fn clamp_px(value: f32, limit: u32) -> u32 {
+    (value.round().max(0.0) as u32).min(limit.saturating_sub(1))
+}
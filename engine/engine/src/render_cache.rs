@@ -1,42 +1,80 @@
 use std::collections::HashSet;
 use uuid::Uuid;
 
-#[derive(Debug, Default)]
+/// Share of a page's blocks that may be dirty while clean blocks on it are
+/// still skipped.
+pub const DEFAULT_DIRTY_THRESHOLD: f32 = 0.05;
+
+#[derive(Debug)]
 pub struct RenderCache {
     dirty_blocks: HashSet<Uuid>,
     dirty_threshold: f32,
 }
 
+impl Default for RenderCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RenderCache {
     pub fn new() -> Self {
-        Self { dirty_blocks: HashSet::new(), dirty_threshold: 0.05 }
+        Self::with_threshold(DEFAULT_DIRTY_THRESHOLD)
+    }
+
+    pub fn with_threshold(dirty_threshold: f32) -> Self {
+        Self { dirty_blocks: HashSet::new(), dirty_threshold }
+    }
+
+    pub fn dirty_threshold(&self) -> f32 {
+        self.dirty_threshold
+    }
+
+    pub fn set_dirty_threshold(&mut self, threshold: f32) {
+        self.dirty_threshold = threshold;
     }
 
     pub fn mark_dirty(&mut self, id: Uuid) {
         self.dirty_blocks.insert(id);
     }
 
+    /// Call once a block has been painted, so later frames stop treating it
+    /// as changed.
+    pub fn mark_clean(&mut self, id: Uuid) {
+        self.dirty_blocks.remove(&id);
+    }
+
     pub fn clear(&mut self) {
         self.dirty_blocks.clear();
     }
 
-    pub fn dirty_ratio(&self, total_blocks: usize) -> f32 {
-        if total_blocks == 0 { return 0.0; }
-        self.dirty_blocks.len() as f32 / total_blocks as f32
+    /// Share of the page's blocks that are dirty.
+    pub fn dirty_ratio(&self, page_blocks: impl IntoIterator<Item = Uuid>) -> f32 {
+        let mut total = 0usize;
+        let mut dirty = 0usize;
+        for id in page_blocks {
+            total += 1;
+            if self.dirty_blocks.contains(&id) {
+                dirty += 1;
+            }
+        }
+        if total == 0 {
+            return 0.0;
+        }
+        dirty as f32 / total as f32
     }
 
     pub fn is_dirty(&self, id: Uuid) -> bool {
         self.dirty_blocks.contains(&id)
     }
 
-    pub fn should_render(&self, id: Uuid, total_blocks: usize) -> bool {
-        let ratio = self.dirty_ratio(total_blocks);
-        if ratio == 0.0 {
-            return true;
-        }
-        if ratio <= self.dirty_threshold {
-            return self.is_dirty(id);
-        }
-        true
+    /// Whether clean blocks on a page with `page_ratio` dirty blocks can be
+    /// skipped: only when a few, but not zero, of them changed.
+    pub fn skips_clean(&self, page_ratio: f32) -> bool {
+        page_ratio > 0.0 && page_ratio <= self.dirty_threshold
+    }
+
+    pub fn should_render(&self, id: Uuid, page_ratio: f32) -> bool {
+        !self.skips_clean(page_ratio) || self.is_dirty(id)
     }
 }
@@ -0,0 +1,77 @@
+use serde::Serialize;
+use std::time::Duration;
+
+/// What the layout engine did since the last `LayoutEngine::take_stats`.
+/// Counters are always kept; the timings stay zero unless timing collection
+/// is on (`LayoutEngine::set_collect_timings`), and always on wasm, where
+/// there is no clock to read.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LayoutStats {
+    /// `layout*` calls covered.
+    pub calls: u64,
+    /// Blocks taken from the layout cache as they were.
+    pub blocks_reused: u64,
+    /// Blocks laid out afresh.
+    pub blocks_laid_out: u64,
+    /// Pages produced by the most recent call.
+    pub pages: usize,
+    pub break_cache_hits: u64,
+    pub break_cache_misses: u64,
+    /// Glyph cache lookups; the cache is shared by every engine on the same
+    /// font context.
+    pub glyph_hits: u64,
+    pub glyph_misses: u64,
+    /// Rayon threads of the most recent parallel layout, 0 when it ran
+    /// serially.
+    pub parallel_workers: usize,
+    /// Breaking and wrapping text into lines.
+    pub wrap: Duration,
+    /// Laying out blocks, apart from wrapping: code lines, table rows,
+    /// figures and custom blocks.
+    pub measure: Duration,
+    /// Everything else: cache lookups, hashing and splitting into pages.
+    pub paginate: Duration,
+}
+
+impl LayoutStats {
+    pub fn break_hit_rate(&self) -> Option<f64> {
+        hit_rate(self.break_cache_hits, self.break_cache_misses)
+    }
+
+    pub fn glyph_hit_rate(&self) -> Option<f64> {
+        hit_rate(self.glyph_hits, self.glyph_misses)
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.wrap + self.measure + self.paginate
+    }
+}
+
+fn hit_rate(hits: u64, misses: u64) -> Option<f64> {
+    let total = hits + misses;
+    (total > 0).then(|| hits as f64 / total as f64)
+}
+
+/// A clock that only runs when timings are collected.
+#[derive(Clone, Copy)]
+pub(crate) struct Timer(#[cfg(not(target_arch = "wasm32"))] Option<std::time::Instant>);
+
+impl Timer {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn start(enabled: bool) -> Self {
+        Timer(enabled.then(std::time::Instant::now))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn start(_enabled: bool) -> Self {
+        Timer()
+    }
+
+    pub(crate) fn elapsed(self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(start) = self.0 {
+            return start.elapsed();
+        }
+        Duration::ZERO
+    }
+}
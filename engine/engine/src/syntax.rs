@@ -1,47 +1,114 @@
-use std::sync::Arc;
+use crate::{FontMetrics, TextMeasurer, TextRun};
+use wa_core::CodeText;
 
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{Style, Theme, ThemeSet};
-use syntect::parsing::{SyntaxReference, SyntaxSet};
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodeTheme {
+    #[default]
+    Light,
+    Dark,
+}
 
-#[derive(Clone)]
+/// Colors code block lines with syntect. Without the `highlight` feature every
+/// block is left as plain text.
+#[derive(Debug, Clone, Default)]
 pub struct SyntaxHighlighter {
-    syntax_set: Arc<SyntaxSet>,
-    theme: Theme,
+    theme: CodeTheme,
 }
 
 impl SyntaxHighlighter {
-    pub fn new() -> Self {
-        let syntax_set = SyntaxSet::load_defaults_newlines();
-        let themes = ThemeSet::load_defaults();
-        let theme = themes.themes.get("base16-ocean.dark").cloned().unwrap_or_else(|| themes.themes.values().next().cloned().unwrap());
-        Self { syntax_set: Arc::new(syntax_set), theme }
+    pub fn new(theme: CodeTheme) -> Self {
+        Self { theme }
+    }
+
+    pub fn theme(&self) -> CodeTheme {
+        self.theme
     }
 
-    pub fn highlight_lines(&self, lang: &str, code: &str) -> Vec<Vec<(Style, String)>> {
-        let syntax = self.syntax(lang).unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
-        let mut h = HighlightLines::new(syntax, &self.theme);
+    pub fn set_theme(&mut self, theme: CodeTheme) {
+        self.theme = theme;
+    }
+
+    /// One run list per `code.lines()` entry, or `None` when the language is
+    /// unknown.
+    #[cfg(feature = "highlight")]
+    pub fn highlight_lines(
+        &self,
+        lang: &str,
+        code: &CodeText,
+        measurer: &dyn TextMeasurer,
+        metrics: FontMetrics,
+    ) -> Option<Vec<Vec<TextRun>>> {
+        use syntect::easy::HighlightLines;
+        use syntect::highlighting::FontStyle;
+
+        let syntax_set = syntax_set();
+        let syntax = find_syntax(syntax_set, lang)?;
+        let mut h = HighlightLines::new(syntax, theme(self.theme));
         let mut out = Vec::new();
-        for line in code.lines() {
-            if let Ok(ranges) = h.highlight_line(line, &self.syntax_set) {
-                let spans = ranges
-                    .into_iter()
-                    .map(|(style, slice)| (style, slice.to_string()))
-                    .collect::<Vec<_>>();
-                out.push(spans);
-            } else {
-                out.push(vec![]);
+        for line in code.lines_with_endings() {
+            let content_len = line.trim_end_matches(['\n', '\r']).len();
+            let mut runs = Vec::new();
+            let mut start = 0usize;
+            for (style, piece) in h.highlight_line(&line, syntax_set).unwrap_or_default() {
+                let end = (start + piece.len()).min(content_len);
+                if end > start {
+                    let range = start..end;
+                    runs.push(TextRun {
+                        width: measurer.measure(&line[range.clone()], metrics),
+                        range,
+                        style: wa_core::Style {
+                            bold: style.font_style.contains(FontStyle::BOLD),
+                            italic: style.font_style.contains(FontStyle::ITALIC),
+                            underline: style.font_style.contains(FontStyle::UNDERLINE),
+                            strikethrough: false,
+                        },
+                        link: None,
+                        code: false,
+                        color: Some([style.foreground.r, style.foreground.g, style.foreground.b]),
+                    });
+                }
+                start += piece.len();
             }
+            out.push(runs);
         }
-        out
+        Some(out)
     }
 
-    fn syntax(&self, lang: &str) -> Option<&SyntaxReference> {
-        if lang.is_empty() {
-            return None;
-        }
-        self.syntax_set
-            .find_syntax_by_token(lang)
-            .or_else(|| self.syntax_set.find_syntax_by_extension(lang))
+    #[cfg(not(feature = "highlight"))]
+    pub fn highlight_lines(
+        &self,
+        _lang: &str,
+        _code: &CodeText,
+        _measurer: &dyn TextMeasurer,
+        _metrics: FontMetrics,
+    ) -> Option<Vec<Vec<TextRun>>> {
+        None
+    }
+}
+
+#[cfg(feature = "highlight")]
+fn syntax_set() -> &'static syntect::parsing::SyntaxSet {
+    static SET: std::sync::OnceLock<syntect::parsing::SyntaxSet> = std::sync::OnceLock::new();
+    SET.get_or_init(syntect::parsing::SyntaxSet::load_defaults_newlines)
+}
+
+#[cfg(feature = "highlight")]
+fn theme(theme: CodeTheme) -> &'static syntect::highlighting::Theme {
+    static THEMES: std::sync::OnceLock<syntect::highlighting::ThemeSet> = std::sync::OnceLock::new();
+    let themes = THEMES.get_or_init(syntect::highlighting::ThemeSet::load_defaults);
+    let name = match theme {
+        CodeTheme::Light => "InspiredGitHub",
+        CodeTheme::Dark => "base16-ocean.dark",
+    };
+    &themes.themes[name]
+}
+
+#[cfg(feature = "highlight")]
+fn find_syntax<'a>(set: &'a syntect::parsing::SyntaxSet, lang: &str) -> Option<&'a syntect::parsing::SyntaxReference> {
+    let lang = lang.trim();
+    if lang.is_empty() {
+        return None;
     }
+    set.find_syntax_by_token(lang)
+        .or_else(|| set.find_syntax_by_extension(lang))
 }
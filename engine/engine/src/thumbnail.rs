@@ -0,0 +1,203 @@
+use crate::{LayoutConfig, LayoutKind, LayoutTree};
+use std::sync::Arc;
+
+const PAGE_BACKGROUND: [u8; 4] = [250, 248, 242, 255];
+const PAGE_BORDER: [u8; 4] = [200, 200, 200, 255];
+const TEXT_BAR: [u8; 4] = [175, 170, 160, 255];
+const HEADING_BAR: [u8; 4] = [110, 100, 90, 255];
+const CODE_BACKGROUND: [u8; 4] = [232, 228, 218, 255];
+const FIGURE_BACKGROUND: [u8; 4] = [215, 205, 188, 255];
+
+/// A page drawn small, straight RGBA8. Lines show as grey bars as long as
+/// their text, so no glyphs are needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThumbnailData {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// Sketches page `page_index` of `layout` at `scale` times its drawn size.
+pub fn page_thumbnail(layout: &LayoutTree, config: &LayoutConfig, page_index: usize, scale: f32) -> Option<ThumbnailData> {
+    let page = layout.pages.get(page_index)?;
+    let (width, height) = thumbnail_size(config, page.extent(config), scale);
+    let mut sketch = Sketch { width, height, rgba: PAGE_BACKGROUND.repeat((width * height) as usize), scale };
+    let line_height = config.metrics.font_size * config.metrics.line_height;
+    let (left, right) = (config.margin, config.page_width - config.margin);
+    let mut top = config.margin;
+    for block in &page.blocks {
+        let mut line_y = top;
+        let bar = match block.kind {
+            LayoutKind::Heading(_) => HEADING_BAR,
+            _ => TEXT_BAR,
+        };
+        match block.kind {
+            LayoutKind::Code => sketch.fill(left, top, right, top + block.height, CODE_BACKGROUND),
+            LayoutKind::Figure => {
+                let image_height = block.meta.as_ref().map(|m| m.height).unwrap_or(0.0);
+                sketch.fill(left, top, right, top + image_height, FIGURE_BACKGROUND);
+                line_y += image_height;
+            }
+            _ => {}
+        }
+        for line in &block.lines {
+            if !line.text.trim().is_empty() {
+                let width = line.width.min(right - left);
+                sketch.fill(left, line_y + line_height * 0.3, left + width, line_y + line_height * 0.7, bar);
+            }
+            line_y += line_height;
+        }
+        top += block.height + config.block_gap();
+    }
+    sketch.stroke_border();
+    Some(ThumbnailData { width, height, rgba: sketch.rgba })
+}
+
+/// Pixel size of a thumbnail of a page drawn `extent` high.
+fn thumbnail_size(config: &LayoutConfig, extent: f32, scale: f32) -> (u32, u32) {
+    ((config.page_width * scale).ceil().max(1.0) as u32, (extent * scale).ceil().max(1.0) as u32)
+}
+
+/// Pages of `new` drawn differently from the same page of `old`: holding
+/// other blocks, or blocks laid out again with other lines. Pages `old`
+/// did not have count as changed.
+pub fn diff_layout(old: &LayoutTree, new: &LayoutTree) -> Vec<usize> {
+    new.pages
+        .iter()
+        .enumerate()
+        .filter(|(idx, page)| {
+            let Some(before) = old.pages.get(*idx) else {
+                return true;
+            };
+            before.blocks.len() != page.blocks.len()
+                || before.blocks.iter().zip(&page.blocks).any(|(a, b)| !Arc::ptr_eq(a, b) && a != b)
+        })
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// A thumbnail `ThumbnailCache::update` wants drawn again. The stamp tells
+/// a result apart from one for an older layout of the same page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThumbnailJob {
+    pub page: usize,
+    pub stamp: u64,
+}
+
+/// Thumbnails of one document's pages across layouts. Only pages
+/// `diff_layout` reports changed are drawn again; the rest keep theirs.
+/// Drawing is left to the caller, typically on a worker thread, and a
+/// page's last thumbnail stays up until the new one arrives.
+#[derive(Debug, Default)]
+pub struct ThumbnailCache {
+    layout: Option<LayoutTree>,
+    /// Page width and scale the thumbnails were drawn for.
+    size: Option<(u32, u32)>,
+    slots: Vec<Slot>,
+    next_stamp: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Slot {
+    /// Stamp of the job whose result is still to come, if any.
+    pending: Option<u64>,
+    thumbnail: Option<Arc<ThumbnailData>>,
+}
+
+impl ThumbnailCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes in a new layout and returns the pages to draw again. Another
+    /// page width or scale throws every thumbnail away.
+    pub fn update(&mut self, layout: &LayoutTree, config: &LayoutConfig, scale: f32) -> Vec<ThumbnailJob> {
+        let size = (config.page_width.to_bits(), scale.to_bits());
+        let changed = match self.layout.as_ref().filter(|_| self.size == Some(size)) {
+            Some(old) => diff_layout(old, layout),
+            None => {
+                self.slots.clear();
+                (0..layout.pages.len()).collect()
+            }
+        };
+        self.size = Some(size);
+        self.layout = Some(layout.clone());
+        self.slots.resize_with(layout.pages.len(), Slot::default);
+        changed
+            .into_iter()
+            .map(|page| {
+                self.next_stamp += 1;
+                self.slots[page].pending = Some(self.next_stamp);
+                ThumbnailJob { page, stamp: self.next_stamp }
+            })
+            .collect()
+    }
+
+    /// Stores a drawn thumbnail. Returns false, dropping it, when a newer
+    /// layout of the page came in meanwhile.
+    pub fn insert(&mut self, job: ThumbnailJob, thumbnail: ThumbnailData) -> bool {
+        match self.slots.get_mut(job.page) {
+            Some(slot) if slot.pending == Some(job.stamp) => {
+                slot.pending = None;
+                slot.thumbnail = Some(Arc::new(thumbnail));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The latest thumbnail drawn for `page`, possibly of an older layout.
+    pub fn get(&self, page: usize) -> Option<&Arc<ThumbnailData>> {
+        self.slots.get(page)?.thumbnail.as_ref()
+    }
+
+    /// Whether a thumbnail for the current layout of `page` is still due.
+    pub fn is_pending(&self, page: usize) -> bool {
+        self.slots.get(page).is_some_and(|slot| slot.pending.is_some())
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+struct Sketch {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+    scale: f32,
+}
+
+impl Sketch {
+    /// Fills a rectangle given in page coordinates.
+    fn fill(&mut self, left: f32, top: f32, right: f32, bottom: f32, color: [u8; 4]) {
+        let px = |v: f32, max: u32| ((v * self.scale).round().max(0.0) as u32).min(max);
+        let (x0, x1) = (px(left, self.width), px(right, self.width).max(px(left, self.width) + 1).min(self.width));
+        let (y0, y1) = (px(top, self.height), px(bottom, self.height).max(px(top, self.height) + 1).min(self.height));
+        for y in y0..y1 {
+            for x in x0..x1 {
+                self.put(x, y, color);
+            }
+        }
+    }
+
+    fn stroke_border(&mut self) {
+        for x in 0..self.width {
+            self.put(x, 0, PAGE_BORDER);
+            self.put(x, self.height - 1, PAGE_BORDER);
+        }
+        for y in 0..self.height {
+            self.put(0, y, PAGE_BORDER);
+            self.put(self.width - 1, y, PAGE_BORDER);
+        }
+    }
+
+    fn put(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let at = ((y * self.width + x) * 4) as usize;
+        self.rgba[at..at + 4].copy_from_slice(&color);
+    }
+}
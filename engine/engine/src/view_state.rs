@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use wa_core::{Document, Position};
+
+use crate::{LayoutBlock, LayoutConfig, LayoutTree};
+
+/// The top-level block at the top of the viewport.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScrollAnchor {
+    pub block_id: Uuid,
+    /// Where the block stood in the document, so a neighbour can stand in
+    /// once it is deleted.
+    pub block_index: usize,
+    /// How far down the block the viewport top was, from 0 to 1, so zoom
+    /// and rewrapping do not move the reader.
+    pub fraction: f32,
+}
+
+/// Where a reader was in a document, kept next to it so reopening lands in
+/// the same place.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewState {
+    /// Scroll offset when the state was taken.
+    pub scroll_offset: f32,
+    /// Page at the top of the viewport, counted from 0.
+    pub page: usize,
+    pub anchor: Option<ScrollAnchor>,
+    pub caret: Option<Position>,
+}
+
+impl ViewState {
+    /// The view of `layout` scrolled to `scroll_offset`, with pages drawn
+    /// `page_gap` apart.
+    pub fn capture(doc: &Document, layout: &LayoutTree, config: &LayoutConfig, page_gap: f32, scroll_offset: f32) -> Self {
+        let page = layout.page_at_offset(scroll_offset, config, page_gap);
+        let anchor = layout.block_at_offset(scroll_offset, config, page_gap).and_then(|(block_id, top, height)| {
+            let block_index = doc.blocks.iter().position(|b| b.id() == block_id)?;
+            let fraction = if height > 0.0 { ((scroll_offset - top) / height).clamp(0.0, 1.0) } else { 0.0 };
+            Some(ScrollAnchor { block_id, block_index, fraction })
+        });
+        Self { scroll_offset, page, anchor, caret: None }
+    }
+
+    /// Scroll offset that brings the reader back: the anchor block if it is
+    /// still there, else the block now at its old index, else the saved
+    /// page.
+    pub fn restore_offset(&self, doc: &Document, layout: &LayoutTree, config: &LayoutConfig, page_gap: f32) -> f32 {
+        if let Some(anchor) = self.anchor {
+            if let Some((top, height)) = layout.block_extent(anchor.block_id, config, page_gap) {
+                return top + height * anchor.fraction;
+            }
+            let fallback = doc.blocks.get(anchor.block_index.min(doc.blocks.len().saturating_sub(1)));
+            if let Some((top, _)) = fallback.and_then(|b| layout.block_extent(b.id(), config, page_gap)) {
+                return top;
+            }
+        }
+        layout.page_top(self.page.min(layout.pages.len().saturating_sub(1)), config, page_gap)
+    }
+
+    /// The saved caret, if its block still exists; the offset is clamped to
+    /// the block's text.
+    pub fn caret_in(&self, doc: &Document) -> Option<Position> {
+        let caret = self.caret?;
+        let block = doc.blocks.iter().find(|b| b.id() == caret.block_id)?;
+        let len = wa_core::block_text_len(block);
+        Some(Position { offset: caret.offset.min(len), ..caret })
+    }
+}
+
+impl LayoutTree {
+    /// Top scroll offset and height of the first part of `block_id`, with
+    /// pages `page_gap` apart and blocks spaced the way hit testing does.
+    pub fn block_extent(&self, block_id: Uuid, config: &LayoutConfig, page_gap: f32) -> Option<(f32, f32)> {
+        let mut found = None;
+        self.visit_block_tops(config, page_gap, &mut |block, top| {
+            if block.block_id == block_id {
+                found = Some((top, block.height));
+            }
+            found.is_some()
+        });
+        found
+    }
+
+    /// The block under scroll offset `y` with its top and height. Offsets in
+    /// a margin or gap belong to the next block, past the end to the last.
+    pub fn block_at_offset(&self, y: f32, config: &LayoutConfig, page_gap: f32) -> Option<(Uuid, f32, f32)> {
+        let mut found = None;
+        self.visit_block_tops(config, page_gap, &mut |block, top| {
+            found = Some((block.block_id, top, block.height));
+            y < top + block.height
+        });
+        found
+    }
+
+    /// Calls `f` with each block's id, top and height, as drawn, until it
+    /// returns true.
+    pub(crate) fn visit_block_tops(&self, config: &LayoutConfig, page_gap: f32, f: &mut dyn FnMut(&LayoutBlock, f32) -> bool) {
+        let mut page_top = 0.0;
+        for page in &self.pages {
+            let mut top = page_top + config.margin;
+            for block in &page.blocks {
+                if f(block, top) {
+                    return;
+                }
+                top += block.height + config.block_gap();
+            }
+            page_top += page.extent(config) + page_gap;
+        }
+    }
+}
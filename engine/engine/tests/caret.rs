@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use wa_core::{Affinity, Block, Document, Editor, Inline, Position, Selection};
+use wa_engine::{caret_geometry_with_affinity, move_caret_vertically, LayoutConfig, LayoutEngine};
+
+fn paragraph(text: &str) -> Block {
+    Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false }
+}
+
+#[test]
+fn vertical_moves_return_to_the_goal_column() {
+    let mut doc = Document::new();
+    doc.blocks = vec![paragraph(&"a".repeat(40)), paragraph(&"a".repeat(5)), paragraph(&"a".repeat(40))];
+    let ids: Vec<_> = doc.blocks.iter().map(Block::id).collect();
+    let mut engine = LayoutEngine::new();
+    let config = LayoutConfig::default();
+    let layout = engine.layout(&doc, &config);
+    let mut editor = Editor::new(doc);
+    editor.selection = Selection::collapsed(Position { block_id: ids[0], offset: 37 });
+    let down = |editor: &mut Editor| {
+        assert!(move_caret_vertically(editor, &layout, &config, engine.measurer(), true, false));
+        editor.selection.focus
+    };
+
+    assert_eq!(down(&mut editor), Position { block_id: ids[1], offset: 5 });
+    assert!(editor.goal_x().is_some());
+    assert_eq!(down(&mut editor), Position { block_id: ids[2], offset: 37 });
+    // Past the last line the caret goes to its end.
+    assert_eq!(down(&mut editor), Position { block_id: ids[2], offset: 40 });
+
+    // A horizontal move drops the goal, and so does putting the caret
+    // anywhere by hand.
+    editor.move_caret_by_char(false, false);
+    assert_eq!(editor.goal_x(), None);
+    assert_eq!(editor.selection.focus, Position { block_id: ids[2], offset: 39 });
+    assert!(move_caret_vertically(&mut editor, &layout, &config, engine.measurer(), false, true));
+    assert!(editor.goal_x().is_some());
+    assert_eq!(editor.selection.anchor, Position { block_id: ids[2], offset: 39 });
+    editor.selection = Selection::collapsed(Position { block_id: ids[0], offset: 2 });
+    assert_eq!(editor.goal_x(), None);
+}
+
+#[test]
+fn affinity_picks_the_line_at_a_wrap_point() {
+    let mut doc = Document::new();
+    // CJK text wraps between characters, so the end of one line is the
+    // start of the next.
+    doc.blocks = vec![paragraph(&"中文排版测试".repeat(40))];
+    let id = doc.blocks[0].id();
+    let mut engine = LayoutEngine::new();
+    let config = LayoutConfig::default();
+    let layout = engine.layout(&doc, &config);
+    let block = &layout.pages[0].blocks[0];
+    let wrap = block.lines[1].start;
+    let measurer = engine.measurer();
+
+    let downstream = caret_geometry_with_affinity(block, wrap, Affinity::Downstream, &config, measurer);
+    assert_eq!((downstream.line, downstream.x), (1, 0.0));
+    let upstream = caret_geometry_with_affinity(block, wrap, Affinity::Upstream, &config, measurer);
+    assert_eq!(upstream.line, 0);
+    assert!(upstream.x > 0.0);
+    // Away from a wrap point both sides agree.
+    let inside = |affinity| caret_geometry_with_affinity(block, wrap + 1, affinity, &config, measurer);
+    assert_eq!(inside(Affinity::Upstream), inside(Affinity::Downstream));
+
+    // Moving up from the end of the second line, aiming past the end of
+    // the first, lands at the wrap point and draws on the first line.
+    let mut editor = Editor::new(doc.clone());
+    editor.place_caret(Position { block_id: id, offset: block.lines[2].start }, Affinity::Upstream, Some(f32::MAX), false);
+    assert!(move_caret_vertically(&mut editor, &layout, &config, measurer, false, false));
+    assert_eq!(editor.selection.focus.offset, wrap);
+    assert_eq!(editor.caret_affinity(), Affinity::Upstream);
+    assert_eq!(caret_geometry_with_affinity(block, wrap, editor.caret_affinity(), &config, measurer).line, 0);
+}
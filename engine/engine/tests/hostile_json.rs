@@ -0,0 +1,80 @@
+use proptest::prelude::*;
+use serde_json::json;
+use wa_core::{import_json, validate_doc, Block, Document, FigureSize};
+use wa_engine::{LayoutConfig, LayoutEngine, LayoutTree};
+
+fn assert_finite(tree: &LayoutTree) {
+    for page in &tree.pages {
+        assert!(page.height.is_finite(), "page {} height {}", page.number, page.height);
+        for block in &page.blocks {
+            assert!(block.height.is_finite(), "block height {}", block.height);
+            if let Some(meta) = &block.meta {
+                assert!(meta.width.is_finite() && meta.height.is_finite(), "{:?}", meta);
+            }
+        }
+    }
+}
+
+fn arb_number() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        Just(0.0),
+        Just(-5.0),
+        Just(1e300),
+        Just(-1e300),
+        Just(f64::from(f32::MAX) * 2.0),
+        -1e6..1e6f64,
+    ]
+}
+
+fn arb_block() -> impl Strategy<Value = serde_json::Value> {
+    let cell = json!({ "content": [{ "type": "text", "value": "格" }] });
+    prop_oneof![
+        (arb_number(), arb_number()).prop_map(|(width, height)| json!({
+            "type": "figure", "id": uuid::Uuid::new_v4(), "url": "local://x", "caption": null,
+            "size": { "width": width, "height": height }, "dirty": false,
+        })),
+        any::<u8>().prop_map(|level| json!({
+            "type": "heading", "id": uuid::Uuid::new_v4(), "level": level,
+            "content": [{ "type": "text", "value": "标题" }], "dirty": false,
+        })),
+        (prop::collection::vec(0..4usize, 0..4), prop::collection::vec(arb_number(), 0..4)).prop_map(move |(rows, widths)| json!({
+            "type": "table", "id": uuid::Uuid::new_v4(), "header": false, "col_widths": widths, "dirty": false,
+            "rows": rows.iter().map(|&n| vec![cell.clone(); n]).collect::<Vec<_>>(),
+        })),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn hostile_json_lays_out_finite(blocks in prop::collection::vec(arb_block(), 1..8), nest in any::<bool>()) {
+        let blocks = if nest {
+            vec![json!({ "type": "quote", "id": uuid::Uuid::new_v4(), "content": blocks, "dirty": false })]
+        } else {
+            blocks
+        };
+        let raw = json!({
+            "id": uuid::Uuid::new_v4(), "version": 1, "blocks": blocks,
+            "metadata": { "title": "", "author": "", "created_at": 0, "updated_at": 0 },
+        });
+        let doc = import_json(&raw.to_string()).unwrap();
+        for config in [LayoutConfig::default(), LayoutConfig { paged: false, ..LayoutConfig::default() }] {
+            assert_finite(&LayoutEngine::new().layout(&doc, &config));
+        }
+    }
+}
+
+#[test]
+fn nan_sizes_are_dropped_before_layout() {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Figure {
+        id: uuid::Uuid::new_v4(),
+        url: "local://nan".into(),
+        caption: None,
+        size: Some(FigureSize { width: f32::NAN, height: f32::INFINITY }),
+        dirty: false,
+        locked: false,
+    });
+    assert_eq!(validate_doc(&mut doc).len(), 1);
+    assert!(matches!(doc.blocks[0], Block::Figure { size: None, .. }));
+    assert_finite(&LayoutEngine::new().layout(&doc, &LayoutConfig::default()));
+}
@@ -1,5 +1,8 @@
-﻿use wa_engine::{ImageCache, LayoutCache, LayoutConfig, LayoutEngine};
-use wa_core::{Block, Document, Inline};
+﻿use wa_engine::{
+    caret_geometry, decode_image, encode_png, BlockHandler, EngineOptions, FontMetrics, ImageCache, LayoutCache, LayoutConfig, LayoutEngine, LayoutKind,
+    LayoutStats, MathMeasurer, SimpleMeasurer, TextMeasurer, PLACEHOLDER_LINES,
+};
+use wa_core::{Block, CodeText, Document, Editor, EditorCommand, Inline, Style};
 use std::sync::Arc;
 
 #[test]
@@ -9,6 +12,7 @@ fn layout_paged_vs_scroll() {
         id: uuid::Uuid::new_v4(),
         content: vec![Inline::Text { value: Arc::from("测试分页与滚动布局") }],
         dirty: false,
+        locked: false,
     });
     let mut engine = LayoutEngine::new();
 
@@ -24,7 +28,7 @@ fn layout_paged_vs_scroll() {
 
 #[test]
 fn image_cache_basic() {
-    let mut cache = ImageCache::new();
+    let cache = ImageCache::new();
     let a = cache.load("local://a");
     let b = cache.load("local://a");
     assert_eq!(a.key, b.key);
@@ -37,6 +41,7 @@ fn layout_cache_reuse() {
         id: uuid::Uuid::new_v4(),
         content: vec![Inline::Text { value: Arc::from("缓存复用测试") }],
         dirty: false,
+        locked: false,
     });
     let mut engine = LayoutEngine::new();
     let mut cache = LayoutCache::new();
@@ -68,6 +73,7 @@ fn cjk_forbidden_line_start_end() {
         id: uuid::Uuid::new_v4(),
         content: vec![Inline::Text { value: Arc::from("测试（禁则），应该避免行首标点。") }],
         dirty: false,
+        locked: false,
     });
     let mut engine = LayoutEngine::new();
     let config = LayoutConfig {
@@ -92,3 +98,543 @@ fn cjk_forbidden_line_start_end() {
         }
     }
 }
+
+#[test]
+fn styled_inlines_produce_runs() {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![
+            Inline::Text { value: Arc::from("plain ") },
+            Inline::Styled {
+                style: Style { bold: true, ..Style::default() },
+                content: vec![
+                    Inline::Text { value: Arc::from("bold ") },
+                    Inline::Styled {
+                        style: Style { strikethrough: true, ..Style::default() },
+                        content: vec![Inline::Text { value: Arc::from("struck") }],
+                    },
+                ],
+            },
+            Inline::Link {
+                url: Arc::from("https://example.com"),
+                text: vec![Inline::Text { value: Arc::from(" link") }],
+            },
+        ],
+        dirty: false,
+        locked: false,
+    });
+    let mut engine = LayoutEngine::new();
+    let tree = engine.layout(&doc, &LayoutConfig::default());
+    let line = &tree.pages[0].blocks[0].lines[0];
+    let pieces: Vec<&str> = line.runs.iter().map(|r| &line.text[r.range.clone()]).collect();
+    assert_eq!(pieces, vec!["plain ", "bold ", "struck", " link"]);
+    assert!(line.runs[0].style.is_plain());
+    assert!(line.runs[1].style.bold && !line.runs[1].style.strikethrough);
+    assert!(line.runs[2].style.bold && line.runs[2].style.strikethrough);
+    assert_eq!(line.runs[3].link.as_deref(), Some("https://example.com"));
+    let total: f32 = line.runs.iter().map(|r| r.width).sum();
+    assert!((total - line.width).abs() < 1.0);
+
+    let mut plain = Document::new();
+    plain.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![Inline::Text { value: Arc::from("no styles here") }],
+        dirty: false,
+        locked: false,
+    });
+    let tree = engine.layout(&plain, &LayoutConfig::default());
+    assert!(tree.pages[0].blocks[0].lines[0].runs.is_empty());
+}
+
+/// Sets every formula piece 10px wide.
+struct FixedMath;
+
+impl MathMeasurer for FixedMath {
+    fn measure(&self, _tex: &str, _text: &dyn TextMeasurer, _metrics: FontMetrics) -> f32 {
+        10.0
+    }
+}
+
+#[test]
+fn math_lays_out_as_italic_source() {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![Inline::Text { value: Arc::from("设 ") }, Inline::Math { tex: Arc::from("x^2"), display: false }],
+        dirty: false,
+        locked: false,
+    });
+    let mut engine = LayoutEngine::new();
+    let tree = engine.layout(&doc, &LayoutConfig::default());
+    let line = &tree.pages[0].blocks[0].lines[0];
+    assert_eq!(line.text, "设 x^2");
+    let math = &line.runs[1];
+    assert_eq!(&line.text[math.range.clone()], "x^2");
+    assert!(math.style.italic && !math.code);
+    assert!((math.width - engine.measurer().measure("x^2", LayoutConfig::default().metrics)).abs() < 0.01);
+
+    engine.set_math_measurer(FixedMath);
+    let tree = engine.layout(&doc, &LayoutConfig::default());
+    assert_eq!(tree.pages[0].blocks[0].lines[0].runs[1].width, 10.0);
+}
+
+#[test]
+fn caret_geometry_follows_wrapped_lines() {
+    let mut doc = Document::new();
+    let text = "word ".repeat(80);
+    doc.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![Inline::Text { value: Arc::from(text.as_str()) }],
+        dirty: false,
+        locked: false,
+    });
+    let mut engine = LayoutEngine::new();
+    let config = LayoutConfig::default();
+    let tree = engine.layout(&doc, &config);
+    let block = &tree.pages[0].blocks[0];
+    assert!(block.lines.len() > 1);
+    let measurer = SimpleMeasurer;
+
+    let start = caret_geometry(block, 0, &config, &measurer);
+    assert_eq!(start.line, 0);
+    assert_eq!(start.x, 0.0);
+
+    let second = block.lines[1].start;
+    assert!(second > 0);
+    let at_second = caret_geometry(block, second + 2, &config, &measurer);
+    assert_eq!(at_second.line, 1);
+    assert!(at_second.x > 0.0);
+    assert!(at_second.y > start.y);
+
+    let past_end = caret_geometry(block, text.chars().count() + 10, &config, &measurer);
+    assert_eq!(past_end.line, block.lines.len() - 1);
+}
+
+fn png_bytes(width: u32, height: u32) -> Vec<u8> {
+    let img = image::RgbaImage::from_pixel(width, height, image::Rgba([200, 10, 10, 255]));
+    let mut out = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+    out.into_inner()
+}
+
+#[test]
+fn image_cache_reads_intrinsic_size() {
+    use base64::Engine as _;
+    let uri = format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png_bytes(3, 2))
+    );
+    let decoded = decode_image(&uri).unwrap();
+    assert_eq!((decoded.width, decoded.height), (3, 2));
+    assert_eq!(decoded.rgba.len(), 3 * 2 * 4);
+
+    let path = std::env::temp_dir().join(format!("wa_image_{}.png", uuid::Uuid::new_v4()));
+    std::fs::write(&path, png_bytes(40, 30)).unwrap();
+    let cache = ImageCache::new();
+    let asset = cache.load_from_path(&path);
+    assert_eq!((asset.width, asset.height), (40.0, 30.0));
+
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Figure {
+        id: uuid::Uuid::new_v4(),
+        url: Arc::from(format!("file://{}", path.display())),
+        caption: None,
+        size: None,
+        dirty: false,
+        locked: false,
+    });
+    let mut engine = LayoutEngine::new();
+    let tree = engine.layout(&doc, &LayoutConfig::default());
+    let meta = tree.pages[0].blocks[0].meta.as_ref().unwrap();
+    assert_eq!((meta.width, meta.height), (40.0, 30.0));
+    let _ = std::fs::remove_file(&path);
+
+    assert!(decode_image("local://missing").is_err());
+    assert!(decode_image("data:image/png;base64,@@@").is_err());
+}
+
+#[test]
+fn pasted_pixels_lay_out_at_their_size() {
+    let png = encode_png(5, 4, &[7; 5 * 4 * 4]).unwrap();
+    let mut editor = Editor::new(Document::new());
+    editor.execute(EditorCommand::InsertImageData { bytes: png, mime: "image/png".to_string(), caption: None });
+    let Block::Figure { url, .. } = &editor.doc.blocks[0] else { panic!("expected a figure") };
+    let decoded = decode_image(url).unwrap();
+    assert_eq!((decoded.width, decoded.height), (5, 4));
+    assert_eq!(decoded.rgba.as_slice(), &[7; 5 * 4 * 4][..]);
+
+    let mut engine = LayoutEngine::new();
+    let tree = engine.layout(&editor.doc, &LayoutConfig::default());
+    let meta = tree.pages[0].blocks[0].meta.as_ref().unwrap();
+    assert_eq!((meta.width, meta.height), (5.0, 4.0));
+}
+
+#[test]
+fn inserted_images_keep_their_size_after_eviction() {
+    let key = "local://logo";
+    let png = png_bytes(60, 20);
+    let mut engine = LayoutEngine::new();
+    let asset = engine.images().insert_bytes(key, &png).unwrap();
+    assert_eq!((asset.width, asset.height), (60.0, 20.0));
+    assert_eq!(engine.images().resident_bytes(), 60 * 20 * 4);
+
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Figure { id: uuid::Uuid::new_v4(), url: Arc::from(key), caption: None, size: None, dirty: false, locked: false });
+    let config = LayoutConfig::default();
+    let figure_size = |engine: &mut LayoutEngine| {
+        let tree = engine.layout(&doc, &config);
+        let meta = tree.pages[0].blocks[0].meta.as_ref().unwrap();
+        (meta.width, meta.height)
+    };
+    assert_eq!(figure_size(&mut engine), (60.0, 20.0));
+
+    assert!(engine.images().evict(key));
+    assert!(!engine.images().is_resident(key));
+    assert_eq!(engine.images().resident_bytes(), 0);
+    assert_eq!(engine.images().get(key).map(|a| (a.width, a.height)), Some((60.0, 20.0)));
+    assert_eq!(figure_size(&mut engine), (60.0, 20.0));
+    // The pixels come back from the inserted bytes.
+    let pixels = engine.images().pixels(key).unwrap();
+    assert_eq!((pixels.width, pixels.height), (60, 20));
+    assert!(engine.images().is_resident(key));
+
+    assert!(engine.images().insert_bytes("local://broken", b"not an image").is_err());
+    assert!(engine.images().get("local://broken").is_none());
+}
+
+#[test]
+fn image_budget_evicts_least_recently_used_pixels() {
+    let cache = ImageCache::with_budget(2 * 10 * 10 * 4);
+    for key in ["a", "b"] {
+        cache.insert_bytes(key, &png_bytes(10, 10)).unwrap();
+    }
+    // Using `a` makes `b` the one to go.
+    cache.pixels("a").unwrap();
+    cache.insert_bytes("c", &png_bytes(10, 10)).unwrap();
+    assert!(cache.is_resident("a") && !cache.is_resident("b") && cache.is_resident("c"));
+    assert_eq!(cache.get("b").map(|a| a.width), Some(10.0));
+
+    // Clones share the cache.
+    cache.clone().set_budget(10 * 10 * 4);
+    assert_eq!(cache.resident_bytes(), 10 * 10 * 4);
+    assert!(cache.is_resident("c"));
+}
+
+#[test]
+fn undo_and_redo_refresh_cached_layout() {
+    let mut doc = Document::new();
+    let id = uuid::Uuid::new_v4();
+    doc.blocks.push(Block::Paragraph { id, content: vec![Inline::Text { value: Arc::from("撤销") }], dirty: false, locked: false });
+    let mut editor = Editor::new(doc);
+    editor.selection = wa_core::Selection::collapsed(wa_core::Position { block_id: id, offset: 2 });
+    let mut engine = LayoutEngine::new();
+    let mut cache = LayoutCache::new();
+    let config = LayoutConfig::default();
+    let mut shown = |editor: &mut Editor, last: &mut u64| {
+        assert!(editor.doc.version > *last, "layout would not refresh");
+        *last = editor.doc.version;
+        let tree = engine.layout_cached_with_signatures(&editor.doc, &config, &mut cache, &editor.signatures);
+        editor.doc.clear_dirty();
+        tree.pages.iter().flat_map(|p| &p.blocks).flat_map(|b| &b.lines).map(|l| l.text.to_string()).collect::<Vec<_>>()
+    };
+    let mut last = 0;
+    assert_eq!(shown(&mut editor, &mut last), vec!["撤销"]);
+
+    editor.execute(EditorCommand::InsertText("重做".to_string()));
+    assert_eq!(shown(&mut editor, &mut last), vec!["撤销重做"]);
+    editor.execute(EditorCommand::Undo);
+    assert_eq!(shown(&mut editor, &mut last), vec!["撤销"]);
+    editor.execute(EditorCommand::Redo);
+    assert_eq!(shown(&mut editor, &mut last), vec!["撤销重做"]);
+
+    // Snapshot entries restore an older version number; it must not go back.
+    editor.execute(EditorCommand::InsertQuote("引用".to_string()));
+    assert_eq!(shown(&mut editor, &mut last), vec!["撤销重做", "引用"]);
+    editor.execute(EditorCommand::Undo);
+    assert!(!editor.doc.blocks[0].is_dirty(), "unchanged blocks keep their layout");
+    assert_eq!(shown(&mut editor, &mut last), vec!["撤销重做"]);
+}
+
+#[cfg(feature = "highlight")]
+#[test]
+fn code_blocks_get_highlight_runs() {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Code {
+        id: uuid::Uuid::new_v4(),
+        lang: Arc::from("rs"),
+        code: CodeText::from("fn main() {\n    let x = 1;\n}"),
+        dirty: false,
+        locked: false,
+    });
+    doc.blocks.push(Block::Code {
+        id: uuid::Uuid::new_v4(),
+        lang: Arc::from("no-such-language"),
+        code: CodeText::from("plain"),
+        dirty: false,
+        locked: false,
+    });
+    let mut engine = LayoutEngine::new();
+    let tree = engine.layout(&doc, &LayoutConfig::default());
+    let rust = &tree.pages[0].blocks[0];
+    assert_eq!(rust.lines.len(), 3);
+    for line in &rust.lines {
+        let covered: String = line.runs.iter().map(|r| &line.text[r.range.clone()]).collect();
+        assert_eq!(covered, line.text);
+    }
+    assert!(rust.lines[0].runs.len() > 1);
+    assert!(rust.lines[0].runs.iter().all(|r| r.color.is_some()));
+    assert!(tree.pages[0].blocks[1].lines[0].runs.is_empty());
+}
+
+#[test]
+fn page_of_finds_block_page() {
+    let mut doc = Document::new();
+    for i in 0..80 {
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(format!("第 {} 段", i)) }],
+            dirty: false,
+            locked: false,
+        });
+    }
+    let layout = LayoutEngine::new().layout(&doc, &LayoutConfig { paged: true, ..LayoutConfig::default() });
+    assert!(layout.pages.len() > 1);
+    let last = doc.blocks.last().unwrap().id();
+    assert_eq!(layout.page_of(doc.blocks[0].id()), Some(0));
+    assert_eq!(layout.page_of(last), Some(layout.pages.len() - 1));
+    assert_eq!(layout.page_of(uuid::Uuid::new_v4()), None);
+}
+
+#[test]
+fn zoomed_layout_keeps_line_breaks() {
+    let mut doc = Document::new();
+    doc.blocks.push(Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![Inline::Text { value: Arc::from("zoom should scale every measurement together so wrapping stays put ".repeat(4)) }],
+        dirty: false,
+        locked: false,
+    });
+    let base = LayoutConfig { page_width: 320.0, ..LayoutConfig::default() };
+    let zoomed = base.clone().zoomed(2.0);
+    assert_eq!(zoomed.page_width, 640.0);
+    assert_eq!(zoomed.metrics.font_size, base.metrics.font_size * 2.0);
+    assert_eq!(zoomed.clone().zoomed(1.0).margin, base.margin);
+
+    let mut engine = LayoutEngine::new();
+    let lines = |tree: wa_engine::LayoutTree| {
+        tree.pages[0].blocks[0].lines.iter().map(|l| l.text.clone()).collect::<Vec<_>>()
+    };
+    let at_base = lines(engine.layout(&doc, &base));
+    assert!(at_base.len() > 1);
+    assert_eq!(lines(engine.layout(&doc, &zoomed)), at_base);
+}
+
+#[test]
+fn prewarm_resumes_where_it_stopped() {
+    let mut doc = Document::new();
+    for text in ["abcdef", "ghijkl", "一二三四五六", "mnop"] {
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(text) }],
+            dirty: true,
+            locked: false,
+        });
+    }
+    let mut engine = LayoutEngine::new();
+    let config = LayoutConfig { prewarm_limit: 8, ..LayoutConfig::default() };
+    engine.layout(&doc, &config);
+    let first = engine.prewarm_coverage();
+    assert_eq!(first.chars, 8);
+    assert_eq!(first.blocks, 1);
+    assert!(!first.complete);
+
+    doc.touch();
+    engine.layout(&doc, &config);
+    let second = engine.prewarm_coverage();
+    assert_eq!(second.chars, 16);
+    assert_eq!(second.blocks, 2);
+
+    let rest = engine.prewarm_in_background(&doc, config.metrics).expect("blocks left to warm");
+    assert_eq!(rest.join().unwrap(), 6);
+    assert!(engine.prewarm_coverage().complete);
+    assert_eq!(engine.prewarm_coverage().blocks, 4);
+    assert!(engine.prewarm_in_background(&doc, config.metrics).is_none());
+}
+
+#[test]
+fn custom_blocks_use_their_handler_or_a_placeholder() {
+    struct Fixed;
+    impl BlockHandler for Fixed {
+        fn measure(&self, data: &serde_json::Value, _: &LayoutConfig) -> (f32, Vec<String>) {
+            (data["height"].as_f64().unwrap_or(0.0) as f32, vec!["chart".to_string()])
+        }
+    }
+    let custom = |kind: &str, data: serde_json::Value| Block::Custom {
+        id: uuid::Uuid::new_v4(),
+        kind: Arc::from(kind),
+        data,
+        dirty: false,
+        locked: false,
+    };
+    let mut doc = Document::new();
+    doc.blocks.push(custom("callout", serde_json::json!({ "title": "注意", "text": "第一行\n第二行" })));
+    doc.blocks.push(custom("chart", serde_json::json!({ "height": 240 })));
+    let config = LayoutConfig::default();
+    let line = config.metrics.font_size * config.metrics.line_height;
+
+    let mut engine = LayoutEngine::new();
+    let tree = engine.layout(&doc, &config);
+    let blocks = &tree.pages[0].blocks;
+    assert!(matches!(&blocks[0].kind, LayoutKind::Custom(kind) if kind.as_ref() == "callout"));
+    assert_eq!(blocks[0].lines.iter().map(|l| l.text.as_str()).collect::<Vec<_>>(), vec!["注意", "第一行", "第二行"]);
+    assert_eq!(blocks[0].height, 4.0 * line);
+    assert_eq!(blocks[1].lines[0].text, "[chart]");
+    assert_eq!(blocks[1].height, PLACEHOLDER_LINES as f32 * line);
+
+    engine.register_block_handler("chart", Fixed);
+    let tree = engine.layout(&doc, &config);
+    assert_eq!(tree.pages[0].blocks[1].height, 240.0);
+}
+
+#[test]
+fn stats_count_reuse_and_reset_on_take() {
+    let mut doc = Document::new();
+    for text in ["第一段", "second paragraph", "第三段"] {
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(text) }],
+            dirty: false,
+            locked: false,
+        });
+    }
+    let config = LayoutConfig::default();
+    let mut engine = LayoutEngine::new();
+    let mut cache = LayoutCache::new();
+    engine.take_stats();
+
+    engine.layout_cached(&doc, &config, &mut cache);
+    engine.layout_cached(&doc, &config, &mut cache);
+    let stats = engine.take_stats();
+    assert_eq!((stats.calls, stats.blocks_laid_out, stats.blocks_reused, stats.pages), (2, 3, 3, 1));
+    assert_eq!(stats.break_cache_hits + stats.break_cache_misses, 3);
+    assert_eq!(stats.total_time(), std::time::Duration::ZERO);
+    assert_eq!(engine.take_stats(), LayoutStats::default());
+
+    engine.set_collect_timings(true);
+    engine.layout(&doc, &config);
+    let stats = engine.take_stats();
+    assert_eq!((stats.calls, stats.blocks_laid_out, stats.break_cache_hits), (1, 3, 3));
+    assert_eq!(stats.break_hit_rate(), Some(1.0));
+    assert!(stats.total_time() > std::time::Duration::ZERO);
+}
+
+#[test]
+fn low_spec_options_shrink_the_break_cache() {
+    let mut doc = Document::new();
+    for i in 0..2000 {
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(format!("段落 {i}")) }],
+            dirty: false,
+            locked: false,
+        });
+    }
+    let config = LayoutConfig::default();
+    let second_pass_hits = |options: EngineOptions| {
+        let mut engine = LayoutEngine::with_options(options.clone());
+        assert_eq!(engine.options(), &options);
+        engine.layout(&doc, &config);
+        engine.take_stats();
+        engine.layout(&doc, &config);
+        engine.take_stats().break_cache_hits
+    };
+    assert_eq!(second_pass_hits(EngineOptions::default()), 2000);
+    assert!(second_pass_hits(EngineOptions { low_spec: true, ..EngineOptions::default() }) < 2000);
+}
+
+#[test]
+fn page_break_ends_its_page() {
+    let mut doc = Document::new();
+    for text in ["第一段", "第二段"] {
+        doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false });
+    }
+    let mut editor = Editor::new(doc);
+    editor.selection = wa_core::Selection::collapsed(wa_core::Position { block_id: editor.doc.blocks[0].id(), offset: 0 });
+    editor.execute(EditorCommand::InsertPageBreak);
+    assert!(matches!(editor.doc.blocks[1], Block::PageBreak { .. }));
+    let doc = wa_core::import_markdown(&wa_core::export_markdown(&editor.doc));
+    assert!(matches!(doc.blocks[1], Block::PageBreak { .. }));
+
+    let mut engine = LayoutEngine::new();
+    let paged = engine.layout(&doc, &LayoutConfig::default());
+    assert_eq!(paged.pages.len(), 2);
+    assert_eq!(paged.pages[0].blocks.last().unwrap().kind, LayoutKind::PageBreak);
+    assert_eq!(paged.pages[0].blocks.last().unwrap().height, 0.0);
+    assert_eq!(paged.pages[1].blocks.len(), 1);
+    let scroll = engine.layout(&doc, &LayoutConfig { paged: false, ..LayoutConfig::default() });
+    assert_eq!(scroll.pages.len(), 1);
+
+    // A break at the very end leaves no empty page behind it.
+    editor.selection = wa_core::Selection::collapsed(wa_core::Position { block_id: editor.doc.blocks[2].id(), offset: 0 });
+    editor.execute(EditorCommand::InsertPageBreak);
+    assert_eq!(engine.layout(&editor.doc, &LayoutConfig::default()).pages.len(), 2);
+}
+
+#[test]
+fn line_numbers_run_on_across_pages() {
+    let mut doc = Document::new();
+    for i in 0..30 {
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(format!("第 {i} 段，").repeat(if i % 3 == 0 { 12 } else { 1 })) }],
+            dirty: false,
+            locked: false,
+        });
+    }
+    let config = LayoutConfig { page_width: 300.0, page_height: 300.0, margin: 10.0, line_numbers: true, ..LayoutConfig::default() };
+    let numbers = |tree: &wa_engine::LayoutTree| -> Vec<Option<usize>> {
+        tree.pages.iter().flat_map(|p| &p.blocks).flat_map(|b| &b.lines).map(|l| l.number).collect()
+    };
+    let mut engine = LayoutEngine::new();
+    let mut cache = LayoutCache::new();
+    let first = engine.layout_cached(&doc, &config, &mut cache);
+    assert!(first.pages.len() > 2);
+    let expected: Vec<Option<usize>> = (1..=numbers(&first).len()).map(Some).collect();
+    assert!(expected.len() > doc.blocks.len());
+    assert_eq!(numbers(&first), expected);
+
+    // Blocks taken from the cache are numbered too, and the cache itself
+    // is left unnumbered.
+    let second = engine.layout_cached(&doc, &config, &mut cache);
+    assert_eq!(numbers(&second), expected);
+    let plain = engine.layout_cached(&doc, &LayoutConfig { line_numbers: false, ..config.clone() }, &mut cache);
+    assert!(numbers(&plain).iter().all(Option::is_none));
+    assert_eq!(numbers(&engine.layout(&doc, &config)), expected);
+}
+
+#[test]
+fn task_items_show_their_box_instead_of_a_number() {
+    let mut doc = wa_core::import_markdown("1. first\n2. [x] done\n3. [ ] todo");
+    let mut engine = LayoutEngine::new();
+    let mut cache = LayoutCache::new();
+    let config = LayoutConfig::default();
+    let texts = |tree: &wa_engine::LayoutTree| tree.pages[0].blocks[0].lines.iter().map(|l| l.text.clone()).collect::<Vec<_>>();
+    assert_eq!(texts(&engine.layout_cached(&doc, &config, &mut cache)), ["1 first", "[x] done", "[ ] todo"]);
+
+    // Ticking an item is seen through the cache.
+    if let Block::List { items, dirty, .. } = &mut doc.blocks[0] {
+        items[2].checked = Some(true);
+        *dirty = true;
+    }
+    assert_eq!(texts(&engine.layout_cached(&doc, &config, &mut cache)), ["1 first", "[x] done", "[x] todo"]);
+}
+
+#[test]
+fn nested_list_items_indent_by_the_configured_width() {
+    let doc = wa_core::import_markdown("- a\n  - b\n    - c");
+    let mut engine = LayoutEngine::new();
+    let mut texts = |config: &LayoutConfig| engine.layout(&doc, config).pages[0].blocks[0].lines.iter().map(|l| l.text.clone()).collect::<Vec<_>>();
+    assert_eq!(texts(&LayoutConfig::default()), ["1 a", "\u{3000}\u{3000}2 b", "\u{3000}\u{3000}\u{3000}\u{3000}3 c"]);
+    assert_eq!(texts(&LayoutConfig { list_indent: 1, ..LayoutConfig::default() }), ["1 a", "\u{3000}2 b", "\u{3000}\u{3000}3 c"]);
+}
@@ -0,0 +1,168 @@
+use proptest::prelude::*;
+use std::sync::Arc;
+use wa_core::{Block, Cell, CodeText, Document, FigureSize, Inline, ListItem, Style};
+use wa_engine::{EngineOptions, LayoutCache, LayoutConfig, LayoutEngine, LayoutTree};
+
+fn arb_text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-zA-Z]{1,12}( [a-zA-Z,.]{1,12}){0,12}",
+        "[\u{4e00}-\u{4e40}，。、]{1,60}",
+        "[a-z\u{4e00}-\u{4e20} ]{0,80}",
+    ]
+}
+
+fn arb_inlines() -> impl Strategy<Value = Vec<Inline>> {
+    let inline = (arb_text(), 0..4u8).prop_map(|(text, kind)| {
+        let value: Arc<str> = Arc::from(text.as_str());
+        match kind {
+            0 => Inline::Styled { style: Style { bold: true, ..Style::default() }, content: vec![Inline::Text { value }] },
+            1 => Inline::Link { url: Arc::from("https://example.com"), text: vec![Inline::Text { value }] },
+            2 => Inline::CodeSpan { value },
+            _ => Inline::Text { value },
+        }
+    });
+    prop::collection::vec(inline, 0..4)
+}
+
+fn arb_block() -> impl Strategy<Value = Block> {
+    let id = || uuid::Uuid::new_v4();
+    prop_oneof![
+        (1..4u8, arb_inlines(), any::<bool>()).prop_map(move |(level, content, dirty)| Block::Heading { id: id(), level, content, dirty, locked: false }),
+        (arb_inlines(), any::<bool>()).prop_map(move |(content, dirty)| Block::Paragraph { id: id(), content, dirty, locked: false }),
+        (prop::collection::vec((arb_inlines(), 0..3u8, prop::option::of(any::<bool>())), 1..5), any::<bool>(), any::<bool>()).prop_map(move |(items, ordered, dirty)| Block::List {
+            id: id(),
+            ordered,
+            items: items.into_iter().map(|(content, level, checked)| ListItem { id: id(), content, level, checked }).collect(),
+            dirty,
+            locked: false,
+            toc: false,
+        }),
+        (prop::collection::vec(arb_inlines(), 1..4), any::<bool>()).prop_map(move |(paragraphs, dirty)| Block::Quote {
+            id: id(),
+            content: paragraphs.into_iter().map(|content| Block::Paragraph { id: id(), content, dirty, locked: false }).collect(),
+            dirty,
+            locked: false,
+        }),
+        (prop::collection::vec(arb_text(), 0..6), any::<bool>()).prop_map(move |(lines, dirty)| Block::Code {
+            id: id(),
+            lang: Arc::from("rust"),
+            code: CodeText::from(lines.join("\n").as_str()),
+            dirty,
+            locked: false,
+        }),
+        (prop::collection::vec(prop::collection::vec(arb_inlines(), 1..4), 1..4), any::<bool>()).prop_map(move |(rows, dirty)| Block::Table {
+            id: id(),
+            rows: rows.into_iter().map(|row| row.into_iter().map(|content| Cell { content }).collect()).collect(),
+            header: false,
+            col_widths: Vec::new(),
+            dirty,
+            locked: false,
+        }),
+        (prop::option::of(arb_text()), any::<bool>()).prop_map(move |(caption, dirty)| Block::Figure {
+            id: id(),
+            url: Arc::from("local://missing"),
+            caption: caption.map(|c| Arc::from(c.as_str())),
+            size: Some(FigureSize { width: 200.0, height: 120.0 }),
+            dirty,
+            locked: false,
+        }),
+        any::<bool>().prop_map(move |dirty| Block::PageBreak { id: id(), dirty, locked: false }),
+    ]
+}
+
+fn config(line_numbers: bool) -> LayoutConfig {
+    LayoutConfig { page_height: 400.0, line_numbers, ..LayoutConfig::default() }
+}
+
+fn engine(parallel: bool) -> LayoutEngine {
+    LayoutEngine::with_options(EngineOptions { parallel, ..EngineOptions::default() })
+}
+
+/// Page breaks, then every block's lines and height, bit for bit.
+fn assert_same(expected: &LayoutTree, actual: &LayoutTree, path: &str) {
+    assert_eq!(expected.pages.len(), actual.pages.len(), "{path}: page count");
+    for (want, got) in expected.pages.iter().zip(&actual.pages) {
+        let ids = |page: &wa_engine::Page| page.blocks.iter().map(|b| b.block_id).collect::<Vec<_>>();
+        assert_eq!(ids(want), ids(got), "{path}: blocks on page {}", want.number);
+        for (a, b) in want.blocks.iter().zip(&got.blocks) {
+            assert_eq!(a.height.to_bits(), b.height.to_bits(), "{path}: height of {}", a.block_id);
+            let lines = |block: &wa_engine::LayoutBlock| {
+                block.lines.iter().map(|l| (l.text.clone(), l.start, l.width.to_bits(), l.number)).collect::<Vec<_>>()
+            };
+            assert_eq!(lines(a), lines(b), "{path}: lines of {}", a.block_id);
+        }
+    }
+}
+
+/// Lays `doc` out along every path and checks they agree, then edits it
+/// and checks a warm cache agrees with a cold one.
+fn check_paths(mut doc: Document, edits: &[(usize, Block)], line_numbers: bool) {
+    let config = config(line_numbers);
+    let reference = engine(false).layout(&doc, &config);
+    let mut cache = LayoutCache::new();
+    let mut cached = engine(false);
+    assert_same(&reference, &cached.layout_cached(&doc, &config, &mut cache), "cold cache");
+
+    for (at, block) in edits {
+        let at = at % doc.blocks.len();
+        let mut block = block.clone();
+        block.set_dirty(true);
+        doc.blocks[at] = block;
+    }
+    doc.touch();
+    let reference = engine(false).layout(&doc, &config);
+    assert_same(&reference, &cached.layout_cached(&doc, &config, &mut cache), "warm cache");
+    assert_same(&reference, &engine(false).layout_cached(&doc, &config, &mut LayoutCache::new()), "cold cache after edits");
+
+    #[cfg(feature = "parallel")]
+    {
+        assert_same(&reference, &engine(false).layout_parallel(&doc, &config), "parallel");
+        // The parallel paths only kick in for large documents.
+        let mut big = doc.clone();
+        while big.blocks.len() <= wa_engine::PARALLEL_MIN_BLOCKS {
+            big.blocks.extend(doc.blocks.iter().map(|b| {
+                let mut b = b.clone();
+                *b.id_mut() = uuid::Uuid::new_v4();
+                b
+            }));
+        }
+        let reference = engine(false).layout(&big, &config);
+        assert_same(&reference, &engine(true).layout(&big, &config), "parallel layout");
+        let mut cache = LayoutCache::new();
+        let mut parallel = engine(true);
+        assert_same(&reference, &parallel.layout_cached(&big, &config, &mut cache), "parallel cold cache");
+        assert_same(&reference, &parallel.layout_cached(&big, &config, &mut cache), "parallel warm cache");
+    }
+}
+
+fn arb_case() -> impl Strategy<Value = (Vec<Block>, Vec<(usize, Block)>, bool)> {
+    (
+        prop::collection::vec(arb_block(), 1..24),
+        prop::collection::vec((any::<usize>(), arb_block()), 0..4),
+        any::<bool>(),
+    )
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(24))]
+
+    #[test]
+    fn layout_paths_agree((blocks, edits, line_numbers) in arb_case()) {
+        let mut doc = Document::new();
+        doc.blocks = blocks;
+        check_paths(doc, &edits, line_numbers);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(1000))]
+
+    /// The same check over many more documents: `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn layout_paths_agree_long((blocks, edits, line_numbers) in arb_case()) {
+        let mut doc = Document::new();
+        doc.blocks = blocks;
+        check_paths(doc, &edits, line_numbers);
+    }
+}
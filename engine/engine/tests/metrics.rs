@@ -1,4 +1,4 @@
-use wa_engine::{FontMetrics, RealMeasurer, TextMeasurer};
+use wa_engine::{FontContext, FontMetrics, LayoutConfig, LayoutEngine, RealMeasurer, TextMeasurer};
 
 #[test]
 fn font_cache_hit_rate_smoke() {
@@ -11,3 +11,39 @@ fn font_cache_hit_rate_smoke() {
         assert!(rate > 0.95, "glyph cache hit rate too low: {}", rate);
     }
 }
+
+#[test]
+fn second_consumer_of_a_context_starts_warm() {
+    let Ok(bytes) = std::fs::read("/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf") else {
+        return;
+    };
+    let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default()).unwrap();
+    let context = FontContext::new(font, 1024);
+    let metrics = FontMetrics::default();
+    let text = "Größe ändern — ".repeat(20);
+
+    let first = RealMeasurer::from_context(&context);
+    let width = first.measure(&text, metrics);
+    let cold = context.glyph_stats().unwrap();
+    assert!(cold.misses > 0);
+
+    let second = RealMeasurer::from_context(&context);
+    assert_eq!(second.measure(&text, metrics), width);
+    let warm = context.glyph_stats().unwrap();
+    assert_eq!(warm.misses, cold.misses);
+    assert!(warm.hits > cold.hits);
+
+    // Layout prewarming fills the same cache the measurers read.
+    let mut engine = LayoutEngine::with_context(&context);
+    let mut doc = wa_core::Document::new();
+    doc.blocks.push(wa_core::Block::Paragraph {
+        id: uuid::Uuid::new_v4(),
+        content: vec![wa_core::Inline::Text { value: std::sync::Arc::from("Ωmega") }],
+        dirty: true,
+        locked: false,
+    });
+    engine.layout(&doc, &LayoutConfig::default());
+    let misses = context.glyph_stats().unwrap().misses;
+    second.measure(&"Ωmega ".repeat(30), metrics);
+    assert_eq!(context.glyph_stats().unwrap().misses, misses);
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "raster")]
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use wa_core::{import_markdown, Block, Cell, Document, Inline};
+use wa_engine::{render_page, render_png, FontContext, LayoutConfig, LayoutEngine};
+
+/// Share of pixels allowed to differ before a golden comparison fails.
+const TOLERANCE: f64 = 0.002;
+
+fn fixture() -> Document {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut doc = import_markdown(&std::fs::read_to_string(dir.join("fixture.md")).unwrap());
+    let cell = |text: &str| Cell { content: vec![Inline::Text { value: Arc::from(text) }] };
+    let at = doc.blocks.iter().position(|b| matches!(b, Block::Code { .. })).unwrap();
+    doc.blocks.insert(at, Block::Table {
+        id: uuid::Uuid::new_v4(),
+        rows: vec![
+            vec![cell("名称"), cell("数量"), cell("备注")],
+            vec![cell("苹果"), cell("3"), cell("新鲜，")],
+            vec![cell("banana"), cell("12"), cell("long note that needs to wrap inside its cell")],
+        ],
+        header: true,
+        col_widths: Vec::new(),
+        dirty: false,
+        locked: false,
+    });
+    doc
+}
+
+/// Compares page `page` against `tests/golden/<name>.png`. Set
+/// WA_UPDATE_GOLDEN=1 to rewrite the golden files instead.
+fn assert_golden(name: &str, config: &LayoutConfig, page: usize) {
+    let context = FontContext::without_font();
+    let tree = LayoutEngine::with_context(&context).layout(&fixture(), config);
+    let actual = render_page(&tree, config, page, &context).expect("page exists");
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.png", name));
+    if std::env::var("WA_UPDATE_GOLDEN").ok().as_deref() == Some("1") {
+        actual.save(&path).unwrap();
+        return;
+    }
+    let expected = image::open(&path)
+        .unwrap_or_else(|e| panic!("{}: {} (run with WA_UPDATE_GOLDEN=1 to create it)", path.display(), e))
+        .into_rgba8();
+    assert_eq!(actual.dimensions(), expected.dimensions(), "{}", name);
+    let differing = actual
+        .pixels()
+        .zip(expected.pixels())
+        .filter(|(a, b)| a.0.iter().zip(b.0).any(|(x, y)| x.abs_diff(y) > 16))
+        .count();
+    let share = differing as f64 / (actual.width() * actual.height()) as f64;
+    if share > TOLERANCE {
+        let out = std::env::temp_dir().join(format!("{}.actual.png", name));
+        actual.save(&out).unwrap();
+        panic!("{}: {:.3}% of pixels differ, actual written to {}", name, share * 100.0, out.display());
+    }
+}
+
+#[test]
+fn fixture_matches_golden_pages() {
+    assert_golden("fixture_a4", &LayoutConfig::default(), 0);
+    let narrow = LayoutConfig { page_width: 360.0, page_height: 520.0, margin: 24.0, ..LayoutConfig::default() };
+    assert_golden("fixture_narrow_1", &narrow, 0);
+    assert_golden("fixture_narrow_2", &narrow, 1);
+}
+
+#[test]
+fn render_png_encodes_existing_pages_only() {
+    let doc = fixture();
+    let png = render_png(&doc, &LayoutConfig::default(), 0);
+    let decoded = image::load_from_memory(&png).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (794, 1123));
+    assert!(render_png(&doc, &LayoutConfig::default(), 99).is_empty());
+}
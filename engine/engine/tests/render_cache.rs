@@ -0,0 +1,49 @@
+use uuid::Uuid;
+use wa_engine::RenderCache;
+
+fn page(len: usize) -> Vec<Uuid> {
+    (0..len).map(|_| Uuid::new_v4()).collect()
+}
+
+#[test]
+fn ratio_is_per_page() {
+    let mut cache = RenderCache::new();
+    let first = page(10);
+    let second = page(40);
+    cache.mark_dirty(second[3]);
+    assert_eq!(cache.dirty_ratio(first.iter().copied()), 0.0);
+    assert_eq!(cache.dirty_ratio(second.iter().copied()), 0.025);
+    assert_eq!(cache.dirty_ratio(std::iter::empty()), 0.0);
+}
+
+#[test]
+fn should_render_skips_clean_blocks_on_lightly_edited_pages() {
+    let mut cache = RenderCache::new();
+    let blocks = page(40);
+    cache.mark_dirty(blocks[0]);
+    let ratio = cache.dirty_ratio(blocks.iter().copied());
+    assert!(cache.should_render(blocks[0], ratio));
+    assert!(!cache.should_render(blocks[1], ratio));
+
+    // Past the threshold the whole page repaints.
+    for id in &blocks[1..4] {
+        cache.mark_dirty(*id);
+    }
+    let ratio = cache.dirty_ratio(blocks.iter().copied());
+    assert!(ratio > cache.dirty_threshold());
+    assert!(cache.should_render(blocks[10], ratio));
+
+    cache.set_dirty_threshold(0.5);
+    assert!(!cache.should_render(blocks[10], ratio));
+}
+
+#[test]
+fn painted_blocks_are_marked_clean() {
+    let mut cache = RenderCache::with_threshold(0.1);
+    let blocks = page(20);
+    cache.mark_dirty(blocks[5]);
+    cache.mark_clean(blocks[5]);
+    let ratio = cache.dirty_ratio(blocks.iter().copied());
+    assert_eq!(ratio, 0.0);
+    assert!(cache.should_render(blocks[0], ratio));
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+use wa_core::{Block, Document, Inline};
+use wa_engine::{diff_layout, page_thumbnail, LayoutCache, LayoutConfig, LayoutEngine, ThumbnailCache};
+
+fn paragraph(text: &str) -> Block {
+    Block::Paragraph { id: uuid::Uuid::new_v4(), content: vec![Inline::Text { value: Arc::from(text) }], dirty: false, locked: false }
+}
+
+fn set_text(block: &mut Block, text: &str) {
+    if let Block::Paragraph { content, dirty, .. } = block {
+        *content = vec![Inline::Text { value: Arc::from(text) }];
+        *dirty = true;
+    }
+}
+
+fn config() -> LayoutConfig {
+    LayoutConfig { page_height: 300.0, margin: 10.0, ..LayoutConfig::default() }
+}
+
+#[test]
+fn thumbnails_are_drawn_again_only_for_pages_the_layout_diff_reports() {
+    let mut doc = Document::new();
+    doc.blocks = (0..40).map(|i| paragraph(&format!("第 {i} 段"))).collect();
+    let mut engine = LayoutEngine::new();
+    let mut cache = LayoutCache::new();
+    let mut thumbnails = ThumbnailCache::new();
+    let scale = 0.2;
+
+    let first = engine.layout_cached(&doc, &config(), &mut cache);
+    assert!(first.pages.len() > 3);
+    let jobs = thumbnails.update(&first, &config(), scale);
+    assert_eq!(jobs.len(), first.pages.len());
+    assert!(thumbnails.get(0).is_none() && thumbnails.is_pending(0));
+    for job in &jobs {
+        assert!(thumbnails.insert(*job, page_thumbnail(&first, &config(), job.page, scale).unwrap()));
+    }
+    let drawn: Vec<_> = (0..first.pages.len()).map(|page| thumbnails.get(page).unwrap().clone()).collect();
+    let thumb = &drawn[0];
+    assert_eq!((thumb.width, thumb.height), ((794.0f32 * scale).ceil() as u32, (300.0f32 * scale).ceil() as u32));
+    assert_eq!(thumb.rgba.len(), (thumb.width * thumb.height * 4) as usize);
+
+    // Same length, so nothing reflows past the edited block's page.
+    let page = first.page_of(doc.blocks[20].id()).unwrap();
+    set_text(&mut doc.blocks[20], "第 X 段");
+    doc.touch();
+    let second = engine.layout_cached(&doc, &config(), &mut cache);
+    assert_eq!(diff_layout(&first, &second), [page]);
+    let jobs = thumbnails.update(&second, &config(), scale);
+    assert_eq!(jobs.iter().map(|job| job.page).collect::<Vec<_>>(), [page]);
+    assert!(thumbnails.is_pending(page) && !thumbnails.is_pending(page + 1));
+    // The old thumbnail stays up until the new one is in.
+    assert!(Arc::ptr_eq(thumbnails.get(page).unwrap(), &drawn[page]));
+
+    // A block grown to several lines pushes the rest down a page each.
+    let stale = jobs[0];
+    set_text(&mut doc.blocks[20], &"很长的一段文字".repeat(40));
+    doc.touch();
+    let third = engine.layout_cached(&doc, &config(), &mut cache);
+    let changed = diff_layout(&second, &third);
+    assert_eq!(changed, (page..third.pages.len()).collect::<Vec<_>>());
+    let jobs = thumbnails.update(&third, &config(), scale);
+    assert_eq!(jobs.len(), changed.len());
+    assert!(!thumbnails.insert(stale, page_thumbnail(&second, &config(), page, scale).unwrap()));
+    for job in &jobs {
+        assert!(thumbnails.insert(*job, page_thumbnail(&third, &config(), job.page, scale).unwrap()));
+    }
+    assert!((0..page).all(|p| Arc::ptr_eq(thumbnails.get(p).unwrap(), &drawn[p])));
+    assert_ne!(**thumbnails.get(page).unwrap(), *drawn[page]);
+
+    // Another scale starts over.
+    assert_eq!(thumbnails.update(&third, &config(), 0.1).len(), third.pages.len());
+}
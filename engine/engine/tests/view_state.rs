@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use wa_core::{Block, Document, Inline, Position};
+use wa_engine::{HitTester, LayoutConfig, LayoutEngine, ViewState};
+
+const GAP: f32 = 24.0;
+
+fn doc_of(count: usize) -> Document {
+    let mut doc = Document::new();
+    for i in 0..count {
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(format!("第 {i} 段，用来撑满几页的内容。")) }],
+            dirty: false,
+            locked: false,
+        });
+    }
+    doc
+}
+
+fn config() -> LayoutConfig {
+    LayoutConfig { page_height: 300.0, margin: 10.0, ..LayoutConfig::default() }
+}
+
+#[test]
+fn view_state_returns_to_the_anchored_block() {
+    let doc = doc_of(60);
+    let mut engine = LayoutEngine::new();
+    let layout = engine.layout(&doc, &config());
+    assert!(layout.pages.len() > 3);
+
+    let target = doc.blocks[30].id();
+    let (top, height) = layout.block_extent(target, &config(), GAP).unwrap();
+    let mut state = ViewState::capture(&doc, &layout, &config(), GAP, top + height * 0.5);
+    let anchor = state.anchor.unwrap();
+    assert_eq!((anchor.block_id, anchor.block_index), (target, 30));
+    assert!((anchor.fraction - 0.5).abs() < 1e-3);
+    assert_eq!(state.page, layout.page_of(target).unwrap());
+    state.caret = Some(Position { block_id: target, offset: 999 });
+
+    let state: ViewState = serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+    let restored = state.restore_offset(&doc, &layout, &config(), GAP);
+    assert!((restored - (top + height * 0.5)).abs() < 1e-3);
+    let text_len = wa_core::block_plain_text(&doc.blocks[30]).chars().count();
+    assert_eq!(state.caret_in(&doc), Some(Position { block_id: target, offset: text_len }));
+}
+
+#[test]
+fn view_state_falls_back_to_the_nearest_surviving_block() {
+    let mut doc = doc_of(60);
+    let mut engine = LayoutEngine::new();
+    let layout = engine.layout(&doc, &config());
+    let (top, _) = layout.block_extent(doc.blocks[30].id(), &config(), GAP).unwrap();
+    let mut state = ViewState::capture(&doc, &layout, &config(), GAP, top + 1.0);
+    state.caret = Some(Position { block_id: doc.blocks[30].id(), offset: 0 });
+
+    doc.blocks.remove(30);
+    let layout = engine.layout(&doc, &config());
+    let (next_top, _) = layout.block_extent(doc.blocks[30].id(), &config(), GAP).unwrap();
+    assert_eq!(state.restore_offset(&doc, &layout, &config(), GAP), next_top);
+    assert_eq!(state.caret_in(&doc), None);
+
+    // With everything from the anchor on deleted, the last block stands in.
+    doc.blocks.truncate(10);
+    let layout = engine.layout(&doc, &config());
+    let (last_top, _) = layout.block_extent(doc.blocks[9].id(), &config(), GAP).unwrap();
+    assert_eq!(state.restore_offset(&doc, &layout, &config(), GAP), last_top);
+
+    // No blocks at all: back to the saved page, clamped to what exists.
+    let empty = Document::new();
+    let layout = engine.layout(&empty, &config());
+    assert_eq!(state.restore_offset(&empty, &layout, &config(), GAP), 0.0);
+}
+
+#[test]
+fn clicks_on_a_first_line_hit_that_block_in_both_views() {
+    let mut doc = doc_of(40);
+    for i in (0..40).step_by(7) {
+        doc.blocks.insert(i, Block::Heading { id: uuid::Uuid::new_v4(), level: 1, content: vec![Inline::Text { value: Arc::from("标题") }], dirty: false, locked: false });
+    }
+    let hit_tester = HitTester::new();
+    let mut engine = LayoutEngine::new();
+    for config in [config(), LayoutConfig { paged: false, ..config() }] {
+        let layout = engine.layout(&doc, &config);
+        assert_eq!(layout.pages.len() == 1, !config.paged);
+        let line_height = config.metrics.font_size * config.metrics.line_height;
+        for block in &doc.blocks {
+            let (top, height) = layout.block_extent(block.id(), &config, GAP).unwrap();
+            let page = layout.page_of(block.id()).unwrap();
+            let page_bottom = layout.page_top(page, &config, GAP) + layout.pages[page].extent(&config);
+            assert!(top + height <= page_bottom - config.margin + 1e-3, "block spills off page {page}");
+            let hit = hit_tester.hit_test(&layout, &config, config.margin, top + line_height * 0.5, GAP);
+            assert_eq!(hit, Some(Position { block_id: block.id(), offset: 0 }), "paged: {}", config.paged);
+        }
+        let last = layout.pages.len() - 1;
+        let bottom = layout.page_top(last, &config, GAP) + layout.pages[last].extent(&config);
+        let (top, height) = layout.block_extent(doc.blocks.last().unwrap().id(), &config, GAP).unwrap();
+        assert!(top + height <= bottom - config.margin + 1e-3);
+        assert_eq!(layout.page_at_offset(bottom - 1.0, &config, GAP), last);
+    }
+}
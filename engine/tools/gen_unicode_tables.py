@@ -0,0 +1,63 @@
+"""Writes core/src/unicode_tables.rs, the data behind wa_core::to_nfc.
+
+Run with the Python whose Unicode version the tables should follow:
+    python3 tools/gen_unicode_tables.py > core/src/unicode_tables.rs
+"""
+import sys
+import unicodedata
+
+HANGUL = range(0xAC00, 0xD7A4)
+
+
+def main() -> int:
+    ccc = []
+    decomp = []
+    for cp in range(0x110000):
+        ch = chr(cp)
+        cls = unicodedata.combining(ch)
+        if cls:
+            if ccc and ccc[-1][1] == cp - 1 and ccc[-1][2] == cls:
+                ccc[-1][1] = cp
+            else:
+                ccc.append([cp, cp, cls])
+        raw = unicodedata.decomposition(ch)
+        if raw and not raw.startswith("<") and cp not in HANGUL:
+            parts = [int(p, 16) for p in raw.split()]
+            decomp.append((cp, parts[0], parts[1] if len(parts) > 1 else 0))
+
+    compose = []
+    for cp, first, second in decomp:
+        if second == 0 or unicodedata.combining(chr(first)):
+            continue
+        if unicodedata.normalize("NFC", chr(first) + chr(second)) != chr(cp):
+            continue
+        compose.append((first, second, cp))
+    compose.sort()
+
+    out = sys.stdout
+    out.write(f"// Generated by tools/gen_unicode_tables.py from Unicode {unicodedata.unidata_version}; do not edit.\n\n")
+    out.write("/// Canonical combining classes other than 0, as inclusive ranges.\n")
+    write_table(out, "CANONICAL_COMBINING_CLASS", "(u32, u32, u8)", ccc, 6)
+    out.write("\n/// One step of canonical decomposition; the second char is 0 for singletons.\n")
+    write_table(out, "CANONICAL_DECOMPOSITION", "(u32, u32, u32)", decomp, 5)
+    out.write("\n/// Primary composites by their two-char decomposition, sorted.\n")
+    write_table(out, "CANONICAL_COMPOSITION", "(u32, u32, u32)", compose, 5)
+    return 0
+
+
+def write_table(out, name, ty, rows, per_line):
+    out.write(f"pub(crate) static {name}: &[{ty}] = &[\n")
+    for start in range(0, len(rows), per_line):
+        cells = ", ".join(fmt_row(ty, row) for row in rows[start:start + per_line])
+        out.write(f"    {cells},\n")
+    out.write("];\n")
+
+
+def fmt_row(ty, row):
+    # The class column of the combining class table stays decimal.
+    cells = [str(v) if ty.endswith("u8)") and i == 2 else f"0x{v:04X}" for i, v in enumerate(row)]
+    return "(" + ", ".join(cells) + ")"
+
+
+if __name__ == "__main__":
+    raise SystemExit(main())
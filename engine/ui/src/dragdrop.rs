@@ -0,0 +1,66 @@
+use eframe::egui;
+
+/// Distance from the viewport edge where a block drag starts scrolling.
+const AUTOSCROLL_MARGIN: f32 = 48.0;
+const AUTOSCROLL_MAX_SPEED: f32 = 18.0;
+
+/// A block being dragged by its handle.
+#[derive(Debug, Clone)]
+pub struct BlockDrag {
+    pub block_id: uuid::Uuid,
+    /// First line of the block, drawn in the ghost under the pointer.
+    pub preview: String,
+    pub target: Option<DropTarget>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DropTarget {
+    /// Gap index in document order, as `MoveBlock` expects it.
+    pub index: usize,
+    /// Screen-space insertion indicator.
+    pub line: [egui::Pos2; 2],
+}
+
+/// The gap a pointer at `y` over a block spanning `top..bottom` points at:
+/// the upper half drops before the block, the lower half after it.
+pub fn drop_gap(y: f32, top: f32, bottom: f32, index: usize) -> usize {
+    if y < (top + bottom) * 0.5 {
+        index
+    } else {
+        index + 1
+    }
+}
+
+/// Scroll delta for a drag near the top or bottom of the viewport, faster
+/// the closer the pointer gets to the edge. Positive scrolls content down
+/// (towards the top of the document).
+pub fn autoscroll_delta(y: f32, top: f32, bottom: f32) -> f32 {
+    let into_top = top + AUTOSCROLL_MARGIN - y;
+    let into_bottom = y - (bottom - AUTOSCROLL_MARGIN);
+    if into_top > 0.0 {
+        AUTOSCROLL_MAX_SPEED * (into_top / AUTOSCROLL_MARGIN).min(1.0)
+    } else if into_bottom > 0.0 {
+        -AUTOSCROLL_MAX_SPEED * (into_bottom / AUTOSCROLL_MARGIN).min(1.0)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gap_follows_block_halves() {
+        assert_eq!(drop_gap(105.0, 100.0, 140.0, 3), 3);
+        assert_eq!(drop_gap(125.0, 100.0, 140.0, 3), 4);
+    }
+
+    #[test]
+    fn autoscroll_near_edges_only() {
+        assert_eq!(autoscroll_delta(300.0, 0.0, 600.0), 0.0);
+        assert!(autoscroll_delta(10.0, 0.0, 600.0) > 0.0);
+        assert!(autoscroll_delta(590.0, 0.0, 600.0) < 0.0);
+        assert_eq!(autoscroll_delta(-50.0, 0.0, 600.0), AUTOSCROLL_MAX_SPEED);
+    }
+}
@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+use wa_core::{Document, Journal};
+use wa_engine::ViewState;
+
+pub const APP_TITLE: &str = "Writing Agent Editor";
+const OPEN_EXTENSIONS: &[&str] = &["json", "md", "markdown", "txt", "html", "htm", "docx"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Docx,
+    Pdf,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Docx => "docx",
+            ExportFormat::Pdf => "pdf",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "Markdown",
+            ExportFormat::Docx => "DOCX",
+            ExportFormat::Pdf => "PDF",
+        }
+    }
+
+    /// DOCX and PDF need wa_core's `export_docx` feature, enabled through the
+    /// `docx` feature of this crate.
+    pub fn available(self) -> bool {
+        match self {
+            ExportFormat::Markdown => true,
+            ExportFormat::Docx | ExportFormat::Pdf => cfg!(feature = "docx"),
+        }
+    }
+
+    pub fn write(self, doc: &Document, path: &Path) -> Result<(), String> {
+        match self {
+            ExportFormat::Markdown => std::fs::write(path, wa_core::export_markdown(doc))
+                .map_err(|e| format!("导出 Markdown 失败：{}", e)),
+            #[cfg(feature = "docx")]
+            ExportFormat::Docx => wa_core::export_docx(doc, path).map_err(|e| format!("导出 DOCX 失败：{}", e)),
+            #[cfg(feature = "docx")]
+            ExportFormat::Pdf => wa_core::export_pdf(doc, path).map_err(|e| format!("导出 PDF 失败：{}", e)),
+            #[cfg(not(feature = "docx"))]
+            ExportFormat::Docx | ExportFormat::Pdf => Err(format!("未启用 {} 导出", self.label())),
+        }
+    }
+}
+
+pub fn open_document(path: &Path) -> Result<Document, String> {
+    wa_core::import_any(path).map_err(|e| format!("无法打开 {}：{}", path.display(), e))
+}
+
+pub fn save_json(doc: &Document, path: &Path) -> Result<(), String> {
+    let options = wa_core::SaveOptions { backup: true, verify: true };
+    wa_core::export_json_to_file_with(doc, path, options).map_err(|e| format!("保存失败：{}", e))
+}
+
+/// The hidden file next to `path` that keeps its `ViewState`.
+pub fn view_state_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.view.json", name))
+}
+
+pub fn save_view_state(path: &Path, state: &ViewState) -> Result<(), String> {
+    let json = serde_json::to_string(state).map_err(|e| format!("保存视图状态失败：{}", e))?;
+    std::fs::write(view_state_path(path), json).map_err(|e| format!("保存视图状态失败：{}", e))
+}
+
+/// The view saved for `path`, if there is a readable one.
+pub fn load_view_state(path: &Path) -> Option<ViewState> {
+    serde_json::from_str(&std::fs::read_to_string(view_state_path(path)).ok()?).ok()
+}
+
+/// The hidden file next to `path` journaling the edits made since its last
+/// save.
+pub fn journal_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.journal", name))
+}
+
+/// Whether a crash left edits to `path` in its journal. A save truncates
+/// the journal, so records older than the file were saved already.
+pub fn has_recoverable_journal(path: &Path) -> bool {
+    let journal = journal_path(path);
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    Journal::has_records(&journal) && modified(&journal) >= modified(path)
+}
+
+/// Opens the journal for `path`, emptied unless `keep` holds records to
+/// build on.
+pub fn open_journal(path: &Path, keep: bool) -> Result<Journal, String> {
+    let mut journal = Journal::open(&journal_path(path)).map_err(|e| format!("无法打开恢复日志：{}", e))?;
+    if !keep {
+        journal.truncate().map_err(|e| format!("无法打开恢复日志：{}", e))?;
+    }
+    Ok(journal)
+}
+
+/// Only JSON files are saved in place; anything else was imported and gets
+/// a Save As prompt.
+pub fn is_native(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+}
+
+pub fn window_title(path: Option<&Path>, dirty: bool) -> String {
+    let name = path
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "未命名".to_string());
+    let marker = if dirty { "*" } else { "" };
+    format!("{}{} - {}", name, marker, APP_TITLE)
+}
+
+pub fn pick_open() -> Option<PathBuf> {
+    rfd::FileDialog::new()
+        .add_filter("文档", OPEN_EXTENSIONS)
+        .pick_file()
+}
+
+pub fn pick_save(current: Option<&Path>, extension: &str) -> Option<PathBuf> {
+    let stem = current
+        .and_then(|p| p.file_stem())
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "未命名".to_string());
+    let mut dialog = rfd::FileDialog::new()
+        .add_filter(extension, &[extension])
+        .set_file_name(format!("{}.{}", stem, extension));
+    if let Some(dir) = current.and_then(|p| p.parent()) {
+        dialog = dialog.set_directory(dir);
+    }
+    dialog.save_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use wa_core::{Block, Inline};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wa_ui_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn title_shows_name_and_dirty_marker() {
+        assert_eq!(window_title(None, false), format!("未命名 - {}", APP_TITLE));
+        let path = Path::new("/tmp/notes.json");
+        assert_eq!(window_title(Some(path), true), format!("notes.json* - {}", APP_TITLE));
+        assert!(is_native(path));
+        assert!(!is_native(Path::new("notes.md")));
+    }
+
+    #[test]
+    fn save_and_export_round_trip() {
+        let mut doc = Document::new();
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from("保存测试") }],
+            dirty: false,
+            locked: false,
+        });
+        let json = temp_path("save.json");
+        save_json(&doc, &json).unwrap();
+        let loaded = open_document(&json).unwrap();
+        assert_eq!(loaded.blocks.len(), 1);
+
+        let md = temp_path("export.md");
+        ExportFormat::Markdown.write(&doc, &md).unwrap();
+        assert!(std::fs::read_to_string(&md).unwrap().contains("保存测试"));
+        let _ = std::fs::remove_file(json);
+        let _ = std::fs::remove_file(md);
+
+        assert!(open_document(Path::new("missing.md")).is_err());
+    }
+
+    #[test]
+    fn view_state_lives_in_a_hidden_file_next_to_the_document() {
+        assert_eq!(view_state_path(Path::new("/tmp/notes.json")), Path::new("/tmp/.notes.json.view.json"));
+        let doc_path = temp_path("view.json");
+        assert_eq!(load_view_state(&doc_path), None);
+        let state = ViewState { scroll_offset: 420.0, page: 2, ..ViewState::default() };
+        save_view_state(&doc_path, &state).unwrap();
+        assert_eq!(load_view_state(&doc_path), Some(state));
+        let _ = std::fs::remove_file(view_state_path(&doc_path));
+    }
+
+    #[test]
+    fn only_a_journal_written_after_the_save_is_recoverable() {
+        assert_eq!(journal_path(Path::new("/tmp/notes.json")), Path::new("/tmp/.notes.json.journal"));
+        let doc_path = temp_path("journal.json");
+        let mut doc = Document::new();
+        doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: Vec::new(), dirty: false, locked: false });
+        save_json(&doc, &doc_path).unwrap();
+        assert!(!has_recoverable_journal(&doc_path));
+
+        let mut editor = wa_core::Editor::new(doc);
+        editor.selection = wa_core::Selection::collapsed(wa_core::Position { block_id: editor.doc.blocks[0].id(), offset: 0 });
+        editor.attach_journal(open_journal(&doc_path, false).unwrap());
+        editor.execute(wa_core::EditorCommand::InsertText("字".to_string()));
+        drop(editor);
+        assert!(has_recoverable_journal(&doc_path));
+
+        open_journal(&doc_path, false).unwrap().discard().unwrap();
+        assert!(!has_recoverable_journal(&doc_path));
+        let _ = std::fs::remove_file(doc_path);
+    }
+}
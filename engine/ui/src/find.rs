@@ -0,0 +1,150 @@
+use wa_core::{Document, FindMatch, FindOptions, PlainTextIndex};
+
+/// State of the Ctrl+F panel. Matches are recomputed whenever the query, the
+/// options or the document version change.
+#[derive(Debug, Default)]
+pub struct FindPanel {
+    pub open: bool,
+    pub query: String,
+    pub replacement: String,
+    pub case_insensitive: bool,
+    /// Search headings only, e.g. to fix the case of titles.
+    pub headings_only: bool,
+    pub matches: Vec<FindMatch>,
+    pub current: Option<usize>,
+    pub focus_query: bool,
+    searched: Option<(String, bool, bool, u64)>,
+}
+
+impl FindPanel {
+    pub fn options(&self) -> FindOptions {
+        // The panel selects hits in the text, so link URLs stay out of it.
+        FindOptions {
+            case_insensitive: self.case_insensitive,
+            include_code: true,
+            include_code_spans: true,
+            include_math: true,
+            include_urls: false,
+            block_types: if self.headings_only { vec!["heading".to_string()] } else { Vec::new() },
+            scope: None,
+        }
+    }
+
+    /// Drops matches from another document, e.g. after switching tabs.
+    pub fn reset(&mut self) {
+        self.matches.clear();
+        self.current = None;
+        self.searched = None;
+    }
+
+    pub fn refresh(&mut self, doc: &Document, index: &mut PlainTextIndex) {
+        let key = (self.query.clone(), self.case_insensitive, self.headings_only, doc.version);
+        if self.searched.as_ref() == Some(&key) {
+            return;
+        }
+        self.matches = index.find_in_doc(doc, &self.query, self.options());
+        self.current = match self.current {
+            _ if self.matches.is_empty() => None,
+            Some(idx) => Some(idx.min(self.matches.len() - 1)),
+            None => None,
+        };
+        self.searched = Some(key);
+    }
+
+    /// Moves to the next (or previous) match, wrapping around the document.
+    pub fn step(&mut self, forward: bool) -> Option<FindMatch> {
+        let len = self.matches.len();
+        if len == 0 {
+            self.current = None;
+            return None;
+        }
+        let next = match (self.current, forward) {
+            (None, true) => 0,
+            (None, false) => len - 1,
+            (Some(idx), true) => (idx + 1) % len,
+            (Some(idx), false) => (idx + len - 1) % len,
+        };
+        self.current = Some(next);
+        Some(self.matches[next])
+    }
+
+    pub fn current_match(&self) -> Option<FindMatch> {
+        self.current.and_then(|idx| self.matches.get(idx).copied())
+    }
+
+    pub fn counter(&self) -> String {
+        if self.query.is_empty() {
+            return String::new();
+        }
+        match (self.current, self.matches.len()) {
+            (_, 0) => "无结果".to_string(),
+            (Some(idx), total) => format!("{}/{}", idx + 1, total),
+            (None, total) => format!("{} 个结果", total),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use wa_core::{Block, Inline};
+
+    fn doc(text: &str) -> Document {
+        let mut doc = Document::new();
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from(text) }],
+            dirty: false,
+            locked: false,
+        });
+        doc
+    }
+
+    #[test]
+    fn step_wraps_around() {
+        let doc = doc("a b a b a");
+        let mut panel = FindPanel { query: "a".to_string(), ..FindPanel::default() };
+        panel.refresh(&doc, &mut PlainTextIndex::new());
+        assert_eq!(panel.counter(), "3 个结果");
+        assert_eq!(panel.step(true).map(|m| m.start), Some(0));
+        assert_eq!(panel.step(true).map(|m| m.start), Some(4));
+        assert_eq!(panel.step(true).map(|m| m.start), Some(8));
+        assert_eq!(panel.step(true).map(|m| m.start), Some(0));
+        assert_eq!(panel.step(false).map(|m| m.start), Some(8));
+        assert_eq!(panel.counter(), "3/3");
+    }
+
+    #[test]
+    fn refresh_follows_document_version() {
+        let mut doc = doc("Rust rust");
+        let mut index = PlainTextIndex::new();
+        let mut panel = FindPanel { query: "rust".to_string(), ..FindPanel::default() };
+        panel.refresh(&doc, &mut index);
+        assert_eq!(panel.matches.len(), 1);
+        panel.case_insensitive = true;
+        panel.refresh(&doc, &mut index);
+        assert_eq!(panel.matches.len(), 2);
+        panel.step(false);
+        if let Block::Paragraph { content, .. } = &mut doc.blocks[0] {
+            content[0] = Inline::Text { value: Arc::from("rust") };
+        }
+        doc.touch();
+        panel.refresh(&doc, &mut index);
+        assert_eq!(panel.matches.len(), 1);
+        assert_eq!(panel.current, Some(0));
+    }
+
+    #[test]
+    fn headings_only_skips_other_blocks() {
+        let mut doc = doc("第一章 正文");
+        doc.blocks.insert(0, Block::Heading { id: uuid::Uuid::new_v4(), level: 1, content: vec![Inline::Text { value: Arc::from("第一章") }], dirty: false, locked: false });
+        let mut index = PlainTextIndex::new();
+        let mut panel = FindPanel { query: "第一章".to_string(), ..FindPanel::default() };
+        panel.refresh(&doc, &mut index);
+        assert_eq!(panel.matches.len(), 2);
+        panel.headings_only = true;
+        panel.refresh(&doc, &mut index);
+        assert_eq!(panel.matches.iter().map(|m| m.block_index).collect::<Vec<_>>(), [0]);
+    }
+}
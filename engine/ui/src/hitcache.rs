@@ -0,0 +1,106 @@
+use lru::LruCache;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+
+/// Lines whose glyph advances are kept for click hit testing.
+const HIT_CACHE_LINES: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct HitKey {
+    block_id: uuid::Uuid,
+    line: usize,
+    /// Line text and font size; rewrapping or zooming changes it, so stale
+    /// advances are never served.
+    signature: u64,
+}
+
+/// Per-line x advances, most recently used lines first.
+pub struct HitCache {
+    lines: LruCache<HitKey, Vec<f32>>,
+}
+
+impl Default for HitCache {
+    fn default() -> Self {
+        Self::new(HIT_CACHE_LINES)
+    }
+}
+
+impl HitCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { lines: LruCache::new(NonZeroUsize::new(capacity.max(1)).unwrap()) }
+    }
+
+    pub fn get(&mut self, block_id: uuid::Uuid, line: usize, signature: u64) -> Option<&Vec<f32>> {
+        self.lines.get(&HitKey { block_id, line, signature })
+    }
+
+    pub fn insert(&mut self, block_id: uuid::Uuid, line: usize, signature: u64, advances: Vec<f32>) {
+        self.lines.put(HitKey { block_id, line, signature }, advances);
+    }
+
+    /// Drops every line, as when the font changed.
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Drops every line of a block whose content changed.
+    pub fn invalidate_block(&mut self, block_id: uuid::Uuid) {
+        let stale: Vec<HitKey> = self.lines.iter().map(|(k, _)| *k).filter(|k| k.block_id == block_id).collect();
+        for key in stale {
+            self.lines.pop(&key);
+        }
+    }
+}
+
+pub fn line_signature(text: &str, font_size: f32) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    font_size.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Char index of the advance at or left of `x`.
+pub fn offset_at(advances: &[f32], x: f32) -> usize {
+    advances.partition_point(|v| *v <= x).saturating_sub(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrapped_or_zoomed_lines_miss() {
+        let id = uuid::Uuid::new_v4();
+        let mut cache = HitCache::default();
+        cache.insert(id, 0, line_signature("hello world", 14.0), vec![0.0, 8.0, 16.0]);
+        assert!(cache.get(id, 0, line_signature("hello world", 14.0)).is_some());
+        // A narrower window wraps the line differently.
+        assert!(cache.get(id, 0, line_signature("hello ", 14.0)).is_none());
+        assert!(cache.get(id, 0, line_signature("hello world", 21.0)).is_none());
+    }
+
+    #[test]
+    fn bounded_and_invalidated_per_block() {
+        let (a, b) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let mut cache = HitCache::new(3);
+        for line in 0..3 {
+            cache.insert(a, line, 1, Vec::new());
+        }
+        cache.insert(b, 0, 1, Vec::new());
+        assert_eq!(cache.lines.len(), 3);
+        assert!(cache.get(a, 0, 1).is_none());
+        cache.invalidate_block(a);
+        assert_eq!(cache.lines.len(), 1);
+        assert!(cache.get(b, 0, 1).is_some());
+    }
+
+    #[test]
+    fn offset_rounds_down_to_char_start() {
+        let advances = [0.0, 8.0, 16.0, 24.0];
+        assert_eq!(offset_at(&advances, 0.0), 0);
+        assert_eq!(offset_at(&advances, 9.5), 1);
+        assert_eq!(offset_at(&advances, 16.0), 2);
+        assert_eq!(offset_at(&advances, 100.0), 3);
+    }
+}
@@ -0,0 +1,59 @@
+use eframe::egui;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+pub enum TextureEntry {
+    Loading,
+    Ready(egui::TextureHandle),
+    Failed(String),
+}
+
+type Decoded = (String, Result<wa_engine::DecodedImage, String>);
+
+/// Figure textures keyed by url. Pixels come from the layout engine's
+/// `ImageCache`, decoded on a worker thread; the UI thread only uploads them.
+pub struct ImageTextures {
+    images: wa_engine::ImageCache,
+    entries: HashMap<String, TextureEntry>,
+    tx: Sender<Decoded>,
+    rx: Receiver<Decoded>,
+}
+
+impl ImageTextures {
+    pub fn new(images: wa_engine::ImageCache) -> Self {
+        let (tx, rx) = channel();
+        Self { images, entries: HashMap::new(), tx, rx }
+    }
+
+    pub fn poll(&mut self, ctx: &egui::Context) {
+        while let Ok((url, result)) = self.rx.try_recv() {
+            let entry = match result {
+                Ok(img) => {
+                    let pixels = egui::ColorImage::from_rgba_unmultiplied(
+                        [img.width as usize, img.height as usize],
+                        &img.rgba,
+                    );
+                    TextureEntry::Ready(ctx.load_texture(url.clone(), pixels, egui::TextureOptions::LINEAR))
+                }
+                Err(err) => TextureEntry::Failed(err),
+            };
+            self.entries.insert(url, entry);
+        }
+    }
+
+    pub fn get(&mut self, ctx: &egui::Context, url: &str) -> &TextureEntry {
+        if !self.entries.contains_key(url) {
+            let tx = self.tx.clone();
+            let ctx = ctx.clone();
+            let key = url.to_string();
+            let images = self.images.clone();
+            std::thread::spawn(move || {
+                let result = images.pixels(&key).map_err(|e| e.to_string());
+                let _ = tx.send((key, result));
+                ctx.request_repaint();
+            });
+            self.entries.insert(url.to_string(), TextureEntry::Loading);
+        }
+        &self.entries[url]
+    }
+}
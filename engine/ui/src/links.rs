@@ -0,0 +1,64 @@
+use wa_core::SharedStr;
+
+const ALLOWED_SCHEMES: &[&str] = &["http", "https", "mailto", "file", "ftp"];
+
+#[derive(Debug, Clone)]
+pub struct LinkPopover {
+    pub block_id: uuid::Uuid,
+    pub url: SharedStr,
+    pub pos: eframe::egui::Pos2,
+    pub editing: Option<String>,
+    pub opened_frame: u64,
+}
+
+pub fn validate_url(url: &str) -> Result<(), String> {
+    let url = url.trim();
+    if url.is_empty() {
+        return Err("链接为空".to_string());
+    }
+    if url.chars().any(char::is_whitespace) {
+        return Err(format!("链接包含空白字符：{}", url));
+    }
+    let Some((scheme, rest)) = url.split_once(':') else {
+        return Err(format!("链接缺少协议：{}", url));
+    };
+    let scheme = scheme.to_ascii_lowercase();
+    if !ALLOWED_SCHEMES.contains(&scheme.as_str()) {
+        return Err(format!("不支持的链接协议：{}", scheme));
+    }
+    if matches!(scheme.as_str(), "http" | "https" | "ftp") {
+        let host = rest.strip_prefix("//").unwrap_or("");
+        if host.split(['/', '?', '#']).next().unwrap_or("").is_empty() {
+            return Err(format!("链接缺少主机名：{}", url));
+        }
+    }
+    Ok(())
+}
+
+pub fn open_url(url: &str) -> Result<(), String> {
+    validate_url(url)?;
+    open::that(url.trim()).map_err(|err| format!("无法打开链接：{}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_common_urls() {
+        assert!(validate_url("https://example.com/a?b=c").is_ok());
+        assert!(validate_url("HTTP://example.com").is_ok());
+        assert!(validate_url("mailto:someone@example.com").is_ok());
+        assert!(validate_url("file:///tmp/a.txt").is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_urls() {
+        assert!(validate_url("").is_err());
+        assert!(validate_url("example.com").is_err());
+        assert!(validate_url("https://").is_err());
+        assert!(validate_url("https:///path").is_err());
+        assert!(validate_url("https://exa mple.com").is_err());
+        assert!(validate_url("javascript:alert(1)").is_err());
+    }
+}
@@ -1,39 +1,101 @@
 ﻿use eframe::{egui, App, Frame};
-use wa_core::{Block, Document, Editor, EditorCommand, FigureSize, Inline, Style, import_html_rich};
+use wa_core::{Block, CommandOutcome, Direction, Document, EditorCommand, Inline, Style, TableEditor, TargetKind, TemplateRegistry, import_html_rich};
 use std::sync::Arc;
-use wa_engine::{FontMetrics, LayoutCache, LayoutConfig, LayoutEngine, LayoutKind, RealMeasurer, RenderCache, TextMeasurer};
+use wa_engine::{CodeTheme, EngineOptions, FontMetrics, LayoutConfig, LayoutEngine, LayoutKind, RealMeasurer, TextMeasurer, ViewState};
 use arboard::Clipboard;
 
+mod dragdrop;
+mod files;
+mod find;
+mod hitcache;
+mod images;
+mod links;
+mod print;
+mod render;
+mod settings;
+mod status;
+mod tables;
+mod tabs;
+mod thumbnails;
+
 pub fn main() -> eframe::Result<()> {
+    let engine_options = EngineOptions::from_env();
+    if engine_options.diagnostics {
+        tracing_subscriber::fmt().with_max_level(tracing::Level::DEBUG).with_writer(std::io::stderr).init();
+    }
+    let settings_path = settings::Settings::default_path();
+    let (settings, warning) = settings_path.as_deref().map(settings::Settings::load).unwrap_or_default();
     let options = eframe::NativeOptions::default();
+    let mut app = EditorApp::new(engine_options, settings, settings_path);
+    if let Some(warning) = warning {
+        tracing::warn!("{}", warning);
+        app.show_toast(warning);
+    }
+    for path in std::env::args().skip(1) {
+        app.open_path(std::path::PathBuf::from(path));
+    }
     eframe::run_native(
-        "Writing Agent Editor",
+        files::APP_TITLE,
         options,
-        Box::new(|_cc| Box::new(EditorApp::new())),
+        Box::new(|_cc| Box::new(app)),
     )
 }
 
 struct EditorApp {
-    editor: Editor,
+    tabs: Vec<tabs::DocumentView>,
+    active: usize,
+    /// Tab waiting for the save prompt before it closes.
+    closing_tab: Option<usize>,
+    /// Tab whose file has unsaved edits left in its journal by a crash,
+    /// waiting for the recovery prompt.
+    recovering: Option<uuid::Uuid>,
     layout: LayoutEngine,
-    cache: LayoutCache,
-    render_cache: RenderCache,
     view_mode: ViewMode,
+    /// Page thumbnails to the right of the paged view.
+    show_thumbnails: bool,
+    /// Running line numbers in the left margin.
+    line_numbers: bool,
     measurer: RealMeasurer,
     ime_buffer: String,
     ime_active: bool,
-    image_sizes: std::collections::HashMap<uuid::Uuid, (f32, f32)>,
-    resizing_image: Option<(String, egui::Pos2)>,
-    rect_select: Option<(egui::Pos2, egui::Pos2)>,
-    extra_cursors: Vec<wa_core::Position>,
-    table_focus: Option<(uuid::Uuid, usize, usize)>,
-    layout_tree: Option<wa_engine::LayoutTree>,
-    layout_version: u64,
+    /// Screen rect of the caret this frame, reported to the OS as the IME
+    /// candidate window position.
+    ime_cursor_rect: Option<egui::Rect>,
     last_scroll_at: Option<std::time::Instant>,
     scroll_debounce: std::time::Duration,
-    layout_paged_view: bool,
-    layout_page_height: i32,
-    hit_cache: std::collections::HashMap<(uuid::Uuid, usize), Vec<f32>>,
+    caret_epoch: std::time::Instant,
+    toast: Option<(String, std::time::Instant)>,
+    textures: images::ImageTextures,
+    find: find::FindPanel,
+    window_title: String,
+    confirm_close: bool,
+    allow_close: bool,
+    zoom: f32,
+    goto_page: usize,
+    /// Stats of the last layout, shown in the F12 debug overlay while set.
+    layout_stats: Option<wa_engine::LayoutStats>,
+    templates: TemplateRegistry,
+    /// Print options being picked, while the print dialog is open.
+    print_dialog: Option<print::PrintDialog>,
+    settings: settings::Settings,
+    /// Where settings are saved; `None` keeps them for this session only.
+    settings_path: Option<std::path::PathBuf>,
+    /// The settings as last written to `settings_path`.
+    saved_settings: settings::Settings,
+    /// Engine options from the environment, under the settings.
+    env_options: EngineOptions,
+    show_settings: bool,
+    last_autosave: std::time::Instant,
+}
+
+const CARET_BLINK: std::time::Duration = std::time::Duration::from_millis(530);
+const ZOOM_RANGE: std::ops::RangeInclusive<f32> = 0.5..=3.0;
+
+fn paper_label(paper: wa_core::Paper) -> &'static str {
+    match paper {
+        wa_core::Paper::A4 => "A4",
+        wa_core::Paper::Letter => "Letter",
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -43,7 +105,8 @@ enum ViewMode {
 }
 
 impl EditorApp {
-    fn new() -> Self {
+    fn new(env_options: EngineOptions, settings: settings::Settings, settings_path: Option<std::path::PathBuf>) -> Self {
+        let options = settings.engine_options(&env_options);
         let mut doc = Document::new();
         doc.blocks = vec![
             Block::Heading {
@@ -51,6 +114,7 @@ impl EditorApp {
                 level: 1,
                 content: vec![Inline::Text { value: Arc::from("示例标题") }],
                 dirty: false,
+                locked: false,
             },
             Block::Paragraph {
                 id: uuid::Uuid::new_v4(),
@@ -58,124 +122,716 @@ impl EditorApp {
                     value: Arc::from("这里是 Rust + egui 引擎原型。开始输入即可修改内容。"),
                 }],
                 dirty: false,
+                locked: false,
             },
         ];
+        let layout = LayoutEngine::with_options(options.clone());
         Self {
-            editor: Editor::new(doc),
-            layout: LayoutEngine::new(),
-            cache: LayoutCache::new(),
-            render_cache: RenderCache::new(),
-            view_mode: ViewMode::Paged,
-            measurer: RealMeasurer::new(),
+            tabs: vec![tabs::DocumentView::new(doc, None)],
+            active: 0,
+            closing_tab: None,
+            recovering: None,
+            measurer: RealMeasurer::with_options(&options),
+            textures: images::ImageTextures::new(layout.images().clone()),
+            layout,
+            view_mode: if settings.paged { ViewMode::Paged } else { ViewMode::Scroll },
+            show_thumbnails: true,
+            line_numbers: false,
             ime_buffer: String::new(),
             ime_active: false,
-            image_sizes: std::collections::HashMap::new(),
-            resizing_image: None,
-            rect_select: None,
-            extra_cursors: Vec::new(),
-            table_focus: None,
-            layout_tree: None,
-            layout_version: 0,
+            ime_cursor_rect: None,
             last_scroll_at: None,
             scroll_debounce: std::time::Duration::from_millis(80),
-            layout_paged_view: true,
-            layout_page_height: LayoutConfig::default().page_height as i32,
-            hit_cache: std::collections::HashMap::new(),
+            caret_epoch: std::time::Instant::now(),
+            toast: None,
+            find: find::FindPanel::default(),
+            window_title: String::new(),
+            confirm_close: false,
+            allow_close: false,
+            zoom: settings.zoom.clamp(*ZOOM_RANGE.start(), *ZOOM_RANGE.end()),
+            goto_page: 1,
+            layout_stats: None,
+            templates: TemplateRegistry::with_builtins(),
+            print_dialog: None,
+            saved_settings: settings.clone(),
+            settings,
+            settings_path,
+            env_options,
+            show_settings: false,
+            last_autosave: std::time::Instant::now(),
+        }
+    }
+
+    fn view(&self) -> &tabs::DocumentView {
+        &self.tabs[self.active]
+    }
+
+    fn view_mut(&mut self) -> &mut tabs::DocumentView {
+        &mut self.tabs[self.active]
+    }
+
+    /// Opens `doc` in a new tab, or in the current one if it is still the
+    /// untouched empty tab.
+    fn add_tab(&mut self, doc: Document, path: Option<std::path::PathBuf>) {
+        let view = tabs::DocumentView::new(doc, path);
+        if self.view().is_pristine() {
+            self.tabs[self.active] = view;
+            self.switch_tab(self.active);
+        } else {
+            self.tabs.push(view);
+            self.switch_tab(self.tabs.len() - 1);
         }
     }
 
+    fn new_tab(&mut self) {
+        let mut doc = Document::new();
+        doc.blocks.push(Block::Paragraph { id: uuid::Uuid::new_v4(), content: Vec::new(), dirty: false, locked: false });
+        self.tabs.push(tabs::DocumentView::new(doc, None));
+        self.switch_tab(self.tabs.len() - 1);
+    }
 
-    fn block_to_text(block: &Block) -> String {
-        fn inline_to_text(inlines: &[Inline], out: &mut String) {
-            for inline in inlines {
-                match inline {
-                    Inline::Text { value } => out.push_str(value.as_ref()),
-                    Inline::CodeSpan { value } => out.push_str(value.as_ref()),
-                    Inline::Link { text, .. } => inline_to_text(text, out),
-                    Inline::Styled { content, .. } => inline_to_text(content, out),
+    fn switch_tab(&mut self, index: usize) {
+        self.active = index.min(self.tabs.len() - 1);
+        self.find.reset();
+        self.ime_active = false;
+        self.ime_buffer.clear();
+    }
+
+    /// Closes a tab, asking first when it has unsaved changes.
+    fn close_tab(&mut self, index: usize) {
+        if self.tabs[index].is_modified() {
+            self.closing_tab = Some(index);
+        } else {
+            self.remove_tab(index);
+        }
+    }
+
+    fn remove_tab(&mut self, index: usize) {
+        self.remember_view(index);
+        if let Some(journal) = self.tabs[index].editor.detach_journal() {
+            let _ = journal.discard();
+        }
+        if self.tabs.len() == 1 {
+            self.tabs.clear();
+            self.new_tab();
+            return;
+        }
+        self.tabs.remove(index);
+        self.switch_tab(tabs::active_after_close(self.active, index, self.tabs.len() + 1));
+    }
+
+    fn open_path(&mut self, path: std::path::PathBuf) {
+        match files::open_document(&path) {
+            Ok(doc) => {
+                self.settings.add_recent(&path);
+                let saved_view = files::load_view_state(&path);
+                self.add_tab(doc, Some(path.clone()));
+                self.view_mut().restore_view = saved_view;
+                if files::is_native(&path) && files::has_recoverable_journal(&path) {
+                    self.recovering = Some(self.view().id);
+                } else if files::is_native(&path) {
+                    self.attach_journal(self.active, false);
                 }
             }
+            Err(err) => self.show_toast(err),
         }
-        let mut out = String::new();
-        match block {
-            Block::Heading { content, .. } | Block::Paragraph { content, .. } => {
-                inline_to_text(content, &mut out);
+    }
+
+    /// Journals the edits of a tab from here on, so a crash loses none of
+    /// them. `keep` builds on the records already in the journal.
+    fn attach_journal(&mut self, index: usize, keep: bool) {
+        let Some(path) = self.tabs[index].document_path.clone() else {
+            return;
+        };
+        match files::open_journal(&path, keep) {
+            Ok(journal) => self.tabs[index].editor.attach_journal(journal),
+            Err(err) => self.show_toast(err),
+        }
+    }
+
+    /// Reloads a tab's last save and replays its journal on top.
+    fn recover(&mut self, index: usize) {
+        let Some(path) = self.tabs[index].document_path.clone() else {
+            return;
+        };
+        let mut view = match files::open_document(&path) {
+            Ok(doc) => tabs::DocumentView::new(doc, Some(path.clone())),
+            Err(err) => return self.show_toast(err),
+        };
+        view.restore_view = files::load_view_state(&path);
+        match wa_core::Journal::replay(&files::journal_path(&path), &mut view.editor) {
+            Ok(replay) if replay.stopped_at.is_some() => {
+                self.show_toast(format!("已恢复 {} 步操作，日志末尾已损坏", replay.applied));
             }
-            Block::List { items, .. } => {
-                for (i, item) in items.iter().enumerate() {
-                    if i > 0 { out.push('\n'); }
-                    inline_to_text(&item.content, &mut out);
+            Ok(replay) => self.show_toast(format!("已恢复 {} 步操作", replay.applied)),
+            Err(err) => return self.show_toast(format!("恢复失败：{}", err)),
+        }
+        self.tabs[index] = view;
+        self.attach_journal(index, true);
+    }
+
+    fn handle_recovery_prompt(&mut self, ctx: &egui::Context) {
+        let Some(index) = self.recovering.and_then(|id| self.tabs.iter().position(|t| t.id == id)) else {
+            self.recovering = None;
+            return;
+        };
+        let mut recover = false;
+        let mut discard = false;
+        egui::Window::new("恢复未保存的更改")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("“{}”上次没有正常关闭，留有未保存的更改。是否恢复？", self.tabs[index].tab_label()));
+                ui.horizontal(|ui| {
+                    recover = ui.button("恢复").clicked();
+                    discard = ui.button("丢弃").clicked();
+                });
+            });
+        if recover {
+            self.recovering = None;
+            self.recover(index);
+        } else if discard {
+            self.recovering = None;
+            self.attach_journal(index, false);
+        }
+    }
+
+    fn report_journal_errors(&mut self) {
+        let errors: Vec<_> = self.tabs.iter_mut().filter_map(|t| t.editor.take_journal_error()).collect();
+        for err in errors {
+            self.show_toast(format!("恢复日志写入失败，已停用：{}", err));
+        }
+    }
+
+    fn open_dialog(&mut self) {
+        if let Some(path) = files::pick_open() {
+            self.open_path(path);
+        }
+    }
+
+    /// Returns false when the user cancelled the dialog or writing failed.
+    fn save(&mut self, save_as: bool) -> bool {
+        let target = match &self.view().document_path {
+            Some(path) if !save_as && files::is_native(path) => Some(path.clone()),
+            current => files::pick_save(current.as_deref(), "json"),
+        };
+        let Some(path) = target else {
+            return false;
+        };
+        self.write_tab(self.active, path)
+    }
+
+    /// Saves tab `index` as `path` and journals on from there.
+    fn write_tab(&mut self, index: usize, path: std::path::PathBuf) -> bool {
+        match files::save_json(&self.tabs[index].editor.doc, &path) {
+            Ok(()) => {
+                let journal_path = files::journal_path(&path);
+                let view = &mut self.tabs[index];
+                view.saved_version = view.editor.doc.version;
+                view.document_path = Some(path.clone());
+                self.settings.add_recent(&path);
+                self.remember_view(index);
+                // Everything journaled is in the file now; a save under a
+                // new name leaves the old file's journal behind.
+                if let Some(journal) = self.tabs[index].editor.detach_journal() {
+                    if journal.path() != journal_path {
+                        let _ = journal.discard();
+                    }
                 }
+                let id = self.tabs[index].id;
+                self.recovering = self.recovering.filter(|r| *r != id);
+                self.attach_journal(index, false);
+                true
             }
-            Block::Quote { content, .. } => {
-                for (i, b) in content.iter().enumerate() {
-                    if i > 0 { out.push('\n'); }
-                    if let Block::Paragraph { content, .. } = b {
-                        inline_to_text(content, &mut out);
-                    }
+            Err(err) => {
+                self.show_toast(err);
+                false
+            }
+        }
+    }
+
+    /// Saves modified tabs that have a file of their own once the autosave
+    /// interval has passed since the last round.
+    fn autosave(&mut self) {
+        let interval = std::time::Duration::from_secs(self.settings.autosave_secs);
+        if interval.is_zero() || self.last_autosave.elapsed() < interval {
+            return;
+        }
+        self.last_autosave = std::time::Instant::now();
+        for index in 0..self.tabs.len() {
+            let path = self.tabs[index].document_path.clone().filter(|p| files::is_native(p));
+            if let Some(path) = path.filter(|_| self.tabs[index].is_modified()) {
+                self.write_tab(index, path);
+            }
+        }
+    }
+
+    /// Writes a tab's view state next to its file. Losing it only costs the
+    /// scroll position, so failures are ignored.
+    fn remember_view(&self, index: usize) {
+        let view = &self.tabs[index];
+        if let Some(path) = &view.document_path {
+            let state = ViewState { caret: Some(view.editor.selection.focus), ..view.view_state.clone() };
+            let _ = files::save_view_state(path, &state);
+        }
+    }
+
+    /// Saves every modified tab, stopping at the first one that is cancelled
+    /// or fails (which is left active).
+    fn save_all(&mut self) -> bool {
+        for index in 0..self.tabs.len() {
+            if self.tabs[index].is_modified() {
+                self.switch_tab(index);
+                if !self.save(false) {
+                    return false;
                 }
             }
-            Block::Code { code, .. } => out.push_str(code.as_ref()),
-            Block::Table { rows, .. } => {
-                for (ri, row) in rows.iter().enumerate() {
-                    if ri > 0 { out.push('\n'); }
-                    for (ci, cell) in row.iter().enumerate() {
-                        if ci > 0 { out.push('\t'); }
-                        inline_to_text(&cell.content, &mut out);
+        }
+        true
+    }
+
+    fn draw_tab_strip(&mut self, ctx: &egui::Context) {
+        let mut activate = None;
+        let mut close = None;
+        let mut create = false;
+        egui::TopBottomPanel::top("tab_strip").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for (index, view) in self.tabs.iter().enumerate() {
+                    let label = if view.is_modified() {
+                        format!("● {}", view.tab_label())
+                    } else {
+                        view.tab_label()
+                    };
+                    let tab = ui.selectable_label(index == self.active, label);
+                    if tab.clicked() {
+                        activate = Some(index);
+                    }
+                    if tab.middle_clicked() {
+                        close = Some(index);
+                    }
+                    if let Some(path) = &view.document_path {
+                        tab.on_hover_text(path.display().to_string());
                     }
+                    if ui.small_button("×").on_hover_text("关闭 (Ctrl+W)").clicked() {
+                        close = Some(index);
+                    }
+                    ui.separator();
+                }
+                if ui.small_button("+").on_hover_text("新建 (Ctrl+N)").clicked() {
+                    create = true;
                 }
+            });
+        });
+        if let Some(index) = activate {
+            self.switch_tab(index);
+        }
+        if let Some(index) = close {
+            self.close_tab(index);
+        }
+        if create {
+            self.new_tab();
+        }
+    }
+
+    fn handle_tab_close_prompt(&mut self, ctx: &egui::Context) {
+        let Some(index) = self.closing_tab.filter(|&i| i < self.tabs.len()) else {
+            self.closing_tab = None;
+            return;
+        };
+        let mut close = false;
+        let mut cancel = false;
+        egui::Window::new("关闭标签")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("“{}”有未保存的更改，是否在关闭前保存？", self.tabs[index].tab_label()));
+                ui.horizontal(|ui| {
+                    if ui.button("保存").clicked() {
+                        self.switch_tab(index);
+                        close = self.save(false);
+                        cancel = !close;
+                    }
+                    if ui.button("不保存").clicked() {
+                        close = true;
+                    }
+                    if ui.button("取消").clicked() {
+                        cancel = true;
+                    }
+                });
+            });
+        if close {
+            self.closing_tab = None;
+            self.remove_tab(index);
+        } else if cancel {
+            self.closing_tab = None;
+        }
+    }
+
+    fn draw_settings(&mut self, ctx: &egui::Context) {
+        if !self.show_settings {
+            return;
+        }
+        let mut open = true;
+        let before = self.settings.clone();
+        egui::Window::new("设置").open(&mut open).collapsible(false).resizable(false).show(ctx, |ui| {
+            egui::Grid::new("settings_grid").num_columns(2).show(ui, |ui| {
+                ui.label("字体文件");
+                ui.horizontal(|ui| {
+                    ui.label(self.settings.font.as_ref().map_or("系统字体".to_string(), |p| p.display().to_string()));
+                    if ui.button("浏览…").clicked() {
+                        if let Some(path) = rfd::FileDialog::new().add_filter("字体", &["ttf", "otf", "ttc"]).pick_file() {
+                            self.settings.font = Some(path);
+                        }
+                    }
+                    if ui.add_enabled(self.settings.font.is_some(), egui::Button::new("清除")).clicked() {
+                        self.settings.font = None;
+                    }
+                });
+                ui.end_row();
+                ui.label("默认纸张");
+                egui::ComboBox::from_id_source("settings_paper")
+                    .selected_text(paper_label(self.settings.paper))
+                    .show_ui(ui, |ui| {
+                        for paper in [wa_core::Paper::A4, wa_core::Paper::Letter] {
+                            ui.selectable_value(&mut self.settings.paper, paper, paper_label(paper));
+                        }
+                    });
+                ui.end_row();
+                ui.label("性能");
+                ui.vertical(|ui| {
+                    ui.checkbox(&mut self.settings.low_spec, "低配模式（更小的缓存）");
+                    ui.checkbox(&mut self.settings.parallel, "并行排版大文档");
+                });
+                ui.end_row();
+                ui.label("自动保存");
+                ui.add(egui::DragValue::new(&mut self.settings.autosave_secs).clamp_range(0..=3600).suffix(" 秒"))
+                    .on_hover_text("0 表示关闭；只保存已有文件的标签");
+                ui.end_row();
+            });
+        });
+        self.show_settings = open;
+        let engine_changed = (&before.font, before.low_spec, before.parallel)
+            != (&self.settings.font, self.settings.low_spec, self.settings.parallel);
+        if engine_changed {
+            self.rebuild_layout_engine();
+        } else if before.paper != self.settings.paper {
+            for view in &mut self.tabs {
+                view.invalidate_layout();
             }
-            Block::Figure { caption, .. } => {
-                if let Some(c) = caption { out.push_str(c.as_ref()); }
+        }
+    }
+
+    /// Builds the layout engine and measurer again for changed font or
+    /// performance settings.
+    fn rebuild_layout_engine(&mut self) {
+        let options = self.settings.engine_options(&self.env_options);
+        self.measurer = RealMeasurer::with_options(&options);
+        let images = self.layout.images().clone();
+        self.layout = LayoutEngine::with_options(options);
+        self.layout.set_images(images);
+        if self.layout_stats.is_some() {
+            self.layout.set_collect_timings(true);
+        }
+        for view in &mut self.tabs {
+            view.invalidate_layout();
+            view.hit_cache.clear();
+        }
+    }
+
+    /// Writes the settings when they changed, waiting for drags (zoom
+    /// slider, margins) to end.
+    fn sync_settings(&mut self, ctx: &egui::Context) {
+        self.settings.paged = self.view_mode == ViewMode::Paged;
+        self.settings.zoom = self.zoom;
+        if self.settings == self.saved_settings || ctx.input(|i| i.pointer.any_down()) {
+            return;
+        }
+        self.saved_settings = self.settings.clone();
+        if let Some(path) = &self.settings_path {
+            if let Err(err) = self.settings.save(path) {
+                self.show_toast(err);
             }
         }
-        out
     }
 
-    fn selection_text(&self) -> String {
-        let sel = self.editor.selection;
-        if let Some(block) = self.editor.doc.blocks.iter().find(|b| b.id() == sel.focus.block_id) {
-            let text = Self::block_to_text(block);
-            let start = sel.anchor.offset.min(sel.focus.offset);
-            let end = sel.anchor.offset.max(sel.focus.offset);
-            let mut s_idx = 0usize;
-            let mut e_idx = text.len();
-            let mut count = 0usize;
-            for (idx, _) in text.char_indices() {
-                if count == start { s_idx = idx; }
-                if count == end { e_idx = idx; break; }
-                count += 1;
+    fn open_print_dialog(&mut self) {
+        let config = self.layout_config();
+        let mut dialog = print::PrintDialog::for_page(config.page_width / self.zoom, config.margin / self.zoom);
+        dialog.paper = self.settings.paper;
+        self.print_dialog = Some(dialog);
+    }
+
+    fn handle_print_dialog(&mut self, ctx: &egui::Context) {
+        let Some(mut dialog) = self.print_dialog.take() else {
+            return;
+        };
+        let (mut confirm, mut cancel) = (false, false);
+        egui::Window::new("打印")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                egui::Grid::new("print_options").num_columns(2).show(ui, |ui| {
+                    ui.label("纸张");
+                    egui::ComboBox::from_id_source("print_paper")
+                        .selected_text(paper_label(dialog.paper))
+                        .show_ui(ui, |ui| {
+                            for paper in [wa_core::Paper::A4, wa_core::Paper::Letter] {
+                                ui.selectable_value(&mut dialog.paper, paper, paper_label(paper));
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("页边距");
+                    ui.add(egui::DragValue::new(&mut dialog.margin_mm).clamp_range(0.0..=50.0).suffix(" mm"));
+                    ui.end_row();
+                    ui.label("页码范围");
+                    ui.add(egui::TextEdit::singleline(&mut dialog.pages).hint_text("全部，或如 2-5").desired_width(120.0));
+                    ui.end_row();
+                });
+                if let Some(err) = &dialog.error {
+                    ui.colored_label(egui::Color32::from_rgb(200, 60, 60), err);
+                }
+                ui.horizontal(|ui| {
+                    confirm = ui.button("打印").clicked();
+                    cancel = ui.button("取消").clicked();
+                });
+            });
+        if cancel || ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+            return;
+        }
+        if confirm {
+            match dialog.options() {
+                Ok(options) => {
+                    match print::print(&self.view().editor.doc, &options) {
+                        Ok(()) => self.show_toast("已交给系统打印".to_string()),
+                        Err(err) => self.show_toast(err),
+                    }
+                    return;
+                }
+                Err(err) => dialog.error = Some(err),
             }
-            return text[s_idx..e_idx].to_string();
         }
-        String::new()
+        self.print_dialog = Some(dialog);
+    }
+
+    fn export(&mut self, format: files::ExportFormat) {
+        let Some(path) = files::pick_save(self.view().document_path.as_deref(), format.extension()) else {
+            return;
+        };
+        match format.write(&self.view().editor.doc, &path) {
+            Ok(()) => self.show_toast(format!("已导出 {}", path.display())),
+            Err(err) => self.show_toast(err),
+        }
+    }
+
+    fn draw_menu_bar(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            egui::menu::bar(ui, |ui| {
+                ui.menu_button("文件", |ui| {
+                    if ui.button("新建 (Ctrl+N)").clicked() {
+                        ui.close_menu();
+                        self.new_tab();
+                    }
+                    if ui.button("打开… (Ctrl+O)").clicked() {
+                        ui.close_menu();
+                        self.open_dialog();
+                    }
+                    if ui.button("保存 (Ctrl+S)").clicked() {
+                        ui.close_menu();
+                        self.save(false);
+                    }
+                    if ui.button("另存为… (Ctrl+Shift+S)").clicked() {
+                        ui.close_menu();
+                        self.save(true);
+                    }
+                    if ui.button("关闭标签 (Ctrl+W)").clicked() {
+                        ui.close_menu();
+                        self.close_tab(self.active);
+                    }
+                    ui.add_enabled_ui(!self.settings.recent_files.is_empty(), |ui| {
+                        ui.menu_button("最近打开", |ui| {
+                            for path in self.settings.recent_files.clone() {
+                                if ui.button(path.display().to_string()).clicked() {
+                                    ui.close_menu();
+                                    self.open_path(path);
+                                }
+                            }
+                        });
+                    });
+                    if ui.button("设置…").clicked() {
+                        ui.close_menu();
+                        self.show_settings = true;
+                    }
+                    if ui.button("打印… (Ctrl+P)").clicked() {
+                        ui.close_menu();
+                        self.open_print_dialog();
+                    }
+                    ui.separator();
+                    ui.menu_button("导出", |ui| {
+                        for format in [files::ExportFormat::Markdown, files::ExportFormat::Docx, files::ExportFormat::Pdf] {
+                            let button = ui
+                                .add_enabled(format.available(), egui::Button::new(format!("{}…", format.label())))
+                                .on_disabled_hover_text("需要以 docx 功能编译");
+                            if button.clicked() {
+                                ui.close_menu();
+                                self.export(format);
+                            }
+                        }
+                    });
+                });
+                ui.menu_button("编辑", |ui| {
+                    if ui.button("复制为片段").on_hover_text("复制选中的块，可粘贴到其他文档").clicked() {
+                        ui.close_menu();
+                        self.copy_snippet();
+                    }
+                    if ui.button("粘贴片段").clicked() {
+                        ui.close_menu();
+                        self.paste_snippet();
+                    }
+                });
+            });
+        });
+    }
+
+    fn sync_window_title(&mut self, ctx: &egui::Context) {
+        let view = self.view();
+        let title = files::window_title(view.document_path.as_deref(), view.is_modified());
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+
+    fn handle_close_request(&mut self, ctx: &egui::Context) {
+        let close_requested = ctx.input(|i| i.viewport().close_requested());
+        if close_requested {
+            (0..self.tabs.len()).for_each(|index| self.remember_view(index));
+        }
+        let any_modified = self.tabs.iter().any(|t| t.is_modified());
+        if close_requested && (self.allow_close || !any_modified) {
+            // Saved or thrown away on purpose: nothing left to recover.
+            for view in &mut self.tabs {
+                if let Some(journal) = view.editor.detach_journal() {
+                    let _ = journal.discard();
+                }
+            }
+        }
+        if close_requested && !self.allow_close && any_modified {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.confirm_close = true;
+        }
+        if !self.confirm_close {
+            return;
+        }
+        let mut close = false;
+        let mut cancel = false;
+        egui::Window::new("未保存的更改")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("有文档包含未保存的更改，是否在关闭前保存？");
+                ui.horizontal(|ui| {
+                    if ui.button("全部保存").clicked() {
+                        close = self.save_all();
+                        cancel = !close;
+                    }
+                    if ui.button("不保存").clicked() {
+                        close = true;
+                    }
+                    if ui.button("取消").clicked() {
+                        cancel = true;
+                    }
+                });
+            });
+        if close {
+            self.confirm_close = false;
+            self.allow_close = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        } else if cancel {
+            self.confirm_close = false;
+        }
+    }
+
+
+    /// The selection as a document of its own, for the clipboard formats.
+    fn selection_doc(&self) -> Document {
+        Document { blocks: self.view().editor.selection_fragment(), ..Document::new() }
+    }
+
+    fn copy_snippet(&mut self) {
+        let blocks = self.view().editor.selection_fragment();
+        if blocks.is_empty() {
+            self.show_toast("没有选中内容".to_string());
+            return;
+        }
+        let json = wa_core::Fragment::new(blocks, Some(self.view().editor.doc.id)).to_json();
+        match Clipboard::new().and_then(|mut cb| cb.set_text(json)) {
+            Ok(()) => self.show_toast("已复制片段".to_string()),
+            Err(err) => self.show_toast(format!("无法复制片段: {}", err)),
+        }
     }
 
-    fn selection_html(&self) -> String {
-        let plain = self.selection_text();
-        if plain.is_empty() {
-            return String::new();
+    fn paste_snippet(&mut self) {
+        let text = match Clipboard::new().and_then(|mut cb| cb.get_text()) {
+            Ok(text) => text,
+            Err(err) => {
+                self.show_toast(format!("无法读取剪贴板: {}", err));
+                return;
+            }
+        };
+        match wa_core::import_fragment(&text) {
+            Ok(blocks) => self.view_mut().editor.insert_blocks(blocks),
+            Err(err) => self.show_toast(format!("剪贴板中不是片段: {}", err)),
         }
-        format!("<p>{}</p>", plain.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;"))
     }
 
+    /// Runs `cmd` at the caret and every extra cursor. Commands the focused
+    /// block cannot take, or that hit a locked block, are reported in a
+    /// toast.
     fn apply_to_cursors(&mut self, cmd: EditorCommand, extra: &[wa_core::Position]) {
         if extra.is_empty() {
-            self.editor.execute(cmd);
+            match self.view_mut().editor.try_execute(cmd) {
+                Err(err) => self.show_command_error(err),
+                Ok(CommandOutcome::BlockedByLock { .. }) => self.show_toast("该块已锁定，不能编辑".to_string()),
+                Ok(CommandOutcome::Applied) => {}
+            }
             return;
         }
         let mut positions = Vec::with_capacity(extra.len() + 1);
-        positions.push(self.editor.selection.focus);
+        positions.push(self.view().editor.selection.focus);
         for p in extra {
             if !positions.iter().any(|x| x.block_id == p.block_id && x.offset == p.offset) {
                 positions.push(*p);
             }
         }
+        let mut refused = None;
+        let mut blocked = false;
         for pos in &positions {
-            self.editor.selection = wa_core::Selection::collapsed(*pos);
-            self.editor.execute(cmd.clone());
+            self.view_mut().editor.selection = wa_core::Selection::collapsed(*pos);
+            match self.view_mut().editor.try_execute(cmd.clone()) {
+                Err(err) => {
+                    refused.get_or_insert(err);
+                }
+                Ok(outcome) => blocked |= outcome != CommandOutcome::Applied,
+            }
+        }
+        self.view_mut().editor.selection = wa_core::Selection::collapsed(positions[0]);
+        if let Some(err) = refused {
+            self.show_command_error(err);
+        } else if blocked {
+            self.show_toast("部分光标位于锁定的块中，已跳过".to_string());
         }
-        self.editor.selection = wa_core::Selection::collapsed(positions[0]);
+    }
+
+    fn show_command_error(&mut self, err: wa_core::CommandError) {
+        let message = match err {
+            wa_core::CommandError::NotConvertible(_) => "当前块不能转换为标题".to_string(),
+            wa_core::CommandError::CannotConvert { .. } => "当前块不能转换为该类型".to_string(),
+        };
+        self.show_toast(message);
     }
 
     fn find_table_cell(&self, page: &wa_engine::Page, config: &LayoutConfig, rect: egui::Rect, pos: egui::Pos2) -> Option<(uuid::Uuid, usize, usize)> {
@@ -185,39 +841,249 @@ impl EditorApp {
             let block_height = block.height;
             let end_y = start_y + block_height;
             if pos.y >= start_y && pos.y <= end_y {
-                if let Some(doc_block) = self.editor.doc.blocks.iter().find(|b| b.id() == block.block_id) {
-                    if let Block::Table { rows, .. } = doc_block {
-                        if rows.is_empty() || rows[0].is_empty() {
-                            return None;
+                if let Some(table @ Block::Table { rows, .. }) = self.view().editor.doc.blocks.iter().find(|b| b.id() == block.block_id) {
+                    if rows.is_empty() || rows[0].is_empty() {
+                        return None;
+                    }
+                    let row_h = config.metrics.font_size * config.metrics.line_height;
+                    let row = ((pos.y - start_y) / row_h).floor() as usize;
+                    let width = config.page_width - config.margin * 2.0;
+                    let local_x = (pos.x - (rect.left() + config.margin)).max(0.0);
+                    let col = render::column_at(&TableEditor::col_fractions(table), local_x / width);
+                    return Some((block.block_id, row.min(rows.len() - 1), col));
+                }
+            }
+            cursor_y = end_y + config.block_gap();
+        }
+        None
+    }
+    /// Column fractions and, per row, the byte length of each cell's text as
+    /// the layout joined it into the row line.
+    fn table_cell_lens(&self, block_id: uuid::Uuid) -> Option<(Vec<f32>, Vec<Vec<usize>>)> {
+        let table = self.view().editor.doc.blocks.iter().find(|b| b.id() == block_id)?;
+        let Block::Table { rows, .. } = table else {
+            return None;
+        };
+        let mut text = String::new();
+        let lens = rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| {
+                        text.clear();
+                        wa_core::inline_plain_text(&cell.content, &mut text);
+                        text.len()
+                    })
+                    .collect()
+            })
+            .collect();
+        Some((TableEditor::col_fractions(table), lens))
+    }
+
+    /// Draws the internal column borders of a table and lets them be dragged.
+    /// Widths are sent as throttled `TableSetColWidth` commands, which the
+    /// editor merges into a single undo entry.
+    fn table_column_borders(
+        &mut self,
+        ui: &egui::Ui,
+        painter: &egui::Painter,
+        block_id: uuid::Uuid,
+        block_rect: egui::Rect,
+        fractions: &[f32],
+    ) {
+        let width = block_rect.width();
+        if width <= 0.0 {
+            return;
+        }
+        for col in 0..fractions.len().saturating_sub(1) {
+            let (left, right) = render::column_span(fractions, col);
+            let x = block_rect.left() + right * width;
+            painter.line_segment(
+                [egui::pos2(x, block_rect.top()), egui::pos2(x, block_rect.bottom())],
+                egui::Stroke::new(1.0, render::TABLE_BORDER_COLOR),
+            );
+            let handle = egui::Rect::from_x_y_ranges(x - 3.0..=x + 3.0, block_rect.y_range());
+            let resp = ui
+                .interact(handle, egui::Id::new(("table_col_border", block_id, col)), egui::Sense::drag())
+                .on_hover_cursor(egui::CursorIcon::ResizeHorizontal);
+            if resp.dragged() {
+                let Some(pointer) = resp.interact_pointer_pos() else {
+                    continue;
+                };
+                let new_width = (pointer.x - (block_rect.left() + left * width)) / width;
+                let now = std::time::Instant::now();
+                match self.view().column_resize {
+                    Some(resize) if resize.block_id == block_id && resize.col == col => {
+                        if resize.due(new_width, now) {
+                            self.resize_column(block_id, col, new_width, now);
                         }
-                        let row_h = config.metrics.font_size * config.metrics.line_height;
-                        let row = ((pos.y - start_y) / row_h).floor() as usize;
-                        let cols = rows[0].len();
-                        let width = config.page_width - config.margin * 2.0;
-                        let col_w = width / cols as f32;
-                        let local_x = (pos.x - (rect.left() + config.margin)).max(0.0);
-                        let col = (local_x / col_w).floor() as usize;
-                        return Some((block.block_id, row.min(rows.len() - 1), col.min(cols - 1)));
                     }
+                    _ => self.resize_column(block_id, col, new_width, now),
                 }
             }
-            for _line in &block.lines {
-                cursor_y += config.metrics.font_size * config.metrics.line_height;
+            if resp.drag_stopped() {
+                if let Some(resize) = self.view_mut().column_resize.take() {
+                    if let Some(pointer) = resp.interact_pointer_pos() {
+                        let new_width = (pointer.x - (block_rect.left() + left * width)) / width;
+                        if (new_width - resize.width).abs() > 1e-3 {
+                            self.resize_column(block_id, col, new_width, std::time::Instant::now());
+                            self.view_mut().column_resize = None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn resize_column(&mut self, block_id: uuid::Uuid, col: usize, width: f32, now: std::time::Instant) {
+        self.view_mut().editor.execute(EditorCommand::TableSetColWidth { block_id, col, width });
+        self.view_mut().column_resize = Some(tables::ColumnResize { block_id, col, sent_at: now, width });
+    }
+
+    fn table_size(&self, block_id: uuid::Uuid) -> Option<(usize, usize, bool)> {
+        self.view().editor.doc.blocks.iter().find_map(|b| match b {
+            Block::Table { id, rows, header, .. } if *id == block_id => {
+                Some((rows.len(), rows.first().map(|r| r.len()).unwrap_or(0), *header))
+            }
+            _ => None,
+        })
+    }
+
+    /// Keeps the focused cell inside its table after edits that shrink it.
+    fn clamp_table_focus(&mut self) {
+        if let Some((block_id, row, col)) = self.view().table_focus {
+            self.view_mut().table_focus = match self.table_size(block_id) {
+                Some((rows, cols, _)) if rows > 0 && cols > 0 => {
+                    Some((block_id, row.min(rows - 1), col.min(cols - 1)))
+                }
+                _ => None,
+            };
+        }
+    }
+
+    /// Tab, Shift+Tab and Enter in a focused cell: next cell (appending a
+    /// row after the last one), previous cell, cell below.
+    fn step_table_focus(&mut self, (block_id, row, col): (uuid::Uuid, usize, usize), key: egui::Key, shift: bool) {
+        let next = if key == egui::Key::Tab && !shift {
+            self.view_mut().editor.next_table_cell(block_id, row, col)
+        } else {
+            match self.view().editor.doc.blocks.iter().find(|b| b.id() == block_id) {
+                Some(Block::Table { rows, .. }) if key == egui::Key::Tab => TableEditor::prev_cell(rows, row, col),
+                Some(Block::Table { rows, .. }) if !shift => TableEditor::cell_below(rows, row, col),
+                _ => None,
+            }
+        };
+        if let Some((row, col)) = next {
+            self.view_mut().table_focus = Some((block_id, row, col));
+        }
+    }
+
+    /// Arrow keys: left and right step a character, or with Ctrl a word,
+    /// up and down go to the line above or below in the last layout, or
+    /// with Ctrl to the previous or next block. Home and End go to the line
+    /// ends, with Ctrl to the block ends. Shift extends the selection.
+    fn move_caret(&mut self, key: egui::Key, modifiers: egui::Modifiers) {
+        let config = self.layout_config();
+        let view = &mut self.tabs[self.active];
+        let extend = modifiers.shift;
+        let direction = match key {
+            egui::Key::ArrowUp if modifiers.ctrl => Some(Direction::PrevBlock),
+            egui::Key::ArrowDown if modifiers.ctrl => Some(Direction::NextBlock),
+            egui::Key::Home if modifiers.ctrl => Some(Direction::BlockStart),
+            egui::Key::End if modifiers.ctrl => Some(Direction::BlockEnd),
+            egui::Key::Home => Some(Direction::LineStart),
+            egui::Key::End => Some(Direction::LineEnd),
+            _ => None,
+        };
+        if let Some(direction) = direction {
+            view.editor.execute(EditorCommand::MoveCursor { direction, extend });
+        } else if matches!(key, egui::Key::ArrowLeft | egui::Key::ArrowRight) && modifiers.ctrl {
+            view.editor.execute(EditorCommand::MoveCursorWord { forward: key == egui::Key::ArrowRight, extend });
+        } else if matches!(key, egui::Key::ArrowLeft | egui::Key::ArrowRight) {
+            view.editor.move_caret_by_char(key == egui::Key::ArrowRight, extend);
+        } else if let Some(layout) = view.layout_tree.as_ref() {
+            let down = key == egui::Key::ArrowDown;
+            wa_engine::move_caret_vertically(&mut view.editor, layout, &config, &self.measurer, down, extend);
+        }
+        view.extra_cursors.clear();
+    }
+
+    fn table_context_menu(&mut self, ui: &mut egui::Ui, (block_id, row, col): (uuid::Uuid, usize, usize)) {
+        let header = self.table_size(block_id).is_some_and(|(_, _, header)| header);
+        for action in tables::TableAction::MENU {
+            if matches!(action, tables::TableAction::DeleteRow | tables::TableAction::ToggleHeader) {
+                ui.separator();
+            }
+            if ui.button(action.label(header)).clicked() {
+                ui.close_menu();
+                self.view_mut().editor.execute(action.command(block_id, row, col));
+                self.view_mut().table_focus = self
+                    .table_size(block_id)
+                    .and_then(|(rows, cols, _)| action.focus_after(row, col, rows, cols))
+                    .map(|(r, c)| (block_id, r, c));
+                self.view_mut().table_menu = None;
             }
-            cursor_y += config.metrics.font_size * 0.5;
         }
-        None
     }
+
+    /// Typed text goes into the focused table cell, or at every cursor.
+    fn insert_typed(&mut self, text: String, extra: &[wa_core::Position]) {
+        if let Some((bid, row, col)) = self.view().table_focus {
+            if let Some(Block::Table { rows, .. }) = self.view().editor.doc.blocks.iter().find(|b| b.id() == bid) {
+                if let Some(c) = rows.get(row).and_then(|r| r.get(col)) {
+                    let mut current = String::new();
+                    for inline in &c.content {
+                        if let Inline::Text { value } = inline {
+                            current.push_str(value.as_ref());
+                        }
+                    }
+                    current.push_str(&text);
+                    self.view_mut().editor.execute(EditorCommand::TableEditCell { block_id: bid, row, col, text: current });
+                }
+            }
+        } else {
+            self.apply_to_cursors(EditorCommand::InsertText(text), extra);
+        }
+    }
+
+    fn backspace_at_cursors(&mut self, word: bool, extra: &[wa_core::Position]) {
+        if let Some((bid, row, col)) = self.view().table_focus {
+            if let Some(Block::Table { rows, .. }) = self.view().editor.doc.blocks.iter().find(|b| b.id() == bid) {
+                if let Some(c) = rows.get(row).and_then(|r| r.get(col)) {
+                    let mut current = String::new();
+                    for inline in &c.content {
+                        if let Inline::Text { value } = inline {
+                            current.push_str(value.as_ref());
+                        }
+                    }
+                    current.pop();
+                    self.view_mut().editor.execute(EditorCommand::TableEditCell { block_id: bid, row, col, text: current });
+                }
+            }
+        } else if word {
+            self.apply_to_cursors(EditorCommand::DeleteWordBackward, extra);
+        } else {
+            self.apply_to_cursors(EditorCommand::DeleteSelection, extra);
+        }
+    }
+
     fn handle_input(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
         let mut to_insert = String::new();
         let mut copy = false;
+        let mut copy_markdown = false;
         let mut paste = false;
         let mut paste_image = false;
-        let mut backspace = false;
+        let mut had_insert = false;
+        let mut delete_word = false;
         let mut bold = false;
         let mut italic = false;
+        let mut clear_formatting = false;
         let mut heading = None;
-        let extra = self.extra_cursors.clone();
+        let mut history = None;
+        let extra = self.view().extra_cursors.clone();
         ctx.input(|i| {
             for ev in &i.events {
                 match ev {
@@ -236,18 +1102,14 @@ impl EditorApp {
                         }
                         self.ime_buffer.clear();
                     }
-                    egui::Event::Text(text) => {
-                        if !self.ime_active {
-                            to_insert.push_str(text);
-                        }
+                    egui::Event::Text(text) if !self.ime_active => {
+                        to_insert.push_str(text);
                     }
                     egui::Event::Paste(text) => {
                         if text.contains("<") && text.contains(">") {
-                            self.editor.checkpoint();
-                            let mut doc = import_html_rich(text);
+                            let doc = import_html_rich(text);
                             if !doc.blocks.is_empty() {
-                                self.editor.doc.blocks.extend(doc.blocks.drain(..));
-                                self.editor.doc.touch();
+                                self.view_mut().editor.paste_blocks(doc.blocks);
                             } else {
                                 to_insert.push_str(text);
                             }
@@ -259,18 +1121,51 @@ impl EditorApp {
                         if !*pressed {
                             continue;
                         }
+                        if matches!(key, egui::Key::Tab | egui::Key::Enter) {
+                            if let Some(focus) = self.view().table_focus {
+                                self.step_table_focus(focus, *key, modifiers.shift);
+                                continue;
+                            }
+                        }
+                        if modifiers.alt && matches!(key, egui::Key::ArrowUp | egui::Key::ArrowDown) {
+                            let cmd = if *key == egui::Key::ArrowUp { EditorCommand::MoveBlockUp } else { EditorCommand::MoveBlockDown };
+                            self.apply_to_cursors(cmd, &[]);
+                            continue;
+                        }
+                        if matches!(key, egui::Key::ArrowLeft | egui::Key::ArrowRight | egui::Key::ArrowUp | egui::Key::ArrowDown | egui::Key::Home | egui::Key::End)
+                            && self.view().table_focus.is_none()
+                        {
+                            self.move_caret(*key, *modifiers);
+                            continue;
+                        }
                         if *key == egui::Key::Tab {
                             if modifiers.shift {
-                                self.apply_to_cursors(EditorCommand::ListOutdent, &extra);
+                                self.apply_to_cursors(EditorCommand::ListOutdent { block_id: None, item: None }, &extra);
                             } else {
-                                self.apply_to_cursors(EditorCommand::ListIndent, &extra);
+                                self.apply_to_cursors(EditorCommand::ListIndent { block_id: None, item: None }, &extra);
                             }
                         }
+                        // Breaks and deletions land between the text typed
+                        // around them, so pending text goes in first.
+                        if matches!(key, egui::Key::Backspace | egui::Key::Enter) && !to_insert.is_empty() {
+                            had_insert = true;
+                            self.insert_typed(std::mem::take(&mut to_insert), &extra);
+                        }
                         if *key == egui::Key::Backspace {
-                            backspace = true;
+                            self.backspace_at_cursors(modifiers.ctrl, &extra);
+                        }
+                        if modifiers.ctrl && *key == egui::Key::Delete {
+                            delete_word = true;
+                        }
+                        if *key == egui::Key::Enter {
+                            self.apply_to_cursors(EditorCommand::InsertParagraphBreak, &extra);
                         }
                         if modifiers.ctrl && *key == egui::Key::C {
-                            copy = true;
+                            if modifiers.shift {
+                                copy_markdown = true;
+                            } else {
+                                copy = true;
+                            }
                         }
                         if modifiers.ctrl && *key == egui::Key::V {
                             paste = true;
@@ -282,181 +1177,682 @@ impl EditorApp {
                         if modifiers.ctrl && *key == egui::Key::I {
                             italic = true;
                         }
+                        if modifiers.ctrl && *key == egui::Key::A && self.view().table_focus.is_none() {
+                            self.view_mut().editor.execute(EditorCommand::SelectAll);
+                            self.view_mut().extra_cursors.clear();
+                        }
+                        if modifiers.ctrl && *key == egui::Key::Backslash {
+                            clear_formatting = true;
+                        }
                         if modifiers.ctrl && *key == egui::Key::Num1 {
                             heading = Some(1);
                         }
                         if modifiers.ctrl && *key == egui::Key::Num2 {
                             heading = Some(2);
                         }
+                        if modifiers.ctrl && *key == egui::Key::Z {
+                            history = Some(if modifiers.shift { EditorCommand::Redo } else { EditorCommand::Undo });
+                        }
+                        if modifiers.ctrl && *key == egui::Key::Y {
+                            history = Some(EditorCommand::Redo);
+                        }
                     }
                     _ => {}
                 }
             }
         });
 
-        let had_insert = !to_insert.is_empty();
-        if had_insert {
-            let insert_text = std::mem::take(&mut to_insert);
-            if let Some((bid, row, col)) = self.table_focus {
-                if let Some(block) = self.editor.doc.blocks.iter().find(|b| b.id() == bid) {
-                    if let Block::Table { rows, .. } = block {
-                        if let Some(r) = rows.get(row) {
-                            if let Some(c) = r.get(col) {
-                                let mut current = String::new();
-                                for inline in &c.content {
-                                    if let Inline::Text { value } = inline {
-                                        current.push_str(value.as_ref());
-                                    }
-                                }
-                                current.push_str(&insert_text);
-                                self.editor.execute(EditorCommand::TableEditCell { block_id: bid, row, col, text: current });
-                            }
-                        }
+        if !to_insert.is_empty() {
+            had_insert = true;
+            self.insert_typed(to_insert, &extra);
+        }
+        if delete_word && self.view().table_focus.is_none() {
+            self.apply_to_cursors(EditorCommand::DeleteWordForward, &extra);
+        }
+        if bold {
+            self.apply_to_cursors(EditorCommand::ApplyStyle(Style { bold: true, italic: false, underline: false, strikethrough: false }), &extra);
+        }
+        if italic {
+            self.apply_to_cursors(EditorCommand::ApplyStyle(Style { bold: false, italic: true, underline: false, strikethrough: false }), &extra);
+        }
+        if clear_formatting {
+            self.apply_to_cursors(EditorCommand::ClearFormatting, &extra);
+        }
+        if let Some(level) = heading {
+            self.apply_to_cursors(EditorCommand::SetHeading(level), &extra);
+        }
+        if let Some(cmd) = history {
+            self.view_mut().editor.execute(cmd);
+            self.view_mut().extra_cursors.clear();
+        }
+
+        if copy || copy_markdown {
+            let doc = self.selection_doc();
+            if !doc.blocks.is_empty() {
+                // Ctrl+C offers HTML with a plain text fallback, Ctrl+Shift+C
+                // the Markdown source as text.
+                let plain = if copy_markdown {
+                    wa_core::export_markdown(&doc)
+                } else {
+                    doc.blocks.iter().map(wa_core::block_plain_text).collect::<Vec<_>>().join("\n")
+                };
+                let stored = Clipboard::new().is_ok_and(|mut cb| {
+                    if copy_markdown {
+                        cb.set_text(plain.clone()).is_ok()
+                    } else {
+                        cb.set_html(wa_core::export_html(&doc), Some(plain.clone())).is_ok()
+                    }
+                });
+                if !stored {
+                    ctx.output_mut(|o| o.copied_text = plain);
+                }
+            }
+        }
+
+        if paste && !had_insert && paste_image {
+            if let Ok(mut cb) = Clipboard::new() {
+                if let Ok(image) = cb.get_image() {
+                    match wa_engine::encode_png(image.width as u32, image.height as u32, &image.bytes) {
+                        Ok(bytes) => self.view_mut().editor.execute(EditorCommand::InsertImageData {
+                            bytes,
+                            mime: "image/png".to_string(),
+                            caption: None,
+                        }),
+                        Err(err) => self.show_toast(format!("无法粘贴图片: {}", err)),
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_block_frame(painter: &egui::Painter, rect: egui::Rect) {
+        painter.rect_stroke(rect, 4.0, egui::Stroke::new(1.0, egui::Color32::from_gray(210)));
+    }
+
+
+    /// Blocks are stepped through at their laid-out heights, as
+    /// `draw_page_at` places them; the gap below a block hits its end.
+    fn hit_test_page(&mut self, page: &wa_engine::Page, config: &LayoutConfig, rect: egui::Rect, pos: egui::Pos2) -> Option<wa_core::Position> {
+        let line_height = config.metrics.font_size * config.metrics.line_height;
+        let mut block_top = rect.top() + config.margin;
+        for block in &page.blocks {
+            if pos.y < block_top {
+                break;
+            }
+            let block_bottom = block_top + block.height;
+            if pos.y <= block_bottom + config.block_gap() {
+                let last = block.lines.len().checked_sub(1)?;
+                if pos.y > block_bottom {
+                    let offset = block.lines[last].start + block.lines[last].text.chars().count();
+                    return Some(wa_core::Position { block_id: block.block_id, offset });
+                }
+                let line_idx = (((pos.y - block_top) / line_height) as usize).min(last);
+                let local_x = (pos.x - (rect.left() + config.margin)).max(0.0);
+                let offset = self.line_offset_at(block, line_idx, config, local_x);
+                return Some(wa_core::Position { block_id: block.block_id, offset: block.lines[line_idx].start + offset });
+            }
+            block_top = block_bottom + config.block_gap();
+        }
+        None
+    }
+
+    fn line_offset_at(&mut self, block: &wa_engine::LayoutBlock, line_idx: usize, config: &LayoutConfig, local_x: f32) -> usize {
+        let line = &block.lines[line_idx];
+        let signature = hitcache::line_signature(&line.text, config.metrics.font_size);
+        if let Some(offsets) = self.view_mut().hit_cache.get(block.block_id, line_idx, signature) {
+            return hitcache::offset_at(offsets, local_x);
+        }
+        let mut acc = 0.0f32;
+        let mut offsets = Vec::with_capacity(line.text.chars().count() + 1);
+        offsets.push(0.0);
+        let mut buf = [0u8; 4];
+        for ch in line.text.chars() {
+            acc += self.measurer.measure(ch.encode_utf8(&mut buf), config.metrics);
+            offsets.push(acc);
+        }
+        let offset = hitcache::offset_at(&offsets, local_x);
+        self.view_mut().hit_cache.insert(block.block_id, line_idx, signature, offsets);
+        offset
+    }
+
+    fn link_at(&self, page: &wa_engine::Page, config: &LayoutConfig, rect: egui::Rect, pos: egui::Pos2) -> Option<(uuid::Uuid, wa_core::SharedStr)> {
+        let line_height = config.metrics.font_size * config.metrics.line_height;
+        let left = rect.left() + config.margin;
+        let mut cursor_y = rect.top() + config.margin;
+        for block in &page.blocks {
+            let block_top = cursor_y;
+            cursor_y = block_top + block.height + config.metrics.font_size * 0.5;
+            if pos.y < block_top || pos.y >= block_top + block.lines.len() as f32 * line_height {
+                continue;
+            }
+            let line = &block.lines[((pos.y - block_top) / line_height) as usize];
+            let scale = render::block_font(&block.kind, config).size / config.metrics.font_size;
+            let run = render::run_at_x(line, pos.x - left, scale)?;
+            return run.link.clone().map(|url| (block.block_id, url));
+        }
+        None
+    }
+
+    fn open_link(&mut self, url: &str) {
+        if let Err(err) = links::open_url(url) {
+            self.show_toast(err);
+        }
+    }
+
+    fn show_toast(&mut self, message: String) {
+        self.toast = Some((message, std::time::Instant::now()));
+    }
+
+    fn draw_toast(&mut self, ctx: &egui::Context) {
+        let Some((message, at)) = &self.toast else {
+            return;
+        };
+        if at.elapsed() > std::time::Duration::from_secs(3) {
+            self.toast = None;
+            return;
+        }
+        egui::Area::new(egui::Id::new("toast"))
+            .order(egui::Order::Tooltip)
+            .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -24.0))
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.colored_label(egui::Color32::from_rgb(180, 50, 40), message.as_str());
+                });
+            });
+        ctx.request_repaint_after(std::time::Duration::from_millis(250));
+    }
+
+    fn toggle_layout_stats(&mut self) {
+        let enabled = self.layout_stats.is_none();
+        self.layout.set_collect_timings(enabled);
+        self.layout_stats = enabled.then(|| {
+            self.layout.take_stats();
+            wa_engine::LayoutStats::default()
+        });
+    }
+
+    fn draw_layout_stats(&self, ctx: &egui::Context) {
+        let Some(stats) = &self.layout_stats else {
+            return;
+        };
+        egui::Area::new(egui::Id::new("layout_stats"))
+            .order(egui::Order::Tooltip)
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-12.0, 48.0))
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    for line in status::layout_stats_lines(stats) {
+                        ui.monospace(line);
                     }
-                }
-            } else {
-                self.apply_to_cursors(EditorCommand::InsertText(insert_text), &extra);
-            }
-        }
-        if backspace {
-            if let Some((bid, row, col)) = self.table_focus {
-                if let Some(block) = self.editor.doc.blocks.iter().find(|b| b.id() == bid) {
-                    if let Block::Table { rows, .. } = block {
-                        if let Some(r) = rows.get(row) {
-                            if let Some(c) = r.get(col) {
-                                let mut current = String::new();
-                                for inline in &c.content {
-                                    if let Inline::Text { value } = inline {
-                                        current.push_str(value.as_ref());
+                });
+            });
+    }
+
+    fn draw_link_popover(&mut self, ctx: &egui::Context) {
+        let Some(mut popover) = self.view_mut().link_popover.take() else {
+            return;
+        };
+        let mut keep = true;
+        let area = egui::Area::new(egui::Id::new("link_popover"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(popover.pos + egui::vec2(0.0, 16.0))
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(popover.url.as_ref());
+                    if let Some(edit) = popover.editing.as_mut() {
+                        ui.text_edit_singleline(edit).request_focus();
+                        ui.horizontal(|ui| {
+                            if ui.button("确定").clicked() {
+                                match links::validate_url(edit) {
+                                    Ok(()) => {
+                                        self.view_mut().editor.execute(EditorCommand::EditLink {
+                                            block_id: popover.block_id,
+                                            url: popover.url.to_string(),
+                                            new_url: edit.trim().to_string(),
+                                        });
+                                        keep = false;
                                     }
+                                    Err(err) => self.show_toast(err),
                                 }
-                                current.pop();
-                                self.editor.execute(EditorCommand::TableEditCell { block_id: bid, row, col, text: current });
                             }
-                        }
+                            if ui.button("取消").clicked() {
+                                keep = false;
+                            }
+                        });
+                    } else {
+                        ui.horizontal(|ui| {
+                            if ui.button("打开").clicked() {
+                                self.open_link(&popover.url);
+                                keep = false;
+                            }
+                            if ui.button("编辑链接…").clicked() {
+                                popover.editing = Some(popover.url.to_string());
+                            }
+                            if ui.button("移除链接").clicked() {
+                                self.view_mut().editor.execute(EditorCommand::RemoveLink {
+                                    block_id: popover.block_id,
+                                    url: popover.url.to_string(),
+                                });
+                                keep = false;
+                            }
+                        });
                     }
-                }
-            } else {
-                self.apply_to_cursors(EditorCommand::DeleteSelection, &extra);
-            }
+                });
+            });
+        let clicked_outside = ctx.frame_nr() > popover.opened_frame
+            && ctx.input(|i| i.pointer.any_click())
+            && ctx
+                .input(|i| i.pointer.interact_pos())
+                .is_some_and(|p| !area.response.rect.contains(p));
+        if clicked_outside || ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+            keep = false;
         }
-        if bold {
-            self.apply_to_cursors(EditorCommand::ApplyStyle(Style { bold: true, italic: false, underline: false, strikethrough: false }), &extra);
+        if keep {
+            self.view_mut().link_popover = Some(popover);
         }
-        if italic {
-            self.apply_to_cursors(EditorCommand::ApplyStyle(Style { bold: false, italic: true, underline: false, strikethrough: false }), &extra);
+    }
+
+    fn select_match(&mut self, m: wa_core::FindMatch) {
+        self.view_mut().editor.selection = wa_core::Selection {
+            anchor: wa_core::Position { block_id: m.block_id, offset: m.start },
+            focus: wa_core::Position { block_id: m.block_id, offset: m.end },
+        };
+        self.view_mut().extra_cursors.clear();
+        self.view_mut().scroll_to_block = Some(m.block_id);
+    }
+
+    fn find_step(&mut self, forward: bool) {
+        let editor = &mut self.tabs[self.active].editor;
+        self.find.refresh(&editor.doc, &mut editor.text_index);
+        if let Some(m) = self.find.step(forward) {
+            self.select_match(m);
         }
-        if let Some(level) = heading {
-            self.apply_to_cursors(EditorCommand::SetHeading(level), &extra);
+    }
+
+    fn replace_current(&mut self) {
+        let editor = &mut self.tabs[self.active].editor;
+        self.find.refresh(&editor.doc, &mut editor.text_index);
+        let Some(m) = self.find.current_match() else {
+            self.find_step(true);
+            return;
+        };
+        self.tabs[self.active].editor.execute(EditorCommand::ReplaceMatch {
+            block_id: m.block_id,
+            start: m.start,
+            end: m.end,
+            replacement: self.find.replacement.clone(),
+        });
+        if self.tabs[self.active].editor.last_outcome() != CommandOutcome::Applied {
+            self.show_toast("该匹配位于锁定的块中".to_string());
+            self.find_step(true);
+            return;
+        }
+        let editor = &mut self.tabs[self.active].editor;
+        self.find.refresh(&editor.doc, &mut editor.text_index);
+        if let Some(next) = self.find.current_match() {
+            self.select_match(next);
         }
+    }
 
-        if copy {
-            let plain = self.selection_text();
-            let html = self.selection_html();
-            if !plain.is_empty() {
-                ctx.output_mut(|o| o.copied_text = plain.clone());
-                if let Ok(mut cb) = Clipboard::new() {
-                    let _ = cb.set_text(if html.is_empty() { plain } else { html });
-                }
-            }
+    fn replace_all(&mut self) {
+        let editor = &mut self.tabs[self.active].editor;
+        self.find.refresh(&editor.doc, &mut editor.text_index);
+        if self.find.matches.is_empty() {
+            return;
         }
+        let doc = &self.tabs[self.active].editor.doc;
+        let count = self.find.matches.iter().filter(|m| !doc.blocks[m.block_index].is_locked()).count();
+        let skipped = self.find.matches.len() - count;
+        self.tabs[self.active].editor.execute(EditorCommand::ReplaceAll {
+            query: self.find.query.clone(),
+            replacement: self.find.replacement.clone(),
+            options: self.find.options(),
+        });
+        self.find.current = None;
+        if skipped > 0 {
+            self.show_toast(format!("已替换 {} 处，跳过锁定块中的 {} 处", count, skipped));
+        } else {
+            self.show_toast(format!("已替换 {} 处", count));
+        }
+    }
 
-        if paste && !had_insert && paste_image {
-            if let Ok(mut cb) = Clipboard::new() {
-                if let Ok(image) = cb.get_image() {
-                    let id = uuid::Uuid::new_v4();
-                    self.image_sizes.insert(id, (image.width as f32, image.height as f32));
-                    self.editor.doc.blocks.push(wa_core::Block::Figure {
-                        id,
-                        url: std::sync::Arc::from("clipboard://image"),
-                        caption: Some(std::sync::Arc::from("?????")),
-                        size: Some(wa_core::FigureSize { width: image.width as f32, height: image.height as f32 }),
-                        dirty: true,
-                    });
-                    self.editor.doc.touch();
+    fn close_find(&mut self, ctx: &egui::Context) {
+        self.find.open = false;
+        ctx.memory_mut(|m| {
+            m.surrender_focus(egui::Id::new("find_query"));
+            m.surrender_focus(egui::Id::new("find_replacement"));
+        });
+    }
+
+    fn draw_find_panel(&mut self, ctx: &egui::Context) {
+        if !self.find.open {
+            return;
+        }
+        let editor = &mut self.tabs[self.active].editor;
+        self.find.refresh(&editor.doc, &mut editor.text_index);
+        let mut step = None;
+        let mut replace_one = false;
+        let mut replace_all = false;
+        egui::TopBottomPanel::top("find_panel").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("查找：");
+                let query = ui.add(
+                    egui::TextEdit::singleline(&mut self.find.query)
+                        .id(egui::Id::new("find_query"))
+                        .desired_width(180.0),
+                );
+                if self.find.focus_query {
+                    query.request_focus();
+                    self.find.focus_query = false;
                 }
-            }
+                if query.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    step = Some(!ui.input(|i| i.modifiers.shift));
+                    query.request_focus();
+                }
+                ui.checkbox(&mut self.find.case_insensitive, "忽略大小写");
+                ui.checkbox(&mut self.find.headings_only, "仅标题");
+                ui.label(self.find.counter());
+                if ui.button("上一个").clicked() {
+                    step = Some(false);
+                }
+                if ui.button("下一个").clicked() {
+                    step = Some(true);
+                }
+                ui.separator();
+                ui.label("替换为：");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.find.replacement)
+                        .id(egui::Id::new("find_replacement"))
+                        .desired_width(140.0),
+                );
+                replace_one = ui.button("替换").clicked();
+                replace_all = ui.button("全部替换").clicked();
+            });
+        });
+        if let Some(forward) = step {
+            self.find_step(forward);
+        }
+        if replace_one {
+            self.replace_current();
+        }
+        if replace_all {
+            self.replace_all();
         }
     }
 
-    fn draw_block_frame(painter: &egui::Painter, rect: egui::Rect) {
-        painter.rect_stroke(rect, 4.0, egui::Stroke::new(1.0, egui::Color32::from_gray(210)));
+    fn draw_status_bar(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let view = &mut self.tabs[self.active];
+                let selection = view.editor.selection;
+                let counts = match view.status.selection(&view.editor.doc, &selection) {
+                    Some(selected) => status::counts_label(selected, true),
+                    None => status::counts_label(view.status.document(&view.editor.doc, &mut view.editor.text_index).text, false),
+                };
+                ui.label(counts);
+                ui.separator();
+
+                let total = view.layout_tree.as_ref().map(|t| t.pages.len()).unwrap_or(1).max(1);
+                let current = view
+                    .layout_tree
+                    .as_ref()
+                    .and_then(|t| t.page_of(selection.focus.block_id))
+                    .unwrap_or(0)
+                    + 1;
+                let page_label = ui
+                    .add(egui::Label::new(format!("第 {}/{} 页", current, total)).sense(egui::Sense::click()))
+                    .on_hover_text("跳转到页");
+                let popup_id = ui.make_persistent_id("goto_page");
+                if page_label.clicked() {
+                    self.goto_page = current;
+                    ui.memory_mut(|m| m.toggle_popup(popup_id));
+                }
+                egui::popup_above_or_below_widget(ui, popup_id, &page_label, egui::AboveOrBelow::Above, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("跳转到");
+                        ui.add(egui::DragValue::new(&mut self.goto_page).clamp_range(1..=total));
+                        if ui.button("转到").clicked() {
+                            self.view_mut().scroll_to_page = Some(self.goto_page.saturating_sub(1));
+                            ui.memory_mut(|m| m.close_popup());
+                        }
+                    });
+                });
+                ui.separator();
+
+                if let Some(block) = self.view().editor.doc.blocks.iter().find(|b| b.id() == selection.focus.block_id) {
+                    ui.label(format!("{} · 位置 {}", status::block_label(block), selection.focus.offset));
+                }
+            });
+        });
     }
 
+    fn layout_config(&self) -> LayoutConfig {
+        let paper_mm = self.settings.paper.size_mm();
+        LayoutConfig {
+            paged: self.view_mode == ViewMode::Paged,
+            metrics: FontMetrics { font_size: 14.0, line_height: 1.7 },
+            line_numbers: self.line_numbers,
+            page_width: (paper_mm.0 / print::MM_PER_PX).round(),
+            page_height: (paper_mm.1 / print::MM_PER_PX).round(),
+            ..LayoutConfig::default()
+        }
+        .zoomed(self.zoom)
+    }
 
-    fn hit_test_page(&mut self, page: &wa_engine::Page, config: &LayoutConfig, rect: egui::Rect, pos: egui::Pos2) -> Option<wa_core::Position> {
-        let mut cursor_y = rect.top() + config.margin;
-        for (b_idx, block) in page.blocks.iter().enumerate() {
-            for (line_idx, _line) in block.lines.iter().enumerate() {
-                let line_height = config.metrics.font_size * config.metrics.line_height;
-                let line_top = cursor_y;
-                let line_bottom = cursor_y + line_height;
-                if pos.y >= line_top && pos.y <= line_bottom {
-                    let local_x = (pos.x - (rect.left() + config.margin)).max(0.0);
-                    let key = (block.block_id, line_idx);
-                    if let Some(offsets) = self.hit_cache.get(&key) {
-                        let mut offset = 0usize;
-                        if let Err(idx) = offsets.binary_search_by(|v| v.partial_cmp(&local_x).unwrap_or(std::cmp::Ordering::Greater)) {
-                            offset = idx.saturating_sub(1);
-                        } else if let Ok(idx) = offsets.binary_search_by(|v| v.partial_cmp(&local_x).unwrap_or(std::cmp::Ordering::Greater)) {
-                            offset = idx.saturating_sub(1);
+    /// Thumbnails of the pages of the last layout; clicking one scrolls to
+    /// its page.
+    fn draw_thumbnail_panel(&mut self, ctx: &egui::Context) {
+        if !self.show_thumbnails || self.view_mode != ViewMode::Paged {
+            return;
+        }
+        let config = self.layout_config();
+        let view = &mut self.tabs[self.active];
+        let Some(layout) = view.layout_tree.as_ref().filter(|_| view.layout_paged_view) else {
+            return;
+        };
+        view.thumbnails.update(ctx, layout, view.layout_version, &config);
+        view.thumbnails.poll();
+        let current = layout.page_of(view.editor.selection.focus.block_id).unwrap_or(0);
+        let (pages, page_height) = (layout.pages.len(), config.page_height * thumbnails::THUMBNAIL_WIDTH / config.page_width);
+        let mut jump = None;
+        egui::SidePanel::right("thumbnails").resizable(false).show(ctx, |ui| {
+            egui::ScrollArea::vertical().id_source((view.id, "thumbnails")).show(ui, |ui| {
+                for page in 0..pages {
+                    let size = egui::vec2(thumbnails::THUMBNAIL_WIDTH, page_height);
+                    let (rect, resp) = ui.allocate_exact_size(size, egui::Sense::click());
+                    if ui.is_rect_visible(rect) {
+                        match view.thumbnails.texture(ctx, page) {
+                            Some(texture) => {
+                                let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+                                ui.painter().image(texture.id(), rect, uv, egui::Color32::WHITE);
+                            }
+                            None => {
+                                ui.painter().rect_filled(rect, 0.0, ui.visuals().faint_bg_color);
+                            }
+                        }
+                        if page == current {
+                            ui.painter().rect_stroke(rect, 0.0, egui::Stroke::new(2.0, ui.visuals().selection.bg_fill));
                         }
-                        return Some(wa_core::Position { block_id: block.block_id, offset });
                     }
-                    return self.hit_test_page_uncached(page, config, rect, pos);
+                    if resp.on_hover_text(format!("第 {} 页", page + 1)).clicked() {
+                        jump = Some(page);
+                    }
+                    ui.label(format!("{}", page + 1));
                 }
-                cursor_y += line_height;
-            }
-            let block_end = cursor_y;
-            if pos.y > block_end && pos.y <= block_end + (config.metrics.font_size * 0.5) {
-                let offset = block.lines.last()
-                    .map(|l| l.text.chars().count())
-                    .unwrap_or(0);
-                return Some(wa_core::Position { block_id: block.block_id, offset });
+            });
+        });
+        if jump.is_some() {
+            self.view_mut().scroll_to_page = jump;
+        }
+    }
+
+    fn decorations(&self) -> Vec<render::Decoration> {
+        if !self.find.open {
+            return Vec::new();
+        }
+        let current = self.find.current_match();
+        self.find
+            .matches
+            .iter()
+            .map(|m| render::Decoration {
+                block_id: m.block_id,
+                start: m.start,
+                end: m.end,
+                color: if Some(*m) == current {
+                    render::CURRENT_MATCH_HIGHLIGHT
+                } else {
+                    render::MATCH_HIGHLIGHT
+                },
+            })
+            .collect()
+    }
+
+    fn paint_decorations(
+        &self,
+        painter: &egui::Painter,
+        block: &wa_engine::LayoutBlock,
+        block_rect: egui::Rect,
+        decorations: &[render::Decoration],
+        config: &LayoutConfig,
+        font_id: &egui::FontId,
+    ) {
+        let line_h = config.metrics.font_size * config.metrics.line_height;
+        let scale = font_id.size / config.metrics.font_size;
+        for deco in decorations.iter().filter(|d| d.block_id == block.block_id) {
+            let rects = match block.kind {
+                LayoutKind::Code => render::decoration_rects(block, deco.start, deco.end, line_h, |text| {
+                    painter.layout_no_wrap(text.to_string(), font_id.clone(), render::TEXT_COLOR).size().x
+                }),
+                LayoutKind::Paragraph | LayoutKind::Heading(_) => {
+                    render::decoration_rects(block, deco.start, deco.end, line_h, |text| {
+                        self.measurer.measure(text, config.metrics) * scale
+                    })
+                }
+                _ => vec![egui::Rect::from_min_size(egui::Pos2::ZERO, block_rect.size())],
+            };
+            for r in rects {
+                painter.rect_filled(r.translate(block_rect.min.to_vec2()), 2.0, deco.color);
             }
-            cursor_y += config.metrics.font_size * 0.5;
-            if b_idx > 0 && cursor_y > rect.bottom() {
-                break;
+        }
+    }
+
+    /// Grip in the left margin of a hovered block; while a block drag is in
+    /// progress it also tracks which gap the pointer is over.
+    /// Line numbers left of a code block, one per source line rather than
+    /// per wrapped row. Clicking a number puts the caret at that line.
+    fn code_gutter(&mut self, ui: &mut egui::Ui, painter: &egui::Painter, block: &wa_engine::LayoutBlock, block_rect: egui::Rect, config: &LayoutConfig) {
+        let Some(code) = self.view().editor.doc.blocks.iter().find(|b| b.id() == block.block_id).cloned() else {
+            return;
+        };
+        let line_h = config.metrics.font_size * config.metrics.line_height;
+        let mut goto = None;
+        for (row, line) in block.lines.iter().enumerate() {
+            let Some((number, 1)) = wa_core::code_offset_to_line_col(&code, line.start) else {
+                continue;
+            };
+            let top = block_rect.top() + row as f32 * line_h;
+            let cell = egui::Rect::from_min_max(egui::pos2(block_rect.left() - 60.0, top), egui::pos2(block_rect.left() - 28.0, top + line_h));
+            let resp = ui.interact(cell, egui::Id::new(("code_gutter", block.block_id, number)), egui::Sense::click());
+            let color = if resp.hovered() { egui::Color32::from_gray(80) } else { egui::Color32::from_gray(150) };
+            painter.text(cell.right_top(), egui::Align2::RIGHT_TOP, number.to_string(), egui::FontId::monospace(11.0), color);
+            if resp.clicked() {
+                goto = Some(number);
             }
         }
-        None
+        if let Some(number) = goto {
+            self.view_mut().editor.goto_line(block.block_id, number, 1);
+            self.caret_epoch = std::time::Instant::now();
+        }
     }
 
-    fn hit_test_page_uncached(&mut self, page: &wa_engine::Page, config: &LayoutConfig, rect: egui::Rect, pos: egui::Pos2) -> Option<wa_core::Position> {
-        let mut cursor_y = rect.top() + config.margin;
-        for (b_idx, block) in page.blocks.iter().enumerate() {
-            for (line_idx, line) in block.lines.iter().enumerate() {
-                let line_height = config.metrics.font_size * config.metrics.line_height;
-                let line_top = cursor_y;
-                let line_bottom = cursor_y + line_height;
-                if pos.y >= line_top && pos.y <= line_bottom {
-                    let local_x = (pos.x - (rect.left() + config.margin)).max(0.0);
-                    let mut acc = 0.0f32;
-                    let mut offsets = Vec::with_capacity(line.text.chars().count() + 1);
-                    offsets.push(0.0);
-                    let mut buf = [0u8; 4];
-                    for ch in line.text.chars() {
-                        let w = self.measurer.measure(ch.encode_utf8(&mut buf), config.metrics);
-                        acc += w;
-                        offsets.push(acc);
-                    }
-                    self.hit_cache.insert((block.block_id, line_idx), offsets.clone());
-                    let mut offset = 0usize;
-                    if let Err(idx) = offsets.binary_search_by(|v| v.partial_cmp(&local_x).unwrap_or(std::cmp::Ordering::Greater)) {
-                        offset = idx.saturating_sub(1);
-                    } else if let Ok(idx) = offsets.binary_search_by(|v| v.partial_cmp(&local_x).unwrap_or(std::cmp::Ordering::Greater)) {
-                        offset = idx.saturating_sub(1);
-                    }
-                    return Some(wa_core::Position { block_id: block.block_id, offset });
+    fn block_drag_handle(
+        &mut self,
+        ui: &mut egui::Ui,
+        painter: &egui::Painter,
+        block: &wa_engine::LayoutBlock,
+        page_rect: egui::Rect,
+        block_rect: egui::Rect,
+        block_gap: f32,
+    ) {
+        let handle_rect = egui::Rect::from_min_size(
+            egui::pos2(block_rect.left() - 24.0, block_rect.top()),
+            egui::vec2(16.0, block_rect.height().clamp(4.0, 20.0)),
+        );
+        let resp = ui.interact(handle_rect, egui::Id::new(("block_handle", block.block_id)), egui::Sense::drag());
+        let band = egui::Rect::from_min_max(
+            egui::pos2(page_rect.left(), block_rect.top() - block_gap * 0.5),
+            egui::pos2(page_rect.right(), block_rect.bottom() + block_gap * 0.5),
+        );
+        let pointer = ui.ctx().pointer_latest_pos();
+        let over_band = pointer.is_some_and(|p| band.contains(p));
+        if self.view().block_drag.is_none() && (over_band || resp.hovered()) {
+            let color = if resp.hovered() { egui::Color32::from_gray(90) } else { egui::Color32::from_gray(170) };
+            for row in 0..3 {
+                for col in 0..2 {
+                    let dot = handle_rect.left_top() + egui::vec2(5.0 + col as f32 * 5.0, 4.0 + row as f32 * 5.0);
+                    painter.circle_filled(dot, 1.3, color);
                 }
-                cursor_y += line_height;
             }
-            cursor_y += config.metrics.font_size * 0.5;
-            if b_idx > 0 && cursor_y > rect.bottom() {
-                break;
+            if resp.hovered() {
+                ui.ctx().set_cursor_icon(egui::CursorIcon::Grab);
             }
         }
-        None
+        if resp.drag_started() {
+            self.view_mut().block_drag = Some(dragdrop::BlockDrag {
+                block_id: block.block_id,
+                preview: block.lines.first().map(|l| l.text.clone()).unwrap_or_default(),
+                target: None,
+            });
+        }
+        let Some(pointer) = pointer.filter(|_| over_band) else {
+            return;
+        };
+        let Some(index) = self.view().editor.doc.blocks.iter().position(|b| b.id() == block.block_id) else {
+            return;
+        };
+        if let Some(drag) = &mut self.view_mut().block_drag {
+            let gap = dragdrop::drop_gap(pointer.y, block_rect.top(), block_rect.bottom(), index);
+            let y = if gap == index {
+                block_rect.top() - block_gap * 0.5
+            } else {
+                block_rect.bottom() + block_gap * 0.5
+            };
+            drag.target = Some(dragdrop::DropTarget {
+                index: gap,
+                line: [egui::pos2(block_rect.left(), y), egui::pos2(block_rect.right(), y)],
+            });
+        }
+    }
+
+    /// Ghost, insertion line and drop for an active block drag.
+    fn update_block_drag(&mut self, ctx: &egui::Context) {
+        let Some(drag) = self.view().block_drag.clone() else {
+            return;
+        };
+        if !ctx.input(|i| i.pointer.primary_down()) {
+            self.view_mut().block_drag = None;
+            if let Some(target) = drag.target {
+                self.view_mut().editor.execute(EditorCommand::MoveBlock { block_id: drag.block_id, to_index: target.index });
+            }
+            return;
+        }
+        ctx.set_cursor_icon(egui::CursorIcon::Grabbing);
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Tooltip, egui::Id::new("block_drag")));
+        if let Some(target) = drag.target {
+            painter.line_segment(target.line, egui::Stroke::new(2.0, egui::Color32::from_rgb(60, 110, 220)));
+        }
+        if let Some(pos) = ctx.pointer_latest_pos() {
+            let ghost = egui::Rect::from_min_size(pos + egui::vec2(12.0, 8.0), egui::vec2(280.0, 28.0));
+            painter.rect_filled(ghost, 4.0, egui::Color32::from_rgba_unmultiplied(250, 248, 242, 210));
+            painter.rect_stroke(ghost, 4.0, egui::Stroke::new(1.0, egui::Color32::from_gray(180)));
+            let preview: String = drag.preview.chars().take(24).collect();
+            painter.text(
+                ghost.left_center() + egui::vec2(8.0, 0.0),
+                egui::Align2::LEFT_CENTER,
+                preview,
+                egui::FontId::proportional(13.0),
+                render::TEXT_COLOR,
+            );
+        }
     }
 
     fn draw_page_at(&mut self, ui: &mut egui::Ui, page: &wa_engine::Page, config: &LayoutConfig, rect: egui::Rect, show_frame: bool) {
@@ -469,7 +1865,12 @@ impl EditorApp {
         let mut cursor_y = rect.top() + config.margin;
         let block_gap = config.metrics.font_size * 0.5;
         let clip = ui.clip_rect();
-        let ratio = self.render_cache.dirty_ratio(page.blocks.len());
+        let ratio = self.view().render_cache.dirty_ratio(page.blocks.iter().map(|b| b.block_id));
+        let skip_clean = self.view().render_cache.skips_clean(ratio);
+        let blink_phase = self.caret_epoch.elapsed().as_millis() / CARET_BLINK.as_millis();
+        let caret_visible = blink_phase.is_multiple_of(2);
+        let decorations = self.decorations();
+        let locked: std::collections::HashSet<uuid::Uuid> = self.view().editor.doc.blocks.iter().filter(|b| b.is_locked()).map(Block::id).collect();
         let mut idx = 0usize;
         while idx < page.blocks.len() {
             let block = &page.blocks[idx];
@@ -483,12 +1884,12 @@ impl EditorApp {
             if block_top > clip.bottom() {
                 break;
             }
-            if ratio > 0.0 && ratio <= 0.05 && !self.render_cache.is_dirty(block.block_id) {
+            if self.view().block_drag.is_none() && skip_clean && !self.view().render_cache.is_dirty(block.block_id) {
                 let mut skip_height = block.height + block_gap;
                 let mut j = idx + 1;
                 while j < page.blocks.len() {
                     let next = &page.blocks[j];
-                    if self.render_cache.is_dirty(next.block_id) {
+                    if self.view().render_cache.is_dirty(next.block_id) {
                         break;
                     }
                     skip_height += next.height + block_gap;
@@ -502,61 +1903,124 @@ impl EditorApp {
                 egui::pos2(rect.left() + config.margin, block_top),
                 egui::pos2(rect.right() - config.margin, block_bottom),
             );
-            let font_id = match block.kind {
-                LayoutKind::Heading(level) => {
-                    let size = match level {
-                        1 => 20.0,
-                        2 => 18.0,
-                        _ => 16.0,
-                    };
-                    egui::FontId::proportional(size)
-                }
-                _ => egui::FontId::proportional(config.metrics.font_size),
-            };
+            let font_id = render::block_font(&block.kind, config);
             let start_y = block_top;
             let mut line_y = block_top;
-            for line in &block.lines {
+            let scale = font_id.size / config.metrics.font_size;
+            let dark_code = ui.visuals().dark_mode;
+            if let LayoutKind::Code = block.kind {
+                let bg = if dark_code { render::CODE_BLOCK_DARK } else { render::CODE_BLOCK_LIGHT };
+                painter.rect_filled(block_rect, 4.0, bg);
+            }
+            if let LayoutKind::Table = block.kind {
+                if self.table_size(block.block_id).is_some_and(|(_, _, header)| header) {
+                    let row_h = config.metrics.font_size * config.metrics.line_height;
+                    let header_rect = egui::Rect::from_min_size(block_rect.min, egui::vec2(block_rect.width(), row_h));
+                    painter.rect_filled(header_rect, 2.0, render::TABLE_HEADER_BACKGROUND);
+                }
+            }
+            self.paint_decorations(&painter, block, block_rect, &decorations, config, &font_id);
+            if locked.contains(&block.block_id) {
                 painter.text(
-                    egui::pos2(rect.left() + config.margin, line_y),
+                    egui::pos2(block_rect.right() + 4.0, block_top),
                     egui::Align2::LEFT_TOP,
-                    &line.text,
-                    font_id.clone(),
-                    egui::Color32::from_rgb(40, 30, 20),
+                    "🔒",
+                    egui::FontId::proportional(12.0),
+                    egui::Color32::from_gray(140),
                 );
-                line_y += config.metrics.font_size * config.metrics.line_height;
             }
-            if self.editor.selection.focus.block_id == block.block_id {
-                let caret_x = rect.left() + config.margin;
-                let caret_rect = egui::Rect::from_min_size(
-                    egui::pos2(caret_x, start_y),
-                    egui::vec2(2.0, config.metrics.font_size * config.metrics.line_height),
-                );
-                painter.rect_filled(caret_rect, 0.0, egui::Color32::from_rgb(30, 30, 30));
+            let table_cells = match block.kind {
+                LayoutKind::Table => self.table_cell_lens(block.block_id),
+                _ => None,
+            };
+            let selection = self.view().editor.selection;
+            let caret = (selection.is_collapsed() && selection.focus.block_id == block.block_id)
+                .then(|| {
+                    let affinity = self.view().editor.caret_affinity();
+                    wa_engine::caret_geometry_with_affinity(block, selection.focus.offset, affinity, config, &self.measurer)
+                });
+            let composing = self.ime_active && !self.ime_buffer.is_empty();
+            for (line_idx, line) in block.lines.iter().enumerate() {
+                let pos = egui::pos2(rect.left() + config.margin, line_y);
+                let composed;
+                let line = match caret {
+                    Some(caret) if composing && caret.line == line_idx && table_cells.is_none() => {
+                        let at = selection.focus.offset.saturating_sub(line.start);
+                        let measure = |text: &str| self.measurer.measure(text, config.metrics);
+                        composed = render::inject_composition(line, at, &self.ime_buffer, measure).0;
+                        &composed
+                    }
+                    _ => line,
+                };
+                if let Some(number) = line.number {
+                    painter.text(
+                        egui::pos2(rect.left() + config.margin - 8.0, line_y),
+                        egui::Align2::RIGHT_TOP,
+                        number.to_string(),
+                        egui::FontId::monospace(font_id.size * 0.75),
+                        egui::Color32::from_gray(150),
+                    );
+                }
+                if let Some((fractions, cell_lens)) = &table_cells {
+                    let row_h = config.metrics.font_size * config.metrics.line_height;
+                    let lens = cell_lens.get(line_idx).map(Vec::as_slice).unwrap_or(&[]);
+                    let measure = |text: &str| self.measurer.measure(text, config.metrics);
+                    for (col, cell) in render::split_table_row(line, lens, measure).iter().enumerate() {
+                        let (left, right) = render::column_span(fractions, col);
+                        let cell_rect = egui::Rect::from_min_max(
+                            egui::pos2(block_rect.left() + left * block_rect.width(), line_y),
+                            egui::pos2(block_rect.left() + right * block_rect.width(), line_y + row_h),
+                        );
+                        let cell_painter = painter.with_clip_rect(cell_rect.intersect(painter.clip_rect()));
+                        let at = egui::pos2(cell_rect.left() + render::TABLE_CELL_PADDING, line_y);
+                        render::paint_line(&cell_painter, at, cell, &font_id, render::TEXT_COLOR, scale);
+                    }
+                } else if let LayoutKind::Code = block.kind {
+                    let color = if dark_code { render::CODE_TEXT_DARK } else { render::TEXT_COLOR };
+                    let galley = painter.layout_job(render::code_line_job(line, &font_id, color));
+                    painter.galley(pos, galley, color);
+                } else {
+                    render::paint_line(&painter, pos, line, &font_id, render::TEXT_COLOR, scale);
+                }
+                line_y += config.metrics.font_size * config.metrics.line_height;
             }
             match block.kind {
-                LayoutKind::Quote => {
-                    if show_frame {
-                        Self::draw_block_frame(&painter, block_rect);
-                    }
+                LayoutKind::Quote | LayoutKind::Custom(_) if show_frame => {
+                    Self::draw_block_frame(&painter, block_rect);
                 }
                 LayoutKind::Code => {
-                    painter.rect_filled(block_rect, 4.0, egui::Color32::from_rgb(245, 242, 235));
+                    self.code_gutter(ui, &painter, block, block_rect, config);
+                    let lang = self.view().editor.doc.blocks.iter().find_map(|b| match b {
+                        Block::Code { id, lang, .. } if *id == block.block_id => Some(lang.clone()),
+                        _ => None,
+                    });
+                    if let Some(lang) = lang.filter(|l| !l.trim().is_empty()) {
+                        painter.text(
+                            block_rect.right_top() + egui::vec2(-6.0, 4.0),
+                            egui::Align2::RIGHT_TOP,
+                            lang.as_ref(),
+                            egui::FontId::proportional(11.0),
+                            egui::Color32::from_gray(140),
+                        );
+                    }
                 }
                 LayoutKind::Table => {
-                    if let Some((bid, row, col)) = self.table_focus {
+                    if let Some((fractions, _)) = &table_cells {
+                        self.table_column_borders(ui, &painter, block.block_id, block_rect, fractions);
+                    }
+                    if let Some((bid, row, col)) = self.view().table_focus {
                         if bid == block.block_id {
-                            if let Some(doc_block) = self.editor.doc.blocks.iter().find(|b| b.id() == bid) {
-                                if let Block::Table { rows, .. } = doc_block {
-                                    if !rows.is_empty() {
-                                        let row_h = config.metrics.font_size * config.metrics.line_height;
-                                        let cols = rows[0].len().max(1);
-                                        let width = block_rect.width();
-                                        let col_w = width / cols as f32;
-                                        let x0 = block_rect.left() + col_w * col as f32;
-                                        let y0 = block_rect.top() + row_h * row as f32;
-                                        let cell_rect = egui::Rect::from_min_size(egui::pos2(x0, y0), egui::vec2(col_w, row_h));
-                                        painter.rect_stroke(cell_rect, 2.0, egui::Stroke::new(1.0, egui::Color32::from_rgb(90, 120, 200)));
-                                    }
+                            if let Some(table @ Block::Table { rows, .. }) = self.view().editor.doc.blocks.iter().find(|b| b.id() == bid) {
+                                if !rows.is_empty() {
+                                    let row_h = config.metrics.font_size * config.metrics.line_height;
+                                    let (left, right) = render::column_span(&TableEditor::col_fractions(table), col);
+                                    let x0 = block_rect.left() + left * block_rect.width();
+                                    let y0 = block_rect.top() + row_h * row as f32;
+                                    let cell_rect = egui::Rect::from_min_size(
+                                        egui::pos2(x0, y0),
+                                        egui::vec2((right - left) * block_rect.width(), row_h),
+                                    );
+                                    painter.rect_stroke(cell_rect, 2.0, egui::Stroke::new(1.0, egui::Color32::from_rgb(90, 120, 200)));
                                 }
                             }
                         }
@@ -568,8 +2032,8 @@ impl EditorApp {
                 LayoutKind::Figure => {
                     painter.rect_filled(block_rect, 6.0, egui::Color32::from_rgb(238, 232, 220));
                     if let Some(meta) = &block.meta {
-                        let (w, h) = self.image_sizes.get(&block.block_id)
-                            .copied()
+                        let (w, h) = self.view().image_sizes.get(&block.block_id)
+                            .map(|(w, h)| (w * config.zoom, h * config.zoom))
                             .unwrap_or((meta.width, meta.height));
                         let max_w = (block_rect.width() - 16.0).max(1.0);
                         let max_h = (block_rect.height() - 16.0).max(1.0);
@@ -577,14 +2041,39 @@ impl EditorApp {
                             egui::pos2(block_rect.left() + 8.0, block_rect.top() + 8.0),
                             egui::vec2(w.min(max_w), h.min(max_h)),
                         );
-                        painter.rect_filled(img_rect, 4.0, egui::Color32::from_rgb(210, 200, 185));
-                        painter.text(
-                            img_rect.center(),
-                            egui::Align2::CENTER_CENTER,
-                            "图片",
-                            egui::FontId::proportional(12.0),
-                            egui::Color32::from_rgb(90, 80, 70),
-                        );
+                        let url = self.view().editor.doc.blocks.iter().find_map(|b| match b {
+                            Block::Figure { id, url, .. } if *id == block.block_id => Some(url.clone()),
+                            _ => None,
+                        });
+                        let entry = url.as_ref().map(|url| self.textures.get(ui.ctx(), url));
+                        let placeholder = match entry {
+                            Some(images::TextureEntry::Ready(texture)) => {
+                                painter.image(
+                                    texture.id(),
+                                    img_rect,
+                                    egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                                    egui::Color32::WHITE,
+                                );
+                                None
+                            }
+                            Some(images::TextureEntry::Loading) => Some(("加载中…", None)),
+                            Some(images::TextureEntry::Failed(err)) => Some(("图片", Some(err.clone()))),
+                            None => Some(("图片", None)),
+                        };
+                        if let Some((label, error)) = placeholder {
+                            painter.rect_filled(img_rect, 4.0, egui::Color32::from_rgb(210, 200, 185));
+                            painter.text(
+                                img_rect.center(),
+                                egui::Align2::CENTER_CENTER,
+                                label,
+                                egui::FontId::proportional(12.0),
+                                egui::Color32::from_rgb(90, 80, 70),
+                            );
+                            if let Some(error) = error {
+                                ui.interact(img_rect, egui::Id::new(("figure_error", block.block_id)), egui::Sense::hover())
+                                    .on_hover_text(error);
+                            }
+                        }
                         let handle = egui::Rect::from_min_size(
                             egui::pos2(img_rect.right() - 8.0, img_rect.bottom() - 8.0),
                             egui::vec2(8.0, 8.0),
@@ -593,7 +2082,7 @@ impl EditorApp {
                         let resp = ui.interact(handle, egui::Id::new(block.block_id), egui::Sense::drag());
                         if resp.drag_started() {
                             if let Some(pos) = resp.interact_pointer_pos() {
-                                self.resizing_image = Some((block.block_id.to_string(), pos));
+                                self.view_mut().resizing_image = Some((block.block_id.to_string(), pos));
                             }
                         }
                     }
@@ -601,33 +2090,106 @@ impl EditorApp {
                         Self::draw_block_frame(&painter, block_rect);
                     }
                 }
+                // The scroll view does not break pages, so mark where one
+                // would go.
+                LayoutKind::PageBreak if !show_frame => {
+                    painter.hline(block_rect.x_range(), block_top, egui::Stroke::new(1.0, egui::Color32::from_gray(180)));
+                }
                 _ => {}
             }
+            if let Some(caret) = caret {
+                let mut caret_x = rect.left() + config.margin + caret.x * scale;
+                if composing {
+                    let caret_y = start_y + caret.y;
+                    if table_cells.is_some() {
+                        // Table rows are split into cells when painted, so the
+                        // composition is drawn over the row instead of
+                        // spliced into it.
+                        let galley = painter.layout_job(egui::text::LayoutJob::single_section(
+                            self.ime_buffer.clone(),
+                            egui::TextFormat {
+                                font_id: font_id.clone(),
+                                color: render::TEXT_COLOR,
+                                background: egui::Color32::from_rgb(250, 248, 242),
+                                underline: egui::Stroke::new(1.0, render::TEXT_COLOR),
+                                ..Default::default()
+                            },
+                        ));
+                        let width = galley.size().x;
+                        painter.galley(egui::pos2(caret_x, caret_y), galley, render::TEXT_COLOR);
+                        caret_x += width;
+                    } else {
+                        caret_x += self.measurer.measure(&self.ime_buffer, config.metrics) * scale;
+                    }
+                }
+                let caret_rect = egui::Rect::from_min_size(
+                    egui::pos2(caret_x, start_y + caret.y),
+                    egui::vec2(2.0, caret.height),
+                );
+                self.ime_cursor_rect = Some(caret_rect);
+                if caret_visible {
+                    painter.rect_filled(caret_rect, 0.0, egui::Color32::from_rgb(30, 30, 30));
+                }
+            }
+            self.block_drag_handle(ui, &painter, block, rect, block_rect, block_gap);
+            self.view_mut().render_cache.mark_clean(block.block_id);
             cursor_y = block_bottom + block_gap;
             idx += 1;
         }
-
-        if self.ime_active && !self.ime_buffer.is_empty() {
-            let overlay_rect = egui::Rect::from_min_size(
-                egui::pos2(rect.left() + config.margin, rect.bottom() - 48.0),
-                egui::vec2(260.0, 32.0),
-            );
-            painter.rect_filled(overlay_rect, 6.0, egui::Color32::from_rgb(255, 255, 255));
-            painter.rect_stroke(overlay_rect, 6.0, egui::Stroke::new(1.0, egui::Color32::from_gray(180)));
-            painter.text(
-                overlay_rect.min + egui::vec2(8.0, 6.0),
-                egui::Align2::LEFT_TOP,
-                &self.ime_buffer,
-                egui::FontId::proportional(14.0),
-                egui::Color32::from_rgb(80, 70, 60),
-            );
-        }
     }
 }
 
 impl App for EditorApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::F)) {
+            self.find.open = true;
+            self.find.focus_query = true;
+        }
+        if self.find.open && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape)) {
+            self.close_find(ctx);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Tab)) {
+            self.switch_tab(tabs::cycle(self.active, self.tabs.len(), true));
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::Tab)) {
+            self.switch_tab(tabs::cycle(self.active, self.tabs.len(), false));
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::N)) {
+            self.new_tab();
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::W)) {
+            self.close_tab(self.active);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::O)) {
+            self.open_dialog();
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::S)) {
+            self.save(true);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::S)) {
+            self.save(false);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::P)) {
+            self.open_print_dialog();
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::F12)) {
+            self.toggle_layout_stats();
+        }
         self.handle_input(ctx);
+        self.clamp_table_focus();
+        self.textures.poll(ctx);
+
+        if self.view().caret_focus != Some(self.view().editor.selection.focus) {
+            self.view_mut().caret_focus = Some(self.view_mut().editor.selection.focus);
+            self.caret_epoch = std::time::Instant::now();
+        }
+        let blink_elapsed = self.caret_epoch.elapsed().as_millis() % CARET_BLINK.as_millis();
+        ctx.request_repaint_after(CARET_BLINK - std::time::Duration::from_millis(blink_elapsed as u64));
+
+        let zoom_delta = ctx.input(|i| i.zoom_delta());
+        if zoom_delta != 1.0 {
+            self.zoom = (self.zoom * zoom_delta).clamp(*ZOOM_RANGE.start(), *ZOOM_RANGE.end());
+        }
 
         let mut scrolled = false;
         ctx.input(|i| {
@@ -642,175 +2204,342 @@ impl App for EditorApp {
             .map(|t| t.elapsed() < self.scroll_debounce)
             .unwrap_or(false);
 
-        if let Some((block_id, start)) = self.resizing_image.clone() {
+        if let Some((block_id, start)) = self.view().resizing_image.clone() {
             if let Some(pos) = ctx.input(|i| i.pointer.interact_pos()) {
-                let dx = (pos.x - start.x).max(1.0);
-                let dy = (pos.y - start.y).max(1.0);
+                let dx = (pos.x - start.x).max(1.0) / self.zoom;
+                let dy = (pos.y - start.y).max(1.0) / self.zoom;
                 if let Ok(uid) = uuid::Uuid::parse_str(&block_id) {
-                    self.image_sizes.insert(uid, (dx, dy));
-                    for block in &mut self.editor.doc.blocks {
-                        if let wa_core::Block::Figure { id, size, .. } = block {
-                            if *id == uid {
-                                *size = Some(FigureSize { width: dx, height: dy });
-                                block.set_dirty(true);
-                            }
-                        }
-                    }
-                    self.editor.doc.touch();
+                    self.view_mut().image_sizes.insert(uid, (dx, dy));
+                    self.view_mut().editor.execute(EditorCommand::ResizeFigure { block_id: uid, width: dx, height: dy });
                 }
             }
             if ctx.input(|i| !i.pointer.primary_down()) {
-                self.resizing_image = None;
+                self.view_mut().resizing_image = None;
             }
         }
 
+        self.draw_menu_bar(ctx);
+        self.draw_tab_strip(ctx);
         egui::TopBottomPanel::top("toolbar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.label("视图：");
                 ui.selectable_value(&mut self.view_mode, ViewMode::Paged, "分页");
                 ui.selectable_value(&mut self.view_mode, ViewMode::Scroll, "滚动");
+                ui.add_enabled(self.view_mode == ViewMode::Paged, egui::Checkbox::new(&mut self.show_thumbnails, "缩略图"));
+                if ui.checkbox(&mut self.line_numbers, "行号").changed() {
+                    for view in &mut self.tabs {
+                        view.invalidate_layout();
+                    }
+                }
+                ui.add(
+                    egui::Slider::new(&mut self.zoom, ZOOM_RANGE)
+                        .custom_formatter(|v, _| format!("{:.0}%", v * 100.0))
+                        .custom_parser(|s| s.trim().trim_end_matches('%').parse::<f64>().ok().map(|v| v / 100.0)),
+                )
+                .on_hover_text("缩放（Ctrl+滚轮）");
+                ui.separator();
+                if ui.add_enabled(self.view().editor.can_undo(), egui::Button::new("撤销")).on_hover_text("Ctrl+Z").clicked() {
+                    self.view_mut().editor.execute(EditorCommand::Undo);
+                }
+                if ui.add_enabled(self.view().editor.can_redo(), egui::Button::new("重做")).on_hover_text("Ctrl+Shift+Z / Ctrl+Y").clicked() {
+                    self.view_mut().editor.execute(EditorCommand::Redo);
+                }
                 ui.separator();
                 if ui.button("列表").clicked() {
-                    self.editor.execute(EditorCommand::InsertList(false));
+                    self.view_mut().editor.execute(EditorCommand::InsertList(false));
+                }
+                if ui.button("• 列表").on_hover_text("将选中段落转为列表，或将列表转回段落").clicked() {
+                    self.apply_to_cursors(EditorCommand::ToggleList(false), &[]);
+                }
+                if ui.button("1. 列表").on_hover_text("将选中段落转为编号列表，或将编号列表转回段落").clicked() {
+                    self.apply_to_cursors(EditorCommand::ToggleList(true), &[]);
+                }
+                if ui.button("任务").on_hover_text("勾选或取消勾选当前列表项").clicked() {
+                    self.apply_to_cursors(EditorCommand::ToggleTaskItem, &[]);
+                }
+                if ui.button("取消任务").on_hover_text("将当前任务项恢复为普通列表项").clicked() {
+                    self.apply_to_cursors(EditorCommand::SetTaskState(None), &[]);
                 }
                 if ui.button("引用").clicked() {
-                    self.editor.execute(EditorCommand::InsertQuote("引用内容".to_string()));
+                    self.view_mut().editor.execute(EditorCommand::InsertQuote("引用内容".to_string()));
                 }
                 if ui.button("代码块").clicked() {
-                    self.editor.execute(EditorCommand::InsertCode {
+                    self.view_mut().editor.execute(EditorCommand::InsertCode {
                         lang: "rs".to_string(),
                         code: "fn main() {}".to_string(),
                     });
                 }
                 if ui.button("表格").clicked() {
-                    self.editor.execute(EditorCommand::InsertTable(3, 3));
+                    self.view_mut().editor.execute(EditorCommand::InsertTable(3, 3));
+                }
+                ui.menu_button("转换", |ui| {
+                    let targets = [
+                        ("正文", TargetKind::Paragraph),
+                        ("标题 1", TargetKind::Heading(1)),
+                        ("标题 2", TargetKind::Heading(2)),
+                        ("标题 3", TargetKind::Heading(3)),
+                        ("引用", TargetKind::Quote),
+                        ("代码", TargetKind::Code { lang: "text".to_string() }),
+                    ];
+                    for (label, target) in targets {
+                        if ui.button(label).clicked() {
+                            self.apply_to_cursors(EditorCommand::ConvertBlock(target), &[]);
+                            ui.close_menu();
+                        }
+                    }
+                });
+                if ui.button("分页符").clicked() {
+                    self.view_mut().editor.execute(EditorCommand::InsertPageBreak);
+                }
+                if ui.button("目录").on_hover_text("按标题插入目录，点击条目跳转").clicked() {
+                    self.view_mut().editor.execute(EditorCommand::InsertToc);
+                }
+                let focus = self.view().editor.selection.focus.block_id;
+                if let Some(locked) = self.view().editor.doc.blocks.iter().find(|b| b.id() == focus).map(Block::is_locked) {
+                    if ui.button(if locked { "解锁" } else { "锁定" }).on_hover_text("锁定的块不能编辑").clicked() {
+                        self.view_mut().editor.execute(EditorCommand::SetBlockLocked { block_id: focus, locked: !locked });
+                    }
                 }
                 if ui.button("图" ).clicked() {
-                    let id = uuid::Uuid::new_v4();
-                    self.image_sizes.insert(id, (320.0, 180.0));
-                    self.editor.doc.blocks.push(wa_core::Block::Figure {
-                        id,
-                        url: std::sync::Arc::from("local://placeholder"),
-                        caption: Some(std::sync::Arc::from("示意图")),
-                        size: Some(wa_core::FigureSize { width: 320.0, height: 180.0 }),
-                        dirty: true,
+                    self.view_mut().editor.execute(EditorCommand::InsertFigure {
+                        url: "local://placeholder".to_string(),
+                        caption: Some("示意图".to_string()),
                     });
                 }
+                ui.menu_button("模板", |ui| {
+                    let mut chosen = None;
+                    for name in self.templates.names() {
+                        if ui.button(name).clicked() {
+                            chosen = Some(name.to_string());
+                        }
+                    }
+                    if let Some(template) = chosen.and_then(|name| self.templates.get(&name).cloned()) {
+                        self.view_mut().editor.insert_template(&template);
+                        ui.close_menu();
+                    }
+                });
                 ui.separator();
                 if ui.button("+行").clicked() {
-                    self.editor.execute(EditorCommand::TableInsertRow);
+                    self.view_mut().editor.execute(EditorCommand::TableInsertRow);
                 }
                 if ui.button("+列").clicked() {
-                    self.editor.execute(EditorCommand::TableInsertColumn);
+                    self.view_mut().editor.execute(EditorCommand::TableInsertColumn);
                 }
                 if ui.button("-行").clicked() {
-                    self.editor.execute(EditorCommand::TableDeleteRow);
+                    self.view_mut().editor.execute(EditorCommand::TableDeleteRow);
                 }
                 if ui.button("-列").clicked() {
-                    self.editor.execute(EditorCommand::TableDeleteColumn);
+                    self.view_mut().editor.execute(EditorCommand::TableDeleteColumn);
                 }
             });
         });
 
+        self.draw_find_panel(ctx);
+        self.draw_status_bar(ctx);
+        self.draw_thumbnail_panel(ctx);
+
+        self.ime_cursor_rect = None;
         egui::CentralPanel::default().show(ctx, |ui| {
             let paged_view = self.view_mode == ViewMode::Paged;
-            let mut base = LayoutConfig::default();
             let viewport_h = ui.available_height().max(600.0);
-            let page_height = if paged_view { base.page_height } else { viewport_h };
-            base.page_height = page_height;
-            let config = LayoutConfig {
-                paged: true,
-                metrics: FontMetrics { font_size: 14.0, line_height: 1.7 },
-                ..base
-            };
-            let config_changed = self.layout_paged_view != paged_view
-                || (self.layout_page_height - page_height as i32).abs() > 1;
-            if self.editor.doc.version != self.layout_version || config_changed {
-                self.render_cache.clear();
-                self.hit_cache.clear();
-                for block in &self.editor.doc.blocks {
-                    if block.is_dirty() {
-                        self.render_cache.mark_dirty(block.id());
-                    }
-                }
-                let layout = self.layout.layout_cached(&self.editor.doc, &config, &mut self.cache);
-                self.layout_tree = Some(layout);
-                self.layout_version = self.editor.doc.version;
-                self.layout_paged_view = paged_view;
-                self.layout_page_height = page_height as i32;
-                self.editor.doc.clear_dirty();
-            }
-            let layout = self.layout_tree.as_ref().unwrap().clone();
-            egui::ScrollArea::vertical().show(ui, |ui| {
+            let config = self.layout_config();
+            let page_height = config.page_height;
+            let code_theme = if ui.visuals().dark_mode { CodeTheme::Dark } else { CodeTheme::Light };
+            if self.layout.set_code_theme(code_theme) {
+                for view in &mut self.tabs {
+                    view.invalidate_layout();
+                }
+            }
+            let view = &mut self.tabs[self.active];
+            if view.layout_zoom != self.zoom {
+                view.invalidate_layout();
+                view.layout_zoom = self.zoom;
+            }
+            let config_changed = view.layout_paged_view != paged_view
+                || (view.layout_page_height - page_height as i32).abs() > 1;
+            if view.editor.doc.version != view.layout_version || config_changed || view.layout_tree.is_none() {
+                if config_changed || view.layout_tree.is_none() {
+                    view.render_cache.clear();
+                }
+                for block in &view.editor.doc.blocks {
+                    if block.is_effectively_dirty() {
+                        view.render_cache.mark_dirty(block.id());
+                        view.hit_cache.invalidate_block(block.id());
+                    }
+                }
+                let layout = self.layout.layout_cached_with_signatures(&view.editor.doc, &config, &mut view.cache, &view.editor.signatures);
+                if self.layout_stats.is_some() {
+                    self.layout_stats = Some(self.layout.take_stats());
+                }
+                if view.layout_version == 0 {
+                    // Warm glyphs for the rest of a freshly opened document
+                    // before the user scrolls there.
+                    let _ = self.layout.prewarm_in_background(&view.editor.doc, config.metrics);
+                }
+                view.layout_tree = Some(layout);
+                view.layout_version = view.editor.doc.version;
+                view.layout_paged_view = paged_view;
+                view.layout_page_height = page_height as i32;
+                view.editor.doc.clear_dirty();
+            }
+            let layout = view.layout_tree.as_ref().unwrap().clone();
+            let gap = if paged_view { 24.0 } else { 0.0 };
+            let mut scroll = egui::ScrollArea::vertical().id_source(self.view().id);
+            if let Some(state) = self.view_mut().restore_view.take() {
+                let view = self.view_mut();
+                scroll = scroll.vertical_scroll_offset(state.restore_offset(&view.editor.doc, &layout, &config, gap));
+                if let Some(caret) = state.caret_in(&view.editor.doc) {
+                    view.editor.selection = wa_core::Selection::collapsed(caret);
+                }
+            }
+            if let Some(block_id) = self.view_mut().scroll_to_block.take() {
+                if let Some((top, _)) = layout.block_extent(block_id, &config, gap) {
+                    scroll = scroll.vertical_scroll_offset((top - viewport_h * 0.3).max(0.0));
+                }
+            }
+            if let Some(page_idx) = self.view_mut().scroll_to_page.take() {
+                let page_idx = page_idx.min(layout.pages.len().saturating_sub(1));
+                scroll = scroll.vertical_scroll_offset(layout.page_top(page_idx, &config, gap));
+            }
+            let output = scroll.show(ui, |ui| {
                 let clip = ui.clip_rect();
-                let gap = if paged_view { 24.0 } else { 0.0 };
-                let page_h = config.page_height + gap;
-                let buf_pages = 1usize;
-                let total_pages = layout.pages.len();
-                let start_idx = ((clip.top() / page_h).floor() as isize - buf_pages as isize).max(0) as usize;
-                let end_idx = ((clip.bottom() / page_h).ceil() as isize + buf_pages as isize).min(total_pages as isize) as usize;
-                for (idx, page) in layout.pages.iter().enumerate() {
-                    if idx < start_idx || idx >= end_idx {
-                        ui.add_space(page_h);
+                // Visible range in content coordinates, padded by a screen
+                // either way. The scroll view is one tall page, which
+                // `draw_page_at` paints only within the clip.
+                let origin = ui.cursor().top();
+                let (visible_top, visible_bottom) = (clip.top() - origin - clip.height(), clip.bottom() - origin + clip.height());
+                let mut page_top = 0.0;
+                for page in &layout.pages {
+                    let extent = page.extent(&config);
+                    let (top, bottom) = (page_top, page_top + extent);
+                    page_top = bottom + gap;
+                    if bottom < visible_top || top > visible_bottom {
+                        ui.add_space(extent + gap);
                         continue;
                     }
-                    let (rect, resp) = ui.allocate_exact_size(
-                        egui::vec2(config.page_width, config.page_height),
-                        egui::Sense::click(),
-                    );
+                    let (rect, resp) = ui.allocate_exact_size(egui::vec2(config.page_width, extent), egui::Sense::click());
+                    if let Some(hover) = resp.hover_pos() {
+                        if let Some((_, url)) = self.link_at(page, &config, rect, hover) {
+                            ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                            egui::show_tooltip_at_pointer(ui.ctx(), egui::Id::new("link_tooltip"), |ui| {
+                                ui.label(url.as_ref());
+                            });
+                        }
+                    }
                     if resp.clicked() {
                         if resp.ctx.input(|i| i.modifiers.alt) {
                             if let Some(pos) = resp.interact_pointer_pos() {
                                 if let Some(hit) = self.hit_test_page(page, &config, rect, pos) {
-                                    if let Some(idx) = self.extra_cursors.iter().position(|p| *p == hit) {
-                                        self.extra_cursors.remove(idx);
+                                    if let Some(idx) = self.view().extra_cursors.iter().position(|p| *p == hit) {
+                                        self.view_mut().extra_cursors.remove(idx);
                                     } else {
-                                        self.extra_cursors.push(hit);
+                                        self.view_mut().extra_cursors.push(hit);
                                     }
                                 }
                             }
                         }
                         if let Some(pos) = resp.interact_pointer_pos() {
                             if let Some(hit) = self.hit_test_page(page, &config, rect, pos) {
-                                self.editor.selection = wa_core::Selection::collapsed(hit);
-                                self.table_focus = self.find_table_cell(page, &config, rect, pos);
+                                self.view_mut().editor.selection = wa_core::Selection::collapsed(hit);
+                                self.view_mut().table_focus = self.find_table_cell(page, &config, rect, pos);
                                 if !resp.ctx.input(|i| i.modifiers.alt) {
-                                    self.extra_cursors.clear();
+                                    self.view_mut().extra_cursors.clear();
+                                }
+                            }
+                            if let Some((block_id, url)) = self.link_at(page, &config, rect, pos) {
+                                if let Some(target) = wa_core::toc_target(&url) {
+                                    self.view_mut().editor.selection = wa_core::Selection::collapsed(wa_core::Position { block_id: target, offset: 0 });
+                                    self.view_mut().scroll_to_block = Some(target);
+                                } else if resp.ctx.input(|i| i.modifiers.command) {
+                                    self.open_link(&url);
+                                } else {
+                                    self.view_mut().link_popover = Some(links::LinkPopover {
+                                        block_id,
+                                        url,
+                                        pos,
+                                        editing: None,
+                                        opened_frame: resp.ctx.frame_nr(),
+                                    });
                                 }
                             }
                         }
                     }
+                    if resp.secondary_clicked() {
+                        if let Some(pos) = resp.interact_pointer_pos() {
+                            self.view_mut().table_menu = self.find_table_cell(page, &config, rect, pos);
+                            if self.view().table_menu.is_some() {
+                                self.view_mut().table_focus = self.view_mut().table_menu;
+                            }
+                        }
+                    }
+                    if let Some(cell) = self.view().table_menu {
+                        resp.context_menu(|ui| self.table_context_menu(ui, cell));
+                    }
                     if resp.dragged() && !is_scrolling {
                         if resp.ctx.input(|i| i.modifiers.alt) {
                             if let Some(pos) = resp.interact_pointer_pos() {
-                                if let Some((start, _)) = self.rect_select {
-                                    self.rect_select = Some((start, pos));
+                                if let Some((start, _)) = self.view().rect_select {
+                                    self.view_mut().rect_select = Some((start, pos));
                                 } else {
-                                    self.rect_select = Some((pos, pos));
+                                    self.view_mut().rect_select = Some((pos, pos));
                                 }
                             }
                             continue;
                         }
                         if let Some(pos) = resp.interact_pointer_pos() {
                             if let Some(hit) = self.hit_test_page(page, &config, rect, pos) {
-                                self.editor.selection.focus = hit;
+                                self.view_mut().editor.selection.focus = hit;
                             }
                         }
                     }
-                    if let Some((a, b)) = self.rect_select {
+                    if let Some((a, b)) = self.view().rect_select {
                         if !resp.ctx.input(|i| i.pointer.primary_down()) {
                             if let Some(start) = self.hit_test_page(page, &config, rect, a) {
                                 if let Some(end) = self.hit_test_page(page, &config, rect, b) {
-                                    self.editor.selection = wa_core::Selection { anchor: start, focus: end };
+                                    self.view_mut().editor.selection = wa_core::Selection { anchor: start, focus: end };
                                 }
                             }
-                            self.rect_select = None;
+                            self.view_mut().rect_select = None;
                         }
                     }
                     self.draw_page_at(ui, page, &config, rect, paged_view);
                     ui.add_space(gap);
                 }
+                if self.view().block_drag.is_some() {
+                    if let Some(pointer) = ui.ctx().pointer_latest_pos() {
+                        let delta = dragdrop::autoscroll_delta(pointer.y, clip.top(), clip.bottom());
+                        if delta != 0.0 {
+                            ui.scroll_with_delta(egui::vec2(0.0, delta));
+                            ui.ctx().request_repaint();
+                        }
+                    }
+                }
             });
+            let offset = output.state.offset.y;
+            let view = self.view_mut();
+            if offset != view.view_state.scroll_offset {
+                view.view_state = ViewState::capture(&view.editor.doc, &layout, &config, gap, offset);
+            }
         });
+
+        self.update_block_drag(ctx);
+        // Text fields (find panel, link editor) report their own IME rect.
+        if let Some(rect) = self.ime_cursor_rect {
+            if ctx.memory(|m| m.focused().is_none()) {
+                ctx.output_mut(|o| o.ime = Some(egui::output::IMEOutput { rect, cursor_rect: rect }));
+            }
+        }
+        self.draw_link_popover(ctx);
+        self.draw_toast(ctx);
+        self.draw_layout_stats(ctx);
+        self.handle_tab_close_prompt(ctx);
+        self.handle_print_dialog(ctx);
+        self.draw_settings(ctx);
+        self.autosave();
+        self.sync_settings(ctx);
+        self.handle_recovery_prompt(ctx);
+        self.report_journal_errors();
+        self.handle_close_request(ctx);
+        self.sync_window_title(ctx);
     }
 }
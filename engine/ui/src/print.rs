@@ -0,0 +1,109 @@
+use std::path::Path;
+use std::process::Command;
+use wa_core::{Document, PageRange, Paper, PdfOptions};
+
+pub const MM_PER_PX: f32 = 25.4 / 96.0;
+
+/// State of the dialog shown before printing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintDialog {
+    pub paper: Paper,
+    pub margin_mm: f32,
+    /// Pages as typed, e.g. `2-5`; blank prints them all.
+    pub pages: String,
+    /// Why the last attempt did not start, shown in the dialog.
+    pub error: Option<String>,
+}
+
+impl PrintDialog {
+    /// Starts from the editor's page setup, given in unzoomed pixels.
+    pub fn for_page(page_width: f32, margin: f32) -> Self {
+        let letter_width = Paper::Letter.size_mm().0;
+        let paper = if (page_width * MM_PER_PX - letter_width).abs() < 1.0 { Paper::Letter } else { Paper::A4 };
+        Self { paper, margin_mm: (margin * MM_PER_PX).round(), pages: String::new(), error: None }
+    }
+
+    pub fn options(&self) -> Result<PdfOptions, String> {
+        let pages = self.pages.trim();
+        let page_range = if pages.is_empty() {
+            None
+        } else {
+            Some(PageRange::parse(pages).ok_or_else(|| format!("页码范围无效：{}", pages))?)
+        };
+        Ok(PdfOptions { paper: self.paper, margin_mm: self.margin_mm, page_range, ..PdfOptions::default() })
+    }
+}
+
+/// Writes `doc` to a temporary PDF and hands it to the system to print.
+pub fn print(doc: &Document, options: &PdfOptions) -> Result<(), String> {
+    let path = std::env::temp_dir().join(format!("wa_print_{}.pdf", uuid::Uuid::new_v4()));
+    write_pdf(doc, &path, options)?;
+    send_to_printer(&path)
+}
+
+#[cfg(feature = "docx")]
+fn write_pdf(doc: &Document, path: &Path, options: &PdfOptions) -> Result<(), String> {
+    wa_core::export_pdf_with(doc, path, options).map_err(|e| format!("生成打印文件失败：{}", e))
+}
+
+#[cfg(not(feature = "docx"))]
+fn write_pdf(_doc: &Document, _path: &Path, _options: &PdfOptions) -> Result<(), String> {
+    Err("未启用 PDF 导出，无法打印".to_string())
+}
+
+/// The ways to hand `path` to the system's printing, tried in order.
+pub fn print_commands(path: &Path) -> Vec<(&'static str, Vec<String>)> {
+    let file = path.display().to_string();
+    if cfg!(target_os = "windows") {
+        // ShellExecute with the "print" verb.
+        let script = format!("Start-Process -FilePath '{}' -Verb Print", file.replace('\'', "''"));
+        vec![("powershell", vec!["-NoProfile".to_string(), "-Command".to_string(), script])]
+    } else if cfg!(target_os = "macos") {
+        vec![("open", vec![file])]
+    } else {
+        vec![("lp", vec![file.clone()]), ("xdg-open", vec![file])]
+    }
+}
+
+/// Runs `print_commands` until one succeeds; the error names every one
+/// that failed.
+pub fn send_to_printer(path: &Path) -> Result<(), String> {
+    let mut failures = Vec::new();
+    for (program, args) in print_commands(path) {
+        match Command::new(program).args(&args).status() {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => failures.push(format!("{} 失败（{}）", program, status)),
+            Err(err) => failures.push(format!("无法运行 {}：{}", program, err)),
+        }
+    }
+    Err(format!("无法打印 {}：{}", path.display(), failures.join("；")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dialog_starts_from_the_page_setup() {
+        let dialog = PrintDialog::for_page(794.0, 64.0);
+        assert_eq!((dialog.paper, dialog.margin_mm), (Paper::A4, 17.0));
+        assert_eq!(PrintDialog::for_page(816.0, 96.0).paper, Paper::Letter);
+        assert_eq!(dialog.options().unwrap().page_range, None);
+    }
+
+    #[test]
+    fn page_field_must_be_a_range() {
+        let mut dialog = PrintDialog::for_page(794.0, 64.0);
+        dialog.pages = " 2-4 ".to_string();
+        assert_eq!(dialog.options().unwrap().page_range, Some(PageRange { first: 2, last: Some(4) }));
+        dialog.pages = "4-2".to_string();
+        assert!(dialog.options().unwrap_err().contains("4-2"));
+    }
+
+    #[test]
+    fn every_platform_has_a_print_command() {
+        let commands = print_commands(Path::new("/tmp/a b.pdf"));
+        assert!(!commands.is_empty());
+        assert!(commands.iter().all(|(_, args)| args.iter().any(|a| a.contains("a b.pdf"))));
+    }
+}
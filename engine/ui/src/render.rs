@@ -0,0 +1,443 @@
+use eframe::egui;
+use egui::text::{LayoutJob, TextFormat};
+use wa_engine::{LayoutBlock, LayoutConfig, LayoutKind, Line, TextRun};
+
+pub const TEXT_COLOR: egui::Color32 = egui::Color32::from_rgb(40, 30, 20);
+pub const LINK_COLOR: egui::Color32 = egui::Color32::from_rgb(40, 90, 200);
+const CODE_BACKGROUND: egui::Color32 = egui::Color32::from_rgb(238, 234, 226);
+const FAUX_BOLD_OFFSET: f32 = 0.6;
+pub const CODE_BLOCK_LIGHT: egui::Color32 = egui::Color32::from_rgb(245, 242, 235);
+pub const CODE_BLOCK_DARK: egui::Color32 = egui::Color32::from_rgb(43, 48, 59);
+pub const CODE_TEXT_DARK: egui::Color32 = egui::Color32::from_rgb(192, 197, 206);
+pub const TABLE_HEADER_BACKGROUND: egui::Color32 = egui::Color32::from_rgb(236, 230, 218);
+pub const TABLE_BORDER_COLOR: egui::Color32 = egui::Color32::from_rgb(214, 206, 192);
+pub const TABLE_CELL_PADDING: f32 = 4.0;
+pub const MATCH_HIGHLIGHT: egui::Color32 = egui::Color32::from_rgba_premultiplied(120, 110, 20, 90);
+pub const CURRENT_MATCH_HIGHLIGHT: egui::Color32 = egui::Color32::from_rgba_premultiplied(200, 120, 0, 150);
+
+/// A background highlight over a char range of a block, painted under the
+/// text (search hits for now).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decoration {
+    pub block_id: uuid::Uuid,
+    pub start: usize,
+    pub end: usize,
+    pub color: egui::Color32,
+}
+
+pub fn block_font(kind: &LayoutKind, config: &LayoutConfig) -> egui::FontId {
+    match kind {
+        LayoutKind::Heading(level) => {
+            let size = match level {
+                1 => 20.0,
+                2 => 18.0,
+                _ => 16.0,
+            };
+            egui::FontId::proportional(size * config.zoom)
+        }
+        LayoutKind::Code => egui::FontId::monospace(config.metrics.font_size),
+        _ => egui::FontId::proportional(config.metrics.font_size),
+    }
+}
+
+/// One job per code line with a section per highlight run, so the monospace
+/// font keeps its own advances instead of the layout's proportional widths.
+pub fn code_line_job(line: &Line, font_id: &egui::FontId, color: egui::Color32) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    if line.runs.is_empty() {
+        job.append(&line.text, 0.0, TextFormat::simple(font_id.clone(), color));
+        return job;
+    }
+    for run in &line.runs {
+        let Some(text) = line.text.get(run.range.clone()) else {
+            continue;
+        };
+        let format = TextFormat {
+            font_id: font_id.clone(),
+            color: run
+                .color
+                .map(|[r, g, b]| egui::Color32::from_rgb(r, g, b))
+                .unwrap_or(color),
+            italics: run.style.italic,
+            underline: if run.style.underline { egui::Stroke::new(1.0, color) } else { egui::Stroke::NONE },
+            ..Default::default()
+        };
+        job.append(text, 0.0, format);
+    }
+    job
+}
+
+/// Rects covering chars `start..end` of a paragraph, heading or code block,
+/// relative to the block's top-left. `measure` returns the drawn width of a
+/// line prefix.
+pub fn decoration_rects(
+    block: &LayoutBlock,
+    start: usize,
+    end: usize,
+    line_height: f32,
+    measure: impl Fn(&str) -> f32,
+) -> Vec<egui::Rect> {
+    let mut out = Vec::new();
+    for (idx, line) in block.lines.iter().enumerate() {
+        let len = line.text.chars().count();
+        let s = start.max(line.start);
+        let e = end.min(line.start + len);
+        if s >= e {
+            continue;
+        }
+        let x0 = measure(prefix(&line.text, s - line.start));
+        let x1 = measure(prefix(&line.text, e - line.start));
+        let y = idx as f32 * line_height;
+        out.push(egui::Rect::from_min_max(egui::pos2(x0, y), egui::pos2(x1, y + line_height)));
+    }
+    out
+}
+
+fn prefix(text: &str, chars: usize) -> &str {
+    &text[..wa_core::char_to_byte_idx(text, chars)]
+}
+
+/// Left and right edges of column `col`, as fractions of the table width.
+pub fn column_span(fractions: &[f32], col: usize) -> (f32, f32) {
+    let left: f32 = fractions.iter().take(col).sum();
+    (left, left + fractions.get(col).copied().unwrap_or(0.0))
+}
+
+/// The column under `x`, given as a fraction of the table width.
+pub fn column_at(fractions: &[f32], x: f32) -> usize {
+    let mut right = 0.0;
+    for (col, w) in fractions.iter().enumerate() {
+        right += w;
+        if x < right {
+            return col;
+        }
+    }
+    fractions.len().saturating_sub(1)
+}
+
+/// Part of a line as a line of its own, with runs clipped to `range` (bytes)
+/// and re-measured.
+pub fn slice_line(line: &Line, range: std::ops::Range<usize>, measure: impl Fn(&str) -> f32) -> Line {
+    let text = line.text.get(range.clone()).unwrap_or("").to_string();
+    let runs = line
+        .runs
+        .iter()
+        .filter_map(|run| {
+            let start = run.range.start.max(range.start);
+            let end = run.range.end.min(range.end);
+            if start >= end {
+                return None;
+            }
+            let local = (start - range.start)..(end - range.start);
+            Some(TextRun { width: measure(text.get(local.clone())?), range: local, ..run.clone() })
+        })
+        .collect();
+    Line { width: measure(&text), text, start: 0, runs, number: None }
+}
+
+/// Splits a table row line back into cells. The layout joins cells with
+/// " | "; `cell_lens` are the byte lengths of the cell texts.
+pub fn split_table_row(line: &Line, cell_lens: &[usize], measure: impl Fn(&str) -> f32) -> Vec<Line> {
+    let mut out = Vec::with_capacity(cell_lens.len());
+    let mut pos = 0usize;
+    for (idx, len) in cell_lens.iter().enumerate() {
+        if idx > 0 {
+            pos += " | ".len();
+        }
+        let end = (pos + len).min(line.text.len());
+        out.push(slice_line(line, pos.min(end)..end, &measure));
+        pos += len;
+    }
+    out
+}
+
+/// A copy of `line` with the IME composition string spliced in at char
+/// `at` (relative to the line), underlined and in the style of the text
+/// before it. Runs after the caret shift right; the document is untouched.
+/// Returns the line and the byte range of the composition within it.
+pub fn inject_composition(
+    line: &Line,
+    at: usize,
+    composition: &str,
+    measure: impl Fn(&str) -> f32,
+) -> (Line, std::ops::Range<usize>) {
+    let byte_at = line.text.char_indices().nth(at).map(|(i, _)| i).unwrap_or(line.text.len());
+    let mut text = String::with_capacity(line.text.len() + composition.len());
+    text.push_str(&line.text[..byte_at]);
+    text.push_str(composition);
+    text.push_str(&line.text[byte_at..]);
+    let inserted = byte_at..byte_at + composition.len();
+
+    let plain;
+    let runs = if line.runs.is_empty() {
+        plain = [TextRun {
+            range: 0..line.text.len(),
+            style: wa_core::Style::default(),
+            link: None,
+            code: false,
+            color: None,
+            width: line.width,
+        }];
+        &plain[..]
+    } else {
+        &line.runs[..]
+    };
+    let shift = composition.len();
+    let mut out = Vec::with_capacity(runs.len() + 2);
+    let mut ime_run = None;
+    for run in runs {
+        if run.range.end <= byte_at {
+            out.push(run.clone());
+        } else if run.range.start >= byte_at {
+            out.push(TextRun { range: run.range.start + shift..run.range.end + shift, ..run.clone() });
+        } else {
+            let head = run.range.start..byte_at;
+            let tail = inserted.end..run.range.end + shift;
+            out.push(TextRun { width: measure(&text[head.clone()]), range: head, ..run.clone() });
+            ime_run = Some(run.clone());
+            out.push(TextRun { width: measure(&text[tail.clone()]), range: tail, ..run.clone() });
+        }
+        if run.range.end == byte_at && ime_run.is_none() {
+            ime_run = Some(run.clone());
+        }
+    }
+    let mut ime_run = ime_run.or_else(|| runs.first().cloned()).unwrap_or(TextRun {
+        range: 0..0,
+        style: wa_core::Style::default(),
+        link: None,
+        code: false,
+        color: None,
+        width: 0.0,
+    });
+    ime_run.range = inserted.clone();
+    ime_run.width = measure(composition);
+    ime_run.style.underline = true;
+    out.retain(|r| !r.range.is_empty());
+    let idx = out.iter().position(|r| r.range.start >= inserted.end).unwrap_or(out.len());
+    out.insert(idx, ime_run);
+    let width = measure(&text);
+    (Line { text, width, start: line.start, runs: out, number: line.number }, inserted)
+}
+
+/// The run under `x`, measured from the line's left edge.
+pub fn run_at_x(line: &Line, x: f32, scale: f32) -> Option<&TextRun> {
+    let mut left = 0.0;
+    for run in &line.runs {
+        let right = left + run.width * scale;
+        if x >= left && x < right {
+            return Some(run);
+        }
+        left = right;
+    }
+    None
+}
+
+pub fn run_layout_job(text: &str, run: &TextRun, font_id: &egui::FontId, color: egui::Color32) -> LayoutJob {
+    let color = if run.link.is_some() { LINK_COLOR } else { color };
+    let decoration = egui::Stroke::new(1.0, color);
+    let format = TextFormat {
+        font_id: if run.code {
+            egui::FontId::monospace(font_id.size)
+        } else {
+            font_id.clone()
+        },
+        color,
+        background: if run.code { CODE_BACKGROUND } else { egui::Color32::TRANSPARENT },
+        italics: run.style.italic,
+        underline: if run.style.underline || run.link.is_some() {
+            decoration
+        } else {
+            egui::Stroke::NONE
+        },
+        strikethrough: if run.style.strikethrough { decoration } else { egui::Stroke::NONE },
+        ..Default::default()
+    };
+    LayoutJob::single_section(text.to_string(), format)
+}
+
+/// Paints one layout line. Runs are placed at the widths the layout engine
+/// measured, multiplied by `scale` when the font is drawn larger than it was
+/// laid out (headings).
+pub fn paint_line(
+    painter: &egui::Painter,
+    pos: egui::Pos2,
+    line: &Line,
+    font_id: &egui::FontId,
+    color: egui::Color32,
+    scale: f32,
+) {
+    if line.runs.is_empty() {
+        painter.text(pos, egui::Align2::LEFT_TOP, &line.text, font_id.clone(), color);
+        return;
+    }
+    let mut x = pos.x;
+    for run in &line.runs {
+        let Some(text) = line.text.get(run.range.clone()) else {
+            continue;
+        };
+        let galley = painter.layout_job(run_layout_job(text, run, font_id, color));
+        let at = egui::pos2(x, pos.y);
+        if run.style.bold {
+            painter.galley(at + egui::vec2(FAUX_BOLD_OFFSET, 0.0), galley.clone(), color);
+        }
+        painter.galley(at, galley, color);
+        x += run.width * scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wa_core::Style;
+
+    fn run(style: Style, link: Option<&str>, code: bool) -> TextRun {
+        TextRun {
+            range: 0..4,
+            style,
+            link: link.map(std::sync::Arc::from),
+            code,
+            color: None,
+            width: 10.0,
+        }
+    }
+
+    #[test]
+    fn styled_run_maps_to_text_format() {
+        let font = egui::FontId::proportional(14.0);
+        let style = Style { italic: true, underline: true, strikethrough: true, ..Style::default() };
+        let job = run_layout_job("text", &run(style, None, false), &font, TEXT_COLOR);
+        assert_eq!(job.text, "text");
+        assert_eq!(job.sections.len(), 1);
+        let format = &job.sections[0].format;
+        assert!(format.italics);
+        assert!(format.underline.width > 0.0);
+        assert!(format.strikethrough.width > 0.0);
+        assert_eq!(format.color, TEXT_COLOR);
+        assert_eq!(format.font_id, font);
+    }
+
+    #[test]
+    fn plain_run_has_no_decoration() {
+        let font = egui::FontId::proportional(14.0);
+        let job = run_layout_job("text", &run(Style::default(), None, false), &font, TEXT_COLOR);
+        let format = &job.sections[0].format;
+        assert!(!format.italics);
+        assert_eq!(format.underline, egui::Stroke::NONE);
+        assert_eq!(format.strikethrough, egui::Stroke::NONE);
+    }
+
+    #[test]
+    fn code_line_uses_highlight_colors() {
+        let font = egui::FontId::monospace(14.0);
+        let mut keyword = run(Style::default(), None, false);
+        keyword.range = 0..2;
+        keyword.color = Some([200, 0, 100]);
+        let mut rest = run(Style::default(), None, false);
+        rest.range = 2..8;
+        let line = Line {
+            text: "fn main".to_string() + "(",
+            width: 0.0,
+            start: 0,
+            runs: vec![keyword, rest],
+            number: None,
+        };
+        let job = code_line_job(&line, &font, TEXT_COLOR);
+        assert_eq!(job.text, "fn main(");
+        assert_eq!(job.sections.len(), 2);
+        assert_eq!(job.sections[0].format.color, egui::Color32::from_rgb(200, 0, 100));
+        assert_eq!(job.sections[1].format.color, TEXT_COLOR);
+        assert_eq!(job.sections[1].format.font_id, font);
+    }
+
+    #[test]
+    fn decoration_spans_wrapped_lines() {
+        let line = |text: &str, start: usize| Line { text: text.to_string(), width: 0.0, start, runs: Vec::new(), number: None };
+        let block = LayoutBlock {
+            block_id: uuid::Uuid::new_v4(),
+            kind: LayoutKind::Paragraph,
+            lines: vec![line("hello ", 0), line("world", 6)],
+            height: 40.0,
+            meta: None,
+        };
+        let measure = |s: &str| s.chars().count() as f32 * 10.0;
+        let rects = decoration_rects(&block, 4, 8, 20.0, measure);
+        assert_eq!(rects.len(), 2);
+        assert_eq!(rects[0], egui::Rect::from_min_max(egui::pos2(40.0, 0.0), egui::pos2(60.0, 20.0)));
+        assert_eq!(rects[1], egui::Rect::from_min_max(egui::pos2(0.0, 20.0), egui::pos2(20.0, 40.0)));
+        assert!(decoration_rects(&block, 11, 12, 20.0, measure).is_empty());
+    }
+
+    #[test]
+    fn columns_from_fractions() {
+        let fractions = [0.5, 0.25, 0.25];
+        assert_eq!(column_span(&fractions, 1), (0.5, 0.75));
+        assert_eq!(column_at(&fractions, 0.1), 0);
+        assert_eq!(column_at(&fractions, 0.6), 1);
+        assert_eq!(column_at(&fractions, 1.2), 2);
+    }
+
+    #[test]
+    fn table_row_splits_into_cells() {
+        let mut bold = run(Style { bold: true, ..Style::default() }, None, false);
+        bold.range = 0..5;
+        let line = Line { text: "ab cd | efg".to_string(), width: 0.0, start: 0, runs: vec![bold], number: None };
+        let measure = |s: &str| s.len() as f32;
+        let cells = split_table_row(&line, &[5, 3], measure);
+        assert_eq!(cells.len(), 2);
+        assert_eq!(cells[0].text, "ab cd");
+        assert_eq!(cells[0].runs.len(), 1);
+        assert_eq!(cells[0].runs[0].width, 5.0);
+        assert_eq!(cells[1].text, "efg");
+        assert!(cells[1].runs.is_empty());
+    }
+
+    #[test]
+    fn composition_splits_run_at_caret() {
+        let measure = |s: &str| s.chars().count() as f32;
+        let mut bold = run(Style { bold: true, ..Style::default() }, None, false);
+        bold.range = 0..6;
+        let mut plain = run(Style::default(), None, false);
+        plain.range = 6..9;
+        let line = Line { text: "abcdefxyz".to_string(), width: 9.0, start: 3, runs: vec![bold, plain], number: None };
+
+        let (out, range) = inject_composition(&line, 2, "你好", measure);
+        assert_eq!(out.text, "ab你好cdefxyz");
+        assert_eq!(range, 2..8);
+        assert_eq!(out.start, 3);
+        assert_eq!(out.width, 11.0);
+        let ranges: Vec<_> = out.runs.iter().map(|r| r.range.clone()).collect();
+        assert_eq!(ranges, vec![0..2, 2..8, 8..12, 12..15]);
+        assert!(out.runs[1].style.bold && out.runs[1].style.underline);
+        assert_eq!(out.runs[1].width, 2.0);
+        assert!(!out.runs[3].style.underline);
+    }
+
+    #[test]
+    fn composition_on_plain_and_empty_lines() {
+        let measure = |s: &str| s.chars().count() as f32;
+        let line = Line { text: "abc".to_string(), width: 3.0, start: 0, runs: Vec::new(), number: None };
+        let (out, range) = inject_composition(&line, 3, "かな", measure);
+        assert_eq!(out.text, "abcかな");
+        assert_eq!(range, 3..9);
+        assert_eq!(out.runs.len(), 2);
+        assert!(out.runs[1].style.underline);
+
+        let empty = Line { text: String::new(), width: 0.0, start: 0, runs: Vec::new(), number: None };
+        let (out, range) = inject_composition(&empty, 0, "拼", measure);
+        assert_eq!(out.text, "拼");
+        assert_eq!(range, 0..3);
+        assert_eq!(out.runs.iter().map(|r| r.range.clone()).collect::<Vec<_>>(), vec![0..3]);
+    }
+
+    #[test]
+    fn link_and_code_runs() {
+        let font = egui::FontId::proportional(14.0);
+        let link = run_layout_job("text", &run(Style::default(), Some("https://example.com"), false), &font, TEXT_COLOR);
+        assert_eq!(link.sections[0].format.color, LINK_COLOR);
+        assert!(link.sections[0].format.underline.width > 0.0);
+
+        let code = run_layout_job("text", &run(Style::default(), None, true), &font, TEXT_COLOR);
+        assert_eq!(code.sections[0].format.font_id.family, egui::FontFamily::Monospace);
+        assert_eq!(code.sections[0].format.background, CODE_BACKGROUND);
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use wa_core::Paper;
+use wa_engine::EngineOptions;
+
+/// Files kept in the recent files menu.
+pub const MAX_RECENT: usize = 10;
+
+/// What the app remembers between sessions, kept as JSON in the platform
+/// config directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub paged: bool,
+    pub zoom: f32,
+    /// Font file for layout; WA_FONT_PATH and the system fonts when unset.
+    pub font: Option<PathBuf>,
+    pub paper: Paper,
+    pub low_spec: bool,
+    pub parallel: bool,
+    /// Most recently opened or saved first.
+    pub recent_files: Vec<PathBuf>,
+    /// Seconds between saves of modified tabs that have a file; 0 is off.
+    pub autosave_secs: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            paged: true,
+            zoom: 1.0,
+            font: None,
+            paper: Paper::A4,
+            low_spec: false,
+            parallel: false,
+            recent_files: Vec::new(),
+            autosave_secs: 0,
+        }
+    }
+}
+
+impl Settings {
+    /// `settings.json` in the platform config directory, if there is one.
+    pub fn default_path() -> Option<PathBuf> {
+        directories::ProjectDirs::from("", "", "writing-agent").map(|dirs| dirs.config_dir().join("settings.json"))
+    }
+
+    /// The settings saved at `path`. A missing file gives the defaults; an
+    /// unreadable or corrupt one gives them too, along with a warning.
+    pub fn load(path: &Path) -> (Settings, Option<String>) {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return (Settings::default(), None),
+            Err(err) => return (Settings::default(), Some(format!("无法读取设置，已使用默认设置：{}", err))),
+        };
+        match serde_json::from_str(&text) {
+            Ok(settings) => (settings, None),
+            Err(err) => (Settings::default(), Some(format!("设置文件已损坏，已使用默认设置：{}", err))),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("保存设置失败：{}", e))?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| format!("保存设置失败：{}", e))?;
+        std::fs::write(path, json).map_err(|e| format!("保存设置失败：{}", e))
+    }
+
+    /// Engine options with these settings on top of `env`'s. Flags set in
+    /// the environment stay on.
+    pub fn engine_options(&self, env: &EngineOptions) -> EngineOptions {
+        EngineOptions {
+            low_spec: self.low_spec || env.low_spec,
+            parallel: self.parallel || env.parallel,
+            font: self.font.clone().or_else(|| env.font.clone()),
+            diagnostics: env.diagnostics,
+        }
+    }
+
+    /// Moves `path` to the top of the recent files.
+    pub fn add_recent(&mut self, path: &Path) {
+        self.recent_files.retain(|p| p != path);
+        self.recent_files.insert(0, path.to_path_buf());
+        self.recent_files.truncate(MAX_RECENT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        std::env::temp_dir().join(format!("wa_settings_{}", uuid::Uuid::new_v4())).join("settings.json")
+    }
+
+    #[test]
+    fn settings_round_trip_and_survive_a_corrupt_file() {
+        let path = scratch();
+        assert_eq!(Settings::load(&path), (Settings::default(), None));
+        let settings = Settings { paged: false, zoom: 1.5, paper: Paper::Letter, autosave_secs: 60, ..Settings::default() };
+        settings.save(&path).unwrap();
+        assert_eq!(Settings::load(&path), (settings, None));
+
+        std::fs::write(&path, "{\"zoom\": \"big\"").unwrap();
+        let (loaded, warning) = Settings::load(&path);
+        assert_eq!(loaded, Settings::default());
+        assert!(warning.unwrap().contains("已损坏"));
+        // Fields left out keep their defaults.
+        std::fs::write(&path, "{\"low_spec\": true}").unwrap();
+        assert_eq!(Settings::load(&path).0, Settings { low_spec: true, ..Settings::default() });
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn recent_files_are_unique_and_capped() {
+        let mut settings = Settings::default();
+        for i in 0..=MAX_RECENT {
+            settings.add_recent(Path::new(&format!("/docs/{i}.json")));
+        }
+        settings.add_recent(Path::new("/docs/3.json"));
+        assert_eq!(settings.recent_files.len(), MAX_RECENT);
+        assert_eq!(settings.recent_files[0], Path::new("/docs/3.json"));
+        assert_eq!(settings.recent_files.iter().filter(|p| p.ends_with("3.json")).count(), 1);
+    }
+}
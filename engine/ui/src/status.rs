@@ -0,0 +1,136 @@
+use wa_core::{selection_stats, Block, Document, DocumentStats, PlainTextIndex, Selection, TextStats};
+use wa_engine::LayoutStats;
+
+/// Status bar numbers, recomputed only when the document version or the
+/// selection changes.
+#[derive(Debug, Default)]
+pub struct StatusCache {
+    document: Option<(u64, DocumentStats)>,
+    selection: Option<(u64, Selection, Option<TextStats>)>,
+}
+
+impl StatusCache {
+    pub fn document(&mut self, doc: &Document, index: &mut PlainTextIndex) -> DocumentStats {
+        match self.document {
+            Some((version, stats)) if version == doc.version => stats,
+            _ => {
+                let stats = index.document_stats(doc);
+                self.document = Some((doc.version, stats));
+                stats
+            }
+        }
+    }
+
+    pub fn selection(&mut self, doc: &Document, selection: &Selection) -> Option<TextStats> {
+        match self.selection {
+            Some((version, sel, stats)) if version == doc.version && sel == *selection => stats,
+            _ => {
+                let stats = selection_stats(doc, selection);
+                self.selection = Some((doc.version, *selection, stats));
+                stats
+            }
+        }
+    }
+}
+
+pub fn block_label(block: &Block) -> String {
+    match block {
+        Block::Heading { level, .. } => format!("标题 {}", level),
+        Block::Paragraph { .. } => "段落".to_string(),
+        Block::List { toc: true, .. } => "目录".to_string(),
+        Block::List { ordered: true, .. } => "有序列表".to_string(),
+        Block::List { .. } => "列表".to_string(),
+        Block::Quote { .. } => "引用".to_string(),
+        Block::Code { .. } => "代码块".to_string(),
+        Block::Table { .. } => "表格".to_string(),
+        Block::Figure { .. } => "图片".to_string(),
+        Block::PageBreak { .. } => "分页符".to_string(),
+        Block::Custom { kind, .. } => format!("扩展块 {}", kind),
+    }
+}
+
+/// Lines of the layout debug overlay.
+pub fn layout_stats_lines(stats: &LayoutStats) -> Vec<String> {
+    let rate = |rate: Option<f64>| rate.map(|r| format!("{:.0}%", r * 100.0)).unwrap_or_else(|| "-".to_string());
+    let ms = |d: std::time::Duration| format!("{:.2}ms", d.as_secs_f64() * 1000.0);
+    let mut lines = vec![
+        format!("blocks  {} laid out / {} reused", stats.blocks_laid_out, stats.blocks_reused),
+        format!("pages   {}", stats.pages),
+        format!("breaks  {} ({} hits)", rate(stats.break_hit_rate()), stats.break_cache_hits),
+        format!("glyphs  {} ({} hits)", rate(stats.glyph_hit_rate()), stats.glyph_hits),
+        format!("time    wrap {} measure {} paginate {}", ms(stats.wrap), ms(stats.measure), ms(stats.paginate)),
+    ];
+    if stats.parallel_workers > 0 {
+        lines.push(format!("workers {}", stats.parallel_workers));
+    }
+    lines
+}
+
+pub fn counts_label(stats: TextStats, selected: bool) -> String {
+    if selected {
+        format!("已选 {} 字 · {} 字符", stats.words, stats.chars)
+    } else {
+        format!("{} 字 · {} 字符", stats.words, stats.chars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use wa_core::{Inline, Position};
+
+    #[test]
+    fn cache_follows_version_and_selection() {
+        let mut doc = Document::new();
+        let id = uuid::Uuid::new_v4();
+        doc.blocks.push(Block::Paragraph {
+            id,
+            content: vec![Inline::Text { value: Arc::from("hello 世界") }],
+            dirty: false,
+            locked: false,
+        });
+        let mut cache = StatusCache::default();
+        let mut index = PlainTextIndex::new();
+        assert_eq!(cache.document(&doc, &mut index).text.words, 3);
+
+        if let Block::Paragraph { content, .. } = &mut doc.blocks[0] {
+            content.push(Inline::Text { value: Arc::from(" again") });
+        }
+        assert_eq!(cache.document(&doc, &mut index).text.words, 3);
+        doc.touch();
+        assert_eq!(cache.document(&doc, &mut index).text.words, 4);
+
+        let caret = Selection::collapsed(Position { block_id: id, offset: 0 });
+        assert_eq!(cache.selection(&doc, &caret), None);
+        let word = Selection { anchor: caret.anchor, focus: Position { block_id: id, offset: 5 } };
+        assert_eq!(cache.selection(&doc, &word).map(|s| s.chars), Some(5));
+    }
+
+    #[test]
+    fn labels() {
+        let heading = Block::Heading { id: uuid::Uuid::new_v4(), level: 2, content: Vec::new(), dirty: false, locked: false };
+        assert_eq!(block_label(&heading), "标题 2");
+        let stats = TextStats { chars: 10, chars_no_spaces: 8, words: 3 };
+        assert_eq!(counts_label(stats, false), "3 字 · 10 字符");
+        assert_eq!(counts_label(stats, true), "已选 3 字 · 10 字符");
+    }
+
+    #[test]
+    fn layout_stats_overlay_lines() {
+        let stats = LayoutStats {
+            blocks_laid_out: 3,
+            blocks_reused: 7,
+            break_cache_hits: 3,
+            break_cache_misses: 1,
+            wrap: std::time::Duration::from_micros(1500),
+            ..LayoutStats::default()
+        };
+        let lines = layout_stats_lines(&stats);
+        assert_eq!(lines[0], "blocks  3 laid out / 7 reused");
+        assert_eq!(lines[2], "breaks  75% (3 hits)");
+        assert_eq!(lines[3], "glyphs  - (0 hits)");
+        assert!(lines[4].starts_with("time    wrap 1.50ms"));
+        assert_eq!(lines.len(), 5);
+    }
+}
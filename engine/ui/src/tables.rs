@@ -0,0 +1,126 @@
+use std::time::{Duration, Instant};
+use wa_core::EditorCommand;
+
+const RESIZE_INTERVAL: Duration = Duration::from_millis(30);
+/// Resend the width this often while the pointer rests so the drag keeps
+/// merging into the same undo entry.
+const RESIZE_KEEPALIVE: Duration = Duration::from_millis(200);
+
+/// A column border being dragged.
+#[derive(Debug, Clone, Copy)]
+pub struct ColumnResize {
+    pub block_id: uuid::Uuid,
+    pub col: usize,
+    pub sent_at: Instant,
+    pub width: f32,
+}
+
+impl ColumnResize {
+    /// Whether a new width should be sent now.
+    pub fn due(&self, width: f32, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.sent_at);
+        (elapsed >= RESIZE_INTERVAL && (width - self.width).abs() > 1e-3) || elapsed >= RESIZE_KEEPALIVE
+    }
+}
+
+/// Structural edits offered by the table context menu, relative to the
+/// right-clicked cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableAction {
+    InsertRowAbove,
+    InsertRowBelow,
+    InsertColumnLeft,
+    InsertColumnRight,
+    DeleteRow,
+    DeleteColumn,
+    ToggleHeader,
+}
+
+impl TableAction {
+    pub const MENU: [TableAction; 7] = [
+        TableAction::InsertRowAbove,
+        TableAction::InsertRowBelow,
+        TableAction::InsertColumnLeft,
+        TableAction::InsertColumnRight,
+        TableAction::DeleteRow,
+        TableAction::DeleteColumn,
+        TableAction::ToggleHeader,
+    ];
+
+    pub fn label(self, header: bool) -> &'static str {
+        match self {
+            TableAction::InsertRowAbove => "在上方插入行",
+            TableAction::InsertRowBelow => "在下方插入行",
+            TableAction::InsertColumnLeft => "在左侧插入列",
+            TableAction::InsertColumnRight => "在右侧插入列",
+            TableAction::DeleteRow => "删除行",
+            TableAction::DeleteColumn => "删除列",
+            TableAction::ToggleHeader if header => "取消表头行",
+            TableAction::ToggleHeader => "设为表头行",
+        }
+    }
+
+    pub fn command(self, block_id: uuid::Uuid, row: usize, col: usize) -> EditorCommand {
+        match self {
+            TableAction::InsertRowAbove => EditorCommand::TableInsertRowAt { block_id, index: row },
+            TableAction::InsertRowBelow => EditorCommand::TableInsertRowAt { block_id, index: row + 1 },
+            TableAction::InsertColumnLeft => EditorCommand::TableInsertColumnAt { block_id, index: col },
+            TableAction::InsertColumnRight => EditorCommand::TableInsertColumnAt { block_id, index: col + 1 },
+            TableAction::DeleteRow => EditorCommand::TableDeleteRowAt { block_id, index: row },
+            TableAction::DeleteColumn => EditorCommand::TableDeleteColumnAt { block_id, index: col },
+            TableAction::ToggleHeader => EditorCommand::TableToggleHeader { block_id },
+        }
+    }
+
+    /// Where the focused cell ends up once the action ran on a table that
+    /// now has `rows` x `cols` cells, so the outline keeps following the
+    /// same content.
+    pub fn focus_after(self, row: usize, col: usize, rows: usize, cols: usize) -> Option<(usize, usize)> {
+        let (row, col) = match self {
+            TableAction::InsertRowAbove => (row + 1, col),
+            TableAction::InsertColumnLeft => (row, col + 1),
+            _ => (row, col),
+        };
+        if rows == 0 || cols == 0 {
+            return None;
+        }
+        Some((row.min(rows - 1), col.min(cols - 1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn focus_follows_shifted_cell() {
+        assert_eq!(TableAction::InsertRowAbove.focus_after(1, 1, 4, 3), Some((2, 1)));
+        assert_eq!(TableAction::InsertRowBelow.focus_after(1, 1, 4, 3), Some((1, 1)));
+        assert_eq!(TableAction::InsertColumnLeft.focus_after(0, 2, 3, 4), Some((0, 3)));
+        assert_eq!(TableAction::DeleteRow.focus_after(2, 0, 2, 3), Some((1, 0)));
+        assert_eq!(TableAction::DeleteColumn.focus_after(0, 0, 1, 0), None);
+    }
+
+    #[test]
+    fn column_resize_is_throttled() {
+        let start = Instant::now();
+        let resize = ColumnResize { block_id: uuid::Uuid::new_v4(), col: 0, sent_at: start, width: 0.3 };
+        assert!(!resize.due(0.4, start + Duration::from_millis(5)));
+        assert!(resize.due(0.4, start + Duration::from_millis(40)));
+        assert!(!resize.due(0.3, start + Duration::from_millis(40)));
+        assert!(resize.due(0.3, start + Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn commands_target_clicked_cell() {
+        let id = uuid::Uuid::new_v4();
+        assert!(matches!(
+            TableAction::InsertRowBelow.command(id, 2, 0),
+            EditorCommand::TableInsertRowAt { index: 3, .. }
+        ));
+        assert!(matches!(
+            TableAction::DeleteColumn.command(id, 0, 1),
+            EditorCommand::TableDeleteColumnAt { index: 1, .. }
+        ));
+    }
+}
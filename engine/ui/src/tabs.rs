@@ -0,0 +1,174 @@
+use eframe::egui;
+use std::path::PathBuf;
+use wa_core::{Document, Editor};
+use wa_engine::{LayoutCache, LayoutConfig, LayoutTree, RenderCache, ViewState};
+
+use crate::{dragdrop, hitcache, links, status, tables, thumbnails};
+
+/// Everything tied to one open document. Layout and render caches live here
+/// so that switching tabs never mixes up layout versions between documents.
+pub struct DocumentView {
+    /// Stable identity for egui ids (scroll offsets, tab widgets).
+    pub id: uuid::Uuid,
+    pub editor: Editor,
+    pub cache: LayoutCache,
+    pub render_cache: RenderCache,
+    pub layout_tree: Option<LayoutTree>,
+    pub layout_version: u64,
+    pub layout_paged_view: bool,
+    pub layout_page_height: i32,
+    pub layout_zoom: f32,
+    pub hit_cache: hitcache::HitCache,
+    pub image_sizes: std::collections::HashMap<uuid::Uuid, (f32, f32)>,
+    pub resizing_image: Option<(String, egui::Pos2)>,
+    pub rect_select: Option<(egui::Pos2, egui::Pos2)>,
+    pub extra_cursors: Vec<wa_core::Position>,
+    pub table_focus: Option<(uuid::Uuid, usize, usize)>,
+    pub table_menu: Option<(uuid::Uuid, usize, usize)>,
+    pub column_resize: Option<tables::ColumnResize>,
+    pub caret_focus: Option<wa_core::Position>,
+    pub link_popover: Option<links::LinkPopover>,
+    pub block_drag: Option<dragdrop::BlockDrag>,
+    pub scroll_to_block: Option<uuid::Uuid>,
+    pub scroll_to_page: Option<usize>,
+    /// Where the reader is, refreshed when the scroll offset moves and
+    /// written next to the file on save and close.
+    pub view_state: ViewState,
+    /// A saved view to scroll back to once the document is laid out.
+    pub restore_view: Option<ViewState>,
+    pub status: status::StatusCache,
+    pub thumbnails: thumbnails::PageThumbnails,
+    pub document_path: Option<PathBuf>,
+    pub saved_version: u64,
+}
+
+impl DocumentView {
+    pub fn new(doc: Document, path: Option<PathBuf>) -> Self {
+        let editor = Editor::new(doc);
+        Self {
+            id: uuid::Uuid::new_v4(),
+            saved_version: editor.doc.version,
+            editor,
+            cache: LayoutCache::new(),
+            render_cache: RenderCache::new(),
+            layout_tree: None,
+            layout_version: 0,
+            layout_paged_view: true,
+            layout_page_height: LayoutConfig::default().page_height as i32,
+            layout_zoom: 1.0,
+            hit_cache: hitcache::HitCache::default(),
+            image_sizes: std::collections::HashMap::new(),
+            resizing_image: None,
+            rect_select: None,
+            extra_cursors: Vec::new(),
+            table_focus: None,
+            table_menu: None,
+            column_resize: None,
+            caret_focus: None,
+            link_popover: None,
+            block_drag: None,
+            scroll_to_block: None,
+            scroll_to_page: None,
+            view_state: ViewState::default(),
+            restore_view: None,
+            status: status::StatusCache::default(),
+            thumbnails: thumbnails::PageThumbnails::new(),
+            document_path: path,
+        }
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.editor.doc.version != self.saved_version
+    }
+
+    /// A fresh, never edited tab without a file; opening a file replaces it
+    /// instead of adding a tab next to it.
+    pub fn is_pristine(&self) -> bool {
+        self.document_path.is_none() && !self.is_modified() && !self.editor.can_undo()
+    }
+
+    pub fn tab_label(&self) -> String {
+        self.document_path
+            .as_deref()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "未命名".to_string())
+    }
+
+    /// Forces the next frame to lay the document out again.
+    pub fn invalidate_layout(&mut self) {
+        self.cache.clear();
+        self.layout_tree = None;
+    }
+}
+
+/// Tab index reached by Ctrl+Tab (or Ctrl+Shift+Tab), wrapping around.
+pub fn cycle(active: usize, len: usize, backwards: bool) -> usize {
+    if len == 0 {
+        return 0;
+    }
+    if backwards {
+        (active + len - 1) % len
+    } else {
+        (active + 1) % len
+    }
+}
+
+/// Active index after closing tab `closed` out of `len`. The tab to the
+/// right takes over when the active one closes, or the left one at the end.
+pub fn active_after_close(active: usize, closed: usize, len: usize) -> usize {
+    let remaining = len.saturating_sub(1);
+    if remaining == 0 {
+        return 0;
+    }
+    if closed < active {
+        active - 1
+    } else {
+        active.min(remaining - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use wa_core::{Block, EditorCommand, Inline};
+
+    #[test]
+    fn cycling_wraps() {
+        assert_eq!(cycle(2, 3, false), 0);
+        assert_eq!(cycle(0, 3, true), 2);
+        assert_eq!(cycle(0, 1, false), 0);
+    }
+
+    #[test]
+    fn closing_keeps_a_neighbour_active() {
+        assert_eq!(active_after_close(1, 1, 3), 1);
+        assert_eq!(active_after_close(2, 2, 3), 1);
+        assert_eq!(active_after_close(2, 0, 3), 1);
+        assert_eq!(active_after_close(0, 2, 3), 0);
+        assert_eq!(active_after_close(0, 0, 1), 0);
+    }
+
+    #[test]
+    fn views_track_their_own_state() {
+        let mut doc = Document::new();
+        doc.blocks.push(Block::Paragraph {
+            id: uuid::Uuid::new_v4(),
+            content: vec![Inline::Text { value: Arc::from("a") }],
+            dirty: false,
+            locked: false,
+        });
+        let mut first = DocumentView::new(doc.clone(), None);
+        let second = DocumentView::new(doc, Some(PathBuf::from("/tmp/draft.json")));
+        assert!(first.is_pristine());
+        assert!(!second.is_pristine());
+        assert_eq!(second.tab_label(), "draft.json");
+        assert_ne!(first.id, second.id);
+
+        first.editor.execute(EditorCommand::InsertText("b".to_string()));
+        assert!(first.is_modified());
+        assert!(!first.is_pristine());
+        assert!(!second.is_modified());
+    }
+}
@@ -0,0 +1,72 @@
+use eframe::egui;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use wa_engine::{LayoutConfig, LayoutTree, ThumbnailCache, ThumbnailData, ThumbnailJob};
+
+/// Width the thumbnail strip draws pages at.
+pub const THUMBNAIL_WIDTH: f32 = 120.0;
+
+type Drawn = (ThumbnailJob, ThumbnailData);
+
+/// Page thumbnails of one tab. Sketches are drawn on a worker thread; the
+/// UI thread only uploads finished pixels, and pages without one yet get a
+/// placeholder.
+pub struct PageThumbnails {
+    cache: ThumbnailCache,
+    /// Layout version and scale last handed to the cache.
+    seen: Option<(u64, u32)>,
+    textures: HashMap<usize, (Arc<ThumbnailData>, egui::TextureHandle)>,
+    tx: Sender<Drawn>,
+    rx: Receiver<Drawn>,
+}
+
+impl PageThumbnails {
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        Self { cache: ThumbnailCache::new(), seen: None, textures: HashMap::new(), tx, rx }
+    }
+
+    /// Starts drawing the pages that changed since the last layout seen.
+    pub fn update(&mut self, ctx: &egui::Context, layout: &LayoutTree, layout_version: u64, config: &LayoutConfig) {
+        let scale = THUMBNAIL_WIDTH / config.page_width;
+        if self.seen == Some((layout_version, scale.to_bits())) {
+            return;
+        }
+        self.seen = Some((layout_version, scale.to_bits()));
+        self.textures.retain(|page, _| *page < layout.pages.len());
+        let jobs = self.cache.update(layout, config, scale);
+        if jobs.is_empty() {
+            return;
+        }
+        let (tx, ctx, layout, config) = (self.tx.clone(), ctx.clone(), layout.clone(), config.clone());
+        std::thread::spawn(move || {
+            for job in jobs {
+                if let Some(thumbnail) = wa_engine::page_thumbnail(&layout, &config, job.page, scale) {
+                    if tx.send((job, thumbnail)).is_err() {
+                        return;
+                    }
+                }
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    pub fn poll(&mut self) {
+        while let Ok((job, thumbnail)) = self.rx.try_recv() {
+            self.cache.insert(job, thumbnail);
+        }
+    }
+
+    /// The texture of `page`'s latest thumbnail, uploading it when new.
+    pub fn texture(&mut self, ctx: &egui::Context, page: usize) -> Option<&egui::TextureHandle> {
+        let thumbnail = self.cache.get(page)?.clone();
+        let current = self.textures.get(&page).is_some_and(|(drawn, _)| Arc::ptr_eq(drawn, &thumbnail));
+        if !current {
+            let pixels = egui::ColorImage::from_rgba_unmultiplied([thumbnail.width as usize, thumbnail.height as usize], &thumbnail.rgba);
+            let texture = ctx.load_texture(format!("thumbnail_{}", page), pixels, egui::TextureOptions::LINEAR);
+            self.textures.insert(page, (thumbnail, texture));
+        }
+        self.textures.get(&page).map(|(_, texture)| texture)
+    }
+}